@@ -0,0 +1,63 @@
+#!/bin/sh
+# Benchmark `git-rs status` on a small work tree next to an ignored directory of $IGNORED files
+# (200k by default), laid out like a `node_modules`.
+#
+# The walk must not descend into the ignored directory, so the status must take at most $MAX_MS
+# milliseconds, and its output must be the same as git's. The untracked cache is removed before
+# every run so each one walks the whole tree.
+#
+#   cargo build --release && scripts/bench_ignored.sh
+set -eu
+
+BIN=${BIN:-$(pwd)/target/release/git-rs}
+IGNORED=${IGNORED:-200000}
+MAX_MS=${MAX_MS:-200}
+DIR=$(mktemp -d)
+trap 'rm -rf "$DIR"' EXIT
+
+cd "$DIR"
+git init -q .
+echo "generating $IGNORED ignored files in $DIR"
+python3 - "$IGNORED" <<'PY'
+import os, sys
+ignored = int(sys.argv[1])
+for i in range(1000):
+    d = f"src/m{i // 100}"
+    os.makedirs(d, exist_ok=True)
+    with open(f"{d}/f{i}", "w") as f:
+        f.write(f"file {i}\n")
+for i in range(ignored):
+    d = f"node_modules/p{i // 1000}/lib/d{i // 100 % 10}"
+    os.makedirs(d, exist_ok=True)
+    open(f"{d}/f{i}.js", "w").close()
+PY
+echo node_modules/ >.gitignore
+git add .gitignore src
+git -c gc.auto=0 -c user.name=bench -c user.email=bench@example.com commit -q -m files
+# a modified and an untracked file, so there is something to report
+echo changed >>src/m0/f0
+touch src/m1/new
+"$BIN" status >/dev/null
+
+now() { date +%s%N; }
+run() {
+    rm -f .git/untracked-cache
+    start=$(now)
+    "$BIN" status --porcelain >"$DIR/status"
+    echo $(( ($(now) - start) / 1000000 ))
+}
+# the best of a few runs, so a hiccup on a busy machine doesn't decide the result
+best=$(run)
+for _ in 1 2; do
+    t=$(run)
+    if [ "$t" -lt "$best" ]; then best=$t; fi
+done
+echo "status: ${best}ms"
+if ! git status --porcelain | diff -u - "$DIR/status"; then
+    echo "status differs from git's" >&2
+    exit 1
+fi
+if [ "$best" -gt "$MAX_MS" ]; then
+    echo "status took more than ${MAX_MS}ms" >&2
+    exit 1
+fi
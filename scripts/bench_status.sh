@@ -0,0 +1,59 @@
+#!/bin/sh
+# Benchmark `git-rs status` on a generated work tree of $FILES files (100k by default).
+#
+# The cold run starts from an index without stat data (as left by `read-tree`) and no untracked
+# cache, so every file is hashed and every directory read. The warm runs that follow can trust
+# the refreshed stat data and skip reading unchanged directories; the best of them must be at
+# least $SPEEDUP times faster.
+#
+#   cargo build --release && scripts/bench_status.sh
+set -eu
+
+BIN=${BIN:-$(pwd)/target/release/git-rs}
+FILES=${FILES:-100000}
+SPEEDUP=${SPEEDUP:-3}
+DIR=$(mktemp -d)
+trap 'rm -rf "$DIR"' EXIT
+
+cd "$DIR"
+git init -q .
+echo "generating $FILES files in $DIR"
+python3 - "$FILES" <<'PY'
+import os, sys
+files = int(sys.argv[1])
+for i in range(files):
+    d = f"d{i // 10000}/e{i // 100 % 100}"
+    os.makedirs(d, exist_ok=True)
+    with open(f"{d}/f{i}", "w") as f:
+        # about the size of a small source file
+        f.write(f"file {i}\n" * 100)
+# untracked files next to tracked ones, and an untracked tree
+for i in range(0, files, 1000):
+    os.makedirs(f"new/n{i}", exist_ok=True)
+    open(f"new/n{i}/u", "w").close()
+    open(f"d{i // 10000}/e{i // 100 % 100}/u{i}.new", "w").close()
+PY
+git add -- d*
+git -c gc.auto=0 -c user.name=bench -c user.email=bench@example.com commit -q -m files
+"$BIN" read-tree HEAD
+rm -f .git/untracked-cache
+
+now() { date +%s%N; }
+run() {
+    start=$(now)
+    "$BIN" status >/dev/null
+    echo $(( ($(now) - start) / 1000000 ))
+}
+cold=$(run)
+# the best of a few warm runs, so a hiccup on a busy machine doesn't decide the result
+warm=$(run)
+for _ in 1 2; do
+    t=$(run)
+    if [ "$t" -lt "$warm" ]; then warm=$t; fi
+done
+echo "cold: ${cold}ms"
+echo "warm: ${warm}ms"
+if [ $(( warm * SPEEDUP )) -gt "$cold" ]; then
+    echo "warm run is less than ${SPEEDUP}x faster than the cold run" >&2
+    exit 1
+fi
@@ -0,0 +1,60 @@
+#!/bin/sh
+# Benchmark counting the objects of a full clone in `git-rs upload-pack`, on a generated
+# repository of $COMMITS commits (50k by default) repacked with reachability bitmaps and a
+# reverse index, as recent versions of git write by default.
+#
+# The counting time comes from the GIT_TRACE line upload-pack writes once it knows what to send.
+# With the bitmaps it must take at most $MAX_MS milliseconds; the same count by walking every
+# commit and tree, with the bitmaps moved away, is shown for comparison.
+#
+#   cargo build --release && scripts/bench_upload_pack.sh
+set -eu
+
+BIN=${BIN:-$(pwd)/target/release/git-rs}
+COMMITS=${COMMITS:-50000}
+MAX_MS=${MAX_MS:-100}
+DIR=$(mktemp -d)
+trap 'rm -rf "$DIR"' EXIT
+
+cd "$DIR"
+git init -q .
+echo "generating $COMMITS commits in $DIR"
+python3 - "$COMMITS" <<'PY' | git fast-import --quiet
+import sys
+commits = int(sys.argv[1])
+out = []
+for i in range(commits):
+    # each commit changes one of a few hundred files, spread over directories
+    message = f"commit {i}\n"
+    data = f"content {i}\n"
+    out.append(f"commit refs/heads/master\n"
+               f"committer bench <bench@example.com> {1700000000 + i} +0000\n"
+               f"data {len(message)}\n{message}"
+               f"M 100644 inline d{i % 17}/e{i % 7}/f{i % 301}\n"
+               f"data {len(data)}\n{data}\n")
+sys.stdout.write("".join(out))
+PY
+git -c repack.writeBitmaps=true -c pack.writeReverseIndex=true repack -adq
+
+head=$(git rev-parse HEAD)
+request() {
+    want="want $head no-progress"
+    printf '%04x%s\n0000' $(( ${#want} + 5 )) "$want"
+    printf '0009done\n'
+}
+run() {
+    rm -f "$DIR/trace"
+    request | GIT_TRACE="$DIR/trace" "$BIN" upload-pack . >/dev/null
+    sed -n 's/.*counted \([0-9]*\) objects in \([0-9.]*\) s.*/\1 \2/p' "$DIR/trace" |
+        awk '{ printf "%d objects in %dms\n", $1, $2 * 1000 }'
+}
+with=$(run)
+mv .git/objects/pack/*.bitmap "$DIR/saved.bitmap"
+without=$(run)
+echo "bitmaps: $with"
+echo "walk:    $without"
+ms=$(echo "$with" | sed 's/.* in \([0-9]*\)ms/\1/')
+if [ "$ms" -gt "$MAX_MS" ]; then
+    echo "counting with bitmaps took more than ${MAX_MS}ms" >&2
+    exit 1
+fi
@@ -0,0 +1,600 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    binary_patch::{blob_hash, parse_binary_patch, BinaryHunk},
+    diff::split_lines,
+    hash::{HashAlgo, ObjectId},
+    index::{Index, IndexEntry},
+    merge::merge_text,
+    objects::{object_read, object_resolve_prefix, write_object, Kind, Mode},
+    repository::GitRepository,
+};
+
+/// The changes a patch makes to one file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FilePatch {
+    /// Path before the change, `None` when the file is created.
+    pub(crate) old_path: Option<String>,
+    /// Path after the change, `None` when the file is deleted.
+    pub(crate) new_path: Option<String>,
+    pub(crate) old_mode: Option<Mode>,
+    pub(crate) new_mode: Option<Mode>,
+    /// The (possibly abbreviated) blob hashes from the `index` line.
+    pub(crate) old_hash: Option<String>,
+    pub(crate) new_hash: Option<String>,
+    /// A patch of binary content: `Binary files ... differ`, which can't be applied, or a
+    /// `GIT binary patch` with the hunks in `binary_hunks`.
+    pub(crate) binary: bool,
+    /// The hunks of a `GIT binary patch` that turn the old content into the new one and, if the
+    /// patch has it, back.
+    pub(crate) binary_hunks: Option<(BinaryHunk, Option<BinaryHunk>)>,
+    pub(crate) hunks: Vec<PatchHunk>,
+}
+
+impl FilePatch {
+    /// The path this patch is reported under.
+    pub(crate) fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// The patch that undoes this one, as `git apply -R` applies it. A `GIT binary patch` can
+    /// only be reversed if it has the hunk going back.
+    pub(crate) fn reverse(&self) -> Result<FilePatch> {
+        let binary_hunks = match &self.binary_hunks {
+            Some((forward, Some(reverse))) => Some((reverse.clone(), Some(forward.clone()))),
+            Some((_, None)) => bail!(
+                "cannot reverse-apply a binary patch without the reverse hunk to '{}'",
+                self.path()
+            ),
+            None => None,
+        };
+        Ok(FilePatch {
+            old_path: self.new_path.clone(),
+            new_path: self.old_path.clone(),
+            old_mode: self.new_mode,
+            new_mode: self.old_mode,
+            old_hash: self.new_hash.clone(),
+            new_hash: self.old_hash.clone(),
+            binary: self.binary,
+            binary_hunks,
+            hunks: self.hunks.iter().map(PatchHunk::reverse).collect(),
+        })
+    }
+}
+
+/// One `@@` hunk of a patch. `lines` holds each line's prefix (` `, `-` or `+`) and its text,
+/// including the newline unless the line was marked `\ No newline at end of file`.
+#[derive(Debug, Clone)]
+pub(crate) struct PatchHunk {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+    pub(crate) lines: Vec<(u8, Vec<u8>)>,
+}
+
+impl PatchHunk {
+    /// The hunk with its sides swapped: added lines become removed ones and the other way round.
+    fn reverse(&self) -> PatchHunk {
+        let lines = self
+            .lines
+            .iter()
+            .map(|(tag, text)| {
+                let tag = match tag {
+                    b'+' => b'-',
+                    b'-' => b'+',
+                    tag => *tag,
+                };
+                (tag, text.clone())
+            })
+            .collect();
+        PatchHunk {
+            old_start: self.new_start,
+            old_len: self.new_len,
+            new_start: self.old_start,
+            new_len: self.old_len,
+            lines,
+        }
+    }
+
+    /// The hunk as a patch shows it, starting with its `@@` header.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = format!(
+            "@@ -{},{} +{},{} @@\n",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+        .into_bytes();
+        for (tag, text) in &self.lines {
+            out.push(*tag);
+            out.extend_from_slice(text);
+            if !text.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+        out
+    }
+}
+
+/// Parse the file patches in a unified diff, either in git's extended format (`diff --git`) or
+/// a plain `---`/`+++` diff. Paths are taken with their first component (`a/`, `b/`) removed.
+/// Anything before, between or after the patches (commit messages, diffstats) is skipped.
+pub(crate) fn parse_patch(text: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = split_lines(text);
+    let mut patches = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(rest) = line.strip_prefix(b"diff --git ") {
+            let mut patch = FilePatch::default();
+            if let Some((old, new)) = split_git_header(&text_line(rest)) {
+                patch.old_path = Some(old);
+                patch.new_path = Some(new);
+            }
+            i += 1;
+            i = parse_extended_headers(&lines, i, &mut patch)?;
+            i = parse_hunks(&lines, i, &mut patch)?;
+            patches.push(patch);
+        } else if line.starts_with(b"--- ")
+            && lines.get(i + 1).is_some_and(|l| l.starts_with(b"+++ "))
+            && lines.get(i + 2).is_some_and(|l| l.starts_with(b"@@ "))
+        {
+            let mut patch = FilePatch::default();
+            i = parse_hunks(&lines, i, &mut patch)?;
+            patches.push(patch);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(patches)
+}
+
+fn text_line(line: &[u8]) -> String {
+    String::from_utf8_lossy(line)
+        .trim_end_matches(['\n', '\r'])
+        .to_string()
+}
+
+/// Strip the first path component, as `git apply` does by default (`-p1`).
+fn strip_prefix(path: &str) -> String {
+    path.split_once('/')
+        .map_or(path, |(_, rest)| rest)
+        .to_string()
+}
+
+/// Split `a/<old> b/<new>` from a `diff --git` line. The split is only unambiguous when both
+/// names are the same, so other cases rely on the `---`/`+++` or rename headers instead.
+fn split_git_header(rest: &str) -> Option<(String, String)> {
+    let rest = rest.strip_prefix("a/")?;
+    let half = rest.len().checked_sub(3)? / 2;
+    let (old, new) = (rest.get(..half)?, rest.get(half..)?);
+    let new = new.strip_prefix(" b/")?;
+    (old == new).then(|| (old.to_string(), new.to_string()))
+}
+
+fn parse_extended_headers(lines: &[&[u8]], mut i: usize, patch: &mut FilePatch) -> Result<usize> {
+    while i < lines.len() {
+        let line = text_line(lines[i]);
+        if let Some(mode) = line.strip_prefix("new file mode ") {
+            patch.old_path = None;
+            patch.new_mode = Some(mode.parse()?);
+        } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+            patch.new_path = None;
+            patch.old_mode = Some(mode.parse()?);
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            patch.old_mode = Some(mode.parse()?);
+        } else if let Some(mode) = line.strip_prefix("new mode ") {
+            patch.new_mode = Some(mode.parse()?);
+        } else if let Some(path) = line
+            .strip_prefix("rename from ")
+            .or(line.strip_prefix("copy from "))
+        {
+            patch.old_path = Some(path.to_string());
+        } else if let Some(path) = line
+            .strip_prefix("rename to ")
+            .or(line.strip_prefix("copy to "))
+        {
+            patch.new_path = Some(path.to_string());
+        } else if let Some(hashes) = line.strip_prefix("index ") {
+            let (hashes, mode) = hashes.split_once(' ').unwrap_or((hashes, ""));
+            if let Some((old, new)) = hashes.split_once("..") {
+                patch.old_hash = Some(old.to_string()).filter(|h| !h.trim_matches('0').is_empty());
+                patch.new_hash = Some(new.to_string()).filter(|h| !h.trim_matches('0').is_empty());
+            }
+            if !mode.is_empty() {
+                let mode = mode.parse()?;
+                patch.old_mode.get_or_insert(mode);
+                patch.new_mode.get_or_insert(mode);
+            }
+        } else if line == "GIT binary patch" {
+            let (forward, reverse, next) = parse_binary_patch(lines, i + 1)
+                .with_context(|| format!("corrupt binary patch for '{}'", patch.path()))?;
+            patch.binary = true;
+            patch.binary_hunks = Some((forward, reverse));
+            return Ok(next);
+        } else if line.starts_with("Binary files ") {
+            patch.binary = true;
+        } else if !(line.starts_with("similarity index ")
+            || line.starts_with("dissimilarity index "))
+        {
+            break;
+        }
+        i += 1;
+    }
+    Ok(i)
+}
+
+/// Parse the optional `---`/`+++` lines and the hunks that follow them.
+fn parse_hunks(lines: &[&[u8]], mut i: usize, patch: &mut FilePatch) -> Result<usize> {
+    if lines.get(i).is_some_and(|l| l.starts_with(b"--- ")) {
+        let old = text_line(&lines[i][4..]);
+        let new = text_line(lines.get(i + 1).map_or(&b""[..], |l| &l[4.min(l.len())..]));
+        // a name may be followed by a tab and a timestamp in plain diffs
+        let name = |s: &str| s.split('\t').next().unwrap_or(s).to_string();
+        let (old, new) = (name(&old), name(&new));
+        patch.old_path = (old != "/dev/null").then(|| strip_prefix(&old));
+        patch.new_path = (new != "/dev/null").then(|| strip_prefix(&new));
+        i += 2;
+    }
+
+    while let Some(line) = lines.get(i).filter(|l| l.starts_with(b"@@ ")) {
+        let header = text_line(line);
+        let (old_start, old_len, new_start, new_len) = parse_hunk_header(&header)
+            .with_context(|| format!("malformed hunk header {header:?}"))?;
+        let mut hunk = PatchHunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+            lines: Vec::new(),
+        };
+        i += 1;
+        let (mut old_seen, mut new_seen) = (0, 0);
+        while old_seen < old_len || new_seen < new_len {
+            let Some(line) = lines.get(i) else {
+                bail!("patch ends in the middle of a hunk");
+            };
+            // some mailers drop the space of empty context lines
+            let (tag, text) = match line.first() {
+                Some(b'\n') | Some(b'\r') => (b' ', line.to_vec()),
+                Some(&tag @ (b' ' | b'-' | b'+')) => (tag, line[1..].to_vec()),
+                _ => bail!("unexpected line in hunk: {:?}", text_line(line)),
+            };
+            if tag != b'+' {
+                old_seen += 1;
+            }
+            if tag != b'-' {
+                new_seen += 1;
+            }
+            hunk.lines.push((tag, text));
+            i += 1;
+            if lines.get(i).is_some_and(|l| l.starts_with(b"\\ ")) {
+                if let Some((_, text)) = hunk.lines.last_mut() {
+                    if text.ends_with(b"\n") {
+                        text.pop();
+                    }
+                }
+                i += 1;
+            }
+        }
+        patch.hunks.push(hunk);
+    }
+    Ok(i)
+}
+
+/// Parse `@@ -a,b +c,d @@` into `(a, b, c, d)`; an omitted length means 1.
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut parts = header.strip_prefix("@@ -")?.split(' ');
+    let range = |s: &str| -> Option<(usize, usize)> {
+        match s.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((s.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = range(parts.next()?)?;
+    let (new_start, new_len) = range(parts.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_len, new_start, new_len))
+}
+
+/// Apply `hunks` to `content`. Each hunk has to match exactly, but may be found some lines away
+/// from where it says it starts (git's offset handling, without fuzz).
+pub(crate) fn apply_hunks(content: &[u8], hunks: &[PatchHunk]) -> Result<Vec<u8>> {
+    let (out, rejected) = apply_hunks_partial(content, hunks, usize::MAX);
+    if let Some(n) = rejected.first() {
+        bail!("hunk #{} does not apply", n + 1);
+    }
+    Ok(out)
+}
+
+/// Apply what can be applied of `hunks` to `content`, returning the result and the indexes of
+/// the hunks that don't apply. A hunk may be found some lines away from where it says it
+/// starts. If it doesn't match anywhere, its outer context lines are dropped one at a time,
+/// from the side with more of them, as long as more than `min_context` are left on that side
+/// (git's `-C<n>`).
+pub(crate) fn apply_hunks_partial(
+    content: &[u8],
+    hunks: &[PatchHunk],
+    min_context: usize,
+) -> (Vec<u8>, Vec<usize>) {
+    let lines = split_lines(content);
+    let mut out: Vec<u8> = Vec::with_capacity(content.len());
+    let mut rejected = Vec::new();
+    // next line of `lines` not yet copied to `out`
+    let mut next = 0;
+    let mut offset: isize = 0;
+    for (n, hunk) in hunks.iter().enumerate() {
+        let context = |lines: &mut dyn Iterator<Item = &(u8, Vec<u8>)>| {
+            lines.take_while(|(tag, _)| *tag == b' ').count()
+        };
+        let mut leading = context(&mut hunk.lines.iter());
+        let mut trailing = context(&mut hunk.lines.iter().rev()).min(hunk.lines.len() - leading);
+        // the lines of the hunk still taken into account
+        let (mut first, mut last) = (0, hunk.lines.len());
+        let found = loop {
+            let pre = hunk.lines[first..last]
+                .iter()
+                .filter(|(tag, _)| *tag != b'+')
+                .map(|(_, text)| text.as_slice())
+                .collect::<Vec<_>>();
+            // the old start of a hunk is the line before it when it has no old lines
+            let expected = if hunk.old_len == 0 {
+                hunk.old_start
+            } else {
+                hunk.old_start.saturating_sub(1)
+            };
+            let expected =
+                (expected as isize + first as isize + offset).max(next as isize) as usize;
+            let matches_at =
+                |at: usize| at + pre.len() <= lines.len() && lines[at..at + pre.len()] == pre[..];
+            let found = (0..=lines.len())
+                .flat_map(|d| [expected.checked_add(d), expected.checked_sub(d)])
+                .flatten()
+                .filter(|at| *at >= next && *at <= lines.len())
+                .find(|at| matches_at(*at));
+            if let Some(at) = found {
+                break Some((at, expected, pre.len()));
+            }
+            if leading <= min_context && trailing <= min_context {
+                break None;
+            }
+            if leading >= trailing {
+                first += 1;
+                leading -= 1;
+            }
+            if trailing > leading {
+                last -= 1;
+                trailing -= 1;
+            }
+        };
+        let Some((at, expected, pre_len)) = found else {
+            rejected.push(n);
+            continue;
+        };
+        offset += at as isize - expected as isize;
+        for line in &lines[next..at] {
+            out.extend_from_slice(line);
+        }
+        for (tag, text) in &hunk.lines[first..last] {
+            if *tag != b'-' {
+                out.extend_from_slice(text);
+            }
+        }
+        next = at + pre_len;
+    }
+    for line in &lines[next..] {
+        out.extend_from_slice(line);
+    }
+    (out, rejected)
+}
+
+/// Apply the hunks of a `GIT binary patch` to `current`. Binary patches don't have context to
+/// check, so the content must be exactly the blob the `index` line names, and the result the
+/// blob it names after the change.
+pub(crate) fn apply_binary(
+    algo: HashAlgo,
+    patch: &FilePatch,
+    current: &[u8],
+    (forward, reverse): &(BinaryHunk, Option<BinaryHunk>),
+) -> Result<Vec<u8>> {
+    let path = patch.path();
+    let full_hash = |hash: &Option<String>| match hash {
+        None => Ok(None),
+        Some(hash) if hash.len() == algo.hex_len() => Ok(Some(hash.clone())),
+        Some(_) => bail!("cannot apply binary patch to '{path}' without full index line"),
+    };
+    let old_hash = full_hash(&patch.old_hash)?;
+    let new_hash = full_hash(&patch.new_hash)?;
+    if patch.old_path.is_some() && old_hash.as_deref() != Some(&blob_hash(algo, current)) {
+        bail!(
+            "the patch applies to '{path}' ({}), which does not match the current contents",
+            old_hash.as_deref().unwrap_or("empty")
+        );
+    }
+    let content = forward
+        .apply(current)
+        .with_context(|| format!("binary patch does not apply to '{path}'"))?;
+    let expected = new_hash.unwrap_or_else(|| blob_hash(algo, b""));
+    if blob_hash(algo, &content) != expected {
+        bail!("binary patch to '{path}' creates incorrect result (expecting {expected})");
+    }
+    if let Some(reverse) = reverse {
+        if reverse.apply(&content).ok().as_deref() != Some(current) {
+            bail!("binary patch to '{path}' has a reverse hunk that does not undo it");
+        }
+    }
+    Ok(content)
+}
+
+/// What happened to a file when applying with three-way fallback.
+pub(crate) struct Applied {
+    /// Paths that were merged with conflicts; their work tree files contain conflict markers and
+    /// the index has their base, our and their versions at stages 1, 2 and 3.
+    pub(crate) conflicts: Vec<String>,
+}
+
+/// The checked result of one file of a patch, ready to be written.
+struct Patched {
+    path: String,
+    /// The new content and mode, or `None` to delete the file.
+    result: Option<(Vec<u8>, Mode)>,
+    /// For a conflicted merge: the base blob and the patched base ("their" version).
+    conflict: Option<(String, Vec<u8>)>,
+}
+
+/// Apply `patches` to the work tree and `index` of `git_repo`.
+///
+/// Files are patched as they are in the work tree. All patches are checked before anything is
+/// written, so a patch that doesn't apply leaves everything untouched. With `three_way`, a
+/// patch that doesn't apply directly is applied to the blob it was made against (when that
+/// blob is available) and the result merged into the current file, which may leave conflicts.
+pub(crate) fn apply_patches(
+    git_repo: &GitRepository,
+    index: &mut Index,
+    patches: &[FilePatch],
+    three_way: bool,
+) -> Result<Applied> {
+    let mut results = Vec::new();
+    for patch in patches {
+        if patch.binary && patch.binary_hunks.is_none() {
+            bail!("cannot apply binary patch to '{}'", patch.path());
+        }
+        let current = match &patch.old_path {
+            Some(path) => {
+                let full = git_repo.work_tree().join(path);
+                Some(
+                    fs::read(&full)
+                        .with_context(|| format!("{path}: does not exist in working tree"))?,
+                )
+            }
+            None => {
+                let path = patch.new_path.as_deref().unwrap_or_default();
+                if git_repo.work_tree().join(path).exists() || index.get(path).is_some() {
+                    bail!("{path}: already exists in working directory");
+                }
+                None
+            }
+        };
+        let current_bytes = current.as_deref().unwrap_or_default();
+
+        let applied = match &patch.binary_hunks {
+            Some(hunks) => Ok(apply_binary(
+                git_repo.hash_algo(),
+                patch,
+                current_bytes,
+                hunks,
+            )?),
+            None => apply_hunks(current_bytes, &patch.hunks),
+        };
+        let (content, conflict) = match applied {
+            Ok(content) => (content, None),
+            Err(e) if three_way => {
+                let base = patch
+                    .old_hash
+                    .as_deref()
+                    .map(|h| object_resolve_prefix(git_repo, h))
+                    .transpose()?
+                    .flatten()
+                    .with_context(|| {
+                        format!(
+                            "{}: {e}, and the blob it was made against is not available",
+                            patch.path()
+                        )
+                    })?;
+                let base_data = object_read(git_repo, &base)?.serialize();
+                let theirs = apply_hunks(&base_data, &patch.hunks).with_context(|| {
+                    format!("{}: patch does not apply to its own base", patch.path())
+                })?;
+                let merged = merge_text(&base_data, current_bytes, &theirs, "ours", "theirs");
+                (
+                    merged.text,
+                    (merged.conflicts > 0).then_some((base, theirs)),
+                )
+            }
+            Err(e) => return Err(e).with_context(|| format!("patch failed: {}", patch.path())),
+        };
+
+        if let Some(old) = &patch.old_path {
+            if patch.new_path.as_deref() != Some(old) {
+                results.push(Patched {
+                    path: old.clone(),
+                    result: None,
+                    conflict: None,
+                });
+            }
+        }
+        if let Some(new) = &patch.new_path {
+            let mode = patch
+                .new_mode
+                .or_else(|| {
+                    index
+                        .get(patch.old_path.as_deref().unwrap_or(new))
+                        .map(|e| Mode::from_bits(e.mode))
+                })
+                .unwrap_or(Mode::RegularFile);
+            results.push(Patched {
+                path: new.clone(),
+                result: Some((content, mode)),
+                conflict,
+            });
+        } else if !content.is_empty() {
+            bail!("{}: removal patch leaves file contents", patch.path());
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for Patched {
+        path,
+        result,
+        conflict,
+    } in results
+    {
+        let full = git_repo.work_tree().join(&path);
+        let Some((content, mode)) = result else {
+            if full.exists() {
+                fs::remove_file(&full).with_context(|| format!("remove {}", full.display()))?;
+            }
+            index.remove(&path);
+            continue;
+        };
+        if let Some(parent) = full.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        fs::write(&full, &content).with_context(|| format!("write {}", full.display()))?;
+        set_executable(&full, mode == Mode::Executable)?;
+        let mode = mode.bits();
+        let blob = |data: &[u8]| -> Result<ObjectId> {
+            ObjectId::from_hex(&write_object(git_repo, Kind::Blob, data)?)
+        };
+        if let Some((base, theirs)) = conflict {
+            let ours = index.get(&path).map(|e| (e.mode, e.hash));
+            let base = ObjectId::from_hex(&base)?;
+            let base_mode = ours.map_or(mode, |(ours_mode, _)| ours_mode);
+            index.add_conflict(
+                &path,
+                [Some((base_mode, base)), ours, Some((mode, blob(&theirs)?))],
+            );
+            conflicts.push(path);
+            continue;
+        }
+        let meta = fs::symlink_metadata(&full)?;
+        index.add(IndexEntry::from_metadata(
+            &path,
+            &meta,
+            mode,
+            blob(&content)?,
+        ));
+    }
+    index.sort();
+    Ok(Applied { conflicts })
+}
+
+pub(crate) fn set_executable(path: &std::path::Path, executable: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = if executable { 0o755 } else { 0o644 };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("set mode of {}", path.display()))
+}
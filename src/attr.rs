@@ -0,0 +1,138 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::Result;
+
+use crate::{
+    ignore::wildmatch,
+    repository::{repo_file, GitRepository},
+};
+
+/// The value an attributes file gives an attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AttrState {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+}
+
+/// One line of an attributes file: a pattern and the attributes it assigns. `None` is `!name`,
+/// which returns the attribute to unspecified.
+#[derive(Debug, Clone)]
+struct AttrRule {
+    pattern: String,
+    /// The pattern contains a `/`, so it matches the path from the file's directory rather than
+    /// just the last component.
+    anchored: bool,
+    attrs: Vec<(String, Option<AttrState>)>,
+}
+
+impl AttrRule {
+    /// Parse one line, returning `None` for blank lines and comments.
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let pattern = words.next().filter(|p| !p.starts_with('#'))?;
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern).to_string();
+        let mut attrs = Vec::new();
+        for word in words {
+            let attr = if let Some(name) = word.strip_prefix('-') {
+                (name.to_string(), Some(AttrState::Unset))
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name.to_string(), None)
+            } else if let Some((name, value)) = word.split_once('=') {
+                (name.to_string(), Some(AttrState::Value(value.to_string())))
+            } else {
+                (word.to_string(), Some(AttrState::Set))
+            };
+            // the built-in `binary` macro stands for `-diff -merge -text`
+            if attr == ("binary".to_string(), Some(AttrState::Set)) {
+                for name in ["diff", "merge", "text"] {
+                    attrs.push((name.to_string(), Some(AttrState::Unset)));
+                }
+            }
+            attrs.push(attr);
+        }
+        Some(Self {
+            pattern,
+            anchored,
+            attrs,
+        })
+    }
+
+    /// Whether the rule applies to `path`, relative to the directory of its file.
+    fn matches(&self, path: &str) -> bool {
+        if self.anchored {
+            wildmatch(self.pattern.as_bytes(), path.as_bytes())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(self.pattern.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+fn parse_rules(text: &str) -> Vec<AttrRule> {
+    text.lines().filter_map(AttrRule::parse).collect()
+}
+
+/// The attributes of work tree paths, from the `.gitattributes` files of the work tree and
+/// `.git/info/attributes`. Files are read as they are needed.
+pub(crate) struct Attributes<'r> {
+    git_repo: &'r GitRepository,
+    /// The rules of `.git/info/attributes`, which override all others.
+    info: Vec<AttrRule>,
+    /// The rules of each directory's `.gitattributes` (`""` or ending in `/`).
+    dirs: HashMap<String, Vec<AttrRule>>,
+}
+
+impl<'r> Attributes<'r> {
+    pub(crate) fn new(git_repo: &'r GitRepository) -> Result<Self> {
+        let info = repo_file(git_repo, &["info", "attributes"], false)?;
+        Ok(Self {
+            git_repo,
+            info: parse_rules(&fs::read_to_string(info).unwrap_or_default()),
+            dirs: HashMap::new(),
+        })
+    }
+
+    fn dir_rules(&mut self, dir: &str) -> &[AttrRule] {
+        if !self.dirs.contains_key(dir) {
+            let file = self.git_repo.work_tree().join(dir).join(".gitattributes");
+            let text = fs::read_to_string(file).unwrap_or_default();
+            self.dirs.insert(dir.to_string(), parse_rules(&text));
+        }
+        &self.dirs[dir]
+    }
+
+    /// The state of attribute `name` for `path` (relative to the top of the work tree), or
+    /// `None` if it is unspecified. Deeper files override shallower ones, and within a file the
+    /// last matching line wins.
+    pub(crate) fn get(&mut self, path: &str, name: &str) -> Option<AttrState> {
+        let lookup = |rules: &[AttrRule], relative: &str| {
+            rules
+                .iter()
+                .rev()
+                .filter(|r| r.matches(relative))
+                .find_map(|r| {
+                    r.attrs
+                        .iter()
+                        .rev()
+                        .find(|(attr, _)| attr == name)
+                        .map(|(_, state)| state.clone())
+                })
+        };
+        if let Some(state) = lookup(&self.info, path) {
+            return state;
+        }
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(at, _)| &path[..=at]));
+        for dir in dirs.into_iter().rev() {
+            if let Some(state) = lookup(self.dir_rules(dir), &path[dir.len()..]) {
+                return state;
+            }
+        }
+        None
+    }
+}
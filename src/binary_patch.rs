@@ -0,0 +1,197 @@
+use std::io::{Read, Write};
+
+use crate::{
+    hash::HashAlgo,
+    pack::{apply_delta, create_delta},
+};
+use anyhow::{bail, Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+/// Each line of a binary hunk carries up to this many bytes of compressed data.
+const LINE_BYTES: usize = 52;
+
+/// One direction of a `GIT binary patch`: the whole new content, or a delta against the old.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BinaryHunk {
+    Literal(Vec<u8>),
+    Delta(Vec<u8>),
+}
+
+impl BinaryHunk {
+    /// Turn `old` into the content this hunk describes.
+    pub(crate) fn apply(&self, old: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Literal(data) => Ok(data.clone()),
+            Self::Delta(delta) => apply_delta(old, delta),
+        }
+    }
+}
+
+/// Encode 4-byte groups as 5 base-85 digits, the last group padded with zeros.
+fn encode_85(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len().div_ceil(4) * 5);
+    for group in data.chunks(4) {
+        let mut acc = 0u32;
+        for (i, byte) in group.iter().enumerate() {
+            acc |= (*byte as u32) << (24 - 8 * i);
+        }
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85[(acc % 85) as usize];
+            acc /= 85;
+        }
+        out.extend_from_slice(&digits);
+    }
+    out
+}
+
+/// Decode `len` bytes from base-85 `text`.
+fn decode_85(text: &[u8], len: usize) -> Result<Vec<u8>> {
+    if text.len() != len.div_ceil(4) * 5 {
+        bail!("base85 line has the wrong length");
+    }
+    let mut out = Vec::with_capacity(len);
+    for group in text.chunks(5) {
+        let mut acc = 0u32;
+        for c in group {
+            let digit = BASE85
+                .iter()
+                .position(|d| d == c)
+                .with_context(|| format!("invalid base85 character {:?}", *c as char))?;
+            acc = acc
+                .checked_mul(85)
+                .and_then(|acc| acc.checked_add(digit as u32))
+                .context("invalid base85 group")?;
+        }
+        let take = (len - out.len()).min(4);
+        out.extend_from_slice(&acc.to_be_bytes()[..take]);
+    }
+    Ok(out)
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Write one hunk: a `literal <size>` or `delta <size>` line, the deflated data in base-85
+/// lines whose first character gives their length (`A`-`Z` for 1-26, `a`-`z` for 27-52), and
+/// a blank line. The delta is used when it compresses smaller than the whole content.
+fn write_hunk(out: &mut impl Write, old: &[u8], new: &[u8]) -> Result<()> {
+    let literal = deflate(new)?;
+    let delta = if old.is_empty() || new.is_empty() {
+        None
+    } else {
+        let delta = create_delta(old, new);
+        let deflated = deflate(&delta)?;
+        (deflated.len() < literal.len()).then_some((delta.len(), deflated))
+    };
+    let data = match delta {
+        Some((size, deflated)) => {
+            writeln!(out, "delta {size}")?;
+            deflated
+        }
+        None => {
+            writeln!(out, "literal {}", new.len())?;
+            literal
+        }
+    };
+    for line in data.chunks(LINE_BYTES) {
+        let len = if line.len() <= 26 {
+            b'A' + line.len() as u8 - 1
+        } else {
+            b'a' + line.len() as u8 - 27
+        };
+        out.write_all(&[len])?;
+        out.write_all(&encode_85(line))?;
+        writeln!(out)?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write the body of a `GIT binary patch`: the hunk from `old` to `new`, then the reverse one.
+pub(crate) fn write_binary_patch(out: &mut impl Write, old: &[u8], new: &[u8]) -> Result<()> {
+    writeln!(out, "GIT binary patch")?;
+    write_hunk(out, old, new)?;
+    write_hunk(out, new, old)
+}
+
+/// Parse one hunk starting at `lines[i]`, returning it and the index of the line after it.
+fn parse_hunk(lines: &[&[u8]], mut i: usize) -> Result<Option<(BinaryHunk, usize)>> {
+    let Some(header) = lines.get(i) else {
+        return Ok(None);
+    };
+    let header = String::from_utf8_lossy(header);
+    let header = header.trim_end();
+    let (kind, size) = if let Some(size) = header.strip_prefix("literal ") {
+        ("literal", size)
+    } else if let Some(size) = header.strip_prefix("delta ") {
+        ("delta", size)
+    } else {
+        return Ok(None);
+    };
+    let size: usize = size
+        .parse()
+        .with_context(|| format!("malformed binary hunk header {header:?}"))?;
+    i += 1;
+
+    let mut deflated = Vec::new();
+    loop {
+        let Some(line) = lines.get(i) else {
+            bail!("binary patch ends in the middle of a hunk");
+        };
+        i += 1;
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some((&len, text)) = line.split_first() else {
+            break;
+        };
+        let len = match len {
+            b'A'..=b'Z' => len - b'A' + 1,
+            b'a'..=b'z' => len - b'a' + 27,
+            _ => bail!("corrupt binary patch line"),
+        };
+        deflated.extend(decode_85(text, len as usize)?);
+    }
+    let mut data = Vec::with_capacity(size);
+    ZlibDecoder::new(&deflated[..])
+        .read_to_end(&mut data)
+        .context("inflate binary patch")?;
+    if data.len() != size {
+        bail!(
+            "binary patch hunk has {} bytes, expected {size}",
+            data.len()
+        );
+    }
+    let hunk = match kind {
+        "literal" => BinaryHunk::Literal(data),
+        _ => BinaryHunk::Delta(data),
+    };
+    Ok(Some((hunk, i)))
+}
+
+/// Parse the hunks after a `GIT binary patch` line starting at `lines[i]`: the forward hunk and
+/// the optional reverse one. Returns them and the index of the line after them.
+pub(crate) fn parse_binary_patch(
+    lines: &[&[u8]],
+    i: usize,
+) -> Result<(BinaryHunk, Option<BinaryHunk>, usize)> {
+    let (forward, i) = parse_hunk(lines, i)?.context("binary patch without data")?;
+    match parse_hunk(lines, i)? {
+        Some((reverse, i)) => Ok((forward, Some(reverse), i)),
+        None => Ok((forward, None, i)),
+    }
+}
+
+/// The hex `algo` blob hash of `data`.
+pub(crate) fn blob_hash(algo: HashAlgo, data: &[u8]) -> String {
+    let mut hasher = algo.hasher();
+    hasher.update(format!("blob {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().to_string()
+}
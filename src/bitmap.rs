@@ -0,0 +1,468 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    hash::{HashAlgo, ObjectId},
+    pack::PackIndex,
+};
+
+const BITMAP_MAGIC: &[u8; 4] = b"BITM";
+const REV_MAGIC: &[u8; 4] = b"RIDX";
+
+/// The bitmaps cover everything reachable from their commits, not just what's in the pack.
+const OPT_FULL_DAG: u16 = 0x1;
+
+/// A set of objects of a pack, one bit each by their position in the pack.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub(crate) fn get(&self, pos: usize) -> bool {
+        self.words
+            .get(pos / 64)
+            .is_some_and(|word| word & (1 << (pos % 64)) != 0)
+    }
+
+    pub(crate) fn set(&mut self, pos: usize) {
+        if self.words.len() <= pos / 64 {
+            self.words.resize(pos / 64 + 1, 0);
+        }
+        self.words[pos / 64] |= 1 << (pos % 64);
+    }
+
+    pub(crate) fn or(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    fn xor(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word ^= other;
+        }
+    }
+
+    /// Remove the objects of `other`.
+    pub(crate) fn and_not(&mut self, other: &Bitmap) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    /// The positions in the set, in order.
+    pub(crate) fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| i * 64 + bit)
+        })
+    }
+}
+
+fn read_u32(buf: &[u8], at: &mut usize) -> Result<u32> {
+    let bytes = buf.get(*at..*at + 4).context("bitmap file is truncated")?;
+    *at += 4;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Skip the EWAH compressed bitmap at `at`, returning where it starts.
+fn skip_ewah(buf: &[u8], at: &mut usize) -> Result<usize> {
+    let start = *at;
+    *at += 4;
+    let words = read_u32(buf, at)? as usize;
+    *at += words * 8 + 4;
+    if *at > buf.len() {
+        bail!("bitmap file is truncated");
+    }
+    Ok(start)
+}
+
+/// Decode the EWAH compressed bitmap at `at`: its size in bits and its number of 64-bit words,
+/// the words, and the position of the last run-length word. Each run-length word gives a run of
+/// all-zero or all-one words in its low 33 bits, followed by as many literal words as its high
+/// 31 bits say. A run can't go past the size, so a corrupt one doesn't get to allocate more.
+fn read_ewah(buf: &[u8], mut at: usize) -> Result<Bitmap> {
+    let bits = read_u32(buf, &mut at)? as usize;
+    let count = read_u32(buf, &mut at)? as usize;
+    let data = buf
+        .get(at..at + count * 8)
+        .context("bitmap file is truncated")?;
+    let compressed = data
+        .chunks_exact(8)
+        .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+        .collect::<Vec<_>>();
+
+    let size = bits.div_ceil(64);
+    let mut words = Vec::with_capacity(size);
+    let mut i = 0;
+    while i < compressed.len() {
+        let marker = compressed[i];
+        let fill = if marker & 1 != 0 { u64::MAX } else { 0 };
+        let run = ((marker >> 1) & 0xffff_ffff) as usize;
+        let literals = (marker >> 33) as usize;
+        let literal = compressed
+            .get(i + 1..i + 1 + literals)
+            .context("EWAH bitmap is corrupt")?;
+        if run > size.saturating_sub(words.len()) {
+            bail!("EWAH bitmap runs past its {bits} bits");
+        }
+        words.resize(words.len() + run, fill);
+        words.extend_from_slice(literal);
+        i += 1 + literals;
+    }
+    words.truncate(size);
+    Ok(Bitmap { words })
+}
+
+/// The order of the objects of the pack of `index` from its reverse index `rev` (a `.rev` file):
+/// after the header, the position in the index of each object, in pack order.
+fn read_rev(index: &PackIndex, rev: &[u8]) -> Result<Vec<usize>> {
+    let count = index.hashes().len();
+    let hash_len = index.pack_checksum().as_bytes().len();
+    if rev.len() != 12 + count * 4 + 2 * hash_len || &rev[0..4] != REV_MAGIC {
+        bail!("reverse index has no header or the wrong size");
+    }
+    let version = u32::from_be_bytes(rev[4..8].try_into().unwrap());
+    if version != 1 {
+        bail!("unsupported reverse index version {version}");
+    }
+    let trailer = 12 + count * 4;
+    if rev[trailer..trailer + hash_len] != *index.pack_checksum().as_bytes() {
+        bail!("reverse index doesn't match its pack");
+    }
+    rev[12..trailer]
+        .chunks_exact(4)
+        .map(|i| {
+            let i = u32::from_be_bytes(i.try_into().unwrap()) as usize;
+            match i < count {
+                true => Ok(i),
+                false => bail!("reverse index names object {i} outside the pack"),
+            }
+        })
+        .collect()
+}
+
+/// The bitmap of a commit, XORed with the one `xor_offset` entries before it unless that's 0.
+struct Entry {
+    xor_offset: usize,
+    ewah: usize,
+}
+
+/// The reachability bitmaps of a pack, as git writes them next to the pack's index with
+/// `repack -b`: for some of its commits, the set of objects reachable from the commit.
+pub(crate) struct BitmapIndex {
+    index: PackIndex,
+    data: Vec<u8>,
+    /// Where each object is in the index, by its position in the pack.
+    pack_order: Vec<usize>,
+    /// Where each object is in the pack, by its position in the index.
+    positions: Vec<usize>,
+    entries: Vec<Entry>,
+    /// The entries of the commits with bitmaps, by their position in the pack.
+    commits: HashMap<usize, usize>,
+}
+
+impl BitmapIndex {
+    /// Open the bitmaps of the pack under `objects_dir/pack` that has them (there is at most one),
+    /// if any does. The pack's objects are named with `algo`.
+    pub(crate) fn open(objects_dir: &Path, algo: HashAlgo) -> Result<Option<Self>> {
+        let pack_dir = objects_dir.join("pack");
+        if !pack_dir.is_dir() {
+            return Ok(None);
+        }
+        let mut paths = fs::read_dir(&pack_dir)
+            .with_context(|| format!("open directory {}", pack_dir.display()))?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        paths.retain(|p| {
+            p.extension().is_some_and(|ext| ext == "bitmap") && p.with_extension("pack").exists()
+        });
+        paths.sort();
+        let Some(path) = paths.first() else {
+            return Ok(None);
+        };
+        let idx_path = path.with_extension("idx");
+        let idx = fs::read(&idx_path).with_context(|| format!("read {}", idx_path.display()))?;
+        let index = PackIndex::parse(&idx, algo)
+            .with_context(|| format!("parse {}", idx_path.display()))?;
+        let rev_path = path.with_extension("rev");
+        let pack_order = match rev_path.exists() {
+            true => {
+                let rev =
+                    fs::read(&rev_path).with_context(|| format!("read {}", rev_path.display()))?;
+                read_rev(&index, &rev).with_context(|| format!("parse {}", rev_path.display()))?
+            }
+            false => {
+                let mut by_offset = index
+                    .offsets()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &offset)| (offset, i))
+                    .collect::<Vec<_>>();
+                by_offset.sort_unstable();
+                by_offset.into_iter().map(|(_, i)| i).collect()
+            }
+        };
+        let data = fs::read(path).with_context(|| format!("read {}", path.display()))?;
+        Self::parse(index, pack_order, data)
+            .with_context(|| format!("parse {}", path.display()))
+            .map(Some)
+    }
+
+    fn parse(index: PackIndex, pack_order: Vec<usize>, data: Vec<u8>) -> Result<Self> {
+        // the header ends in the checksum of the pack
+        let checksum = index.pack_checksum().as_bytes();
+        let header_len = 12 + checksum.len();
+        if data.len() < header_len || &data[0..4] != BITMAP_MAGIC {
+            bail!("bitmap file has no header");
+        }
+        let version = u16::from_be_bytes(data[4..6].try_into().unwrap());
+        if version != 1 {
+            bail!("unsupported bitmap version {version}");
+        }
+        let flags = u16::from_be_bytes(data[6..8].try_into().unwrap());
+        if flags & OPT_FULL_DAG == 0 {
+            bail!("bitmaps without full closure are not supported");
+        }
+        let count = u32::from_be_bytes(data[8..12].try_into().unwrap()) as usize;
+        if data[12..header_len] != *checksum {
+            bail!("bitmap doesn't match its pack");
+        }
+
+        let mut positions = vec![0; pack_order.len()];
+        for (pos, &i) in pack_order.iter().enumerate() {
+            positions[i] = pos;
+        }
+
+        // the objects of each type, which the walk doesn't need
+        let mut at = header_len;
+        for _ in 0..4 {
+            skip_ewah(&data, &mut at)?;
+        }
+        let mut entries = Vec::with_capacity(count);
+        let mut commits = HashMap::with_capacity(count);
+        for n in 0..count {
+            let i = read_u32(&data, &mut at)? as usize;
+            let &pos = positions
+                .get(i)
+                .with_context(|| format!("bitmap for object {i} outside the pack"))?;
+            let xor_offset = *data.get(at).context("bitmap file is truncated")? as usize;
+            // then a byte of flags, which only matter when writing
+            at += 2;
+            if xor_offset > n {
+                bail!("bitmap {n} is XORed with one before the first");
+            }
+            let ewah = skip_ewah(&data, &mut at)?;
+            entries.push(Entry { xor_offset, ewah });
+            commits.insert(pos, n);
+        }
+        Ok(Self {
+            index,
+            data,
+            pack_order,
+            positions,
+            entries,
+            commits,
+        })
+    }
+
+    /// Where `hash` is in the pack, if it's there.
+    pub(crate) fn position(&self, hash: &str) -> Option<usize> {
+        let hash = ObjectId::from_hex(hash).ok()?;
+        self.index.position(&hash).map(|i| self.positions[i])
+    }
+
+    /// The hash of the object at `pos` in the pack.
+    pub(crate) fn hash_at(&self, pos: usize) -> String {
+        self.index.hashes()[self.pack_order[pos]].to_string()
+    }
+
+    /// The objects reachable from the commit at `pos` in the pack, if it has a bitmap.
+    pub(crate) fn reachable_from(&self, pos: usize) -> Result<Option<Bitmap>> {
+        let Some(&n) = self.commits.get(&pos) else {
+            return Ok(None);
+        };
+        // follow the chain of XORed bitmaps back to a whole one, then undo it
+        let mut chain = vec![n];
+        let mut n = n;
+        while self.entries[n].xor_offset != 0 {
+            n -= self.entries[n].xor_offset;
+            chain.push(n);
+        }
+        let mut bitmap = Bitmap::default();
+        for n in chain.into_iter().rev() {
+            bitmap.xor(&read_ewah(&self.data, self.entries[n].ewah)?);
+        }
+        Ok(Some(bitmap))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::collections::BTreeSet;
+
+    /// An EWAH bitmap of `bits` bits from its compressed words.
+    fn ewah(bits: u32, words: &[u64]) -> Vec<u8> {
+        let mut buf = bits.to_be_bytes().to_vec();
+        buf.extend((words.len() as u32).to_be_bytes());
+        for word in words {
+            buf.extend(word.to_be_bytes());
+        }
+        // the position of the last run-length word, which reading doesn't need
+        buf.extend(0u32.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn read_ewah_expands_runs_and_literals() {
+        // two words of ones then one literal, then a word of zeros then one literal
+        let words = [
+            (1 << 33) | (2 << 1) | 1,
+            0b1010,
+            (1 << 33) | (1 << 1),
+            1 << 63,
+        ];
+        let bitmap = read_ewah(&ewah(320, &words), 0).unwrap();
+        let ones = bitmap.ones().collect::<Vec<_>>();
+        let mut expected = (0..128).collect::<Vec<_>>();
+        expected.extend([129, 131, 319]);
+        assert_eq!(ones, expected);
+
+        let mut at = 0;
+        let buf = ewah(320, &words);
+        assert_eq!(skip_ewah(&buf, &mut at).unwrap(), 0);
+        assert_eq!(at, buf.len());
+
+        // a run-length word promising more literals than there are
+        assert!(read_ewah(&ewah(128, &[1 << 33]), 0).is_err());
+        // or a longer run than the bitmap has bits
+        let error = read_ewah(&ewah(128, &[0xffff_ffff << 1]), 0).unwrap_err();
+        assert!(error.to_string().contains("128 bits"), "{error}");
+        assert!(read_ewah(&ewah(128, &[3 << 1]), 0).is_err());
+    }
+
+    #[test]
+    fn bitmap_set_operations() {
+        let mut a = Bitmap::default();
+        for pos in [1, 64, 200] {
+            a.set(pos);
+        }
+        let mut b = Bitmap::default();
+        b.set(64);
+        b.set(3);
+        assert!(a.get(200) && !a.get(3) && !a.get(1000));
+
+        let mut union = a.clone();
+        union.or(&b);
+        assert_eq!(union.ones().collect::<Vec<_>>(), [1, 3, 64, 200]);
+        union.and_not(&b);
+        assert_eq!(union.ones().collect::<Vec<_>>(), [1, 200]);
+    }
+
+    /// A repository of `algo` objects with a branchy history, repacked by git with bitmaps;
+    /// `options` are passed to `repack`'s git.
+    fn bitmapped_repo(algo: HashAlgo, options: &[&str]) -> TempDir {
+        let dir = TempDir::new();
+        let format = format!("--object-format={}", algo.name());
+        dir.git(&["init", "-q", &format], b"");
+        for n in 0..12 {
+            fs::write(dir.path().join(format!("f{}", n % 4)), format!("{n}\n")).unwrap();
+            dir.git(&["add", "."], b"");
+            dir.git(&["commit", "-q", "-m", &format!("c{n}")], b"");
+            if n == 5 {
+                dir.git(&["branch", "side"], b"");
+            }
+        }
+        dir.git(&["checkout", "-q", "side"], b"");
+        fs::write(dir.path().join("side"), "side\n").unwrap();
+        dir.git(&["add", "."], b"");
+        dir.git(&["commit", "-q", "-m", "side"], b"");
+        dir.git(
+            &[options, &["repack", "-q", "-a", "-d", "-b"]].concat(),
+            b"",
+        );
+        dir
+    }
+
+    /// Check every bitmap of the repository against what git says its commit reaches.
+    fn check_against_git(dir: &TempDir, algo: HashAlgo) {
+        let objects = dir.path().join(".git/objects");
+        let bitmaps = BitmapIndex::open(&objects, algo)
+            .unwrap()
+            .expect("a bitmap index");
+        let mut checked = 0;
+        for commit in dir.git(&["rev-list", "--all"], b"").lines() {
+            let pos = bitmaps.position(commit).expect("every commit is packed");
+            assert_eq!(bitmaps.hash_at(pos), commit);
+            let Some(bitmap) = bitmaps.reachable_from(pos).unwrap() else {
+                continue;
+            };
+            let reached = bitmap
+                .ones()
+                .map(|pos| bitmaps.hash_at(pos))
+                .collect::<BTreeSet<_>>();
+            let by_git = dir.git(&["rev-list", "--objects", "--no-object-names", commit], b"");
+            assert_eq!(
+                reached,
+                by_git.lines().map(str::to_string).collect(),
+                "{commit}"
+            );
+            checked += 1;
+        }
+        assert!(checked > 0, "no commit has a bitmap");
+    }
+
+    #[test]
+    fn reads_the_bitmaps_git_writes() {
+        check_against_git(
+            &bitmapped_repo(HashAlgo::Sha1, &["-c", "pack.writeReverseIndex=false"]),
+            HashAlgo::Sha1,
+        );
+    }
+
+    #[test]
+    fn reads_the_bitmaps_git_writes_with_a_reverse_index() {
+        let dir = bitmapped_repo(HashAlgo::Sha1, &["-c", "pack.writeReverseIndex=true"]);
+        let pack = dir.path().join(".git/objects/pack");
+        let has_rev = fs::read_dir(pack).unwrap().any(|entry| {
+            entry
+                .unwrap()
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == "rev")
+        });
+        assert!(has_rev);
+        check_against_git(&dir, HashAlgo::Sha1);
+    }
+
+    #[test]
+    fn reads_the_bitmaps_of_a_sha256_repository() {
+        for reverse_index in ["false", "true"] {
+            let option = format!("pack.writeReverseIndex={reverse_index}");
+            let dir = bitmapped_repo(HashAlgo::Sha256, &["-c", &option]);
+            check_against_git(&dir, HashAlgo::Sha256);
+        }
+    }
+
+    #[test]
+    fn no_bitmap_file_is_no_index() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"], b"");
+        assert!(
+            BitmapIndex::open(&dir.path().join(".git/objects"), HashAlgo::Sha1)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
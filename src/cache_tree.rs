@@ -0,0 +1,123 @@
+use anyhow::{bail, Context, Result};
+
+use crate::hash::{HashAlgo, ObjectId};
+
+/// The index's `TREE` extension: the tree object of each directory, as long as none of the
+/// entries below it changed since the tree was written.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CacheTree {
+    /// The number of index entries below this directory and its tree hash, or `None` if the
+    /// directory changed since its tree was written.
+    pub(crate) valid: Option<(usize, ObjectId)>,
+    /// Subdirectories by name, in the order they are stored.
+    pub(crate) children: Vec<(String, CacheTree)>,
+}
+
+impl CacheTree {
+    /// A valid node for a tree with `entries` index entries below it.
+    pub(crate) fn new(entries: usize, hash: ObjectId, mut children: Vec<(String, Self)>) -> Self {
+        // git keeps children ordered by name length first
+        children.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+        Self {
+            valid: Some((entries, hash)),
+            children,
+        }
+    }
+
+    /// Parse the body of a `TREE` extension, whose hashes `algo` made.
+    pub(crate) fn parse(data: &[u8], algo: HashAlgo) -> Result<Self> {
+        let (name, tree, rest) = Self::parse_node(data, algo.raw_len())?;
+        if !name.is_empty() || !rest.is_empty() {
+            bail!("malformed TREE extension");
+        }
+        Ok(tree)
+    }
+
+    /// Parse one node and its children: `<name>\0<entry count> <subtree count>\n`, followed by
+    /// the tree hash if the entry count isn't -1.
+    fn parse_node(data: &[u8], hash_len: usize) -> Result<(String, Self, &[u8])> {
+        let nul = data
+            .iter()
+            .position(|b| *b == 0)
+            .context("TREE extension is truncated")?;
+        let name = String::from_utf8(data[..nul].to_vec()).context("TREE path isn't utf-8")?;
+        let rest = &data[nul + 1..];
+        let newline = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .context("TREE extension is truncated")?;
+        let counts = std::str::from_utf8(&rest[..newline]).context("malformed TREE counts")?;
+        let (entries, subtrees) = counts.split_once(' ').context("malformed TREE counts")?;
+        let entries: i64 = entries.parse().context("malformed TREE entry count")?;
+        let subtrees: usize = subtrees.parse().context("malformed TREE subtree count")?;
+        let mut rest = &rest[newline + 1..];
+
+        let valid = if entries >= 0 {
+            let hash = rest
+                .get(..hash_len)
+                .context("TREE extension is truncated")?;
+            let hash = ObjectId::from_bytes(hash)?;
+            rest = &rest[hash_len..];
+            Some((entries as usize, hash))
+        } else {
+            None
+        };
+        let mut children = Vec::with_capacity(subtrees);
+        for _ in 0..subtrees {
+            let (child_name, child, after) = Self::parse_node(rest, hash_len)?;
+            children.push((child_name, child));
+            rest = after;
+        }
+        Ok((name, Self { valid, children }, rest))
+    }
+
+    /// The body of a `TREE` extension for this tree.
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.serialize_node("", &mut buf);
+        buf
+    }
+
+    fn serialize_node(&self, name: &str, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(0);
+        let entries = self.valid.map_or(-1, |(entries, _)| entries as i64);
+        buf.extend_from_slice(format!("{entries} {}\n", self.children.len()).as_bytes());
+        if let Some((_, hash)) = &self.valid {
+            buf.extend_from_slice(hash.as_bytes());
+        }
+        for (child_name, child) in &self.children {
+            child.serialize_node(child_name, buf);
+        }
+    }
+
+    /// The node for the subdirectory `name`, if there is one.
+    pub(crate) fn child(&self, name: &str) -> Option<&CacheTree> {
+        self.children
+            .iter()
+            .find(|(child, _)| child == name)
+            .map(|(_, tree)| tree)
+    }
+
+    /// The trees of this directory and those below it that are still valid.
+    pub(crate) fn trees(&self) -> Vec<ObjectId> {
+        let mut trees = Vec::new();
+        if let Some((_, hash)) = &self.valid {
+            trees.push(*hash);
+        }
+        for (_, child) in &self.children {
+            trees.extend(child.trees());
+        }
+        trees
+    }
+
+    /// Mark the directories containing `path` as changed.
+    pub(crate) fn invalidate(&mut self, path: &str) {
+        self.valid = None;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some((_, child)) = self.children.iter_mut().find(|(name, _)| name == dir) {
+                child.invalidate(rest);
+            }
+        }
+    }
+}
@@ -0,0 +1,2104 @@
+//! The `git-rs` command line: parsing the arguments and running the command they ask for.
+
+use std::{ffi::OsString, path::PathBuf, time::Instant};
+
+use anyhow::{bail, Result};
+use clap::{value_parser, Parser, Subcommand};
+use regex::Regex;
+
+use crate::{
+    color::ColorWhen,
+    commands::{
+        self,
+        am::Resume,
+        branch::{BranchAction, BranchFilter},
+        cat_file::cmd_cat_file,
+        clone::CloneOptions,
+        fetch::FetchOptions,
+        hash_object::cmd_hash_object,
+        init::cmd_init,
+        log::{CommitFilter, PrettyOptions},
+        ls_files::Show,
+        push::PushOptions,
+        tag::TagAction,
+    },
+    date,
+    diff::{DiffOptions, RawFormat, Whitespace},
+    hash::HashAlgo,
+    interrupt,
+    objects::ObjectType,
+    repository::{repo_setup, ConfigScope},
+    trace::{quote_args, trace, TRACE},
+    ExitStatus,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Don't do optional writes, such as refreshing the index during `status`, that could get
+    /// in the way of other processes.
+    #[arg(long, global = true)]
+    no_optional_locks: bool,
+
+    /// Write the output of `log` and `diff` straight to stdout, without a pager.
+    #[arg(long, global = true)]
+    no_pager: bool,
+
+    /// The git directory of the repository to work in, rather than one found from the current
+    /// directory. Like `$GIT_DIR`, which this sets.
+    #[arg(long, global = true, value_name = "path")]
+    git_dir: Option<PathBuf>,
+
+    /// The top of the work tree. Like `$GIT_WORK_TREE`, which this sets.
+    #[arg(long, global = true, value_name = "path")]
+    work_tree: Option<PathBuf>,
+
+    #[command(subcommand)]
+    cmd: Commands,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Commands {
+    /// Stage files from the work tree, which marks their conflicts resolved.
+    Add {
+        /// Stage every change in the work tree when no paths are given.
+        #[arg(short = 'A', long)]
+        all: bool,
+
+        /// The files or directories to stage.
+        #[arg(required_unless_present = "all")]
+        paths: Vec<String>,
+    },
+
+    /// Apply a series of patches from a mailbox, committing each one.
+    Am {
+        /// Fall back to a three-way merge when a patch doesn't apply cleanly.
+        #[arg(short = '3', long = "3way")]
+        three_way: bool,
+
+        /// Drop everything in the message body before a scissors line (`-- >8 --`).
+        #[arg(short = 'c', long)]
+        scissors: bool,
+
+        /// Commit the resolved patch and go on with the rest.
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+
+        /// Skip the current patch.
+        #[arg(long, conflicts_with = "abort")]
+        skip: bool,
+
+        /// Stop and restore the original branch.
+        #[arg(long)]
+        abort: bool,
+
+        /// An mbox file, or a directory with one mail per file.
+        #[arg(required_unless_present_any = ["resume", "skip", "abort"])]
+        mbox: Option<PathBuf>,
+    },
+
+    /// Apply a patch to the files in the work tree.
+    Apply {
+        /// Only check that the patch applies, without changing anything.
+        #[arg(long)]
+        check: bool,
+
+        /// Undo the patch instead of applying it.
+        #[arg(short = 'R', long)]
+        reverse: bool,
+
+        /// Apply the hunks that can be applied and leave the others in `<path>.rej` files.
+        #[arg(long)]
+        reject: bool,
+
+        /// Let hunks whose full context doesn't match apply with only this many context lines
+        /// on each side.
+        #[arg(short = 'C', value_name = "n")]
+        context: Option<usize>,
+
+        /// The patch, a unified diff; read from standard input by default.
+        patch: Option<PathBuf>,
+    },
+
+    /// Initialize a new, empty repository.
+    Init {
+        /// Where to create the repository.
+        path: Option<PathBuf>,
+
+        /// Only print error messages.
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// The hash function naming the repository's objects.
+        #[arg(long, value_name = "FORMAT")]
+        object_format: Option<HashAlgo>,
+    },
+
+    /// Show what revision and author last modified each line of a file.
+    Blame {
+        /// Show identities as recorded, without mapping them through `.mailmap`.
+        #[arg(long)]
+        no_mailmap: bool,
+
+        /// `[<rev>] <file>`: the file to annotate, as of HEAD or the given revision.
+        #[arg(num_args = 1..=2, required = true)]
+        args: Vec<String>,
+    },
+
+    /// List or create branches.
+    Branch {
+        /// Only list branches that contain this commit (HEAD by default).
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        contains: Option<String>,
+
+        /// Only list branches whose tips are reachable from this commit (HEAD by default).
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        merged: Option<String>,
+
+        /// Only list branches whose tips are not reachable from this commit (HEAD by default).
+        #[arg(long, value_name = "commit", num_args = 0..=1, default_missing_value = "HEAD")]
+        no_merged: Option<String>,
+
+        /// Show each branch's tip and subject; given twice, also name its upstream.
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        verbose: u8,
+
+        /// Make this branch (e.g. `origin/main`) the upstream of the named or current branch.
+        #[arg(
+            short = 'u',
+            long,
+            value_name = "upstream",
+            conflicts_with = "unset_upstream"
+        )]
+        set_upstream_to: Option<String>,
+
+        /// Forget the upstream of the named or current branch.
+        #[arg(long)]
+        unset_upstream: bool,
+
+        /// Name of a branch to create; branches are listed when absent.
+        #[arg(conflicts_with_all = ["contains", "merged", "no_merged", "verbose"])]
+        name: Option<String>,
+
+        /// Commit the new branch starts at (HEAD by default).
+        #[arg(requires = "name", conflicts_with_all = ["set_upstream_to", "unset_upstream"])]
+        start: Option<String>,
+    },
+
+    /// List, create or delete tags.
+    Tag {
+        /// Make an annotated tag, with a message written in the editor unless `-m` gives it.
+        #[arg(short, long)]
+        annotate: bool,
+
+        /// The message of an annotated tag (implies `-a`).
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Replace a tag that already exists.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Delete the named tags.
+        #[arg(short, long, conflicts_with_all = ["annotate", "message", "force", "list"])]
+        delete: bool,
+
+        /// List the tags matching the given patterns (all of them without any).
+        #[arg(short, long, conflicts_with_all = ["annotate", "message", "force"])]
+        list: bool,
+
+        /// The tag to create, then the object it tags (HEAD by default); the tags to delete;
+        /// or the patterns to list.
+        args: Vec<String>,
+    },
+
+    /// Show canonical names and emails of contacts (`Name <email>` or `<email>`).
+    CheckMailmap {
+        #[arg(required = true)]
+        contacts: Vec<String>,
+    },
+
+    /// Check that commits are well-formed: one tree, valid parents, and parseable identities.
+    VerifyCommit {
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+
+    /// Check that annotated tags are well-formed: one object, type and name, and a parseable
+    /// tagger.
+    VerifyTag {
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Check that every reachable object exists and is intact, and list dangling objects.
+    Fsck {
+        /// Only check that reachable objects exist, without reading blobs or rehashing objects.
+        #[arg(long)]
+        connectivity_only: bool,
+    },
+
+    /// Delete the loose objects that nothing reaches, and those that are also packed.
+    Prune {
+        /// Only list the unreachable objects, without deleting anything.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// List the unreachable objects as they are deleted.
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Pack the reachable objects that aren't packed yet.
+    Repack {
+        /// Pack everything reachable into one pack, not only the loose objects.
+        #[arg(short = 'a')]
+        all: bool,
+
+        /// Then delete what the new pack makes redundant: the loose copies of its objects,
+        /// and with -a the other packs.
+        #[arg(short = 'd')]
+        delete: bool,
+
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// Provide content of repository objects.
+    CatFile {
+        /// Specify the type.
+        #[arg(
+            value_parser = value_parser!(ObjectType),
+            required_unless_present_any = ["textconv", "batch_check"]
+        )]
+        object_type: Option<ObjectType>,
+
+        /// The object to display, or `<rev>:<path>` for the entry at a path in a tree.
+        #[arg(required_unless_present_any = ["textconv", "batch_check"])]
+        object: Option<String>,
+
+        /// Follow symlinks in `<rev>:<path>` to the entries they point at inside the tree.
+        #[arg(long, conflicts_with = "textconv")]
+        follow_symlinks: bool,
+
+        /// Show the blob at `<rev>:<path>` converted by the path's textconv driver.
+        #[arg(long, value_name = "rev:path", conflicts_with_all = ["object_type", "object"])]
+        textconv: Option<String>,
+
+        /// Print the id, type and size of each object named on standard input, or a line in
+        /// the given format with `%(objectname)`, `%(objecttype)` and `%(objectsize)` replaced.
+        #[arg(
+            long,
+            value_name = "format",
+            num_args = 0..=1,
+            require_equals = true,
+            conflicts_with_all = ["object_type", "object", "textconv"]
+        )]
+        batch_check: Option<Option<String>>,
+
+        /// With `--batch-check`, show every object in the repository instead of reading names.
+        #[arg(long, requires = "batch_check")]
+        batch_all_objects: bool,
+
+        /// With `--batch-all-objects`, show the objects in the order they are stored rather than
+        /// sorted by id.
+        #[arg(long, requires = "batch_all_objects")]
+        unordered: bool,
+
+        /// With `--batch-check`, don't flush the output after each object.
+        #[arg(long, requires = "batch_check")]
+        buffer: bool,
+    },
+
+    /// Output information on each ref.
+    ForEachRef {
+        /// Format of each line, with atoms such as `%(refname)`, `%(refname:short)`,
+        /// `%(objectname)`, `%(objectname:short)`, `%(objecttype)`, `%(subject)`,
+        /// `%(committerdate:iso)` and `%(upstream)` replaced by the ref's values. A `*` before the
+        /// atom name (as in `%(*objectname)`) takes it from the object an annotated tag points at.
+        #[arg(long, default_value = commands::for_each_ref::DEFAULT_FORMAT)]
+        format: String,
+
+        /// Sort by this atom (e.g. `refname` or `-committerdate` for newest first); the last
+        /// `--sort` given is the primary key.
+        #[arg(long, value_name = "key")]
+        sort: Vec<String>,
+
+        /// Only show refs under this prefix or matching this glob, e.g. `refs/heads/*`.
+        pattern: Option<String>,
+    },
+
+    /// Write commits as patch mails, one file each, to be sent or applied with `am`.
+    FormatPatch {
+        /// Write the files to this directory instead of the current one (or
+        /// `format.outputDirectory`).
+        #[arg(short = 'o', long = "output-directory", value_name = "dir")]
+        output_dir: Option<PathBuf>,
+
+        /// Print the mails instead of writing files.
+        #[arg(long)]
+        stdout: bool,
+
+        /// Take every commit reachable from the revision, back to the root.
+        #[arg(long)]
+        root: bool,
+
+        /// Write changes to binary files as patches that apply (the default).
+        #[arg(long, overrides_with = "no_binary")]
+        binary: bool,
+
+        /// Only say that binary files differ, instead of writing patches for them.
+        #[arg(long)]
+        no_binary: bool,
+
+        /// `<since>` for the commits since then, or `<since>..<until>`.
+        #[arg(required_unless_present = "root")]
+        range: Option<String>,
+    },
+
+    /// List refs with the objects they point at.
+    ShowRef {
+        /// Only show branches (with `--tags`, branches and tags).
+        #[arg(long)]
+        heads: bool,
+
+        /// Only show tags (with `--heads`, branches and tags).
+        #[arg(long)]
+        tags: bool,
+
+        /// Also show the object each annotated tag points at, as `<ref>^{}`.
+        #[arg(short, long)]
+        dereference: bool,
+
+        /// Only show the object hashes.
+        #[arg(short = 's', long = "hash")]
+        hash: bool,
+
+        /// Only show refs whose name is, or ends in `/` followed by, one of these.
+        patterns: Vec<String>,
+    },
+
+    /// Get, set or list config settings.
+    Config {
+        /// Use the user's `~/.gitconfig` (or `$XDG_CONFIG_HOME/git/config` if only that exists).
+        #[arg(long, conflicts_with_all = ["local", "worktree"])]
+        global: bool,
+
+        /// Use the repository's `config` (the default for setting).
+        #[arg(long, conflicts_with = "worktree")]
+        local: bool,
+
+        /// Use the worktree's `config.worktree`, which needs `extensions.worktreeConfig` once
+        /// there are linked worktrees.
+        #[arg(long)]
+        worktree: bool,
+
+        /// Remove the setting.
+        #[arg(long, conflicts_with_all = ["value", "list"])]
+        unset: bool,
+
+        /// List every setting as `name=value` lines.
+        #[arg(short, long, conflicts_with = "name")]
+        list: bool,
+
+        /// The setting, as `section.key` or `section.subsection.key`.
+        #[arg(required_unless_present = "list")]
+        name: Option<String>,
+
+        /// The value to set it to; without one, print the current value.
+        value: Option<String>,
+    },
+
+    /// Print a logical variable such as `GIT_AUTHOR_IDENT`, resolved the way commands use it.
+    Var {
+        /// List the config and every variable.
+        #[arg(short = 'l', conflicts_with = "variable")]
+        list: bool,
+
+        /// One of `GIT_AUTHOR_IDENT`, `GIT_COMMITTER_IDENT`, `GIT_EDITOR` or `GIT_PAGER`.
+        #[arg(required_unless_present = "list")]
+        variable: Option<String>,
+    },
+
+    /// Show the attributes `.gitattributes` files give paths.
+    CheckAttr {
+        /// The attributes to look up, followed by the paths unless those come after `--`.
+        #[arg(required = true)]
+        attrs: Vec<String>,
+
+        /// The paths to look the attributes up for.
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+
+    /// Check that a ref name is well-formed, exiting with status 1 if it isn't.
+    CheckRefFormat {
+        /// Check the name as a branch name (without `refs/heads/`) and print it.
+        #[arg(long)]
+        branch: bool,
+
+        /// Remove leading and repeated slashes first, and print the result when valid.
+        #[arg(long)]
+        normalize: bool,
+
+        /// Accept names with a single component, like `main`.
+        #[arg(long)]
+        allow_onelevel: bool,
+
+        name: String,
+    },
+
+    /// Add trailers to a commit message, or parse the ones it has.
+    InterpretTrailers {
+        /// Print the message's trailers instead of the message.
+        #[arg(long)]
+        parse: bool,
+
+        /// A `Key: value` (or `Key=value`) trailer to add unless the message already has it.
+        #[arg(long, value_name = "trailer")]
+        trailer: Vec<String>,
+
+        /// The message file; standard input is read when absent.
+        file: Option<PathBuf>,
+    },
+
+    /// Clean up text from standard input as commit messages are: strip trailing whitespace and
+    /// collapse blank lines.
+    Stripspace {
+        /// Also drop lines starting with `#`.
+        #[arg(short, long)]
+        strip_comments: bool,
+
+        /// Turn every line into a `#` comment instead.
+        #[arg(short, long, conflicts_with = "strip_comments")]
+        comment_lines: bool,
+    },
+
+    /// Compute object ID and optionally creates a blob from a file.
+    HashObject {
+        /// Write the object into the git database.
+        #[arg(short, long)]
+        write: bool,
+
+        /// Specify the type.
+        #[arg(short, long, default_value_t = ObjectType::Blob, value_parser = value_parser!(ObjectType))]
+        object_type: ObjectType,
+
+        /// Hash blobs as if they were the file at this path, converted by its filters and line
+        /// ending settings.
+        #[arg(long)]
+        path: Option<String>,
+
+        /// Hash the content as it is, without converting it.
+        #[arg(long, conflicts_with = "path")]
+        no_filters: bool,
+
+        /// Read the object from standard input.
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
+
+        /// Read the object from a file.
+        #[arg(required_unless_present = "stdin")]
+        file: Option<PathBuf>,
+    },
+
+    /// Download objects and refs from another repository.
+    Fetch {
+        /// Fetch all tags from the remote.
+        #[arg(short = 't', long, conflicts_with = "no_tags")]
+        tags: bool,
+
+        /// Don't fetch tags the refspecs don't name.
+        #[arg(short = 'n', long)]
+        no_tags: bool,
+
+        /// Update local refs even when it isn't a fast-forward.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Delete remote-tracking refs whose branch is gone from the remote.
+        #[arg(short, long, conflicts_with = "no_prune")]
+        prune: bool,
+
+        /// Don't prune, whatever fetch.prune says.
+        #[arg(long)]
+        no_prune: bool,
+
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// The program to run for the remote end instead of git-upload-pack.
+        #[arg(long)]
+        upload_pack: Option<String>,
+
+        /// The remote name or URL to fetch from.
+        remote: Option<String>,
+
+        /// The refs to fetch, and where to store them.
+        refspecs: Vec<String>,
+    },
+
+    /// Clone a repository into a new directory.
+    Clone {
+        /// Name the remote this instead of origin.
+        #[arg(short, long)]
+        origin: Option<String>,
+
+        /// Check out this branch instead of the remote's HEAD.
+        #[arg(short, long)]
+        branch: Option<String>,
+
+        /// Don't check out HEAD after cloning.
+        #[arg(short, long)]
+        no_checkout: bool,
+
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// The program to run for the remote end instead of git-upload-pack.
+        #[arg(short, long)]
+        upload_pack: Option<String>,
+
+        /// The repository to clone.
+        repository: String,
+
+        /// The directory to clone into.
+        directory: Option<PathBuf>,
+    },
+
+    /// Update remote refs along with the objects they need.
+    Push {
+        /// Update remote refs even when it isn't a fast-forward.
+        #[arg(short, long)]
+        force: bool,
+
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Make the branches pushed track the remote branches they were pushed to.
+        #[arg(short = 'u', long)]
+        set_upstream: bool,
+
+        /// The program to run for the remote end instead of git-receive-pack.
+        #[arg(long)]
+        receive_pack: Option<String>,
+
+        /// The remote name or URL to push to.
+        remote: Option<String>,
+
+        /// The refs to push, and where to.
+        refspecs: Vec<String>,
+    },
+
+    /// List references in a remote repository.
+    LsRemote {
+        /// Remote name or URL of the repository.
+        remote: String,
+    },
+
+    /// Show commit logs.
+    Log {
+        /// Show the changes each commit made, as a patch.
+        #[arg(short = 'p', long)]
+        patch: bool,
+
+        #[command(flatten)]
+        pretty: PrettyArgs,
+
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// Commits to start from (HEAD by default).
+        revs: Vec<String>,
+
+        #[command(flatten)]
+        limit: LimitArgs,
+    },
+
+    /// Show commits with the changes they made.
+    Show {
+        /// Only show the commits, without their changes.
+        #[arg(short = 's', long)]
+        no_patch: bool,
+
+        #[command(flatten)]
+        pretty: PrettyArgs,
+
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// The commits to show (HEAD by default).
+        revs: Vec<String>,
+    },
+
+    /// Name commits by the nearest ref they are reachable from, like `main~3`.
+    NameRev {
+        /// Only print the names, not the commits asked for.
+        #[arg(long)]
+        name_only: bool,
+
+        #[arg(required = true)]
+        revs: Vec<String>,
+    },
+
+    /// Print the objects revisions name.
+    RevParse {
+        /// Print the full name of the ref each revision means instead.
+        #[arg(long)]
+        symbolic_full_name: bool,
+
+        #[arg(required = true)]
+        revs: Vec<String>,
+    },
+
+    LsTree {
+        #[arg(short)]
+        name_only: bool,
+
+        /// The tree to list, or a commit or tag that leads to one.
+        tree_ish: String,
+    },
+
+    WriteTree {
+        /// Number of threads used to hash and write blobs.
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
+    },
+
+    /// Read trees into the index, replacing it or merging them.
+    ReadTree {
+        /// Merge the trees into the index: with one tree, keep the stat data of unchanged
+        /// entries; with two, move from the first tree to the second keeping local changes; with
+        /// three (base, ours, theirs), leave conflicting paths unmerged.
+        #[arg(short = 'm')]
+        merge: bool,
+
+        /// Also update the work tree to match the merged index. Files that are about to change
+        /// must be up to date with the index.
+        #[arg(short = 'u', requires = "merge")]
+        update: bool,
+
+        /// Add the tree below this directory, keeping the rest of the index.
+        #[arg(long)]
+        prefix: Option<String>,
+
+        #[arg(required = true, num_args = 1..=3)]
+        trees: Vec<String>,
+    },
+
+    /// Write files from the index to the work tree.
+    CheckoutIndex {
+        /// Check out every entry below the current directory.
+        #[arg(short, long)]
+        all: bool,
+
+        /// Overwrite existing files.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Write the files below this path (relative to the top of the work tree) instead.
+        #[arg(long)]
+        prefix: Option<String>,
+
+        paths: Vec<String>,
+    },
+
+    /// List the files in the index, untracked files, or files changed in the work tree.
+    LsFiles {
+        /// Show the files in the index (the default).
+        #[arg(short, long)]
+        cached: bool,
+
+        /// Show untracked files.
+        #[arg(short, long)]
+        others: bool,
+
+        /// Show only ignored files among the untracked ones.
+        #[arg(short, long)]
+        ignored: bool,
+
+        /// Leave out the files that `.gitignore`, `.git/info/exclude` and `core.excludesFile`
+        /// ignore.
+        #[arg(long)]
+        exclude_standard: bool,
+
+        /// Show files that changed in the work tree, deleted ones included.
+        #[arg(short, long)]
+        modified: bool,
+
+        /// Show files deleted from the work tree.
+        #[arg(short, long)]
+        deleted: bool,
+
+        /// Fail if a path matches none of the files shown.
+        #[arg(long)]
+        error_unmatch: bool,
+
+        /// End each path with NUL instead of a newline, and don't quote it.
+        #[arg(short = 'z')]
+        nul: bool,
+
+        paths: Vec<String>,
+    },
+
+    /// Print every field of the index, for debugging.
+    #[command(hide = true)]
+    DumpIndex,
+
+    /// Show the differences between HEAD, the index and the work tree, and untracked files.
+    Status {
+        /// Print one line per path in a stable format for scripts.
+        #[arg(long)]
+        porcelain: bool,
+
+        /// End porcelain lines with NUL instead of newline, without quoting paths.
+        #[arg(short = 'z')]
+        nul: bool,
+    },
+
+    /// Point refs at new values, checking the values they have first; several refs change
+    /// together, or none do.
+    UpdateRef {
+        /// The reason recorded in the reflogs.
+        #[arg(short = 'm', value_name = "reason")]
+        message: Option<String>,
+
+        /// Delete the ref; the value after its name is then the one it must be at.
+        #[arg(short = 'd')]
+        delete: bool,
+
+        /// Read `update`, `create`, `delete` and `verify` commands from standard input, one per
+        /// line, and make the changes together; `start`, `prepare`, `commit` and `abort` control
+        /// the transaction explicitly.
+        #[arg(long, conflicts_with_all = ["delete", "name"])]
+        stdin: bool,
+
+        /// The ref to change.
+        #[arg(required_unless_present = "stdin")]
+        name: Option<String>,
+
+        /// The value to point it at.
+        new_value: Option<String>,
+
+        /// The value it must be at, or the null id if it must not exist.
+        old_value: Option<String>,
+    },
+
+    /// Update index entries from the work tree or from given blobs.
+    UpdateIndex {
+        /// Allow adding paths that aren't in the index yet.
+        #[arg(long)]
+        add: bool,
+
+        /// Remove the entries of paths that are missing from the work tree.
+        #[arg(long)]
+        remove: bool,
+
+        /// Re-stat every entry, refreshing the cached stat data of unchanged files.
+        #[arg(long)]
+        refresh: bool,
+
+        /// Add an entry for a blob without touching the work tree.
+        #[arg(long, value_name = "mode>,<sha1>,<path")]
+        cacheinfo: Vec<String>,
+
+        /// Set (`+x`) or clear (`-x`) the executable bit of the given paths' entries.
+        #[arg(long, allow_hyphen_values = true, value_name = "(+|-)x")]
+        chmod: Option<String>,
+
+        paths: Vec<String>,
+    },
+
+    /// Manage the remotes of the repository.
+    Remote {
+        #[command(subcommand)]
+        command: RemoteCommands,
+    },
+
+    /// Inspect the submodules of the repository.
+    Submodule {
+        #[command(subcommand)]
+        command: SubmoduleCommands,
+    },
+
+    /// Find the commit that introduced a bug by binary search through the history.
+    Bisect {
+        #[command(subcommand)]
+        command: BisectCommands,
+    },
+
+    /// Set local changes aside, and bring them back later.
+    Stash {
+        #[command(subcommand)]
+        command: Option<StashCommands>,
+    },
+
+    /// Manage the work trees linked to the repository.
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommands,
+    },
+
+    /// Add or inspect notes attached to commits.
+    Notes {
+        #[command(subcommand)]
+        command: NotesCommands,
+    },
+
+    /// Join the history of another commit into the current branch.
+    Merge {
+        /// The commit to merge.
+        rev: String,
+
+        /// The message of the merge commit.
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Stage the merged result but don't commit it.
+        #[arg(long)]
+        no_commit: bool,
+
+        /// Stage the merged changes without recording a merge, so that the next commit has a
+        /// single parent.
+        #[arg(long, conflicts_with = "no_commit")]
+        squash: bool,
+    },
+
+    /// Merge two commits into a tree, without touching the index or work tree.
+    MergeTree {
+        /// Write the merged tree and print its id (the only mode there is).
+        #[arg(long)]
+        write_tree: bool,
+
+        /// The commit merged into, whose version conflict markers show first.
+        branch1: String,
+
+        /// The commit to merge.
+        branch2: String,
+    },
+
+    /// Compare two versions of a series of commits, like a branch before and after a rebase.
+    RangeDiff {
+        /// How much larger than a commit's own changes the differences from its counterpart
+        /// may be, in percent, before the two are shown as one removed and another added.
+        #[arg(long, default_value_t = 60)]
+        creation_factor: usize,
+
+        /// `<base>..<tip> <base>..<tip>`, `<tip>...<tip>` or `<base> <tip> <tip>`.
+        #[arg(required = true, num_args = 1..=3)]
+        ranges: Vec<String>,
+    },
+
+    /// Replay the commits of the current branch on top of another commit.
+    Rebase {
+        /// Replay onto this commit instead of the upstream.
+        #[arg(long, value_name = "newbase")]
+        onto: Option<String>,
+
+        /// Edit the list of commits to replay, and what is done with each, before the rebase
+        /// starts.
+        #[arg(short, long, conflicts_with = "todo_file")]
+        interactive: bool,
+
+        /// Move `fixup!` and `squash!` commits after the commits they name in the todo list,
+        /// marked to be folded into them.
+        #[arg(long, requires = "interactive")]
+        autosquash: bool,
+
+        /// Run this todo list (`pick`, `reword`, `squash`, `fixup` or `drop` and a commit on
+        /// each line) instead of replaying every commit.
+        #[arg(long, value_name = "file")]
+        todo_file: Option<PathBuf>,
+
+        /// Commit the resolved commit and go on with the rest.
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort"])]
+        resume: bool,
+
+        /// Skip the current commit.
+        #[arg(long, conflicts_with = "abort")]
+        skip: bool,
+
+        /// Stop and go back to the original branch.
+        #[arg(long)]
+        abort: bool,
+
+        /// The commits of the current branch that this doesn't have are replayed.
+        #[arg(required_unless_present_any = ["resume", "skip", "abort"])]
+        upstream: Option<String>,
+    },
+
+    /// Reuse recorded resolutions of conflicts seen before. With no subcommand, record the
+    /// resolutions of the conflicts resolved so far.
+    Rerere {
+        #[command(subcommand)]
+        command: Option<RerereCommands>,
+    },
+
+    /// Receive objects and ref updates from a pushing client over stdin and stdout: the server
+    /// side of push.
+    ReceivePack {
+        /// The repository to update.
+        directory: PathBuf,
+    },
+
+    /// Send objects to a fetching client over stdin and stdout: the server side of fetch and
+    /// clone.
+    UploadPack {
+        /// The repository to serve.
+        directory: PathBuf,
+    },
+
+    CommitTree {
+        /// The commit message; read from stdin if not given.
+        #[arg(short)]
+        message: Option<String>,
+
+        #[arg(short, value_parser = validate_object_hash)]
+        parent_tree_hash: Option<String>,
+
+        /// tree hash to print
+        #[arg(value_parser = validate_object_hash)]
+        tree_hash: String,
+    },
+
+    /// Record the staged changes as a new commit on HEAD.
+    Commit {
+        /// The commit message; without it, the message is written in the editor.
+        #[arg(short)]
+        message: Option<String>,
+
+        /// Stage modified and deleted tracked files before committing.
+        #[arg(short, long)]
+        all: bool,
+
+        /// Commit even if the tree is the same as the parent's.
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Add a `Signed-off-by` trailer for the committer.
+        #[arg(short, long)]
+        signoff: bool,
+
+        /// Make a commit that `rebase --autosquash` folds into this one, dropping its message.
+        #[arg(long, value_name = "commit", conflicts_with = "squash")]
+        fixup: Option<String>,
+
+        /// Make a commit that `rebase --autosquash` folds into this one, joining the messages.
+        #[arg(long, value_name = "commit")]
+        squash: Option<String>,
+    },
+
+    /// Summarize commits by author.
+    Shortlog {
+        /// Only show the number of commits per author.
+        #[arg(short, long)]
+        summary: bool,
+
+        /// Sort authors by number of commits instead of by name.
+        #[arg(short, long)]
+        numbered: bool,
+
+        /// Show each author's email.
+        #[arg(short, long)]
+        email: bool,
+
+        /// Group by identities as recorded, without mapping them through `.mailmap`.
+        #[arg(long)]
+        no_mailmap: bool,
+
+        /// Commits to start from (HEAD by default).
+        revs: Vec<String>,
+    },
+
+    /// Switch branches or check out a commit, updating the index and work tree.
+    Checkout {
+        /// Discard local changes that would be overwritten.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Branch or commit to check out.
+        rev: String,
+    },
+
+    /// Switch to a branch, checking it out.
+    Switch {
+        /// Create the branch first, at <start> (HEAD by default).
+        #[arg(short = 'c', long)]
+        create: bool,
+
+        /// Detach HEAD at the commit <branch> names instead.
+        #[arg(short, long, conflicts_with = "create")]
+        detach: bool,
+
+        /// Discard local changes that would be overwritten.
+        #[arg(short, long, alias = "discard-changes")]
+        force: bool,
+
+        branch: String,
+
+        #[arg(requires = "create")]
+        start: Option<String>,
+    },
+
+    /// Restore work tree files from the index, or index entries from HEAD.
+    Restore {
+        /// Restore from this commit instead.
+        #[arg(short, long)]
+        source: Option<String>,
+
+        /// Restore the index (from HEAD by default).
+        #[arg(short = 'S', long)]
+        staged: bool,
+
+        /// Restore the work tree too, which is the default without `--staged`.
+        #[arg(short = 'W', long)]
+        worktree: bool,
+
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Show changes between the work tree, the index and commits.
+    Diff {
+        #[command(flatten)]
+        diff: DiffArgs,
+
+        /// Compare with the index rather than the work tree.
+        #[arg(long, alias = "staged", conflicts_with = "new")]
+        cached: bool,
+
+        /// Exit with status 1 if there are differences, and 0 if there are none.
+        #[arg(long)]
+        exit_code: bool,
+
+        /// Print nothing; implies `--exit-code`.
+        #[arg(long)]
+        quiet: bool,
+
+        /// The revision to compare from: the index by default, or HEAD with `--cached`.
+        old: Option<String>,
+
+        /// The revision to compare to: the work tree by default.
+        new: Option<String>,
+    },
+
+    /// Show the changes between the index and the work tree in raw format.
+    DiffFiles {
+        #[command(flatten)]
+        raw: RawArgs,
+    },
+
+    /// Show the changes between a tree and the work tree (or the index) in raw format.
+    DiffIndex {
+        /// Compare with the index rather than the work tree.
+        #[arg(long)]
+        cached: bool,
+
+        #[command(flatten)]
+        raw: RawArgs,
+
+        /// The tree (or commit) to compare from.
+        tree_ish: String,
+    },
+}
+
+/// Which of `--continue`, `--skip` and `--abort` resumes a stopped `am` or `rebase`.
+fn resume_action(resume: bool, skip: bool, abort: bool) -> Option<Resume> {
+    if resume {
+        Some(Resume::Continue)
+    } else if skip {
+        Some(Resume::Skip)
+    } else if abort {
+        Some(Resume::Abort)
+    } else {
+        None
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum BisectCommands {
+    /// Start bisecting, optionally with the bad commit and good ones.
+    Start {
+        /// A commit that has the bug.
+        bad: Option<String>,
+
+        /// Commits that don't have it.
+        good: Vec<String>,
+    },
+
+    /// Mark a commit (HEAD by default) as having the bug.
+    Bad { rev: Option<String> },
+
+    /// Mark commits (HEAD by default) as not having the bug.
+    Good { revs: Vec<String> },
+
+    /// Mark commits (HEAD by default) as untestable.
+    Skip { revs: Vec<String> },
+
+    /// Stop bisecting and go back to where HEAD was before.
+    Reset,
+
+    /// Test each commit with a command: exit status 0 means good, 125 untestable, and anything
+    /// else below 128 bad.
+    Run {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum SubmoduleCommands {
+    /// Show the commit each submodule has checked out, and whether it is the one recorded in
+    /// the index.
+    Status {
+        /// Show the commit recorded in the index instead.
+        #[arg(long)]
+        cached: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum RerereCommands {
+    /// List the paths whose conflicts are being recorded.
+    Status,
+
+    /// Show how each of those files changed since its conflict was recorded.
+    Diff,
+
+    /// Forget the recorded resolution of a path's conflict.
+    Forget { path: String },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum NotesCommands {
+    /// Attach a note to a commit.
+    Add {
+        /// The note; several are joined as separate paragraphs.
+        #[arg(short, long, required = true)]
+        message: Vec<String>,
+
+        /// Replace the commit's existing note.
+        #[arg(short, long)]
+        force: bool,
+
+        /// The commit to annotate (HEAD by default).
+        object: Option<String>,
+    },
+
+    /// Print the note attached to a commit.
+    Show {
+        /// The commit whose note to show (HEAD by default).
+        object: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum WorktreeCommands {
+    /// Create a work tree at <path> with <branch> checked out, sharing this repository's
+    /// objects and branches.
+    Add { path: PathBuf, branch: String },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum StashCommands {
+    /// Save the local changes as a new stash and reset to HEAD (the default).
+    Push {
+        /// Describe the stash with this instead of HEAD's subject.
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// List the stashes, newest first.
+    List,
+
+    /// Apply a stash's changes to the work tree, keeping the stash.
+    Apply {
+        /// The stash, as `stash@{<n>}` or `<n>` (the newest by default).
+        stash: Option<String>,
+    },
+
+    /// Remove a stash from the list.
+    Drop {
+        /// The stash, as `stash@{<n>}` or `<n>` (the newest by default).
+        stash: Option<String>,
+    },
+
+    /// Apply a stash and remove it from the list, unless it conflicts.
+    Pop {
+        /// The stash, as `stash@{<n>}` or `<n>` (the newest by default).
+        stash: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum RemoteCommands {
+    /// Delete remote-tracking refs whose branch is gone from the remote.
+    Prune {
+        /// Only report what would be pruned.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// The remote to prune.
+        name: String,
+    },
+}
+
+/// Which commits `log` shows, and in what order.
+#[derive(clap::Args, Debug, Clone)]
+struct LimitArgs {
+    /// Limit the number of commits to show.
+    #[arg(short = 'n', long)]
+    max_count: Option<usize>,
+
+    /// Only show commits more recent than a date, like `2026-10-01` or `2.weeks.ago`.
+    #[arg(long, alias = "after", value_name = "date", value_parser = parse_date)]
+    since: Option<i64>,
+
+    /// Only show commits older than a date.
+    #[arg(long, alias = "before", value_name = "date", value_parser = parse_date)]
+    until: Option<i64>,
+
+    /// Only show commits whose author matches this regular expression (any of them, if given
+    /// more than once).
+    #[arg(long, value_name = "pattern")]
+    author: Vec<Regex>,
+
+    /// Only show commits whose message matches this regular expression (any of them, if given
+    /// more than once).
+    #[arg(long, value_name = "pattern")]
+    grep: Vec<Regex>,
+
+    /// Only show merge commits.
+    #[arg(long, conflicts_with = "no_merges")]
+    merges: bool,
+
+    /// Don't show merge commits.
+    #[arg(long)]
+    no_merges: bool,
+
+    /// Show the commits in reverse order, oldest first.
+    #[arg(long)]
+    reverse: bool,
+
+    /// Don't show a parent before all its children, whatever the commit dates.
+    #[arg(long)]
+    topo_order: bool,
+
+    /// Follow the history of the single path given across renames.
+    #[arg(long)]
+    follow: bool,
+
+    /// Only follow the first parent of merges, leaving out the commits they merged in.
+    #[arg(long)]
+    first_parent: bool,
+
+    /// Only show commits that change these files or directories (after `--`).
+    #[arg(last = true)]
+    paths: Vec<String>,
+}
+
+impl LimitArgs {
+    fn filter(self) -> CommitFilter {
+        CommitFilter {
+            paths: self.paths,
+            follow: self.follow,
+            first_parent: self.first_parent,
+            max_count: self.max_count,
+            since: self.since,
+            until: self.until,
+            authors: self.author,
+            greps: self.grep,
+            merges: match (self.merges, self.no_merges) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            },
+            reverse: self.reverse,
+            topo_order: self.topo_order,
+        }
+    }
+}
+
+/// Options shared by the commands that print patches.
+#[derive(clap::Args, Debug, Clone)]
+struct DiffArgs {
+    /// Detect renames, optionally with a minimum similarity percentage (default 50).
+    #[arg(
+        short = 'M',
+        long,
+        value_name = "n",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "50",
+        value_parser = parse_percentage
+    )]
+    find_renames: Option<u8>,
+
+    /// Turn off rename detection.
+    #[arg(long, conflicts_with = "find_renames")]
+    no_renames: bool,
+
+    /// Ignore whitespace when comparing lines.
+    #[arg(short = 'w', long)]
+    ignore_all_space: bool,
+
+    /// Ignore changes in the amount of whitespace.
+    #[arg(short = 'b', long)]
+    ignore_space_change: bool,
+
+    /// Ignore changes whose lines are all blank.
+    #[arg(long)]
+    ignore_blank_lines: bool,
+
+    /// Show changed words inline instead of changed lines.
+    #[arg(long)]
+    word_diff: bool,
+
+    /// Compare files by their blobs even if they have a textconv driver.
+    #[arg(long)]
+    no_textconv: bool,
+
+    /// Write binary changes as patches that `am` can apply.
+    #[arg(long)]
+    binary: bool,
+
+    /// Show a summary of the lines changed in each file instead of the patch.
+    #[arg(long)]
+    stat: bool,
+
+    /// Color the output: `always`, `never`, or `auto` (when writing to a terminal). Without
+    /// it, `color.diff` and `color.ui` decide.
+    #[arg(
+        long,
+        value_name = "when",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "always"
+    )]
+    color: Option<ColorWhen>,
+}
+
+impl DiffArgs {
+    fn options(&self) -> DiffOptions {
+        let mut opts = DiffOptions::default();
+        if self.no_renames {
+            opts.rename_threshold = None;
+        } else if let Some(threshold) = self.find_renames {
+            opts.rename_threshold = Some(threshold);
+        }
+        opts.whitespace = if self.ignore_all_space {
+            Whitespace::IgnoreAll
+        } else if self.ignore_space_change {
+            Whitespace::IgnoreChange
+        } else {
+            Whitespace::Exact
+        };
+        opts.ignore_blank_lines = self.ignore_blank_lines;
+        opts.word_diff = self.word_diff;
+        opts.textconv = !self.no_textconv;
+        opts.binary = self.binary;
+        opts.stat = self.stat;
+        opts
+    }
+}
+
+/// Options of the commands that print commits.
+#[derive(clap::Args, Debug, Clone)]
+struct PrettyArgs {
+    /// `medium`, `oneline`, `json` (a JSON object per commit, with its `hash`, `parents`,
+    /// `author`, `committer`, `message` and `trailers`), or a format string
+    /// (`format:`/`tformat:` prefixed or bare) with placeholders such as `%H`, `%an`, `%aN`
+    /// and `%s`.
+    #[arg(long, alias = "pretty")]
+    format: Option<String>,
+
+    /// Show each commit as its abbreviated hash and subject on one line.
+    #[arg(long, conflicts_with = "format")]
+    oneline: bool,
+
+    /// Show dates as `relative` (like `3 days ago`), `iso`, `short` (the day only), `unix`
+    /// or `default`, instead of as the `log.date` config says.
+    #[arg(long, value_name = "format")]
+    date: Option<String>,
+
+    /// Show identities as recorded, without mapping them through `.mailmap`.
+    #[arg(long)]
+    no_mailmap: bool,
+
+    /// Show the notes attached to each commit after its message.
+    #[arg(long)]
+    show_notes: bool,
+
+    /// Show the refs pointing at each commit (the default with `log.decorate`).
+    #[arg(long, overrides_with = "no_decorate")]
+    decorate: bool,
+
+    /// Don't show refs, even with `log.decorate`.
+    #[arg(long)]
+    no_decorate: bool,
+}
+
+impl PrettyArgs {
+    /// The options for printing commits, followed by their changes if `diff` is given.
+    fn options(self, diff: Option<DiffArgs>) -> PrettyOptions {
+        PrettyOptions {
+            format: match self.oneline {
+                true => Some("oneline".to_string()),
+                false => self.format,
+            },
+            abbrev: self.oneline,
+            date: self.date,
+            use_mailmap: !self.no_mailmap,
+            show_notes: self.show_notes,
+            decorate: (self.decorate || self.no_decorate).then_some(self.decorate),
+            color: diff.as_ref().and_then(|diff| diff.color),
+            diff: diff.map(|diff| diff.options()),
+            merge_changes: false,
+        }
+    }
+}
+
+/// Options of the commands that print changes in raw format.
+#[derive(clap::Args, Debug, Clone)]
+struct RawArgs {
+    /// Show only the names of changed files.
+    #[arg(long, conflicts_with = "name_status")]
+    name_only: bool,
+
+    /// Show only the names and status letters of changed files.
+    #[arg(long)]
+    name_status: bool,
+
+    /// End records with NUL rather than newline, and don't quote paths.
+    #[arg(short = 'z')]
+    z: bool,
+}
+
+impl RawArgs {
+    fn format(&self) -> RawFormat {
+        if self.name_only {
+            RawFormat::NameOnly
+        } else if self.name_status {
+            RawFormat::NameStatus
+        } else {
+            RawFormat::Raw
+        }
+    }
+}
+
+/// Validate that the object hash is a valid SHA-1 or SHA-256 hash
+/// TODO: support shortest-unique object hash
+fn validate_object_hash(s: &str) -> Result<String, String> {
+    if s.len() != 40 && s.len() != 64 {
+        return Err("Object hash must be 40 or 64 characters long".to_string());
+    }
+    if !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("Object hash must contain only hexadecimal characters".to_string());
+    }
+    Ok(s.to_string())
+}
+
+/// Parse a percentage such as `50` or `50%`.
+fn parse_date(s: &str) -> Result<i64, String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs() as i64;
+    date::parse_approxidate(s, now).ok_or_else(|| format!("invalid date '{s}'"))
+}
+
+fn parse_percentage(s: &str) -> Result<u8, String> {
+    let n = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .parse::<u8>()
+        .map_err(|e| e.to_string())?;
+    if n > 100 {
+        return Err("percentage must be at most 100".to_string());
+    }
+    Ok(n)
+}
+
+/// Spell out the short forms git allows that clap can't parse: a rename threshold attached to
+/// `-M` (like `-M50`), whose value is optional so clap only takes it after `=`, and `log -<n>`
+/// for `--max-count=<n>`.
+fn expand_short_forms(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut args = args.collect::<Vec<_>>();
+    let mut command = None;
+    // whether the argument before was a global option whose value is the next one
+    let mut value_next = false;
+    for arg in args.iter_mut().skip(1) {
+        let Some(text) = arg.to_str() else { continue };
+        if text == "--" {
+            break;
+        }
+        let Some(command) = &command else {
+            if value_next {
+                value_next = false;
+            } else if matches!(text, "--git-dir" | "--work-tree") {
+                value_next = true;
+            } else if !text.starts_with('-') {
+                command = Some(text.to_string());
+            }
+            continue;
+        };
+        let digits = |n: &str| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit());
+        let expanded = match text.strip_prefix('-') {
+            Some(n) if command == "log" && digits(n) => format!("--max-count={n}"),
+            Some(n)
+                if ["diff", "log", "show"].contains(&command.as_str())
+                    && n.strip_prefix('M')
+                        .is_some_and(|n| digits(n.trim_end_matches('%'))) =>
+            {
+                format!("--find-renames={}", &n[1..])
+            }
+            _ => continue,
+        };
+        *arg = expanded.into();
+    }
+    args
+}
+
+/// Run the command the arguments ask for, exiting with its status.
+pub fn main() -> Result<()> {
+    interrupt::install_handler()?;
+    let start = Instant::now();
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    trace!(TRACE, "built-in: git-rs {}", quote_args(&args));
+    let result = run(Args::parse_from(expand_short_forms(std::env::args_os())));
+    let code = match &result {
+        Ok(()) => 0,
+        Err(e) => e.downcast_ref().map_or(1, |ExitStatus(code)| *code),
+    };
+    trace!(
+        TRACE,
+        "exit: status {code} after {:.6} s",
+        start.elapsed().as_secs_f64()
+    );
+    if let Some(ExitStatus(code)) = result.as_ref().err().and_then(|e| e.downcast_ref()) {
+        std::process::exit(*code);
+    }
+    result
+}
+
+fn run(args: Args) -> Result<()> {
+    if args.no_optional_locks {
+        // like git, pass the setting on through the environment
+        std::env::set_var("GIT_OPTIONAL_LOCKS", "0");
+    }
+    // git passes these on through the environment too, for hooks and filters to see
+    if let Some(git_dir) = &args.git_dir {
+        std::env::set_var("GIT_DIR", std::path::absolute(git_dir)?);
+    }
+    if let Some(work_tree) = &args.work_tree {
+        std::env::set_var("GIT_WORK_TREE", std::path::absolute(work_tree)?);
+    }
+    let repo = || repo_setup(true);
+    match args.cmd {
+        Commands::Apply {
+            check,
+            reverse,
+            reject,
+            context,
+            patch,
+        } => commands::apply::invoke(&repo()?, patch, check, reverse, reject, context)?,
+        Commands::Am {
+            three_way,
+            scissors,
+            resume,
+            skip,
+            abort,
+            mbox,
+        } => commands::am::invoke(
+            &repo()?,
+            mbox,
+            three_way,
+            scissors,
+            resume_action(resume, skip, abort),
+        )?,
+        Commands::Blame {
+            no_mailmap,
+            mut args,
+        } => {
+            let path = args.pop().expect("clap requires a path");
+            commands::blame::invoke(&repo()?, path, args.pop(), !no_mailmap)?
+        }
+        Commands::Branch {
+            contains,
+            merged,
+            no_merged,
+            verbose,
+            set_upstream_to,
+            unset_upstream,
+            name,
+            start,
+        } => commands::branch::invoke(
+            &mut repo()?,
+            match (set_upstream_to, unset_upstream, name) {
+                (Some(upstream), _, name) => BranchAction::SetUpstream { name, upstream },
+                (None, true, name) => BranchAction::UnsetUpstream { name },
+                (None, false, Some(name)) => BranchAction::Create { name, start },
+                (None, false, None) => BranchAction::List {
+                    filter: BranchFilter {
+                        contains,
+                        merged,
+                        no_merged,
+                    },
+                    verbose,
+                },
+            },
+        )?,
+        Commands::Tag {
+            annotate,
+            message,
+            force,
+            delete,
+            list,
+            mut args,
+        } => commands::tag::invoke(
+            &repo()?,
+            if delete {
+                TagAction::Delete { names: args }
+            } else if list || args.is_empty() {
+                TagAction::List { patterns: args }
+            } else {
+                if args.len() > 2 {
+                    bail!("too many arguments");
+                }
+                let target = (args.len() == 2).then(|| args.remove(1));
+                TagAction::Create {
+                    name: args.remove(0),
+                    target,
+                    annotate,
+                    message,
+                    force,
+                }
+            },
+        )?,
+        Commands::Init {
+            path,
+            quiet,
+            object_format,
+        } => cmd_init(path, quiet, object_format)?,
+        Commands::CatFile {
+            object_type,
+            object,
+            follow_symlinks,
+            textconv,
+            batch_check,
+            batch_all_objects,
+            unordered,
+            buffer,
+        } => match (textconv, batch_check) {
+            (Some(spec), _) => commands::cat_file::invoke_textconv(&repo()?, spec)?,
+            (None, Some(format)) => commands::cat_file::invoke_batch_check(
+                &repo()?,
+                format,
+                batch_all_objects,
+                unordered,
+                buffer,
+            )?,
+            (None, None) => cmd_cat_file(
+                &repo()?,
+                object_type.expect("clap requires the object type"),
+                object.expect("clap requires the object"),
+                follow_symlinks,
+            )?,
+        },
+        Commands::CheckMailmap { contacts } => commands::check_mailmap::invoke(&repo()?, contacts)?,
+        Commands::VerifyCommit { commits } => commands::verify::invoke_commit(&repo()?, commits)?,
+        Commands::VerifyTag { tags } => commands::verify::invoke_tag(&repo()?, tags)?,
+        Commands::Fsck { connectivity_only } => {
+            commands::fsck::invoke(&repo()?, connectivity_only)?
+        }
+        Commands::Prune { dry_run, verbose } => {
+            commands::prune::invoke(&repo()?, dry_run, verbose)?
+        }
+        Commands::Repack { all, delete, quiet } => {
+            commands::repack::invoke(&repo()?, all, delete, quiet)?
+        }
+        Commands::ForEachRef {
+            format,
+            sort,
+            pattern,
+        } => commands::for_each_ref::invoke(&repo()?, pattern, format, sort)?,
+        Commands::FormatPatch {
+            output_dir,
+            stdout,
+            root,
+            binary: _,
+            no_binary,
+            range,
+        } => commands::format_patch::invoke(&repo()?, range, root, !no_binary, output_dir, stdout)?,
+        Commands::ShowRef {
+            heads,
+            tags,
+            dereference,
+            hash,
+            patterns,
+        } => commands::show_ref::invoke(&repo()?, patterns, heads, tags, dereference, hash)?,
+        Commands::Config {
+            global,
+            local,
+            worktree,
+            unset,
+            list,
+            name,
+            value,
+        } => {
+            let scope = match (global, local, worktree) {
+                (true, _, _) => Some(ConfigScope::Global),
+                (_, true, _) => Some(ConfigScope::Local),
+                (_, _, true) => Some(ConfigScope::Worktree),
+                _ => None,
+            };
+            commands::config::invoke(&mut repo()?, scope, name, value, unset, list)?
+        }
+        Commands::Var { list, variable } => commands::var::invoke(&repo()?, variable, list)?,
+        Commands::CheckAttr { attrs, paths } => {
+            commands::check_attr::invoke(&repo()?, attrs, paths)?
+        }
+        Commands::CheckRefFormat {
+            branch,
+            normalize,
+            allow_onelevel,
+            name,
+        } => commands::check_ref_format::invoke(name, branch, normalize, allow_onelevel)?,
+        Commands::HashObject {
+            write,
+            object_type,
+            path,
+            no_filters,
+            stdin: _,
+            file,
+        } => cmd_hash_object(
+            match write {
+                true => Some(repo_setup(true)?),
+                // outside a repository there are no attributes to convert by
+                false => repo_setup(true).ok(),
+            }
+            .as_ref(),
+            write,
+            object_type,
+            file,
+            path,
+            no_filters,
+        )?,
+        Commands::Fetch {
+            tags,
+            no_tags,
+            force,
+            prune,
+            no_prune,
+            quiet,
+            upload_pack,
+            remote,
+            refspecs,
+        } => commands::fetch::invoke(
+            &repo()?,
+            remote,
+            refspecs,
+            FetchOptions {
+                tags: (tags || no_tags).then_some(tags),
+                force,
+                prune: (prune || no_prune).then_some(prune),
+                quiet,
+                upload_pack,
+            },
+        )?,
+        Commands::Clone {
+            origin,
+            branch,
+            no_checkout,
+            quiet,
+            upload_pack,
+            repository,
+            directory,
+        } => commands::clone::invoke(
+            repository,
+            directory,
+            CloneOptions {
+                origin,
+                branch,
+                no_checkout,
+                quiet,
+                upload_pack,
+            },
+        )?,
+        Commands::Push {
+            force,
+            quiet,
+            set_upstream,
+            receive_pack,
+            remote,
+            refspecs,
+        } => commands::push::invoke(
+            &mut repo()?,
+            remote,
+            refspecs,
+            PushOptions {
+                force,
+                quiet,
+                set_upstream,
+                receive_pack,
+            },
+        )?,
+        Commands::LsRemote { remote } => commands::ls_remote::invoke(&repo_setup(false)?, remote)?,
+        Commands::Log {
+            patch,
+            pretty,
+            diff,
+            revs,
+            limit,
+        } => {
+            let diff = (patch || diff.stat).then_some(diff);
+            commands::log::invoke(
+                &repo()?,
+                revs,
+                limit.filter(),
+                pretty.options(diff),
+                !args.no_pager,
+            )?
+        }
+        Commands::Show {
+            no_patch,
+            pretty,
+            diff,
+            revs,
+        } => {
+            let diff = (!no_patch).then_some(diff);
+            commands::show::invoke(&repo()?, revs, pretty.options(diff), !args.no_pager)?
+        }
+        Commands::NameRev { name_only, revs } => {
+            commands::name_rev::invoke(&repo()?, revs, name_only)?
+        }
+        Commands::RevParse {
+            symbolic_full_name,
+            revs,
+        } => commands::rev_parse::invoke(&repo()?, revs, symbolic_full_name)?,
+        Commands::LsTree {
+            name_only,
+            tree_ish,
+        } => commands::ls_tree::invoke(&repo()?, name_only, tree_ish)?,
+        Commands::WriteTree { jobs } => commands::write_tree::invoke(&repo()?, jobs)?,
+        Commands::ReadTree {
+            merge,
+            update,
+            prefix,
+            trees,
+        } => commands::read_tree::invoke(&repo()?, trees, merge, update, prefix)?,
+        Commands::LsFiles {
+            cached,
+            others,
+            ignored,
+            exclude_standard,
+            modified,
+            deleted,
+            error_unmatch,
+            nul,
+            paths,
+        } => commands::ls_files::invoke(
+            &repo()?,
+            paths,
+            Show {
+                cached,
+                others,
+                ignored,
+                exclude_standard,
+                modified,
+                deleted,
+            },
+            error_unmatch,
+            nul,
+        )?,
+        Commands::CheckoutIndex {
+            all,
+            force,
+            prefix,
+            paths,
+        } => commands::checkout_index::invoke(&repo()?, paths, all, force, prefix)?,
+        Commands::Status { porcelain, nul } => commands::status::invoke(&repo()?, porcelain, nul)?,
+        Commands::DumpIndex => commands::dump_index::invoke(&repo()?)?,
+        Commands::UpdateRef {
+            message,
+            delete,
+            stdin,
+            name,
+            new_value,
+            old_value,
+        } => commands::update_ref::invoke(
+            &repo()?,
+            message,
+            delete,
+            stdin,
+            name,
+            new_value,
+            old_value,
+        )?,
+        Commands::Add { all, paths } => commands::add::invoke(&repo()?, paths, all)?,
+        Commands::UpdateIndex {
+            add,
+            remove,
+            refresh,
+            cacheinfo,
+            chmod,
+            paths,
+        } => {
+            commands::update_index::invoke(&repo()?, paths, add, remove, refresh, cacheinfo, chmod)?
+        }
+        Commands::Bisect { command } => match command {
+            BisectCommands::Start { bad, good } => {
+                commands::bisect::invoke_start(&repo()?, bad, good)?
+            }
+            BisectCommands::Bad { rev } => commands::bisect::invoke_bad(&repo()?, rev)?,
+            BisectCommands::Good { revs } => commands::bisect::invoke_good(&repo()?, revs)?,
+            BisectCommands::Skip { revs } => commands::bisect::invoke_skip(&repo()?, revs)?,
+            BisectCommands::Reset => commands::bisect::invoke_reset(&repo()?)?,
+            BisectCommands::Run { command } => commands::bisect::invoke_run(&repo()?, command)?,
+        },
+        Commands::Stash { command } => match command {
+            None => commands::stash::invoke_push(&repo()?, None)?,
+            Some(StashCommands::Push { message }) => {
+                commands::stash::invoke_push(&repo()?, message)?
+            }
+            Some(StashCommands::List) => commands::stash::invoke_list(&repo()?)?,
+            Some(StashCommands::Apply { stash }) => commands::stash::invoke_apply(&repo()?, stash)?,
+            Some(StashCommands::Drop { stash }) => commands::stash::invoke_drop(&repo()?, stash)?,
+            Some(StashCommands::Pop { stash }) => commands::stash::invoke_pop(&repo()?, stash)?,
+        },
+        Commands::Worktree { command } => match command {
+            WorktreeCommands::Add { path, branch } => {
+                commands::worktree::invoke_add(&repo()?, path, branch)?
+            }
+        },
+        Commands::Notes { command } => match command {
+            NotesCommands::Add {
+                message,
+                force,
+                object,
+            } => commands::notes::invoke_add(&repo()?, object, message, force)?,
+            NotesCommands::Show { object } => commands::notes::invoke_show(&repo()?, object)?,
+        },
+        Commands::Remote { command } => match command {
+            RemoteCommands::Prune { dry_run, name } => {
+                commands::remote::invoke_prune(&repo()?, name, dry_run)?
+            }
+        },
+        Commands::Submodule { command } => match command {
+            SubmoduleCommands::Status { cached } => {
+                commands::submodule::invoke_status(&repo()?, cached)?
+            }
+        },
+        Commands::Rerere { command } => match command {
+            None => commands::rerere::invoke(&repo()?)?,
+            Some(RerereCommands::Status) => commands::rerere::invoke_status(&repo()?)?,
+            Some(RerereCommands::Diff) => commands::rerere::invoke_diff(&repo()?)?,
+            Some(RerereCommands::Forget { path }) => {
+                commands::rerere::invoke_forget(&repo()?, path)?
+            }
+        },
+        Commands::Merge {
+            rev,
+            message,
+            no_commit,
+            squash,
+        } => commands::merge::invoke(&repo()?, rev, message, no_commit, squash)?,
+        Commands::MergeTree {
+            write_tree: _,
+            branch1,
+            branch2,
+        } => commands::merge_tree::invoke(&repo()?, branch1, branch2)?,
+        Commands::RangeDiff {
+            creation_factor,
+            ranges,
+        } => commands::range_diff::invoke(&repo()?, ranges, creation_factor, !args.no_pager)?,
+        Commands::Rebase {
+            onto,
+            interactive,
+            autosquash,
+            todo_file,
+            resume,
+            skip,
+            abort,
+            upstream,
+        } => commands::rebase::invoke(
+            &repo()?,
+            upstream,
+            onto,
+            interactive,
+            autosquash,
+            todo_file,
+            resume_action(resume, skip, abort),
+        )?,
+        Commands::ReceivePack { directory } => commands::receive_pack::invoke(directory)?,
+        Commands::UploadPack { directory } => commands::upload_pack::invoke(directory)?,
+        Commands::CommitTree {
+            message,
+            parent_tree_hash,
+            tree_hash,
+        } => commands::commit_tree::invoke(&repo()?, message, tree_hash, parent_tree_hash)?,
+        Commands::Shortlog {
+            summary,
+            numbered,
+            email,
+            no_mailmap,
+            revs,
+        } => commands::shortlog::invoke(&repo()?, revs, summary, numbered, email, !no_mailmap)?,
+        Commands::Checkout { force, rev } => commands::checkout::invoke(&repo()?, rev, force)?,
+        Commands::Switch {
+            create,
+            detach,
+            force,
+            branch,
+            start,
+        } => commands::switch::invoke(&repo()?, branch, create, start, detach, force)?,
+        Commands::Restore {
+            source,
+            staged,
+            worktree,
+            paths,
+        } => commands::restore::invoke(&repo()?, paths, source, staged, worktree)?,
+        Commands::Diff {
+            diff,
+            cached,
+            exit_code,
+            quiet,
+            old,
+            new,
+        } => commands::diff::invoke(
+            &repo()?,
+            old,
+            new,
+            cached,
+            diff.options(),
+            diff.color,
+            exit_code,
+            quiet,
+            !args.no_pager,
+        )?,
+        Commands::DiffFiles { raw } => commands::diff_files::invoke(&repo()?, raw.format(), raw.z)?,
+        Commands::DiffIndex {
+            cached,
+            raw,
+            tree_ish,
+        } => commands::diff_index::invoke(&repo()?, tree_ish, cached, raw.format(), raw.z)?,
+        Commands::Commit {
+            message,
+            all,
+            allow_empty,
+            signoff,
+            fixup,
+            squash,
+        } => commands::commit::invoke(&repo()?, message, all, allow_empty, signoff, fixup, squash)?,
+        Commands::InterpretTrailers {
+            parse,
+            trailer,
+            file,
+        } => commands::interpret_trailers::invoke(file, parse, trailer)?,
+        Commands::Stripspace {
+            strip_comments,
+            comment_lines,
+        } => commands::stripspace::invoke(strip_comments, comment_lines)?,
+    }
+    Ok(())
+}
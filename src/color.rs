@@ -0,0 +1,53 @@
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+
+use crate::repository::GitRepository;
+
+pub(crate) const RESET: &str = "\x1b[m";
+pub(crate) const BOLD: &str = "\x1b[1m";
+pub(crate) const RED: &str = "\x1b[31m";
+pub(crate) const GREEN: &str = "\x1b[32m";
+pub(crate) const CYAN: &str = "\x1b[36m";
+
+/// When to color output, from `--color=<when>` or the `color.*` config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ColorWhen {
+    Always,
+    Never,
+    /// Only when writing to a terminal.
+    Auto,
+}
+
+impl ColorWhen {
+    fn from_config(git_repo: &GitRepository, section: &str, key: &str) -> Option<Self> {
+        match git_repo
+            .config_get(section, key)?
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "always" => Some(Self::Always),
+            "never" => Some(Self::Never),
+            "auto" => Some(Self::Auto),
+            // a plain `true` means `auto`, as in git
+            _ => match git_repo.config_bool(section, key)? {
+                true => Some(Self::Auto),
+                false => Some(Self::Never),
+            },
+        }
+    }
+
+    /// Whether to color the output of `command`: `when` if given on the command line, otherwise
+    /// `color.<command>`, then `color.ui`, which defaults to `auto`.
+    pub(crate) fn resolve(when: Option<Self>, git_repo: &GitRepository, command: &str) -> bool {
+        let when = when
+            .or_else(|| Self::from_config(git_repo, "color", command))
+            .or_else(|| Self::from_config(git_repo, "color", "ui"))
+            .unwrap_or(Self::Auto);
+        match when {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => std::io::stdout().is_terminal(),
+        }
+    }
+}
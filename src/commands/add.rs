@@ -0,0 +1,63 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::update_index::update_path,
+    convert::Converter,
+    index::Index,
+    repository::{worktree_path, GitRepository},
+    worktree::{path_matches, WorktreeWalker},
+};
+
+/// Stage the files at `paths` (files or directories, relative to the current directory) from
+/// the work tree, or with `all` and no paths the whole work tree. Tracked files that are gone
+/// are removed from the index, and untracked files below a directory are added unless ignored.
+///
+/// Staging a path replaces all its entries, so a conflicted path's stages 1 to 3 collapse into
+/// the one at stage 0, marking it resolved.
+pub(crate) fn invoke(repo: &GitRepository, paths: Vec<String>, all: bool) -> Result<()> {
+    let paths = match paths.is_empty() && all {
+        true => vec![".".to_string()],
+        false => paths,
+    };
+    let (mut index, lock) = Index::lock(repo)?;
+    let mut converter = Converter::new(repo)?;
+    for path in &paths {
+        let spec = worktree_path(repo, path)?;
+        let full = repo.work_tree().join(&spec);
+        let mut found = match full.is_dir() {
+            true => {
+                let dir = match spec.is_empty() {
+                    true => String::new(),
+                    false => format!("{spec}/"),
+                };
+                WorktreeWalker::new(repo)
+                    .walk(&dir)?
+                    .into_iter()
+                    .filter(|file| !file.metadata.is_dir())
+                    .map(|file| file.path)
+                    .collect()
+            }
+            false if full.symlink_metadata().is_ok() => vec![spec.clone()],
+            false => Vec::new(),
+        };
+        // tracked files that are gone are staged as removed; submodules and files outside the
+        // sparse checkout stay as they are staged
+        found.extend(
+            index
+                .entries
+                .iter()
+                .filter(|e| path_matches(&spec, &e.path))
+                .filter(|e| !e.skip_worktree() && e.mode != 0o160000)
+                .map(|e| e.path.clone()),
+        );
+        found.sort();
+        found.dedup();
+        if found.is_empty() {
+            bail!("pathspec '{path}' did not match any files");
+        }
+        for file in &found {
+            update_path(repo, &mut converter, &mut index, file, true, true)?;
+        }
+    }
+    lock.commit(&index)
+}
@@ -0,0 +1,624 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    apply::{apply_patches, parse_patch, FilePatch},
+    commands::{
+        checkout::checkout_tree,
+        commit_tree::{identity, write_commit_object},
+        write_tree::write_index_tree,
+    },
+    date::parse_rfc2822,
+    hash::ObjectId,
+    index::{Index, IndexEntry},
+    objects::{object_read, read_commit, write_object, Kind},
+    refs::{ref_resolve, ref_update},
+    repository::{repo_path, GitRepository},
+};
+
+/// What to do with a stopped `am` or `rebase` session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Resume {
+    /// Commit the current patch (or commit) as resolved in the work tree and go on.
+    Continue,
+    /// Drop the current patch (or commit) and go on with the next one.
+    Skip,
+    /// Stop, and go back to where HEAD was before the session started.
+    Abort,
+}
+
+/// A patch recovered from an email.
+#[derive(Debug)]
+struct Mail {
+    /// `Name <email>`.
+    author: String,
+    /// `<unix time> <timezone>`, if the mail had a usable `Date` header.
+    date: Option<String>,
+    subject: String,
+    message: String,
+    patch: Vec<u8>,
+}
+
+/// The state of a session in `.git/rebase-apply`: one numbered file per mail plus counters,
+/// like git's own `am`.
+struct Session {
+    dir: PathBuf,
+    next: usize,
+    last: usize,
+    three_way: bool,
+    scissors: bool,
+}
+
+impl Session {
+    fn path(git_repo: &GitRepository) -> Result<PathBuf> {
+        repo_path(git_repo, &["rebase-apply"])
+    }
+
+    fn create(
+        git_repo: &GitRepository,
+        mails: &[Vec<u8>],
+        three_way: bool,
+        scissors: bool,
+    ) -> Result<Self> {
+        let dir = Self::path(git_repo)?;
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        for (n, mail) in mails.iter().enumerate() {
+            fs::write(dir.join(format!("{:04}", n + 1)), mail)?;
+        }
+        let orig_head = ref_resolve(git_repo, "HEAD")?.unwrap_or_default();
+        fs::write(dir.join("orig-head"), format!("{orig_head}\n"))?;
+        fs::write(dir.join("threeway"), if three_way { "t\n" } else { "f\n" })?;
+        fs::write(dir.join("scissors"), if scissors { "t\n" } else { "f\n" })?;
+        let session = Self {
+            dir,
+            next: 1,
+            last: mails.len(),
+            three_way,
+            scissors,
+        };
+        session.save()?;
+        Ok(session)
+    }
+
+    fn load(git_repo: &GitRepository) -> Result<Self> {
+        let dir = Self::path(git_repo)?;
+        if !dir.is_dir() {
+            bail!("no am session in progress");
+        }
+        if dir.join("rebasing").exists() {
+            bail!("a rebase is in progress; use git-rs rebase --continue, --skip or --abort");
+        }
+        let read = |name: &str| -> Result<String> {
+            Ok(fs::read_to_string(dir.join(name))
+                .with_context(|| format!("read rebase-apply/{name}"))?
+                .trim()
+                .to_string())
+        };
+        Ok(Self {
+            next: read("next")?.parse()?,
+            last: read("last")?.parse()?,
+            three_way: read("threeway")? == "t",
+            scissors: read("scissors")? == "t",
+            dir,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::write(self.dir.join("next"), format!("{}\n", self.next))?;
+        fs::write(self.dir.join("last"), format!("{}\n", self.last))?;
+        Ok(())
+    }
+
+    fn current(&self) -> Result<Mail> {
+        let path = self.dir.join(format!("{:04}", self.next));
+        let raw = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+        parse_mail(&raw, self.scissors)
+    }
+
+    fn orig_head(&self) -> Result<Option<String>> {
+        let head = fs::read_to_string(self.dir.join("orig-head"))?;
+        Ok(Some(head.trim().to_string()).filter(|h| !h.is_empty()))
+    }
+}
+
+/// Split `input` into raw mails: an mbox file, or a directory (e.g. format-patch output or a
+/// maildir) with one mail per file.
+fn read_mails(input: &Path) -> Result<Vec<Vec<u8>>> {
+    if input.is_dir() {
+        let mut files = Vec::new();
+        for dir in [input.join("cur"), input.join("new")] {
+            if dir.is_dir() {
+                files.extend(fs::read_dir(&dir)?.map(|e| e.map(|e| e.path())));
+            }
+        }
+        if files.is_empty() {
+            files.extend(fs::read_dir(input)?.map(|e| e.map(|e| e.path())));
+        }
+        let mut files = files.into_iter().collect::<std::io::Result<Vec<_>>>()?;
+        files.retain(|f| f.is_file());
+        files.sort();
+        return files
+            .iter()
+            .map(|f| fs::read(f).with_context(|| format!("read {}", f.display())))
+            .collect();
+    }
+
+    let data = fs::read(input).with_context(|| format!("read {}", input.display()))?;
+    let mut mails: Vec<Vec<u8>> = Vec::new();
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if is_from_line(line) || mails.is_empty() {
+            mails.push(Vec::new());
+        }
+        mails.last_mut().unwrap().extend_from_slice(line);
+    }
+    mails.retain(|m| !m.iter().all(u8::is_ascii_whitespace));
+    Ok(mails)
+}
+
+/// Whether `line` is an mbox separator (`From <sender> <date>`), e.g. the
+/// `From <hash> Mon Sep 17 00:00:00 2001` line that format-patch writes.
+fn is_from_line(line: &[u8]) -> bool {
+    line.starts_with(b"From ") && line.contains(&b':')
+}
+
+/// Decode RFC 2047 encoded words (`=?charset?Q?...?=` and `=?charset?B?...?=`). The charset is
+/// assumed to be UTF-8 compatible.
+fn decode_header(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let Some(word) = rest[start + 2..]
+            .splitn(4, '?')
+            .collect::<Vec<_>>()
+            .get(..3)
+            .map(|p| p.to_vec())
+        else {
+            break;
+        };
+        let (encoding, text) = (word[1], word[2]);
+        let end = start + 2 + word[0].len() + 1 + encoding.len() + 1 + text.len();
+        if !rest[end..].starts_with("?=") {
+            break;
+        }
+        let bytes = match encoding {
+            "Q" | "q" => decode_q(text),
+            "B" | "b" => decode_base64(text),
+            _ => break,
+        };
+        // whitespace between adjacent encoded words is dropped
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            out.push_str(between);
+        }
+        out.push_str(&String::from_utf8_lossy(&bytes));
+        rest = &rest[end + 2..];
+        after_word = true;
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_q(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'_' => out.push(b' '),
+            b'=' if i + 3 <= bytes.len() => {
+                let digits = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(digits, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    Err(_) => out.push(b'='),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn decode_base64(text: &str) -> Vec<u8> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0);
+    for v in text.bytes().filter_map(value) {
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    out
+}
+
+/// Strip `Re:` and bracketed (`[PATCH v2 1/3]`) prefixes from a subject.
+fn clean_subject(subject: &str) -> String {
+    let mut s = subject.split_whitespace().collect::<Vec<_>>().join(" ");
+    loop {
+        let trimmed = s.trim_start();
+        if let Some(rest) = trimmed
+            .strip_prefix("Re:")
+            .or(trimmed.strip_prefix("re:"))
+            .or(trimmed.strip_prefix("RE:"))
+        {
+            s = rest.to_string();
+        } else if trimmed.starts_with('[') {
+            match trimmed.find(']') {
+                Some(end) => s = trimmed[end + 1..].to_string(),
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    s.trim().to_string()
+}
+
+/// Turn a `From` header into `Name <email>`. Handles `Name <email>`, `email (Name)` and a bare
+/// address (whose local part becomes the name).
+fn parse_author(from: &str) -> Result<String> {
+    let from = from.trim();
+    if let (Some(lt), Some(gt)) = (from.find('<'), from.rfind('>')) {
+        let name = from[..lt].trim().trim_matches('"').trim();
+        let email = from[lt + 1..gt].trim();
+        let name = if name.is_empty() {
+            email.split('@').next().unwrap_or(email)
+        } else {
+            name
+        };
+        return Ok(format!("{name} <{email}>"));
+    }
+    if let (Some(open), Some(close)) = (from.find('('), from.rfind(')')) {
+        let email = from[..open].trim();
+        let name = from[open + 1..close].trim();
+        return Ok(format!("{name} <{email}>"));
+    }
+    if from.contains('@') {
+        let name = from.split('@').next().unwrap_or(from);
+        return Ok(format!("{name} <{from}>"));
+    }
+    bail!("cannot parse author from {from:?}");
+}
+
+/// Whether `line` is a scissors line (`-- >8 --`): everything above it is dropped from the
+/// message.
+fn is_scissors(line: &str) -> bool {
+    let line = line.trim();
+    (line.contains(">8") || line.contains("8<"))
+        && line.len() >= 8
+        && line
+            .chars()
+            .filter(|c| !matches!(c, '-' | '>' | '<' | '8' | ' '))
+            .all(char::is_alphabetic)
+        && line.matches('-').count() >= 4
+}
+
+/// Whether `line` starts the patch part of a mail body: the `---` line before the diffstat, or
+/// the diff itself.
+fn is_patch_start(line: &str) -> bool {
+    let line = line.trim_end_matches(['\n', '\r']);
+    if let Some(rest) = line.strip_prefix("---") {
+        if rest.starts_with(' ') && !rest[1..].starts_with(char::is_whitespace) {
+            return true;
+        }
+        if rest.trim().is_empty() {
+            return true;
+        }
+    }
+    line.starts_with("diff -") || line.starts_with("Index: ")
+}
+
+fn parse_mail(raw: &[u8], scissors: bool) -> Result<Mail> {
+    let text = String::from_utf8_lossy(raw);
+    let mut lines = text.split_inclusive('\n').peekable();
+    if lines.peek().is_some_and(|l| is_from_line(l.as_bytes())) {
+        lines.next();
+    }
+
+    // headers, with folded continuation lines joined
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in lines.by_ref() {
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| decode_header(v))
+    };
+    let mut from = header("from");
+    let mut subject = header("subject").unwrap_or_default();
+    let mut date = header("date");
+
+    let mut body: Vec<&str> = lines.collect();
+    if scissors {
+        if let Some(at) = body.iter().position(|l| is_scissors(l)) {
+            body.drain(..=at);
+        }
+    }
+    // in-body headers override the mail's own
+    let mut at = 0;
+    while body.get(at).is_some_and(|l| l.trim().is_empty()) {
+        at += 1;
+    }
+    let mut in_body = false;
+    while let Some(line) = body.get(at) {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let Some((key, value)) = line.split_once(':') else {
+            break;
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "from" => from = Some(decode_header(value.trim())),
+            "subject" => subject = decode_header(value.trim()),
+            "date" => date = Some(value.trim().to_string()),
+            _ => break,
+        }
+        in_body = true;
+        at += 1;
+    }
+    if in_body {
+        body.drain(..at);
+    }
+
+    let patch_at = body
+        .iter()
+        .position(|l| is_patch_start(l))
+        .unwrap_or(body.len());
+    let patch = body[patch_at..].concat().into_bytes();
+    let message_body = body[..patch_at].concat();
+    let message_body = message_body.trim();
+
+    let subject = clean_subject(&subject);
+    let message = if message_body.is_empty() {
+        format!("{subject}\n")
+    } else {
+        format!("{subject}\n\n{message_body}\n")
+    };
+    Ok(Mail {
+        author: parse_author(&from.context("mail has no From header")?)?,
+        date: date.as_deref().and_then(parse_rfc2822),
+        subject,
+        message,
+        patch,
+    })
+}
+
+/// Commit the index as the next commit on HEAD, with the author and message from `mail`.
+fn commit_mail(git_repo: &GitRepository, index: &Index, mail: &Mail) -> Result<()> {
+    let tree = write_index_tree(git_repo, index)?;
+    let parents = ref_resolve(git_repo, "HEAD")?
+        .into_iter()
+        .collect::<Vec<_>>();
+    let committer = identity(git_repo, "committer")?;
+    // without a usable Date header, the author date is the commit time
+    let date = match &mail.date {
+        Some(date) => date.clone(),
+        None => format!("{} {}", committer.time, committer.tz()),
+    };
+    let author = format!("{} {date}", mail.author);
+    let commit = write_commit_object(
+        git_repo,
+        &tree,
+        &parents,
+        &author,
+        &committer.to_string(),
+        &mail.message,
+    )?;
+    let message = format!("am: {}", mail.subject);
+    ref_update(
+        git_repo,
+        "HEAD",
+        &commit,
+        parents.first().map(String::as_str),
+        &message,
+    )
+}
+
+/// Put the paths touched by `patches` back the way the index has them, undoing a failed or
+/// conflicted apply in the work tree. Conflicted paths go back to our version.
+fn restore_paths(git_repo: &GitRepository, index: &mut Index, patches: &[FilePatch]) -> Result<()> {
+    for patch in patches {
+        for path in [&patch.old_path, &patch.new_path].into_iter().flatten() {
+            let full = git_repo.work_tree().join(path);
+            let ours = index
+                .get(path)
+                .or_else(|| index.get_stage(path, 2))
+                .cloned();
+            match ours {
+                Some(mut entry) => {
+                    let data = object_read(git_repo, &entry.hash_hex())?.serialize();
+                    fs::write(&full, data).with_context(|| format!("write {}", full.display()))?;
+                    if entry.stage() != 0 {
+                        entry.set_stage(0);
+                        index.add(entry);
+                    }
+                }
+                None => {
+                    if full.exists() {
+                        fs::remove_file(&full)
+                            .with_context(|| format!("remove {}", full.display()))?;
+                    }
+                    index.remove(path);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stage the work tree versions of the paths touched by `patches`, for `--continue`.
+fn stage_paths(git_repo: &GitRepository, index: &mut Index, patches: &[FilePatch]) -> Result<()> {
+    for patch in patches {
+        for path in [&patch.old_path, &patch.new_path].into_iter().flatten() {
+            let full = git_repo.work_tree().join(path);
+            let old_mode = index.get(path).map(|e| e.mode);
+            index.entries.retain(|e| &e.path != path);
+            let Ok(meta) = fs::symlink_metadata(&full) else {
+                continue;
+            };
+            let data = fs::read(&full).with_context(|| format!("read {}", full.display()))?;
+            if data.windows(8).any(|w| w == b"<<<<<<< ") {
+                bail!("{path} still contains conflict markers");
+            }
+            let hash = ObjectId::from_hex(&write_object(git_repo, Kind::Blob, &data)?)?;
+            let mode = old_mode.unwrap_or(0o100644);
+            index
+                .entries
+                .push(IndexEntry::from_metadata(path, &meta, mode, hash));
+        }
+    }
+    index.sort();
+    Ok(())
+}
+
+/// Refuse to start when the index has changes that aren't committed.
+fn check_clean_index(git_repo: &GitRepository, index: &Index) -> Result<()> {
+    let tree = write_index_tree(git_repo, index)?;
+    let head_tree = match ref_resolve(git_repo, "HEAD")? {
+        Some(head) => read_commit(git_repo, &head)?.tree,
+        None => write_object(git_repo, Kind::Tree, b"")?,
+    };
+    if tree != head_tree {
+        bail!("Dirty index: cannot apply patches");
+    }
+    Ok(())
+}
+
+/// Apply the mails of `session` from `next` on, committing each one.
+fn run(git_repo: &GitRepository, session: &mut Session) -> Result<()> {
+    while session.next <= session.last {
+        let mail = session.current()?;
+        println!("Applying: {}", mail.subject);
+        let patches = parse_patch(&mail.patch)?;
+        if patches.is_empty() {
+            bail!(
+                "Patch is empty.\nWhen you have resolved this problem, run \"git-rs am --continue\".\nIf you prefer to skip this patch, run \"git-rs am --skip\" instead."
+            );
+        }
+        let (mut index, lock) = Index::lock(git_repo)?;
+        let result = apply_patches(git_repo, &mut index, &patches, session.three_way);
+        let failure = match result {
+            Ok(applied) if applied.conflicts.is_empty() => {
+                lock.commit(&index)?;
+                None
+            }
+            Ok(applied) => {
+                for path in &applied.conflicts {
+                    println!("CONFLICT (content): Merge conflict in {path}");
+                }
+                // the conflicts stay in the index until they are resolved
+                lock.commit(&index)?;
+                Some("Failed to merge in the changes.".to_string())
+            }
+            Err(e) => Some(format!("{e:#}")),
+        };
+        if let Some(failure) = failure {
+            bail!(
+                "{failure}\nPatch failed at {:04} {}\nWhen you have resolved this problem, run \"git-rs am --continue\".\nIf you prefer to skip this patch, run \"git-rs am --skip\" instead.\nTo restore the original branch and stop patching, run \"git-rs am --abort\".",
+                session.next,
+                mail.subject
+            );
+        }
+        commit_mail(git_repo, &index, &mail)?;
+        session.next += 1;
+        session.save()?;
+    }
+    fs::remove_dir_all(&session.dir).context("remove rebase-apply")?;
+    Ok(())
+}
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    mbox: Option<PathBuf>,
+    three_way: bool,
+    scissors: bool,
+    resume: Option<Resume>,
+) -> Result<()> {
+    let Some(resume) = resume else {
+        if Session::path(repo)?.exists() {
+            bail!(
+                "previous rebase directory {} still exists; use --continue, --skip or --abort",
+                Session::path(repo)?.display()
+            );
+        }
+        let mbox = mbox.context("no mbox given")?;
+        let mails = read_mails(&mbox)?;
+        if mails.is_empty() {
+            bail!("no patches found in {}", mbox.display());
+        }
+        check_clean_index(repo, &Index::read(repo)?)?;
+        let mut session = Session::create(repo, &mails, three_way, scissors)?;
+        return run(repo, &mut session);
+    };
+
+    let mut session = Session::load(repo)?;
+    let mail = session.current()?;
+    let patches = parse_patch(&mail.patch)?;
+    let (mut index, lock) = Index::lock(repo)?;
+    match resume {
+        Resume::Continue => {
+            stage_paths(repo, &mut index, &patches)?;
+            let head_tree = match ref_resolve(repo, "HEAD")? {
+                Some(head) => Some(read_commit(repo, &head)?.tree),
+                None => None,
+            };
+            if head_tree.as_deref() == Some(write_index_tree(repo, &index)?.as_str()) {
+                bail!("No changes - did you forget to resolve the conflicts?");
+            }
+            lock.commit(&index)?;
+            println!("Applying: {}", mail.subject);
+            commit_mail(repo, &index, &mail)?;
+        }
+        Resume::Skip | Resume::Abort => {
+            restore_paths(repo, &mut index, &patches)?;
+            lock.commit(&index)?;
+        }
+    }
+    if resume == Resume::Abort {
+        match session.orig_head()? {
+            Some(orig) => {
+                checkout_tree(repo, &read_commit(repo, &orig)?.tree, true)?;
+                ref_update(repo, "HEAD", &orig, None, "am --abort")?;
+            }
+            None => {
+                let empty = write_object(repo, Kind::Tree, b"")?;
+                checkout_tree(repo, &empty, true)?;
+            }
+        }
+        return fs::remove_dir_all(&session.dir).context("remove rebase-apply");
+    }
+    session.next += 1;
+    session.save()?;
+    run(repo, &mut session)
+}
@@ -0,0 +1,221 @@
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    apply::{apply_binary, apply_hunks_partial, parse_patch, set_executable, FilePatch},
+    index::Index,
+    objects::Mode,
+    repository::GitRepository,
+    ExitStatus,
+};
+
+/// How one file of a patch turned out.
+struct Outcome<'a> {
+    patch: &'a FilePatch,
+    /// The patched content, `None` to delete the file.
+    content: Option<Vec<u8>>,
+    /// The hunks that don't apply, by their index in the patch.
+    rejected: Vec<usize>,
+}
+
+/// Read the patch at `path`, or standard input for `None` or `-`.
+fn read_patch(path: Option<&Path>) -> Result<Vec<u8>> {
+    match path.filter(|path| *path != Path::new("-")) {
+        Some(path) => {
+            fs::read(path).with_context(|| format!("can't open patch '{}'", path.display()))
+        }
+        None => {
+            let mut data = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("read patch from standard input")?;
+            Ok(data)
+        }
+    }
+}
+
+/// Apply `patch` to the work tree file it changes, with hunks that need at least
+/// `min_context` lines of context to match.
+fn apply_file<'a>(
+    git_repo: &GitRepository,
+    patch: &'a FilePatch,
+    min_context: usize,
+) -> Result<Outcome<'a>> {
+    if patch.binary && patch.binary_hunks.is_none() {
+        bail!(
+            "cannot apply binary patch to '{}' without full index line",
+            patch.path()
+        );
+    }
+    let current = match &patch.old_path {
+        Some(path) => {
+            let full = git_repo.work_tree().join(path);
+            if !full.exists() {
+                bail!("{path}: No such file or directory");
+            }
+            fs::read(&full).with_context(|| format!("read {}", full.display()))?
+        }
+        None => {
+            let path = patch.path();
+            if git_repo.work_tree().join(path).exists() {
+                bail!("{path}: already exists in working directory");
+            }
+            Vec::new()
+        }
+    };
+    let (content, rejected) = match &patch.binary_hunks {
+        Some(hunks) => (
+            apply_binary(git_repo.hash_algo(), patch, &current, hunks)?,
+            Vec::new(),
+        ),
+        None => apply_hunks_partial(&current, &patch.hunks, min_context),
+    };
+    if patch.new_path.is_none() && rejected.is_empty() && !content.is_empty() {
+        bail!("{}: removal patch leaves file contents", patch.path());
+    }
+    Ok(Outcome {
+        patch,
+        content: patch.new_path.as_ref().map(|_| content),
+        rejected,
+    })
+}
+
+/// Write the rejected hunks of `outcome` next to its file, as `<path>.rej`.
+fn write_rejects(git_repo: &GitRepository, outcome: &Outcome) -> Result<()> {
+    let path = outcome.patch.path();
+    let mut data = format!("diff a/{path} b/{path}\t(rejected hunks)\n").into_bytes();
+    for &n in &outcome.rejected {
+        data.extend(outcome.patch.hunks[n].to_bytes());
+    }
+    let full = git_repo.work_tree().join(format!("{path}.rej"));
+    fs::write(&full, data).with_context(|| format!("write {}", full.display()))
+}
+
+/// Write the patched file of `outcome` to the work tree, or delete it, taking care of renames
+/// and mode changes.
+fn write_file(git_repo: &GitRepository, index: &Index, outcome: Outcome) -> Result<()> {
+    let patch = outcome.patch;
+    if let Some(old) = &patch.old_path {
+        if patch.new_path.as_ref() != Some(old) {
+            let full = git_repo.work_tree().join(old);
+            fs::remove_file(&full).with_context(|| format!("remove {}", full.display()))?;
+        }
+    }
+    let (Some(new), Some(content)) = (&patch.new_path, outcome.content) else {
+        return Ok(());
+    };
+    let full = git_repo.work_tree().join(new);
+    if let Some(parent) = full.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    fs::write(&full, content).with_context(|| format!("write {}", full.display()))?;
+    let mode = patch.new_mode.or_else(|| {
+        index
+            .get(patch.old_path.as_deref().unwrap_or(new))
+            .map(|e| Mode::from_bits(e.mode))
+    });
+    if let Some(mode) = mode {
+        set_executable(&full, mode == Mode::Executable)?;
+    }
+    Ok(())
+}
+
+/// Apply the unified diff at `patch_path` (standard input by default) to the work tree, leaving
+/// the index alone. With `reverse` the patch is undone instead. Hunks that don't match where
+/// they say they start are looked for elsewhere in the file; with `min_context`, their outer
+/// context may shrink down to that many lines to match.
+///
+/// Nothing is written if any file doesn't apply, unless `reject` is given: then the hunks that
+/// apply are, and the others are saved to `<path>.rej`. With `check` the patch is only checked.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    patch_path: Option<PathBuf>,
+    check: bool,
+    reverse: bool,
+    reject: bool,
+    min_context: Option<usize>,
+) -> Result<()> {
+    let mut patches = parse_patch(&read_patch(patch_path.as_deref())?)?;
+    if patches.is_empty() {
+        bail!("No valid patches in input");
+    }
+    if reverse {
+        patches = patches
+            .iter()
+            .map(FilePatch::reverse)
+            .collect::<Result<_>>()?;
+    }
+    let index = Index::read(repo)?;
+    let min_context = min_context.unwrap_or(usize::MAX);
+
+    let mut outcomes = Vec::new();
+    let mut failed = false;
+    for patch in &patches {
+        if reject {
+            eprintln!("Checking patch {}...", patch.path());
+        }
+        match apply_file(repo, patch, min_context) {
+            Ok(outcome) => {
+                for &n in &outcome.rejected {
+                    eprintln!(
+                        "error: patch failed: {}:{}",
+                        patch.path(),
+                        patch.hunks[n].old_start
+                    );
+                }
+                if !outcome.rejected.is_empty() {
+                    failed = true;
+                    if !reject {
+                        eprintln!("error: {}: patch does not apply", patch.path());
+                        continue;
+                    }
+                }
+                outcomes.push(outcome);
+            }
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                failed = true;
+            }
+        }
+    }
+    if check || (failed && !reject) {
+        return match failed {
+            true => Err(ExitStatus(1).into()),
+            false => Ok(()),
+        };
+    }
+
+    for outcome in outcomes {
+        let path = outcome.patch.path().to_string();
+        if reject {
+            match outcome.rejected.len() {
+                0 => eprintln!("Applied patch {path} cleanly."),
+                1 => eprintln!("Applying patch {path} with 1 reject..."),
+                n => eprintln!("Applying patch {path} with {n} rejects..."),
+            }
+            for n in (0..outcome.patch.hunks.len()).filter(|_| !outcome.rejected.is_empty()) {
+                match outcome.rejected.contains(&n) {
+                    true => eprintln!("Rejected hunk #{}.", n + 1),
+                    false => eprintln!("Hunk #{} applied cleanly.", n + 1),
+                }
+            }
+        }
+        if !outcome.rejected.is_empty() {
+            write_rejects(repo, &outcome)?;
+            // a file whose removal is only partly applied stays as it was
+            if outcome.patch.new_path.is_none() {
+                continue;
+            }
+        }
+        write_file(repo, &index, outcome)?;
+    }
+    match failed {
+        true => Err(ExitStatus(1).into()),
+        false => Ok(()),
+    }
+}
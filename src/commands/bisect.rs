@@ -0,0 +1,446 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Write,
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{checkout::checkout_tree, log::write_medium},
+    diff::{diff_trees, write_stat, BlobCache, DiffOptions},
+    objects::{object_find, read_commit, subject, ObjectType},
+    refs::{ref_delete, ref_list, ref_resolve, ref_update, resolve_head, write_head, Head},
+    repository::{repo_path, GitRepository},
+    revwalk::RevWalk,
+};
+
+const BAD_REF: &str = "refs/bisect/bad";
+const GOOD_PREFIX: &str = "refs/bisect/good-";
+const SKIP_PREFIX: &str = "refs/bisect/skip-";
+
+/// Exit status of a `bisect run` command that can't test the commit it is given.
+const RUN_SKIP: i32 = 125;
+
+/// How a commit was found to behave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Good,
+    Bad,
+    Skip,
+}
+
+/// Where the bisection stands after a verdict.
+enum Outcome {
+    /// More commits need testing; the next one is checked out.
+    Continue,
+    /// The first bad commit was found.
+    Found,
+    /// Only skipped commits are left, so the first bad commit can't be told.
+    OnlySkipped,
+}
+
+/// The commits a bisection is between: the bad one, the good ones and those skipped.
+struct State {
+    bad: Option<String>,
+    good: Vec<String>,
+    skipped: HashSet<String>,
+}
+
+impl State {
+    fn load(git_repo: &GitRepository) -> Result<Self> {
+        let mut state = Self {
+            bad: ref_resolve(git_repo, BAD_REF)?,
+            good: Vec::new(),
+            skipped: HashSet::new(),
+        };
+        for (name, hash) in ref_list(git_repo)? {
+            if name.starts_with(GOOD_PREFIX) {
+                state.good.push(hash);
+            } else if name.starts_with(SKIP_PREFIX) {
+                state.skipped.insert(hash);
+            }
+        }
+        Ok(state)
+    }
+}
+
+/// Whether a bisection is in progress, which `BISECT_START` (holding where HEAD was) records.
+fn in_progress(git_repo: &GitRepository) -> Result<bool> {
+    Ok(repo_path(git_repo, &["BISECT_START"])?.is_file())
+}
+
+fn require_in_progress(git_repo: &GitRepository) -> Result<()> {
+    if !in_progress(git_repo)? {
+        bail!("You need to start by \"git-rs bisect start\"");
+    }
+    Ok(())
+}
+
+/// Record `verdict` for `commit`.
+fn mark(git_repo: &GitRepository, commit: &str, verdict: Verdict) -> Result<()> {
+    match verdict {
+        Verdict::Bad => ref_update(git_repo, BAD_REF, commit, None, ""),
+        Verdict::Good => ref_update(
+            git_repo,
+            &format!("{GOOD_PREFIX}{commit}"),
+            commit,
+            None,
+            "",
+        ),
+        Verdict::Skip => ref_update(
+            git_repo,
+            &format!("{SKIP_PREFIX}{commit}"),
+            commit,
+            None,
+            "",
+        ),
+    }
+}
+
+/// The commits that may still be the first bad one: reachable from `bad` but from no good
+/// commit, newest first.
+fn candidates(git_repo: &GitRepository, bad: &str, good: &[String]) -> Result<Vec<String>> {
+    let mut excluded = HashSet::new();
+    let mut pending = good.to_vec();
+    while let Some(commit) = pending.pop() {
+        if excluded.insert(commit.clone()) {
+            pending.extend(read_commit(git_repo, &commit)?.parents);
+        }
+    }
+
+    let mut walk = RevWalk::new(git_repo);
+    walk.push(bad)?;
+    let mut result = Vec::new();
+    for entry in walk {
+        let (hash, _) = entry?;
+        if !excluded.contains(&hash) {
+            result.push(hash);
+        }
+    }
+    Ok(result)
+}
+
+/// The candidate to test next and how many candidates it reaches (itself included), chosen the
+/// way git does so that both test the same commits.
+///
+/// Going from the oldest candidate to the newest, merges and then commits with one candidate
+/// parent are taken as soon as one halves the candidates to within one. Failing that (and
+/// always when some commits are skipped), the oldest commit whose reach is closest to half of
+/// the candidates is taken.
+fn midpoint<'c>(
+    git_repo: &GitRepository,
+    candidates: &'c [String],
+    skipped: &HashSet<String>,
+) -> Result<(&'c String, usize)> {
+    let index = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, hash)| (hash.as_str(), i))
+        .collect::<HashMap<_, _>>();
+    let mut parents = Vec::with_capacity(candidates.len());
+    for hash in candidates {
+        parents.push(
+            read_commit(git_repo, hash)?
+                .parents
+                .iter()
+                .filter_map(|parent| index.get(parent.as_str()).copied())
+                .collect::<Vec<_>>(),
+        );
+    }
+    let mut counts: Vec<usize> = Vec::with_capacity(candidates.len());
+    for start in 0..candidates.len() {
+        let mut seen = vec![false; candidates.len()];
+        let mut pending = vec![start];
+        let mut count = 0;
+        while let Some(i) = pending.pop() {
+            if !std::mem::replace(&mut seen[i], true) {
+                count += 1;
+                pending.extend(&parents[i]);
+            }
+        }
+        counts.push(count);
+    }
+
+    let all = candidates.len();
+    let oldest_first = (0..all)
+        .rev()
+        .filter(|&i| !skipped.contains(&candidates[i]))
+        .collect::<Vec<_>>();
+    if skipped.is_empty() {
+        let merges = oldest_first.iter().filter(|&&i| parents[i].len() > 1);
+        let singles = oldest_first.iter().filter(|&&i| parents[i].len() == 1);
+        if let Some(&i) = merges
+            .chain(singles)
+            .find(|&&i| (2 * counts[i]).abs_diff(all) <= 1)
+        {
+            return Ok((&candidates[i], counts[i]));
+        }
+    }
+    let distance = |i: usize| counts[i].min(all - counts[i]);
+    let best = oldest_first
+        .iter()
+        .copied()
+        .fold(None, |best: Option<usize>, i| match best {
+            Some(best) if distance(best) >= distance(i) => Some(best),
+            _ => Some(i),
+        })
+        .context("no commit left to test")?;
+    Ok((&candidates[best], counts[best]))
+}
+
+/// Roughly how many more steps bisecting `all` candidates takes, as git estimates it.
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = all.ilog2() as usize;
+    let e = 1 << n;
+    if e < 3 * (all - e) {
+        n
+    } else {
+        n - 1
+    }
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{n} {}", if n == 1 { one } else { many })
+}
+
+/// Print the first bad commit with its message and the files it changed.
+fn print_first_bad(git_repo: &GitRepository, bad: &str) -> Result<()> {
+    let commit = read_commit(git_repo, bad)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    writeln!(out, "{bad} is the first bad commit")?;
+    write_medium(git_repo, &mut out, bad, &commit)?;
+    writeln!(out)?;
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(read_commit(git_repo, parent)?.tree),
+        None => None,
+    };
+    let changes = diff_trees(git_repo, parent_tree.as_deref(), Some(&commit.tree))?;
+    write_stat(
+        &mut out,
+        &mut BlobCache::new(git_repo),
+        &changes,
+        &DiffOptions::default(),
+    )?;
+    Ok(())
+}
+
+/// Check out the commit that best halves the remaining candidates, or report the first bad
+/// commit once it is known.
+fn next(git_repo: &GitRepository) -> Result<Outcome> {
+    let state = State::load(git_repo)?;
+    let bad = match (&state.bad, state.good.len()) {
+        (None, 0) => {
+            println!("status: waiting for both good and bad commits");
+            return Ok(Outcome::Continue);
+        }
+        (None, good) => {
+            println!(
+                "status: waiting for bad commit, {} known",
+                plural(good, "good commit", "good commits")
+            );
+            return Ok(Outcome::Continue);
+        }
+        (Some(_), 0) => {
+            println!("status: waiting for good commit(s), bad commit known");
+            return Ok(Outcome::Continue);
+        }
+        (Some(bad), _) => bad.clone(),
+    };
+
+    let candidates = candidates(git_repo, &bad, &state.good)?;
+    if candidates.is_empty() {
+        bail!("The merge base {} is bad.\nThis means the bug has been fixed between the bad commit and a good one.", &bad);
+    }
+    let testable = candidates
+        .iter()
+        .filter(|c| **c != bad && !state.skipped.contains(*c))
+        .count();
+    if testable == 0 {
+        if candidates.len() == 1 {
+            print_first_bad(git_repo, &bad)?;
+            return Ok(Outcome::Found);
+        }
+        println!(
+            "There are only 'skip'ped commits left to test.\nThe first bad commit could be any of:"
+        );
+        for commit in &candidates {
+            println!("{commit}");
+        }
+        println!("We cannot bisect more!");
+        return Ok(Outcome::OnlySkipped);
+    }
+
+    let all = candidates.len();
+    let (best, reaches) = midpoint(git_repo, &candidates, &state.skipped)?;
+    let commit = read_commit(git_repo, best)?;
+    checkout_tree(git_repo, &commit.tree, false)?;
+    write_head(
+        git_repo,
+        &Head::Detached(best.clone()),
+        &format!(
+            "checkout: moving from {} to {best}",
+            resolve_head(git_repo)?.reflog_name()
+        ),
+    )?;
+    let left = all - reaches - 1;
+    println!(
+        "Bisecting: {} left to test after this (roughly {})",
+        plural(left, "revision", "revisions"),
+        plural(estimate_steps(all), "step", "steps")
+    );
+    println!("[{best}] {}", subject(commit.message.as_bytes()));
+    Ok(Outcome::Continue)
+}
+
+/// Start bisecting, remembering where HEAD is to go back to it on `reset`. A `bad` commit and
+/// `good` ones may be given right away.
+pub(crate) fn invoke_start(
+    repo: &GitRepository,
+    bad: Option<String>,
+    good: Vec<String>,
+) -> Result<()> {
+    if in_progress(repo)? {
+        reset(repo, false)?;
+    }
+    let start = match resolve_head(repo)? {
+        Head::Branch(branch, Some(_)) => branch
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&branch)
+            .to_string(),
+        Head::Detached(commit) => commit,
+        Head::Branch(_, None) => bail!("your current branch does not have any commits yet"),
+    };
+    let bad = bad
+        .map(|rev| object_find(repo, rev, ObjectType::Commit))
+        .transpose()?;
+    let good = good
+        .into_iter()
+        .map(|rev| object_find(repo, rev, ObjectType::Commit))
+        .collect::<Result<Vec<_>>>()?;
+
+    fs::write(repo_path(repo, &["BISECT_START"])?, format!("{start}\n"))?;
+    if let Some(bad) = &bad {
+        mark(repo, bad, Verdict::Bad)?;
+    }
+    for commit in &good {
+        mark(repo, commit, Verdict::Good)?;
+    }
+    next(repo)?;
+    Ok(())
+}
+
+/// Record `verdict` for `revs` (HEAD when empty), then move on to the next commit to test.
+fn invoke_mark(repo: &GitRepository, verdict: Verdict, revs: Vec<String>) -> Result<()> {
+    require_in_progress(repo)?;
+    let revs = match revs.is_empty() {
+        true => vec!["HEAD".to_string()],
+        false => revs,
+    };
+    if verdict == Verdict::Bad && revs.len() > 1 {
+        bail!("'git-rs bisect bad' can take only one argument.");
+    }
+    for rev in revs {
+        let commit = object_find(repo, rev, ObjectType::Commit)?;
+        mark(repo, &commit, verdict)?;
+    }
+    next(repo)?;
+    Ok(())
+}
+
+pub(crate) fn invoke_bad(repo: &GitRepository, rev: Option<String>) -> Result<()> {
+    invoke_mark(repo, Verdict::Bad, rev.into_iter().collect())
+}
+
+pub(crate) fn invoke_good(repo: &GitRepository, revs: Vec<String>) -> Result<()> {
+    invoke_mark(repo, Verdict::Good, revs)
+}
+
+pub(crate) fn invoke_skip(repo: &GitRepository, revs: Vec<String>) -> Result<()> {
+    invoke_mark(repo, Verdict::Skip, revs)
+}
+
+/// Drop the bisection state and, if `checkout`, go back to where HEAD was when it started.
+fn reset(git_repo: &GitRepository, checkout: bool) -> Result<()> {
+    let start_file = repo_path(git_repo, &["BISECT_START"])?;
+    let start = fs::read_to_string(&start_file).context("read BISECT_START")?;
+    let start = start.trim();
+    if checkout {
+        let branch = format!("refs/heads/{start}");
+        let head = match ref_resolve(git_repo, &branch)? {
+            Some(commit) => Head::Branch(branch, Some(commit)),
+            None => Head::Detached(object_find(
+                git_repo,
+                start.to_string(),
+                ObjectType::Commit,
+            )?),
+        };
+        let commit = head
+            .commit()
+            .expect("the start of a bisection has a commit");
+        checkout_tree(git_repo, &read_commit(git_repo, commit)?.tree, false)?;
+        write_head(git_repo, &head, "bisect reset")?;
+    }
+    for (name, _) in ref_list(git_repo)? {
+        if name.starts_with("refs/bisect/") {
+            ref_delete(git_repo, &name)?;
+        }
+    }
+    fs::remove_file(&start_file).context("remove BISECT_START")?;
+    Ok(())
+}
+
+/// End the bisection and check out the commit HEAD was at before it started.
+pub(crate) fn invoke_reset(repo: &GitRepository) -> Result<()> {
+    if !in_progress(repo)? {
+        println!("We are not bisecting.");
+        return Ok(());
+    }
+    reset(repo, true)
+}
+
+/// Bisect automatically: run `command` on each commit to test, taking exit status 0 as good,
+/// 125 as untestable and any other status up to 127 as bad. Stops when the first bad commit is
+/// found, or when the command exits with 128 or more or is killed. The command is run as given,
+/// its arguments passed on as they are rather than through a shell.
+pub(crate) fn invoke_run(repo: &GitRepository, command: Vec<String>) -> Result<()> {
+    require_in_progress(repo)?;
+    let state = State::load(repo)?;
+    if state.bad.is_none() || state.good.is_empty() {
+        bail!("bisect run failed: both a bad and a good commit are needed");
+    }
+
+    let (program, args) = command
+        .split_first()
+        .context("bisect run failed: no command given")?;
+    let command = command.join(" ");
+    loop {
+        println!("running '{command}'");
+        let status = Command::new(program)
+            .args(args)
+            .current_dir(repo.work_tree())
+            .status()
+            .with_context(|| format!("run '{command}'"))?;
+        let verdict = match status.code() {
+            Some(0) => Verdict::Good,
+            Some(RUN_SKIP) => Verdict::Skip,
+            Some(1..=127) => Verdict::Bad,
+            _ => bail!("bisect run failed: exit code {status} from '{command}' is < 0 or >= 128"),
+        };
+        let head = ref_resolve(repo, "HEAD")?.context("HEAD has no commit")?;
+        mark(repo, &head, verdict)?;
+        match next(repo)? {
+            Outcome::Continue => {}
+            Outcome::Found => {
+                println!("bisect found first bad commit");
+                return Ok(());
+            }
+            Outcome::OnlySkipped => bail!("bisect run cannot continue any more"),
+        }
+    }
+}
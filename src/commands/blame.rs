@@ -0,0 +1,169 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    io::Write,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    date::format_iso,
+    diff::{diff_lines, split_lines, Edit, Whitespace},
+    mailmap::Mailmap,
+    objects::{object_find, object_read, read_commit, tree_lookup, Commit, ObjectType},
+    repository::{worktree_path, GitRepository},
+    signature::Signature,
+};
+
+/// Lines of the blamed file still looking for the commit that introduced them, as
+/// `(line in the final file, line in this commit's version)`.
+type Pending = Vec<(usize, usize)>;
+
+/// The blob of `path` in `commit`, if the commit has that file.
+fn blob_at(git_repo: &GitRepository, commit: &Commit, path: &str) -> Result<Option<String>> {
+    Ok(tree_lookup(git_repo, &commit.tree, path)?
+        .filter(|entry| !entry.is_tree())
+        .map(|entry| entry.hash))
+}
+
+/// Find, for each line of `path` as of `start`, the commit that last changed it.
+///
+/// Commits are visited newest first. Each passes the lines it shares with a parent on to that
+/// parent (trying parents in order) and keeps the rest, which it introduced.
+fn blame(git_repo: &GitRepository, start: &str, path: &str) -> Result<(Vec<u8>, Vec<String>)> {
+    let commit = read_commit(git_repo, start)?;
+    let blob = blob_at(git_repo, &commit, path)?
+        .with_context(|| format!("no such path '{path}' in {start}"))?;
+    let content = object_read(git_repo, &blob)?.serialize();
+    let line_count = split_lines(&content).len();
+
+    let mut owners = vec![String::new(); line_count];
+    let mut pending: HashMap<String, (Commit, String, Pending)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    queue.push((commit.commit_time(), Reverse(0), start.to_string()));
+    pending.insert(
+        start.to_string(),
+        (commit, blob, (0..line_count).map(|i| (i, i)).collect()),
+    );
+    let mut found = 1;
+
+    while let Some((_, _, hash)) = queue.pop() {
+        let (commit, blob, mut lines) = pending.remove(&hash).expect("queued commits are pending");
+        let data = object_read(git_repo, &blob)?.serialize();
+        let ours = split_lines(&data);
+        for parent_hash in &commit.parents {
+            if lines.is_empty() {
+                break;
+            }
+            let parent = read_commit(git_repo, parent_hash)?;
+            let Some(parent_blob) = blob_at(git_repo, &parent, path)? else {
+                continue;
+            };
+            let passed = if parent_blob == blob {
+                std::mem::take(&mut lines)
+            } else {
+                let parent_data = object_read(git_repo, &parent_blob)?.serialize();
+                let theirs = split_lines(&parent_data);
+                // where each of our lines is in the parent, for lines the parent has too
+                let mut in_parent = vec![None; ours.len()];
+                for edit in diff_lines(&theirs, &ours, Whitespace::Exact) {
+                    if let Edit::Equal(old, new) = edit {
+                        in_parent[new] = Some(old);
+                    }
+                }
+                let (passed, kept): (Pending, Pending) = lines
+                    .iter()
+                    .partition(|(_, ours)| in_parent[*ours].is_some());
+                lines = kept;
+                passed
+                    .into_iter()
+                    .map(|(line, ours)| (line, in_parent[ours].unwrap()))
+                    .collect()
+            };
+            if passed.is_empty() {
+                continue;
+            }
+            match pending.get_mut(parent_hash) {
+                Some((_, _, parent_lines)) => parent_lines.extend(passed),
+                None => {
+                    queue.push((parent.commit_time(), Reverse(found), parent_hash.clone()));
+                    found += 1;
+                    pending.insert(parent_hash.clone(), (parent, parent_blob, passed));
+                }
+            }
+        }
+        for (line, _) in lines {
+            owners[line] = hash.clone();
+        }
+    }
+    Ok((content, owners))
+}
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    path: String,
+    rev: Option<String>,
+    use_mailmap: bool,
+) -> Result<()> {
+    let start = object_find(
+        repo,
+        rev.unwrap_or_else(|| "HEAD".to_string()),
+        ObjectType::Commit,
+    )?;
+    let path = worktree_path(repo, &path)?;
+    if path.is_empty() {
+        bail!("no path given to blame");
+    }
+    let mailmap = if use_mailmap {
+        Mailmap::load(repo)?
+    } else {
+        Mailmap::default()
+    };
+
+    let (content, owners) = blame(repo, &start, &path)?;
+
+    // per commit: (abbreviated hash, author, date)
+    let mut info: HashMap<&str, (String, String, String)> = HashMap::new();
+    for owner in &owners {
+        if info.contains_key(owner.as_str()) {
+            continue;
+        }
+        let commit = read_commit(repo, owner)?;
+        let (name, date) = match Signature::parse(&commit.author) {
+            Some(author) => (
+                mailmap.map_identity(&author.name, &author.email).0,
+                format_iso(author.time, &author.tz()),
+            ),
+            None => (String::new(), String::new()),
+        };
+        // root commits are boundaries, marked with `^`
+        let abbrev = if commit.parents.is_empty() {
+            format!("^{}", &owner[..7])
+        } else {
+            owner[..8].to_string()
+        };
+        info.insert(owner, (abbrev, name, date));
+    }
+
+    let name_width = info
+        .values()
+        .map(|(_, name, _)| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = owners.len().to_string().len();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (n, (line, owner)) in split_lines(&content).iter().zip(&owners).enumerate() {
+        let (abbrev, name, date) = &info[owner.as_str()];
+        write!(
+            stdout,
+            "{abbrev} ({name:<name_width$} {date} {:>number_width$}) ",
+            n + 1
+        )?;
+        stdout.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            writeln!(stdout)?;
+        }
+    }
+    Ok(())
+}
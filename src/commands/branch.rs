@@ -0,0 +1,316 @@
+use std::{collections::HashSet, io::Write};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::{for_each_ref::RefInfo, remote::map_refspec},
+    objects::{is_ancestor, object_find, read_commit, ObjectType},
+    refs::{ref_list, ref_resolve, resolve_head, valid_branch_name, Head, RefTransaction},
+    repository::{ConfigScope, GitRepository},
+};
+
+/// Which branches to list, by how their tips relate to other commits.
+#[derive(Debug)]
+pub(crate) struct BranchFilter {
+    /// Only branches whose tip contains (has as an ancestor) this commit.
+    pub(crate) contains: Option<String>,
+    /// Only branches whose tip is reachable from this commit.
+    pub(crate) merged: Option<String>,
+    /// Only branches whose tip is not reachable from this commit.
+    pub(crate) no_merged: Option<String>,
+}
+
+impl BranchFilter {
+    /// The same filter with each revision resolved to a commit hash.
+    fn resolve(&self, git_repo: &GitRepository) -> Result<Self> {
+        let resolve = |rev: &Option<String>| {
+            rev.as_ref()
+                .map(|rev| object_find(git_repo, rev.clone(), ObjectType::Commit))
+                .transpose()
+        };
+        Ok(Self {
+            contains: resolve(&self.contains)?,
+            merged: resolve(&self.merged)?,
+            no_merged: resolve(&self.no_merged)?,
+        })
+    }
+
+    /// Whether a branch at `tip` passes the (resolved) filter.
+    fn matches(&self, git_repo: &GitRepository, tip: &str) -> Result<bool> {
+        if let Some(commit) = &self.contains {
+            if !is_ancestor(git_repo, commit, tip)? {
+                return Ok(false);
+            }
+        }
+        if let Some(commit) = &self.merged {
+            if !is_ancestor(git_repo, tip, commit)? {
+                return Ok(false);
+            }
+        }
+        if let Some(commit) = &self.no_merged {
+            if is_ancestor(git_repo, tip, commit)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// The ref that tracks the upstream of `branch` (a full `refs/heads/` name), as configured by
+/// `branch.<name>.remote` and `branch.<name>.merge`: the remote's fetch refspec maps the merge
+/// ref onto a remote-tracking ref, while the remote `.` means a local branch.
+pub(crate) fn upstream(git_repo: &GitRepository, branch: &str) -> Option<String> {
+    let name = branch.strip_prefix("refs/heads/")?;
+    let section = format!("branch \"{name}\"");
+    let remote = git_repo.config_get(&section, "remote")?;
+    let merge = git_repo.config_get(&section, "merge")?;
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    let (src, dst) = fetch_refspec(git_repo, remote)?;
+    map_refspec(&src, &dst, merge)
+}
+
+/// The source and destination of the fetch refspec of `remote`, if it is a configured remote.
+fn fetch_refspec(git_repo: &GitRepository, remote: &str) -> Option<(String, String)> {
+    let section = format!("remote \"{remote}\"");
+    git_repo.config_get(&section, "url")?;
+    let default = format!("+refs/heads/*:refs/remotes/{remote}/*");
+    let refspec = git_repo.config_get(&section, "fetch").unwrap_or(&default);
+    let (src, dst) = refspec.trim_start_matches('+').split_once(':')?;
+    Some((src.to_string(), dst.to_string()))
+}
+
+/// The name git shows for a ref: `main` for a branch, `v1.0` for a tag, `origin/main` for a
+/// remote-tracking ref.
+pub(crate) fn short_ref_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/tags/"))
+        .or_else(|| name.strip_prefix("refs/remotes/"))
+        .unwrap_or(name)
+}
+
+fn ancestors(git_repo: &GitRepository, tip: &str) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![tip.to_string()];
+    while let Some(commit) = pending.pop() {
+        if seen.insert(commit.clone()) {
+            pending.extend(read_commit(git_repo, &commit)?.parents);
+        }
+    }
+    Ok(seen)
+}
+
+/// How many commits `ours` has that `theirs` doesn't, and how many `theirs` has that `ours`
+/// doesn't.
+pub(crate) fn ahead_behind(
+    git_repo: &GitRepository,
+    ours: &str,
+    theirs: &str,
+) -> Result<(usize, usize)> {
+    let ours = ancestors(git_repo, ours)?;
+    let theirs = ancestors(git_repo, theirs)?;
+    Ok((
+        ours.difference(&theirs).count(),
+        theirs.difference(&ours).count(),
+    ))
+}
+
+/// The `[...]` tracking annotation of `branch -v` (just the counts, and only when they aren't
+/// zero) or `branch -vv` (with the upstream's name).
+fn tracking_info(git_repo: &GitRepository, branch: &str, tip: &str, verbose: u8) -> Result<String> {
+    let Some(tracking) = upstream(git_repo, branch) else {
+        return Ok(String::new());
+    };
+    let mut counts = Vec::new();
+    let gone = match ref_resolve(git_repo, &tracking)? {
+        Some(theirs) => {
+            let (ahead, behind) = ahead_behind(git_repo, tip, &theirs)?;
+            if ahead > 0 {
+                counts.push(format!("ahead {ahead}"));
+            }
+            if behind > 0 {
+                counts.push(format!("behind {behind}"));
+            }
+            false
+        }
+        None => true,
+    };
+    let name = short_ref_name(&tracking);
+    Ok(match (verbose, gone, counts.is_empty()) {
+        (1, true, _) => "[gone] ".to_string(),
+        (1, false, true) => String::new(),
+        (1, false, false) => format!("[{}] ", counts.join(", ")),
+        (_, true, _) => format!("[{name}: gone] "),
+        (_, false, true) => format!("[{name}] "),
+        (_, false, false) => format!("[{name}: {}] ", counts.join(", ")),
+    })
+}
+
+/// List the local branches passing `filter`, marking the current one with `*`. With `verbose`,
+/// each also shows its tip and subject, and its upstream tracking state.
+fn list(git_repo: &GitRepository, filter: &BranchFilter, verbose: u8) -> Result<()> {
+    let filter = filter.resolve(git_repo)?;
+
+    let head = resolve_head(git_repo)?;
+
+    // (marker, label, full ref name, tip)
+    let mut rows = Vec::new();
+    if let Head::Detached(commit) = &head {
+        if filter.matches(git_repo, commit)? {
+            let label = format!("(HEAD detached at {})", &commit[..7]);
+            rows.push(('*', label, String::new(), commit.clone()));
+        }
+    }
+    for (name, tip) in ref_list(git_repo)? {
+        let Some(branch) = name.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        if !filter.matches(git_repo, &tip)? {
+            continue;
+        }
+        let marker = if matches!(&head, Head::Branch(current, _) if *current == name) {
+            '*'
+        } else {
+            ' '
+        };
+        rows.push((marker, branch.to_string(), name.clone(), tip));
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let width = rows
+        .iter()
+        .map(|(_, label, ..)| label.len())
+        .max()
+        .unwrap_or(0);
+    for (marker, label, name, tip) in rows {
+        if verbose == 0 {
+            writeln!(stdout, "{marker} {label}")?;
+            continue;
+        }
+        let tracking = tracking_info(git_repo, &name, &tip, verbose)?;
+        let info = RefInfo::read(git_repo, name, tip)?;
+        writeln!(
+            stdout,
+            "{marker} {label:<width$} {} {tracking}{}",
+            info.value(git_repo, "objectname:short")?,
+            info.value(git_repo, "subject")?
+        )?;
+    }
+    Ok(())
+}
+
+/// Check that `name` can name a new branch, being well formed and not taken, returning its
+/// full ref name.
+pub(crate) fn check_new_branch(git_repo: &GitRepository, name: &str) -> Result<String> {
+    if !valid_branch_name(name) {
+        bail!("'{name}' is not a valid branch name");
+    }
+    let refname = format!("refs/heads/{name}");
+    if ref_resolve(git_repo, &refname)?.is_some() {
+        bail!("a branch named '{name}' already exists");
+    }
+    Ok(refname)
+}
+
+/// Create the branch `name` at `start` (HEAD by default).
+fn create(git_repo: &GitRepository, name: &str, start: Option<String>) -> Result<()> {
+    let refname = check_new_branch(git_repo, name)?;
+    let start = start.unwrap_or_else(|| "HEAD".to_string());
+    let commit = object_find(git_repo, start.clone(), ObjectType::Commit)?;
+    let mut transaction = RefTransaction::new(git_repo, &format!("branch: Created from {start}"));
+    transaction.create(&refname, &commit)?;
+    transaction.commit()
+}
+
+/// The branch `name`, or the current branch when it is `None`, as a full ref name.
+fn branch_or_current(git_repo: &GitRepository, name: Option<String>) -> Result<String> {
+    match name {
+        Some(name) => {
+            let refname = format!("refs/heads/{name}");
+            if ref_resolve(git_repo, &refname)?.is_none() {
+                bail!("branch '{name}' does not exist");
+            }
+            Ok(refname)
+        }
+        None => match resolve_head(git_repo)? {
+            Head::Branch(branch, _) => Ok(branch),
+            Head::Detached(_) => bail!("HEAD is detached; name the branch to change"),
+        },
+    }
+}
+
+/// Make `upstream` (a remote-tracking branch such as `origin/main`, or a local branch) the
+/// upstream of the branch `name` (the current branch by default).
+fn set_upstream(git_repo: &mut GitRepository, name: Option<String>, upstream: &str) -> Result<()> {
+    let branch = branch_or_current(git_repo, name)?;
+    let remote_branch = upstream.split_once('/').and_then(|(remote, _)| {
+        let (src, dst) = fetch_refspec(git_repo, remote)?;
+        let tracking = format!("refs/remotes/{upstream}");
+        Some((
+            remote.to_string(),
+            map_refspec(&dst, &src, &tracking)?,
+            tracking,
+        ))
+    });
+    let (remote, merge) = match remote_branch {
+        Some((remote, merge, tracking)) if ref_resolve(git_repo, &tracking)?.is_some() => {
+            (remote, merge)
+        }
+        _ => {
+            let local = format!("refs/heads/{upstream}");
+            if ref_resolve(git_repo, &local)?.is_none() {
+                bail!("the requested upstream branch '{upstream}' does not exist");
+            }
+            (".".to_string(), local)
+        }
+    };
+    let short = short_ref_name(&branch).to_string();
+    let section = format!("branch \"{short}\"");
+    git_repo.config_set(ConfigScope::Local, &section, "remote", Some(&remote))?;
+    git_repo.config_set(ConfigScope::Local, &section, "merge", Some(&merge))?;
+    println!("branch '{short}' set up to track '{upstream}'.");
+    Ok(())
+}
+
+/// Forget the upstream of the branch `name` (the current branch by default).
+fn unset_upstream(git_repo: &mut GitRepository, name: Option<String>) -> Result<()> {
+    let branch = branch_or_current(git_repo, name)?;
+    let short = short_ref_name(&branch);
+    let section = format!("branch \"{short}\"");
+    if git_repo.config_get(&section, "merge").is_none() {
+        bail!("branch '{short}' has no upstream information");
+    }
+    git_repo.config_set(ConfigScope::Local, &section, "remote", None)?;
+    git_repo.config_set(ConfigScope::Local, &section, "merge", None)?;
+    Ok(())
+}
+
+/// What `branch` was asked to do.
+pub(crate) enum BranchAction {
+    List {
+        filter: BranchFilter,
+        verbose: u8,
+    },
+    Create {
+        name: String,
+        start: Option<String>,
+    },
+    SetUpstream {
+        name: Option<String>,
+        upstream: String,
+    },
+    UnsetUpstream {
+        name: Option<String>,
+    },
+}
+
+pub(crate) fn invoke(repo: &mut GitRepository, action: BranchAction) -> Result<()> {
+    match action {
+        BranchAction::List { filter, verbose } => list(repo, &filter, verbose),
+        BranchAction::Create { name, start } => create(repo, &name, start),
+        BranchAction::SetUpstream { name, upstream } => set_upstream(repo, name, &upstream),
+        BranchAction::UnsetUpstream { name } => unset_upstream(repo, name),
+    }
+}
@@ -1,15 +1,151 @@
-use std::io::Write;
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufWriter, Write},
+};
 
 use crate::{
-    objects::{object_find, object_read},
-    repository::repo_find,
-    ObjectType,
+    decorate::ref_index,
+    diff::BlobCache,
+    objects::ObjectType,
+    objects::{
+        all_objects, object_exists, object_find, object_header, object_ids, object_read,
+        object_resolve_prefix, tree_ish, tree_lookup, tree_lookup_follow_symlinks,
+    },
+    repository::GitRepository,
 };
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-pub(crate) fn cmd_cat_file(tp: ObjectType, obj: String) -> Result<()> {
-    let repo = repo_find(".", true)?;
-    let obj = object_read(&repo, &object_find(&repo, obj, tp)?)?;
+/// Print the object `obj` as type `tp`. `obj` can also be `<rev>:<path>`, naming the entry at
+/// `path` in the tree of `rev`; with `follow_symlinks`, symlinks along that path are followed
+/// to what they point at inside the tree.
+pub(crate) fn cmd_cat_file(
+    repo: &GitRepository,
+    tp: ObjectType,
+    obj: String,
+    follow_symlinks: bool,
+) -> Result<()> {
+    let obj = match obj.split_once(':') {
+        Some((rev, path)) => {
+            let tree = tree_ish(repo, rev)?;
+            let entry = match follow_symlinks {
+                true => tree_lookup_follow_symlinks(repo, &tree, path)?,
+                false => tree_lookup(repo, &tree, path)?,
+            };
+            entry
+                .with_context(|| format!("path '{path}' does not exist in '{rev}'"))?
+                .hash
+        }
+        None => obj,
+    };
+    let obj = object_read(repo, &object_find(repo, obj, tp)?)?;
     std::io::stdout().write_all(&obj.serialize())?;
     Ok(())
 }
+
+/// Print the blob named by `spec` (`<rev>:<path>`), converted by the textconv driver of the path
+/// if it has one.
+pub(crate) fn invoke_textconv(repo: &GitRepository, spec: String) -> Result<()> {
+    let (rev, path) = spec
+        .split_once(':')
+        .with_context(|| format!("<object>:<path> required, only <object> '{spec}' given"))?;
+    let tree = tree_ish(repo, rev)?;
+    let Some(entry) = tree_lookup(repo, &tree, path)?.filter(|e| !e.is_tree()) else {
+        bail!("path '{path}' does not exist in '{rev}'");
+    };
+
+    let mut blobs = BlobCache::new(repo);
+    let data = match blobs.textconv_driver(path)? {
+        Some((_, command)) => blobs.textconv(&command, &entry.hash)?,
+        None => blobs.get(&entry.hash)?.to_vec(),
+    };
+    std::io::stdout().write_all(&data)?;
+    Ok(())
+}
+
+/// The line `--batch-check` prints for each object unless given a format.
+pub(crate) const BATCH_CHECK_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+/// `format` with the atoms `%(objectname)`, `%(objecttype)` and `%(objectsize)` replaced by
+/// those of the object `sha`.
+fn batch_check_line(repo: &GitRepository, format: &str, sha: &str) -> Result<String> {
+    let (kind, size) = object_header(repo, sha)?;
+    let mut line = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find("%(") {
+        line.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find(')')
+            .with_context(|| format!("unterminated format element: {}", &rest[start..]))?;
+        match &rest[start + 2..start + end] {
+            "objectname" => line.push_str(sha),
+            "objecttype" => line.push_str(&kind.to_string()),
+            "objectsize" => line.push_str(&size.to_string()),
+            atom => bail!("unknown format element: {atom}"),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    line.push_str(rest);
+    Ok(line)
+}
+
+/// The object `name` names, or `None` if there is none: a ref names its own object, like an
+/// annotated tag rather than its commit.
+fn batch_object(repo: &GitRepository, name: &str) -> Result<Option<String>> {
+    if repo.hash_algo().is_hex_id(name) {
+        let sha = name.to_ascii_lowercase();
+        return Ok(object_exists(repo, &sha)?.then_some(sha));
+    }
+    if name.contains(['^', '~']) {
+        return Ok(object_find(repo, name.to_string(), ObjectType::Commit).ok());
+    }
+    if let Some(tip) = ref_index(repo)?.resolve(name) {
+        return Ok(Some(tip.hash.clone()));
+    }
+    Ok(object_resolve_prefix(repo, name).ok().flatten())
+}
+
+/// Print a line in `format` (by default [`BATCH_CHECK_FORMAT`]) for each object named on
+/// standard input, or `<name> missing` for names of no object. With `all_objects`, every object
+/// in the store is printed instead, sorted by id unless `unordered`. Each line is flushed as it
+/// is printed unless `buffer`.
+pub(crate) fn invoke_batch_check(
+    repo: &GitRepository,
+    format: Option<String>,
+    all: bool,
+    unordered: bool,
+    buffer: bool,
+) -> Result<()> {
+    let format = format.as_deref().unwrap_or(BATCH_CHECK_FORMAT);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let mut print = |line: &str| -> Result<()> {
+        writeln!(out, "{line}")?;
+        if !buffer {
+            out.flush()?;
+        }
+        Ok(())
+    };
+    if all {
+        let ids: Vec<String> = match unordered {
+            true => {
+                let mut seen = HashSet::new();
+                object_ids(repo)?
+                    .filter(|sha| sha.as_ref().map_or(true, |sha| seen.insert(sha.clone())))
+                    .collect::<Result<_>>()?
+            }
+            false => all_objects(repo)?.into_iter().collect(),
+        };
+        for sha in ids {
+            print(&batch_check_line(repo, format, &sha)?)?;
+        }
+    } else {
+        for name in std::io::stdin().lock().lines() {
+            let name = name?;
+            match batch_object(repo, &name)? {
+                Some(sha) => print(&batch_check_line(repo, format, &sha)?)?,
+                None => print(&format!("{name} missing"))?,
+            }
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    attr::{AttrState, Attributes},
+    repository::{worktree_path, GitRepository},
+};
+
+/// Print the state of each of `attrs` for each of `paths`, as `<path>: <attr>: <state>` where
+/// the state is `set`, `unset`, `unspecified` or the attribute's value. Like git, without
+/// paths after `--` the first argument is the attribute and the rest are paths.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    mut attrs: Vec<String>,
+    mut paths: Vec<String>,
+) -> Result<()> {
+    if paths.is_empty() {
+        if attrs.len() < 2 {
+            bail!("no file name specified");
+        }
+        paths = attrs.split_off(1);
+    }
+    let mut attributes = Attributes::new(repo)?;
+    for path in &paths {
+        let relative = worktree_path(repo, path)?;
+        for attr in &attrs {
+            let state = attributes.get(&relative, attr);
+            let state = match &state {
+                Some(AttrState::Set) => "set",
+                Some(AttrState::Unset) => "unset",
+                Some(AttrState::Value(value)) => value,
+                None => "unspecified",
+            };
+            println!("{path}: {attr}: {state}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use anyhow::{bail, Result};
+
+use crate::{mailmap::Mailmap, repository::GitRepository};
+
+pub(crate) fn invoke(repo: &GitRepository, contacts: Vec<String>) -> Result<()> {
+    let mailmap = Mailmap::load(repo)?;
+    for contact in contacts {
+        let (Some((name, rest)), true) = (contact.split_once('<'), contact.ends_with('>')) else {
+            bail!("unable to parse contact: {contact}");
+        };
+        let email = &rest[..rest.len() - 1];
+        let (name, email) = mailmap.map_identity(name.trim(), email);
+        if name.is_empty() {
+            println!("<{email}>");
+        } else {
+            println!("{name} <{email}>");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    refs::{check_ref_format, normalize_ref_name, valid_branch_name},
+    ExitStatus,
+};
+
+/// Exit with status 1 unless `name` is a valid ref name. With `normalize`, the name is first
+/// tidied of extra slashes and printed when valid. With `branch`, `name` is checked as a branch
+/// name and printed.
+pub(crate) fn invoke(
+    name: String,
+    branch: bool,
+    normalize: bool,
+    allow_onelevel: bool,
+) -> Result<()> {
+    if branch {
+        if !valid_branch_name(&name) {
+            bail!("'{name}' is not a valid branch name");
+        }
+        println!("{name}");
+        return Ok(());
+    }
+    let name = match normalize {
+        true => normalize_ref_name(&name),
+        false => name,
+    };
+    if !check_ref_format(&name, allow_onelevel) {
+        // like git, an invalid name is reported only through the exit status
+        return Err(ExitStatus(1).into());
+    }
+    if normalize {
+        println!("{name}");
+    }
+    Ok(())
+}
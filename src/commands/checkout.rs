@@ -0,0 +1,312 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    ignore::PatternList,
+    index::{hash_file, stat_matches, worktree_state, Index, IndexEntry, IndexLock, WorktreeState},
+    objects::{
+        object_find, object_read, peel_to, read_tree_recursive, Kind, Mode, ObjectType, TreeEntry,
+    },
+    refs::{ref_resolve, resolve_head, write_head, Head},
+    repository::{repo_file, GitRepository},
+};
+
+/// Load `.git/info/sparse-checkout` when `core.sparseCheckout` is enabled.
+///
+/// A path is checked out if the last pattern matching it (or, failing that, one of its parent
+/// directories) is a positive one.
+pub(crate) fn sparse_patterns(git_repo: &GitRepository) -> Result<Option<PatternList>> {
+    if git_repo.config_bool("core", "sparseCheckout") != Some(true) {
+        return Ok(None);
+    }
+    let path = repo_file(git_repo, &["info", "sparse-checkout"], false)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    Ok(Some(PatternList::parse(&text)))
+}
+
+/// Whether `path` should be materialized in the work tree under the sparse `patterns`.
+pub(crate) fn in_sparse_checkout(patterns: Option<&PatternList>, path: &str) -> bool {
+    match patterns {
+        Some(patterns) => patterns.matched_with_parents(path, false) == Some(true),
+        None => true,
+    }
+}
+
+/// Write the blob of `entry` to `path` in the work tree, with the entry's mode.
+pub(crate) fn checkout_entry(
+    git_repo: &GitRepository,
+    entry: &TreeEntry,
+    path: &Path,
+) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+    }
+    match entry.mode {
+        Mode::Gitlink => {
+            // submodules are not checked out, only their directory is created
+            fs::create_dir_all(path).with_context(|| format!("create {}", path.display()))?;
+            return Ok(());
+        }
+        Mode::Symlink => {
+            let target = object_read(git_repo, &entry.hash)?.serialize();
+            let target = String::from_utf8(target).context("symlink target isn't utf-8")?;
+            symlink(target, path).with_context(|| format!("create symlink {}", path.display()))?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let data = object_read(git_repo, &entry.hash)?.serialize();
+    fs::write(path, data).with_context(|| format!("write {}", path.display()))?;
+    let mode = if entry.mode == Mode::Executable {
+        0o755
+    } else {
+        0o644
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("set mode of {}", path.display()))?;
+    Ok(())
+}
+
+/// Remove `path` from the work tree along with any parent directories left empty.
+pub(crate) fn remove_worktree_file(git_repo: &GitRepository, path: &str) -> Result<()> {
+    let full = git_repo.work_tree().join(path);
+    if fs::symlink_metadata(&full).is_err() {
+        return Ok(());
+    }
+    fs::remove_file(&full).with_context(|| format!("remove {}", full.display()))?;
+    let mut dir = Path::new(path).parent();
+    while let Some(d) = dir.filter(|d| !d.as_os_str().is_empty()) {
+        // stops at the first directory that still has content
+        if fs::remove_dir(git_repo.work_tree().join(d)).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+    Ok(())
+}
+
+pub(crate) fn invoke(repo: &GitRepository, rev: String, force: bool) -> Result<()> {
+    let commit = object_find(repo, rev.clone(), ObjectType::Commit)?;
+    let tree = peel_to(repo, &commit, Kind::Tree)?;
+    checkout_tree(repo, &tree, force)?;
+
+    // checking out a branch (re)attaches HEAD to it; anything else detaches it
+    let branch = format!("refs/heads/{rev}");
+    let moving = format!(
+        "checkout: moving from {} to {rev}",
+        resolve_head(repo)?.reflog_name()
+    );
+    if ref_resolve(repo, &branch)?.is_some() {
+        write_head(repo, &Head::Branch(branch, Some(commit)), &moving)?;
+        println!("Switched to branch '{rev}'");
+    } else {
+        println!("HEAD is now at {}", &commit[..7]);
+        write_head(repo, &Head::Detached(commit), &moving)?;
+    }
+    Ok(())
+}
+
+/// Make the index and work tree match `tree`, leaving HEAD alone. Unless `force` is set, this
+/// fails rather than overwrite local changes.
+pub(crate) fn checkout_tree(repo: &GitRepository, tree: &str, force: bool) -> Result<()> {
+    let sparse = sparse_patterns(repo)?;
+
+    let (old_index, lock) = Index::lock(repo)?;
+    let target = read_tree_recursive(repo, tree, "")?;
+    let target_by_path = target
+        .iter()
+        .map(|e| (e.name.as_str(), e))
+        .collect::<HashMap<_, _>>();
+
+    if !force {
+        check_overwrites(repo, &old_index, &target_by_path)?;
+    }
+
+    // drop tracked files that are gone from the target or fall outside the sparse patterns
+    for entry in &old_index.entries {
+        if entry.skip_worktree() {
+            continue;
+        }
+        let keep = target_by_path.contains_key(entry.path.as_str())
+            && in_sparse_checkout(sparse.as_ref(), &entry.path);
+        if !keep {
+            remove_worktree_file(repo, &entry.path)?;
+        }
+    }
+
+    // on a filesystem that folds case, paths differing only in case would overwrite each other;
+    // only the first of each such group is written
+    let mut written = HashSet::new();
+    let mut collided = BTreeSet::new();
+    let mut index = Index::new(repo.hash_algo());
+    for entry in &target {
+        let mut index_entry = IndexEntry::from_tree_entry(entry)?;
+        let (mode, hash) = (index_entry.mode, index_entry.hash);
+        if !in_sparse_checkout(sparse.as_ref(), &entry.name) {
+            index_entry.set_skip_worktree(true);
+            index.entries.push(index_entry);
+            continue;
+        }
+
+        if repo.ignorecase() && !written.insert(entry.name.to_ascii_lowercase()) {
+            collided.insert(entry.name.as_str());
+            index.entries.push(index_entry);
+            continue;
+        }
+
+        // files already at the target version are left alone, keeping any local changes
+        let path = repo.work_tree().join(&entry.name);
+        if let Some(old) = old_index.get(&entry.name).filter(|old| {
+            old.hash_hex() == entry.hash
+                && old.mode == mode
+                && !old.skip_worktree()
+                && fs::symlink_metadata(&path).is_ok()
+        }) {
+            // the old entry may spell the path in another case
+            index.entries.push(IndexEntry {
+                path: entry.name.clone(),
+                ..old.clone()
+            });
+            continue;
+        }
+        checkout_entry(repo, entry, &path)?;
+        let meta =
+            fs::symlink_metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+        index
+            .entries
+            .push(IndexEntry::from_metadata(&entry.name, &meta, mode, hash));
+    }
+    if !collided.is_empty() {
+        eprintln!(
+            "warning: the following paths have collided (e.g. case-sensitive paths\n\
+             on a case-insensitive filesystem) and only one from the same\n\
+             colliding group is in the working tree:\n"
+        );
+        let groups = target
+            .iter()
+            .filter(|e| collided.iter().any(|c| c.eq_ignore_ascii_case(&e.name)));
+        for entry in groups {
+            eprintln!("  '{}'", entry.name);
+        }
+    }
+    index.sort();
+    lock.commit(&index)
+}
+
+/// Make the index and work tree match `tree` exactly, discarding local changes to tracked files
+/// as `reset --hard` does. Untracked files are left alone.
+pub(crate) fn reset_to_tree(repo: &GitRepository, tree: &str) -> Result<()> {
+    let (old_index, lock) = Index::lock(repo)?;
+    reset_locked_to_tree(repo, &old_index, lock, tree)
+}
+
+/// Reset to `tree` as [`reset_to_tree`] does, from `old_index`, which the caller read and still
+/// holds `lock` on.
+pub(crate) fn reset_locked_to_tree(
+    repo: &GitRepository,
+    old_index: &Index,
+    lock: IndexLock,
+    tree: &str,
+) -> Result<()> {
+    let target = read_tree_recursive(repo, tree, "")?;
+    let target_paths = target
+        .iter()
+        .map(|e| e.name.as_str())
+        .collect::<HashSet<_>>();
+    for entry in &old_index.entries {
+        if !target_paths.contains(entry.path.as_str()) {
+            remove_worktree_file(repo, &entry.path)?;
+        }
+    }
+
+    let mut index = Index::new(repo.hash_algo());
+    for entry in &target {
+        let mut index_entry = IndexEntry::from_tree_entry(entry)?;
+        // files already at the target version are left alone
+        if let Some(old) = old_index
+            .get(&entry.name)
+            .filter(|old| old.hash == index_entry.hash && old.mode == index_entry.mode)
+        {
+            if let WorktreeState::Unchanged(meta) = worktree_state(repo, old)? {
+                index_entry.refresh_stat(&meta);
+                index.entries.push(index_entry);
+                continue;
+            }
+        }
+        let path = repo.work_tree().join(&entry.name);
+        checkout_entry(repo, entry, &path)?;
+        let meta =
+            fs::symlink_metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+        index_entry.refresh_stat(&meta);
+        index.entries.push(index_entry);
+    }
+    index.sort();
+    lock.commit(&index)
+}
+
+/// Refuse to clobber local modifications of tracked files, or untracked files, that the
+/// checkout would overwrite or delete.
+fn check_overwrites(
+    git_repo: &GitRepository,
+    old_index: &Index,
+    target: &HashMap<&str, &TreeEntry>,
+) -> Result<()> {
+    let mut modified = Vec::new();
+    for entry in &old_index.entries {
+        if entry.skip_worktree() {
+            continue;
+        }
+        let path = git_repo.work_tree().join(&entry.path);
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let target_hash = target.get(entry.path.as_str()).map(|e| e.hash.as_str());
+        if target_hash == Some(&entry.hash_hex()) || stat_matches(entry, &meta) {
+            continue;
+        }
+        if hash_file(&path, git_repo.hash_algo())? != entry.hash {
+            modified.push(entry.path.clone());
+        }
+    }
+
+    let mut untracked = Vec::new();
+    for (path, entry) in target {
+        if old_index.get(path).is_some() {
+            continue;
+        }
+        let full = git_repo.work_tree().join(path);
+        if fs::symlink_metadata(&full).is_ok_and(|m| !m.is_dir())
+            && hex::encode(hash_file(&full, git_repo.hash_algo())?) != entry.hash
+        {
+            untracked.push(path.to_string());
+        }
+    }
+    untracked.sort();
+
+    if !modified.is_empty() {
+        bail!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}\nPlease commit your changes or stash them before you switch branches.",
+            modified.join("\n\t")
+        );
+    }
+    if !untracked.is_empty() {
+        bail!(
+            "The following untracked working tree files would be overwritten by checkout:\n\t{}\nPlease move or remove them before you switch branches.",
+            untracked.join("\n\t")
+        );
+    }
+    Ok(())
+}
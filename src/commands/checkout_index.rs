@@ -0,0 +1,91 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::checkout::checkout_entry,
+    index::{stat_matches, Index},
+    repository::{worktree_path, GitRepository},
+};
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    paths: Vec<String>,
+    all: bool,
+    force: bool,
+    prefix: Option<String>,
+) -> Result<()> {
+    let (mut index, lock) = Index::lock(repo)?;
+
+    // `-a` covers the entries below the current directory
+    let mut wanted = paths
+        .iter()
+        .map(|path| worktree_path(repo, path))
+        .collect::<Result<Vec<_>>>()?;
+    if all {
+        let here = worktree_path(repo, ".")?;
+        let here = if here.is_empty() {
+            here
+        } else {
+            format!("{here}/")
+        };
+        wanted.extend(
+            index
+                .entries
+                .iter()
+                .filter(|e| e.stage() == 0 && !e.skip_worktree() && e.path.starts_with(&here))
+                .map(|e| e.path.clone()),
+        );
+    }
+
+    let mut failed = false;
+    for path in wanted {
+        let Some(entry) = index
+            .entries
+            .iter_mut()
+            .find(|e| e.path == path && e.stage() == 0)
+        else {
+            if index.entries.iter().any(|e| e.path == path) {
+                eprintln!("{path}: needs merge");
+            } else {
+                eprintln!("git checkout-index: {path} is not in the cache");
+            }
+            failed = true;
+            continue;
+        };
+        // a relative prefix is taken from the top of the work tree, like the paths themselves
+        let target = repo
+            .work_tree()
+            .join(format!("{}{path}", prefix.as_deref().unwrap_or_default()));
+        if let Ok(meta) = fs::symlink_metadata(&target) {
+            // an up-to-date work tree file needs no checkout
+            if prefix.is_none() && stat_matches(entry, &meta) {
+                continue;
+            }
+            if !force {
+                eprintln!("{path} already exists, no checkout");
+                failed = true;
+                continue;
+            }
+            if meta.is_dir() {
+                fs::remove_dir_all(&target)
+                    .with_context(|| format!("remove {}", target.display()))?;
+            }
+        }
+        checkout_entry(repo, &entry.tree_entry(), &target)?;
+        // files written to the work tree are now up to date
+        if prefix.is_none() {
+            let meta = fs::symlink_metadata(&target)
+                .with_context(|| format!("stat {}", target.display()))?;
+            entry.refresh_stat(&meta);
+        }
+    }
+
+    if prefix.is_none() {
+        lock.commit(&index)?;
+    }
+    if failed {
+        bail!("some paths could not be checked out");
+    }
+    Ok(())
+}
@@ -0,0 +1,195 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        checkout::checkout_tree,
+        fetch::{fetch_refs, FetchOptions, Remote},
+        remote::Refspec,
+    },
+    objects::{peel_to, Kind},
+    refs::{ref_update, write_head, Head},
+    repository::{repo_create, repo_file, ConfigScope, GitRepository},
+    transport::RemoteUrl,
+};
+
+/// How `clone` was asked to clone.
+#[derive(Debug, Default)]
+pub(crate) struct CloneOptions {
+    /// What the remote is called instead of `origin`.
+    pub(crate) origin: Option<String>,
+    /// The branch to check out instead of the remote's HEAD.
+    pub(crate) branch: Option<String>,
+    pub(crate) no_checkout: bool,
+    pub(crate) quiet: bool,
+    /// The program to run instead of `git-upload-pack`, remembered for later fetches.
+    pub(crate) upload_pack: Option<String>,
+}
+
+/// The directory a clone of `url` goes to by default: the last component of its path,
+/// without a `.git` suffix.
+fn default_directory(url: &str) -> Result<PathBuf> {
+    let path = url.trim_end_matches('/');
+    let path = path.strip_suffix("/.git").unwrap_or(path);
+    let name = path.rsplit(['/', ':']).next().unwrap_or_default();
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    if name.is_empty() {
+        bail!("cannot guess a directory name for {url}; give one");
+    }
+    Ok(PathBuf::from(name))
+}
+
+/// Set up the new repository at `git_repo` as a clone of `url`: fetch everything the remote
+/// has into its remote-tracking refs and tags, then create the branch the remote's HEAD is on
+/// (or `options.branch`) and check it out.
+fn clone_into(git_repo: &mut GitRepository, url: &str, options: &CloneOptions) -> Result<()> {
+    let origin = options.origin.as_deref().unwrap_or("origin");
+    let section = format!("remote \"{origin}\"");
+    // a local path is remembered whole, so the clone can fetch from anywhere
+    let url = match RemoteUrl::parse(url)? {
+        RemoteUrl::Local(path) => path
+            .canonicalize()
+            .with_context(|| format!("repository '{url}' does not exist"))?
+            .to_string_lossy()
+            .into_owned(),
+        RemoteUrl::Ssh { .. } | RemoteUrl::Http(_) => url.to_string(),
+    };
+    let fetch = format!("+refs/heads/*:refs/remotes/{origin}/*");
+    git_repo.config_set(ConfigScope::Local, &section, "url", Some(&url))?;
+    git_repo.config_set(ConfigScope::Local, &section, "fetch", Some(&fetch))?;
+    if let Some(upload_pack) = &options.upload_pack {
+        git_repo.config_set(
+            ConfigScope::Local,
+            &section,
+            "uploadpack",
+            Some(upload_pack),
+        )?;
+    }
+
+    let remote = Remote::find(git_repo, origin);
+    let fetch_options = FetchOptions {
+        tags: Some(true),
+        quiet: options.quiet,
+        ..FetchOptions::default()
+    };
+    let (fetched, advertisement) = fetch_refs(
+        git_repo,
+        &remote,
+        &[Refspec::parse(&fetch)],
+        false,
+        &fetch_options,
+    )?;
+    let reflog = format!("clone: from {url}");
+    let null = git_repo.hash_algo().null().to_string();
+    for item in &fetched {
+        if let Some(dst) = &item.dst {
+            ref_update(git_repo, dst, &item.hash, Some(&null), &reflog)?;
+        }
+    }
+
+    if advertisement.refs.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    }
+    let branch = match &options.branch {
+        Some(branch) => {
+            let name = format!("refs/heads/{branch}");
+            if advertisement.get(&name).is_none() {
+                bail!("Remote branch {branch} not found in upstream {origin}");
+            }
+            name
+        }
+        None => {
+            let head = advertisement
+                .symref("HEAD")
+                .map(str::to_string)
+                .or_else(|| {
+                    // without the symref capability, the first branch at HEAD's commit is HEAD's
+                    let head = advertisement.get("HEAD")?;
+                    advertisement
+                        .refs
+                        .iter()
+                        .find(|(name, hash)| name.starts_with("refs/heads/") && hash == head)
+                        .map(|(name, _)| name.clone())
+                });
+            match head {
+                Some(head) => head,
+                None => {
+                    eprintln!("warning: remote HEAD refers to nonexistent ref, unable to checkout");
+                    return Ok(());
+                }
+            }
+        }
+    };
+    let short = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+    let commit = advertisement
+        .get(&branch)
+        .with_context(|| format!("remote HEAD refers to nonexistent ref {branch}"))?
+        .to_string();
+
+    let remote_head = repo_file(git_repo, &["refs", "remotes", origin, "HEAD"], true)?;
+    fs::write(
+        &remote_head,
+        format!("ref: refs/remotes/{origin}/{short}\n"),
+    )
+    .with_context(|| format!("write {}", remote_head.display()))?;
+    ref_update(git_repo, &branch, &commit, Some(&null), &reflog)?;
+    write_head(
+        git_repo,
+        &Head::Branch(branch.clone(), Some(commit.clone())),
+        &reflog,
+    )?;
+    let section = format!("branch \"{short}\"");
+    git_repo.config_set(ConfigScope::Local, &section, "remote", Some(origin))?;
+    git_repo.config_set(ConfigScope::Local, &section, "merge", Some(&branch))?;
+
+    if !options.no_checkout {
+        let tree = peel_to(git_repo, &commit, Kind::Tree)?;
+        checkout_tree(git_repo, &tree, true)?;
+    }
+    Ok(())
+}
+
+/// Clone the repository at `url` into `directory` (named after the URL by default), which
+/// must not exist or be empty. A clone that fails leaves nothing behind.
+pub(crate) fn invoke(url: String, directory: Option<PathBuf>, options: CloneOptions) -> Result<()> {
+    let directory = match directory {
+        Some(directory) => directory,
+        None => default_directory(&url)?,
+    };
+    let existed = directory.exists();
+    if existed
+        && fs::read_dir(&directory)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(true)
+    {
+        bail!(
+            "destination path '{}' already exists and is not an empty directory.",
+            directory.display()
+        );
+    }
+    if !options.quiet {
+        eprintln!("Cloning into '{}'...", directory.display());
+    }
+    let result = repo_create(&directory, None)
+        .and_then(|(mut git_repo, _)| clone_into(&mut git_repo, &url, &options));
+    if result.is_err() {
+        let cleanup = match existed {
+            true => fs::read_dir(&directory).and_then(|mut entries| {
+                entries.try_for_each(|entry| {
+                    let path = entry?.path();
+                    match path.is_dir() {
+                        true => fs::remove_dir_all(path),
+                        false => fs::remove_file(path),
+                    }
+                })
+            }),
+            false => fs::remove_dir_all(&directory),
+        };
+        if let Err(e) = cleanup {
+            eprintln!("warning: could not clean up {}: {e}", directory.display());
+        }
+    }
+    result
+}
@@ -0,0 +1,184 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        commit_tree::{identity, write_commit_object},
+        merge::{clear_merge_state, MERGE_HEAD, MERGE_MSG, SQUASH_MSG},
+        rerere,
+        status::{long_status, Status},
+        write_tree::update_cache_tree,
+    },
+    editor::launch_editor,
+    index::Index,
+    message::{comment_lines, stripspace},
+    objects::{object_find, read_commit, subject, ObjectType},
+    refs::{ref_update, resolve_head, Head},
+    repository::{repo_path, GitRepository},
+    trailer::{add_trailer, Trailer},
+};
+
+/// The `Signed-off-by` trailer for the committer of `git_repo`.
+fn signoff(git_repo: &GitRepository) -> Result<Trailer> {
+    let committer = identity(git_repo, "committer")?;
+    Ok(Trailer {
+        key: "Signed-off-by".to_string(),
+        value: format!("{} <{}>", committer.name, committer.email),
+    })
+}
+
+/// The file the commit message is edited in.
+const COMMIT_EDITMSG: &str = "COMMIT_EDITMSG";
+
+/// The message prepared by an unfinished `merge` (`MERGE_MSG`, or `SQUASH_MSG` for a squash),
+/// as it was written.
+fn prepared_message(git_repo: &GitRepository) -> Result<Option<String>> {
+    for name in [MERGE_MSG, SQUASH_MSG] {
+        let path = repo_path(git_repo, &[name])?;
+        if let Ok(text) = fs::read_to_string(&path) {
+            return Ok(Some(text));
+        }
+    }
+    Ok(None)
+}
+
+/// Have the user write the commit message in their editor, starting from `COMMIT_EDITMSG`
+/// filled with the message prepared by an unfinished merge (or else `start`, if any) and,
+/// commented out, the status of what is being committed. Returns the message without its
+/// comments.
+fn edit_message(
+    git_repo: &GitRepository,
+    head: &Head,
+    index: &mut Index,
+    start: Option<&str>,
+) -> Result<String> {
+    let (status, _) = Status::collect(git_repo, head.commit(), index)?;
+    let mut template = match prepared_message(git_repo)? {
+        Some(prepared) => prepared,
+        None => start.map(|start| format!("{start}\n")).unwrap_or_default(),
+    };
+    template.push('\n');
+    template.push_str(&comment_lines(&format!(
+        "Please enter the commit message for your changes. Lines starting\n\
+         with '#' will be ignored, and an empty message aborts the commit.\n\n{}",
+        long_status(git_repo, head, &status)?
+    )));
+    let path = repo_path(git_repo, &[COMMIT_EDITMSG])?;
+    fs::write(&path, template).with_context(|| format!("write {}", path.display()))?;
+    launch_editor(git_repo, &path)?;
+    let text = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    Ok(stripspace(&text, true))
+}
+
+/// The subject `rebase --autosquash` recognizes a commit folding into `target` by, like
+/// `fixup! <subject of target>` for `action` `fixup`.
+fn fold_subject(git_repo: &GitRepository, action: &str, target: String) -> Result<String> {
+    let target = object_find(git_repo, target, ObjectType::Commit)?;
+    let message = read_commit(git_repo, &target)?.message;
+    Ok(format!("{action}! {}", subject(message.as_bytes())))
+}
+
+/// Commit the index on top of HEAD. Without `message`, the message is written in the editor,
+/// and if an unfinished merge recorded `MERGE_HEAD`, it becomes the second parent.
+///
+/// With `fixup` or `squash`, the commit is marked to be folded into that commit by
+/// `rebase --autosquash`: its message starts with `fixup! ` or `squash! ` and the target's
+/// subject. A fixup's message needs no editing, as its own is dropped when it is folded.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    message: Option<String>,
+    all: bool,
+    allow_empty: bool,
+    signoff_flag: bool,
+    fixup: Option<String>,
+    squash: Option<String>,
+) -> Result<()> {
+    rerere::record_resolutions(repo)?;
+    let (mut index, lock) = Index::lock(repo)?;
+    if all {
+        index.stage_tracked(repo)?;
+    }
+    let tree = update_cache_tree(repo, &mut index)?;
+    let head = resolve_head(repo)?;
+    let mut parents = head
+        .commit()
+        .map(str::to_string)
+        .into_iter()
+        .collect::<Vec<_>>();
+    let merge_head = repo_path(repo, &[MERGE_HEAD])?;
+    let merging = merge_head.exists();
+    if merging {
+        let merged = fs::read_to_string(&merge_head)
+            .with_context(|| format!("read {}", merge_head.display()))?;
+        parents.push(merged.trim().to_string());
+    }
+    // a merge commit is never empty: it records that the histories were joined
+    if !allow_empty && !merging {
+        // an empty commit records the same tree as its parent (or no files at all for a root)
+        let unchanged = match parents.first() {
+            Some(parent) => read_commit(repo, parent)?.tree == tree,
+            None => index.entries.is_empty(),
+        };
+        if unchanged {
+            bail!("nothing to commit (use --allow-empty to commit anyway)");
+        }
+    }
+
+    let folded = fixup.is_some();
+    let fold = match (fixup, squash) {
+        (Some(target), _) => Some(fold_subject(repo, "fixup", target)?),
+        (_, Some(target)) => Some(fold_subject(repo, "squash", target)?),
+        (None, None) => None,
+    };
+    let message = message.or_else(|| folded.then(String::new));
+    let mut message = match message {
+        Some(message) => {
+            let message = match &fold {
+                Some(fold) => format!("{fold}\n\n{message}"),
+                None => message,
+            };
+            let message = stripspace(&message, false);
+            let path = repo_path(repo, &[COMMIT_EDITMSG])?;
+            fs::write(&path, &message).with_context(|| format!("write {}", path.display()))?;
+            message
+        }
+        None => edit_message(repo, &head, &mut index, fold.as_deref())?,
+    };
+    if message.is_empty() {
+        bail!("Aborting commit due to empty commit message.");
+    }
+    if signoff_flag {
+        message = add_trailer(&message, &signoff(repo)?);
+    }
+    let author = identity(repo, "author")?.to_string();
+    let committer = identity(repo, "committer")?.to_string();
+    let commit = write_commit_object(repo, &tree, &parents, &author, &committer, &message)?;
+    let kind = match (parents.len(), merging) {
+        (0, _) => " (initial)",
+        (_, true) => " (merge)",
+        _ => "",
+    };
+    let reflog = format!("commit{kind}: {}", subject(message.as_bytes()));
+    // a detached HEAD moves by itself; otherwise the branch moves
+    let null = repo.hash_algo().null().to_string();
+    let old = head.commit().unwrap_or(&null);
+    ref_update(repo, "HEAD", &commit, Some(old), &reflog)?;
+    let branch = match &head {
+        Head::Branch(branch, _) => branch.strip_prefix("refs/heads/").unwrap_or(branch),
+        Head::Detached(_) => "detached HEAD",
+    };
+    // only now that the commit is made: with `all`, an aborted commit leaves the index alone
+    lock.commit(&index)?;
+
+    clear_merge_state(repo)?;
+
+    let root = if parents.is_empty() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    let subject = message.lines().next().unwrap_or_default();
+    println!("[{branch}{root} {}] {subject}", &commit[..7]);
+    Ok(())
+}
@@ -1,55 +1,232 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 
-use std::{collections::HashMap, fmt::Write, io::Cursor};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{Read, Write},
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::objects::{Kind, Object};
+use crate::{
+    objects::{write_object, Kind},
+    repository::GitRepository,
+    signature::Signature,
+};
 
-pub(crate) fn write_commit(
+/// The identity of the commit `role` (`author` or `committer`), which commit headers record as
+/// `Name <email> <unix time> <timezone>`. Every command recording who did something goes
+/// through here, so that their identities always agree.
+///
+/// Like git, `GIT_<ROLE>_NAME`, `GIT_<ROLE>_EMAIL` and `GIT_<ROLE>_DATE` take precedence over
+/// `user.name` and `user.email` from the config, and `$EMAIL` comes after the config. What is
+/// still missing is made up from the system username and hostname, with a warning. The date
+/// defaults to now, in UTC.
+pub(crate) fn identity(git_repo: &GitRepository, role: &str) -> Result<Signature> {
+    let env = |what: &str| std::env::var(format!("GIT_{}_{what}", role.to_ascii_uppercase())).ok();
+    let name = env("NAME").or_else(|| git_repo.config_get("user", "name").map(str::to_string));
+    let email = env("EMAIL")
+        .or_else(|| git_repo.config_get("user", "email").map(str::to_string))
+        .or_else(|| std::env::var("EMAIL").ok());
+    let (name, email) = match (name, email) {
+        (Some(name), Some(email)) => (name, email),
+        (name, email) => {
+            let (Some(user), Some(host)) = (system_username(), system_hostname()) else {
+                bail!("Please tell me who you are: set user.name and user.email in the config");
+            };
+            static WARNED: std::sync::Once = std::sync::Once::new();
+            WARNED.call_once(|| {
+                eprintln!(
+                    "warning: user.name or user.email is not set; using your username and hostname.\n\
+                     Please tell me who you are: set user.name and user.email in the config."
+                )
+            });
+            let email = email.unwrap_or_else(|| format!("{user}@{host}"));
+            (name.unwrap_or(user), email)
+        }
+    };
+    let (time, offset) = match env("DATE") {
+        Some(date) => {
+            Signature::parse_date(&date).with_context(|| format!("invalid date format: {date}"))?
+        }
+        None => (
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            0,
+        ),
+    };
+    Ok(Signature {
+        name,
+        email,
+        time,
+        offset,
+    })
+}
+
+/// The name of the user running us, from `$USER` or `$LOGNAME`.
+fn system_username() -> Option<String> {
+    ["USER", "LOGNAME"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+}
+
+/// The name of this machine, as `hostname` reports it.
+fn system_hostname() -> Option<String> {
+    let output = Command::new("hostname").output().ok()?;
+    let host = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !host.is_empty()).then_some(host)
+}
+
+/// Sign `payload` with `gpg.program` (`gpg` by default), returning the ASCII-armored detached
+/// signature. The key is `user.signingkey`, or the `committer` identity without its date.
+fn sign_buffer(git_repo: &GitRepository, payload: &str, committer: &str) -> Result<String> {
+    let program = git_repo.config_get("gpg", "program").unwrap_or("gpg");
+    let key = match git_repo.config_get("user", "signingkey") {
+        Some(key) => key.to_string(),
+        None => match committer.rfind('>') {
+            Some(end) => committer[..=end].to_string(),
+            None => committer.to_string(),
+        },
+    };
+    let mut child = Command::new(program)
+        .args(["--status-fd=2", "-bsau", &key])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("cannot run {program}; gpg failed to sign the data"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // a signer that gives up before reading it closes the pipe; its exit status says why
+        let _ = stdin.write_all(payload.as_bytes());
+    }
+    let output = child.wait_with_output()?;
+    let status = String::from_utf8_lossy(&output.stderr);
+    // gpg can exit 0 without signing; the status line is the real confirmation
+    if !output.status.success() || !status.contains("[GNUPG:] SIG_CREATED ") {
+        bail!("gpg failed to sign the data:\n{}", status.trim_end());
+    }
+    String::from_utf8(output.stdout).context("signature isn't utf-8")
+}
+
+/// Write a commit object and return its hash.
+///
+/// With `commit.gpgsign` set, the commit is signed first and the signature embedded in a
+/// `gpgsig` header, as git does.
+pub(crate) fn write_commit_object(
+    git_repo: &GitRepository,
+    tree: &str,
+    parents: &[String],
+    author: &str,
+    committer: &str,
     message: &str,
-    tree_hash: &str,
-    parent_tree_hash: Option<&str>,
-) -> Result<[u8; 20]> {
+) -> Result<String> {
     let mut commit = String::new();
-    writeln!(commit, "tree {}", tree_hash)?;
-    if let Some(parent_tree_hash) = parent_tree_hash {
-        writeln!(commit, "parent {}", parent_tree_hash)?;
+    writeln!(commit, "tree {tree}")?;
+    for parent in parents {
+        writeln!(commit, "parent {parent}")?;
+    }
+    writeln!(commit, "author {author}")?;
+    writeln!(commit, "committer {committer}")?;
+    let mut body = String::new();
+    writeln!(body)?;
+    body.push_str(message);
+    if !body.ends_with('\n') {
+        body.push('\n');
     }
-    let author = "root <root@vmi2447354.contaboserver.net>";
-    let committer = "root <root@vmi2447354.contaboserver.net>";
-    writeln!(commit, "author {}", author)?;
-    writeln!(commit, "committer {}", committer)?;
-    writeln!(commit, "{}", message)?;
-    Object {
-        kind: Kind::Commit,
-        expected_size: commit.len() as u64,
-        reader: Cursor::new(commit),
+    if git_repo.config_bool("commit", "gpgsign").unwrap_or(false) {
+        let signature = sign_buffer(git_repo, &format!("{commit}{body}"), committer)?;
+        // continuation lines of a header start with a space
+        writeln!(
+            commit,
+            "gpgsig {}",
+            signature.trim_end().replace('\n', "\n ")
+        )?;
     }
-    .write_to_objects()
-    .context("write commit object")
+    commit.push_str(&body);
+    write_object(git_repo, Kind::Commit, commit.as_bytes()).context("write commit object")
 }
 
-pub fn invoke(message: String, tree_hash: String, parent_tree_hash: Option<String>) -> Result<()> {
-    let hash = write_commit(&message, &tree_hash, parent_tree_hash.as_deref())?;
+pub fn invoke(
+    repo: &GitRepository,
+    message: Option<String>,
+    tree_hash: String,
+    parent_tree_hash: Option<String>,
+) -> Result<()> {
+    let message = match message {
+        Some(message) => message,
+        None => {
+            let mut message = String::new();
+            std::io::stdin()
+                .read_to_string(&mut message)
+                .context("read message from stdin")?;
+            // the message's own final newline stands in for the one `-m` messages get
+            if message.ends_with('\n') {
+                message.pop();
+            }
+            message
+        }
+    };
+    let author = identity(repo, "author")?;
+    let committer = identity(repo, "committer")?;
+    let parents = parent_tree_hash.into_iter().collect::<Vec<_>>();
+    let hash = write_commit_object(
+        repo,
+        &tree_hash,
+        &parents,
+        &author.to_string(),
+        &committer.to_string(),
+        &message,
+    )?;
+    println!("{hash}");
     Ok(())
 }
 
-fn kvlm_parse(mut raw: &[u8], start: usize, mut map: HashMap<Vec<u8>, Vec<Vec<u8>>>) -> Result<()> {
-    if start >= raw.len() {
-        return Ok(());
-    }
-    raw = &raw[start..];
-    let next_space = raw
-        .iter()
-        .position(|b| *b == b' ')
-        .context("No space found in raw data")?;
-    let next_new_line = raw
-        .iter()
-        .position(|b| *b == b'\n')
-        .context("No newline found in raw data")?;
-    let key = raw[start..next_space].to_vec();
-    let value = raw[next_space + 1..next_new_line].to_vec();
-
-    //TODO: check if key already exists
+/// Parse a key-value list with message (the format shared by commits and tags).
+///
+/// Continuation lines of a value start with a space, which is stripped. The message, which
+/// follows the first blank line, is stored under the empty key.
+pub(crate) fn kvlm_parse(raw: &[u8]) -> Result<HashMap<Vec<u8>, Vec<Vec<u8>>>> {
+    let mut map: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
+    let mut start = 0;
+    loop {
+        let next_new_line = raw[start..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map(|i| start + i);
+        // a blank line (or running out of headers) means the rest is the message
+        if next_new_line == Some(start) || next_new_line.is_none() {
+            let message_start = (start + 1).min(raw.len());
+            map.insert(Vec::new(), vec![raw[message_start..].to_vec()]);
+            return Ok(map);
+        }
+        let next_space = raw[start..]
+            .iter()
+            .position(|b| *b == b' ')
+            .map(|i| start + i)
+            .context("No space found in raw data")?;
 
-    Ok(())
+        // the value runs until a newline that is not followed by a space
+        let mut end = start;
+        loop {
+            end += raw[end..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .context("No newline found in raw data")?;
+            if raw.get(end + 1) != Some(&b' ') {
+                break;
+            }
+            end += 1;
+        }
+        let key = raw[start..next_space].to_vec();
+        let mut value = Vec::new();
+        for (i, line) in raw[next_space + 1..end].split(|b| *b == b'\n').enumerate() {
+            if i > 0 {
+                value.push(b'\n');
+                value.extend_from_slice(line.strip_prefix(b" ").unwrap_or(line));
+            } else {
+                value.extend_from_slice(line);
+            }
+        }
+        map.entry(key).or_default().push(value);
+        start = end + 1;
+    }
 }
@@ -0,0 +1,66 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    repository::{ConfigScope, GitRepository},
+    ExitStatus,
+};
+
+/// Split a setting's name into the config section it lives in and its key: `core.bare` is
+/// `bare` in `[core]`, and `branch.main.remote` is `remote` in `[branch "main"]`.
+fn split_name(name: &str) -> Result<(String, &str)> {
+    let Some((section, key)) = name.rsplit_once('.') else {
+        bail!("key does not contain a section: {name}");
+    };
+    if section.is_empty() || key.is_empty() {
+        bail!("invalid key: {name}");
+    }
+    let section = match section.split_once('.') {
+        Some((section, sub)) => format!("{} \"{sub}\"", section.to_ascii_lowercase()),
+        None => section.to_ascii_lowercase(),
+    };
+    Ok((section, key))
+}
+
+/// Print the value of the setting `name` (exiting with status 1 if it has none), set it to
+/// `value`, remove it with `unset`, or with `list` print every setting. Reading looks in the
+/// worktree's config, then the repository's and then the user's unless `scope` picks one;
+/// writing goes to the repository's config unless `scope` picks another.
+pub(crate) fn invoke(
+    repo: &mut GitRepository,
+    scope: Option<ConfigScope>,
+    name: Option<String>,
+    value: Option<String>,
+    unset: bool,
+    list: bool,
+) -> Result<()> {
+    let scope = scope.map(|scope| repo.resolve_scope(scope)).transpose()?;
+    if list {
+        let entries = match scope {
+            Some(scope) => repo.config_entries_in(scope),
+            None => repo.config_entries(),
+        };
+        for (key, value) in entries {
+            println!("{key}={value}");
+        }
+        return Ok(());
+    }
+    let name = name.unwrap_or_default();
+    let (section, key) = split_name(&name)?;
+    if value.is_some() || unset {
+        let scope = scope.unwrap_or(ConfigScope::Local);
+        if unset && repo.config_get_in(scope, &section, key).is_none() {
+            return Err(ExitStatus(5).into());
+        }
+        repo.config_set(scope, &section, key, value.as_deref())?;
+        return Ok(());
+    }
+    let found = match scope {
+        Some(scope) => repo.config_get_in(scope, &section, key),
+        None => repo.config_get(&section, key),
+    };
+    match found {
+        Some(value) => println!("{value}"),
+        None => return Err(ExitStatus(1).into()),
+    }
+    Ok(())
+}
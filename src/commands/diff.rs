@@ -0,0 +1,161 @@
+use anyhow::Result;
+
+use crate::{
+    binary_patch::blob_hash,
+    color::ColorWhen,
+    convert::Converter,
+    diff::{
+        detect_renames, diff_listings, diff_trees, write_patch, write_stat, BlobCache, Change,
+        DiffOptions, Listing,
+    },
+    index::{worktree_state, Index, WorktreeState},
+    objects::{read_tree_recursive, tree_ish, Mode},
+    pager::paged,
+    refs::resolve_head,
+    repository::GitRepository,
+    ExitStatus,
+};
+
+/// The files of the tree `tree`.
+pub(crate) fn tree_listing(git_repo: &GitRepository, tree: &str) -> Result<Listing> {
+    Ok(read_tree_recursive(git_repo, tree, "")?
+        .into_iter()
+        .map(|e| (e.name, (e.mode, e.hash)))
+        .collect())
+}
+
+/// The files staged in `index`, leaving out unmerged paths.
+pub(crate) fn index_listing(index: &Index) -> Listing {
+    index
+        .entries
+        .iter()
+        .filter(|e| e.stage() == 0)
+        .map(|e| (e.path.clone(), (Mode::from_bits(e.mode), e.hash_hex())))
+        .collect()
+}
+
+/// The work tree versions of the files staged in `index`, leaving out unmerged and deleted
+/// ones. Files whose stat data says they are unchanged keep their staged blob; the others are
+/// hashed as staging them would store them, and their content is put in `blobs`.
+pub(crate) fn worktree_listing(
+    git_repo: &GitRepository,
+    index: &Index,
+    blobs: &mut BlobCache,
+) -> Result<Listing> {
+    let mut converter = Converter::new(git_repo)?;
+    let mut listing = Listing::new();
+    for entry in index.entries.iter().filter(|e| e.stage() == 0) {
+        let staged = (Mode::from_bits(entry.mode), entry.hash_hex());
+        // sparse and submodule entries are compared by what is staged
+        if entry.skip_worktree() || staged.0 == Mode::Gitlink {
+            listing.insert(entry.path.clone(), staged);
+            continue;
+        }
+        let file = match worktree_state(git_repo, entry)? {
+            WorktreeState::Unchanged(_) => staged,
+            WorktreeState::Deleted => continue,
+            WorktreeState::Modified(mode, _) => {
+                let data = converter.worktree_blob(&entry.path)?;
+                let hash = blob_hash(git_repo.hash_algo(), &data);
+                blobs.insert(hash.clone(), data);
+                (Mode::from_bits(mode), hash)
+            }
+        };
+        listing.insert(entry.path.clone(), file);
+    }
+    Ok(listing)
+}
+
+/// `index` with each unmerged path replaced by its "ours" version (stage 2), if it has one, so
+/// that it can be compared like a staged file.
+pub(crate) fn ours_index(index: &Index) -> Index {
+    let mut ours = index.clone();
+    ours.entries.retain(|e| matches!(e.stage(), 0 | 2));
+    for entry in &mut ours.entries {
+        entry.set_stage(0);
+    }
+    ours
+}
+
+/// The paths `index` has conflicts at, in order.
+pub(crate) fn unmerged_paths(index: &Index) -> Vec<String> {
+    let mut paths: Vec<String> = Vec::new();
+    for entry in index.entries.iter().filter(|e| e.stage() != 0) {
+        if paths.last() != Some(&entry.path) {
+            paths.push(entry.path.clone());
+        }
+    }
+    paths
+}
+
+/// Print the paths `index` has conflicts at, which aren't compared, like git does.
+fn print_unmerged(index: &Index) {
+    for path in unmerged_paths(index) {
+        println!("* Unmerged path {path}");
+    }
+}
+
+/// Show the changes between two sides: with two revisions, between their trees; with one,
+/// from its tree to the work tree (or to the index with `cached`); with none, from the index to
+/// the work tree (or from HEAD to the index with `cached`).
+///
+/// With `exit_code`, finding changes makes the exit status 1. `quiet` prints nothing and only
+/// sets the status.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    old: Option<String>,
+    new: Option<String>,
+    cached: bool,
+    mut opts: DiffOptions,
+    color: Option<ColorWhen>,
+    exit_code: bool,
+    quiet: bool,
+    paginate: bool,
+) -> Result<()> {
+    opts.color = ColorWhen::resolve(color, repo, "diff");
+
+    let mut blobs = BlobCache::new(repo);
+    let mut changes: Vec<Change> = match (old, new) {
+        (Some(old), Some(new)) => {
+            let old = tree_ish(repo, &old)?;
+            let new = tree_ish(repo, &new)?;
+            diff_trees(repo, Some(&old), Some(&new))?
+        }
+        (old, _) => {
+            let index = Index::read(repo)?;
+            print_unmerged(&index);
+            let old = match old {
+                Some(rev) => tree_listing(repo, &tree_ish(repo, &rev)?)?,
+                None if cached => match resolve_head(repo)?.commit() {
+                    Some(head) => tree_listing(repo, &tree_ish(repo, head)?)?,
+                    // before the first commit, everything staged is new
+                    None => Listing::new(),
+                },
+                None => index_listing(&index),
+            };
+            let new = match cached {
+                true => index_listing(&index),
+                false => worktree_listing(repo, &index, &mut blobs)?,
+            };
+            diff_listings(&old, &new)
+        }
+    };
+    if let Some(threshold) = opts.rename_threshold {
+        changes = detect_renames(&mut blobs, changes, threshold)?;
+    }
+
+    if !quiet {
+        paged(paginate, |mut out| {
+            if opts.stat {
+                write_stat(&mut out, &mut blobs, &changes, &opts)
+            } else {
+                write_patch(&mut out, &mut blobs, &changes, &opts)
+            }
+        })?;
+    }
+    if (exit_code || quiet) && !changes.is_empty() {
+        return Err(ExitStatus(1).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use std::fs;
+
+use anyhow::Result;
+
+use crate::{
+    commands::diff::{index_listing, ours_index, unmerged_paths, worktree_listing},
+    diff::{diff_listings, write_raw, BlobCache, RawFormat, Unmerged},
+    index::{mode_from_metadata, Index},
+    objects::Mode,
+    repository::GitRepository,
+};
+
+/// Compare the index with the work tree and print the changes in raw format, as `diff-files`
+/// does. Work tree files aren't stored as blobs, so their side shows the null id. An unmerged
+/// path is shown as `U` with the mode of its work tree file, then compared from "our" version
+/// like a staged file.
+pub(crate) fn invoke(repo: &GitRepository, format: RawFormat, nul: bool) -> Result<()> {
+    let index = Index::read(repo)?;
+    let ours = ours_index(&index);
+    let mut blobs = BlobCache::new(repo);
+    let worktree = worktree_listing(repo, &ours, &mut blobs)?;
+    let mut changes = diff_listings(&index_listing(&ours), &worktree);
+    let null = repo.hash_algo().null().to_string();
+    for new in changes.iter_mut().filter_map(|c| c.new.as_mut()) {
+        new.hash = null.clone();
+    }
+
+    let mut unmerged = Vec::new();
+    for path in unmerged_paths(&index) {
+        let new_mode = match fs::symlink_metadata(repo.work_tree().join(&path)) {
+            Ok(meta) if !meta.is_dir() => {
+                Some(Mode::from_bits(mode_from_metadata(repo, &meta, None)))
+            }
+            _ => None,
+        };
+        unmerged.push(Unmerged {
+            path,
+            old: None,
+            new_mode,
+        });
+    }
+
+    let stdout = std::io::stdout();
+    write_raw(
+        &mut stdout.lock(),
+        repo.hash_algo(),
+        &changes,
+        &unmerged,
+        format,
+        nul,
+    )
+}
@@ -0,0 +1,68 @@
+use anyhow::Result;
+
+use crate::{
+    commands::diff::{index_listing, ours_index, tree_listing, unmerged_paths, worktree_listing},
+    diff::{diff_listings, write_raw, BlobCache, RawFormat, Unmerged},
+    index::Index,
+    objects::tree_ish,
+    repository::GitRepository,
+};
+
+/// Compare the tree of `rev` with the work tree (or with the index, with `cached`) and print the
+/// changes in raw format, as `diff-index` does.
+///
+/// Against the work tree, a file that differs from its staged version has no blob yet and shows
+/// the null id, and an unmerged path is compared through "our" version. Against the index, an
+/// unmerged path is shown as `U` with the tree's side of it.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    rev: String,
+    cached: bool,
+    format: RawFormat,
+    nul: bool,
+) -> Result<()> {
+    let tree = tree_listing(repo, &tree_ish(repo, &rev)?)?;
+    let index = Index::read(repo)?;
+
+    let mut unmerged = Vec::new();
+    let changes = if cached {
+        let conflicted = unmerged_paths(&index);
+        let mut changes = diff_listings(&tree, &index_listing(&index));
+        changes.retain(|c| {
+            conflicted
+                .binary_search_by(|p| p.as_str().cmp(c.path()))
+                .is_err()
+        });
+        for path in conflicted {
+            let old = tree.get(&path).cloned();
+            unmerged.push(Unmerged {
+                path,
+                old,
+                new_mode: None,
+            });
+        }
+        changes
+    } else {
+        let ours = ours_index(&index);
+        let staged = index_listing(&ours);
+        let mut blobs = BlobCache::new(repo);
+        let mut changes = diff_listings(&tree, &worktree_listing(repo, &ours, &mut blobs)?);
+        let null = repo.hash_algo().null().to_string();
+        for new in changes.iter_mut().filter_map(|c| c.new.as_mut()) {
+            if staged.get(&new.path) != Some(&(new.mode, new.hash.clone())) {
+                new.hash = null.clone();
+            }
+        }
+        changes
+    };
+
+    let stdout = std::io::stdout();
+    write_raw(
+        &mut stdout.lock(),
+        repo.hash_algo(),
+        &changes,
+        &unmerged,
+        format,
+        nul,
+    )
+}
@@ -0,0 +1,47 @@
+use std::{fs, io::Write};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    index::{checksum_valid, Index},
+    repository::{repo_file, GitRepository},
+};
+
+/// Print every field of the index header and entries, for debugging the index code.
+pub(crate) fn invoke(repo: &GitRepository) -> Result<()> {
+    let path = repo_file(repo, &["index"], false)?;
+    let buf = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+    let index = Index::parse_unverified(&buf, repo.hash_algo())
+        .with_context(|| format!("parse {}", path.display()))?;
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "version {}", index.version)?;
+    writeln!(stdout, "entries {}", index.entries.len())?;
+    writeln!(
+        stdout,
+        "checksum {} valid {}",
+        hex::encode(&buf[buf.len() - repo.hash_algo().raw_len()..]),
+        checksum_valid(&buf, repo.hash_algo())
+    )?;
+    for e in &index.entries {
+        writeln!(stdout, "{}", e.path)?;
+        writeln!(stdout, "  ctime {}:{}", e.ctime.0, e.ctime.1)?;
+        writeln!(stdout, "  mtime {}:{}", e.mtime.0, e.mtime.1)?;
+        writeln!(stdout, "  dev {} ino {}", e.dev, e.ino)?;
+        writeln!(
+            stdout,
+            "  mode {:o} uid {} gid {} size {}",
+            e.mode, e.uid, e.gid, e.size
+        )?;
+        writeln!(stdout, "  hash {}", e.hash_hex())?;
+        writeln!(
+            stdout,
+            "  flags {:#06x} stage {} extended {:#06x}",
+            e.flags,
+            e.stage(),
+            e.extended_flags
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,630 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{IsTerminal, Write},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::{
+        branch::short_ref_name,
+        remote::{fetch_refspecs, map_refspec, prune_refs, stale_refs, Refspec},
+    },
+    objects::{is_ancestor, object_exists, object_kind, Kind},
+    refs::{ref_list, ref_resolve, ref_update, resolve_head, Head},
+    repository::{repo_path, GitRepository},
+    transport::{fetch_pack, haves, Advertisement, Connection, Service},
+    ExitStatus,
+};
+
+/// How wide the summary column of a ref update is: two abbreviated hashes and `...`.
+const SUMMARY_WIDTH: usize = 17;
+
+/// The narrowest the column of remote ref names gets.
+const REFCOL_WIDTH: usize = 10;
+
+/// How `fetch` (and `clone`) was asked to fetch.
+#[derive(Debug, Default)]
+pub(crate) struct FetchOptions {
+    /// Fetch every tag (`Some(true)`), no tags but those the refspecs name (`Some(false)`), or
+    /// the tags pointing into the history fetched (`None`).
+    pub(crate) tags: Option<bool>,
+    /// Allow updates that aren't fast-forwards for every refspec.
+    pub(crate) force: bool,
+    /// Delete the refs the refspecs map remote refs to that the remote no longer has, instead
+    /// of what `remote.<name>.prune` or `fetch.prune` say.
+    pub(crate) prune: Option<bool>,
+    pub(crate) quiet: bool,
+    /// The program to run instead of `git-upload-pack`.
+    pub(crate) upload_pack: Option<String>,
+}
+
+/// A remote repository: a configured remote (with a `name`) or a bare URL.
+pub(crate) struct Remote {
+    pub(crate) name: Option<String>,
+    pub(crate) url: String,
+    /// The program serving fetches, from `--upload-pack` or `remote.<name>.uploadpack`.
+    pub(crate) upload_pack: Option<String>,
+}
+
+impl Remote {
+    /// The remote `name_or_url` names: a configured remote, or else a URL or path.
+    pub(crate) fn find(git_repo: &GitRepository, name_or_url: &str) -> Self {
+        let section = format!("remote \"{name_or_url}\"");
+        match git_repo.config_get(&section, "url") {
+            Some(url) => Self {
+                name: Some(name_or_url.to_string()),
+                url: url.to_string(),
+                upload_pack: git_repo
+                    .config_get(&section, "uploadpack")
+                    .map(str::to_string),
+            },
+            None => Self {
+                name: None,
+                url: name_or_url.to_string(),
+                upload_pack: None,
+            },
+        }
+    }
+}
+
+/// The remote to use when none is named: the current branch's `branch.<name>.remote`, or
+/// `origin`.
+pub(crate) fn default_remote(git_repo: &GitRepository) -> Result<String> {
+    if let Head::Branch(branch, _) = resolve_head(git_repo)? {
+        let section = format!("branch \"{}\"", short_ref_name(&branch));
+        if let Some(remote) = git_repo.config_get(&section, "remote") {
+            return Ok(remote.to_string());
+        }
+    }
+    Ok("origin".to_string())
+}
+
+/// A ref fetched from the remote, and where it goes.
+#[derive(Debug, Clone)]
+pub(crate) struct Fetched {
+    /// The ref's name on the remote.
+    pub(crate) src: String,
+    pub(crate) hash: String,
+    /// The local ref it updates, if any.
+    pub(crate) dst: Option<String>,
+    pub(crate) force: bool,
+    /// Whether it is a candidate for merging, as `FETCH_HEAD` records.
+    pub(crate) merge: bool,
+}
+
+/// The names a non-pattern refspec `src` may mean, in the order git tries them: as given, under
+/// `refs/`, then as a tag, a branch and a remote-tracking branch.
+fn ref_candidates(src: &str) -> [String; 6] {
+    [
+        src.to_string(),
+        format!("refs/{src}"),
+        format!("refs/tags/{src}"),
+        format!("refs/heads/{src}"),
+        format!("refs/remotes/{src}"),
+        format!("refs/remotes/{src}/HEAD"),
+    ]
+}
+
+/// The prefixes of the remote refs `refspecs` can match, for a protocol v2 server to list only
+/// those: with the tags if `tags`, and always `HEAD`, which tells a clone what to check out.
+pub(crate) fn ref_prefixes(refspecs: &[Refspec], tags: bool) -> Vec<String> {
+    let mut prefixes = vec!["HEAD".to_string()];
+    if tags {
+        prefixes.push("refs/tags/".to_string());
+    }
+    for refspec in refspecs {
+        match refspec.src.split_once('*') {
+            Some((prefix, _)) => prefixes.push(prefix.to_string()),
+            None => prefixes.extend(ref_candidates(&refspec.src)),
+        }
+    }
+    prefixes.sort();
+    prefixes.dedup();
+    prefixes
+}
+
+/// The remote ref a non-pattern refspec `src` names, tried like git does.
+fn find_remote_ref<'a>(advertisement: &'a Advertisement, src: &str) -> Option<(&'a str, &'a str)> {
+    ref_candidates(src).iter().find_map(|name| {
+        advertisement
+            .refs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(n, h)| (n.as_str(), h.as_str()))
+    })
+}
+
+/// Match the advertised refs against `refspecs`. Refspecs from the command line (`explicit`)
+/// are merge candidates and must each find their ref; the remote's configured ones
+/// (`configured`) also update the remote-tracking refs of what the explicit ones fetch.
+pub(crate) fn map_refs(
+    advertisement: &Advertisement,
+    refspecs: &[Refspec],
+    explicit: bool,
+    configured: &[Refspec],
+) -> Result<Vec<Fetched>> {
+    let mut fetched = Vec::<Fetched>::new();
+    let mut add = |item: Fetched| {
+        let duplicate = fetched
+            .iter()
+            .any(|f| f.src == item.src && f.dst == item.dst);
+        if !duplicate {
+            fetched.push(item);
+        }
+    };
+    for refspec in refspecs {
+        if refspec.is_pattern() {
+            let dst = refspec.dst.as_deref().unwrap_or(&refspec.src);
+            for (name, hash) in &advertisement.refs {
+                if name.ends_with("^{}") {
+                    continue;
+                }
+                if let Some(local) = map_refspec(&refspec.src, dst, name) {
+                    add(Fetched {
+                        src: name.clone(),
+                        hash: hash.clone(),
+                        dst: refspec.dst.is_some().then_some(local),
+                        force: refspec.force,
+                        merge: false,
+                    });
+                }
+            }
+            continue;
+        }
+        let Some((name, hash)) = find_remote_ref(advertisement, &refspec.src) else {
+            bail!("couldn't find remote ref {}", refspec.src);
+        };
+        let dst = refspec
+            .dst
+            .as_ref()
+            .filter(|dst| !dst.is_empty())
+            .map(
+                |dst| match (dst.starts_with("refs/"), name.starts_with("refs/tags/")) {
+                    (true, _) => dst.clone(),
+                    (false, true) => format!("refs/tags/{dst}"),
+                    (false, false) => format!("refs/heads/{dst}"),
+                },
+            );
+        add(Fetched {
+            src: name.to_string(),
+            hash: hash.to_string(),
+            dst,
+            force: refspec.force,
+            merge: explicit,
+        });
+        // what a remote branch is fetched into by name, its remote-tracking ref follows too
+        for configured in configured {
+            let Some(tracking) = configured
+                .dst
+                .as_deref()
+                .and_then(|dst| map_refspec(&configured.src, dst, name))
+            else {
+                continue;
+            };
+            add(Fetched {
+                src: name.to_string(),
+                hash: hash.to_string(),
+                dst: Some(tracking),
+                force: configured.force,
+                merge: false,
+            });
+        }
+    }
+    Ok(fetched)
+}
+
+/// The tags worth following: those the remote has that point at objects the repository has
+/// (`peeled` names what each annotated tag tags), and that don't exist here yet.
+fn tags_to_follow(
+    git_repo: &GitRepository,
+    advertisement: &Advertisement,
+    fetched: &[Fetched],
+) -> Result<Vec<Fetched>> {
+    let mut tags = Vec::new();
+    for (name, hash) in &advertisement.refs {
+        if !name.starts_with("refs/tags/") || name.ends_with("^{}") {
+            continue;
+        }
+        if fetched.iter().any(|f| f.src == *name) || ref_resolve(git_repo, name)?.is_some() {
+            continue;
+        }
+        let peeled = advertisement.get(&format!("{name}^{{}}")).unwrap_or(hash);
+        if object_exists(git_repo, peeled)? {
+            tags.push(Fetched {
+                src: name.clone(),
+                hash: hash.clone(),
+                dst: Some(name.clone()),
+                force: false,
+                merge: false,
+            });
+        }
+    }
+    Ok(tags)
+}
+
+/// Fetch the objects of `fetched` that the repository doesn't have over `connection`, whose
+/// advertisement was read, and end the conversation. The remote's progress is shown unless
+/// `quiet`.
+fn fetch_objects(
+    git_repo: &GitRepository,
+    mut connection: Connection,
+    advertisement: &Advertisement,
+    fetched: &[Fetched],
+    quiet: bool,
+) -> Result<()> {
+    let mut wants = Vec::new();
+    for item in fetched {
+        if !wants.contains(&item.hash) && !object_exists(git_repo, &item.hash)? {
+            wants.push(item.hash.clone());
+        }
+    }
+    if wants.is_empty() {
+        return connection.disconnect();
+    }
+    let tips = ref_list(git_repo)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect::<Vec<_>>();
+    let haves = haves(git_repo, &tips)?;
+    // like git, progress is only worth showing on a terminal
+    let progress = !quiet && std::io::stderr().is_terminal();
+    fetch_pack(
+        git_repo,
+        &mut connection,
+        advertisement,
+        &wants,
+        &haves,
+        progress,
+    )?;
+    connection.finish()
+}
+
+/// Connect to `remote` for a fetch and read what it advertises, which is at least its refs
+/// starting with one of `prefixes`.
+pub(crate) fn connect(
+    git_repo: &GitRepository,
+    remote: &Remote,
+    prefixes: &[String],
+) -> Result<(Connection, Advertisement)> {
+    let mut connection = Connection::open(
+        git_repo,
+        &remote.url,
+        Service::UploadPack,
+        remote.upload_pack.as_deref(),
+    )?;
+    let mut advertisement = Advertisement::read(&mut connection.input)?;
+    advertisement.list_refs(git_repo, &mut connection, prefixes)?;
+    Ok((connection, advertisement))
+}
+
+/// `refspecs`, and all tags if `options` asks for them.
+fn with_tags(refspecs: &[Refspec], options: &FetchOptions) -> Vec<Refspec> {
+    let mut refspecs = refspecs.to_vec();
+    if options.tags == Some(true) {
+        refspecs.push(Refspec::parse("+refs/tags/*:refs/tags/*"));
+    }
+    refspecs
+}
+
+/// Fetch the refs `refspecs` name from `remote`, and with them the tags `options` asks for,
+/// and store their objects. Returns what was fetched and the advertisement it came from; the
+/// local refs are left for [`update_refs`].
+pub(crate) fn fetch_refs(
+    git_repo: &GitRepository,
+    remote: &Remote,
+    refspecs: &[Refspec],
+    explicit: bool,
+    options: &FetchOptions,
+) -> Result<(Vec<Fetched>, Advertisement)> {
+    let configured = match &remote.name {
+        Some(name) => fetch_refspecs(git_repo, name),
+        None => Vec::new(),
+    };
+    let refspecs = with_tags(refspecs, options);
+    let prefixes = ref_prefixes(&refspecs, options.tags != Some(false));
+    let (connection, advertisement) = connect(git_repo, remote, &prefixes)?;
+    let mut fetched = map_refs(&advertisement, &refspecs, explicit, &configured)?;
+    fetch_objects(
+        git_repo,
+        connection,
+        &advertisement,
+        &fetched,
+        options.quiet,
+    )?;
+
+    // like git, a tag whose object isn't here yet takes a second fetch
+    if options.tags.is_none() {
+        let tags = tags_to_follow(git_repo, &advertisement, &fetched)?;
+        let mut missing = Vec::new();
+        for tag in &tags {
+            if !object_exists(git_repo, &tag.hash)? {
+                missing.push(tag.clone());
+            }
+        }
+        if !missing.is_empty() {
+            let prefixes = missing
+                .iter()
+                .map(|tag| tag.src.clone())
+                .collect::<Vec<_>>();
+            let (connection, advertisement) = connect(git_repo, remote, &prefixes)?;
+            fetch_objects(
+                git_repo,
+                connection,
+                &advertisement,
+                &missing,
+                options.quiet,
+            )?;
+        }
+        fetched.extend(tags);
+    }
+    Ok((fetched, advertisement))
+}
+
+/// How a local ref changed in a fetch, and how that is shown.
+struct RefUpdateLine {
+    code: char,
+    summary: String,
+    src: String,
+    dst: String,
+    reason: Option<&'static str>,
+}
+
+/// Prints the lines reporting ref updates, after a `From <url>` header.
+pub(crate) struct UpdateReport {
+    url: String,
+    lines: Vec<RefUpdateLine>,
+}
+
+impl UpdateReport {
+    pub(crate) fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            lines: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        code: char,
+        summary: impl Into<String>,
+        src: &str,
+        dst: &str,
+        reason: Option<&'static str>,
+    ) {
+        self.lines.push(RefUpdateLine {
+            code,
+            summary: summary.into(),
+            src: short_ref_name(src).to_string(),
+            dst: short_ref_name(dst).to_string(),
+            reason,
+        });
+    }
+
+    /// Print the report to stderr. Like git, the column of remote names is as wide as the
+    /// longest one, unless that would make its line longer than the terminal.
+    pub(crate) fn print(&self) -> Result<()> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+        let columns = std::env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(80);
+        let width = self
+            .lines
+            .iter()
+            .filter(|line| 21 + line.src.len() + 4 + line.dst.len() < columns)
+            .map(|line| line.src.len())
+            .fold(REFCOL_WIDTH, usize::max);
+        let stderr = std::io::stderr();
+        let mut stderr = stderr.lock();
+        writeln!(stderr, "From {}", self.url)?;
+        for line in &self.lines {
+            write!(
+                stderr,
+                " {} {:<SUMMARY_WIDTH$} {:<width$} -> {}",
+                line.code, line.summary, line.src, line.dst
+            )?;
+            match line.reason {
+                Some(reason) => writeln!(stderr, "  ({reason})")?,
+                None => writeln!(stderr)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Point the local refs of `fetched` at what was fetched, adding a line for each change to
+/// `report`. Fast-forwards are always allowed, other updates only when forced, and an existing
+/// tag is only moved when forced. Returns whether any update was rejected.
+pub(crate) fn update_refs(
+    git_repo: &GitRepository,
+    fetched: &[Fetched],
+    reflog: &str,
+    force: bool,
+    report: &mut UpdateReport,
+) -> Result<bool> {
+    let null = git_repo.hash_algo().null().to_string();
+    let mut rejected = false;
+    for item in fetched {
+        let Some(dst) = &item.dst else {
+            continue;
+        };
+        let old = ref_resolve(git_repo, dst)?;
+        let forced = item.force || force;
+        let (code, summary, reason, action) = match &old {
+            Some(old) if *old == item.hash => continue,
+            None => {
+                let (summary, action) = match item.src.strip_prefix("refs/") {
+                    Some(src) if src.starts_with("tags/") => ("[new tag]", "storing tag"),
+                    Some(src) if src.starts_with("heads/") => ("[new branch]", "storing head"),
+                    _ => ("[new ref]", "storing ref"),
+                };
+                ('*', summary.to_string(), None, action)
+            }
+            Some(_) if dst.starts_with("refs/tags/") => match forced {
+                true => ('t', "[tag update]".to_string(), None, "updating tag"),
+                false => {
+                    report.add(
+                        '!',
+                        "[rejected]",
+                        &item.src,
+                        dst,
+                        Some("would clobber existing tag"),
+                    );
+                    rejected = true;
+                    continue;
+                }
+            },
+            Some(old) => {
+                let commits = object_kind(git_repo, old).is_ok_and(|k| k == Kind::Commit)
+                    && object_kind(git_repo, &item.hash)? == Kind::Commit;
+                let range = |dots| format!("{}{dots}{}", &old[..7], &item.hash[..7]);
+                if commits && is_ancestor(git_repo, old, &item.hash)? {
+                    (' ', range(".."), None, "fast-forward")
+                } else if forced {
+                    ('+', range("..."), Some("forced update"), "forced-update")
+                } else {
+                    report.add('!', "[rejected]", &item.src, dst, Some("non-fast-forward"));
+                    rejected = true;
+                    continue;
+                }
+            }
+        };
+        let expected = old.as_deref().unwrap_or(&null);
+        ref_update(
+            git_repo,
+            dst,
+            &item.hash,
+            Some(expected),
+            &format!("{reflog}: {action}"),
+        )?;
+        report.add(code, summary, &item.src, dst, reason);
+    }
+    Ok(rejected)
+}
+
+/// Record what was fetched in `FETCH_HEAD`, the merge candidates first, for whatever wants to
+/// merge it next.
+pub(crate) fn write_fetch_head(
+    git_repo: &GitRepository,
+    url: &str,
+    fetched: &[Fetched],
+) -> Result<()> {
+    let mut text = String::new();
+    let mut written = HashSet::new();
+    for merge in [true, false] {
+        for item in fetched.iter().filter(|item| item.merge == merge) {
+            if !written.insert(&item.src) {
+                continue;
+            }
+            let marker = if merge { "" } else { "not-for-merge" };
+            let what = if let Some(branch) = item.src.strip_prefix("refs/heads/") {
+                format!("branch '{branch}' of ")
+            } else if let Some(tag) = item.src.strip_prefix("refs/tags/") {
+                format!("tag '{tag}' of ")
+            } else if item.src == "HEAD" {
+                String::new()
+            } else {
+                format!("'{}' of ", item.src)
+            };
+            text.push_str(&format!("{}\t{marker}\t{what}{url}\n", item.hash));
+        }
+    }
+    let path = repo_path(git_repo, &["FETCH_HEAD"])?;
+    fs::write(&path, text)?;
+    Ok(())
+}
+
+/// Mark the fetched branches that `branch.<name>.merge` of the current branch names as merge
+/// candidates, when they come from its remote.
+fn mark_merge_candidates(
+    git_repo: &GitRepository,
+    remote: &Remote,
+    fetched: &mut [Fetched],
+) -> Result<()> {
+    let Head::Branch(branch, _) = resolve_head(git_repo)? else {
+        return Ok(());
+    };
+    let section = format!("branch \"{}\"", short_ref_name(&branch));
+    if git_repo.config_get(&section, "remote") != remote.name.as_deref() {
+        return Ok(());
+    }
+    let merges = git_repo.config_get_all(&section, "merge");
+    for item in fetched.iter_mut() {
+        item.merge = merges.contains(&item.src.as_str());
+    }
+    Ok(())
+}
+
+/// Fetch from `remote` (the current branch's remote, or `origin`, by default) the refs
+/// `refspecs` name, or those its `remote.<name>.fetch` refspecs name, updating the local refs
+/// they map to and writing `FETCH_HEAD`.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    remote: Option<String>,
+    refspecs: Vec<String>,
+    options: FetchOptions,
+) -> Result<()> {
+    let name = match remote {
+        Some(remote) => remote,
+        None => default_remote(repo)?,
+    };
+    let mut remote = Remote::find(repo, &name);
+    if options.upload_pack.is_some() {
+        remote.upload_pack = options.upload_pack.clone();
+    }
+    let explicit = !refspecs.is_empty();
+    let refspecs = match (&remote.name, explicit) {
+        (_, true) => refspecs.iter().map(|spec| Refspec::parse(spec)).collect(),
+        (Some(name), false) => fetch_refspecs(repo, name),
+        (None, false) => vec![Refspec::parse("HEAD")],
+    };
+    let mut options = options;
+    if options.tags.is_none() {
+        let tag_opt = remote
+            .name
+            .as_ref()
+            .and_then(|name| repo.config_get(&format!("remote \"{name}\""), "tagOpt"));
+        options.tags = match tag_opt {
+            Some("--tags") => Some(true),
+            Some("--no-tags") => Some(false),
+            _ => None,
+        };
+    }
+
+    let prune = options
+        .prune
+        .or_else(|| {
+            let name = remote.name.as_ref()?;
+            repo.config_bool(&format!("remote \"{name}\""), "prune")
+        })
+        .or_else(|| repo.config_bool("fetch", "prune"))
+        .unwrap_or(false);
+
+    let (mut fetched, advertisement) = fetch_refs(repo, &remote, &refspecs, explicit, &options)?;
+    if !explicit {
+        mark_merge_candidates(repo, &remote, &mut fetched)?;
+    }
+    write_fetch_head(repo, &remote.url, &fetched)?;
+    let mut report = UpdateReport::new(&remote.url);
+    let reflog = format!("fetch {name}");
+    // pruning first frees the names of deleted refs for new ones that need them
+    if prune {
+        let stale = stale_refs(repo, &advertisement, &with_tags(&refspecs, &options))?;
+        prune_refs(repo, &stale, &format!("{reflog}: pruning"))?;
+        for (name, _) in &stale {
+            report.add('-', "[deleted]", "(none)", name, None);
+        }
+    }
+    let rejected = update_refs(repo, &fetched, &reflog, options.force, &mut report)?;
+    if !options.quiet {
+        report.print()?;
+    }
+    if rejected {
+        return Err(ExitStatus(1).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        branch::{short_ref_name, upstream},
+        commit_tree::kvlm_parse,
+    },
+    date::{format_default, format_iso},
+    ignore::wildmatch,
+    objects::{object_read, subject, GitObject},
+    refs::ref_list,
+    repository::GitRepository,
+    signature::Signature,
+    worktree::path_matches,
+};
+
+pub(crate) const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/// Whether `name` is selected by `pattern`: like git, a pattern matches the refs below it
+/// (`refs/heads` matches `refs/heads/main`) or is a glob matched against the full name.
+fn ref_matches(pattern: &str, name: &str) -> bool {
+    let prefix = pattern.trim_end_matches('/');
+    (!prefix.is_empty() && path_matches(prefix, name))
+        || wildmatch(pattern.as_bytes(), name.as_bytes())
+}
+
+/// A ref with its object read, and for annotated tags the object they point at.
+pub(crate) struct RefInfo {
+    name: String,
+    hash: String,
+    obj: Box<dyn GitObject>,
+    /// For an annotated tag, the tagged object. Like git, only one level of tag is
+    /// dereferenced, so a tag of a tag gives the inner tag.
+    peeled: Option<(String, Box<dyn GitObject>)>,
+}
+
+impl RefInfo {
+    pub(crate) fn read(git_repo: &GitRepository, name: String, hash: String) -> Result<Self> {
+        let obj = object_read(git_repo, &hash)?;
+        let peeled = match obj.format() {
+            "tag" => {
+                let kvlm = kvlm_parse(&obj.serialize())?;
+                let target = kvlm
+                    .get(b"object".as_slice())
+                    .and_then(|v| v.first())
+                    .with_context(|| format!("tag {hash} has no object header"))?;
+                let target = String::from_utf8(target.clone()).context("tag object isn't utf-8")?;
+                let target_obj = object_read(git_repo, &target)?;
+                Some((target, target_obj))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            name,
+            hash,
+            obj,
+            peeled,
+        })
+    }
+
+    /// The object an atom describes: the ref's own, or with `*` the tagged one (`None` when
+    /// the ref isn't an annotated tag).
+    fn object<'a>(&self, atom: &'a str) -> (&'a str, Option<(&str, &dyn GitObject)>) {
+        match atom.strip_prefix('*') {
+            Some(field) => (
+                field,
+                self.peeled
+                    .as_ref()
+                    .map(|(hash, obj)| (hash.as_str(), obj.as_ref())),
+            ),
+            None => (atom, Some((self.hash.as_str(), self.obj.as_ref()))),
+        }
+    }
+
+    /// The `author` or `committer` identity line of a commit.
+    fn ident(obj: &dyn GitObject, role: &str) -> Result<Option<String>> {
+        if obj.format() != "commit" {
+            return Ok(None);
+        }
+        let kvlm = kvlm_parse(&obj.serialize())?;
+        Ok(kvlm
+            .get(role.as_bytes())
+            .and_then(|v| v.first())
+            .map(|v| String::from_utf8_lossy(v).into_owned()))
+    }
+
+    /// The value of `atom` (e.g. `refname:short` or `*objectname`) for this ref.
+    pub(crate) fn value(&self, git_repo: &GitRepository, atom: &str) -> Result<String> {
+        let (field, object) = self.object(atom);
+        let Some((hash, obj)) = object else {
+            return Ok(String::new());
+        };
+        let (field, modifier) = match field.split_once(':') {
+            Some((field, modifier)) => (field, Some(modifier)),
+            None => (field, None),
+        };
+        Ok(match (field, modifier) {
+            ("refname", None) => self.name.clone(),
+            ("refname", Some("short")) => short_ref_name(&self.name).to_string(),
+            ("objectname", None) => hash.to_string(),
+            ("objectname", Some("short")) => hash[..7].to_string(),
+            ("objecttype", None) => obj.format().to_string(),
+            ("subject", None) => match obj.format() {
+                "commit" | "tag" => {
+                    let kvlm = kvlm_parse(&obj.serialize())?;
+                    kvlm.get(b"".as_slice())
+                        .and_then(|v| v.first())
+                        .map(|message| subject(message))
+                        .unwrap_or_default()
+                }
+                _ => String::new(),
+            },
+            ("authordate" | "committerdate", modifier) => {
+                let role = field.trim_end_matches("date");
+                let Some(line) = Self::ident(obj, role)? else {
+                    return Ok(String::new());
+                };
+                let Some(signature) = Signature::parse(&line) else {
+                    return Ok(String::new());
+                };
+                match modifier {
+                    None => format_default(signature.time, &signature.tz()),
+                    Some("iso") => format_iso(signature.time, &signature.tz()),
+                    Some("unix") => signature.time.to_string(),
+                    Some(other) => bail!("unknown date format: {other}"),
+                }
+            }
+            ("upstream", modifier) => match upstream(git_repo, &self.name) {
+                Some(tracking) if modifier == Some("short") => {
+                    short_ref_name(&tracking).to_string()
+                }
+                Some(tracking) if modifier.is_none() => tracking,
+                Some(_) => bail!("unknown upstream modifier: {atom}"),
+                None => String::new(),
+            },
+            _ => bail!("unknown field name: {field}"),
+        })
+    }
+
+    /// What `atom` sorts by: dates by their time, everything else by its text.
+    fn sort_key(&self, git_repo: &GitRepository, atom: &str) -> Result<SortKey> {
+        let (field, object) = self.object(atom);
+        let field = field.split(':').next().unwrap_or(field);
+        if let ("authordate" | "committerdate", Some((_, obj))) = (field, object) {
+            let time = Self::ident(obj, field.trim_end_matches("date"))?
+                .as_deref()
+                .and_then(Signature::parse)
+                .map_or(0, |signature| signature.time);
+            return Ok(SortKey::Time(time));
+        }
+        Ok(SortKey::Text(self.value(git_repo, atom)?))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Time(i64),
+    Text(String),
+}
+
+/// Expand the `%(atom)` placeholders of `format` for `info`. `%%` is a literal `%`.
+///
+/// `%(*atom)` is the atom of the object an annotated tag points at, and empty for refs that
+/// aren't annotated tags.
+fn expand(git_repo: &GitRepository, format: &str, info: &RefInfo) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        if let Some(after) = rest.strip_prefix("%%") {
+            out.push('%');
+            rest = after;
+            continue;
+        }
+        let Some(atom) = rest.strip_prefix("%(") else {
+            out.push('%');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = atom
+            .find(')')
+            .with_context(|| format!("malformed format string {format}"))?;
+        out.push_str(&info.value(git_repo, &atom[..end])?);
+        rest = &atom[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// List the refs matching `pattern`, formatted with `format` and sorted by the `sort` keys (an
+/// atom name, with a leading `-` for descending order). The last key is the primary one, and
+/// refs that tie on every key stay in name order.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    pattern: Option<String>,
+    format: String,
+    sort: Vec<String>,
+) -> Result<()> {
+    let mut refs = Vec::new();
+    for (name, hash) in ref_list(repo)? {
+        if pattern.as_deref().is_some_and(|p| !ref_matches(p, &name)) {
+            continue;
+        }
+        refs.push(RefInfo::read(repo, name, hash)?);
+    }
+
+    let keys = sort
+        .iter()
+        .rev()
+        .map(|key| match key.strip_prefix('-') {
+            Some(atom) => (atom, true),
+            None => (key.as_str(), false),
+        })
+        .collect::<Vec<_>>();
+    if !keys.is_empty() {
+        let mut keyed = refs
+            .into_iter()
+            .map(|info| {
+                let values = keys
+                    .iter()
+                    .map(|(atom, _)| info.sort_key(repo, atom))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok((values, info))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        keyed.sort_by(|(a, _), (b, _)| {
+            keys.iter()
+                .zip(a.iter().zip(b))
+                .map(|((_, descending), (a, b))| match descending {
+                    true => b.cmp(a),
+                    false => a.cmp(b),
+                })
+                .find(|order| order.is_ne())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        refs = keyed.into_iter().map(|(_, info)| info).collect();
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for info in &refs {
+        writeln!(stdout, "{}", expand(repo, &format, info)?)?;
+    }
+    Ok(())
+}
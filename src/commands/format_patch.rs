@@ -0,0 +1,288 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{log::body, rebase::commits_to_replay},
+    date::format_rfc2822,
+    diff::{
+        commit_changes, write_patch, write_stat, write_summary, BlobCache, Change, DiffOptions,
+    },
+    objects::{object_find, read_commit, subject, Commit, ObjectType},
+    repository::GitRepository,
+    signature::Signature,
+};
+
+/// The width the `Subject:` header is wrapped at.
+const SUBJECT_WIDTH: usize = 78;
+
+/// The width of an RFC 2047 encoded header line.
+const ENCODED_WIDTH: usize = 76;
+
+/// The width of the diffstat, whatever the terminal's.
+const STAT_WIDTH: usize = 72;
+
+/// The longest a patch file name gets, `.patch` included.
+const NAME_MAX: usize = 64;
+
+/// The name of the file the `nr`th patch is written to: the number and the subject with
+/// everything but letters, digits, `.` and `_` squeezed into `-`, like
+/// `0001-Fix-the-frobnicator.patch`.
+fn file_name(nr: usize, subject: &str) -> String {
+    let mut name = format!("{nr:04}-");
+    let start = name.len();
+    // 2 before anything was added, 1 after a character that isn't kept
+    let mut space = 2;
+    let mut chars = subject.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space == 1 {
+                name.push('-');
+            }
+            space = 0;
+            name.push(c);
+            while c == '.' && chars.peek() == Some(&'.') {
+                chars.next();
+            }
+        } else {
+            space |= 1;
+        }
+    }
+    while name.len() > start && name.ends_with(['.', '-']) {
+        name.pop();
+    }
+    name.truncate(NAME_MAX - ".patch".len() - 1);
+    name + ".patch"
+}
+
+/// Whether a header value has to be RFC 2047 encoded: it isn't plain ASCII on one line, or it
+/// could be mistaken for an encoded word.
+fn needs_rfc2047(text: &str) -> bool {
+    !text.is_ascii() || text.contains('\n') || text.contains("=?")
+}
+
+/// The length of the last line of `text`.
+fn last_line_len(text: &str) -> usize {
+    text.len() - text.rfind('\n').map_or(0, |at| at + 1)
+}
+
+/// Append `text` to the header in `out` as RFC 2047 encoded words (`=?UTF-8?q?...?=`), starting
+/// a new line when one gets too long. For an `address` (the name in `From:`) fewer characters
+/// are left as they are.
+fn add_rfc2047(out: &mut String, text: &str, address: bool) {
+    const START: &str = "=?UTF-8?q?";
+    let mut line_len = last_line_len(out) + START.len();
+    out.push_str(START);
+    for c in text.chars() {
+        let mut bytes = [0; 4];
+        let bytes = c.encode_utf8(&mut bytes).as_bytes();
+        let special = bytes.len() > 1
+            || !c.is_ascii_graphic()
+            || "=?_".contains(c)
+            || (address && !(c.is_ascii_alphanumeric() || "!*+-/".contains(c)));
+        let len = if special { 3 * bytes.len() } else { 1 };
+        if line_len + 2 + len > ENCODED_WIDTH {
+            out.push_str("?=\n ");
+            out.push_str(START);
+            line_len = START.len() + 1;
+        }
+        if special {
+            for byte in bytes {
+                out.push_str(&format!("={byte:02X}"));
+            }
+        } else {
+            out.push(c);
+        }
+        line_len += len;
+    }
+    out.push_str("?=");
+}
+
+/// Append `text` to the header in `out`, breaking it at whitespace into lines of at most
+/// [`SUBJECT_WIDTH`] columns, continued with a space.
+fn add_wrapped(out: &mut String, text: &str) {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut width = last_line_len(out);
+    // where the current line of `text` starts, and the whitespace before the word being measured
+    let mut line_start = 0;
+    let mut space = Some(0);
+    let mut i = 0;
+    loop {
+        let c = chars.get(i);
+        if c.is_some_and(|c| !c.is_whitespace()) {
+            width += 1;
+            i += 1;
+            continue;
+        }
+        if width <= SUBJECT_WIDTH || space.is_none() {
+            let start = match space {
+                Some(space) => space,
+                None => {
+                    out.push(' ');
+                    line_start
+                }
+            };
+            out.extend(&chars[start..i]);
+            if c.is_none() {
+                return;
+            }
+            space = Some(i);
+            width += 1;
+            i += 1;
+        } else {
+            out.push('\n');
+            let at = space.expect("a line breaks at a space");
+            i = at + usize::from(chars[at].is_whitespace());
+            line_start = i;
+            space = None;
+            width = 1;
+        }
+    }
+}
+
+/// The `From:` header naming `author`, encoded or quoted as the name needs.
+fn from_header(author: &Signature) -> String {
+    let mut header = String::from("From: ");
+    if needs_rfc2047(&author.name) {
+        add_rfc2047(&mut header, &author.name, true);
+    } else if author.name.contains(|c| "()<>@,;:\\\".[]".contains(c)) {
+        let quoted = author.name.replace('\\', "\\\\").replace('"', "\\\"");
+        header.push_str(&format!("\"{quoted}\""));
+    } else {
+        header.push_str(&author.name);
+    }
+    header.push_str(&format!(" <{}>\n", author.email));
+    header
+}
+
+/// How patches show changes: with `binary`, binary files as patches that can be applied, and
+/// the diffstat [`STAT_WIDTH`] wide.
+fn diff_options(binary: bool) -> DiffOptions {
+    DiffOptions {
+        binary,
+        stat_width: Some(STAT_WIDTH),
+        ..DiffOptions::default()
+    }
+}
+
+/// The mail of the `nr`th of `total` patches, the `changes` of `commit`.
+fn write_mail(
+    blobs: &mut BlobCache,
+    hash: &str,
+    commit: &Commit,
+    changes: &[Change],
+    (nr, total): (usize, usize),
+    opts: &DiffOptions,
+) -> Result<Vec<u8>> {
+    let author = Signature::parse(&commit.author)
+        .with_context(|| format!("malformed author in commit {hash}"))?;
+    let title = subject(commit.message.as_bytes());
+    let body = body(&commit.message);
+    let mut header = format!("From {hash} Mon Sep 17 00:00:00 2001\n");
+    header.push_str(&from_header(&author));
+    header.push_str(&format!(
+        "Date: {}\n",
+        format_rfc2822(author.time, &author.tz())
+    ));
+    header.push_str("Subject: ");
+    match total {
+        1 => header.push_str("[PATCH] "),
+        _ => header.push_str(&format!("[PATCH {nr}/{total}] ")),
+    }
+    if needs_rfc2047(&title) {
+        add_rfc2047(&mut header, &title, false);
+    } else {
+        add_wrapped(&mut header, &title);
+    }
+    header.push('\n');
+    if !(author.name.is_ascii() && commit.message.is_ascii()) {
+        header.push_str(
+            "MIME-Version: 1.0\n\
+             Content-Type: text/plain; charset=UTF-8\n\
+             Content-Transfer-Encoding: 8bit\n",
+        );
+    }
+    header.push('\n');
+    if !body.is_empty() {
+        header.push_str(body);
+        if !body.ends_with('\n') {
+            header.push('\n');
+        }
+    }
+    header.push_str("---\n");
+
+    let mut mail = header.into_bytes();
+    write_stat(&mut mail, blobs, changes, opts)?;
+    write_summary(&mut mail, changes)?;
+    writeln!(mail)?;
+    write_patch(&mut mail, blobs, changes, opts)?;
+    write!(mail, "-- \n{}\n\n", env!("CARGO_PKG_VERSION"))?;
+    Ok(mail)
+}
+
+/// Write each commit of `range` as a patch mail in mbox format, ready to be sent or applied
+/// with `am`: `<since>` means the commits since then up to HEAD, `<since>..<until>` the commits
+/// in between, and with `root` the commits reachable from `range` (HEAD by default) are all
+/// written. Merges and commits that change nothing are left out. Changes to binary files are
+/// written as `GIT binary patch`es if `binary`, as git does by default.
+///
+/// Each mail goes to a numbered file named after its subject in `output_dir` (by default
+/// `format.outputDirectory`, or else the current directory), whose path is printed, or with
+/// `stdout` they are all printed.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    range: Option<String>,
+    root: bool,
+    binary: bool,
+    output_dir: Option<PathBuf>,
+    stdout: bool,
+) -> Result<()> {
+    let find = |rev: &str| object_find(repo, rev.to_string(), ObjectType::Commit);
+    let (since, until) = match range.as_deref() {
+        Some(range) if !root => match range.split_once("..") {
+            Some((since, until)) => (
+                Some(find(if since.is_empty() { "HEAD" } else { since })?),
+                find(if until.is_empty() { "HEAD" } else { until })?,
+            ),
+            None => (Some(find(range)?), find("HEAD")?),
+        },
+        Some(range) => (None, find(range)?),
+        None if root => (None, find("HEAD")?),
+        None => bail!("need a revision range, or --root"),
+    };
+
+    let opts = diff_options(binary);
+    let mut blobs = BlobCache::new(repo);
+    let mut patches = Vec::new();
+    for hash in commits_to_replay(repo, &until, since.as_deref())? {
+        let commit = read_commit(repo, &hash)?;
+        let changes = commit_changes(&mut blobs, &commit, opts.rename_threshold)?;
+        if !changes.is_empty() {
+            patches.push((hash, commit, changes));
+        }
+    }
+
+    let output_dir = output_dir.or_else(|| {
+        repo.config_get("format", "outputDirectory")
+            .map(PathBuf::from)
+    });
+    if let Some(dir) = output_dir.as_ref().filter(|_| !stdout) {
+        fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let total = patches.len();
+    for (i, (hash, commit, changes)) in patches.iter().enumerate() {
+        let mail = write_mail(&mut blobs, hash, commit, changes, (i + 1, total), &opts)?;
+        if stdout {
+            std::io::stdout().lock().write_all(&mail)?;
+            continue;
+        }
+        let name = file_name(i + 1, &subject(commit.message.as_bytes()));
+        let path = match &output_dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        };
+        fs::write(&path, mail).with_context(|| format!("write {}", path.display()))?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
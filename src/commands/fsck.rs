@@ -0,0 +1,153 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    commands::commit_tree::kvlm_parse,
+    index::Index,
+    objects::{
+        all_objects, hash_object, object_exists, object_kind, object_read, parse_tree, Commit,
+        Kind, Mode,
+    },
+    refs::{ref_list, resolve_head, Head},
+    repository::GitRepository,
+    ExitStatus,
+};
+
+/// Exit status bits, as git sets them: an object is corrupt, or a reachable one is missing.
+const ERROR_OBJECT: i32 = 1;
+const ERROR_REACHABLE: i32 = 2;
+
+/// An object to visit: the kind it should have if that's known, its hash, and the object
+/// pointing to it.
+type Visit = (Option<Kind>, String, Option<(Kind, String)>);
+
+/// The objects that `data`, the content of an object of `kind`, points to, with the kind each
+/// should have. Gitlinks are left out, as their commits live in other repositories.
+fn links(git_repo: &GitRepository, kind: Kind, data: &[u8]) -> Result<Vec<(Kind, String)>> {
+    Ok(match kind {
+        Kind::Commit => {
+            let commit = Commit::parse(data)?;
+            let parents = commit.parents.into_iter().map(|p| (Kind::Commit, p));
+            std::iter::once((Kind::Tree, commit.tree))
+                .chain(parents)
+                .collect()
+        }
+        Kind::Tree => parse_tree(data, git_repo.hash_algo())?
+            .into_iter()
+            .filter(|e| e.mode != Mode::Gitlink)
+            .map(|e| match e.is_tree() {
+                true => (Kind::Tree, e.hash),
+                false => (Kind::Blob, e.hash),
+            })
+            .collect(),
+        Kind::Tag => {
+            let kvlm = kvlm_parse(data)?;
+            let header = |key: &str| {
+                kvlm.get(key.as_bytes())
+                    .and_then(|v| v.first())
+                    .map(|v| String::from_utf8_lossy(v).into_owned())
+                    .with_context(|| format!("tag has no {key} header"))
+            };
+            vec![(header("type")?.parse()?, header("object")?)]
+        }
+        Kind::Blob => Vec::new(),
+    })
+}
+
+/// The objects the object `sha` of `kind` points to; see [`links`].
+fn read_links(git_repo: &GitRepository, kind: Kind, sha: &str) -> Result<Vec<(Kind, String)>> {
+    let obj = object_read(git_repo, sha)?;
+    links(git_repo, kind, &obj.serialize()).with_context(|| format!("{kind} {sha}"))
+}
+
+/// Check the object store: every object reachable from refs, `HEAD` and the index must exist,
+/// and unreachable objects no other unreachable object points to are reported as dangling.
+/// Unless `connectivity_only`, every object is also read back and rehashed; with it, blobs are
+/// never read, only looked up. Problems make the exit status nonzero.
+pub(crate) fn invoke(repo: &GitRepository, connectivity_only: bool) -> Result<()> {
+    let all = all_objects(repo)?;
+    let mut errors = 0;
+
+    if !connectivity_only {
+        for sha in &all {
+            let checked = object_read(repo, sha).and_then(|obj| {
+                let actual = hash_object(repo.hash_algo(), obj.format().parse()?, &obj.serialize());
+                match &actual == sha {
+                    true => Ok(()),
+                    false => anyhow::bail!("hash mismatch for object {sha} (found {actual})"),
+                }
+            });
+            if let Err(e) = checked {
+                eprintln!("error: {e:#}");
+                errors |= ERROR_OBJECT;
+            }
+        }
+    }
+
+    let mut pending: Vec<Visit> = Vec::new();
+    pending.extend(ref_list(repo)?.into_iter().map(|(_, h)| (None, h, None)));
+    if let Head::Detached(hash) = resolve_head(repo)? {
+        pending.push((None, hash, None));
+    }
+    for entry in Index::read(repo)?.entries {
+        if Mode::from_bits(entry.mode) != Mode::Gitlink {
+            pending.push((Some(Kind::Blob), entry.hash_hex(), None));
+        }
+    }
+    let mut reachable = HashSet::new();
+    while let Some((expected, sha, from)) = pending.pop() {
+        if !reachable.insert(sha.clone()) {
+            continue;
+        }
+        if !object_exists(repo, &sha)? {
+            let kind = expected.map_or("object".to_string(), |kind| kind.to_string());
+            if let Some((from_kind, from)) = from {
+                println!("broken link from {:>7} {from}", from_kind.to_string());
+                println!("              to {kind:>7} {sha}");
+            }
+            println!("missing {kind} {sha}");
+            errors |= ERROR_REACHABLE;
+            continue;
+        }
+        // blobs point to nothing, so they never need reading here
+        if expected == Some(Kind::Blob) {
+            continue;
+        }
+        let found = expected.map_or_else(|| object_kind(repo, &sha), Ok)?;
+        match read_links(repo, found, &sha) {
+            Ok(links) => pending.extend(
+                links
+                    .into_iter()
+                    .map(|(kind, hash)| (Some(kind), hash, Some((found, sha.clone())))),
+            ),
+            Err(e) => {
+                eprintln!("error: {e:#}");
+                errors |= ERROR_OBJECT;
+            }
+        }
+    }
+
+    // only the tips of unreachable history are dangling; what they point to isn't reported
+    let mut unreachable = Vec::new();
+    let mut referenced = HashSet::new();
+    for sha in all.iter().filter(|sha| !reachable.contains(*sha)) {
+        let kind = object_kind(repo, sha)?;
+        if kind != Kind::Blob {
+            if let Ok(links) = read_links(repo, kind, sha) {
+                referenced.extend(links.into_iter().map(|(_, hash)| hash));
+            }
+        }
+        unreachable.push((kind, sha));
+    }
+    for (kind, sha) in unreachable {
+        if !referenced.contains(sha) {
+            println!("dangling {kind} {sha}");
+        }
+    }
+
+    if errors != 0 {
+        return Err(ExitStatus(errors).into());
+    }
+    Ok(())
+}
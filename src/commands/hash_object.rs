@@ -1,20 +1,22 @@
 use std::{
-    io::Write,
-    path::{Path, PathBuf},
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
 };
 
 use anyhow::{Context, Result};
-use sha1::{Digest, Sha1};
 
 use crate::{
-    objects::{object_hash, Object},
-    repository::repo_find,
-    ObjectType,
+    convert::Converter,
+    hash::Hasher,
+    objects::object_hash,
+    objects::ObjectType,
+    repository::{worktree_path, GitRepository},
 };
 
 pub(crate) struct HashWriter<W> {
     pub(crate) writer: W,
-    pub(crate) hasher: Sha1,
+    pub(crate) hasher: Hasher,
 }
 
 impl<W: Write> Write for HashWriter<W> {
@@ -29,29 +31,37 @@ impl<W: Write> Write for HashWriter<W> {
     }
 }
 
-pub(crate) fn invoke(write: bool, file: &Path) -> Result<()> {
-    let object = Object::blob_from_file(file).context("open blob input file")?;
-    let hash = if write {
-        object
-            .write_to_objects()
-            .context("write blob object to .git/objects")?
-    } else {
-        let hash = object
-            .write(std::io::sink())
-            .context("write out blob object")?;
-        hash
+/// Hash `file`, or standard input when it is `None`, as an object of `object_type`, writing it
+/// with `write`. A blob is hashed as staging it would store it: converted by the filters and
+/// line ending settings for `path`, or for `file` itself, unless `no_filters`.
+pub(crate) fn cmd_hash_object(
+    repo: Option<&GitRepository>,
+    write: bool,
+    object_type: ObjectType,
+    file: Option<PathBuf>,
+    path: Option<String>,
+    no_filters: bool,
+) -> Result<()> {
+    let mut data = match &file {
+        Some(file) => fs::read(file).with_context(|| format!("read {}", file.display()))?,
+        None => {
+            let mut data = Vec::new();
+            std::io::stdin()
+                .read_to_end(&mut data)
+                .context("read stdin")?;
+            data
+        }
     };
-    println!("{}", hex::encode(hash));
-    Ok(())
-}
-
-pub(crate) fn cmd_hash_object(write: bool, object_type: ObjectType, file: PathBuf) -> Result<()> {
-    let mut repo = None;
-    if write {
-        repo = Some(repo_find(".", true)?);
+    let path = path.or_else(|| Some(file?.to_str()?.to_string()));
+    let filtered = matches!(object_type, ObjectType::Blob) && !no_filters;
+    if let (Some(repo), Some(path), true) = (repo, path, filtered) {
+        // a file outside the work tree has no attributes
+        if let Ok(path) = worktree_path(repo, &path) {
+            data = Converter::new(repo)?.convert_to_git(&path, data)?;
+        }
     }
 
-    let data = object_hash(repo, file, object_type)?;
-    println!("{:?}", data);
+    let hash = object_hash(repo, write, &data, object_type)?;
+    println!("{}", hex::encode(hash));
     Ok(())
 }
@@ -1,14 +1,34 @@
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-use crate::repository::repo_create;
+use crate::{hash::HashAlgo, repository::repo_create};
 
-pub(crate) fn cmd_init<P: AsRef<Path>>(path: Option<P>) -> Result<()> {
-    if let Some(p) = path {
-        repo_create(p)?;
-    } else {
-        repo_create(".")?;
+/// Create an empty repository at `path` (the current directory by default), or reinitialize
+/// the one already there, saying which and where unless `quiet`. A new repository names its
+/// objects with `object_format`.
+pub(crate) fn cmd_init<P: AsRef<Path>>(
+    path: Option<P>,
+    quiet: bool,
+    object_format: Option<HashAlgo>,
+) -> Result<()> {
+    let (repo, existed) = match path {
+        Some(p) => repo_create(p, object_format)?,
+        None => repo_create(".", object_format)?,
+    };
+    if !quiet {
+        let work_tree = repo
+            .work_tree()
+            .canonicalize()
+            .with_context(|| format!("resolve {}", repo.work_tree().display()))?;
+        let git_dir = work_tree.join(".git");
+        match existed {
+            true => println!(
+                "Reinitialized existing Git repository in {}/",
+                git_dir.display()
+            ),
+            false => println!("Initialized empty Git repository in {}/", git_dir.display()),
+        }
     }
     Ok(())
 }
@@ -0,0 +1,36 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::trailer::{add_trailer, parse_trailers, Trailer};
+
+pub(crate) fn invoke(file: Option<PathBuf>, parse: bool, trailers: Vec<String>) -> Result<()> {
+    let message = match &file {
+        Some(file) => {
+            std::fs::read_to_string(file).with_context(|| format!("read {}", file.display()))?
+        }
+        None => {
+            let mut message = String::new();
+            std::io::stdin()
+                .read_to_string(&mut message)
+                .context("read message from stdin")?;
+            message
+        }
+    };
+
+    if parse {
+        for trailer in parse_trailers(&message) {
+            println!("{trailer}");
+        }
+        return Ok(());
+    }
+
+    let mut message = message;
+    for trailer in &trailers {
+        let trailer =
+            Trailer::parse(trailer).with_context(|| format!("invalid trailer {trailer:?}"))?;
+        message = add_trailer(&message, &trailer);
+    }
+    print!("{message}");
+    Ok(())
+}
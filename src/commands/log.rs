@@ -0,0 +1,540 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{
+    color::ColorWhen,
+    commands::notes::{read_note, read_notes},
+    date::DateFormat,
+    decorate::{ref_index, RefIndex},
+    diff::{commit_changes, write_patch, write_stat, BlobCache, DiffOptions},
+    mailmap::Mailmap,
+    objects::{object_find, subject, Commit, ObjectType},
+    pager::paged,
+    repository::{worktree_path, GitRepository},
+    revwalk::{topo_sort, RevWalk},
+    signature::Signature,
+};
+
+/// Length of abbreviated hashes.
+const ABBREV: usize = 7;
+
+/// How each commit is printed.
+enum Format {
+    /// git's default: hash, author, date and the indented message.
+    Medium,
+    /// The hash (abbreviated with `abbrev`) and subject on one line.
+    Oneline { abbrev: bool },
+    /// A format string, with a newline after (`tformat:`) or between (`format:`) commits.
+    Custom { format: String, terminator: bool },
+    /// A [`JsonCommit`] object per line, for tools.
+    Json,
+}
+
+impl Format {
+    fn parse(format: Option<&str>, abbrev: bool) -> Self {
+        match format {
+            None | Some("medium") => Self::Medium,
+            Some("oneline") => Self::Oneline { abbrev },
+            Some("json") => Self::Json,
+            Some(format) => match format.strip_prefix("format:") {
+                Some(format) => Self::Custom {
+                    format: format.to_string(),
+                    terminator: false,
+                },
+                None => Self::Custom {
+                    format: format
+                        .strip_prefix("tformat:")
+                        .unwrap_or(format)
+                        .to_string(),
+                    terminator: true,
+                },
+            },
+        }
+    }
+}
+
+/// A commit as `--format=json` prints it. The field names are kept stable for tools reading
+/// them.
+#[derive(Serialize)]
+struct JsonCommit<'a> {
+    hash: &'a str,
+    parents: &'a [String],
+    author: JsonIdent,
+    committer: JsonIdent,
+    message: &'a str,
+    /// The message's trailers, as `[key, value]` pairs.
+    trailers: Vec<(String, String)>,
+}
+
+/// An author or committer of a [`JsonCommit`]: the identity, the unix time and the time zone
+/// offset (like `+0100`).
+#[derive(Serialize)]
+struct JsonIdent {
+    name: String,
+    email: String,
+    time: i64,
+    tz: String,
+}
+
+/// The message after the subject paragraph.
+pub(crate) fn body(message: &str) -> &str {
+    let message = message.trim_start_matches('\n');
+    match message.find("\n\n") {
+        Some(at) => message[at..].trim_start_matches('\n'),
+        None => "",
+    }
+}
+
+/// Prints commits, mapping identities through the mailmap where asked to.
+struct Printer<'a> {
+    mailmap: Option<&'a Mailmap>,
+    /// The note blob of each annotated commit, when notes are shown.
+    notes: Option<HashMap<String, String>>,
+    /// The refs to decorate commits with, when they are shown (`%d` and `%D` always show them).
+    decorations: Option<&'a RefIndex>,
+    /// How dates are shown, and the unix time relative ones count back from.
+    date: DateFormat,
+    now: i64,
+    git_repo: &'a GitRepository,
+}
+
+impl Printer<'_> {
+    /// The refs pointing at `hash`, as ` (HEAD -> main, tag: v1)`, or nothing when decorations
+    /// aren't shown or there are none.
+    fn decoration(&self, hash: &str) -> String {
+        let Some(index) = self.decorations else {
+            return String::new();
+        };
+        match index.decorations(hash).join(", ") {
+            names if names.is_empty() => names,
+            names => format!(" ({names})"),
+        }
+    }
+
+    /// The `(name, email)` of `ident`, through the mailmap if `mapped` and one is in use.
+    fn identity(&self, signature: &Signature, mapped: bool) -> (String, String) {
+        match self.mailmap.filter(|_| mapped) {
+            Some(mailmap) => mailmap.map_identity(&signature.name, &signature.email),
+            None => (signature.name.clone(), signature.email.clone()),
+        }
+    }
+
+    /// Expand the `%` placeholders of `format` for `commit`. Unknown placeholders are copied
+    /// as they are.
+    fn expand(&self, format: &str, hash: &str, commit: &Commit) -> String {
+        let author = Signature::parse(&commit.author).unwrap_or_default();
+        let committer = Signature::parse(&commit.committer).unwrap_or_default();
+
+        let mut out = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            let Some(spec) = chars.next() else {
+                out.push('%');
+                break;
+            };
+            let ident = match spec {
+                'a' => Some(&author),
+                'c' => Some(&committer),
+                _ => None,
+            };
+            if let Some(ident) = ident {
+                let field = chars.peek().copied();
+                let expanded = match field {
+                    Some('n') => Some(self.identity(ident, false).0),
+                    Some('e') => Some(self.identity(ident, false).1),
+                    Some('N') => Some(self.identity(ident, true).0),
+                    Some('E') => Some(self.identity(ident, true).1),
+                    Some('d') => Some(ident.date(self.date, self.now)),
+                    Some('t') => Some(ident.time.to_string()),
+                    _ => None,
+                };
+                match expanded {
+                    Some(expanded) => {
+                        chars.next();
+                        out.push_str(&expanded);
+                    }
+                    None => {
+                        out.push('%');
+                        out.push(spec);
+                    }
+                }
+                continue;
+            }
+            match spec {
+                'd' | 'D' => {
+                    let names = ref_index(self.git_repo)
+                        .map(|index| index.decorations(hash).join(", "))
+                        .unwrap_or_default();
+                    match spec {
+                        'd' if !names.is_empty() => out.push_str(&format!(" ({names})")),
+                        _ => out.push_str(&names),
+                    }
+                }
+                'H' => out.push_str(hash),
+                'h' => out.push_str(&hash[..ABBREV]),
+                'T' => out.push_str(&commit.tree),
+                't' => out.push_str(&commit.tree[..ABBREV]),
+                'P' => out.push_str(&commit.parents.join(" ")),
+                'p' => out.push_str(
+                    &commit
+                        .parents
+                        .iter()
+                        .map(|p| &p[..ABBREV])
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+                's' => out.push_str(&subject(commit.message.as_bytes())),
+                'b' => out.push_str(body(&commit.message)),
+                'B' => out.push_str(&commit.message),
+                'n' => out.push('\n'),
+                '%' => out.push('%'),
+                other => {
+                    out.push('%');
+                    out.push(other);
+                }
+            }
+        }
+        out
+    }
+
+    /// Print `commit` as a line of JSON, with identities through the mailmap.
+    fn json(&self, out: &mut impl Write, hash: &str, commit: &Commit) -> Result<()> {
+        let ident = |line| {
+            let signature = Signature::parse(line).unwrap_or_default();
+            let (name, email) = self.identity(&signature, true);
+            JsonIdent {
+                name,
+                email,
+                time: signature.time,
+                tz: signature.tz(),
+            }
+        };
+        let commit = JsonCommit {
+            hash,
+            parents: &commit.parents,
+            author: ident(&commit.author),
+            committer: ident(&commit.committer),
+            message: &commit.message,
+            trailers: commit.trailers(),
+        };
+        serde_json::to_writer(&mut *out, &commit)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn medium(&self, out: &mut impl Write, hash: &str, commit: &Commit) -> Result<()> {
+        writeln!(out, "commit {hash}{}", self.decoration(hash))?;
+        if commit.parents.len() > 1 {
+            let parents = commit
+                .parents
+                .iter()
+                .map(|p| &p[..ABBREV])
+                .collect::<Vec<_>>();
+            writeln!(out, "Merge: {}", parents.join(" "))?;
+        }
+        if let Some(author) = Signature::parse(&commit.author) {
+            let (name, email) = self.identity(&author, true);
+            writeln!(out, "Author: {name} <{email}>")?;
+            writeln!(out, "Date:   {}", author.date(self.date, self.now))?;
+        }
+        writeln!(out)?;
+        for line in commit.message.trim_end_matches('\n').lines() {
+            writeln!(out, "    {line}")?;
+        }
+        if let Some(blob) = self.notes.as_ref().and_then(|notes| notes.get(hash)) {
+            writeln!(out, "\nNotes:")?;
+            for line in read_note(self.git_repo, blob)?
+                .trim_end_matches('\n')
+                .lines()
+            {
+                writeln!(out, "    {line}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The date format from `date`, or else the `log.date` config.
+fn date_format(git_repo: &GitRepository, date: Option<&str>) -> Result<DateFormat> {
+    match date.or_else(|| git_repo.config_get("log", "date")) {
+        Some(name) => DateFormat::parse(name),
+        None => Ok(DateFormat::Default),
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Print `commit` in `log`'s default format, for commands that show a single commit.
+pub(crate) fn write_medium(
+    git_repo: &GitRepository,
+    out: &mut impl Write,
+    hash: &str,
+    commit: &Commit,
+) -> Result<()> {
+    let mailmap = Mailmap::load(git_repo)?;
+    let printer = Printer {
+        mailmap: Some(&mailmap),
+        notes: None,
+        decorations: None,
+        date: date_format(git_repo, None)?,
+        now: now(),
+        git_repo,
+    };
+    printer.medium(out, hash, commit)
+}
+
+/// Which of the walked commits `log` shows, and in what order.
+#[derive(Debug, Default)]
+pub(crate) struct CommitFilter {
+    /// Only commits that change these paths (relative to the current directory).
+    pub(crate) paths: Vec<String>,
+    /// Follow the single path back across renames.
+    pub(crate) follow: bool,
+    /// Only follow the first parent of merges.
+    pub(crate) first_parent: bool,
+    pub(crate) max_count: Option<usize>,
+    /// Only commits made (by committer date) at or after this unix time. The walk stops at
+    /// older commits.
+    pub(crate) since: Option<i64>,
+    /// Only commits made at or before this unix time.
+    pub(crate) until: Option<i64>,
+    /// Only commits whose author `Name <email>` matches one of these.
+    pub(crate) authors: Vec<Regex>,
+    /// Only commits whose message matches one of these.
+    pub(crate) greps: Vec<Regex>,
+    /// Only merges (`Some(true)`) or only non-merges (`Some(false)`).
+    pub(crate) merges: Option<bool>,
+    /// Show children after their parents, oldest first.
+    pub(crate) reverse: bool,
+    /// Never show a parent before its children, even when commit dates say otherwise.
+    pub(crate) topo_order: bool,
+}
+
+impl CommitFilter {
+    fn matches(&self, commit: &Commit) -> bool {
+        let time = commit.commit_time();
+        if self.since.is_some_and(|since| time < since)
+            || self.until.is_some_and(|until| time > until)
+        {
+            return false;
+        }
+        if self
+            .merges
+            .is_some_and(|merges| merges != (commit.parents.len() > 1))
+        {
+            return false;
+        }
+        if !self.authors.is_empty() {
+            // the identity without its date
+            let author = commit
+                .author
+                .rfind('>')
+                .map_or(commit.author.as_str(), |end| &commit.author[..=end]);
+            if !self.authors.iter().any(|re| re.is_match(author)) {
+                return false;
+            }
+        }
+        self.greps.is_empty() || self.greps.iter().any(|re| re.is_match(&commit.message))
+    }
+}
+
+/// How `log` and `show` print commits.
+#[derive(Debug, Default)]
+pub(crate) struct PrettyOptions {
+    /// `medium` by default, or another format `--format` names.
+    pub(crate) format: Option<String>,
+    /// Abbreviate the hashes of the `oneline` format, as `--oneline` does.
+    pub(crate) abbrev: bool,
+    /// How dates are shown, by default as the `log.date` config says.
+    pub(crate) date: Option<String>,
+    pub(crate) use_mailmap: bool,
+    pub(crate) show_notes: bool,
+    /// Whether the refs pointing at each commit are shown, by default as the `log.decorate`
+    /// config says.
+    pub(crate) decorate: Option<bool>,
+    /// Follow each commit with the changes it made to its first parent, as a patch or (with
+    /// `stat`) a summary.
+    pub(crate) diff: Option<DiffOptions>,
+    /// Show the changes of merges too, as `show` does: their summary against the first parent,
+    /// and an empty patch. Combined diffs aren't implemented, so that is only what git shows
+    /// for a merge that resolved no conflicts. Otherwise merges show no changes, like `log`.
+    pub(crate) merge_changes: bool,
+    /// Whether the changes are colored, by default as `color.diff` and `color.ui` say.
+    pub(crate) color: Option<ColorWhen>,
+}
+
+/// Show the commits reachable from `revs` that pass `filter`, printed as `pretty` says.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    revs: Vec<String>,
+    filter: CommitFilter,
+    pretty: PrettyOptions,
+    paginate: bool,
+) -> Result<()> {
+    if filter.follow && filter.paths.len() != 1 {
+        bail!("--follow requires exactly one pathspec");
+    }
+    let mut walk = start_walk(repo, revs)?;
+    if filter.first_parent {
+        walk.first_parent_only();
+    }
+    if !filter.paths.is_empty() {
+        let paths = filter
+            .paths
+            .iter()
+            .map(|path| worktree_path(repo, path))
+            .collect::<Result<_>>()?;
+        walk.limit_to_paths(paths, filter.follow);
+    }
+    if let Some(since) = filter.since {
+        walk.stop_before(since);
+    }
+    let commits: Box<dyn Iterator<Item = Result<(String, Commit)>>> = match filter.topo_order {
+        true => Box::new(topo_sort(walk.collect::<Result<_>>()?).into_iter().map(Ok)),
+        false => Box::new(walk),
+    };
+    let commits = commits
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .map_or(true, |(_, commit)| filter.matches(commit))
+        })
+        .take(filter.max_count.unwrap_or(usize::MAX));
+    let commits: Box<dyn Iterator<Item = Result<(String, Commit)>>> = match filter.reverse {
+        true => Box::new(commits.collect::<Vec<_>>().into_iter().rev()),
+        false => Box::new(commits),
+    };
+    write_commits(repo, commits, pretty, paginate)
+}
+
+/// Print `commits` one after the other as `pretty` says.
+pub(crate) fn write_commits(
+    repo: &GitRepository,
+    commits: impl Iterator<Item = Result<(String, Commit)>>,
+    mut pretty: PrettyOptions,
+    paginate: bool,
+) -> Result<()> {
+    let mailmap = if pretty.use_mailmap {
+        Some(Mailmap::load(repo)?)
+    } else {
+        None
+    };
+    let notes = match pretty.show_notes {
+        true => Some(read_notes(repo)?.into_iter().collect()),
+        false => None,
+    };
+    let decorate = match pretty.decorate {
+        Some(decorate) => decorate,
+        None => matches!(
+            repo.config_get("log", "decorate"),
+            Some("true" | "short" | "yes" | "on" | "1")
+        ),
+    };
+    let decorations = match decorate {
+        true => Some(ref_index(repo)?),
+        false => None,
+    };
+    let printer = Printer {
+        mailmap: mailmap.as_ref(),
+        notes,
+        decorations,
+        date: date_format(repo, pretty.date.as_deref())?,
+        now: now(),
+        git_repo: repo,
+    };
+    let format = Format::parse(pretty.format.as_deref(), pretty.abbrev);
+    if let Some(opts) = &mut pretty.diff {
+        opts.color = ColorWhen::resolve(pretty.color, repo, "diff");
+    }
+    let mut blobs = BlobCache::new(repo);
+
+    paged(paginate, |mut out| {
+        let mut first = true;
+        for entry in commits {
+            let (hash, commit) = entry?;
+            match &format {
+                Format::Medium => {
+                    if !first {
+                        writeln!(out)?;
+                    }
+                    printer.medium(&mut out, &hash, &commit)?;
+                }
+                Format::Oneline { abbrev } => {
+                    writeln!(
+                        out,
+                        "{}{} {}",
+                        if *abbrev { &hash[..ABBREV] } else { &hash },
+                        printer.decoration(&hash),
+                        subject(commit.message.as_bytes())
+                    )?;
+                }
+                Format::Json => printer.json(&mut out, &hash, &commit)?,
+                Format::Custom { format, terminator } => {
+                    if !first && !terminator {
+                        writeln!(out)?;
+                    }
+                    write!(out, "{}", printer.expand(format, &hash, &commit))?;
+                    if *terminator {
+                        writeln!(out)?;
+                    }
+                }
+            }
+            first = false;
+
+            let Some(opts) = &pretty.diff else {
+                continue;
+            };
+            let merge = commit.parents.len() > 1;
+            if merge && !pretty.merge_changes {
+                continue;
+            }
+            let changes = match merge && !opts.stat {
+                true => Vec::new(),
+                false => commit_changes(&mut blobs, &commit, opts.rename_threshold)?,
+            };
+            if changes.is_empty() && !merge {
+                continue;
+            }
+            // a blank line parts the message from the changes, except on one line; a merge's
+            // empty patch is that line alone
+            if changes.is_empty() || !matches!(format, Format::Oneline { .. }) {
+                writeln!(out)?;
+            }
+            match opts.stat {
+                true => write_stat(&mut out, &mut blobs, &changes, opts)?,
+                false => write_patch(&mut out, &mut blobs, &changes, opts)?,
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Start a walk from `revs`, or from HEAD when there are none.
+pub(crate) fn start_walk(git_repo: &GitRepository, revs: Vec<String>) -> Result<RevWalk<'_>> {
+    let mut walk = RevWalk::new(git_repo);
+    if revs.is_empty() {
+        let Ok(head) = object_find(git_repo, "HEAD".to_string(), ObjectType::Commit) else {
+            bail!("your current branch does not have any commits yet");
+        };
+        walk.push(&head)?;
+    }
+    for rev in revs {
+        walk.push(&object_find(git_repo, rev, ObjectType::Commit)?)?;
+    }
+    Ok(walk)
+}
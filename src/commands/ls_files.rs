@@ -0,0 +1,185 @@
+use std::io::{BufWriter, Write};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::status::relative,
+    diff::quote_path,
+    index::{worktree_state, Index, IndexEntry, WorktreeState},
+    objects::Mode,
+    repository::{worktree_path, GitRepository},
+    worktree::{parallel_map, path_matches, Ignored, WorktreeWalker},
+    ExitStatus,
+};
+
+/// Which files `ls-files` shows. With none of `cached`, `others`, `modified` and `deleted`
+/// given, the index is listed.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Show {
+    pub(crate) cached: bool,
+    pub(crate) others: bool,
+    /// Show only ignored files among the others.
+    pub(crate) ignored: bool,
+    /// Leave the files the standard ignore rules match out of the others.
+    pub(crate) exclude_standard: bool,
+    pub(crate) modified: bool,
+    pub(crate) deleted: bool,
+}
+
+/// The directory (`""` or ending in `/`) below which every path `specs` match lies, where the
+/// walk for untracked files can start.
+fn walk_root(repo: &GitRepository, specs: &[String]) -> String {
+    let dirs = specs
+        .iter()
+        .map(|spec| match repo.work_tree().join(spec).is_dir() {
+            true => spec.as_str(),
+            false => spec.rsplit_once('/').map_or("", |(dir, _)| dir),
+        });
+    let mut common: Option<Vec<&str>> = None;
+    for dir in dirs {
+        let components = dir.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+        common = Some(match common {
+            None => components,
+            Some(common) => common
+                .into_iter()
+                .zip(components)
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    match common.unwrap_or_default().join("/") {
+        root if root.is_empty() => root,
+        root => format!("{root}/"),
+    }
+}
+
+/// `git ls-files`: list the files that `show` asks for below `paths` (the current directory by
+/// default), relative to the current directory. Files are listed in path order: untracked ones
+/// first, then for each index entry whether it is cached, deleted and modified, so an entry may
+/// be listed more than once.
+///
+/// Untracked files come from a walk of the work tree that skips ignored directories unless
+/// ignored files are asked for. Deleted and modified files are found from the index's stat data,
+/// hashing only the files whose stat data changed. With `error_unmatch`, a path that matches
+/// no listed file makes the command fail. With `nul`, paths end in NUL and aren't quoted.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    paths: Vec<String>,
+    mut show: Show,
+    error_unmatch: bool,
+    nul: bool,
+) -> Result<()> {
+    if show.ignored && !show.others {
+        bail!("ls-files -i must be used with -o");
+    }
+    if show.ignored && !show.exclude_standard {
+        bail!("ls-files --ignored needs some exclude pattern");
+    }
+    if !(show.others || show.modified || show.deleted) {
+        show.cached = true;
+    }
+    let cwd = worktree_path(repo, ".")?;
+    let specs = match paths.is_empty() {
+        true => vec![cwd.clone()],
+        false => paths
+            .iter()
+            .map(|path| worktree_path(repo, path))
+            .collect::<Result<_>>()?,
+    };
+    let wanted = |path: &str| specs.iter().any(|spec| path_matches(spec, path));
+
+    let index = Index::read(repo)?;
+    let mut listed = Vec::new();
+    if show.others {
+        let ignored = match (show.ignored, show.exclude_standard) {
+            (true, _) => Ignored::Only,
+            (false, true) => Ignored::Skip,
+            (false, false) => Ignored::Include,
+        };
+        let root = walk_root(repo, &specs);
+        let root = match repo.work_tree().join(&root).is_dir() {
+            true => root,
+            false => String::new(),
+        };
+        let files = WorktreeWalker::new(repo)
+            .parallel(true)
+            .ignored(ignored)
+            .walk(&root)?;
+        for file in files {
+            if wanted(&file.path) && index.get(&file.path).is_none() {
+                // a nested repository is shown as the directory it is
+                match file.metadata.is_dir() {
+                    true => listed.push(format!("{}/", file.path)),
+                    false => listed.push(file.path),
+                }
+            }
+        }
+    }
+
+    let entries = index
+        .entries
+        .iter()
+        .filter(|e| wanted(&e.path))
+        .collect::<Vec<_>>();
+    let states = match show.modified || show.deleted {
+        true => parallel_map(&entries, |entry| state(repo, entry))?,
+        false => entries.iter().map(|_| None).collect(),
+    };
+    for (entry, state) in entries.into_iter().zip(states) {
+        if show.cached {
+            listed.push(entry.path.clone());
+        }
+        if show.deleted && matches!(state, Some(WorktreeState::Deleted)) {
+            listed.push(entry.path.clone());
+        }
+        if show.modified
+            && matches!(
+                state,
+                Some(WorktreeState::Deleted | WorktreeState::Modified(..))
+            )
+        {
+            listed.push(entry.path.clone());
+        }
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for path in &listed {
+        let path = relative(path, &cwd);
+        match nul {
+            true => write!(out, "{path}\0")?,
+            false => writeln!(out, "{}", quote_path(&path, false))?,
+        }
+    }
+    out.flush()?;
+
+    if error_unmatch {
+        for (spec, path) in specs.iter().zip(&paths) {
+            if !listed
+                .iter()
+                .any(|listed| path_matches(spec, listed.trim_end_matches('/')))
+            {
+                eprintln!(
+                    "error: pathspec '{path}' did not match any file(s) known to git\n\
+                     Did you forget to 'git add'?"
+                );
+                return Err(ExitStatus(1).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// How the work tree file of `entry` compares to it, or `None` for entries whose file isn't
+/// looked at: those outside the sparse checkout, and submodules, which are only deleted when
+/// their directory is gone.
+fn state(repo: &GitRepository, entry: &IndexEntry) -> Result<Option<WorktreeState>> {
+    if entry.skip_worktree() {
+        return Ok(None);
+    }
+    if Mode::from_bits(entry.mode) == Mode::Gitlink {
+        return Ok((!repo.work_tree().join(&entry.path).is_dir()).then_some(WorktreeState::Deleted));
+    }
+    worktree_state(repo, entry).map(Some)
+}
@@ -0,0 +1,45 @@
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::{
+    commands::{
+        commit_tree::kvlm_parse,
+        fetch::{connect, Remote},
+    },
+    objects::object_read,
+    repository::GitRepository,
+};
+
+/// If `hash` is an annotated tag, follow it (and any tags it points at) to the tagged object.
+pub(crate) fn peel_tag(git_repo: &GitRepository, hash: &str) -> Result<Option<String>> {
+    let mut hash = hash.to_string();
+    let mut peeled = false;
+    loop {
+        let obj = object_read(git_repo, &hash)?;
+        if obj.format() != "tag" {
+            return Ok(peeled.then_some(hash));
+        }
+        let kvlm = kvlm_parse(&obj.serialize())?;
+        let target = kvlm
+            .get(b"object".as_slice())
+            .and_then(|v| v.first())
+            .with_context(|| format!("tag {hash} has no object header"))?;
+        hash = String::from_utf8(target.clone()).context("tag object isn't utf-8")?;
+        peeled = true;
+    }
+}
+
+/// List the refs of `remote`, a remote name or a URL, as its upload-pack advertises them.
+pub(crate) fn invoke(repo: &GitRepository, remote: String) -> Result<()> {
+    let remote = Remote::find(repo, &remote);
+    let (connection, advertisement) = connect(repo, &remote, &[])?;
+    connection.disconnect()?;
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (name, hash) in &advertisement.refs {
+        writeln!(stdout, "{hash}\t{name}")?;
+    }
+    Ok(())
+}
@@ -2,15 +2,19 @@ use anyhow::{Context, Result};
 use std::ffi::CStr;
 use std::io::{BufRead, Read, Write};
 
-use crate::objects::{Kind, Object};
+use crate::{
+    objects::{tree_ish, Kind, Mode, Object},
+    repository::GitRepository,
+};
 
-pub fn invoke(name_only: bool, tree_hash: String) -> Result<()> {
-    let mut object = Object::read(&tree_hash).context("parse out tree object file")?;
+pub fn invoke(repo: &GitRepository, name_only: bool, tree_ish_name: String) -> Result<()> {
+    let tree_hash = tree_ish(repo, &tree_ish_name)?;
+    let mut object = Object::read(repo, &tree_hash).context("parse out tree object file")?;
 
     match object.kind {
         Kind::Tree => {
             let mut buf = Vec::new();
-            let mut hash_buf = [0; 20];
+            let mut hash_buf = vec![0; repo.hash_algo().raw_len()];
             let stdout = std::io::stdout();
             let mut stdout = stdout.lock();
             loop {
@@ -37,21 +41,26 @@ pub fn invoke(name_only: bool, tree_hash: String) -> Result<()> {
                         .write_all(name)
                         .context("write tree entry name to stdout")?;
                 } else {
-                    let hash = hex::encode(hash_buf);
-                    let object = Object::read(&hash)
-                        .with_context(|| format!("read object for tree entry {}", hash))?;
-                    write!(
-                        stdout,
-                        "{:0>6} {} {hash} ",
-                        std::str::from_utf8(mode).context("mode is not valid utf-8")?,
-                        object.kind
-                    )?;
+                    let hash = hex::encode(&hash_buf);
+                    let mode = std::str::from_utf8(mode)
+                        .context("mode is not valid utf-8")?
+                        .parse::<Mode>()?;
+                    // a gitlink's commit lives in the submodule, not in this repository
+                    let kind = match mode {
+                        Mode::Gitlink => Kind::Commit,
+                        _ => {
+                            Object::read(repo, &hash)
+                                .with_context(|| format!("read object for tree entry {}", hash))?
+                                .kind
+                        }
+                    };
+                    write!(stdout, "{:0>6} {kind} {hash} ", mode.to_string())?;
                     stdout
-                        .write_all(&name)
+                        .write_all(name)
                         .context("write tree entry name to stdout")?;
                 }
 
-                writeln!(stdout, "").context("write newline to stdout")?;
+                writeln!(stdout).context("write newline to stdout")?;
             }
         }
         _ => anyhow::bail!("don't know how to ls {}", object.kind),
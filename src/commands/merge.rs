@@ -0,0 +1,216 @@
+use std::{fs, io::Write};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        checkout::checkout_tree,
+        commit_tree::{identity, write_commit_object},
+        log::write_medium,
+        rebase::merge_into_index,
+        status::Status,
+        write_tree::write_index_tree,
+    },
+    diff::{diff_trees, write_stat, BlobCache, DiffOptions},
+    index::Index,
+    objects::{ancestors, is_ancestor, merge_base, object_find, read_commit, ObjectType},
+    refs::{ref_resolve, ref_update, resolve_head, Head},
+    repository::{repo_path, GitRepository},
+    revwalk::RevWalk,
+};
+
+/// The files recording a merge in progress, which `commit` concludes.
+pub(crate) const MERGE_HEAD: &str = "MERGE_HEAD";
+pub(crate) const MERGE_MSG: &str = "MERGE_MSG";
+pub(crate) const MERGE_MODE: &str = "MERGE_MODE";
+/// The message prepared by `merge --squash`, which records no merge.
+pub(crate) const SQUASH_MSG: &str = "SQUASH_MSG";
+
+/// Remove the files of a concluded (or abandoned) merge.
+pub(crate) fn clear_merge_state(git_repo: &GitRepository) -> Result<()> {
+    for name in [MERGE_HEAD, MERGE_MSG, MERGE_MODE, SQUASH_MSG] {
+        let path = repo_path(git_repo, &[name])?;
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("remove {}", path.display()));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The default message of a merge of `rev` into `head`, like `Merge branch 'topic'`. The
+/// branch merged into is only named when it isn't `master` or `main`.
+fn merge_message(git_repo: &GitRepository, rev: &str, head: &Head) -> Result<String> {
+    let what = if ref_resolve(git_repo, &format!("refs/heads/{rev}"))?.is_some() {
+        format!("branch '{rev}'")
+    } else if ref_resolve(git_repo, &format!("refs/tags/{rev}"))?.is_some() {
+        format!("tag '{rev}'")
+    } else if ref_resolve(git_repo, &format!("refs/remotes/{rev}"))?.is_some() {
+        format!("remote-tracking branch '{rev}'")
+    } else {
+        format!("commit '{rev}'")
+    };
+    Ok(match head {
+        Head::Branch(branch, _) => match branch.strip_prefix("refs/heads/").unwrap_or(branch) {
+            "master" | "main" => format!("Merge {what}\n"),
+            name => format!("Merge {what} into {name}\n"),
+        },
+        Head::Detached(_) => format!("Merge {what}\n"),
+    })
+}
+
+/// The message `merge --squash` prepares: every commit being squashed, in `log`'s format.
+fn squash_message(git_repo: &GitRepository, head: &str, theirs: &str) -> Result<Vec<u8>> {
+    let merged = ancestors(git_repo, &[head.to_string()])?;
+    let mut walk = RevWalk::new(git_repo);
+    walk.push(theirs)?;
+    let mut message = b"Squashed commit of the following:\n".to_vec();
+    for entry in walk {
+        let (hash, commit) = entry?;
+        if merged.contains(&hash) {
+            continue;
+        }
+        writeln!(message)?;
+        write_medium(git_repo, &mut message, &hash, &commit)?;
+    }
+    Ok(message)
+}
+
+/// Point the current branch (or a detached HEAD) at `commit`, which merging `rev` made, as
+/// `how` says.
+fn advance_head(
+    git_repo: &GitRepository,
+    head: &Head,
+    commit: &str,
+    rev: &str,
+    how: &str,
+) -> Result<()> {
+    ref_update(
+        git_repo,
+        "HEAD",
+        commit,
+        head.commit(),
+        &format!("merge {rev}: {how}"),
+    )
+}
+
+/// Print how many lines each file changed between the commits `from` and `to`.
+fn print_stat(git_repo: &GitRepository, from: &str, to: &str) -> Result<()> {
+    let changes = diff_trees(
+        git_repo,
+        Some(&read_commit(git_repo, from)?.tree),
+        Some(&read_commit(git_repo, to)?.tree),
+    )?;
+    let stdout = std::io::stdout();
+    write_stat(
+        &mut stdout.lock(),
+        &mut BlobCache::new(git_repo),
+        &changes,
+        &DiffOptions::default(),
+    )
+}
+
+/// Merge `rev` into the current branch: fast-forward when the branch has nothing `rev`
+/// doesn't, and otherwise merge the changes both made since their merge base and commit the
+/// result with both as parents.
+///
+/// With `no_commit`, the merged result is only staged, and `MERGE_HEAD` and `MERGE_MSG` are left
+/// for `commit` to record the merge. With `squash`, the changes are staged without recording a
+/// merge at all, so the next commit has a single parent; its message is prepared in
+/// `SQUASH_MSG`.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    rev: String,
+    message: Option<String>,
+    no_commit: bool,
+    squash: bool,
+) -> Result<()> {
+    if repo_path(repo, &[MERGE_HEAD])?.exists() {
+        bail!("You have not concluded your merge (MERGE_HEAD exists).\nPlease, commit your changes before you merge.");
+    }
+    let head = resolve_head(repo)?;
+    let Some(ours) = head.commit().map(str::to_string) else {
+        bail!("your current branch does not have any commits yet");
+    };
+    let theirs = object_find(repo, rev.clone(), ObjectType::Commit)?;
+
+    let (mut index, lock) = Index::lock(repo)?;
+    let (status, _) = Status::collect(repo, Some(&ours), &mut index)?;
+    if !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+        bail!("cannot merge: You have unstaged changes.");
+    }
+    if !status.staged.is_empty() {
+        bail!("cannot merge: Your index contains uncommitted changes.");
+    }
+    // keep the refreshed stat data
+    lock.commit(&index)?;
+
+    if is_ancestor(repo, &theirs, &ours)? {
+        println!("Already up to date.");
+        return Ok(());
+    }
+    let base = merge_base(repo, &ours, &theirs)?;
+    if base.as_deref() == Some(ours.as_str()) && !squash {
+        println!("Updating {}..{}", &ours[..7], &theirs[..7]);
+        println!("Fast-forward");
+        checkout_tree(repo, &read_commit(repo, &theirs)?.tree, false)?;
+        advance_head(repo, &head, &theirs, &rev, "Fast-forward")?;
+        return print_stat(repo, &ours, &theirs);
+    }
+
+    let conflicts = merge_into_index(repo, base.as_deref(), &theirs, &rev)?;
+    let mut message = match message {
+        Some(message) => format!("{}\n", message.trim_end()),
+        None => merge_message(repo, &rev, &head)?,
+    }
+    .into_bytes();
+    if squash {
+        message = squash_message(repo, &ours, &theirs)?;
+    }
+    if !conflicts.is_empty() {
+        message.extend_from_slice(b"\n# Conflicts:\n");
+        for path in &conflicts {
+            message.extend_from_slice(format!("#\t{path}\n").as_bytes());
+        }
+    }
+    if squash {
+        fs::write(repo_path(repo, &[SQUASH_MSG])?, &message)?;
+        println!("Squash commit -- not updating HEAD");
+    } else {
+        fs::write(repo_path(repo, &[MERGE_HEAD])?, format!("{theirs}\n"))?;
+        fs::write(repo_path(repo, &[MERGE_MSG])?, &message)?;
+        fs::write(repo_path(repo, &[MERGE_MODE])?, "")?;
+    }
+    if !conflicts.is_empty() {
+        bail!("Automatic merge failed; fix conflicts and then commit the result.");
+    }
+    if squash || no_commit {
+        println!("Automatic merge went well; stopped before committing as requested");
+        return Ok(());
+    }
+
+    let tree = write_index_tree(repo, &Index::read(repo)?)?;
+    let author = identity(repo, "author")?.to_string();
+    let committer = identity(repo, "committer")?.to_string();
+    let message = String::from_utf8(message).context("merge message isn't utf-8")?;
+    let commit = write_commit_object(
+        repo,
+        &tree,
+        &[ours.clone(), theirs],
+        &author,
+        &committer,
+        &message,
+    )?;
+    advance_head(
+        repo,
+        &head,
+        &commit,
+        &rev,
+        "Merge made by the 'ort' strategy.",
+    )?;
+    clear_merge_state(repo)?;
+    println!("Merge made by the 'ort' strategy.");
+    print_stat(repo, &ours, &commit)
+}
@@ -0,0 +1,92 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::{
+        read_tree::tree_entries,
+        rebase::{merge_trees, TreeMerge},
+        write_tree::write_index_tree,
+    },
+    hash::ObjectId,
+    index::{Index, IndexEntry},
+    objects::{merge_base, object_find, write_object, Kind, ObjectType},
+    repository::GitRepository,
+    ExitStatus,
+};
+
+/// Merge the commits `branch1` and `branch2` as `merge-tree --write-tree` does, without an
+/// index or work tree, so that it works in bare repositories too. The merged tree is written,
+/// with conflicted files holding their conflict markers, and its id printed. When there are
+/// conflicts, the id is followed by the stages of each conflicted path, as
+/// `<mode> <blob> <stage>\t<path>`, then a blank line and the messages of the merge, and the
+/// exit status is 1.
+pub(crate) fn invoke(repo: &GitRepository, branch1: String, branch2: String) -> Result<()> {
+    let ours = object_find(repo, branch1.clone(), ObjectType::Commit)?;
+    let theirs = object_find(repo, branch2.clone(), ObjectType::Commit)?;
+    let Some(base) = merge_base(repo, &ours, &theirs)? else {
+        bail!("refusing to merge unrelated histories");
+    };
+    // the index a merge would replace matches ours, so no local changes can be in the way
+    let mut old = Index::new(repo.hash_algo());
+    old.entries = tree_entries(repo, &ours, "")?.0.into_values().collect();
+    let TreeMerge {
+        entries,
+        conflicts,
+        messages,
+    } = merge_trees(
+        repo,
+        &old,
+        Some(&base),
+        &ours,
+        &theirs,
+        [&branch1, &branch2],
+    )?;
+
+    // the tree records conflicted paths as a work tree would have them
+    let mut tree = Index::new(repo.hash_algo());
+    tree.entries = entries.iter().filter(|e| e.stage() == 0).cloned().collect();
+    for (path, content) in &conflicts {
+        let entry = match content {
+            Some((mode, data)) => {
+                let blob = ObjectId::from_hex(&write_object(repo, Kind::Blob, data)?)?;
+                IndexEntry::without_stat(path, *mode, blob)
+            }
+            None => {
+                let mut sides = entries.iter().filter(|e| &e.path == path);
+                let mut entry = sides
+                    .clone()
+                    .find(|e| e.stage() == 2)
+                    .or_else(|| sides.find(|e| e.stage() == 3))
+                    .expect("a conflict without content has both sides")
+                    .clone();
+                entry.set_stage(0);
+                entry
+            }
+        };
+        tree.entries.push(entry);
+    }
+    tree.sort();
+    println!("{}", write_index_tree(repo, &tree)?);
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    let mut stages = entries
+        .iter()
+        .filter(|e| e.stage() != 0)
+        .collect::<Vec<_>>();
+    stages.sort_by(|a, b| (a.path.as_bytes(), a.stage()).cmp(&(b.path.as_bytes(), b.stage())));
+    for entry in stages {
+        println!(
+            "{:06o} {} {}\t{}",
+            entry.mode,
+            entry.hash_hex(),
+            entry.stage(),
+            entry.path
+        );
+    }
+    println!();
+    for message in messages {
+        println!("{message}");
+    }
+    Err(ExitStatus(1).into())
+}
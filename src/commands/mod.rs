@@ -1,6 +1,62 @@
+pub(crate) mod add;
+pub(crate) mod am;
+pub(crate) mod apply;
+pub(crate) mod bisect;
+pub(crate) mod blame;
+pub(crate) mod branch;
 pub(crate) mod cat_file;
+pub(crate) mod check_attr;
+pub(crate) mod check_mailmap;
+pub(crate) mod check_ref_format;
+pub(crate) mod checkout;
+pub(crate) mod checkout_index;
+pub(crate) mod clone;
+pub(crate) mod commit;
 pub(crate) mod commit_tree;
+pub(crate) mod config;
+pub(crate) mod diff;
+pub(crate) mod diff_files;
+pub(crate) mod diff_index;
+pub(crate) mod dump_index;
+pub(crate) mod fetch;
+pub(crate) mod for_each_ref;
+pub(crate) mod format_patch;
+pub(crate) mod fsck;
 pub(crate) mod hash_object;
 pub(crate) mod init;
+pub(crate) mod interpret_trailers;
+pub(crate) mod log;
+pub(crate) mod ls_files;
+pub(crate) mod ls_remote;
 pub(crate) mod ls_tree;
+pub(crate) mod merge;
+pub(crate) mod merge_tree;
+pub(crate) mod name_rev;
+pub(crate) mod notes;
+pub(crate) mod prune;
+pub(crate) mod push;
+pub(crate) mod range_diff;
+pub(crate) mod read_tree;
+pub(crate) mod rebase;
+pub(crate) mod receive_pack;
+pub(crate) mod remote;
+pub(crate) mod repack;
+pub(crate) mod rerere;
+pub(crate) mod restore;
+pub(crate) mod rev_parse;
+pub(crate) mod shortlog;
+pub(crate) mod show;
+pub(crate) mod show_ref;
+pub(crate) mod stash;
+pub(crate) mod status;
+pub(crate) mod stripspace;
+pub(crate) mod submodule;
+pub(crate) mod switch;
+pub(crate) mod tag;
+pub(crate) mod update_index;
+pub(crate) mod update_ref;
+pub(crate) mod upload_pack;
+pub(crate) mod var;
+pub(crate) mod verify;
+pub(crate) mod worktree;
 pub(crate) mod write_tree;
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::{
+    decorate::ref_index,
+    objects::{object_find, peel_to, read_commit, Kind, ObjectType},
+    repository::GitRepository,
+};
+
+/// How much stepping to a merge's second (or later) parent counts against a name, so that a
+/// name going through fewer merges is always preferred.
+const MERGE_TRAVERSAL_WEIGHT: u32 = 65535;
+
+/// The name a commit is given, relative to a ref tip.
+#[derive(Debug, Clone)]
+struct RevName {
+    /// The ref (or a merge parent path from it, like `main~2^2`) the generations count from.
+    tip_name: String,
+    /// The tip's tagger date, or commit date for refs that aren't annotated tags.
+    tag_date: i64,
+    /// First-parent steps from `tip_name`.
+    generation: u32,
+    /// Steps from the ref, with merge parents weighted heavily.
+    distance: u32,
+    from_tag: bool,
+}
+
+impl RevName {
+    /// Whether a name with these properties beats `self`, following git: names from tags
+    /// beat the rest, and among tags the older tag wins, then the nearer one. Otherwise the
+    /// nearer name wins, then the one from the older tip, then the one with fewer generations.
+    fn is_worse_than(&self, tag_date: i64, generation: u32, distance: u32, from_tag: bool) -> bool {
+        if from_tag && self.from_tag {
+            return self.tag_date > tag_date
+                || (self.tag_date == tag_date && self.distance > distance);
+        }
+        if self.from_tag != from_tag {
+            return from_tag;
+        }
+        if self.distance != distance {
+            return self.distance > distance;
+        }
+        if self.tag_date != tag_date {
+            return self.tag_date > tag_date;
+        }
+        self.generation > generation
+    }
+
+    /// The name as shown, like `main~3` or `tags/v1.2^0`.
+    fn display(&self) -> String {
+        match self.generation {
+            0 => self.tip_name.clone(),
+            n => format!("{}~{n}", strip_deref(&self.tip_name)),
+        }
+    }
+}
+
+fn strip_deref(name: &str) -> &str {
+    name.strip_suffix("^0").unwrap_or(name)
+}
+
+/// How a ref is named: branches by their short name, other refs without `refs/`.
+fn tip_name(name: &str) -> &str {
+    name.strip_prefix("refs/heads/")
+        .or_else(|| name.strip_prefix("refs/"))
+        .unwrap_or(name)
+}
+
+/// Name every commit reachable from a ref by its nearest ref, as `name-rev` does.
+fn name_commits(git_repo: &GitRepository) -> Result<HashMap<String, RevName>> {
+    let index = ref_index(git_repo)?;
+    let mut tips = Vec::new();
+    for tip in &index.refs {
+        let Ok(commit) = peel_to(git_repo, &tip.peeled, Kind::Commit) else {
+            continue;
+        };
+        let from_tag = tip.name.starts_with("refs/tags/");
+        let tag_date = match tip.tag_date {
+            Some(date) => date,
+            None => read_commit(git_repo, &commit)?.commit_time(),
+        };
+        let short = tip_name(&tip.name);
+        // a commit named by a tag object rather than directly is shown dereferenced
+        let tip_name = match tip.hash == commit {
+            true => short.to_string(),
+            false => format!("{short}^0"),
+        };
+        tips.push((commit, tip_name, tag_date, from_tag));
+    }
+    // tags first, then older tips first
+    tips.sort_by_key(|(_, _, tag_date, from_tag)| (!from_tag, *tag_date));
+
+    let mut names: HashMap<String, RevName> = HashMap::new();
+    for (commit, tip_name, tag_date, from_tag) in tips {
+        let better = |names: &HashMap<String, RevName>, hash: &str, generation, distance| {
+            names
+                .get(hash)
+                .is_none_or(|name| name.is_worse_than(tag_date, generation, distance, from_tag))
+        };
+        if !better(&names, &commit, 0, 0) {
+            continue;
+        }
+        names.insert(
+            commit.clone(),
+            RevName {
+                tip_name,
+                tag_date,
+                generation: 0,
+                distance: 0,
+                from_tag,
+            },
+        );
+        let mut stack = vec![commit];
+        while let Some(hash) = stack.pop() {
+            let name = names[&hash].clone();
+            let mut named = Vec::new();
+            for (i, parent) in read_commit(git_repo, &hash)?
+                .parents
+                .into_iter()
+                .enumerate()
+            {
+                let (generation, distance) = match i {
+                    0 => (name.generation + 1, name.distance + 1),
+                    _ => (0, name.distance + MERGE_TRAVERSAL_WEIGHT),
+                };
+                if !better(&names, &parent, generation, distance) {
+                    continue;
+                }
+                let tip_name = match (i, name.generation) {
+                    (0, _) => name.tip_name.clone(),
+                    (_, 0) => format!("{}^{}", strip_deref(&name.tip_name), i + 1),
+                    (_, g) => format!("{}~{g}^{}", strip_deref(&name.tip_name), i + 1),
+                };
+                names.insert(
+                    parent.clone(),
+                    RevName {
+                        tip_name,
+                        tag_date,
+                        generation,
+                        distance,
+                        from_tag,
+                    },
+                );
+                named.push(parent);
+            }
+            // the first parent is walked first
+            stack.extend(named.into_iter().rev());
+        }
+    }
+    Ok(names)
+}
+
+/// Print each of `revs` with a name for it relative to the nearest ref, like `main~3`, or
+/// `undefined` when no ref reaches it. With `name_only`, only the names are printed.
+pub(crate) fn invoke(repo: &GitRepository, revs: Vec<String>, name_only: bool) -> Result<()> {
+    let index = ref_index(repo)?;
+    let names = name_commits(repo)?;
+    for rev in revs {
+        let name = match index.resolve(&rev) {
+            // an annotated tag is named by its own ref
+            Some(tip) if tip.hash != tip.peeled => tip_name(&tip.name).to_string(),
+            _ => {
+                let hash = object_find(repo, rev.clone(), ObjectType::Commit)?;
+                names
+                    .get(&hash)
+                    .map_or_else(|| "undefined".to_string(), RevName::display)
+            }
+        };
+        match name_only {
+            true => println!("{name}"),
+            false => println!("{rev} {name}"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::commit_tree::{identity, write_commit_object},
+    objects::{
+        object_find, object_read, read_commit, read_tree_recursive, write_object, Kind, ObjectType,
+    },
+    refs::{ref_resolve, ref_update},
+    repository::GitRepository,
+};
+
+/// The ref holding the notes history.
+const NOTES_REF: &str = "refs/notes/commits";
+
+/// Every note, as `(annotated object, note blob)`. Notes trees may be fanned out into
+/// directories (`ab/cdef...`), which are flattened back into the full object hash.
+pub(crate) fn read_notes(git_repo: &GitRepository) -> Result<Vec<(String, String)>> {
+    let Some(notes) = ref_resolve(git_repo, NOTES_REF)? else {
+        return Ok(Vec::new());
+    };
+    let tree = read_commit(git_repo, &notes)?.tree;
+    Ok(read_tree_recursive(git_repo, &tree, "")?
+        .into_iter()
+        .map(|entry| (entry.name.replace('/', ""), entry.hash))
+        .filter(|(object, _)| object.len() == git_repo.hash_algo().hex_len())
+        .collect())
+}
+
+/// The text of the note blob `blob`.
+pub(crate) fn read_note(git_repo: &GitRepository, blob: &str) -> Result<String> {
+    let data = object_read(git_repo, blob)?.serialize();
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Attach `message` as the note of `object` (HEAD by default), recording the change as a new
+/// commit on `refs/notes/commits`. An existing note is only replaced with `force`.
+pub(crate) fn invoke_add(
+    repo: &GitRepository,
+    object: Option<String>,
+    message: Vec<String>,
+    force: bool,
+) -> Result<()> {
+    let object = object_find(
+        repo,
+        object.unwrap_or_else(|| "HEAD".to_string()),
+        ObjectType::Commit,
+    )?;
+
+    // like `-m` for commits, each message is its own paragraph
+    let note = message
+        .iter()
+        .map(|m| m.trim())
+        .filter(|m| !m.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    if note.is_empty() {
+        bail!("Refusing to add empty note; use -m to give one");
+    }
+
+    let mut notes = read_notes(repo)?;
+    if let Some(at) = notes.iter().position(|(o, _)| *o == object) {
+        if !force {
+            bail!(
+                "Cannot add notes. Found existing notes for object {object}. \
+                 Use '-f' to overwrite existing notes"
+            );
+        }
+        notes.remove(at);
+    }
+    notes.push((
+        object,
+        write_object(repo, Kind::Blob, format!("{note}\n").as_bytes())?,
+    ));
+    notes.sort();
+
+    // a flat tree; git only fans notes out into directories once there are many
+    let mut tree = Vec::new();
+    for (object, blob) in notes {
+        write!(tree, "100644 {object}\0")?;
+        tree.extend(hex::decode(&blob)?);
+    }
+    let tree = write_object(repo, Kind::Tree, &tree)?;
+
+    let parents = ref_resolve(repo, NOTES_REF)?
+        .into_iter()
+        .collect::<Vec<_>>();
+    let author = identity(repo, "author")?.to_string();
+    let committer = identity(repo, "committer")?.to_string();
+    let commit = write_commit_object(
+        repo,
+        &tree,
+        &parents,
+        &author,
+        &committer,
+        "Notes added by 'git notes add'\n",
+    )?;
+    let null = repo.hash_algo().null().to_string();
+    let old = parents.first().unwrap_or(&null);
+    ref_update(
+        repo,
+        NOTES_REF,
+        &commit,
+        Some(old),
+        "notes: Notes added by 'git notes add'",
+    )
+}
+
+/// Print the note of `object` (HEAD by default).
+pub(crate) fn invoke_show(repo: &GitRepository, object: Option<String>) -> Result<()> {
+    let object = object_find(
+        repo,
+        object.unwrap_or_else(|| "HEAD".to_string()),
+        ObjectType::Commit,
+    )?;
+    match read_notes(repo)?.into_iter().find(|(o, _)| *o == object) {
+        Some((_, blob)) => print!("{}", read_note(repo, &blob)?),
+        None => bail!("no note found for object {object}."),
+    }
+    Ok(())
+}
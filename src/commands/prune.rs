@@ -0,0 +1,162 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::upload_pack::count_objects,
+    index::Index,
+    objects::{object_exists, object_kind, Mode},
+    pack::packed_contains,
+    refs::ref_list,
+    repository::{repo_path, GitRepository},
+};
+
+/// Add the old and new ids of every reflog entry under `dir` to `ids`, except the null id.
+fn reflog_ids(dir: &Path, null: &str, ids: &mut Vec<String>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            reflog_ids(&path, null, ids)?;
+            continue;
+        }
+        let log = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+        for line in log.lines() {
+            ids.extend(
+                line.split(' ')
+                    .take(2)
+                    .filter(|id| *id != null)
+                    .map(str::to_string),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Every object reachable from the refs, the reflogs, and the `HEAD` and index of each
+/// worktree, including the trees the index has cached: what `prune` keeps and `repack -a`
+/// packs. Tips whose objects are gone, as old reflog entries may be, are skipped.
+pub(crate) fn reachable_objects(git_repo: &GitRepository) -> Result<Vec<String>> {
+    let algo = git_repo.hash_algo();
+    let mut tips = ref_list(git_repo)?
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect::<Vec<_>>();
+    let common_dir = git_repo.common_dir();
+    let mut git_dirs = vec![common_dir.to_path_buf()];
+    if let Ok(worktrees) = fs::read_dir(common_dir.join("worktrees")) {
+        for worktree in worktrees {
+            git_dirs.push(worktree?.path());
+        }
+    }
+    for git_dir in &git_dirs {
+        // a HEAD on a branch is already among the refs
+        if let Ok(head) = fs::read_to_string(git_dir.join("HEAD")) {
+            if algo.is_hex_id(head.trim_end()) {
+                tips.push(head.trim_end().to_string());
+            }
+        }
+        let path = git_dir.join("index");
+        if path.exists() {
+            let buf = fs::read(&path).with_context(|| format!("read {}", path.display()))?;
+            let index =
+                Index::parse(&buf, algo).with_context(|| format!("parse {}", path.display()))?;
+            tips.extend(
+                index
+                    .entries
+                    .iter()
+                    .filter(|entry| Mode::from_bits(entry.mode) != Mode::Gitlink)
+                    .map(|entry| entry.hash_hex()),
+            );
+            if let Some(cache_tree) = index.cache_tree() {
+                tips.extend(cache_tree.trees().iter().map(|hash| hash.to_string()));
+            }
+        }
+        reflog_ids(&git_dir.join("logs"), &algo.null().to_string(), &mut tips)?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut wants = Vec::new();
+    for tip in tips {
+        if seen.insert(tip.clone()) && object_exists(git_repo, &tip)? {
+            wants.push(tip);
+        }
+    }
+    Ok(count_objects(git_repo, wants, Vec::new())?.0)
+}
+
+/// The loose objects of `git_repo` and their files, sorted by id.
+pub(crate) fn loose_objects(git_repo: &GitRepository) -> Result<Vec<(String, PathBuf)>> {
+    let algo = git_repo.hash_algo();
+    let objects = repo_path(git_repo, &["objects"])?;
+    let mut loose = Vec::new();
+    for dir in fs::read_dir(&objects).with_context(|| format!("read {}", objects.display()))? {
+        let dir = dir?;
+        let fanout = dir.file_name().to_string_lossy().into_owned();
+        if fanout.len() != 2 || !fanout.chars().all(|c| c.is_ascii_hexdigit()) {
+            continue;
+        }
+        let path = dir.path();
+        for entry in fs::read_dir(&path).with_context(|| format!("read {}", path.display()))? {
+            let entry = entry?;
+            let hash = format!("{fanout}{}", entry.file_name().to_string_lossy());
+            // temporary files of writers in progress aren't objects
+            if algo.is_hex_id(&hash) {
+                loose.push((hash, entry.path()));
+            }
+        }
+    }
+    loose.sort();
+    Ok(loose)
+}
+
+/// Delete the loose object files `paths`, and the fan-out directories they leave empty.
+pub(crate) fn remove_loose(paths: &[&Path]) -> Result<()> {
+    for path in paths {
+        fs::remove_file(path).with_context(|| format!("remove {}", path.display()))?;
+    }
+    for dir in paths.iter().filter_map(|path| path.parent()) {
+        // fails harmlessly while the directory still holds other objects
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+/// Delete the loose objects nothing reaches, like `git prune`, and those that are packed too.
+/// The unreachable ones are listed as `<id> <type>` with `verbose`, or instead of being
+/// deleted with `dry_run`. A repository whose objects are precious is left alone.
+pub(crate) fn invoke(repo: &GitRepository, dry_run: bool, verbose: bool) -> Result<()> {
+    if repo.precious_objects() {
+        bail!("cannot prune in a precious-objects repo");
+    }
+    let reachable = reachable_objects(repo)?.into_iter().collect::<HashSet<_>>();
+    let objects = repo_path(repo, &["objects"])?;
+    let loose = loose_objects(repo)?;
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut doomed = Vec::new();
+    for (hash, path) in &loose {
+        if !reachable.contains(hash) {
+            if dry_run || verbose {
+                writeln!(stdout, "{hash} {}", object_kind(repo, hash)?)?;
+            }
+        } else if !packed_contains(&objects, repo.hash_algo(), hash)? {
+            continue;
+        }
+        doomed.push(path.as_path());
+    }
+    if !dry_run {
+        remove_loose(&doomed)?;
+    }
+    Ok(())
+}
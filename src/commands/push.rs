@@ -0,0 +1,529 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::{
+        branch::short_ref_name,
+        fetch::default_remote,
+        remote::{fetch_refspecs, map_refspec, Refspec},
+        upload_pack::count_objects,
+    },
+    objects::{is_ancestor, object_exists, object_find, ObjectType},
+    pack::write_pack,
+    pkt_line::{read_pkt_text, write_flush, write_pkt},
+    refs::{ref_delete, ref_resolve, ref_update, resolve_head, Head},
+    repository::{ConfigScope, GitRepository},
+    transport::{Advertisement, Connection, Demux, Service, AGENT},
+    ExitStatus,
+};
+
+/// How wide the summary column of a pushed ref is, as in `fetch`.
+const SUMMARY_WIDTH: usize = 17;
+
+/// How `push` was asked to push.
+#[derive(Debug, Default)]
+pub(crate) struct PushOptions {
+    /// Allow updates that aren't fast-forwards for every refspec.
+    pub(crate) force: bool,
+    pub(crate) quiet: bool,
+    /// Make each branch pushed track the remote branch it was pushed to.
+    pub(crate) set_upstream: bool,
+    /// The program to run instead of `git-receive-pack`.
+    pub(crate) receive_pack: Option<String>,
+}
+
+/// What became of one ref the push was asked to update.
+#[derive(Debug, PartialEq, Eq)]
+enum Status {
+    /// The remote already has it.
+    UpToDate,
+    /// Refused before asking the remote, with the reason.
+    Rejected(&'static str),
+    /// Sent to the remote, which hasn't answered yet.
+    Pending,
+    Ok,
+    /// Refused by the remote, with its reason.
+    RemoteRejected(String),
+}
+
+/// One ref to update on the remote.
+#[derive(Debug)]
+struct PushRef {
+    /// The local ref (or revision) it is pushed from; `None` for a delete.
+    src: Option<String>,
+    /// The ref on the remote.
+    dst: String,
+    /// Where the remote ref is now, or the null id.
+    old: String,
+    /// Where it goes, or the null id for a delete.
+    new: String,
+    status: Status,
+}
+
+/// The local ref `src` names, tried with git's rev-parse rules, or `None` if it names no ref.
+fn find_local_ref(git_repo: &GitRepository, src: &str) -> Result<Option<String>> {
+    if src == "HEAD" {
+        return Ok(match resolve_head(git_repo)? {
+            Head::Branch(branch, _) => Some(branch),
+            Head::Detached(_) => None,
+        });
+    }
+    for name in [
+        src.to_string(),
+        format!("refs/{src}"),
+        format!("refs/tags/{src}"),
+        format!("refs/heads/{src}"),
+        format!("refs/remotes/{src}"),
+    ] {
+        if name.starts_with("refs/") && ref_resolve(git_repo, &name)?.is_some() {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+/// The remote ref a refspec's destination `dst` means: a full ref name as given, a ref the
+/// remote has by that short name, or else a new ref of the same kind as the local `src`.
+fn remote_ref_name(advertisement: &Advertisement, dst: &str, src: Option<&str>) -> Option<String> {
+    if dst.starts_with("refs/") {
+        return Some(dst.to_string());
+    }
+    for name in [format!("refs/heads/{dst}"), format!("refs/tags/{dst}")] {
+        if advertisement.get(&name).is_some() {
+            return Some(name);
+        }
+    }
+    let src = src?;
+    ["refs/heads/", "refs/tags/"]
+        .into_iter()
+        .find(|prefix| src.starts_with(prefix))
+        .map(|prefix| format!("{prefix}{dst}"))
+}
+
+/// The refspecs to push when none are given: `remote.<name>.push`, or else, like
+/// `push.default=simple`, the current branch to the branch of the same name.
+fn default_refspecs(git_repo: &GitRepository, remote: Option<&str>) -> Result<Vec<Refspec>> {
+    if let Some(remote) = remote {
+        let configured = git_repo.config_get_all(&format!("remote \"{remote}\""), "push");
+        if !configured.is_empty() {
+            return Ok(configured.into_iter().map(Refspec::parse).collect());
+        }
+    }
+    let Head::Branch(branch, _) = resolve_head(git_repo)? else {
+        bail!("You are not currently on a branch.");
+    };
+    let short = short_ref_name(&branch);
+    let upstream = git_repo.config_get(&format!("branch \"{short}\""), "merge");
+    // pushing elsewhere than the branch fetches from needs no upstream
+    let fetch_remote = default_remote(git_repo)?;
+    let Some(remote) = remote.filter(|remote| *remote == fetch_remote) else {
+        return Ok(vec![Refspec::parse(&format!("{branch}:{branch}"))]);
+    };
+    match upstream {
+        Some(upstream) if upstream != branch => bail!(
+            "The upstream branch of your current branch does not match\n\
+             the name of your current branch."
+        ),
+        None => bail!(
+            "The current branch {short} has no upstream branch.\n\
+             To push the current branch and set the remote as upstream, use\n\n    \
+             git push --set-upstream {remote} {short}\n"
+        ),
+        _ => Ok(vec![Refspec::parse(&format!("{branch}:{branch}"))]),
+    }
+}
+
+/// Match `refspecs` against the local refs and what the remote advertised, and decide which
+/// remote refs can be updated without asking. A refspec that matches nothing fails the push
+/// to `url` before anything is sent.
+fn map_push_refs(
+    git_repo: &GitRepository,
+    url: &str,
+    advertisement: &Advertisement,
+    refspecs: &[Refspec],
+    force: bool,
+) -> Result<Vec<PushRef>> {
+    let null = git_repo.hash_algo().null().to_string();
+    let mut refs = Vec::new();
+    let mut errors = Vec::new();
+    for refspec in refspecs {
+        if refspec.is_pattern() {
+            bail!(
+                "pushing with a pattern refspec is not supported: {}",
+                refspec.src
+            );
+        }
+        // `:dst` deletes the remote ref
+        if refspec.src.is_empty() {
+            let dst = refspec.dst.as_deref().unwrap_or_default();
+            let found = remote_ref_name(advertisement, dst, None)
+                .and_then(|dst| Some((advertisement.get(&dst)?.to_string(), dst)));
+            let Some((old, dst)) = found else {
+                errors.push(format!(
+                    "unable to delete '{dst}': remote ref does not exist"
+                ));
+                continue;
+            };
+            refs.push(PushRef {
+                src: None,
+                old,
+                new: null.clone(),
+                dst,
+                status: Status::Pending,
+            });
+            continue;
+        }
+        let local = find_local_ref(git_repo, &refspec.src)?;
+        // a ref is pushed as it is, so a tag stays a tag
+        let new = match &local {
+            Some(local) => ref_resolve(git_repo, local)?,
+            None => object_find(git_repo, refspec.src.clone(), ObjectType::Commit).ok(),
+        };
+        let Some(new) = new else {
+            errors.push(format!("src refspec {} does not match any", refspec.src));
+            continue;
+        };
+        let dst = match (&refspec.dst, &local) {
+            (None, Some(local)) => Some(local.clone()),
+            (dst, _) => {
+                let dst = dst.as_deref().unwrap_or(&refspec.src);
+                remote_ref_name(advertisement, dst, local.as_deref())
+            }
+        };
+        let Some(dst) = dst else {
+            errors.push(format!(
+                "The destination you provided is not a full refname (i.e.,\n\
+                 starting with \"refs/\"). You must fully qualify the ref for '{}'.",
+                refspec.dst.as_deref().unwrap_or(&refspec.src)
+            ));
+            continue;
+        };
+        let old = advertisement.get(&dst).unwrap_or(&null).to_string();
+        let status = if old == new {
+            Status::UpToDate
+        } else if old == null || force || refspec.force {
+            Status::Pending
+        } else if dst.starts_with("refs/tags/") {
+            Status::Rejected("already exists")
+        } else if !object_exists(git_repo, &old)? {
+            Status::Rejected("fetch first")
+        } else if !is_ancestor(git_repo, &old, &new)? {
+            Status::Rejected("non-fast-forward")
+        } else {
+            Status::Pending
+        };
+        // git shows `HEAD` as it was given, and other refs by their short names
+        let src = match refspec.src.as_str() {
+            "HEAD" => "HEAD".to_string(),
+            _ => local.unwrap_or_else(|| refspec.src.clone()),
+        };
+        refs.push(PushRef {
+            src: Some(src),
+            dst,
+            old,
+            new,
+            status,
+        });
+    }
+    if !errors.is_empty() {
+        for error in errors {
+            eprintln!("error: {error}");
+        }
+        eprintln!("error: failed to push some refs to '{url}'");
+        return Err(ExitStatus(1).into());
+    }
+    Ok(refs)
+}
+
+/// Send the pending updates of `refs` and the objects they need, and read the remote's
+/// report of which it made.
+fn send_pack(
+    git_repo: &GitRepository,
+    connection: &mut Connection,
+    advertisement: &Advertisement,
+    refs: &mut [PushRef],
+) -> Result<()> {
+    let null = git_repo.hash_algo().null().to_string();
+    let mut capabilities = vec!["report-status"];
+    let sideband = advertisement.has("side-band-64k");
+    if sideband {
+        capabilities.push("side-band-64k");
+    }
+    if advertisement.has("delete-refs") {
+        capabilities.push("delete-refs");
+    } else if refs
+        .iter()
+        .any(|r| r.status == Status::Pending && r.new == null)
+    {
+        bail!("the receiving end does not support deleting refs");
+    }
+    capabilities.push(AGENT);
+
+    let out = &mut connection.output;
+    let mut first = true;
+    for push in refs.iter().filter(|r| r.status == Status::Pending) {
+        let line = format!("{} {} {}", push.old, push.new, push.dst);
+        match first {
+            true => write_pkt(
+                out,
+                format!("{line}\0{}", capabilities.join(" ")).as_bytes(),
+            )?,
+            false => write_pkt(out, format!("{line}\n").as_bytes())?,
+        }
+        first = false;
+    }
+    write_flush(out)?;
+    // a pack comes with anything that isn't a delete, even when it holds no objects
+    let wants = refs
+        .iter()
+        .filter(|r| r.status == Status::Pending && r.new != null)
+        .map(|r| r.new.clone())
+        .collect::<Vec<_>>();
+    if !wants.is_empty() {
+        let mut haves = Vec::new();
+        for (_, hash) in &advertisement.refs {
+            if object_exists(git_repo, hash)? {
+                haves.push(hash.clone());
+            }
+        }
+        let (objects, _) = count_objects(git_repo, wants, haves)?;
+        write_pack(out, git_repo, &objects)?;
+    }
+    out.flush()?;
+
+    let mut report = Vec::new();
+    match sideband {
+        true => {
+            let mut demux = Demux::new(&mut connection.input);
+            while let Some(line) = read_pkt_text(&mut demux)? {
+                report.push(line);
+            }
+            demux.finish()?;
+        }
+        false => {
+            while let Some(line) = read_pkt_text(&mut connection.input)? {
+                report.push(line);
+            }
+        }
+    }
+    let mut lines = report.iter();
+    match lines.next().and_then(|line| line.strip_prefix("unpack ")) {
+        Some("ok") => {}
+        Some(reason) => eprintln!("error: remote unpack failed: {reason}"),
+        None => bail!("protocol error: expected an unpack status"),
+    }
+    for line in lines {
+        let (status, name) = if let Some(name) = line.strip_prefix("ok ") {
+            (Status::Ok, name)
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            (Status::RemoteRejected(reason.to_string()), name)
+        } else {
+            bail!("protocol error: bad report line {line:?}");
+        };
+        if let Some(push) = refs
+            .iter_mut()
+            .find(|r| r.dst == name && r.status == Status::Pending)
+        {
+            push.status = status;
+        }
+    }
+    // an update the remote didn't report on didn't happen
+    for push in refs.iter_mut().filter(|r| r.status == Status::Pending) {
+        push.status = Status::RemoteRejected("no report from remote".to_string());
+    }
+    Ok(())
+}
+
+/// Point the remote-tracking refs of `remote` at what the remote refs became.
+fn update_tracking_refs(git_repo: &GitRepository, remote: &str, refs: &[PushRef]) -> Result<()> {
+    let null = git_repo.hash_algo().null().to_string();
+    let refspecs = fetch_refspecs(git_repo, remote);
+    for push in refs.iter().filter(|r| r.status == Status::Ok) {
+        let tracking = refspecs
+            .iter()
+            .find_map(|refspec| map_refspec(&refspec.src, refspec.dst.as_deref()?, &push.dst));
+        let Some(tracking) = tracking else {
+            continue;
+        };
+        match push.new == null {
+            true => ref_delete(git_repo, &tracking)?,
+            false => ref_update(git_repo, &tracking, &push.new, None, "update by push")?,
+        }
+    }
+    Ok(())
+}
+
+/// Make each local branch that now matches a remote branch track it, as `push -u` does.
+/// `remote` is the remote's name, or its URL when it has none.
+fn set_upstreams(
+    git_repo: &mut GitRepository,
+    remote: &str,
+    refs: &[PushRef],
+    quiet: bool,
+) -> Result<()> {
+    for push in refs {
+        if !matches!(push.status, Status::Ok | Status::UpToDate) {
+            continue;
+        }
+        let Some(branch) = push
+            .src
+            .as_deref()
+            .and_then(|src| src.strip_prefix("refs/heads/"))
+        else {
+            continue;
+        };
+        let Some(merge) = push.dst.strip_prefix("refs/heads/") else {
+            continue;
+        };
+        let section = format!("branch \"{branch}\"");
+        git_repo.config_set(ConfigScope::Local, &section, "remote", Some(remote))?;
+        git_repo.config_set(ConfigScope::Local, &section, "merge", Some(&push.dst))?;
+        if !quiet {
+            println!("branch '{branch}' set up to track '{remote}/{merge}'.");
+        }
+    }
+    Ok(())
+}
+
+/// Print what became of each ref to stderr, after a `To <url>` header, the way git does.
+fn print_report(git_repo: &GitRepository, url: &str, refs: &[PushRef]) -> Result<()> {
+    let null = git_repo.hash_algo().null().to_string();
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    let mut header = false;
+    for push in refs {
+        let dst = short_ref_name(&push.dst);
+        let src = push.src.as_deref().map(short_ref_name).unwrap_or_default();
+        let (code, summary, reason) = match &push.status {
+            Status::UpToDate | Status::Pending => continue,
+            Status::Rejected(reason) => ('!', "[rejected]".to_string(), Some(*reason)),
+            Status::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_string(), Some(reason.as_str()))
+            }
+            Status::Ok if push.new == null => ('-', "[deleted]".to_string(), None),
+            Status::Ok if push.old == null => {
+                let summary = if push.dst.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else if push.dst.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else {
+                    "[new reference]"
+                };
+                ('*', summary.to_string(), None)
+            }
+            Status::Ok => {
+                let (old, new) = (&push.old[..7], &push.new[..7]);
+                match is_ancestor(git_repo, &push.old, &push.new).unwrap_or(false) {
+                    true => (' ', format!("{old}..{new}"), None),
+                    false => ('+', format!("{old}...{new}"), Some("forced update")),
+                }
+            }
+        };
+        if !header {
+            writeln!(stderr, "To {url}")?;
+            header = true;
+        }
+        match push.src {
+            Some(_) => write!(stderr, " {code} {summary:<SUMMARY_WIDTH$} {src} -> {dst}")?,
+            None => write!(stderr, " {code} {summary:<SUMMARY_WIDTH$} {dst}")?,
+        }
+        match reason {
+            Some(reason) => writeln!(stderr, " ({reason})")?,
+            None => writeln!(stderr)?,
+        }
+    }
+    Ok(())
+}
+
+/// Hints for the refs that were rejected because the remote has moved on.
+fn print_hints(refs: &[PushRef]) {
+    let rejected = |reason| refs.iter().any(|r| r.status == Status::Rejected(reason));
+    if rejected("fetch first") {
+        eprintln!(
+            "hint: Updates were rejected because the remote contains work that you do\n\
+             hint: not have locally. This is usually caused by another repository pushing\n\
+             hint: to the same ref. You may want to first integrate the remote changes\n\
+             hint: (e.g., 'git pull ...') before pushing again.\n\
+             hint: See the 'Note about fast-forwards' in 'git push --help' for details."
+        );
+    } else if rejected("non-fast-forward") {
+        eprintln!(
+            "hint: Updates were rejected because a pushed branch tip is behind its remote\n\
+             hint: counterpart. Check out this branch and integrate the remote changes\n\
+             hint: (e.g. 'git pull ...') before pushing again.\n\
+             hint: See the 'Note about fast-forwards' in 'git push --help' for details."
+        );
+    } else if rejected("already exists") {
+        eprintln!("hint: Updates were rejected because the tag already exists in the remote.");
+    }
+}
+
+/// Push to `remote` (the current branch's remote, or `origin`, by default) the refs `refspecs`
+/// name, or the current branch, and update the remote-tracking refs of what was pushed.
+pub(crate) fn invoke(
+    repo: &mut GitRepository,
+    remote: Option<String>,
+    refspecs: Vec<String>,
+    options: PushOptions,
+) -> Result<()> {
+    let name = match remote {
+        Some(remote) => remote,
+        None => default_remote(repo)?,
+    };
+    let section = format!("remote \"{name}\"");
+    let (remote, url) = match repo.config_get(&section, "url") {
+        Some(url) => (Some(name.as_str()), url.to_string()),
+        None => (None, name.clone()),
+    };
+    let url = match remote {
+        Some(_) => repo
+            .config_get(&section, "pushurl")
+            .map_or(url, str::to_string),
+        None => url,
+    };
+    let receive_pack = options
+        .receive_pack
+        .or_else(|| repo.config_get(&section, "receivepack").map(str::to_string));
+    let refspecs = match refspecs.is_empty() {
+        true => default_refspecs(repo, remote)?,
+        false => refspecs.iter().map(|spec| Refspec::parse(spec)).collect(),
+    };
+
+    let mut connection =
+        Connection::open(repo, &url, Service::ReceivePack, receive_pack.as_deref())?;
+    let advertisement = Advertisement::read(&mut connection.input)?;
+    // the remote is told when there is nothing to do, even when nothing could be matched
+    let mut mapped = map_push_refs(repo, &url, &advertisement, &refspecs, options.force);
+    match &mut mapped {
+        Ok(refs) if refs.iter().any(|r| r.status == Status::Pending) => {
+            send_pack(repo, &mut connection, &advertisement, refs)?;
+            connection.finish()?;
+        }
+        _ => connection.disconnect()?,
+    }
+    let refs = mapped?;
+
+    if let Some(remote) = remote {
+        update_tracking_refs(repo, remote, &refs)?;
+    }
+    let failed = refs
+        .iter()
+        .any(|r| matches!(r.status, Status::Rejected(_) | Status::RemoteRejected(_)));
+    if !options.quiet || failed {
+        print_report(repo, &url, &refs)?;
+    }
+    if refs.iter().all(|r| r.status == Status::UpToDate) && !options.quiet {
+        eprintln!("Everything up-to-date");
+    }
+    if failed {
+        eprintln!("error: failed to push some refs to '{url}'");
+        print_hints(&refs);
+        return Err(ExitStatus(1).into());
+    }
+    if options.set_upstream {
+        set_upstreams(repo, remote.unwrap_or(&url), &refs, options.quiet)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,410 @@
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::rebase::commits_to_replay,
+    diff::{detect_renames, diff_lines, diff_trees, hunks, split_lines, write_patch},
+    diff::{BlobCache, Change, DiffFile, DiffOptions, Edit, Whitespace},
+    mailmap::Mailmap,
+    objects::{merge_base, object_find, read_commit, subject, ObjectType},
+    pager::paged,
+    repository::GitRepository,
+    signature::Signature,
+};
+
+/// Lines of context around each change, both when comparing patches and when showing them.
+const CONTEXT: usize = 3;
+
+/// A commit of one of the ranges, as it is compared with the commits of the other.
+struct Patch {
+    hash: String,
+    subject: String,
+    /// The commit's author, message and changes as text, in the form `range-diff` shows the
+    /// differences between two patches in.
+    text: String,
+    /// Where the changes start in `text`.
+    diff_start: usize,
+    /// The number of lines of the changes, blank separators aside.
+    diff_size: usize,
+    /// The position of the matching commit in the other range.
+    matching: Option<usize>,
+}
+
+impl Patch {
+    fn diff(&self) -> &str {
+        &self.text[self.diff_start..]
+    }
+}
+
+/// Write the changes of `change` the way `range-diff` compares them: the `diff --git` header
+/// becomes a ` ## path ##` line that says what happened to the file, and hunk headers lose
+/// their line numbers, keeping only the path and function.
+fn write_file(text: &mut String, blobs: &mut BlobCache, change: &Change) -> Result<usize> {
+    let label = match (&change.old, &change.new) {
+        (None, Some(new)) => format!("{} (new)", new.path),
+        (Some(old), None) => format!("{} (deleted)", old.path),
+        (Some(old), Some(new)) if old.path != new.path => format!("{} => {}", old.path, new.path),
+        _ => change.path().to_string(),
+    };
+    let mode = match (&change.old, &change.new) {
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            format!(" (mode change {} => {})", old.mode, new.mode)
+        }
+        _ => String::new(),
+    };
+    text.push_str(&format!(" ## {label}{mode} ##\n"));
+    let mut lines = 1;
+
+    let mut patch = Vec::new();
+    let opts = DiffOptions {
+        context: CONTEXT,
+        ..DiffOptions::default()
+    };
+    write_patch(&mut patch, blobs, std::slice::from_ref(change), &opts)?;
+    let patch = String::from_utf8_lossy(&patch);
+    let body = patch
+        .lines()
+        .skip_while(|line| !line.starts_with("@@ ") && !line.starts_with("Binary files "));
+    for line in body {
+        if let Some(rest) = line.strip_prefix("@@ ") {
+            let function = rest.split_once("@@").map_or("", |(_, f)| f);
+            match function.is_empty() {
+                true => text.push_str("@@\n"),
+                false => text.push_str(&format!("@@ {}:{function}\n", change.path())),
+            }
+        } else if line.starts_with("Binary files ") {
+            let side = |file: &Option<DiffFile>| {
+                file.as_ref()
+                    .map_or("/dev/null".to_string(), |f| f.path.clone())
+            };
+            text.push_str(&format!(
+                " Binary files {} and {} differ\n",
+                side(&change.old),
+                side(&change.new)
+            ));
+        } else if line.starts_with(['+', '-', ' ']) {
+            text.push_str(line);
+            text.push('\n');
+        } else {
+            text.push(' ');
+            text.push_str(line);
+            text.push('\n');
+        }
+        lines += 1;
+    }
+    Ok(lines)
+}
+
+/// The commits of `range` (those reachable from its tip but not its base), parents first,
+/// as patches.
+fn read_patches(
+    git_repo: &GitRepository,
+    mailmap: &Mailmap,
+    (base, tip): (&str, &str),
+) -> Result<Vec<Patch>> {
+    let mut blobs = BlobCache::new(git_repo);
+    let mut patches = Vec::new();
+    for hash in commits_to_replay(git_repo, tip, Some(base))? {
+        let commit = read_commit(git_repo, &hash)?;
+        let mut text = String::from(" ## Metadata ##\n");
+        if let Some(author) = Signature::parse(&commit.author) {
+            let (name, email) = mailmap.map_identity(&author.name, &author.email);
+            text.push_str(&format!("Author: {name} <{email}>\n"));
+        }
+        text.push_str("\n ## Commit message ##\n");
+        for line in commit.message.trim_end_matches('\n').lines() {
+            text.push_str(format!("    {line}").trim_end());
+            text.push('\n');
+        }
+
+        let parent = match commit.parents.first() {
+            Some(parent) => Some(read_commit(git_repo, parent)?.tree),
+            None => None,
+        };
+        let changes = diff_trees(git_repo, parent.as_deref(), Some(&commit.tree))?;
+        let threshold = DiffOptions::default().rename_threshold.unwrap_or(50);
+        let changes = detect_renames(&mut blobs, changes, threshold)?;
+        let mut diff_start = 0;
+        let mut diff_size = 0;
+        for change in &changes {
+            text.push('\n');
+            if diff_start == 0 {
+                diff_start = text.len();
+            }
+            diff_size += write_file(&mut text, &mut blobs, change)?;
+        }
+        patches.push(Patch {
+            subject: subject(commit.message.as_bytes()),
+            hash,
+            text,
+            diff_start,
+            diff_size,
+            matching: None,
+        });
+    }
+    Ok(patches)
+}
+
+/// How many lines a diff between `a` and `b` has, hunk headers included: how far apart two
+/// patches are.
+fn diff_size(a: &str, b: &str) -> usize {
+    let (a, b) = (split_lines(a.as_bytes()), split_lines(b.as_bytes()));
+    let edits = diff_lines(&a, &b, Whitespace::Exact);
+    hunks(&edits, &vec![false; edits.len()], CONTEXT)
+        .iter()
+        .map(|hunk| 1 + hunk.edits.len())
+        .sum()
+}
+
+/// The assignment of rows to columns of the square matrix `cost` with the least total cost
+/// (the Hungarian method). Returns the column of each row.
+fn assign(cost: &[Vec<i64>]) -> Vec<usize> {
+    let n = cost.len();
+    // 1-based, with row 0 and column 0 as the starting point of each augmenting path
+    let mut u = vec![0; n + 1];
+    let mut v = vec![0; n + 1];
+    let mut row_of = vec![0; n + 1];
+    let mut way = vec![0; n + 1];
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut col = 0;
+        let mut min = vec![i64::MAX; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[col] = true;
+            let r = row_of[col];
+            let mut delta = i64::MAX;
+            let mut next = 0;
+            for c in 1..=n {
+                if used[c] {
+                    continue;
+                }
+                let reduced = cost[r - 1][c - 1] - u[r] - v[c];
+                if reduced < min[c] {
+                    min[c] = reduced;
+                    way[c] = col;
+                }
+                if min[c] < delta {
+                    delta = min[c];
+                    next = c;
+                }
+            }
+            for c in 0..=n {
+                if used[c] {
+                    u[row_of[c]] += delta;
+                    v[c] -= delta;
+                } else {
+                    min[c] -= delta;
+                }
+            }
+            col = next;
+            if row_of[col] == 0 {
+                break;
+            }
+        }
+        while col != 0 {
+            let previous = way[col];
+            row_of[col] = row_of[previous];
+            col = previous;
+        }
+    }
+    let mut assignment = vec![0; n];
+    for col in 1..=n {
+        assignment[row_of[col] - 1] = col - 1;
+    }
+    assignment
+}
+
+/// Pair up the commits of `a` and `b`: first those whose changes are the same, then the rest
+/// by how little their changes differ, unless that is more than `creation_factor` percent of
+/// the size of the changes themselves, when the commits are better shown as one removed and
+/// another added.
+fn correspond(a: &mut [Patch], b: &mut [Patch], creation_factor: usize) {
+    for (j, b_patch) in b.iter_mut().enumerate() {
+        // of several identical commits, the last one is taken, as git's hash map does
+        let same = (0..a.len())
+            .rev()
+            .find(|&i| a[i].matching.is_none() && a[i].diff() == b_patch.diff());
+        if let Some(i) = same {
+            a[i].matching = Some(j);
+            b_patch.matching = Some(i);
+        }
+    }
+
+    // too costly to ever be picked, but far enough from overflowing when added up
+    const IMPOSSIBLE: i64 = i32::MAX as i64;
+    let n = a.len() + b.len();
+    let mut cost = vec![vec![0; n]; n];
+    for (i, a_patch) in a.iter().enumerate() {
+        for (j, b_patch) in b.iter().enumerate() {
+            cost[i][j] = match (a_patch.matching, b_patch.matching) {
+                (Some(m), _) if m == j => 0,
+                (None, None) => diff_size(a_patch.diff(), b_patch.diff()) as i64,
+                _ => IMPOSSIBLE,
+            };
+        }
+        let removed = match a_patch.matching {
+            None => (a_patch.diff_size * creation_factor / 100) as i64,
+            Some(_) => IMPOSSIBLE,
+        };
+        cost[i][b.len()..].fill(removed);
+    }
+    for (j, b_patch) in b.iter().enumerate() {
+        let added = match b_patch.matching {
+            None => (b_patch.diff_size * creation_factor / 100) as i64,
+            Some(_) => IMPOSSIBLE,
+        };
+        for row in &mut cost[a.len()..] {
+            row[j] = added;
+        }
+    }
+    for (i, j) in assign(&cost).into_iter().enumerate().take(a.len()) {
+        if j < b.len() {
+            a[i].matching = Some(j);
+            b[j].matching = Some(i);
+        }
+    }
+}
+
+/// The section a line of a patch starts, as shown on the hunk headers of a diff between
+/// patches: `Commit message` for ` ## Commit message ##`, or the path and function of a hunk.
+fn section(line: &[u8]) -> Option<&[u8]> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    if let Some(name) = line
+        .strip_prefix(b" ## ")
+        .and_then(|rest| rest.strip_suffix(b" ##"))
+    {
+        return Some(name);
+    }
+    line.strip_prefix(b"@@ ")
+        .or_else(|| line.get(1..)?.strip_prefix(b"@@ "))
+}
+
+/// Write the differences between the patches `a` and `b`, indented under their header.
+fn write_patch_diff(out: &mut dyn Write, a: &str, b: &str) -> Result<()> {
+    let (old, new) = (split_lines(a.as_bytes()), split_lines(b.as_bytes()));
+    let edits = diff_lines(&old, &new, Whitespace::Exact);
+    for hunk in hunks(&edits, &vec![false; edits.len()], CONTEXT) {
+        match old[..hunk.old_start].iter().rev().find_map(|l| section(l)) {
+            Some(name) => {
+                write!(out, "    @@ ")?;
+                out.write_all(name)?;
+                writeln!(out)?;
+            }
+            None => writeln!(out, "    @@")?,
+        }
+        for edit in hunk.edits {
+            let (prefix, line) = match edit {
+                Edit::Equal(_, j) => (' ', new[j]),
+                Edit::Delete(i) => ('-', old[i]),
+                Edit::Insert(j) => ('+', new[j]),
+            };
+            write!(out, "    {prefix}")?;
+            out.write_all(line)?;
+        }
+    }
+    Ok(())
+}
+
+/// The base and tip of each of the two ranges `range-diff` compares, from its arguments:
+/// `<base>..<tip> <base>..<tip>`, `<tip>...<tip>` (each from where the two meet), or
+/// `<base> <tip> <tip>`.
+fn ranges(git_repo: &GitRepository, args: &[String]) -> Result<[(String, String); 2]> {
+    let commit = |rev: &str| object_find(git_repo, rev.to_string(), ObjectType::Commit);
+    let range = |arg: &str| -> Result<(String, String)> {
+        let (base, tip) = arg.split_once("..").context("need two commit ranges")?;
+        Ok((commit(base)?, commit(tip)?))
+    };
+    Ok(match args {
+        [a, b] if a.contains("..") && b.contains("..") => [range(a)?, range(b)?],
+        [symmetric] if symmetric.contains("...") => {
+            let (a, b) = symmetric
+                .split_once("...")
+                .context("need two commit ranges")?;
+            let (a, b) = (commit(a)?, commit(b)?);
+            let Some(base) = merge_base(git_repo, &a, &b)? else {
+                bail!("{symmetric}: no merge base");
+            };
+            [(base.clone(), a), (base, b)]
+        }
+        [base, a, b] => {
+            let base = commit(base)?;
+            [(base.clone(), commit(a)?), (base, commit(b)?)]
+        }
+        _ => bail!("need two commit ranges"),
+    })
+}
+
+/// Compare two versions of a series of commits, like a branch before and after it was
+/// rebased. Each commit of the new range is paired with the commit of the old range it most
+/// likely is (see [`correspond`]) and shown as unchanged (`=`), changed (`!`, followed by how
+/// its patch changed), added (`>`) or removed (`<`), under the old commit's subject where
+/// there is one. The new range's order is followed, with
+/// each removed commit shown once the ones before it are.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    args: Vec<String>,
+    creation_factor: usize,
+    paginate: bool,
+) -> Result<()> {
+    let [old, new] = ranges(repo, &args)?;
+    let mailmap = Mailmap::load(repo)?;
+    let mut a = read_patches(repo, &mailmap, (&old.0, &old.1))?;
+    let mut b = read_patches(repo, &mailmap, (&new.0, &new.1))?;
+    correspond(&mut a, &mut b, creation_factor);
+
+    // git sizes the numbers for one more commit than the longer range has
+    let width = (a.len().max(b.len()) + 1).to_string().len();
+    let side = |patches: &[Patch], i: Option<usize>| match i {
+        Some(i) => format!("{:>width$}:  {}", i + 1, &patches[i].hash[..7]),
+        None => format!("{:>width$}:  -------", "-"),
+    };
+    paged(paginate, |out| {
+        let mut shown = vec![false; a.len()];
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            while i < a.len() && shown[i] {
+                i += 1;
+            }
+            if i < a.len() && a[i].matching.is_none() {
+                writeln!(
+                    out,
+                    "{} < {} {}",
+                    side(&a, Some(i)),
+                    side(&b, None),
+                    a[i].subject
+                )?;
+                i += 1;
+                continue;
+            }
+            while j < b.len() && b[j].matching.is_none() {
+                writeln!(
+                    out,
+                    "{} > {} {}",
+                    side(&a, None),
+                    side(&b, Some(j)),
+                    b[j].subject
+                )?;
+                j += 1;
+            }
+            if let Some(m) = b.get(j).and_then(|patch| patch.matching) {
+                let changed = a[m].text != b[j].text;
+                let status = if changed { '!' } else { '=' };
+                writeln!(
+                    out,
+                    "{} {status} {} {}",
+                    side(&a, Some(m)),
+                    side(&b, Some(j)),
+                    a[m].subject
+                )?;
+                if changed {
+                    write_patch_diff(out, &a[m].text, &b[j].text)?;
+                }
+                shown[m] = true;
+                j += 1;
+            }
+        }
+        Ok(())
+    })
+}
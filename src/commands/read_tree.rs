@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    cache_tree::CacheTree,
+    commands::checkout::{checkout_entry, remove_worktree_file},
+    hash::ObjectId,
+    index::{worktree_state, Index, IndexEntry, WorktreeState},
+    objects::{read_tree, tree_ish, TreeEntry},
+    repository::GitRepository,
+};
+
+/// The entries of one tree taking part in a merge, by path.
+pub(crate) type Entries = BTreeMap<String, IndexEntry>;
+
+/// The entries of the tree-ish `name`, with paths under `prefix`, and its cache tree.
+pub(crate) fn tree_entries(
+    git_repo: &GitRepository,
+    name: &str,
+    prefix: &str,
+) -> Result<(Entries, CacheTree)> {
+    fn read(
+        git_repo: &GitRepository,
+        tree: &str,
+        prefix: &str,
+        entries: &mut Entries,
+    ) -> Result<CacheTree> {
+        let before = entries.len();
+        let mut children = Vec::new();
+        for entry in read_tree(git_repo, tree)? {
+            let path = format!("{prefix}{}", entry.name);
+            if entry.is_tree() {
+                let child = read(git_repo, &entry.hash, &format!("{path}/"), entries)?;
+                children.push((entry.name, child));
+            } else {
+                let entry = IndexEntry::from_tree_entry(&TreeEntry {
+                    name: path.clone(),
+                    ..entry
+                })?;
+                entries.insert(path, entry);
+            }
+        }
+        let hash = ObjectId::from_hex(tree).with_context(|| format!("bad tree hash {tree}"))?;
+        Ok(CacheTree::new(entries.len() - before, hash, children))
+    }
+
+    let mut entries = Entries::new();
+    let tree = read(git_repo, &tree_ish(git_repo, name)?, prefix, &mut entries)?;
+    Ok((entries, tree))
+}
+
+/// Whether two versions of a path have the same blob and mode (or are both absent).
+fn same(a: Option<&IndexEntry>, b: Option<&IndexEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// `entry`, or the entry already in `old` if that records the same version, so that the cached
+/// stat data of unchanged files survives.
+fn keep_stat(old: &Index, entry: &IndexEntry) -> IndexEntry {
+    match old.get(&entry.path) {
+        Some(old) if same(Some(old), Some(entry)) => old.clone(),
+        _ => entry.clone(),
+    }
+}
+
+/// Every path of `old` and `trees`.
+fn all_paths<'a>(old: &'a Index, trees: &[&'a Entries]) -> BTreeSet<&'a str> {
+    old.entries
+        .iter()
+        .map(|e| e.path.as_str())
+        .chain(trees.iter().flat_map(|t| t.keys().map(String::as_str)))
+        .collect()
+}
+
+fn overwritten(path: &str) -> anyhow::Error {
+    anyhow!("Entry '{path}' would be overwritten by merge. Cannot merge.")
+}
+
+/// Two-tree merge, moving the index from `head` to `merge` while carrying local changes along:
+/// paths the index changed keep the index version, unless `merge` changed them too.
+fn two_way(old: &Index, head: &Entries, merge: &Entries) -> Result<Vec<IndexEntry>> {
+    let mut result = Vec::new();
+    for path in all_paths(old, &[head, merge]) {
+        let (index, head, merge) = (old.get(path), head.get(path), merge.get(path));
+        let entry = if same(index, merge) || same(head, merge) {
+            index
+        } else if same(index, head) {
+            merge
+        } else {
+            return Err(overwritten(path));
+        };
+        result.extend(entry.map(|e| keep_stat(old, e)));
+    }
+    Ok(result)
+}
+
+/// Three-tree merge: paths changed on only one side (or the same way on both) take that
+/// version; the rest are left unmerged with the `base`, `ours` and `theirs` versions at stages
+/// 1, 2 and 3. The index must match `ours` wherever the merge changes it.
+pub(crate) fn three_way(
+    old: &Index,
+    base: &Entries,
+    ours: &Entries,
+    theirs: &Entries,
+) -> Result<Vec<IndexEntry>> {
+    let mut result = Vec::new();
+    for path in all_paths(old, &[base, ours, theirs]) {
+        let index = old.get(path);
+        let (base, ours, theirs) = (base.get(path), ours.get(path), theirs.get(path));
+        let merged = if same(ours, theirs) {
+            Some(ours)
+        } else if same(base, ours) && theirs.is_some() {
+            Some(theirs)
+        } else if same(base, theirs) && ours.is_some() {
+            Some(ours)
+        } else {
+            None
+        };
+        if !same(index, ours) && !matches!(merged, Some(m) if same(index, m)) {
+            return Err(overwritten(path));
+        }
+        match merged {
+            Some(entry) => result.extend(entry.map(|e| keep_stat(old, e))),
+            None => {
+                for (stage, entry) in [(1, base), (2, ours), (3, theirs)] {
+                    if let Some(entry) = entry {
+                        let mut entry = entry.clone();
+                        entry.set_stage(stage);
+                        result.push(entry);
+                    }
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Bring the work tree from the index `old` to the merged `entries`: files whose entry changed
+/// are written and files whose entry is gone are removed. Nothing is touched unless all of them
+/// are up to date with `old`; paths left unmerged keep the work tree file as it is.
+pub(crate) fn update_worktree(
+    git_repo: &GitRepository,
+    old: &Index,
+    entries: &mut [IndexEntry],
+) -> Result<()> {
+    let merged = entries
+        .iter()
+        .map(|e| e.path.as_str())
+        .collect::<BTreeSet<_>>();
+    let removed = old
+        .entries
+        .iter()
+        .filter(|e| !merged.contains(e.path.as_str()))
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+    let changed = (0..entries.len())
+        .filter(|&i| entries[i].stage() == 0 && !same(old.get(&entries[i].path), Some(&entries[i])))
+        .collect::<Vec<_>>();
+
+    for path in removed
+        .iter()
+        .chain(changed.iter().map(|&i| &entries[i].path))
+    {
+        match old.get(path) {
+            Some(entry) => {
+                if let WorktreeState::Modified(..) = worktree_state(git_repo, entry)? {
+                    bail!("Entry '{path}' not uptodate. Cannot merge.");
+                }
+            }
+            None => {
+                if fs::symlink_metadata(git_repo.work_tree().join(path)).is_ok() {
+                    bail!("Untracked working tree file '{path}' would be overwritten by merge.");
+                }
+            }
+        }
+    }
+
+    for path in &removed {
+        remove_worktree_file(git_repo, path)?;
+    }
+    for i in changed {
+        let entry = &mut entries[i];
+        let path = git_repo.work_tree().join(&entry.path);
+        checkout_entry(git_repo, &entry.tree_entry(), &path)?;
+        let meta =
+            fs::symlink_metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+        entry.refresh_stat(&meta);
+    }
+    Ok(())
+}
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    trees: Vec<String>,
+    merge: bool,
+    update: bool,
+    prefix: Option<String>,
+) -> Result<()> {
+    let (old, lock) = Index::lock(repo)?;
+    if trees.len() > 1 && !merge {
+        bail!("reading more than one tree needs -m");
+    }
+    if merge && old.entries.iter().any(|e| e.stage() != 0) {
+        bail!("you need to resolve your current index first");
+    }
+
+    let mut cache_tree = None;
+    let mut entries = match prefix {
+        Some(prefix) => {
+            if trees.len() > 1 {
+                bail!("--prefix takes a single tree");
+            }
+            // the tree is grafted next to the current entries, which must not be in the way
+            let prefix = format!("{}/", prefix.trim_end_matches('/'));
+            let dir = &prefix[..prefix.len() - 1];
+            if let Some(entry) = old
+                .entries
+                .iter()
+                .find(|e| e.path == dir || e.path.starts_with(&prefix))
+            {
+                bail!(
+                    "Entry '{}' overlaps with '{prefix}'. Cannot bind.",
+                    entry.path
+                );
+            }
+            let (tree, _) = tree_entries(repo, &trees[0], &prefix)?;
+            old.entries
+                .iter()
+                .cloned()
+                .chain(tree.into_values())
+                .collect()
+        }
+        None => {
+            let (trees, mut cache_trees): (Vec<_>, Vec<_>) = trees
+                .iter()
+                .map(|tree| tree_entries(repo, tree, ""))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .unzip();
+            // reading a single tree leaves an index of just that tree
+            if cache_trees.len() == 1 {
+                cache_tree = cache_trees.pop();
+            }
+            match trees.as_slice() {
+                [tree] if merge => tree.values().map(|e| keep_stat(&old, e)).collect(),
+                [tree] => tree.values().cloned().collect(),
+                [head, merge] => two_way(&old, head, merge)?,
+                [base, ours, theirs] => three_way(&old, base, ours, theirs)?,
+                _ => bail!("read-tree takes one to three trees"),
+            }
+        }
+    };
+    if update {
+        update_worktree(repo, &old, &mut entries)?;
+    }
+    let mut index = Index::new(repo.hash_algo());
+    index.version = old.version;
+    index.entries = entries;
+    index.sort();
+    if let Some(tree) = cache_tree {
+        index.set_cache_tree(tree);
+    }
+    lock.commit(&index)
+}
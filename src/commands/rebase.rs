@@ -0,0 +1,923 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        am::Resume,
+        checkout::checkout_tree,
+        commit_tree::{identity, write_commit_object},
+        read_tree::{three_way, tree_entries, update_worktree, Entries},
+        rerere,
+        status::Status,
+        write_tree::write_index_tree,
+    },
+    editor::launch_sequence_editor,
+    hash::ObjectId,
+    index::{Index, IndexEntry},
+    merge::merge_text,
+    objects::{is_ancestor, object_find, object_read, read_commit, subject, write_object, Kind},
+    objects::{Commit, ObjectType},
+    refs::{ref_resolve, ref_update, resolve_head, write_head, Head},
+    repository::{repo_path, GitRepository},
+};
+
+/// What a rebase does with one commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Replay it as is.
+    Pick,
+    /// Replay it, then stop so its message can be edited.
+    Reword,
+    /// Fold it into the previous commit, joining the two messages.
+    Squash,
+    /// Fold it into the previous commit, keeping only that one's message.
+    Fixup,
+    /// Leave it out.
+    Drop,
+}
+
+impl Action {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word {
+            "pick" | "p" => Self::Pick,
+            "reword" | "r" => Self::Reword,
+            "squash" | "s" => Self::Squash,
+            "fixup" | "f" => Self::Fixup,
+            "drop" | "d" => Self::Drop,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Pick => "pick",
+            Self::Reword => "reword",
+            Self::Squash => "squash",
+            Self::Fixup => "fixup",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+/// One line of a todo list: an action and the commit it applies to.
+#[derive(Debug, Clone)]
+struct Step {
+    action: Action,
+    commit: String,
+}
+
+/// Parse a todo list in git's format, one `<action> <commit> [<subject>]` per line, where blank
+/// lines and `#` comments are ignored and the commit may be any revision.
+fn parse_todo(git_repo: &GitRepository, todo: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for (number, line) in todo.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let (Some(action), Some(commit)) = (words.next().and_then(Action::parse), words.next())
+        else {
+            bail!("invalid line {}: {line}", number + 1);
+        };
+        let commit = object_find(git_repo, commit.to_string(), ObjectType::Commit)
+            .with_context(|| format!("invalid line {}: {line}", number + 1))?;
+        steps.push(Step { action, commit });
+    }
+    Ok(steps)
+}
+
+/// Where the state of a rebase lives, and the file that marks it: `.git/rebase-merge` for
+/// `rebase -i`, and `.git/rebase-apply` otherwise, which like git's own is told apart from an
+/// `am` session by a `rebasing` file.
+const STATE_DIRS: [(&str, &str); 2] = [
+    ("rebase-merge", "interactive"),
+    ("rebase-apply", "rebasing"),
+];
+
+/// The file the todo list is kept in, as git names it.
+const TODO: &str = "git-rebase-todo";
+
+/// The state of a rebase in one of the [`STATE_DIRS`].
+struct Session {
+    dir: PathBuf,
+    /// The branch being rebased, or `detached HEAD`.
+    head_name: String,
+    orig_head: String,
+    /// The commit the steps are replayed onto.
+    onto: String,
+    /// The steps still to run, the first being the one in progress.
+    todo: Vec<Step>,
+}
+
+impl Session {
+    /// The state directory and marker file of an interactive rebase or a plain one.
+    fn path(git_repo: &GitRepository, interactive: bool) -> Result<(PathBuf, &'static str)> {
+        let (dir, marker) = STATE_DIRS[if interactive { 0 } else { 1 }];
+        Ok((repo_path(git_repo, &[dir])?, marker))
+    }
+
+    fn create(
+        git_repo: &GitRepository,
+        interactive: bool,
+        head_name: &str,
+        orig_head: &str,
+        onto: &str,
+        todo: Vec<Step>,
+    ) -> Result<Self> {
+        let (dir, marker) = Self::path(git_repo, interactive)?;
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        fs::write(dir.join(marker), "")?;
+        fs::write(dir.join("head-name"), format!("{head_name}\n"))?;
+        fs::write(dir.join("orig-head"), format!("{orig_head}\n"))?;
+        fs::write(dir.join("onto"), format!("{onto}\n"))?;
+        let session = Self {
+            dir,
+            head_name: head_name.to_string(),
+            orig_head: orig_head.to_string(),
+            onto: onto.to_string(),
+            todo,
+        };
+        session.save()?;
+        Ok(session)
+    }
+
+    fn load(git_repo: &GitRepository) -> Result<Self> {
+        let mut found = None;
+        for interactive in [true, false] {
+            let (dir, marker) = Self::path(git_repo, interactive)?;
+            if dir.join(marker).is_file() {
+                found = Some(dir);
+                break;
+            }
+        }
+        let Some(dir) = found else {
+            bail!("no rebase in progress");
+        };
+        let read = |name: &str| -> Result<String> {
+            Ok(fs::read_to_string(dir.join(name))
+                .with_context(|| format!("read {}", dir.join(name).display()))?
+                .trim()
+                .to_string())
+        };
+        Ok(Self {
+            head_name: read("head-name")?,
+            orig_head: read("orig-head")?,
+            onto: read("onto")?,
+            todo: parse_todo(git_repo, &read(TODO)?)?,
+            dir,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let todo = self
+            .todo
+            .iter()
+            .map(|step| format!("{} {}\n", step.action.name(), step.commit))
+            .collect::<String>();
+        fs::write(self.dir.join(TODO), todo)?;
+        Ok(())
+    }
+
+    /// Move on past the commit in progress.
+    fn advance(&mut self) -> Result<()> {
+        if !self.todo.is_empty() {
+            self.todo.remove(0);
+        }
+        self.save()
+    }
+}
+
+/// Refuse a todo list whose first commit is folded into a previous one that isn't there.
+fn check_todo(todo: &[Step]) -> Result<()> {
+    let first = todo.iter().find(|step| step.action != Action::Drop);
+    if let Some(step) = first.filter(|step| matches!(step.action, Action::Squash | Action::Fixup)) {
+        bail!("cannot '{}' without a previous commit", step.action.name());
+    }
+    Ok(())
+}
+
+/// What `rebase -i` explains below the todo list.
+const TODO_HELP: &str = "\
+#
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\" but keep only the previous
+#                    commit's log message
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+#
+";
+
+/// Move each commit of `todo` whose subject is `fixup! <target>` or `squash! <target>` to just
+/// after the commit it names (and any others already folded into it), marked to be folded
+/// into it, as `rebase --autosquash` does. The target is an earlier commit with that subject,
+/// else the commit `<target>` names as a revision, else one whose subject starts with it;
+/// commits naming no target, or more than one, stay where they are as picks.
+fn autosquash(git_repo: &GitRepository, todo: Vec<Step>) -> Result<Vec<Step>> {
+    let subjects = todo
+        .iter()
+        .map(|step| {
+            Ok(subject(
+                read_commit(git_repo, &step.commit)?.message.as_bytes(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut folded: Vec<Vec<usize>> = vec![Vec::new(); todo.len()];
+    let mut root = (0..todo.len()).collect::<Vec<_>>();
+    let mut actions = todo.iter().map(|step| step.action).collect::<Vec<_>>();
+    for (i, full) in subjects.iter().enumerate() {
+        let (action, mut target) = match full {
+            s if s.starts_with("fixup! ") => (Action::Fixup, &s[7..]),
+            s if s.starts_with("squash! ") => (Action::Squash, &s[8..]),
+            _ => continue,
+        };
+        // a fixup of a fixup names the same commit
+        while let Some(rest) = target
+            .strip_prefix("fixup! ")
+            .or_else(|| target.strip_prefix("squash! "))
+        {
+            target = rest;
+        }
+        let earlier = || subjects[..i].iter().enumerate();
+        let mut found = earlier()
+            .filter(|(_, s)| *s == target)
+            .map(|(j, _)| j)
+            .collect::<Vec<_>>();
+        if found.is_empty() && !target.contains(char::is_whitespace) {
+            if let Ok(commit) = object_find(git_repo, target.to_string(), ObjectType::Commit) {
+                found.extend(todo[..i].iter().position(|step| step.commit == commit));
+            }
+        }
+        if found.is_empty() {
+            found = earlier()
+                .filter(|(_, s)| s.starts_with(target))
+                .map(|(j, _)| j)
+                .collect();
+        }
+        match found[..] {
+            [j] => {
+                root[i] = root[j];
+                folded[root[j]].push(i);
+                actions[i] = action;
+            }
+            [] => eprintln!("warning: no commit found for '{full}'; leaving it as a pick"),
+            _ => eprintln!("warning: '{full}' matches more than one commit; leaving it as a pick"),
+        }
+    }
+    let mut order = Vec::new();
+    for i in 0..todo.len() {
+        if root[i] == i {
+            order.push(i);
+            order.extend(&folded[i]);
+        }
+    }
+    Ok(order
+        .into_iter()
+        .map(|i| Step {
+            action: actions[i],
+            commit: todo[i].commit.clone(),
+        })
+        .collect())
+}
+
+/// Have the user edit the todo list that picks each of `commits` in the sequence editor, as
+/// `rebase -i` does, and return the steps they leave in it. With `rearrange`, the list starts
+/// out rearranged by [`autosquash`]. The list is edited in the interactive state directory,
+/// which is removed again if it can't be run: when it has an unknown instruction, folds a
+/// commit into nothing, or is left empty.
+fn edit_todo(
+    git_repo: &GitRepository,
+    commits: Vec<String>,
+    rearrange: bool,
+    upstream: &str,
+    orig_head: &str,
+    onto: &str,
+) -> Result<Vec<Step>> {
+    let (dir, _) = Session::path(git_repo, true)?;
+    fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let mut todo = commits
+        .into_iter()
+        .map(|commit| Step {
+            action: Action::Pick,
+            commit,
+        })
+        .collect::<Vec<_>>();
+    if rearrange {
+        todo = autosquash(git_repo, todo)?;
+    }
+    let mut list = String::new();
+    for step in &todo {
+        let message = read_commit(git_repo, &step.commit)?.message;
+        list.push_str(&format!(
+            "{} {} {}\n",
+            step.action.name(),
+            &step.commit[..7],
+            subject(message.as_bytes())
+        ));
+    }
+    list.push_str(&format!(
+        "\n# Rebase {}..{} onto {} ({} command{})\n{TODO_HELP}",
+        &upstream[..7],
+        &orig_head[..7],
+        &onto[..7],
+        todo.len(),
+        if todo.len() == 1 { "" } else { "s" }
+    ));
+    let path = dir.join(TODO);
+    fs::write(&path, list).with_context(|| format!("write {}", path.display()))?;
+
+    let todo = read_edited_todo(git_repo, &path);
+    if todo.is_err() {
+        fs::remove_dir_all(&dir).with_context(|| format!("remove {}", dir.display()))?;
+    }
+    todo
+}
+
+fn read_edited_todo(git_repo: &GitRepository, path: &Path) -> Result<Vec<Step>> {
+    launch_sequence_editor(git_repo, path)?;
+    let todo = fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let todo = parse_todo(git_repo, &todo)?;
+    if todo.is_empty() {
+        bail!("nothing to do");
+    }
+    check_todo(&todo)?;
+    Ok(todo)
+}
+
+/// The commits reachable from `head` but not from `upstream` (all of them without one), parents
+/// before children. Merge commits are left out, so the replayed history is linear.
+pub(crate) fn commits_to_replay(
+    git_repo: &GitRepository,
+    head: &str,
+    upstream: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut excluded = HashSet::new();
+    let mut pending = upstream.into_iter().map(str::to_string).collect::<Vec<_>>();
+    while let Some(commit) = pending.pop() {
+        if excluded.insert(commit.clone()) {
+            pending.extend(read_commit(git_repo, &commit)?.parents);
+        }
+    }
+
+    // depth-first, emitting each commit once all its parents are out
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack = vec![(head.to_string(), false)];
+    while let Some((commit, parents_done)) = stack.pop() {
+        if parents_done {
+            order.push(commit);
+            continue;
+        }
+        if excluded.contains(&commit) || !seen.insert(commit.clone()) {
+            continue;
+        }
+        let parents = read_commit(git_repo, &commit)?.parents;
+        stack.push((commit, true));
+        stack.extend(parents.into_iter().rev().map(|p| (p, false)));
+    }
+    let mut linear = Vec::new();
+    for commit in order {
+        if read_commit(git_repo, &commit)?.parents.len() <= 1 {
+            linear.push(commit);
+        }
+    }
+    Ok(linear)
+}
+
+fn is_regular(entry: &IndexEntry) -> bool {
+    matches!(entry.mode, 0o100644 | 0o100755)
+}
+
+/// Apply the change `commit` made to its first parent on top of HEAD, in the index and work
+/// tree, merging file contents where both sides changed a file. Returns the paths left in
+/// conflict, as [`merge_into_index`] does.
+pub(crate) fn cherry_pick(
+    git_repo: &GitRepository,
+    hash: &str,
+    commit: &Commit,
+) -> Result<Vec<String>> {
+    let label = format!("{} ({})", &hash[..7], subject(commit.message.as_bytes()));
+    merge_into_index(
+        git_repo,
+        commit.parents.first().map(String::as_str),
+        hash,
+        &label,
+    )
+}
+
+/// The mode and content of a file as a merge leaves it.
+pub(crate) type MergedFile = (u32, Vec<u8>);
+
+/// The result of a three-way merge of trees, made without touching the index or work tree.
+pub(crate) struct TreeMerge {
+    /// The merged entries, with conflicted paths left at stages 1 to 3.
+    pub(crate) entries: Vec<IndexEntry>,
+    /// The conflicted paths, with what a merged tree holds for each: the content with conflict
+    /// markers, or the version of the side that kept the file. Paths whose sides can't be
+    /// merged as text (like symlinks) have no content.
+    pub(crate) conflicts: Vec<(String, Option<MergedFile>)>,
+    /// What was done to each path that needed merging, as git reports it.
+    pub(crate) messages: Vec<String>,
+}
+
+/// Merge the changes from the tree of `base` (a commit, or nothing for an empty tree) to that
+/// of `theirs` into the tree of `ours`, merging file contents where both sides changed a file.
+/// Conflict markers are labelled with `labels`, ours first.
+///
+/// `old` is the index the merge would replace, which must match `ours` wherever the merge
+/// changes it; its stat data is kept for unchanged files.
+pub(crate) fn merge_trees(
+    git_repo: &GitRepository,
+    old: &Index,
+    base: Option<&str>,
+    ours: &str,
+    theirs: &str,
+    labels: [&str; 2],
+) -> Result<TreeMerge> {
+    let base = match base {
+        Some(base) => tree_entries(git_repo, base, "")?.0,
+        None => Entries::new(),
+    };
+    let (ours, _) = tree_entries(git_repo, ours, "")?;
+    let (theirs, _) = tree_entries(git_repo, theirs, "")?;
+    let mut entries = three_way(old, &base, &ours, &theirs)?;
+
+    let unmerged = entries
+        .iter()
+        .filter(|e| e.stage() != 0)
+        .map(|e| e.path.clone())
+        .collect::<BTreeSet<_>>();
+    let mut conflicts = Vec::new();
+    let mut messages = Vec::new();
+    for path in unmerged {
+        let stage = |n: u8| {
+            entries
+                .iter()
+                .find(|e| e.path == path && e.stage() == n)
+                .cloned()
+        };
+        let (base, ours, theirs) = (stage(1), stage(2), stage(3));
+        // read-tree leaves a deletion on one side unmerged even when the other side didn't
+        // change the file; the deletion wins
+        let same = |a: &Option<IndexEntry>, b: &Option<IndexEntry>| match (a, b) {
+            (Some(a), Some(b)) => a.hash == b.hash && a.mode == b.mode,
+            (None, None) => true,
+            _ => false,
+        };
+        if same(&base, &ours) || same(&base, &theirs) {
+            entries.retain(|e| e.path != path);
+            if let Some(mut entry) = if same(&base, &ours) { theirs } else { ours } {
+                entry.set_stage(0);
+                entries.push(entry);
+            }
+            continue;
+        }
+        let (Some(ours), Some(theirs)) = (&ours, &theirs) else {
+            let (kept, deleted_in, modified_in) = match (&ours, &theirs) {
+                (Some(kept), _) => (kept, labels[1], labels[0]),
+                (_, Some(kept)) => (kept, labels[0], labels[1]),
+                _ => unreachable!("a side that deleted the file matches the base"),
+            };
+            messages.push(format!(
+                "CONFLICT (modify/delete): {path} deleted in {deleted_in} and modified in \
+                 {modified_in}.  Version {modified_in} of {path} left in tree."
+            ));
+            let content = (kept.mode, read_blob(git_repo, kept)?);
+            conflicts.push((path, Some(content)));
+            continue;
+        };
+        let kind = match base {
+            Some(_) => "content",
+            None => "add/add",
+        };
+        if !is_regular(ours) || !is_regular(theirs) {
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            conflicts.push((path, None));
+            continue;
+        }
+        messages.push(format!("Auto-merging {path}"));
+        let base_data = match &base {
+            Some(base) => read_blob(git_repo, base)?,
+            None => Vec::new(),
+        };
+        let merged = merge_text(
+            &base_data,
+            &read_blob(git_repo, ours)?,
+            &read_blob(git_repo, theirs)?,
+            labels[0],
+            labels[1],
+        );
+        // a mode change made by only one side wins
+        let mode = match &base {
+            Some(base) if base.mode == ours.mode => theirs.mode,
+            _ => ours.mode,
+        };
+        if merged.conflicts > 0 {
+            messages.push(format!("CONFLICT ({kind}): Merge conflict in {path}"));
+            conflicts.push((path, Some((mode, merged.text))));
+            continue;
+        }
+        let blob = ObjectId::from_hex(&write_object(git_repo, Kind::Blob, &merged.text)?)?;
+        entries.retain(|e| e.path != path);
+        entries.push(IndexEntry::without_stat(&path, mode, blob));
+    }
+    Ok(TreeMerge {
+        entries,
+        conflicts,
+        messages,
+    })
+}
+
+/// Merge the changes from `base` (a commit, or nothing for an empty tree) to `theirs` into
+/// HEAD, in the index and work tree, as [`merge_trees`] merges them. Returns the paths left in
+/// conflict, which keep their stages in the index and conflict markers (labelled
+/// `theirs_label` on their side) in the work tree, unless rerere resolved them from a recorded
+/// resolution.
+pub(crate) fn merge_into_index(
+    git_repo: &GitRepository,
+    base: Option<&str>,
+    theirs: &str,
+    theirs_label: &str,
+) -> Result<Vec<String>> {
+    let (old, lock) = Index::lock(git_repo)?;
+    let (index, conflicts) = merge_with_index(git_repo, &old, base, theirs, theirs_label)?;
+    lock.commit(&index)?;
+    rerere::after_conflicts(git_repo, &conflicts)?;
+    Ok(conflicts)
+}
+
+/// Merge into the work tree as [`merge_into_index`] does, from `old`, the index the caller
+/// holds the lock on, returning the new index for the caller to write and the paths left in
+/// conflict.
+pub(crate) fn merge_with_index(
+    git_repo: &GitRepository,
+    old: &Index,
+    base: Option<&str>,
+    theirs: &str,
+    theirs_label: &str,
+) -> Result<(Index, Vec<String>)> {
+    let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit to merge into")?;
+    let TreeMerge {
+        mut entries,
+        conflicts,
+        messages,
+    } = merge_trees(git_repo, old, base, &head, theirs, ["HEAD", theirs_label])?;
+    for message in messages {
+        println!("{message}");
+    }
+
+    update_worktree(git_repo, old, &mut entries)?;
+    for (path, content) in &conflicts {
+        let Some((_, data)) = content else { continue };
+        let full = git_repo.work_tree().join(path);
+        if let Some(parent) = full.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&full, data).with_context(|| format!("write {}", full.display()))?;
+    }
+    let mut index = Index::new(git_repo.hash_algo());
+    index.version = old.version;
+    index.entries = entries;
+    index.sort();
+    let conflicts = conflicts
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect::<Vec<_>>();
+    Ok((index, conflicts))
+}
+
+fn read_blob(git_repo: &GitRepository, entry: &IndexEntry) -> Result<Vec<u8>> {
+    Ok(object_read(git_repo, &entry.hash_hex())?.serialize())
+}
+
+/// Commit the index on top of HEAD with the author and message of `commit`. Nothing is
+/// committed if the index has no changes, as when the change is already upstream.
+fn commit_picked(git_repo: &GitRepository, index: &Index, commit: &Commit) -> Result<()> {
+    let tree = write_index_tree(git_repo, index)?;
+    let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit")?;
+    if read_commit(git_repo, &head)?.tree == tree {
+        return Ok(());
+    }
+    let committer = identity(git_repo, "committer")?.to_string();
+    let new = write_commit_object(
+        git_repo,
+        &tree,
+        std::slice::from_ref(&head),
+        &commit.author,
+        &committer,
+        &commit.message,
+    )?;
+    let reflog = format!("rebase (pick): {}", subject(commit.message.as_bytes()));
+    ref_update(git_repo, "HEAD", &new, Some(&head), &reflog)
+}
+
+/// Replace HEAD with a commit of the index's tree with the same parents and author, and
+/// `message`, as squashing into it and rewording it (the `action`) do.
+fn amend_head(
+    git_repo: &GitRepository,
+    index: &Index,
+    message: &str,
+    action: Action,
+) -> Result<()> {
+    let tree = write_index_tree(git_repo, index)?;
+    let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit")?;
+    let amended = read_commit(git_repo, &head)?;
+    let committer = identity(git_repo, "committer")?.to_string();
+    let new = write_commit_object(
+        git_repo,
+        &tree,
+        &amended.parents,
+        &amended.author,
+        &committer,
+        message,
+    )?;
+    let reflog = format!(
+        "rebase ({}): {}",
+        action.name(),
+        subject(message.as_bytes())
+    );
+    ref_update(git_repo, "HEAD", &new, Some(&head), &reflog)
+}
+
+/// Commit the picked change of `step` from the index, then for `reword` stop so the message
+/// can be edited in the session's `message` file.
+fn finish_step(
+    git_repo: &GitRepository,
+    session: &Session,
+    index: &Index,
+    step: &Step,
+    commit: &Commit,
+) -> Result<()> {
+    match step.action {
+        Action::Pick | Action::Reword => commit_picked(git_repo, index, commit)?,
+        Action::Squash | Action::Fixup => {
+            let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit")?;
+            let previous = read_commit(git_repo, &head)?.message;
+            // the subject of a `squash!` commit only said where it belongs
+            let squashed = match commit.message.strip_prefix("squash! ") {
+                Some(marked) => marked.split_once('\n').map_or("", |(_, body)| body),
+                None => &commit.message,
+            };
+            let message = match step.action {
+                Action::Squash if !squashed.trim().is_empty() => {
+                    format!("{}\n\n{}", previous.trim_end(), squashed.trim_start())
+                }
+                _ => previous,
+            };
+            amend_head(git_repo, index, &message, step.action)?;
+        }
+        Action::Drop => {}
+    }
+    if step.action == Action::Reword {
+        let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit")?;
+        let message = read_commit(git_repo, &head)?.message;
+        fs::write(session.dir.join("message"), message)?;
+        fs::write(session.dir.join("amend"), format!("{head}\n"))?;
+        bail!(
+            "Stopped at {}... {}\nEdit the commit message in {}, then run \"git-rs rebase --continue\".",
+            &step.commit[..7],
+            subject(commit.message.as_bytes()),
+            session.dir.join("message").display()
+        );
+    }
+    Ok(())
+}
+
+/// Run the steps left in `session`, then point the rebased branch at the result.
+fn run(git_repo: &GitRepository, session: &mut Session) -> Result<()> {
+    while let Some(step) = session.todo.first().cloned() {
+        if step.action == Action::Drop {
+            session.advance()?;
+            continue;
+        }
+        let commit = read_commit(git_repo, &step.commit)?;
+        let conflicts = cherry_pick(git_repo, &step.commit, &commit)?;
+        if !conflicts.is_empty() {
+            bail!(
+                "could not apply {}... {}\nResolve all conflicts manually, then run \"git-rs rebase --continue\".\nIf you prefer to skip this commit, run \"git-rs rebase --skip\" instead.\nTo check out the original branch and stop rebasing, run \"git-rs rebase --abort\".",
+                &step.commit[..7],
+                subject(commit.message.as_bytes())
+            );
+        }
+        finish_step(git_repo, session, &Index::read(git_repo)?, &step, &commit)?;
+        session.advance()?;
+    }
+
+    let head = ref_resolve(git_repo, "HEAD")?.context("HEAD has no commit")?;
+    if session.head_name.starts_with("refs/") {
+        let finish = format!(
+            "rebase (finish): {} onto {}",
+            session.head_name, session.onto
+        );
+        ref_update(
+            git_repo,
+            &session.head_name,
+            &head,
+            Some(&session.orig_head),
+            &finish,
+        )?;
+        write_head(
+            git_repo,
+            &Head::Branch(session.head_name.clone(), Some(head)),
+            &format!("rebase (finish): returning to {}", session.head_name),
+        )?;
+    }
+    fs::remove_dir_all(&session.dir)
+        .with_context(|| format!("remove {}", session.dir.display()))?;
+    println!("Successfully rebased and updated {}.", session.head_name);
+    Ok(())
+}
+
+/// Stage the work tree versions of the paths still unmerged in `index`, for `--continue`.
+fn stage_resolved(git_repo: &GitRepository, index: &mut Index) -> Result<()> {
+    let unmerged = index
+        .entries
+        .iter()
+        .filter(|e| e.stage() != 0)
+        .map(|e| (e.path.clone(), e.mode))
+        .collect::<Vec<_>>();
+    for (path, mode) in unmerged {
+        index.entries.retain(|e| e.path != path);
+        let full = git_repo.work_tree().join(&path);
+        let Ok(meta) = fs::symlink_metadata(&full) else {
+            continue;
+        };
+        let data = fs::read(&full).with_context(|| format!("read {}", full.display()))?;
+        if data.windows(8).any(|w| w == b"<<<<<<< ") {
+            bail!("{path} still contains conflict markers");
+        }
+        let hash = ObjectId::from_hex(&write_object(git_repo, Kind::Blob, &data)?)?;
+        index
+            .entries
+            .push(IndexEntry::from_metadata(&path, &meta, mode, hash));
+    }
+    index.sort();
+    Ok(())
+}
+
+/// Rebase the current branch onto `upstream` (or `onto`, if given): replay the commits it has
+/// that `upstream` doesn't on top of the new base, one at a time, and move the branch to the
+/// last one. A conflict stops the rebase until it is resumed with `resume`.
+///
+/// With `interactive`, the todo list of commits to replay is first edited in the sequence
+/// editor (after [`autosquash`] rearranges it, with `autosquash`), and with `todo_file`, it
+/// comes from that file instead, as `rebase -i` would have it edited.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    upstream: Option<String>,
+    onto: Option<String>,
+    interactive: bool,
+    autosquash: bool,
+    todo_file: Option<PathBuf>,
+    resume: Option<Resume>,
+) -> Result<()> {
+    let Some(resume) = resume else {
+        for (name, _) in STATE_DIRS {
+            let dir = repo_path(repo, &[name])?;
+            if dir.exists() {
+                bail!(
+                    "It seems that there is already a {name} directory ({}); use --continue, --skip or --abort",
+                    dir.display()
+                );
+            }
+        }
+        let head = resolve_head(repo)?;
+        let Some(orig_head) = head.commit().map(str::to_string) else {
+            bail!("your current branch does not have any commits yet");
+        };
+        let head_name = match &head {
+            Head::Branch(branch, _) => branch.clone(),
+            Head::Detached(_) => "detached HEAD".to_string(),
+        };
+
+        let (mut index, lock) = Index::lock(repo)?;
+        let (status, _) = Status::collect(repo, Some(&orig_head), &mut index)?;
+        if !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+            bail!("cannot rebase: You have unstaged changes.");
+        }
+        if !status.staged.is_empty() {
+            bail!("cannot rebase: Your index contains uncommitted changes.");
+        }
+        // keep the refreshed stat data
+        lock.commit(&index)?;
+
+        let upstream = upstream.context("no upstream given")?;
+        let upstream = object_find(repo, upstream, ObjectType::Commit)?;
+        let onto = match onto {
+            Some(onto) => object_find(repo, onto, ObjectType::Commit)?,
+            None => upstream.clone(),
+        };
+        // a todo list given in a file is run as an interactive one
+        let interactive = interactive || todo_file.is_some();
+        let todo = match todo_file {
+            Some(path) => {
+                let todo = fs::read_to_string(&path)
+                    .with_context(|| format!("read {}", path.display()))?;
+                let todo = parse_todo(repo, &todo)?;
+                check_todo(&todo)?;
+                todo
+            }
+            None if interactive => {
+                let commits = commits_to_replay(repo, &orig_head, Some(&upstream))?;
+                edit_todo(repo, commits, autosquash, &upstream, &orig_head, &onto)?
+            }
+            None => {
+                if onto == upstream && is_ancestor(repo, &upstream, &orig_head)? {
+                    let name = head_name.strip_prefix("refs/heads/").unwrap_or(&head_name);
+                    println!("Current branch {name} is up to date.");
+                    return Ok(());
+                }
+                commits_to_replay(repo, &orig_head, Some(&upstream))?
+                    .into_iter()
+                    .map(|commit| Step {
+                        action: Action::Pick,
+                        commit,
+                    })
+                    .collect()
+            }
+        };
+        let mut session = Session::create(repo, interactive, &head_name, &orig_head, &onto, todo)?;
+        checkout_tree(repo, &read_commit(repo, &onto)?.tree, false)?;
+        write_head(
+            repo,
+            &Head::Detached(onto.clone()),
+            &format!("rebase (start): checkout {onto}"),
+        )?;
+        return run(repo, &mut session);
+    };
+
+    let mut session = Session::load(repo)?;
+    match resume {
+        Resume::Continue if session.dir.join("amend").exists() => {
+            // stopped to reword the commit just made
+            let message = fs::read_to_string(session.dir.join("message"))
+                .with_context(|| format!("read {}", session.dir.join("message").display()))?;
+            amend_head(repo, &Index::read(repo)?, &message, Action::Reword)?;
+            fs::remove_file(session.dir.join("amend"))?;
+            fs::remove_file(session.dir.join("message"))?;
+        }
+        Resume::Continue => {
+            rerere::record_resolutions(repo)?;
+            let (mut index, lock) = Index::lock(repo)?;
+            stage_resolved(repo, &mut index)?;
+            lock.commit(&index)?;
+            if let Some(step) = session.todo.first() {
+                let head = ref_resolve(repo, "HEAD")?.context("HEAD has no commit")?;
+                if matches!(step.action, Action::Pick | Action::Reword)
+                    && read_commit(repo, &head)?.tree == write_index_tree(repo, &index)?
+                {
+                    bail!("No changes - did you forget to use 'git-rs add'?\nIf there is nothing left to stage, chances are that something else\nalready introduced the same changes; you might want to skip this commit.");
+                }
+                finish_step(
+                    repo,
+                    &session,
+                    &index,
+                    step,
+                    &read_commit(repo, &step.commit)?,
+                )?;
+            }
+        }
+        Resume::Skip if session.dir.join("amend").exists() => {
+            // the commit to reword is kept as it is
+            fs::remove_file(session.dir.join("amend"))?;
+            fs::remove_file(session.dir.join("message"))?;
+        }
+        Resume::Skip => {
+            rerere::clear(repo)?;
+            let head = ref_resolve(repo, "HEAD")?.context("HEAD has no commit")?;
+            checkout_tree(repo, &read_commit(repo, &head)?.tree, true)?;
+        }
+        Resume::Abort => {
+            rerere::clear(repo)?;
+            let orig = session.orig_head.clone();
+            checkout_tree(repo, &read_commit(repo, &orig)?.tree, true)?;
+            // the branch itself only moves once the rebase is done
+            let head = match session.head_name.starts_with("refs/") {
+                true => Head::Branch(session.head_name.clone(), Some(orig)),
+                false => Head::Detached(orig),
+            };
+            write_head(
+                repo,
+                &head,
+                &format!("rebase (abort): returning to {}", session.head_name),
+            )?;
+            return fs::remove_dir_all(&session.dir)
+                .with_context(|| format!("remove {}", session.dir.display()));
+        }
+    }
+    session.advance()?;
+    run(repo, &mut session)
+}
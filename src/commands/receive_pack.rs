@@ -0,0 +1,393 @@
+use std::{
+    fs,
+    io::{BufWriter, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    interrupt::TempPath,
+    objects::{is_ancestor, object_read, write_loose_object, Kind},
+    pack::read_pack_stream,
+    pkt_line::{
+        read_pkt_text, set_trace_identity, write_advertisement, write_flush, write_pkt, Band,
+        Sideband,
+    },
+    refs::{check_ref_format, ref_list, ref_resolve, resolve_head, Head, RefTransaction},
+    repository::{repo_open, repo_path, GitRepository},
+};
+
+const CAPABILITIES: &str =
+    "report-status delete-refs side-band-64k quiet atomic ofs-delta agent=git-rs/0.1.0";
+
+/// One `<old> <new> <ref>` update the client asked for, and why it was refused, if it was.
+struct RefCommand {
+    old: String,
+    new: String,
+    name: String,
+    error: Option<String>,
+    /// The null id of the repository's hash function, which `old` or `new` is for no ref.
+    null: String,
+}
+
+impl RefCommand {
+    fn is_delete(&self) -> bool {
+        self.new == self.null
+    }
+}
+
+/// Where messages for the pushing user go: band 2 of the side-band stream, or stderr.
+struct Messages<'a, W: Write> {
+    out: &'a mut W,
+    sideband: bool,
+}
+
+impl<W: Write> Messages<'_, W> {
+    fn send(&mut self, text: &[u8]) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        if self.sideband {
+            Sideband::new(&mut *self.out, Band::Progress, 65515).write_all(text)?;
+            self.out.flush()?;
+        } else {
+            std::io::stderr().write_all(text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the hook `name` from the repository's `hooks` directory, if there is an executable one,
+/// with `args`, `input` on its stdin and the extra environment `env`. Its output is passed on to
+/// the user. Returns whether the hook accepted (a missing hook always does).
+fn run_hook<W: Write>(
+    git_dir: &Path,
+    name: &str,
+    args: &[&str],
+    input: &str,
+    env: &[(&str, &Path)],
+    messages: &mut Messages<W>,
+) -> Result<bool> {
+    let hook = git_dir.join("hooks").join(name);
+    match fs::metadata(&hook) {
+        Ok(meta) if meta.is_file() && meta.permissions().mode() & 0o111 != 0 => {}
+        _ => return Ok(true),
+    }
+    let mut child = Command::new(&hook)
+        .args(args)
+        .current_dir(git_dir)
+        .env("GIT_DIR", git_dir)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("run {name} hook"))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // a hook that doesn't read its input closes the pipe early, which is fine
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .with_context(|| format!("wait for {name} hook"))?;
+    messages.send(&output.stdout)?;
+    messages.send(&output.stderr)?;
+    Ok(output.status.success())
+}
+
+/// Move the objects received into `quarantine` into the object store at `objects`.
+fn migrate_objects(quarantine: &Path, objects: &Path) -> Result<()> {
+    for dir in fs::read_dir(quarantine).with_context(|| format!("read {}", quarantine.display()))? {
+        let dir = dir?;
+        let target_dir = objects.join(dir.file_name());
+        fs::create_dir_all(&target_dir)
+            .with_context(|| format!("create {}", target_dir.display()))?;
+        for file in fs::read_dir(dir.path())? {
+            let file = file?;
+            let target = target_dir.join(file.file_name());
+            if !target.exists() {
+                fs::rename(file.path(), &target)
+                    .with_context(|| format!("move object to {}", target.display()))?;
+            }
+        }
+    }
+    fs::remove_dir_all(quarantine).with_context(|| format!("remove {}", quarantine.display()))
+}
+
+/// Check `cmd` against the repository's receive rules, returning why it is refused.
+fn check_update(git_repo: &GitRepository, cmd: &RefCommand) -> Result<Option<&'static str>> {
+    if !cmd.name.starts_with("refs/") || !check_ref_format(&cmd.name, false) {
+        return Ok(Some("funny refname"));
+    }
+    let checked_out = !git_repo.is_bare()
+        && matches!(resolve_head(git_repo)?, Head::Branch(branch, _) if branch == cmd.name);
+    let refuses = |key| {
+        !matches!(
+            git_repo
+                .config_get("receive", key)
+                .map(str::to_ascii_lowercase)
+                .as_deref(),
+            Some("ignore" | "warn" | "false" | "no" | "off" | "0")
+        )
+    };
+    if cmd.is_delete() {
+        if git_repo
+            .config_bool("receive", "denyDeletes")
+            .unwrap_or(false)
+        {
+            return Ok(Some("deletion prohibited"));
+        }
+        if checked_out && refuses("denyDeleteCurrent") {
+            return Ok(Some("deletion of the current branch prohibited"));
+        }
+    } else {
+        if checked_out && refuses("denyCurrentBranch") {
+            return Ok(Some("branch is currently checked out"));
+        }
+        if object_read(git_repo, &cmd.new).is_err() {
+            return Ok(Some("missing necessary objects"));
+        }
+        if cmd.old != cmd.null
+            && git_repo
+                .config_bool("receive", "denyNonFastForwards")
+                .unwrap_or(false)
+            && !is_ancestor(git_repo, &cmd.old, &cmd.new).unwrap_or(false)
+        {
+            return Ok(Some("non-fast-forward"));
+        }
+    }
+    let current = ref_resolve(git_repo, &cmd.name)?;
+    let expected = (cmd.old != cmd.null).then_some(cmd.old.as_str());
+    if current.as_deref() != expected {
+        return Ok(Some("failed to update ref"));
+    }
+    Ok(None)
+}
+
+/// Accept a push into the repository at `directory` over stdin and stdout, like
+/// `git receive-pack`: advertise the refs, read the client's ref updates and the pack of new
+/// objects, and apply the updates that pass the checks and hooks, reporting the outcome of each.
+///
+/// The received objects are kept in a quarantine directory until the `pre-receive` hook accepts
+/// the push, so a refused push leaves nothing behind.
+pub(crate) fn invoke(directory: PathBuf) -> Result<()> {
+    set_trace_identity("receive-pack");
+    let repo = repo_open(&directory)?;
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    write_advertisement(&mut out, &ref_list(&repo)?, CAPABILITIES)?;
+
+    let mut commands = Vec::new();
+    let mut client_capabilities = Vec::new();
+    while let Some(line) = read_pkt_text(&mut input)? {
+        let (line, capabilities) = line.split_once('\0').unwrap_or((&line, ""));
+        if commands.is_empty() {
+            client_capabilities = capabilities
+                .split(' ')
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+        }
+        let mut fields = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("protocol error: expected ref update, got {line:?}");
+        };
+        let algo = repo.hash_algo();
+        if !algo.is_hex_id(old) || !algo.is_hex_id(new) {
+            bail!("protocol error: bad ref update {line:?}");
+        }
+        commands.push(RefCommand {
+            old: old.to_string(),
+            new: new.to_string(),
+            name: name.to_string(),
+            error: None,
+            null: algo.null().to_string(),
+        });
+    }
+    if commands.is_empty() {
+        // the client only wanted the advertisement
+        return Ok(());
+    }
+    let has_capability = |name: &str| client_capabilities.iter().any(|c| c == name);
+    let sideband = has_capability("side-band-64k");
+    let atomic = has_capability("atomic");
+
+    let objects = repo_path(&repo, &["objects"])?
+        .canonicalize()
+        .context("find the object directory")?;
+    let git_dir = objects
+        .parent()
+        .context("find the git directory")?
+        .to_path_buf();
+    // removed if receiving fails or is interrupted before it is migrated
+    let quarantine_dir = TempPath::new(objects.join(format!("incoming-{}", std::process::id())));
+    let quarantine = quarantine_dir.path().to_path_buf();
+
+    // unpack into the quarantine; a pack only comes when something isn't a delete
+    let unpacked = if commands.iter().all(RefCommand::is_delete) {
+        Ok(())
+    } else {
+        read_pack_stream(&mut input, repo.hash_algo(), |hash| {
+            let Ok(obj) = object_read(&repo, hash) else {
+                return Ok(None);
+            };
+            let kind = match obj.format() {
+                "commit" => Kind::Commit,
+                "tree" => Kind::Tree,
+                "blob" => Kind::Blob,
+                "tag" => Kind::Tag,
+                other => bail!("object {hash} has unknown type {other}"),
+            };
+            Ok(Some((kind, obj.serialize())))
+        })
+        .and_then(|received| {
+            fs::create_dir_all(&quarantine)
+                .with_context(|| format!("create {}", quarantine.display()))?;
+            for (kind, data) in received {
+                write_loose_object(&quarantine, repo.hash_algo(), kind, &data, true)?;
+            }
+            Ok(())
+        })
+    };
+
+    let mut messages = Messages {
+        out: &mut out,
+        sideband,
+    };
+    let updates = commands
+        .iter()
+        .map(|cmd| format!("{} {} {}\n", cmd.old, cmd.new, cmd.name))
+        .collect::<String>();
+    match &unpacked {
+        Err(_) => {
+            for cmd in &mut commands {
+                cmd.error = Some("unpacker error".to_string());
+            }
+        }
+        Ok(()) => {
+            let accepted = run_hook(
+                &git_dir,
+                "pre-receive",
+                &[],
+                &updates,
+                &[
+                    ("GIT_QUARANTINE_PATH", &quarantine),
+                    ("GIT_OBJECT_DIRECTORY", &quarantine),
+                    ("GIT_ALTERNATE_OBJECT_DIRECTORIES", &objects),
+                ],
+                &mut messages,
+            )?;
+            if !accepted {
+                for cmd in &mut commands {
+                    cmd.error = Some("pre-receive hook declined".to_string());
+                }
+            }
+        }
+    }
+    if commands.iter().any(|cmd| cmd.error.is_some()) {
+        if quarantine.exists() {
+            fs::remove_dir_all(&quarantine)
+                .with_context(|| format!("remove {}", quarantine.display()))?;
+        }
+    } else if quarantine.exists() {
+        migrate_objects(&quarantine, &objects)?;
+    }
+
+    for cmd in commands.iter_mut().filter(|cmd| cmd.error.is_none()) {
+        cmd.error = match check_update(&repo, cmd)? {
+            Some(reason) => Some(reason.to_string()),
+            None if !run_hook(
+                &git_dir,
+                "update",
+                &[&cmd.name, &cmd.old, &cmd.new],
+                "",
+                &[],
+                &mut messages,
+            )? =>
+            {
+                Some("hook declined".to_string())
+            }
+            None => None,
+        };
+    }
+    if atomic && commands.iter().any(|cmd| cmd.error.is_some()) {
+        for cmd in commands.iter_mut().filter(|cmd| cmd.error.is_none()) {
+            cmd.error = Some("atomic push failure".to_string());
+        }
+    }
+
+    // each update happens under its ref's lock, and only if the ref is still where the client
+    // saw it; an atomic push makes them all in one transaction
+    let accepted = (0..commands.len())
+        .filter(|&i| commands[i].error.is_none())
+        .collect::<Vec<_>>();
+    let batches = match atomic {
+        true => vec![accepted],
+        false => accepted.into_iter().map(|i| vec![i]).collect(),
+    };
+    let mut applied = Vec::new();
+    for batch in batches {
+        let mut transaction = RefTransaction::new(&repo, "push");
+        let result = batch
+            .iter()
+            .try_for_each(|&i| {
+                let cmd = &commands[i];
+                transaction.update(&cmd.name, &cmd.new, Some(&cmd.old))
+            })
+            .and_then(|()| transaction.commit());
+        match result {
+            Ok(()) => applied.extend(batch),
+            Err(e) => {
+                messages.send(format!("error: {e:#}\n").as_bytes())?;
+                let reason = match atomic {
+                    true => "atomic push failure",
+                    false => "failed to update ref",
+                };
+                for i in batch {
+                    commands[i].error = Some(reason.to_string());
+                }
+            }
+        }
+    }
+
+    if !applied.is_empty() {
+        let updated = applied
+            .iter()
+            .map(|&i| {
+                let cmd = &commands[i];
+                format!("{} {} {}\n", cmd.old, cmd.new, cmd.name)
+            })
+            .collect::<String>();
+        run_hook(&git_dir, "post-receive", &[], &updated, &[], &mut messages)?;
+    }
+
+    if has_capability("report-status") {
+        let mut report = Vec::new();
+        match &unpacked {
+            Ok(()) => write_pkt(&mut report, b"unpack ok\n")?,
+            Err(e) => write_pkt(&mut report, format!("unpack {e:#}\n").as_bytes())?,
+        }
+        for cmd in &commands {
+            let line = match &cmd.error {
+                None => format!("ok {}\n", cmd.name),
+                Some(reason) => format!("ng {} {reason}\n", cmd.name),
+            };
+            write_pkt(&mut report, line.as_bytes())?;
+        }
+        write_flush(&mut report)?;
+        if sideband {
+            Sideband::new(&mut out, Band::Data, 65515).write_all(&report)?;
+            write_flush(&mut out)?;
+        } else {
+            out.write_all(&report)?;
+        }
+    }
+    out.flush()?;
+    unpacked
+}
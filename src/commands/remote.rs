@@ -0,0 +1,144 @@
+use std::{fs, io::Write};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::fetch::{connect, ref_prefixes, Remote},
+    refs::{ref_list, RefTransaction},
+    repository::{repo_path, GitRepository},
+    transport::Advertisement,
+};
+
+/// One `[+]<src>[:<dst>]` refspec: the refs `src` names on one side go to `dst` on the other,
+/// and with `+` even when that isn't a fast-forward.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Refspec {
+    pub(crate) force: bool,
+    pub(crate) src: String,
+    pub(crate) dst: Option<String>,
+}
+
+impl Refspec {
+    pub(crate) fn parse(spec: &str) -> Self {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst.to_string())),
+            None => (spec, None),
+        };
+        Self {
+            force,
+            src: src.to_string(),
+            dst,
+        }
+    }
+
+    /// Whether the refspec names refs by a `*` pattern.
+    pub(crate) fn is_pattern(&self) -> bool {
+        self.src.contains('*')
+    }
+}
+
+/// The fetch refspecs of `remote`: every `remote.<name>.fetch`, or mapping its branches to
+/// `refs/remotes/<name>/` when there are none.
+pub(crate) fn fetch_refspecs(git_repo: &GitRepository, remote: &str) -> Vec<Refspec> {
+    let configured = git_repo.config_get_all(&format!("remote \"{remote}\""), "fetch");
+    match configured.is_empty() {
+        true => vec![Refspec::parse(&format!(
+            "+refs/heads/*:refs/remotes/{remote}/*"
+        ))],
+        false => configured.into_iter().map(Refspec::parse).collect(),
+    }
+}
+
+/// Map `name` through one side of a refspec (`pattern`, which may hold one `*`) onto the other
+/// side (`other`). Returns `None` if `name` doesn't match `pattern`.
+pub(crate) fn map_refspec(pattern: &str, other: &str, name: &str) -> Option<String> {
+    match (pattern.split_once('*'), other.split_once('*')) {
+        (Some((prefix, suffix)), Some((other_prefix, other_suffix))) => {
+            let middle = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+            Some(format!("{other_prefix}{middle}{other_suffix}"))
+        }
+        (None, None) if name == pattern => Some(other.to_string()),
+        _ => None,
+    }
+}
+
+/// The refs that `refspecs` map a remote ref to which the remote no longer has, according to
+/// `advertisement`, with their hashes. Only refs matching the destination side of a refspec
+/// are considered, so nothing outside the remote's namespaces is touched.
+pub(crate) fn stale_refs(
+    git_repo: &GitRepository,
+    advertisement: &Advertisement,
+    refspecs: &[Refspec],
+) -> Result<Vec<(String, String)>> {
+    let mut stale = Vec::new();
+    for (name, hash) in ref_list(git_repo)? {
+        let sources = refspecs
+            .iter()
+            .filter_map(|refspec| map_refspec(refspec.dst.as_deref()?, &refspec.src, &name))
+            .collect::<Vec<_>>();
+        if sources.is_empty()
+            || sources
+                .iter()
+                .any(|source| advertisement.get(source).is_some())
+        {
+            continue;
+        }
+        // symbolic refs such as `refs/remotes/origin/HEAD` aren't remote branches
+        let is_symref = fs::read_to_string(repo_path(git_repo, &[&name])?)
+            .is_ok_and(|data| data.starts_with("ref: "));
+        if !is_symref {
+            stale.push((name, hash));
+        }
+    }
+    Ok(stale)
+}
+
+/// Delete the `stale` refs together, each only if it is still where it was found, recording
+/// `message` as the reason.
+pub(crate) fn prune_refs(
+    git_repo: &GitRepository,
+    stale: &[(String, String)],
+    message: &str,
+) -> Result<()> {
+    let mut transaction = RefTransaction::new(git_repo, message);
+    for (name, hash) in stale {
+        transaction.delete(name, Some(hash))?;
+    }
+    transaction.commit()
+}
+
+/// Delete the remote-tracking refs of `remote` whose branch no longer exists in the remote
+/// repository. Only refs that one of the remote's fetch refspecs maps to are considered.
+pub(crate) fn invoke_prune(repo: &GitRepository, remote: String, dry_run: bool) -> Result<()> {
+    let section = format!("remote \"{remote}\"");
+    let Some(url) = repo.config_get(&section, "url") else {
+        bail!("No such remote: '{remote}'");
+    };
+    let refspecs = fetch_refspecs(repo, &remote);
+    let prefixes = ref_prefixes(&refspecs, false);
+    let (connection, advertisement) = connect(repo, &Remote::find(repo, &remote), &prefixes)?;
+    connection.disconnect()?;
+    let stale = stale_refs(repo, &advertisement, &refspecs)?;
+    if stale.is_empty() {
+        return Ok(());
+    }
+    if !dry_run {
+        prune_refs(repo, &stale, &format!("remote: prune {remote}"))?;
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    writeln!(stdout, "Pruning {remote}\nURL: {url}")?;
+    for (name, _) in &stale {
+        let short = name.strip_prefix("refs/remotes/").unwrap_or(name);
+        match dry_run {
+            true => writeln!(stdout, " * [would prune] {short}")?,
+            false => writeln!(stdout, " * [pruned] {short}")?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::{collections::HashSet, ffi::OsStr, fs, io::ErrorKind};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::prune::{loose_objects, reachable_objects, remove_loose},
+    pack::{packed_contains, write_pack_files},
+    repository::{repo_path, GitRepository},
+};
+
+/// The files that make up a pack besides its `.pack`, which go when it does.
+const PACK_EXTENSIONS: &[&str] = &["idx", "bitmap", "rev"];
+
+/// Pack objects, like `git repack`: with `all` everything reachable goes into one new pack,
+/// otherwise only the reachable loose objects that aren't packed yet. With `delete` the loose
+/// copies of what was packed are removed, and with `all` the other packs too (but those with a
+/// `.keep` file), which drops the unreachable objects in them. A repository whose objects are
+/// precious refuses `delete`.
+pub(crate) fn invoke(repo: &GitRepository, all: bool, delete: bool, quiet: bool) -> Result<()> {
+    if delete && repo.precious_objects() {
+        bail!("cannot delete packs in a precious-objects repo");
+    }
+    let objects = repo_path(repo, &["objects"])?;
+    let reachable = reachable_objects(repo)?;
+    let hashes = match all {
+        true => reachable,
+        false => {
+            let reachable = reachable.into_iter().collect::<HashSet<_>>();
+            let mut unpacked = Vec::new();
+            for (hash, _) in loose_objects(repo)? {
+                if reachable.contains(&hash) && !packed_contains(&objects, repo.hash_algo(), &hash)?
+                {
+                    unpacked.push(hash);
+                }
+            }
+            unpacked
+        }
+    };
+    let name = match hashes.is_empty() {
+        true => {
+            if !quiet {
+                println!("Nothing new to pack.");
+            }
+            None
+        }
+        false => Some(write_pack_files(repo, &hashes)?),
+    };
+    if !delete {
+        return Ok(());
+    }
+
+    // decided before any pack goes: a loose object whose only other copy is in a pack about
+    // to be deleted has to stay
+    let packed = hashes.into_iter().collect::<HashSet<_>>();
+    let loose = loose_objects(repo)?;
+    let mut redundant = Vec::new();
+    for (hash, path) in &loose {
+        if packed.contains(hash) || (!all && packed_contains(&objects, repo.hash_algo(), hash)?) {
+            redundant.push(path.as_path());
+        }
+    }
+    if all {
+        let dir = objects.join("pack");
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries.collect::<std::io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+        };
+        for entry in entries {
+            let path = entry.path();
+            let redundant = path.extension().is_some_and(|ext| ext == "pack")
+                && path.file_stem() != name.as_deref().map(OsStr::new)
+                && !path.with_extension("keep").exists();
+            if !redundant {
+                continue;
+            }
+            // the index goes first, so that readers stop finding the pack before it is gone
+            for ext in PACK_EXTENSIONS.iter().chain(&["pack"]) {
+                let path = path.with_extension(ext);
+                match fs::remove_file(&path) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => {
+                        return Err(e).with_context(|| format!("remove {}", path.display()))
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    remove_loose(&redundant)
+}
@@ -0,0 +1,274 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    diff::{write_hunks, DiffOptions},
+    index::Index,
+    merge::merge_text,
+    objects::object_read,
+    repository::{repo_path, worktree_path, GitRepository},
+};
+
+/// Whether conflict resolutions are recorded and reused: `rerere.enabled`, or when that isn't
+/// set, whether `rr-cache` exists.
+pub(crate) fn enabled(git_repo: &GitRepository) -> Result<bool> {
+    Ok(match git_repo.config_bool("rerere", "enabled") {
+        Some(enabled) => enabled,
+        None => repo_path(git_repo, &["rr-cache"])?.is_dir(),
+    })
+}
+
+/// Whether `line` is a conflict marker made of `c`: seven of them, followed by a label for the
+/// `<<<<<<<`, `|||||||` and `>>>>>>>` markers, and by nothing for `=======`.
+fn is_marker(line: &[u8], c: u8) -> bool {
+    let Some(rest) = line.strip_prefix([c; 7].as_slice()) else {
+        return false;
+    };
+    match c {
+        b'=' => rest.is_empty() || rest == b"\n",
+        _ => rest.is_empty() || rest[0].is_ascii_whitespace(),
+    }
+}
+
+/// The conflicts in `data`, normalized the way git's rerere normalizes them: marker labels and
+/// base sections are dropped and the two sides of each conflict are put in order, so that the
+/// same conflict gets the same id whichever side it was merged from.
+///
+/// Returns the conflict id and the normalized text, or `None` when `data` has no conflicts.
+fn normalize(data: &[u8]) -> Result<Option<(String, Vec<u8>)>> {
+    enum Section {
+        Outside,
+        Ours,
+        Base,
+        Theirs,
+    }
+    let mut section = Section::Outside;
+    let mut out = Vec::new();
+    let mut hasher = Sha1::new();
+    let mut conflicts = 0;
+    let (mut ours, mut theirs) = (Vec::new(), Vec::new());
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        match section {
+            Section::Outside if is_marker(line, b'<') => section = Section::Ours,
+            Section::Outside => out.extend_from_slice(line),
+            Section::Ours | Section::Base if is_marker(line, b'=') => section = Section::Theirs,
+            Section::Ours if is_marker(line, b'|') => section = Section::Base,
+            Section::Ours => ours.extend_from_slice(line),
+            Section::Base => {}
+            Section::Theirs if is_marker(line, b'>') => {
+                if ours > theirs {
+                    std::mem::swap(&mut ours, &mut theirs);
+                }
+                out.extend_from_slice(b"<<<<<<<\n");
+                out.extend_from_slice(&ours);
+                out.extend_from_slice(b"=======\n");
+                out.extend_from_slice(&theirs);
+                out.extend_from_slice(b">>>>>>>\n");
+                hasher.update(&ours);
+                hasher.update([0]);
+                hasher.update(&theirs);
+                hasher.update([0]);
+                ours.clear();
+                theirs.clear();
+                conflicts += 1;
+                section = Section::Outside;
+            }
+            Section::Theirs => theirs.extend_from_slice(line),
+        }
+    }
+    if !matches!(section, Section::Outside) {
+        bail!("unterminated conflict");
+    }
+    Ok(match conflicts {
+        0 => None,
+        _ => Some((hex::encode(hasher.finalize()), out)),
+    })
+}
+
+/// `rr-cache/<id>/<file>`.
+fn cache_path(git_repo: &GitRepository, id: &str, file: &str) -> Result<PathBuf> {
+    repo_path(git_repo, &["rr-cache", id, file])
+}
+
+/// The conflicts being tracked, as `(id, path)`, from `MERGE_RR`.
+fn read_merge_rr(git_repo: &GitRepository) -> Result<Vec<(String, String)>> {
+    let path = repo_path(git_repo, &["MERGE_RR"])?;
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    data.split(|&b| b == 0)
+        .filter(|record| !record.is_empty())
+        .map(|record| {
+            let record = String::from_utf8_lossy(record);
+            let (id, path) = record.split_once('\t').context("corrupt MERGE_RR")?;
+            Ok((id.to_string(), path.to_string()))
+        })
+        .collect()
+}
+
+fn write_merge_rr(git_repo: &GitRepository, entries: &[(String, String)]) -> Result<()> {
+    let path = repo_path(git_repo, &["MERGE_RR"])?;
+    if entries.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let data = entries
+        .iter()
+        .map(|(id, path)| format!("{id}\t{path}\0"))
+        .collect::<String>();
+    fs::write(&path, data).with_context(|| format!("write {}", path.display()))
+}
+
+/// After a merge left `paths` in conflict: record the preimage of conflicts not seen before,
+/// and resolve the ones a resolution was recorded for the way they were resolved then. The
+/// resolved files are left unstaged.
+pub(crate) fn after_conflicts(git_repo: &GitRepository, paths: &[String]) -> Result<()> {
+    if !enabled(git_repo)? {
+        return Ok(());
+    }
+    let mut tracked = read_merge_rr(git_repo)?;
+    for path in paths {
+        let full = git_repo.work_tree().join(path);
+        let Ok(data) = fs::read(&full) else {
+            continue;
+        };
+        let Ok(Some((id, preimage))) = normalize(&data) else {
+            continue;
+        };
+        tracked.retain(|(_, p)| p != path);
+        let postimage = cache_path(git_repo, &id, "postimage")?;
+        if postimage.is_file() {
+            let recorded = fs::read(cache_path(git_repo, &id, "preimage")?).unwrap_or_default();
+            let resolution =
+                fs::read(&postimage).with_context(|| format!("read {}", postimage.display()))?;
+            let merged = merge_text(&recorded, &preimage, &resolution, "", "");
+            if merged.conflicts == 0 {
+                fs::write(&full, merged.text)
+                    .with_context(|| format!("write {}", full.display()))?;
+                println!("Resolved '{path}' using previous resolution.");
+                continue;
+            }
+        } else {
+            let dir = repo_path(git_repo, &["rr-cache", &id])?;
+            fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+            fs::write(cache_path(git_repo, &id, "preimage")?, &preimage)?;
+            println!("Recorded preimage for '{path}'");
+        }
+        tracked.push((id, path.clone()));
+    }
+    write_merge_rr(git_repo, &tracked)
+}
+
+/// Record the resolution of every tracked conflict whose file no longer has conflict markers,
+/// and stop tracking it.
+pub(crate) fn record_resolutions(git_repo: &GitRepository) -> Result<()> {
+    let mut remaining = Vec::new();
+    for (id, path) in read_merge_rr(git_repo)? {
+        let full = git_repo.work_tree().join(&path);
+        let Ok(data) = fs::read(&full) else {
+            continue;
+        };
+        if !matches!(normalize(&data), Ok(None)) {
+            remaining.push((id, path));
+            continue;
+        }
+        let dir = repo_path(git_repo, &["rr-cache", &id])?;
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        fs::write(cache_path(git_repo, &id, "postimage")?, data)?;
+        println!("Recorded resolution for '{path}'.");
+    }
+    write_merge_rr(git_repo, &remaining)
+}
+
+/// Stop tracking the current conflicts, dropping the preimages no resolution was recorded for,
+/// as when the merge that made them is abandoned.
+pub(crate) fn clear(git_repo: &GitRepository) -> Result<()> {
+    for (id, _) in read_merge_rr(git_repo)? {
+        if !cache_path(git_repo, &id, "postimage")?.exists() {
+            let dir = repo_path(git_repo, &["rr-cache", &id])?;
+            fs::remove_dir_all(&dir).with_context(|| format!("remove {}", dir.display()))?;
+        }
+    }
+    write_merge_rr(git_repo, &[])
+}
+
+/// `rerere` with no subcommand: record the resolutions made so far.
+pub(crate) fn invoke(repo: &GitRepository) -> Result<()> {
+    if !enabled(repo)? {
+        return Ok(());
+    }
+    record_resolutions(repo)
+}
+
+/// Print the paths whose conflicts rerere is tracking.
+pub(crate) fn invoke_status(repo: &GitRepository) -> Result<()> {
+    for (_, path) in read_merge_rr(repo)? {
+        println!("{path}");
+    }
+    Ok(())
+}
+
+/// Show how each tracked file has changed since its conflict was recorded.
+pub(crate) fn invoke_diff(repo: &GitRepository) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (id, path) in read_merge_rr(repo)? {
+        let preimage = fs::read(cache_path(repo, &id, "preimage")?).unwrap_or_default();
+        let current = fs::read(repo.work_tree().join(&path)).unwrap_or_default();
+        writeln!(stdout, "--- a/{path}")?;
+        writeln!(stdout, "+++ b/{path}")?;
+        write_hunks(&mut stdout, &preimage, &current, &DiffOptions::default())?;
+    }
+    Ok(())
+}
+
+/// Forget the recorded resolution of the conflict in `path`, which must still be unmerged in
+/// the index, so that the next resolution is recorded in its place.
+pub(crate) fn invoke_forget(repo: &GitRepository, path: String) -> Result<()> {
+    let path = worktree_path(repo, &path)?;
+    let index = Index::read(repo)?;
+    let stage = |n: u8| -> Result<Vec<u8>> {
+        match index
+            .entries
+            .iter()
+            .find(|e| e.path == path && e.stage() == n)
+        {
+            Some(entry) => Ok(object_read(repo, &entry.hash_hex())?.serialize()),
+            None => Ok(Vec::new()),
+        }
+    };
+    if !index
+        .entries
+        .iter()
+        .any(|e| e.path == path && e.stage() != 0)
+    {
+        bail!("pathspec '{path}' did not match any unmerged path");
+    }
+    // recreate the conflict from the index to find its id
+    let conflict = merge_text(&stage(1)?, &stage(2)?, &stage(3)?, "", "");
+    let Some((id, preimage)) = normalize(&conflict.text)? else {
+        bail!("could not parse conflict hunks in '{path}'");
+    };
+    let postimage = cache_path(repo, &id, "postimage")?;
+    if postimage.exists() {
+        fs::remove_file(&postimage).with_context(|| format!("remove {}", postimage.display()))?;
+        println!("Forgot resolution for '{path}'");
+    }
+    let dir = repo_path(repo, &["rr-cache", &id])?;
+    fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    fs::write(cache_path(repo, &id, "preimage")?, preimage)?;
+    println!("Updated preimage for '{path}'");
+
+    let mut tracked = read_merge_rr(repo)?;
+    tracked.retain(|(_, p)| *p != path);
+    tracked.push((id, path));
+    write_merge_rr(repo, &tracked)
+}
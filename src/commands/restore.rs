@@ -0,0 +1,124 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::checkout::{checkout_entry, remove_worktree_file},
+    index::{Index, IndexEntry},
+    objects::{object_find, peel_to, read_tree_recursive, Kind, ObjectType, TreeEntry},
+    repository::{worktree_path, GitRepository},
+    worktree::path_matches,
+};
+
+/// `git restore`: put the files at `paths` back the way `source` has them. The work tree is
+/// restored unless only `staged` is given; with `staged`, the index is. The source is the
+/// commit `source` when given, or else HEAD for the index and the index for the work tree.
+///
+/// Tracked files under `paths` that the source lacks are removed, as git's default no-overlay
+/// mode does.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    paths: Vec<String>,
+    source: Option<String>,
+    staged: bool,
+    worktree: bool,
+) -> Result<()> {
+    let worktree = worktree || !staged;
+    let specs = paths
+        .iter()
+        .map(|path| worktree_path(repo, path))
+        .collect::<Result<Vec<_>>>()?;
+    let wanted = |path: &str| specs.iter().any(|spec| path_matches(spec, path));
+
+    let (mut index, lock) = Index::lock(repo)?;
+    let source = source.or_else(|| staged.then(|| "HEAD".to_string()));
+    let entries = match &source {
+        Some(rev) => {
+            let commit = object_find(repo, rev.clone(), ObjectType::Commit)?;
+            let tree = peel_to(repo, &commit, Kind::Tree)?;
+            read_tree_recursive(repo, &tree, "")?
+                .into_iter()
+                .filter(|e| wanted(&e.name))
+                .collect::<Vec<_>>()
+        }
+        None => {
+            let mut entries = Vec::new();
+            for entry in index.entries.iter().filter(|e| wanted(&e.path)) {
+                if index.get(&entry.path).is_none() {
+                    bail!("path '{}' is unmerged", entry.path);
+                }
+                if entry.stage() == 0 && !entry.skip_worktree() {
+                    entries.push(entry.tree_entry());
+                }
+            }
+            entries
+        }
+    };
+    for (spec, path) in specs.iter().zip(&paths) {
+        let known = entries.iter().any(|e| path_matches(spec, &e.name))
+            || index.entries.iter().any(|e| path_matches(spec, &e.path));
+        if !known {
+            bail!("pathspec '{path}' did not match any file(s) known to git");
+        }
+    }
+
+    // tracked files a commit doesn't have go away
+    let gone = index
+        .entries
+        .iter()
+        .filter(|e| source.is_some() && wanted(&e.path))
+        .filter(|e| !entries.iter().any(|s| s.name == e.path))
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+    for path in &gone {
+        if worktree {
+            remove_worktree_file(repo, path)?;
+        }
+        if staged {
+            index.remove(path);
+        }
+    }
+
+    for entry in &entries {
+        if worktree {
+            restore_file(repo, &mut index, entry, staged)?;
+        } else {
+            let index_entry = IndexEntry::from_tree_entry(entry)?;
+            // an unchanged entry keeps its stat data
+            let unchanged = index
+                .get(&entry.name)
+                .is_some_and(|e| e.hash == index_entry.hash && e.mode == index_entry.mode);
+            if !unchanged {
+                index.add(index_entry);
+            }
+        }
+    }
+    lock.commit(&index)
+}
+
+/// Write `entry` to the work tree. The index gets it too with `staged`, and otherwise an index
+/// entry already holding this content gets the new file's stat data, so it doesn't look
+/// modified.
+fn restore_file(
+    repo: &GitRepository,
+    index: &mut Index,
+    entry: &TreeEntry,
+    staged: bool,
+) -> Result<()> {
+    let path = repo.work_tree().join(&entry.name);
+    checkout_entry(repo, entry, &path)?;
+    let meta = fs::symlink_metadata(&path).with_context(|| format!("stat {}", path.display()))?;
+    let mut index_entry = IndexEntry::from_tree_entry(entry)?;
+    if staged {
+        index_entry.refresh_stat(&meta);
+        index.add(index_entry);
+    } else if let Some(old) = index
+        .entries
+        .iter_mut()
+        .find(|e| e.path == entry.name && e.stage() == 0)
+        .filter(|e| e.hash == index_entry.hash && e.mode == index_entry.mode)
+    {
+        old.refresh_stat(&meta);
+    }
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use anyhow::Result;
+
+use crate::{
+    decorate::ref_index,
+    objects::{object_find, ObjectType},
+    repository::GitRepository,
+};
+
+/// Print the object each of `revs` names. With `symbolic_full_name`, print the full name of
+/// the ref each one means instead (`refs/heads/main` for `main` or for `HEAD` on `main`), and
+/// nothing for revisions that aren't refs.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    revs: Vec<String>,
+    symbolic_full_name: bool,
+) -> Result<()> {
+    let index = ref_index(repo)?;
+    for rev in revs {
+        if symbolic_full_name {
+            if let Some(name) = index.full_name(&rev) {
+                println!("{name}");
+            }
+            continue;
+        }
+        // a ref names its own object, which for an annotated tag is the tag
+        let hash = match index.resolve(&rev) {
+            Some(tip) => tip.hash.clone(),
+            None => object_find(repo, rev, ObjectType::Commit)?,
+        };
+        println!("{hash}");
+    }
+    Ok(())
+}
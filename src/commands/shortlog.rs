@@ -0,0 +1,63 @@
+use std::{collections::BTreeMap, io::Write};
+
+use anyhow::Result;
+
+use crate::{
+    commands::log::start_walk, mailmap::Mailmap, objects::subject, repository::GitRepository,
+    signature::Signature,
+};
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    revs: Vec<String>,
+    summary: bool,
+    numbered: bool,
+    email: bool,
+    use_mailmap: bool,
+) -> Result<()> {
+    let mailmap = if use_mailmap {
+        Mailmap::load(repo)?
+    } else {
+        Mailmap::default()
+    };
+
+    // subjects by author, newest first as the walk finds them
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in start_walk(repo, revs)? {
+        let (_, commit) = entry?;
+        let Some(author) = Signature::parse(&commit.author) else {
+            continue;
+        };
+        let (name, mapped_email) = mailmap.map_identity(&author.name, &author.email);
+        let key = if email {
+            format!("{name} <{mapped_email}>")
+        } else {
+            name
+        };
+        authors
+            .entry(key)
+            .or_default()
+            .push(subject(commit.message.as_bytes()));
+    }
+
+    let mut authors = authors.into_iter().collect::<Vec<_>>();
+    if numbered {
+        // stable, so authors with equal counts stay sorted by name
+        authors.sort_by_key(|(_, subjects)| std::cmp::Reverse(subjects.len()));
+    }
+
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    for (author, subjects) in authors {
+        if summary {
+            writeln!(stdout, "{:6}\t{author}", subjects.len())?;
+            continue;
+        }
+        writeln!(stdout, "{author} ({}):", subjects.len())?;
+        for subject in subjects.iter().rev() {
+            writeln!(stdout, "      {subject}")?;
+        }
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
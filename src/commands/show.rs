@@ -0,0 +1,28 @@
+use anyhow::Result;
+
+use crate::{
+    commands::log::{write_commits, PrettyOptions},
+    objects::{object_find, read_commit, ObjectType},
+    repository::GitRepository,
+};
+
+/// Show each of `revs` (HEAD by default) as `pretty` says, without walking on to their parents
+/// as `log` would. A tag is shown as the commit it points at, without the tag's own message.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    revs: Vec<String>,
+    mut pretty: PrettyOptions,
+    paginate: bool,
+) -> Result<()> {
+    let revs = match revs.is_empty() {
+        true => vec!["HEAD".to_string()],
+        false => revs,
+    };
+    let commits = revs.into_iter().map(|rev| {
+        let hash = object_find(repo, rev, ObjectType::Commit)?;
+        let commit = read_commit(repo, &hash)?;
+        Ok((hash, commit))
+    });
+    pretty.merge_changes = true;
+    write_commits(repo, commits, pretty, paginate)
+}
@@ -0,0 +1,57 @@
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::{commands::ls_remote::peel_tag, refs::ref_list, repository::GitRepository, ExitStatus};
+
+/// Whether `name` is selected by `pattern`: like git, the pattern has to match whole trailing
+/// components, so `main` matches `refs/heads/main` but not `refs/heads/domain`.
+fn ref_matches(pattern: &str, name: &str) -> bool {
+    name == pattern
+        || name
+            .strip_suffix(pattern)
+            .is_some_and(|rest| rest.ends_with('/'))
+}
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    patterns: Vec<String>,
+    heads: bool,
+    tags: bool,
+    dereference: bool,
+    hash_only: bool,
+) -> Result<()> {
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut found = false;
+    for (name, hash) in ref_list(repo)? {
+        if (heads || tags)
+            && !(heads && name.starts_with("refs/heads/") || tags && name.starts_with("refs/tags/"))
+        {
+            continue;
+        }
+        if !patterns.is_empty() && !patterns.iter().any(|p| ref_matches(p, &name)) {
+            continue;
+        }
+        found = true;
+        match hash_only {
+            true => writeln!(stdout, "{hash}")?,
+            false => writeln!(stdout, "{hash} {name}")?,
+        }
+        if !dereference {
+            continue;
+        }
+        if let Some(peeled) = peel_tag(repo, &hash)? {
+            match hash_only {
+                true => writeln!(stdout, "{peeled}")?,
+                false => writeln!(stdout, "{peeled} {name}^{{}}")?,
+            }
+        }
+    }
+    stdout.flush()?;
+    if !found {
+        // like git, finding nothing is reported only through the exit status
+        return Err(ExitStatus(1).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::{fs, io::Write};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::{
+        checkout::reset_locked_to_tree,
+        commit_tree::{identity, write_commit_object},
+        rebase::merge_with_index,
+        rerere,
+        write_tree::write_index_tree,
+    },
+    index::Index,
+    lockfile::write_locked,
+    objects::{read_commit, subject},
+    refs::{ref_delete, ref_update, resolve_head, Head},
+    repository::{repo_path, GitRepository},
+    ExitStatus,
+};
+
+/// The ref pointing at the newest stash; its reflog lists them all.
+const STASH_REF: &str = "refs/stash";
+
+/// An entry of the stash reflog.
+struct Entry {
+    /// The stash before this one, or the null id for the first.
+    old: String,
+    /// The stash commit.
+    new: String,
+    /// Who stashed it and when, as `Name <email> time tz`.
+    ident: String,
+    message: String,
+}
+
+/// The entries of the stash reflog, oldest first as the file stores them.
+fn read_log(git_repo: &GitRepository) -> Result<Vec<Entry>> {
+    let path = repo_path(git_repo, &["logs", STASH_REF])?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    let mut entries = Vec::new();
+    for line in text.lines() {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let mut fields = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(ident)) = (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("malformed stash reflog line {line:?}");
+        };
+        entries.push(Entry {
+            old: old.to_string(),
+            new: new.to_string(),
+            ident: ident.to_string(),
+            message: message.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Replace the stash reflog with `entries`, pointing `refs/stash` at the newest one, or removing
+/// both when there are none left.
+fn write_log(git_repo: &GitRepository, entries: &[Entry]) -> Result<()> {
+    let path = repo_path(git_repo, &["logs", STASH_REF])?;
+    let Some(newest) = entries.last() else {
+        ref_delete(git_repo, STASH_REF)?;
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("remove {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    };
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&format!(
+            "{} {} {}\t{}\n",
+            entry.old, entry.new, entry.ident, entry.message
+        ));
+    }
+    // the update appends to the reflog, which is then replaced as a whole
+    ref_update(git_repo, STASH_REF, &newest.new, None, &newest.message)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    write_locked(&path, text.as_bytes())
+}
+
+/// The position in [`read_log`]'s entries of the stash named `spec` (`stash@{n}` or `n`, the
+/// newest by default), with the name it is shown by.
+fn find(entries: &[Entry], spec: Option<&str>) -> Result<(usize, String)> {
+    let Some(spec) = spec else {
+        return match entries.len() {
+            0 => bail!("No stash entries found."),
+            n => Ok((n - 1, format!("{STASH_REF}@{{0}}"))),
+        };
+    };
+    let n = spec
+        .strip_prefix("refs/")
+        .unwrap_or(spec)
+        .strip_prefix("stash@{")
+        .and_then(|n| n.strip_suffix('}'))
+        .unwrap_or(spec);
+    let Some(i) = n.parse::<usize>().ok().filter(|&n| n < entries.len()) else {
+        bail!("{spec} is not a valid reference");
+    };
+    Ok((entries.len() - 1 - i, format!("stash@{{{i}}}")))
+}
+
+/// Save the local changes of the index and work tree as a stash, then reset both to HEAD. The
+/// stash is a commit of the work tree whose parents are HEAD and a commit of the index.
+pub(crate) fn invoke_push(repo: &GitRepository, message: Option<String>) -> Result<()> {
+    let head = resolve_head(repo)?;
+    let Some(head_commit) = head.commit().map(str::to_string) else {
+        bail!("You do not have the initial commit yet");
+    };
+    let head_tree = read_commit(repo, &head_commit)?.tree;
+
+    let (index, lock) = Index::lock(repo)?;
+    if index.entries.iter().any(|e| e.stage() != 0) {
+        bail!("cannot save the current index state: you have unmerged paths");
+    }
+    let index_tree = write_index_tree(repo, &index)?;
+    let mut worktree = index.clone();
+    worktree.stage_tracked(repo)?;
+    let worktree_tree = write_index_tree(repo, &worktree)?;
+    if index_tree == head_tree && worktree_tree == head_tree {
+        println!("No local changes to save");
+        return Ok(());
+    }
+
+    let branch = match &head {
+        Head::Branch(name, _) => name.strip_prefix("refs/heads/").unwrap_or(name),
+        Head::Detached(_) => "(no branch)",
+    };
+    let head_subject = subject(read_commit(repo, &head_commit)?.message.as_bytes());
+    let on = format!("{branch}: {} {head_subject}", &head_commit[..7]);
+    let message = match message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {on}"),
+    };
+    let author = identity(repo, "author")?.to_string();
+    let committer = identity(repo, "committer")?.to_string();
+    let index_commit = write_commit_object(
+        repo,
+        &index_tree,
+        std::slice::from_ref(&head_commit),
+        &author,
+        &committer,
+        &format!("index on {on}\n"),
+    )?;
+    let stash = write_commit_object(
+        repo,
+        &worktree_tree,
+        &[head_commit, index_commit],
+        &author,
+        &committer,
+        &format!("{message}\n"),
+    )?;
+
+    let mut entries = read_log(repo)?;
+    entries.push(Entry {
+        old: entries
+            .last()
+            .map_or_else(|| repo.hash_algo().null().to_string(), |e| e.new.clone()),
+        new: stash,
+        ident: committer,
+        message: message.clone(),
+    });
+    write_log(repo, &entries)?;
+
+    reset_locked_to_tree(repo, &index, lock, &head_tree)?;
+    println!("Saved working directory and index state {message}");
+    Ok(())
+}
+
+/// List the stashes, newest first.
+pub(crate) fn invoke_list(repo: &GitRepository) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    for (i, entry) in read_log(repo)?.iter().rev().enumerate() {
+        writeln!(stdout, "stash@{{{i}}}: {}", entry.message)?;
+    }
+    Ok(())
+}
+
+/// Apply the changes of the stash `spec` (the newest by default) to the work tree, merging them
+/// with the commits made since. The changes are left unstaged, except that files the stash adds
+/// are staged. Returns whether it applied without conflicts.
+fn apply(repo: &GitRepository, spec: Option<&str>) -> Result<bool> {
+    let entries = read_log(repo)?;
+    let (i, _) = find(&entries, spec)?;
+    let stash = read_commit(repo, &entries[i].new)?;
+    let base = stash
+        .parents
+        .first()
+        .with_context(|| format!("{} is not a stash commit", entries[i].new))?;
+
+    let (before, lock) = Index::lock(repo)?;
+    if before.entries.iter().any(|e| e.stage() != 0) {
+        bail!("Cannot apply a stash in the middle of a merge");
+    }
+    let head_tree = resolve_head(repo)?
+        .commit()
+        .map(|head| read_commit(repo, head))
+        .transpose()?
+        .map(|commit| commit.tree);
+    if head_tree.as_deref() != Some(write_index_tree(repo, &before)?.as_str()) {
+        bail!("Cannot apply stash: your index contains uncommitted changes");
+    }
+
+    let (merged, conflicts) =
+        merge_with_index(repo, &before, Some(base), &entries[i].new, "Stash")?;
+    if !conflicts.is_empty() {
+        lock.commit(&merged)?;
+        rerere::after_conflicts(repo, &conflicts)?;
+        return Ok(false);
+    }
+    // only the files the stash adds stay staged
+    let mut index = before;
+    for entry in merged.entries {
+        if index.get(&entry.path).is_none() {
+            index.add(entry);
+        }
+    }
+    lock.commit(&index)?;
+    Ok(true)
+}
+
+/// Apply the stash `spec` (the newest by default) and keep it.
+pub(crate) fn invoke_apply(repo: &GitRepository, spec: Option<String>) -> Result<()> {
+    if !apply(repo, spec.as_deref())? {
+        return Err(ExitStatus(1).into());
+    }
+    Ok(())
+}
+
+/// Remove the stash `spec` (the newest by default) from the stash list.
+pub(crate) fn invoke_drop(repo: &GitRepository, spec: Option<String>) -> Result<()> {
+    let mut entries = read_log(repo)?;
+    let (i, name) = find(&entries, spec.as_deref())?;
+    let dropped = entries.remove(i);
+    // the entry after the dropped one now follows the one before it
+    if let Some(next) = entries.get_mut(i) {
+        next.old = dropped.old;
+    }
+    write_log(repo, &entries)?;
+    println!("Dropped {name} ({})", dropped.new);
+    Ok(())
+}
+
+/// Apply the stash `spec` (the newest by default) and drop it, unless applying it conflicted.
+pub(crate) fn invoke_pop(repo: &GitRepository, spec: Option<String>) -> Result<()> {
+    if !apply(repo, spec.as_deref())? {
+        eprintln!("The stash entry is kept in case you need it again.");
+        return Err(ExitStatus(1).into());
+    }
+    invoke_drop(repo, spec)
+}
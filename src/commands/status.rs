@@ -0,0 +1,494 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Write,
+};
+
+use anyhow::Result;
+
+use crate::{
+    commands::branch::{ahead_behind, short_ref_name, upstream},
+    diff::{self, detect_renames, quote_path, BlobCache, DiffFile},
+    index::{stat_matches, worktree_state, Index, IndexEntry, WorktreeState},
+    objects::{read_commit, read_tree_recursive, Mode, TreeEntry},
+    refs::{ref_resolve, resolve_head, Head},
+    repository::{optional_locks_allowed, worktree_path, GitRepository},
+    worktree::{parallel_map, UntrackedCache, WorktreeWalker},
+};
+
+/// How similar a staged addition must be to a deletion, in percent, to be shown as a rename.
+const RENAME_THRESHOLD: u8 = 50;
+
+/// How a path differs between two versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Change {
+    Added,
+    Modified,
+    Deleted,
+    /// A file became a symlink or the other way around.
+    TypeChanged,
+    /// Added as a copy of a deleted file, perhaps with changes.
+    Renamed,
+}
+
+impl Change {
+    fn label(self) -> &'static str {
+        match self {
+            Change::Added => "new file:",
+            Change::Modified => "modified:",
+            Change::Deleted => "deleted:",
+            Change::TypeChanged => "typechange:",
+            Change::Renamed => "renamed:",
+        }
+    }
+
+    /// The letter for this change in porcelain output.
+    fn code(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::TypeChanged => 'T',
+            Change::Renamed => 'R',
+        }
+    }
+
+    fn between(old: u32, new: u32) -> Self {
+        if old & 0o170000 == new & 0o170000 {
+            Change::Modified
+        } else {
+            Change::TypeChanged
+        }
+    }
+}
+
+/// The stages a conflicted path has in the index, as a bit set of stages 1, 2 and 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Unmerged(pub(crate) u8);
+
+impl Unmerged {
+    fn label(self) -> &'static str {
+        match self.0 {
+            0b001 => "both deleted:",
+            0b010 => "added by us:",
+            0b011 => "deleted by them:",
+            0b100 => "added by them:",
+            0b101 => "deleted by us:",
+            0b110 => "both added:",
+            _ => "both modified:",
+        }
+    }
+
+    /// The two letters for this conflict in porcelain output.
+    fn codes(self) -> &'static str {
+        match self.0 {
+            0b001 => "DD",
+            0b010 => "AU",
+            0b011 => "UD",
+            0b100 => "UA",
+            0b101 => "DU",
+            0b110 => "AA",
+            _ => "UU",
+        }
+    }
+}
+
+/// How HEAD, the index and the work tree differ.
+#[derive(Debug, Default)]
+pub(crate) struct Status {
+    /// Index changes relative to HEAD.
+    pub(crate) staged: Vec<(String, Change)>,
+    /// The path each staged rename was renamed from, by its new path.
+    pub(crate) renamed_from: HashMap<String, String>,
+    /// Work tree changes relative to the index.
+    pub(crate) unstaged: Vec<(String, Change)>,
+    pub(crate) unmerged: Vec<(String, Unmerged)>,
+    pub(crate) untracked: Vec<String>,
+}
+
+impl Status {
+    /// Compare HEAD, the index and the work tree of `git_repo`. Stat data of unchanged files is
+    /// refreshed in `index` along the way; returns whether any was.
+    pub(crate) fn collect(
+        git_repo: &GitRepository,
+        head: Option<&str>,
+        index: &mut Index,
+    ) -> Result<(Self, bool)> {
+        let mut status = Self::default();
+
+        let head_tree = match head {
+            Some(commit) => Some(read_commit(git_repo, commit)?.tree),
+            None => None,
+        };
+        // an index whose cached root tree is HEAD's has nothing staged, and no conflicts either
+        let cached = index
+            .cache_tree()
+            .and_then(|tree| tree.valid)
+            .map(|(_, hash)| hex::encode(hash));
+        if head_tree.is_none() || cached != head_tree {
+            let head_tree = match &head_tree {
+                Some(tree) => read_tree_recursive(git_repo, tree, "")?,
+                None => Vec::new(),
+            };
+            let mut head_entries = head_tree
+                .iter()
+                .map(|e| (e.name.as_str(), e))
+                .collect::<BTreeMap<_, _>>();
+            let mut stages: BTreeMap<&str, u8> = BTreeMap::new();
+            for entry in &index.entries {
+                if entry.stage() != 0 {
+                    *stages.entry(&entry.path).or_default() |= 1 << (entry.stage() - 1);
+                    head_entries.remove(entry.path.as_str());
+                    continue;
+                }
+                let change = match head_entries.remove(entry.path.as_str()) {
+                    None => Some(Change::Added),
+                    Some(old) if old.hash != entry.hash_hex() || old.mode.bits() != entry.mode => {
+                        Some(Change::between(old.mode.bits(), entry.mode))
+                    }
+                    Some(_) => None,
+                };
+                status
+                    .staged
+                    .extend(change.map(|c| (entry.path.clone(), c)));
+            }
+            status.staged.extend(
+                head_entries
+                    .keys()
+                    .map(|path| (path.to_string(), Change::Deleted)),
+            );
+            status.find_renames(git_repo, index, &head_entries)?;
+            status.staged.sort_by(|(a, _), (b, _)| a.cmp(b));
+            status.unmerged = stages
+                .into_iter()
+                .map(|(path, stages)| (path.to_string(), Unmerged(stages)))
+                .collect();
+        }
+
+        // stat every tracked file, in chunks spread over the thread pool
+        let checked = index
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.stage() == 0 && !e.skip_worktree() && e.mode != 0o160000)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let chunks = checked.chunks(1024).collect::<Vec<_>>();
+        let entries = &index.entries;
+        let states = parallel_map(&chunks, |chunk| {
+            chunk
+                .iter()
+                .map(|&i| worktree_state(git_repo, &entries[i]))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let mut refreshed = false;
+        for (i, state) in checked.into_iter().zip(states.into_iter().flatten()) {
+            let entry: &mut IndexEntry = &mut index.entries[i];
+            let change = match state {
+                WorktreeState::Unchanged(meta) => {
+                    if !stat_matches(entry, &meta) {
+                        entry.refresh_stat(&meta);
+                        refreshed = true;
+                    }
+                    continue;
+                }
+                WorktreeState::Modified(mode, _) => Change::between(entry.mode, mode),
+                WorktreeState::Deleted => Change::Deleted,
+            };
+            status.unstaged.push((entry.path.clone(), change));
+        }
+
+        let mut cache = UntrackedCache::load(git_repo)?;
+        status.untracked = WorktreeWalker::new(git_repo)
+            .parallel(true)
+            .untracked(index, &mut cache)?;
+        cache.save(git_repo)?;
+        Ok((status, refreshed))
+    }
+
+    /// Pair up the staged additions with the deletions of the files (from HEAD's `deleted`)
+    /// they were renamed from, as `git status` does.
+    fn find_renames(
+        &mut self,
+        git_repo: &GitRepository,
+        index: &Index,
+        deleted: &BTreeMap<&str, &TreeEntry>,
+    ) -> Result<()> {
+        let added = self
+            .staged
+            .iter()
+            .filter(|(_, change)| *change == Change::Added)
+            .map(|(path, _)| path.as_str())
+            .collect::<HashSet<_>>();
+        if added.is_empty() || deleted.is_empty() {
+            return Ok(());
+        }
+        let changes = index
+            .entries
+            .iter()
+            .filter(|e| e.stage() == 0 && added.contains(e.path.as_str()))
+            .map(|e| diff::Change {
+                old: None,
+                new: Some(DiffFile {
+                    path: e.path.clone(),
+                    mode: Mode::from_bits(e.mode),
+                    hash: e.hash_hex(),
+                }),
+                similarity: None,
+            })
+            .chain(deleted.values().map(|e| diff::Change {
+                old: Some(DiffFile {
+                    path: e.name.clone(),
+                    mode: e.mode,
+                    hash: e.hash.clone(),
+                }),
+                new: None,
+                similarity: None,
+            }))
+            .collect();
+        let changes = detect_renames(&mut BlobCache::new(git_repo), changes, RENAME_THRESHOLD)?;
+        for change in changes {
+            if let (Some(old), Some(new)) = (change.old, change.new) {
+                self.renamed_from.insert(new.path, old.path);
+            }
+        }
+        let old_paths = self.renamed_from.values().collect::<HashSet<_>>();
+        self.staged.retain(|(path, _)| !old_paths.contains(path));
+        for (path, change) in &mut self.staged {
+            if self.renamed_from.contains_key(path) {
+                *change = Change::Renamed;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `path` (relative to the top of the work tree) as seen from `cwd`, a directory relative to
+/// the top.
+pub(crate) fn relative(path: &str, cwd: &str) -> String {
+    if cwd.is_empty() {
+        return path.to_string();
+    }
+    let components = cwd.split('/').collect::<Vec<_>>();
+    let mut rest = path;
+    let mut common = 0;
+    for component in &components {
+        match rest
+            .strip_prefix(component)
+            .and_then(|r| r.strip_prefix('/'))
+        {
+            Some(r) => {
+                rest = r;
+                common += 1;
+            }
+            None => break,
+        }
+    }
+    let relative = format!("{}{rest}", "../".repeat(components.len() - common));
+    if relative.is_empty() {
+        "./".to_string()
+    } else {
+        relative
+    }
+}
+
+/// How the current branch compares with its upstream, as `git status` reports it, or `None`
+/// when HEAD isn't on a branch with an upstream.
+fn tracking_summary(git_repo: &GitRepository, head: &Head) -> Result<Option<String>> {
+    let Head::Branch(branch, Some(ours)) = head else {
+        return Ok(None);
+    };
+    let Some(tracking) = upstream(git_repo, branch) else {
+        return Ok(None);
+    };
+    let name = short_ref_name(&tracking);
+    let Some(theirs) = ref_resolve(git_repo, &tracking)? else {
+        return Ok(Some(format!(
+            "Your branch is based on '{name}', but the upstream is gone."
+        )));
+    };
+    let commits = |n: usize| if n == 1 { "commit" } else { "commits" };
+    Ok(Some(match ahead_behind(git_repo, ours, &theirs)? {
+        (0, 0) => format!("Your branch is up to date with '{name}'."),
+        (ahead, 0) => format!(
+            "Your branch is ahead of '{name}' by {ahead} {}.",
+            commits(ahead)
+        ),
+        (0, behind) => format!(
+            "Your branch is behind '{name}' by {behind} {}, and can be fast-forwarded.",
+            commits(behind)
+        ),
+        (ahead, behind) => format!(
+            "Your branch and '{name}' have diverged,\n\
+             and have {ahead} and {behind} different commits each, respectively."
+        ),
+    }))
+}
+
+/// Write `status` the way `git status` prints it (without the advice lines).
+fn write_long(
+    out: &mut dyn Write,
+    head: &Head,
+    tracking: Option<&str>,
+    status: &Status,
+    cwd: &str,
+) -> Result<()> {
+    match head {
+        Head::Branch(branch, _) => writeln!(
+            out,
+            "On branch {}",
+            branch.strip_prefix("refs/heads/").unwrap_or(branch)
+        )?,
+        Head::Detached(commit) => writeln!(out, "HEAD detached at {}", &commit[..7])?,
+    }
+    if let Some(tracking) = tracking {
+        writeln!(out, "{tracking}\n")?;
+    }
+    if head.commit().is_none() {
+        writeln!(out, "\nNo commits yet\n")?;
+    }
+
+    // labels are padded to line up with the longest one a section can have
+    let sections = [
+        (
+            "Changes to be committed:",
+            status
+                .staged
+                .iter()
+                .map(|(path, change)| match status.renamed_from.get(path) {
+                    Some(old) => (
+                        change.label(),
+                        format!("{} -> {}", relative(old, cwd), relative(path, cwd)),
+                    ),
+                    None => (change.label(), relative(path, cwd)),
+                })
+                .collect::<Vec<_>>(),
+            12,
+        ),
+        (
+            "Unmerged paths:",
+            status
+                .unmerged
+                .iter()
+                .map(|(path, unmerged)| (unmerged.label(), relative(path, cwd)))
+                .collect(),
+            17,
+        ),
+        (
+            "Changes not staged for commit:",
+            status
+                .unstaged
+                .iter()
+                .map(|(path, change)| (change.label(), relative(path, cwd)))
+                .collect(),
+            12,
+        ),
+    ];
+    for (title, lines, width) in sections {
+        if lines.is_empty() {
+            continue;
+        }
+        writeln!(out, "{title}")?;
+        for (label, path) in lines {
+            writeln!(out, "\t{label:<width$}{path}")?;
+        }
+        writeln!(out)?;
+    }
+    if !status.untracked.is_empty() {
+        writeln!(out, "Untracked files:")?;
+        for path in &status.untracked {
+            writeln!(out, "\t{}", relative(path, cwd))?;
+        }
+        writeln!(out)?;
+    }
+
+    if !status.staged.is_empty() {
+        return Ok(());
+    }
+    let summary = if !status.unstaged.is_empty() || !status.unmerged.is_empty() {
+        "no changes added to commit"
+    } else if !status.untracked.is_empty() {
+        "nothing added to commit but untracked files present"
+    } else if head.commit().is_none() {
+        "nothing to commit"
+    } else {
+        "nothing to commit, working tree clean"
+    };
+    writeln!(out, "{summary}")?;
+    Ok(())
+}
+
+/// Print `status` in the stable porcelain format: a staged and an unstaged status letter and the
+/// path (relative to the top of the work tree) for every changed path, then untracked paths.
+/// Renames show as `old -> new`. With `nul`, lines end in NUL and paths aren't quoted.
+fn print_porcelain(status: &Status, nul: bool) -> Result<()> {
+    let mut codes: BTreeMap<&str, [char; 2]> = BTreeMap::new();
+    for (path, change) in &status.staged {
+        codes.entry(path).or_insert([' '; 2])[0] = change.code();
+    }
+    for (path, change) in &status.unstaged {
+        codes.entry(path).or_insert([' '; 2])[1] = change.code();
+    }
+    for (path, unmerged) in &status.unmerged {
+        let mut letters = unmerged.codes().chars();
+        codes.insert(path, [letters.next().unwrap(), letters.next().unwrap()]);
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let end = if nul { '\0' } else { '\n' };
+    let path = |path: &str| {
+        if nul {
+            path.to_string()
+        } else {
+            quote_path(path, true)
+        }
+    };
+    for (file, [x, y]) in codes {
+        match status.renamed_from.get(file) {
+            // -z gives the new path first, as it does no quoting to tell them apart
+            Some(old) if nul => write!(out, "{x}{y} {}{end}{}{end}", path(file), path(old))?,
+            Some(old) => write!(out, "{x}{y} {} -> {}{end}", path(old), path(file))?,
+            None => write!(out, "{x}{y} {}{end}", path(file))?,
+        }
+    }
+    for file in &status.untracked {
+        write!(out, "?? {}{end}", path(file))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn invoke(repo: &GitRepository, porcelain: bool, nul: bool) -> Result<()> {
+    let head = resolve_head(repo)?;
+    let mut index = Index::read(repo)?;
+    let (status, refreshed) = Status::collect(repo, head.commit(), &mut index)?;
+    // refreshed stat data saves hashing the files next time, but is not worth waiting for
+    if refreshed && optional_locks_allowed() {
+        index.try_write(repo)?;
+    }
+    // -z alone implies the porcelain format
+    if porcelain || nul {
+        print_porcelain(&status, nul)
+    } else {
+        print!("{}", long_status(repo, &head, &status)?);
+        Ok(())
+    }
+}
+
+/// `status` of `git_repo` in the long format `status` prints, with paths relative to the
+/// current directory.
+pub(crate) fn long_status(
+    git_repo: &GitRepository,
+    head: &Head,
+    status: &Status,
+) -> Result<String> {
+    let tracking = tracking_summary(git_repo, head)?;
+    let mut out = Vec::new();
+    write_long(
+        &mut out,
+        head,
+        tracking.as_deref(),
+        status,
+        &worktree_path(git_repo, ".")?,
+    )?;
+    Ok(String::from_utf8(out)?)
+}
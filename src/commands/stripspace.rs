@@ -0,0 +1,21 @@
+use std::io::{Read, Write};
+
+use anyhow::{Context, Result};
+
+use crate::message::{comment_lines, stripspace};
+
+/// Read text from stdin and write it cleaned up as commit messages are: trailing whitespace and
+/// extra blank lines removed, and (with `strip_comments`) `#` lines dropped. With
+/// `comment_lines`, every line is turned into a `#` comment instead.
+pub(crate) fn invoke(strip_comments: bool, comment: bool) -> Result<()> {
+    let mut text = String::new();
+    std::io::stdin()
+        .read_to_string(&mut text)
+        .context("read stdin")?;
+    let out = match comment {
+        true => comment_lines(&text),
+        false => stripspace(&text, strip_comments),
+    };
+    std::io::stdout().write_all(out.as_bytes())?;
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use anyhow::{bail, Context, Result};
+use ini::Ini;
+
+use crate::{
+    index::Index,
+    objects::Mode,
+    refs::ref_resolve,
+    repository::{repo_open, GitRepository},
+};
+
+/// A submodule as `.gitmodules` describes it.
+struct Submodule {
+    path: String,
+    url: Option<String>,
+}
+
+/// The submodules listed in the work tree's `.gitmodules`, which is empty when it doesn't exist.
+fn read_gitmodules(git_repo: &GitRepository) -> Result<Vec<Submodule>> {
+    let path = git_repo.work_tree().join(".gitmodules");
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let config = Ini::load_from_file(&path).with_context(|| format!("read {}", path.display()))?;
+    Ok(config
+        .iter()
+        .filter(|(section, _)| section.is_some_and(|s| s.starts_with("submodule ")))
+        .filter_map(|(_, props)| {
+            Some(Submodule {
+                path: props.get("path")?.to_string(),
+                url: props.get("url").map(str::to_string),
+            })
+        })
+        .collect())
+}
+
+/// Show the commit each submodule has checked out, prefixed by `-` if it isn't checked out, `+`
+/// if that isn't the commit recorded in the index, and `U` if it has merge conflicts, followed
+/// by its path and URL. With `cached`, the commit recorded in the index is shown instead.
+pub(crate) fn invoke_status(repo: &GitRepository, cached: bool) -> Result<()> {
+    let modules = read_gitmodules(repo)?;
+    let index = Index::read(repo)?;
+    let mut done = Vec::new();
+    for entry in &index.entries {
+        if Mode::from_bits(entry.mode) != Mode::Gitlink || done.contains(&entry.path) {
+            continue;
+        }
+        done.push(entry.path.clone());
+        let Some(module) = modules.iter().find(|m| m.path == entry.path) else {
+            bail!(
+                "no submodule mapping found in .gitmodules for path '{}'",
+                entry.path
+            );
+        };
+        let url = module.url.as_deref().unwrap_or("");
+        if entry.stage() != 0 {
+            println!("U{} {}", repo.hash_algo().null(), module.path);
+            continue;
+        }
+        let recorded = entry.hash_hex();
+        let dir = repo.work_tree().join(&module.path);
+        let head = match dir.join(".git").exists() {
+            true => ref_resolve(&repo_open(&dir)?, "HEAD")?,
+            false => None,
+        };
+        match head {
+            None => println!("-{recorded} {}", module.path),
+            Some(head) if head == recorded => println!(" {recorded} {} ({url})", module.path),
+            Some(_) if cached => println!("+{recorded} {} ({url})", module.path),
+            Some(head) => println!("+{head} {} ({url})", module.path),
+        }
+    }
+    Ok(())
+}
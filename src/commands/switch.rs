@@ -0,0 +1,72 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::{branch::check_new_branch, checkout::checkout_tree},
+    objects::{object_find, peel_to, Kind, ObjectType},
+    refs::{ref_resolve, resolve_head, write_head, Head, RefTransaction},
+    repository::GitRepository,
+};
+
+/// `git switch`: check out the branch `branch` and attach HEAD to it. With `create`, the branch
+/// is first created at `start` (HEAD by default). Unlike `checkout`, anything but a branch is
+/// refused unless `detach` asks for HEAD to be detached at it, and no paths are restored.
+/// Unless `force` is set, local changes that the switch would overwrite make it fail.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    branch: String,
+    create: bool,
+    start: Option<String>,
+    detach: bool,
+    force: bool,
+) -> Result<()> {
+    if detach {
+        let commit = object_find(repo, branch, ObjectType::Commit)?;
+        checkout_tree(repo, &peel_to(repo, &commit, Kind::Tree)?, force)?;
+        println!("HEAD is now at {}", &commit[..7]);
+        let moving = format!(
+            "checkout: moving from {} to {commit}",
+            resolve_head(repo)?.reflog_name()
+        );
+        return write_head(repo, &Head::Detached(commit), &moving);
+    }
+    let refname = format!("refs/heads/{branch}");
+    let start = start.unwrap_or_else(|| "HEAD".to_string());
+    let commit = match create {
+        true => {
+            check_new_branch(repo, &branch)?;
+            object_find(repo, start.clone(), ObjectType::Commit)?
+        }
+        false => match ref_resolve(repo, &refname)? {
+            Some(commit) => commit,
+            None if object_find(repo, branch.clone(), ObjectType::Commit).is_ok() => {
+                bail!(
+                    "a branch is expected, got commit '{branch}'\nhint: If you want to detach \
+                     HEAD at the commit, try again with the --detach option."
+                )
+            }
+            None => bail!("invalid reference: {branch}"),
+        },
+    };
+    if !create && resolve_head(repo)? == Head::Branch(refname.clone(), Some(commit.clone())) {
+        println!("Already on '{branch}'");
+        return Ok(());
+    }
+
+    let tree = peel_to(repo, &commit, Kind::Tree)?;
+    checkout_tree(repo, &tree, force)?;
+    let moving = format!(
+        "checkout: moving from {} to {branch}",
+        resolve_head(repo)?.reflog_name()
+    );
+    if create {
+        let mut transaction = RefTransaction::new(repo, &format!("branch: Created from {start}"));
+        transaction.create(&refname, &commit)?;
+        transaction.commit()?;
+    }
+    write_head(repo, &Head::Branch(refname, Some(commit)), &moving)?;
+    match create {
+        true => println!("Switched to a new branch '{branch}'"),
+        false => println!("Switched to branch '{branch}'"),
+    }
+    Ok(())
+}
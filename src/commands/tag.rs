@@ -0,0 +1,163 @@
+use std::{fmt::Write as _, fs};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::{
+    commands::commit_tree::identity,
+    editor::launch_editor,
+    ignore::wildmatch,
+    message::{comment_lines, stripspace},
+    objects::{object_find, object_kind, write_object, Kind, ObjectType},
+    refs::{check_ref_format, ref_list, ref_resolve, RefTransaction},
+    repository::{repo_path, GitRepository},
+    ExitStatus,
+};
+
+/// The file the message of an annotated tag is edited in.
+const TAG_EDITMSG: &str = "TAG_EDITMSG";
+
+/// What `tag` was asked to do.
+pub(crate) enum TagAction {
+    /// List the tags matching any of the patterns (all of them without patterns).
+    List {
+        patterns: Vec<String>,
+    },
+    Create {
+        name: String,
+        target: Option<String>,
+        /// Make an annotated tag; the message is written in the editor when there is none.
+        annotate: bool,
+        message: Option<String>,
+        force: bool,
+    },
+    Delete {
+        names: Vec<String>,
+    },
+}
+
+pub(crate) fn invoke(repo: &GitRepository, action: TagAction) -> Result<()> {
+    match action {
+        TagAction::List { patterns } => list(repo, &patterns),
+        TagAction::Create {
+            name,
+            target,
+            annotate,
+            message,
+            force,
+        } => create(repo, &name, target, annotate, message, force),
+        TagAction::Delete { names } => delete(repo, &names),
+    }
+}
+
+fn list(git_repo: &GitRepository, patterns: &[String]) -> Result<()> {
+    for (name, _) in ref_list(git_repo)? {
+        let Some(tag) = name.strip_prefix("refs/tags/") else {
+            continue;
+        };
+        if patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|pattern| wildmatch(pattern.as_bytes(), tag.as_bytes()))
+        {
+            println!("{tag}");
+        }
+    }
+    Ok(())
+}
+
+/// Have the user write the message of the tag `name` in their editor, starting from
+/// `TAG_EDITMSG` with instructions commented out. Returns the message without its comments.
+fn edit_message(git_repo: &GitRepository, name: &str) -> Result<String> {
+    let template = format!(
+        "\n{}",
+        comment_lines(&format!(
+            "\nWrite a message for tag:\n  {name}\nLines starting with '#' will be ignored.\n"
+        ))
+    );
+    let path = repo_path(git_repo, &[TAG_EDITMSG])?;
+    fs::write(&path, template).with_context(|| format!("write {}", path.display()))?;
+    launch_editor(git_repo, &path)
+        .map_err(|e| anyhow!("{e:#}\nPlease supply the message using either -m or -F option."))?;
+    let text = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    let message = stripspace(&text, true);
+    if message.is_empty() {
+        bail!("no tag message?");
+    }
+    Ok(message)
+}
+
+/// Point `refs/tags/<name>` at `target` (HEAD by default), or at a new tag object for it when
+/// the tag is annotated. An existing tag is only replaced with `force`.
+fn create(
+    git_repo: &GitRepository,
+    name: &str,
+    target: Option<String>,
+    annotate: bool,
+    message: Option<String>,
+    force: bool,
+) -> Result<()> {
+    let refname = format!("refs/tags/{name}");
+    if !check_ref_format(&refname, false) {
+        bail!("'{name}' is not a valid tag name.");
+    }
+    let old = ref_resolve(git_repo, &refname)?;
+    if old.is_some() && !force {
+        bail!("tag '{name}' already exists");
+    }
+    let target = object_find(
+        git_repo,
+        target.unwrap_or_else(|| "HEAD".to_string()),
+        ObjectType::Commit,
+    )?;
+
+    let hash = if annotate || message.is_some() {
+        let message = match message {
+            Some(message) => stripspace(&message, false),
+            None => edit_message(git_repo, name)?,
+        };
+        let mut tag = String::new();
+        writeln!(tag, "object {target}")?;
+        writeln!(tag, "type {}", object_kind(git_repo, &target)?)?;
+        writeln!(tag, "tag {name}")?;
+        writeln!(tag, "tagger {}", identity(git_repo, "committer")?)?;
+        writeln!(tag)?;
+        tag.push_str(&message);
+        let hash = write_object(git_repo, Kind::Tag, tag.as_bytes())?;
+        // the message made it into the tag, so there is nothing left to recover
+        let _ = fs::remove_file(repo_path(git_repo, &[TAG_EDITMSG])?);
+        hash
+    } else {
+        target
+    };
+
+    let mut transaction = RefTransaction::new(git_repo, "tag");
+    match &old {
+        Some(old) => transaction.update(&refname, &hash, Some(old))?,
+        None => transaction.create(&refname, &hash)?,
+    }
+    transaction.commit()?;
+    if let Some(old) = old.filter(|old| *old != hash) {
+        println!("Updated tag '{name}' (was {})", &old[..7]);
+    }
+    Ok(())
+}
+
+fn delete(git_repo: &GitRepository, names: &[String]) -> Result<()> {
+    let mut failed = false;
+    for name in names {
+        let refname = format!("refs/tags/{name}");
+        let Some(old) = ref_resolve(git_repo, &refname)? else {
+            eprintln!("error: tag '{name}' not found.");
+            failed = true;
+            continue;
+        };
+        let mut transaction = RefTransaction::new(git_repo, "");
+        transaction.delete(&refname, Some(&old))?;
+        transaction.commit()?;
+        println!("Deleted tag '{name}' (was {})", &old[..7]);
+    }
+    if failed {
+        return Err(ExitStatus(1).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    convert::Converter,
+    hash::ObjectId,
+    index::{mode_from_metadata, worktree_state, Index, IndexEntry, WorktreeState},
+    objects::{write_object, Kind},
+    repository::{worktree_path, GitRepository},
+};
+
+/// Parse the `<mode>,<sha1>,<path>` argument of `--cacheinfo`.
+fn parse_cacheinfo(cacheinfo: &str) -> Result<(u32, ObjectId, &str)> {
+    let parse = || {
+        let mut parts = cacheinfo.splitn(3, ',');
+        let mode = u32::from_str_radix(parts.next()?, 8).ok()?;
+        let hash = ObjectId::from_hex(parts.next()?).ok()?;
+        let path = parts.next().filter(|path| !path.is_empty())?;
+        Some((mode, hash, path))
+    };
+    parse().with_context(|| {
+        format!("option 'cacheinfo' expects <mode>,<sha1>,<path>, got '{cacheinfo}'")
+    })
+}
+
+/// Update the entry for `path` from the work tree: write the file's blob and stat data, or drop
+/// the entry if the file is gone and `remove` is set.
+pub(crate) fn update_path(
+    git_repo: &GitRepository,
+    converter: &mut Converter,
+    index: &mut Index,
+    path: &str,
+    add: bool,
+    remove: bool,
+) -> Result<()> {
+    let file = git_repo.work_tree().join(path);
+    let meta = match fs::symlink_metadata(&file) {
+        Ok(meta) if !meta.is_dir() => Some(meta),
+        Ok(_) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e).with_context(|| format!("stat {}", file.display())),
+    };
+    let Some(meta) = meta else {
+        if !remove {
+            bail!("{path}: does not exist and --remove not passed");
+        }
+        index.remove(path);
+        return Ok(());
+    };
+    if !add && !index.entries.iter().any(|e| e.path == path) {
+        bail!("{path}: cannot add to the index - missing --add option?");
+    }
+    let hash = write_object(git_repo, Kind::Blob, &converter.worktree_blob(path)?)?;
+    let hash = ObjectId::from_hex(&hash)?;
+    let mode = mode_from_metadata(git_repo, &meta, index.get(path).map(|e| e.mode));
+    index.add(IndexEntry::from_metadata(path, &meta, mode, hash));
+    Ok(())
+}
+
+/// Re-stat every tracked file, refreshing the cached stat data of unchanged ones. Returns
+/// whether all of them were unchanged.
+fn refresh(git_repo: &GitRepository, index: &mut Index) -> Result<bool> {
+    let mut clean = true;
+    let mut unmerged = None;
+    for entry in index.entries.iter_mut() {
+        if entry.stage() != 0 {
+            // report each conflicted path once
+            if unmerged.as_deref() != Some(entry.path.as_str()) {
+                println!("{}: needs merge", entry.path);
+                unmerged = Some(entry.path.clone());
+                clean = false;
+            }
+            continue;
+        }
+        if entry.skip_worktree() || entry.mode == 0o160000 {
+            continue;
+        }
+        match worktree_state(git_repo, entry)? {
+            WorktreeState::Unchanged(meta) => entry.refresh_stat(&meta),
+            WorktreeState::Modified(..) | WorktreeState::Deleted => {
+                println!("{}: needs update", entry.path);
+                clean = false;
+            }
+        }
+    }
+    Ok(clean)
+}
+
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    paths: Vec<String>,
+    add: bool,
+    remove: bool,
+    refresh_flag: bool,
+    cacheinfo: Vec<String>,
+    chmod: Option<String>,
+) -> Result<()> {
+    let mode = match chmod.as_deref() {
+        None => None,
+        Some("+x") => Some(0o100755),
+        Some("-x") => Some(0o100644),
+        Some(other) => bail!("option 'chmod' expects \"+x\" or \"-x\", got '{other}'"),
+    };
+    let (mut index, lock) = Index::lock(repo)?;
+
+    let clean = !refresh_flag || refresh(repo, &mut index)?;
+    for cacheinfo in &cacheinfo {
+        let (mode, hash, path) = parse_cacheinfo(cacheinfo)?;
+        if !add && !index.entries.iter().any(|e| e.path == path) {
+            bail!("{path}: cannot add to the index - missing --add option?");
+        }
+        index.add(IndexEntry::without_stat(path, mode, hash));
+    }
+    let mut converter = Converter::new(repo)?;
+    for path in &paths {
+        let path = worktree_path(repo, path)?;
+        update_path(repo, &mut converter, &mut index, &path, add, remove)?;
+        if let Some(mode) = mode {
+            let entry = index
+                .entries
+                .iter_mut()
+                .find(|e| e.path == path && e.stage() == 0 && e.mode & 0o170000 == 0o100000)
+                .with_context(|| {
+                    format!(
+                        "cannot chmod {} '{path}'",
+                        chmod.as_deref().unwrap_or_default()
+                    )
+                })?;
+            entry.mode = mode;
+        }
+    }
+
+    index.sort();
+    lock.commit(&index)?;
+    if !clean {
+        bail!("some files need updating");
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use std::io::BufRead;
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    objects::{object_find, ObjectType},
+    refs::RefTransaction,
+    repository::GitRepository,
+};
+
+/// Where `update-ref --stdin` is in a transaction, in the order it goes through them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    /// Changes are being queued, to be committed at the end of the input.
+    Open,
+    /// `start` was given, so the changes are only made on `commit`.
+    Started,
+    Prepared,
+    /// Committed or aborted, until the next `start`.
+    Closed,
+}
+
+/// The object `value` names: a full hash as it is, or else a revision naming a commit.
+fn resolve_value(git_repo: &GitRepository, value: &str) -> Result<String> {
+    if git_repo.hash_algo().is_hex_id(value) {
+        return Ok(value.to_ascii_lowercase());
+    }
+    object_find(git_repo, value.to_string(), ObjectType::Commit)
+}
+
+/// Queue the change of the `--stdin` command `command` (`update`, `create`, `delete` or
+/// `verify`) with its arguments `args` on `transaction`.
+fn queue(
+    git_repo: &GitRepository,
+    transaction: &mut RefTransaction,
+    command: &str,
+    args: &str,
+) -> Result<()> {
+    let mut args = args.split(' ');
+    let name = args
+        .next()
+        .filter(|name| !name.is_empty())
+        .with_context(|| format!("{command}: missing <ref>"))?;
+    let mut value = |what: &str| -> Result<Option<String>> {
+        match args.next() {
+            Some(value) => resolve_value(git_repo, value)
+                .map(Some)
+                .map_err(|_| anyhow::anyhow!("{command} {name}: invalid <{what}>: {value}")),
+            None => Ok(None),
+        }
+    };
+    let null = git_repo.hash_algo().null().to_string();
+    match command {
+        "update" => {
+            let new = value("newvalue")?
+                .with_context(|| format!("{command} {name}: missing <newvalue>"))?;
+            let old = value("oldvalue")?;
+            transaction.update(name, &new, old.as_deref())?;
+        }
+        "create" => {
+            let new = value("newvalue")?
+                .with_context(|| format!("{command} {name}: missing <newvalue>"))?;
+            if new == null {
+                bail!("{command} {name}: zero <newvalue>");
+            }
+            transaction.create(name, &new)?;
+        }
+        "delete" => {
+            let old = value("oldvalue")?;
+            if old.as_ref() == Some(&null) {
+                bail!("{command} {name}: zero <oldvalue>");
+            }
+            transaction.delete(name, old.as_deref())?;
+        }
+        _ => {
+            let old = value("oldvalue")?.unwrap_or(null);
+            transaction.verify(name, &old)?;
+        }
+    }
+    if let Some(extra) = args.next() {
+        bail!("{command} {name}: extra input: {extra}");
+    }
+    Ok(())
+}
+
+/// Run the commands of `update-ref --stdin`, one per line of `input`:
+/// `update <ref> <new> [<old>]`, `create <ref> <new>`, `delete <ref> [<old>]` and
+/// `verify <ref> [<old>]` queue changes, which without `start` are made together at the end of
+/// the input. `start` opens a transaction explicitly, which `prepare` locks and checks, and
+/// `commit` or `abort` closes; one left open at the end is aborted.
+fn run_stdin(git_repo: &GitRepository, message: &str, input: impl BufRead) -> Result<()> {
+    let mut state = State::Open;
+    let mut transaction = RefTransaction::new(git_repo, message);
+    for line in input.lines() {
+        let line = line.context("read standard input")?;
+        let (command, args) = line.split_once(' ').unwrap_or((&line, ""));
+        let next = match command {
+            "update" | "create" | "delete" | "verify" => State::Open,
+            "start" => State::Started,
+            "prepare" => State::Prepared,
+            "commit" | "abort" => State::Closed,
+            _ => bail!("unknown command: {line}"),
+        };
+        state = match state {
+            State::Started if next == State::Started => bail!("cannot restart ongoing transaction"),
+            // queuing changes doesn't turn an explicit transaction back into an implicit one
+            State::Open | State::Started => state.max(next),
+            State::Prepared if next != State::Closed => {
+                bail!("prepared transactions can only be closed")
+            }
+            State::Prepared => next,
+            State::Closed if next != State::Started => bail!("transaction is closed"),
+            State::Closed => {
+                transaction = RefTransaction::new(git_repo, message);
+                next
+            }
+        };
+
+        match command {
+            "start" => println!("start: ok"),
+            "prepare" => {
+                transaction.prepare()?;
+                println!("prepare: ok");
+            }
+            "commit" => {
+                std::mem::replace(&mut transaction, RefTransaction::new(git_repo, message))
+                    .commit()?;
+                println!("commit: ok");
+            }
+            "abort" => {
+                transaction = RefTransaction::new(git_repo, message);
+                println!("abort: ok");
+            }
+            _ => queue(git_repo, &mut transaction, command, args)?,
+        }
+    }
+    match state {
+        State::Open => transaction.commit(),
+        _ => Ok(()),
+    }
+}
+
+/// Point the ref `name` at `new_value`, or delete it with `delete` (when the second value is
+/// the old one). With an old value, the ref must be at it, or not exist for the null id. With
+/// `stdin`, run the commands read from standard input instead. `message` is the reason recorded
+/// in the reflogs.
+pub(crate) fn invoke(
+    repo: &GitRepository,
+    message: Option<String>,
+    delete: bool,
+    stdin: bool,
+    name: Option<String>,
+    new_value: Option<String>,
+    old_value: Option<String>,
+) -> Result<()> {
+    let message = message.unwrap_or_default();
+    if stdin {
+        return run_stdin(repo, &message, std::io::stdin().lock());
+    }
+    let name =
+        name.context("usage: git-rs update-ref [<options>] <refname> <new-val> [<old-val>]")?;
+    let mut transaction = RefTransaction::new(repo, &message);
+    if delete {
+        if old_value.is_some() {
+            bail!("usage: git-rs update-ref [<options>] -d <refname> [<old-val>]");
+        }
+        let old = new_value
+            .map(|value| resolve_value(repo, &value))
+            .transpose()?;
+        transaction.delete(&name, old.as_deref())?;
+    } else {
+        let new = new_value
+            .context("usage: git-rs update-ref [<options>] <refname> <new-val> [<old-val>]")?;
+        let new = resolve_value(repo, &new)?;
+        let old = old_value
+            .map(|value| resolve_value(repo, &value))
+            .transpose()?;
+        transaction.update(&name, &new, old.as_deref())?;
+    }
+    transaction.commit()
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::HashSet,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    bitmap::{Bitmap, BitmapIndex},
+    commands::{commit_tree::kvlm_parse, ls_remote::peel_tag},
+    objects::{object_read, read_commit, read_tree, Mode},
+    pack::write_pack,
+    pkt_line::{
+        read_pkt_text, set_trace_identity, write_advertisement, write_flush, write_pkt, Band,
+        Sideband,
+    },
+    refs::{ref_list, resolve_head, Head},
+    repository::{repo_open, repo_path, GitRepository},
+    trace::{trace, TRACE},
+};
+
+const CAPABILITIES: &str = "multi_ack side-band side-band-64k no-progress agent=git-rs/0.1.0";
+
+/// The refs to advertise, `HEAD` first, with the peeled `^{}` entries of annotated tags.
+fn advertised_refs(git_repo: &GitRepository) -> Result<Vec<(String, String)>> {
+    let mut refs = Vec::new();
+    if let Some(head) = resolve_head(git_repo)?.commit() {
+        refs.push(("HEAD".to_string(), head.to_string()));
+    }
+    for (name, hash) in ref_list(git_repo)? {
+        let peeled = peel_tag(git_repo, &hash)?;
+        refs.push((name.clone(), hash));
+        if let Some(peeled) = peeled {
+            refs.push((format!("{name}^{{}}"), peeled));
+        }
+    }
+    Ok(refs)
+}
+
+/// The objects a walk reached: those in the pack with bitmaps by their position in it, and the
+/// others by hash.
+#[derive(Default)]
+struct Reached {
+    packed: Bitmap,
+    others: HashSet<String>,
+    /// The others in the order they were found.
+    found: Vec<String>,
+}
+
+impl Reached {
+    fn contains(&self, hash: &str, position: Option<usize>) -> bool {
+        match position {
+            Some(pos) => self.packed.get(pos),
+            None => self.others.contains(hash),
+        }
+    }
+}
+
+/// Collect the objects reachable from `tips` into `reached`, leaving out those in `exclude` and
+/// everything they reach. Where a commit has a bitmap in `bitmaps`, the objects it reaches are
+/// taken from there instead of walking its history. Trees of submodules (gitlinks) are not
+/// followed.
+fn walk_objects(
+    git_repo: &GitRepository,
+    bitmaps: Option<&BitmapIndex>,
+    tips: impl IntoIterator<Item = String>,
+    reached: &mut Reached,
+    exclude: &Reached,
+) -> Result<()> {
+    // blobs are known from their tree entries, so they don't have to be read
+    let mut stack = tips
+        .into_iter()
+        .map(|hash| (hash, false))
+        .collect::<Vec<_>>();
+    while let Some((hash, blob)) = stack.pop() {
+        let position = bitmaps.and_then(|bitmaps| bitmaps.position(&hash));
+        if reached.contains(&hash, position) || exclude.contains(&hash, position) {
+            continue;
+        }
+        match position {
+            Some(pos) => reached.packed.set(pos),
+            None => {
+                reached.others.insert(hash.clone());
+                reached.found.push(hash.clone());
+            }
+        }
+        if let (Some(bitmaps), Some(pos)) = (bitmaps, position) {
+            if let Some(bitmap) = bitmaps.reachable_from(pos)? {
+                reached.packed.or(&bitmap);
+                continue;
+            }
+        }
+        if !blob {
+            let obj = object_read(git_repo, &hash)?;
+            match obj.format() {
+                "commit" => {
+                    let commit = read_commit(git_repo, &hash)?;
+                    stack.extend(commit.parents.into_iter().map(|p| (p, false)));
+                    stack.push((commit.tree, false));
+                }
+                "tree" => {
+                    for entry in read_tree(git_repo, &hash)? {
+                        if entry.mode != Mode::Gitlink {
+                            let blob = !entry.is_tree();
+                            stack.push((entry.hash, blob));
+                        }
+                    }
+                }
+                "tag" => {
+                    let kvlm = kvlm_parse(&obj.serialize())?;
+                    let target = kvlm
+                        .get(b"object".as_slice())
+                        .and_then(|v| v.first())
+                        .with_context(|| format!("tag {hash} has no object header"))?;
+                    stack.push((String::from_utf8_lossy(target).into_owned(), false));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The objects to send a peer that wants `wants` and has `haves`: everything reachable from the
+/// wants but not from the haves, which the peer already has. The repository's bitmaps speed up
+/// counting when it has them; the second value says whether it did.
+pub(crate) fn count_objects(
+    git_repo: &GitRepository,
+    wants: Vec<String>,
+    haves: Vec<String>,
+) -> Result<(Vec<String>, bool)> {
+    // counting still works without the bitmaps, only slower
+    let bitmaps = BitmapIndex::open(&repo_path(git_repo, &["objects"])?, git_repo.hash_algo())
+        .unwrap_or_else(|e| {
+            eprintln!("warning: ignoring bitmaps: {e:#}");
+            None
+        });
+    if let Some(bitmaps) = &bitmaps {
+        match count_with(git_repo, Some(bitmaps), wants.clone(), haves.clone()) {
+            Ok(objects) => return Ok((objects, true)),
+            Err(e) => eprintln!("warning: ignoring bitmaps: {e:#}"),
+        }
+    }
+    Ok((count_with(git_repo, None, wants, haves)?, false))
+}
+
+/// [`count_objects`] with or without `bitmaps`.
+fn count_with(
+    git_repo: &GitRepository,
+    bitmaps: Option<&BitmapIndex>,
+    wants: Vec<String>,
+    haves: Vec<String>,
+) -> Result<Vec<String>> {
+    let mut have = Reached::default();
+    walk_objects(git_repo, bitmaps, haves, &mut have, &Reached::default())?;
+    let mut want = Reached::default();
+    walk_objects(git_repo, bitmaps, wants, &mut want, &have)?;
+    want.packed.and_not(&have.packed);
+    let mut objects = match bitmaps {
+        Some(bitmaps) => want.packed.ones().map(|pos| bitmaps.hash_at(pos)).collect(),
+        None => Vec::new(),
+    };
+    objects.extend(want.found);
+    Ok(objects)
+}
+
+/// Serve a fetch of the repository at `directory` over stdin and stdout, like
+/// `git upload-pack`: advertise the refs, read the client's wants and haves (protocol v0, with
+/// `multi_ack`), and send a pack of everything the client asked for that it doesn't have.
+pub(crate) fn invoke(directory: PathBuf) -> Result<()> {
+    set_trace_identity("upload-pack");
+    let repo = repo_open(&directory)?;
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+
+    let refs = advertised_refs(&repo)?;
+    let mut capabilities = CAPABILITIES.to_string();
+    if let Head::Branch(branch, Some(_)) = resolve_head(&repo)? {
+        capabilities.push_str(&format!(" symref=HEAD:{branch}"));
+    }
+    write_advertisement(&mut out, &refs, &capabilities)?;
+
+    let mut wants = Vec::new();
+    let mut client_capabilities = Vec::new();
+    while let Some(line) = read_pkt_text(&mut input)? {
+        let Some(want) = line.strip_prefix("want ") else {
+            bail!("protocol error: expected want, got {line:?}");
+        };
+        let (hash, capabilities) = want.split_once(' ').unwrap_or((want, ""));
+        if wants.is_empty() {
+            client_capabilities = capabilities
+                .split(' ')
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+        }
+        if !refs.iter().any(|(_, h)| h == hash) {
+            write_pkt(
+                &mut out,
+                format!("ERR upload-pack: not our ref {hash}").as_bytes(),
+            )?;
+            out.flush()?;
+            bail!("not our ref {hash}");
+        }
+        wants.push(hash.to_string());
+    }
+    if wants.is_empty() {
+        // the client only wanted the advertisement
+        return Ok(());
+    }
+    let has_capability = |name: &str| client_capabilities.iter().any(|c| c == name);
+    let multi_ack = has_capability("multi_ack");
+
+    // negotiation: acknowledge the haves we have until the client is done
+    let mut common = Vec::new();
+    loop {
+        let Some(line) = read_pkt_text(&mut input)? else {
+            if common.is_empty() || multi_ack {
+                write_pkt(&mut out, b"NAK\n")?;
+            }
+            out.flush()?;
+            continue;
+        };
+        if line == "done" {
+            match common.last() {
+                Some(last) if multi_ack => write_pkt(&mut out, format!("ACK {last}\n").as_bytes())?,
+                None => write_pkt(&mut out, b"NAK\n")?,
+                Some(_) => {}
+            }
+            break;
+        }
+        let Some(hash) = line.strip_prefix("have ") else {
+            bail!("protocol error: expected have or done, got {line:?}");
+        };
+        if object_read(&repo, hash).is_err() {
+            continue;
+        }
+        if multi_ack {
+            write_pkt(&mut out, format!("ACK {hash} continue\n").as_bytes())?;
+        } else if common.is_empty() {
+            write_pkt(&mut out, format!("ACK {hash}\n").as_bytes())?;
+        }
+        common.push(hash.to_string());
+    }
+
+    let start = Instant::now();
+    let (objects, bitmapped) = count_objects(&repo, wants, common)?;
+    let how = if bitmapped { " with bitmaps" } else { "" };
+    trace!(
+        TRACE,
+        "upload-pack: counted {} objects in {:.6} s{how}",
+        objects.len(),
+        start.elapsed().as_secs_f64()
+    );
+
+    let max = if has_capability("side-band-64k") {
+        65515
+    } else if has_capability("side-band") {
+        999
+    } else {
+        write_pack(&mut out, &repo, &objects)?;
+        return Ok(out.flush()?);
+    };
+    if !has_capability("no-progress") {
+        let mut progress = Sideband::new(&mut out, Band::Progress, max);
+        writeln!(progress, "Counting objects: {}, done.", objects.len())?;
+    }
+    let result = write_pack(
+        &mut Sideband::new(&mut out, Band::Data, max),
+        &repo,
+        &objects,
+    );
+    if let Err(e) = &result {
+        write!(Sideband::new(&mut out, Band::Error, max), "{e}")?;
+    }
+    write_flush(&mut out)?;
+    out.flush()?;
+    result.map(drop)
+}
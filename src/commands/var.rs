@@ -0,0 +1,44 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::commit_tree::identity, editor::editor_command, pager::pager_program,
+    repository::GitRepository,
+};
+
+/// The variables `var` knows, in the order `var -l` lists them.
+const VARIABLES: [&str; 4] = [
+    "GIT_COMMITTER_IDENT",
+    "GIT_AUTHOR_IDENT",
+    "GIT_EDITOR",
+    "GIT_PAGER",
+];
+
+/// The value of the logical variable `name`, resolved as the commands using it would.
+fn value(git_repo: &GitRepository, name: &str) -> Result<String> {
+    match name {
+        "GIT_COMMITTER_IDENT" => Ok(identity(git_repo, "committer")?.to_string()),
+        "GIT_AUTHOR_IDENT" => Ok(identity(git_repo, "author")?.to_string()),
+        "GIT_EDITOR" => editor_command(git_repo),
+        "GIT_PAGER" => Ok(pager_program()),
+        _ => bail!("usage: git var (-l | <variable>)"),
+    }
+}
+
+/// Print the value of the logical variable `variable`. With `list`, print the config followed
+/// by every variable that has a value, as `name=value` lines.
+pub(crate) fn invoke(repo: &GitRepository, variable: Option<String>, list: bool) -> Result<()> {
+    if !list {
+        let variable = variable.unwrap_or_default();
+        println!("{}", value(repo, &variable)?);
+        return Ok(());
+    }
+    for (key, value) in repo.config_entries() {
+        println!("{key}={value}");
+    }
+    for name in VARIABLES {
+        if let Ok(value) = value(repo, name) {
+            println!("{name}={value}");
+        }
+    }
+    Ok(())
+}
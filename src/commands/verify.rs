@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    commands::commit_tree::kvlm_parse,
+    date::parse_tz,
+    hash::HashAlgo,
+    objects::{object_find, object_read, ObjectType},
+    repository::GitRepository,
+};
+
+type Headers = HashMap<Vec<u8>, Vec<Vec<u8>>>;
+
+fn is_hash(algo: HashAlgo, value: &[u8]) -> bool {
+    value.len() == algo.hex_len()
+        && value
+            .iter()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(b))
+}
+
+/// Whether `value` is a well-formed `Name <email> <unix time> <timezone>` identity.
+fn is_ident(value: &[u8]) -> bool {
+    let Ok(value) = std::str::from_utf8(value) else {
+        return false;
+    };
+    let Some((name, rest)) = value.split_once(" <") else {
+        return false;
+    };
+    let Some((email, date)) = rest.split_once("> ") else {
+        return false;
+    };
+    let Some((time, tz)) = date.split_once(' ') else {
+        return false;
+    };
+    !name.contains(['<', '>'])
+        && !email.contains(['<', '>'])
+        && !time.is_empty()
+        && time.bytes().all(|b| b.is_ascii_digit())
+        && parse_tz(tz).is_some()
+}
+
+/// Check that header `key` appears exactly once and that its value passes `valid`.
+fn check_single(
+    headers: &Headers,
+    key: &str,
+    valid: impl Fn(&[u8]) -> bool,
+    issues: &mut Vec<String>,
+) {
+    match headers.get(key.as_bytes()).map(Vec::as_slice) {
+        None | Some([]) => issues.push(format!("missing {key} header")),
+        Some([value]) => {
+            if !valid(value) {
+                issues.push(format!(
+                    "malformed {key} header '{}'",
+                    String::from_utf8_lossy(value)
+                ));
+            }
+        }
+        Some(values) => issues.push(format!("{} {key} headers", values.len())),
+    }
+}
+
+/// Read object `hash`, which should be of type `kind`, and parse its headers. Problems that make
+/// the headers unreadable are returned as the error.
+fn read_headers(git_repo: &GitRepository, hash: &str, kind: &str) -> Result<Headers> {
+    let obj = object_read(git_repo, hash)?;
+    if obj.format() != kind {
+        bail!("object {hash} is a {}, not a {kind}", obj.format());
+    }
+    kvlm_parse(&obj.serialize())
+}
+
+/// Check the structure of commit `hash`: exactly one well-formed `tree`, `author` and `committer`
+/// header, and well-formed `parent` hashes. Returns the problems found; the signature, if any, is
+/// not checked.
+pub(crate) fn verify_commit(git_repo: &GitRepository, hash: &str) -> Result<Vec<String>> {
+    let headers = match read_headers(git_repo, hash, "commit") {
+        Ok(headers) => headers,
+        Err(e) => return Ok(vec![e.to_string()]),
+    };
+    let algo = git_repo.hash_algo();
+    let mut issues = Vec::new();
+    check_single(&headers, "tree", |v| is_hash(algo, v), &mut issues);
+    for parent in headers.get(b"parent".as_slice()).into_iter().flatten() {
+        if !is_hash(algo, parent) {
+            issues.push(format!(
+                "malformed parent header '{}'",
+                String::from_utf8_lossy(parent)
+            ));
+        }
+    }
+    check_single(&headers, "author", is_ident, &mut issues);
+    check_single(&headers, "committer", is_ident, &mut issues);
+    Ok(issues)
+}
+
+/// Check the structure of annotated tag `hash`: exactly one well-formed `object`, `type` and `tag`
+/// header, and a well-formed `tagger` if there is one (very old tags have none).
+pub(crate) fn verify_tag(git_repo: &GitRepository, hash: &str) -> Result<Vec<String>> {
+    let headers = match read_headers(git_repo, hash, "tag") {
+        Ok(headers) => headers,
+        Err(e) => return Ok(vec![e.to_string()]),
+    };
+    let mut issues = Vec::new();
+    let algo = git_repo.hash_algo();
+    check_single(&headers, "object", |v| is_hash(algo, v), &mut issues);
+    check_single(
+        &headers,
+        "type",
+        |kind| [b"blob".as_slice(), b"tree", b"commit", b"tag"].contains(&kind),
+        &mut issues,
+    );
+    check_single(
+        &headers,
+        "tag",
+        |name| !name.is_empty() && !name.contains(&b'\n'),
+        &mut issues,
+    );
+    if headers.contains_key(b"tagger".as_slice()) {
+        check_single(&headers, "tagger", is_ident, &mut issues);
+    }
+    Ok(issues)
+}
+
+/// Verify each of `names`, peeled to `kind`, with `verify`, printing the problems found, and fail if there were any.
+fn run(
+    repo: &GitRepository,
+    names: Vec<String>,
+    kind: ObjectType,
+    verify: fn(&GitRepository, &str) -> Result<Vec<String>>,
+) -> Result<()> {
+    let mut failed = 0;
+    for name in names {
+        let hash = object_find(repo, name.clone(), kind.clone())?;
+        let issues = verify(repo, &hash)?;
+        for issue in &issues {
+            eprintln!("error: {name}: {issue}");
+        }
+        if !issues.is_empty() {
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        bail!("{failed} object(s) failed verification");
+    }
+    Ok(())
+}
+
+pub(crate) fn invoke_commit(repo: &GitRepository, names: Vec<String>) -> Result<()> {
+    run(repo, names, ObjectType::Commit, verify_commit)
+}
+
+pub(crate) fn invoke_tag(repo: &GitRepository, names: Vec<String>) -> Result<()> {
+    run(repo, names, ObjectType::Tag, verify_tag)
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::checkout::reset_to_tree,
+    objects::{peel_to, read_commit, subject, Kind},
+    refs::ref_resolve,
+    repository::{repo_open, repo_path, GitRepository},
+};
+
+/// The work tree that has the branch `refname` checked out, if any: the main one, or a linked
+/// one listed under `worktrees/` in the common git directory.
+fn checked_out_at(repo: &GitRepository, refname: &str) -> Result<Option<PathBuf>> {
+    let on_branch = |git_dir: &Path| {
+        fs::read_to_string(git_dir.join("HEAD"))
+            .is_ok_and(|head| head.trim_end().strip_prefix("ref: ") == Some(refname))
+    };
+    let common_dir = repo.common_dir();
+    if on_branch(common_dir) {
+        return Ok(Some(
+            common_dir.parent().unwrap_or(common_dir).to_path_buf(),
+        ));
+    }
+    let worktrees = repo_path(repo, &["worktrees"])?;
+    let Ok(entries) = fs::read_dir(&worktrees) else {
+        return Ok(None);
+    };
+    for entry in entries {
+        let git_dir = entry?.path();
+        if !on_branch(&git_dir) {
+            continue;
+        }
+        // `gitdir` names the worktree's `.git` file
+        let gitfile = fs::read_to_string(git_dir.join("gitdir"))
+            .with_context(|| format!("read {}", git_dir.join("gitdir").display()))?;
+        let gitfile = PathBuf::from(gitfile.trim_end());
+        return Ok(Some(gitfile.parent().unwrap_or(&gitfile).to_path_buf()));
+    }
+    Ok(None)
+}
+
+/// Create a linked worktree at `path` with `branch` checked out. Its git directory is
+/// `worktrees/<id>` in the common one, named after the last component of `path`, and holds the
+/// worktree's own `HEAD` and index; `path/.git` is a file pointing at it, and the objects and
+/// branches stay shared with the main worktree.
+pub(crate) fn invoke_add(repo: &GitRepository, path: PathBuf, branch: String) -> Result<()> {
+    let refname = format!("refs/heads/{branch}");
+    let Some(commit) = ref_resolve(repo, &refname)? else {
+        bail!("invalid reference: {branch}");
+    };
+    if let Some(at) = checked_out_at(repo, &refname)? {
+        bail!("'{branch}' is already checked out at '{}'", at.display());
+    }
+    // an empty directory may be reused
+    if path.exists() && fs::read_dir(&path)?.next().is_some() {
+        bail!("'{}' already exists", path.display());
+    }
+    fs::create_dir_all(&path).with_context(|| format!("create {}", path.display()))?;
+    let work_tree = path.canonicalize()?;
+    let Some(name) = work_tree.file_name().and_then(|name| name.to_str()) else {
+        bail!("cannot name a worktree after '{}'", path.display());
+    };
+
+    let worktrees = repo_path(repo, &["worktrees"])?;
+    let mut id = name.to_string();
+    let mut suffix = 0;
+    while worktrees.join(&id).exists() {
+        suffix += 1;
+        id = format!("{name}{suffix}");
+    }
+    let git_dir = worktrees.join(&id);
+    fs::create_dir_all(&git_dir).with_context(|| format!("create {}", git_dir.display()))?;
+    let git_dir = git_dir.canonicalize()?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    fs::write(
+        git_dir.join("gitdir"),
+        format!("{}\n", work_tree.join(".git").display()),
+    )?;
+    fs::write(git_dir.join("HEAD"), format!("ref: {refname}\n"))?;
+    fs::write(
+        work_tree.join(".git"),
+        format!("gitdir: {}\n", git_dir.display()),
+    )?;
+
+    eprintln!("Preparing worktree (checking out '{branch}')");
+    let linked = repo_open(&work_tree)?;
+    let tree = peel_to(&linked, &commit, Kind::Tree)?;
+    reset_to_tree(&linked, &tree)?;
+    let message = read_commit(&linked, &commit)?.message;
+    println!(
+        "HEAD is now at {} {}",
+        &commit[..7],
+        subject(message.as_bytes())
+    );
+    Ok(())
+}
@@ -1,111 +1,238 @@
-use anyhow::{Context, Result};
-
-use std::{io::Cursor, os::unix::fs::PermissionsExt, path::Path};
-
-use crate::objects::{Kind, Object};
-
-pub(crate) fn write_tree_for(path: &Path) -> Result<Option<[u8; 20]>> {
-    let mut entries = std::fs::read_dir(path)
-        .with_context(|| format!("open directory {}", path.display()))?
-        .collect::<std::io::Result<Vec<_>>>()
-        .with_context(|| format!("bad directory entry in {}", path.display()))?;
-    entries.sort_unstable_by(|a, b| {
-        let (an, bn) = (a.file_name(), b.file_name());
-        let an_bytes = an.as_encoded_bytes();
-        let bn_bytes = bn.as_encoded_bytes();
-
-        let min_len = an_bytes.len().min(bn_bytes.len());
-        match an_bytes[..min_len].cmp(&bn_bytes[..min_len]) {
-            std::cmp::Ordering::Equal => {
-                if an_bytes.len() == bn_bytes.len() {
-                    std::cmp::Ordering::Equal
-                } else {
-                    // 如果一个是目录，一个是文件，则认为它们相等
-                    let a_is_dir = a.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-                    let b_is_dir = b.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-
-                    if (a_is_dir && an_bytes.len() < bn_bytes.len())
-                        || (b_is_dir && bn_bytes.len() < an_bytes.len())
-                    {
-                        std::cmp::Ordering::Equal
-                    } else if an_bytes.len() < bn_bytes.len() {
-                        std::cmp::Ordering::Less
-                    } else {
-                        std::cmp::Ordering::Greater
-                    }
-                }
-            }
-            other => other,
-        }
-    });
-
-    // get all the files and directories in the directory for the tree object
-    let mut tree_object = Vec::new();
-    for entry in entries {
-        let file_name = entry.file_name();
-        if file_name == ".git" {
-            continue;
-        }
-        let meta = entry.metadata().context("get metadata")?;
-        let mode = if meta.is_dir() {
-            "40000"
-        } else if meta.is_symlink() {
-            "120000"
-        } else if meta.permissions().mode() & 0o111 != 0 {
-            "100755"
+use anyhow::{bail, Context, Result};
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache_tree::CacheTree,
+    hash::ObjectId,
+    index::{mode_from_metadata, Index},
+    interrupt,
+    objects::{write_object, Kind, Mode, Object},
+    refs::ref_resolve,
+    repository::{repo_open, worktree_path, GitRepository},
+    worktree::WorktreeWalker,
+};
+
+/// The mode of a regular file in a tree written from the work tree, given its path from the
+/// top of the work tree and its metadata.
+type FileMode<'a> = dyn Fn(&str, &fs::Metadata) -> u32 + 'a;
+
+/// Write the tree of the work tree directory `dir` (`""` for the top, or a path ending in `/`)
+/// with every file below it that isn't ignored, hashing blobs on `jobs` threads. Nested
+/// repositories become gitlinks to the commit they have checked out. Returns `None` when there
+/// are no files to write.
+pub(crate) fn write_tree_for(
+    git_repo: &GitRepository,
+    dir: &str,
+    jobs: usize,
+    file_mode: &FileMode,
+) -> Result<Option<ObjectId>> {
+    fn write(git_repo: &GitRepository, dir: &Dir) -> Result<ObjectId> {
+        let dirs = dir
+            .dirs
+            .iter()
+            .map(|(name, sub)| Ok((name.as_str(), write(git_repo, sub)?)))
+            .collect::<Result<Vec<_>>>()?;
+        write_tree_object(git_repo, &dir.files, &dirs)
+    }
+
+    let files = WorktreeWalker::new(git_repo).parallel(jobs > 1).walk(dir)?;
+    // blobs are hashed up front (possibly in parallel) and handed out in path order
+    let blobs = files
+        .iter()
+        .filter(|f| !f.metadata.is_dir())
+        .map(|f| git_repo.work_tree().join(&f.path))
+        .collect::<Vec<_>>();
+    let mut blob_hashes = hash_blobs(git_repo, &blobs, jobs)?.into_iter();
+
+    let mut root = Dir::default();
+    for file in &files {
+        let (mode, hash) = if file.metadata.is_dir() {
+            let head = submodule_head(&git_repo.work_tree().join(&file.path))?;
+            (Mode::Gitlink, head)
         } else {
-            "100644"
+            let mode = match file.metadata.is_symlink() {
+                true => Mode::Symlink,
+                false => Mode::from_bits(file_mode(&file.path, &file.metadata)),
+            };
+            let hash = blob_hashes
+                .next()
+                .expect("one hash was computed for every blob entry");
+            (mode, hash)
         };
-        let hash = if meta.is_dir() {
-            if let Some(hash) = write_tree_for(&entry.path())? {
-                hash
+        root.insert(&file.path[dir.len()..], mode, hash);
+    }
+    if root.files.is_empty() && root.dirs.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(write(git_repo, &root)?))
+}
+
+/// A directory of a tree about to be written: its files and subdirectories, by name.
+#[derive(Default)]
+struct Dir {
+    files: Vec<(String, Mode, ObjectId)>,
+    dirs: BTreeMap<String, Dir>,
+    /// Entries in this directory and below.
+    count: usize,
+}
+
+impl Dir {
+    /// Add the entry at `path` (relative to this directory), and the directories leading to it.
+    fn insert(&mut self, path: &str, mode: Mode, hash: ObjectId) {
+        let mut dir = self;
+        dir.count += 1;
+        let mut components = path.split('/').peekable();
+        while let Some(name) = components.next() {
+            if components.peek().is_none() {
+                dir.files.push((name.to_string(), mode, hash));
             } else {
-                continue;
+                dir = dir.dirs.entry(name.to_string()).or_default();
+                dir.count += 1;
             }
-        } else {
-            let tmp = "temporary";
-            let hash = Object::blob_from_file(&entry.path())
-                .context("open blob input file")?
-                .write(std::fs::File::create(tmp).context("write blog object to temporary file")?)
-                .context("stream file into blob")?;
-            let hash_hex = hex::encode(hash);
-            std::fs::create_dir_all(format!(".git/objects/{}/", &hash_hex[..2]))
-                .context("create subdir of .git/objects")?;
-            std::fs::rename(
-                tmp,
-                format!(".git/objects/{}/{}", &hash_hex[..2], &hash_hex[2..]),
-            )
-            .context("move blob file into .git/objects")?;
-            // let mut hash = [0; 20];
-            // hash.copy_from_slice(hash_hex.as_bytes());
-            hash
-        };
-        // {mode} {filename}\0{20字节二进制SHA-1}
-        tree_object.extend_from_slice(mode.as_bytes());
-        tree_object.push(b' ');
-        tree_object.extend(file_name.as_encoded_bytes());
-        tree_object.push(b'\0');
-        tree_object.extend(hash);
+        }
     }
-    if tree_object.is_empty() {
-        Ok(None)
-    } else {
-        // write the tree object to the objects directory
-        Ok(Some(
-            Object {
-                kind: Kind::Tree,
-                expected_size: tree_object.len() as u64,
-                reader: Cursor::new(tree_object),
-            }
-            .write_to_objects()
-            .context("write tree object to .git/objects")?,
-        ))
+}
+
+/// Write the tree object of a directory with `files` and the subdirectories `dirs`, whose trees
+/// are written already, as `(name, tree)`.
+fn write_tree_object(
+    git_repo: &GitRepository,
+    files: &[(String, Mode, ObjectId)],
+    dirs: &[(&str, ObjectId)],
+) -> Result<ObjectId> {
+    // tree entries sort as if directory names ended with a slash
+    let mut entries = Vec::new();
+    for (name, mode, hash) in files {
+        entries.push((name.clone(), *mode, name.as_str(), *hash));
+    }
+    for &(name, hash) in dirs {
+        entries.push((format!("{name}/"), Mode::Dir, name, hash));
+    }
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    let mut tree = Vec::new();
+    for (_, mode, name, hash) in entries {
+        tree.extend_from_slice(mode.to_string().as_bytes());
+        tree.push(b' ');
+        tree.extend_from_slice(name.as_bytes());
+        tree.push(0);
+        tree.extend_from_slice(hash.as_bytes());
     }
+    let hash = write_object(git_repo, Kind::Tree, &tree).context("write tree object")?;
+    ObjectId::from_hex(&hash)
 }
 
-pub(crate) fn invoke() -> Result<()> {
-    let Some(hash) = write_tree_for(Path::new(".")).context("construct root tree object")? else {
-        anyhow::bail!("asked to make tree object for empty directory");
+/// The commit checked out in the nested repository at `path`, which a tree records as a
+/// gitlink instead of descending into it.
+fn submodule_head(path: &Path) -> Result<ObjectId> {
+    let repo = repo_open(path)?;
+    let Some(head) = ref_resolve(&repo, "HEAD")? else {
+        bail!("'{}' does not have a commit checked out", path.display());
+    };
+    ObjectId::from_hex(&head).with_context(|| format!("bad HEAD in {}", path.display()))
+}
+
+/// Hash and write every file in `paths` as a blob, spreading the work over `jobs` threads.
+///
+/// The returned hashes are in the same order as `paths`, regardless of the number of threads.
+fn hash_blobs(git_repo: &GitRepository, paths: &[PathBuf], jobs: usize) -> Result<Vec<ObjectId>> {
+    let write_blob = |path: &PathBuf| {
+        interrupt::check()?;
+        Object::blob_from_file(path)
+            .context("open blob input file")?
+            .write_to_objects(git_repo)
+            .with_context(|| format!("write blob object for {}", path.display()))
+    };
+    if jobs <= 1 || paths.len() <= 1 {
+        return paths.iter().map(write_blob).collect();
+    }
+
+    let chunk_size = paths.len().div_ceil(jobs);
+    std::thread::scope(|s| {
+        let handles = paths
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().map(write_blob).collect::<Result<Vec<_>>>()))
+            .collect::<Vec<_>>();
+        let mut hashes = Vec::with_capacity(paths.len());
+        for handle in handles {
+            hashes.extend(handle.join().expect("blob hashing thread panicked")?);
+        }
+        Ok(hashes)
+    })
+}
+
+/// Write the tree objects for the entries of `index`, which must have no conflicts, and return
+/// the root tree hash.
+/// Directories the index's cache tree still has a valid tree for aren't written again.
+pub(crate) fn write_index_tree(git_repo: &GitRepository, index: &Index) -> Result<String> {
+    let tree = build_cache_tree(git_repo, index)?;
+    Ok(hex::encode(tree.valid.expect("written trees are valid").1))
+}
+
+/// Like [`write_index_tree`], but also record the written trees as the index's cache tree, so
+/// that the next write can reuse them.
+pub(crate) fn update_cache_tree(git_repo: &GitRepository, index: &mut Index) -> Result<String> {
+    let tree = build_cache_tree(git_repo, index)?;
+    let hash = hex::encode(tree.valid.expect("written trees are valid").1);
+    index.set_cache_tree(tree);
+    Ok(hash)
+}
+
+/// Write the trees of `index`, returning them as a fully valid cache tree.
+fn build_cache_tree(git_repo: &GitRepository, index: &Index) -> Result<CacheTree> {
+    fn write(git_repo: &GitRepository, dir: &Dir, cached: Option<&CacheTree>) -> Result<CacheTree> {
+        if let Some(cached) = cached.filter(|c| matches!(c.valid, Some((n, _)) if n == dir.count)) {
+            return Ok(cached.clone());
+        }
+
+        let mut dirs = Vec::new();
+        let mut children = Vec::new();
+        for (name, sub) in &dir.dirs {
+            let child = write(git_repo, sub, cached.and_then(|c| c.child(name)))?;
+            let (_, hash) = child.valid.expect("written trees are valid");
+            dirs.push((name.as_str(), hash));
+            children.push((name.clone(), child));
+        }
+        let hash = write_tree_object(git_repo, &dir.files, &dirs)?;
+        Ok(CacheTree::new(dir.count, hash, children))
+    }
+
+    if let Some(entry) = index.entries.iter().find(|e| e.stage() != 0) {
+        bail!(
+            "{}: unmerged entries in the index, cannot write a tree",
+            entry.path
+        );
+    }
+    let mut root = Dir::default();
+    for entry in &index.entries {
+        root.insert(&entry.path, Mode::from_bits(entry.mode), entry.hash);
+    }
+    write(git_repo, &root, index.cache_tree().as_ref())
+}
+
+pub(crate) fn invoke(repo: &GitRepository, jobs: usize) -> Result<()> {
+    // without core.filemode, files keep the mode they have in the index
+    let index = if repo.filemode() {
+        Index::new(repo.hash_algo())
+    } else {
+        Index::read(repo)?
+    };
+    let dir = match worktree_path(repo, ".")? {
+        prefix if prefix.is_empty() => prefix,
+        prefix => format!("{prefix}/"),
+    };
+    let file_mode = |path: &str, meta: &fs::Metadata| {
+        mode_from_metadata(repo, meta, index.get(path).map(|e| e.mode))
+    };
+    let Some(hash) =
+        write_tree_for(repo, &dir, jobs, &file_mode).context("construct root tree object")?
+    else {
+        bail!("asked to make tree object for empty directory");
     };
     println!("{}", hex::encode(hash));
     Ok(())
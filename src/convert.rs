@@ -0,0 +1,190 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    attr::{AttrState, Attributes},
+    index::read_worktree_file,
+    repository::GitRepository,
+};
+
+/// How a path's line endings are normalized when it is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eol {
+    /// Stored as it is.
+    Binary,
+    /// Always text: CRLF is stored as LF.
+    Text,
+    /// Text unless the content looks binary (`text=auto`, or `core.autocrlf`).
+    Auto,
+}
+
+/// Byte counts deciding whether content is text, as git gathers them.
+#[derive(Debug, Default)]
+struct TextStats {
+    lone_cr: usize,
+    nul: usize,
+    printable: usize,
+    nonprintable: usize,
+}
+
+impl TextStats {
+    fn gather(data: &[u8]) -> Self {
+        let mut stats = Self::default();
+        for (i, &c) in data.iter().enumerate() {
+            match c {
+                b'\r' if data.get(i + 1) == Some(&b'\n') => {}
+                b'\r' => stats.lone_cr += 1,
+                b'\n' | b'\x08' | b'\t' | b'\x1b' | b'\x0c' => stats.printable += 1,
+                0 => {
+                    stats.nul += 1;
+                    stats.nonprintable += 1;
+                }
+                1..=31 | 127 => stats.nonprintable += 1,
+                _ => stats.printable += 1,
+            }
+        }
+        // a DOS end-of-file marker doesn't make text binary
+        if data.last() == Some(&0x1a) {
+            stats.nonprintable -= 1;
+        }
+        stats
+    }
+
+    /// Whether the content is binary: it has NULs or lone CRs, or more than one byte in 128
+    /// isn't printable.
+    fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.nonprintable
+    }
+}
+
+/// Turns work tree content into what gets stored in blobs, as the `filter`, `text` and `eol`
+/// attributes and `core.autocrlf` ask for it. Both staging files and `hash-object` go through
+/// here, so the two always agree on a file's blob.
+pub(crate) struct Converter<'r> {
+    git_repo: &'r GitRepository,
+    attributes: Attributes<'r>,
+}
+
+impl<'r> Converter<'r> {
+    pub(crate) fn new(git_repo: &'r GitRepository) -> Result<Self> {
+        Ok(Self {
+            git_repo,
+            attributes: Attributes::new(git_repo)?,
+        })
+    }
+
+    /// `data`, the content of the work tree file `path` (relative to the top of the work tree),
+    /// as it is stored: run through the `clean` command of the path's filter driver, then with
+    /// CRLF line endings turned into LF if the path is text.
+    pub(crate) fn convert_to_git(&mut self, path: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+        let data = self.clean(path, data)?;
+        Ok(match self.eol(path) {
+            Eol::Binary => data,
+            Eol::Auto if TextStats::gather(&data).is_binary() => data,
+            Eol::Text | Eol::Auto => crlf_to_lf(data),
+        })
+    }
+
+    /// The blob content of the work tree file at `path`: the converted content of a file, or the
+    /// target of a symlink as it is.
+    pub(crate) fn worktree_blob(&mut self, path: &str) -> Result<Vec<u8>> {
+        let file = self.git_repo.work_tree().join(path);
+        let data = read_worktree_file(&file)?;
+        match file.is_symlink() {
+            true => Ok(data),
+            false => self.convert_to_git(path, data),
+        }
+    }
+
+    /// How line endings of `path` are normalized. A `text` attribute decides, and an `eol`
+    /// attribute makes the path text; otherwise `core.autocrlf` set to `true` or `input` makes
+    /// every path `text=auto`.
+    fn eol(&mut self, path: &str) -> Eol {
+        match self.attributes.get(path, "text") {
+            Some(AttrState::Set) => return Eol::Text,
+            Some(AttrState::Unset) => return Eol::Binary,
+            Some(AttrState::Value(value)) if value == "auto" => return Eol::Auto,
+            _ => {}
+        }
+        if matches!(self.attributes.get(path, "eol"), Some(AttrState::Value(_))) {
+            return Eol::Text;
+        }
+        match self.git_repo.config_get("core", "autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => Eol::Auto,
+            _ if self.git_repo.config_bool("core", "autocrlf") == Some(true) => Eol::Auto,
+            _ => Eol::Binary,
+        }
+    }
+
+    /// Run `data` through the `filter.<driver>.clean` command of the filter driver of `path`,
+    /// with `%f` in the command replaced by the quoted path. A driver without a clean command,
+    /// or whose command fails, leaves `data` as it is unless `filter.<driver>.required` is set.
+    fn clean(&mut self, path: &str, data: Vec<u8>) -> Result<Vec<u8>> {
+        let Some(AttrState::Value(driver)) = self.attributes.get(path, "filter") else {
+            return Ok(data);
+        };
+        let section = format!("filter \"{driver}\"");
+        let required = self.git_repo.config_bool(&section, "required") == Some(true);
+        let Some(command) = self.git_repo.config_get(&section, "clean") else {
+            if required {
+                bail!("{path}: clean filter '{driver}' failed");
+            }
+            return Ok(data);
+        };
+        let command = command.replace("%f", &format!("'{}'", path.replace('\'', "'\\''")));
+        match run_filter(self.git_repo, &command, &data) {
+            Ok(cleaned) => Ok(cleaned),
+            Err(e) if required => Err(e.context(format!("{path}: clean filter '{driver}' failed"))),
+            Err(_) => Ok(data),
+        }
+    }
+}
+
+/// Feed `data` to the shell command `command`, run at the top of the work tree, and return its
+/// output.
+fn run_filter(git_repo: &GitRepository, command: &str, data: &[u8]) -> Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(git_repo.work_tree())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("cannot run filter '{command}'"))?;
+    let output = std::thread::scope(|s| {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // write from another thread so a filter streaming its output back can't deadlock
+        let writer = s.spawn(move || stdin.write_all(data));
+        let output = child.wait_with_output();
+        let written = writer.join().expect("filter input thread panicked");
+        // a filter may exit without reading all its input
+        match written {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+            _ => output,
+        }
+    })
+    .with_context(|| format!("run filter '{command}'"))?;
+    if !output.status.success() {
+        bail!("filter '{command}' failed");
+    }
+    Ok(output.stdout)
+}
+
+/// `data` with every CR that ends a line removed.
+fn crlf_to_lf(data: Vec<u8>) -> Vec<u8> {
+    if !data.windows(2).any(|pair| pair == b"\r\n") {
+        return data;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &c) in data.iter().enumerate() {
+        if c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
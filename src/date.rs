@@ -0,0 +1,313 @@
+use anyhow::{bail, Result};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Days since the Unix epoch of a civil date (Howard Hinnant's `days_from_civil`).
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The civil date `(year, month, day)` of a day count since the Unix epoch.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The offset in seconds of a `+hhmm`/`-hhmm` timezone.
+pub(crate) fn parse_tz(tz: &str) -> Option<i64> {
+    let (sign, digits) = match tz.as_bytes().first() {
+        Some(b'-') => (-1, &tz[1..]),
+        Some(b'+') => (1, &tz[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(sign * (digits[..2].parse::<i64>().ok()? * 3600 + digits[2..].parse::<i64>().ok()? * 60))
+}
+
+/// Broken-down local time of `time` in `tz`: `(year, month, day, weekday, hour, minute, second)`.
+fn local(time: i64, tz: &str) -> (i64, i64, i64, usize, i64, i64, i64) {
+    let local = time + parse_tz(tz).unwrap_or(0);
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    (
+        year,
+        month,
+        day,
+        weekday,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+}
+
+/// Format a commit date the way `git log` does by default: `Thu Oct 15 12:34:56 2026 +0200`.
+pub(crate) fn format_default(time: i64, tz: &str) -> String {
+    let (year, month, day, weekday, h, m, s) = local(time, tz);
+    format!(
+        "{} {} {day} {h:02}:{m:02}:{s:02} {year} {tz}",
+        WEEKDAYS[weekday],
+        MONTHS[month as usize - 1]
+    )
+}
+
+/// Format a date for a mail header as RFC 2822 does: `Thu, 15 Oct 2026 12:34:56 +0200`.
+pub(crate) fn format_rfc2822(time: i64, tz: &str) -> String {
+    let (year, month, day, weekday, h, m, s) = local(time, tz);
+    format!(
+        "{}, {day} {} {year} {h:02}:{m:02}:{s:02} {tz}",
+        WEEKDAYS[weekday],
+        MONTHS[month as usize - 1]
+    )
+}
+
+/// Format a commit date as ISO 8601-like `2026-10-15 12:34:56 +0200`.
+pub(crate) fn format_iso(time: i64, tz: &str) -> String {
+    let (year, month, day, _, h, m, s) = local(time, tz);
+    format!("{year}-{month:02}-{day:02} {h:02}:{m:02}:{s:02} {tz}")
+}
+
+/// Format how long before `now` a date at `time` was, the way git rounds it: `89 seconds ago`,
+/// `2 hours ago`, `1 year, 10 months ago`.
+pub(crate) fn format_relative(time: i64, now: i64) -> String {
+    let count = |n: i64, unit: &str| format!("{n} {unit}{}", if n == 1 { "" } else { "s" });
+    if time > now {
+        return "in the future".to_string();
+    }
+    let seconds = now - time;
+    if seconds < 90 {
+        return format!("{} ago", count(seconds, "second"));
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", count(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", count(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", count(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", count((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", count((days + 15) / 30, "month"));
+    }
+    if days < 1825 {
+        let months = (days * 12 * 2 + 365) / (365 * 2);
+        return match months % 12 {
+            0 => format!("{} ago", count(months / 12, "year")),
+            rest => format!(
+                "{}, {} ago",
+                count(months / 12, "year"),
+                count(rest, "month")
+            ),
+        };
+    }
+    format!("{} ago", count((days + 183) / 365, "year"))
+}
+
+/// How `log` shows dates, named as `--date` and `log.date` take them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DateFormat {
+    /// `Thu Oct 15 12:34:56 2026 +0200`.
+    #[default]
+    Default,
+    /// `3 days ago`.
+    Relative,
+    /// `2026-10-15 12:34:56 +0200`.
+    Iso,
+    /// `2026-10-15`.
+    Short,
+    /// The unix time.
+    Unix,
+}
+
+impl DateFormat {
+    pub(crate) fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "default" => Self::Default,
+            "relative" => Self::Relative,
+            "iso" | "iso8601" => Self::Iso,
+            "short" => Self::Short,
+            "unix" => Self::Unix,
+            _ => bail!("unknown date format {name}"),
+        })
+    }
+
+    /// Format `time`, in the timezone `tz` where the format shows one. `now` is the current
+    /// unix time, which relative dates count back from.
+    pub(crate) fn format(self, time: i64, tz: &str, now: i64) -> String {
+        match self {
+            Self::Default => format_default(time, tz),
+            Self::Relative => format_relative(time, now),
+            Self::Iso => format_iso(time, tz),
+            Self::Short => {
+                let (year, month, day, ..) = local(time, tz);
+                format!("{year}-{month:02}-{day:02}")
+            }
+            Self::Unix => time.to_string(),
+        }
+    }
+}
+
+/// Parse an RFC 2822 date (`Thu, 15 Oct 2026 12:34:56 +0200`) into `<unix time> <timezone>`.
+pub(crate) fn parse_rfc2822(date: &str) -> Option<String> {
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let mut parts = date.split_whitespace();
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| month.eq_ignore_ascii_case(m))? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|p| p.parse::<i64>());
+    let (h, m) = (time.next()?.ok()?, time.next()?.ok()?);
+    let s = time.next().unwrap_or(Ok(0)).ok()?;
+    let tz = parts.next().unwrap_or("+0000");
+    let offset = parse_tz(tz)?;
+
+    let unix = days_from_civil(year, month, day) * 86400 + h * 3600 + m * 60 + s - offset;
+    Some(format!("{unix} {tz}"))
+}
+
+/// Seconds in each unit of a relative date, with git's 30-day months and 365-day years.
+const UNITS: [(&str, i64); 7] = [
+    ("second", 1),
+    ("minute", 60),
+    ("hour", 3600),
+    ("day", 86400),
+    ("week", 7 * 86400),
+    ("month", 30 * 86400),
+    ("year", 365 * 86400),
+];
+
+/// Parse a date the way git's `--since`, `--until` and `--date` take them, into a unix time:
+/// `now`, `yesterday`, relative dates like `2.weeks.ago` or `3 days ago`, `@<unix time>` or a
+/// bare unix time, RFC 2822 dates, and ISO 8601 dates `2026-10-15[ T]12:34[:56][ +0200]`.
+///
+/// `now` is the current unix time. Like git, a date without a time of day takes the current one,
+/// and times without a timezone are UTC.
+pub(crate) fn parse_approxidate(date: &str, now: i64) -> Option<i64> {
+    let date = date.trim();
+    match date {
+        "now" => return Some(now),
+        "yesterday" => return Some(now - 86400),
+        _ => {}
+    }
+    if let Some(unix) = date.strip_prefix('@') {
+        return unix.parse().ok();
+    }
+    if date.len() >= 9 && date.bytes().all(|b| b.is_ascii_digit()) {
+        return date.parse().ok();
+    }
+
+    let words = date
+        .split(|c: char| c == '.' || c == '_' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>();
+    if let [count, unit, "ago"] = words[..] {
+        let count: i64 = count.parse().ok()?;
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        let (_, seconds) = UNITS.iter().find(|(name, _)| *name == unit)?;
+        return Some(now - count * seconds);
+    }
+
+    if date.contains(',') || date.split_whitespace().count() >= 4 {
+        let parsed = parse_rfc2822(date)?;
+        return parsed.split_once(' ')?.0.parse().ok();
+    }
+    parse_iso(date, now).map(|(unix, _)| unix)
+}
+
+/// Parse an ISO 8601 date `2026-10-15[ T]12:34[:56][ +0200]` into a unix time and the offset in
+/// seconds of its timezone. A date without a time of day takes the one of `now`, and times
+/// without a timezone are UTC.
+pub(crate) fn parse_iso(date: &str, now: i64) -> Option<(i64, i64)> {
+    let (day, rest) = match date.find(['T', ' ']) {
+        Some(at) => (&date[..at], date[at + 1..].trim()),
+        None => (date.strip_suffix('Z').unwrap_or(date), ""),
+    };
+    let mut ymd = day.split('-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    if ymd.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if rest.is_empty() {
+        return Some((days * 86400 + now.rem_euclid(86400), 0));
+    }
+
+    let (time, tz) = match rest.find(['+', '-', 'Z', ' ']) {
+        Some(at) => (&rest[..at], rest[at..].trim()),
+        None => (rest, ""),
+    };
+    let mut hms = time.split(':').map(|p| p.parse::<i64>().ok());
+    let (h, m) = (hms.next()??, hms.next()??);
+    let s = hms.next().unwrap_or(Some(0))?;
+    let offset = match tz {
+        "" | "Z" => 0,
+        tz => parse_tz(tz)?,
+    };
+    Some((days * 86400 + h * 3600 + m * 60 + s - offset, offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2026-10-15 12:00:00 UTC.
+    const NOW: i64 = 1_792_065_600;
+
+    #[test]
+    fn parses_relative_dates() {
+        assert_eq!(parse_approxidate("now", NOW), Some(NOW));
+        assert_eq!(parse_approxidate("yesterday", NOW), Some(NOW - 86400));
+        assert_eq!(
+            parse_approxidate("2.weeks.ago", NOW),
+            Some(NOW - 14 * 86400)
+        );
+        assert_eq!(parse_approxidate("3 days ago", NOW), Some(NOW - 3 * 86400));
+        assert_eq!(parse_approxidate("1.hour.ago", NOW), Some(NOW - 3600));
+        assert_eq!(parse_approxidate("2.fortnights.ago", NOW), None);
+    }
+
+    #[test]
+    fn parses_absolute_dates() {
+        assert_eq!(parse_approxidate("@1700000000", NOW), Some(1_700_000_000));
+        assert_eq!(parse_approxidate("1700000000", NOW), Some(1_700_000_000));
+        assert_eq!(
+            parse_approxidate("2026-10-15 12:00:00 +0200", NOW),
+            Some(NOW - 7200)
+        );
+        assert_eq!(parse_approxidate("2026-10-15T12:00Z", NOW), Some(NOW));
+        assert_eq!(
+            parse_approxidate("Thu, 15 Oct 2026 12:00:00 +0000", NOW),
+            Some(NOW)
+        );
+        // a day alone takes the current time of day
+        assert_eq!(parse_approxidate("2026-10-14", NOW), Some(NOW - 86400));
+        assert_eq!(parse_approxidate("2026-13-01", NOW), None);
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    commands::commit_tree::kvlm_parse,
+    objects::object_read,
+    refs::{ref_list, resolve_head, Head},
+    repository::GitRepository,
+    signature::Signature,
+};
+
+/// A ref, with what it points at once annotated tags are dereferenced.
+pub(crate) struct RefTip {
+    /// The full name, like `refs/heads/main`.
+    pub(crate) name: String,
+    /// The object the ref itself holds.
+    pub(crate) hash: String,
+    /// The object at the end of any chain of annotated tags.
+    pub(crate) peeled: String,
+    /// For an annotated tag, its tagger date.
+    pub(crate) tag_date: Option<i64>,
+}
+
+/// Every ref of the repository, indexed both by name and by the object it decorates.
+pub(crate) struct RefIndex {
+    /// Sorted by name.
+    pub(crate) refs: Vec<RefTip>,
+    pub(crate) head: Head,
+    /// The refs pointing at each object (directly or through tags), as indexes into `refs`.
+    by_object: HashMap<String, Vec<usize>>,
+}
+
+/// The ref index of `git_repo`, read on first use and kept for the rest of the process, so that
+/// decorating every commit of a long log doesn't list the refs again each time.
+pub(crate) fn ref_index(git_repo: &GitRepository) -> Result<&'static RefIndex> {
+    static INDEX: OnceLock<RefIndex> = OnceLock::new();
+    if let Some(index) = INDEX.get() {
+        return Ok(index);
+    }
+    let index = RefIndex::read(git_repo)?;
+    Ok(INDEX.get_or_init(|| index))
+}
+
+impl RefIndex {
+    fn read(git_repo: &GitRepository) -> Result<Self> {
+        let mut refs = Vec::new();
+        let mut by_object: HashMap<String, Vec<usize>> = HashMap::new();
+        for (name, hash) in ref_list(git_repo)? {
+            let mut peeled = hash.clone();
+            let mut tag_date = None;
+            by_object.entry(hash.clone()).or_default().push(refs.len());
+            // bound the chain so a corrupt tag cycle can't loop forever
+            for _ in 0..10 {
+                let obj = object_read(git_repo, &peeled)?;
+                if obj.format() != "tag" {
+                    break;
+                }
+                let kvlm = kvlm_parse(&obj.serialize())?;
+                let target = kvlm
+                    .get(b"object".as_slice())
+                    .and_then(|v| v.first())
+                    .with_context(|| format!("tag {peeled} has no object header"))?;
+                if tag_date.is_none() {
+                    tag_date = kvlm
+                        .get(b"tagger".as_slice())
+                        .and_then(|v| v.first())
+                        .and_then(|tagger| {
+                            Signature::parse(&String::from_utf8_lossy(tagger))
+                                .map(|tagger| tagger.time)
+                        });
+                }
+                peeled = String::from_utf8(target.clone()).context("tag object isn't utf-8")?;
+                by_object
+                    .entry(peeled.clone())
+                    .or_default()
+                    .push(refs.len());
+            }
+            refs.push(RefTip {
+                name,
+                hash,
+                peeled,
+                tag_date,
+            });
+        }
+        Ok(Self {
+            refs,
+            head: resolve_head(git_repo)?,
+            by_object,
+        })
+    }
+
+    /// The names decorating `hash` as `log --decorate` shows them: `HEAD` first (as
+    /// `HEAD -> <branch>` when it is on a branch here), then the other refs with their
+    /// `refs/heads/`, `refs/remotes/` or `refs/tags/` prefix shortened, tags marked `tag: `.
+    pub(crate) fn decorations(&self, hash: &str) -> Vec<String> {
+        let refs = self.by_object.get(hash).map_or(&[][..], Vec::as_slice);
+        let mut names = Vec::new();
+        let mut current = None;
+        if self.head.commit() == Some(hash) {
+            match &self.head {
+                Head::Branch(branch, _) if refs.iter().any(|&i| self.refs[i].name == *branch) => {
+                    current = Some(branch.as_str());
+                    names.push(format!("HEAD -> {}", short_name(branch)));
+                }
+                _ => names.push("HEAD".to_string()),
+            }
+        }
+        // like git, later refs are listed first
+        for &i in refs.iter().rev() {
+            let name = &self.refs[i].name;
+            if Some(name.as_str()) == current {
+                continue;
+            }
+            names.push(match name.strip_prefix("refs/tags/") {
+                Some(tag) => format!("tag: {tag}"),
+                None => short_name(name).to_string(),
+            });
+        }
+        names
+    }
+
+    /// The full name of the ref that `name` means, trying the same prefixes as revision lookup:
+    /// `HEAD` is the branch it is on (or itself when detached), and `main` is
+    /// `refs/heads/main` unless a `refs/tags/main` comes first. `None` if no ref matches.
+    pub(crate) fn full_name(&self, name: &str) -> Option<String> {
+        if name == "HEAD" {
+            return Some(match &self.head {
+                Head::Branch(branch, _) => branch.clone(),
+                Head::Detached(_) => "HEAD".to_string(),
+            });
+        }
+        [
+            name.to_string(),
+            format!("refs/{name}"),
+            format!("refs/tags/{name}"),
+            format!("refs/heads/{name}"),
+            format!("refs/remotes/{name}"),
+            format!("refs/remotes/{name}/HEAD"),
+        ]
+        .into_iter()
+        .find(|candidate| {
+            self.refs
+                .binary_search_by(|tip| tip.name.as_str().cmp(candidate))
+                .is_ok()
+        })
+    }
+
+    /// The ref that `name` means, as [`RefIndex::full_name`] finds it.
+    pub(crate) fn resolve(&self, name: &str) -> Option<&RefTip> {
+        let full_name = self.full_name(name)?;
+        self.refs.iter().find(|tip| tip.name == full_name)
+    }
+}
+
+/// `name` without its `refs/heads/`, `refs/remotes/` or `refs/tags/` prefix.
+fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/remotes/", "refs/tags/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
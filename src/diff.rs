@@ -0,0 +1,2005 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    attr::{AttrState, Attributes},
+    binary_patch::write_binary_patch,
+    color::{BOLD, CYAN, GREEN, RED, RESET},
+    hash::HashAlgo,
+    interrupt::TempPath,
+    objects::{object_read, read_commit, read_tree, Commit, Mode, TreeEntry},
+    repository::{repo_file, GitRepository},
+};
+
+/// Like git's `diff.renameLimit`: inexact rename detection is skipped when there are more than
+/// this many sources times destinations squared to compare.
+const RENAME_LIMIT: usize = 1000;
+
+/// Only the start of a blob is checked for NUL bytes when deciding whether it's binary.
+const BINARY_CHECK_LEN: usize = 8000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct DiffOptions {
+    /// Minimum similarity (in percent) for an add/delete pair to be reported as a rename, or
+    /// `None` to disable rename detection.
+    pub(crate) rename_threshold: Option<u8>,
+    /// Lines of context around each hunk.
+    pub(crate) context: usize,
+    /// Which whitespace differences are ignored when comparing lines.
+    pub(crate) whitespace: Whitespace,
+    /// Changes that only add or remove blank lines don't produce hunks of their own.
+    pub(crate) ignore_blank_lines: bool,
+    /// Show changed lines inline, with words marked as `[-removed-]` and `{+added+}`.
+    pub(crate) word_diff: bool,
+    /// Compare files with a `diff=<driver>` attribute by the output of the driver's
+    /// `diff.<driver>.textconv` command.
+    pub(crate) textconv: bool,
+    /// Write binary changes as a `GIT binary patch` that can be applied, with full blob hashes
+    /// on their `index` lines, instead of just saying they differ.
+    pub(crate) binary: bool,
+    /// Color headers, hunk headers and changed lines with ANSI escapes.
+    pub(crate) color: bool,
+    /// Show how many lines each file changed instead of the patch.
+    pub(crate) stat: bool,
+    /// The width `--stat` fits in, instead of the terminal's.
+    pub(crate) stat_width: Option<usize>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            rename_threshold: Some(50),
+            context: 3,
+            whitespace: Whitespace::Exact,
+            ignore_blank_lines: false,
+            word_diff: false,
+            textconv: true,
+            binary: false,
+            color: false,
+            stat: false,
+            stat_width: None,
+        }
+    }
+}
+
+/// How whitespace is treated when comparing lines. Lines are only normalized for the
+/// comparison; the output always shows them as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Whitespace {
+    #[default]
+    Exact,
+    /// `-b`: runs of whitespace compare equal to each other, and trailing whitespace is ignored.
+    IgnoreChange,
+    /// `-w`: whitespace is ignored entirely.
+    IgnoreAll,
+}
+
+impl Whitespace {
+    fn normalize(self, line: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            Self::Exact => Cow::Borrowed(line),
+            Self::IgnoreAll => Cow::Owned(
+                line.iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect(),
+            ),
+            Self::IgnoreChange => {
+                let mut out = Vec::with_capacity(line.len());
+                let mut in_space = false;
+                for &b in line {
+                    if b.is_ascii_whitespace() {
+                        in_space = true;
+                        continue;
+                    }
+                    // a run of whitespace becomes one space, and trailing whitespace is dropped
+                    if in_space {
+                        out.push(b' ');
+                        in_space = false;
+                    }
+                    out.push(b);
+                }
+                Cow::Owned(out)
+            }
+        }
+    }
+}
+
+/// One side of a file pair.
+#[derive(Debug, Clone)]
+pub(crate) struct DiffFile {
+    pub(crate) path: String,
+    pub(crate) mode: Mode,
+    pub(crate) hash: String,
+}
+
+/// A changed path: an addition (`old` is `None`), a deletion (`new` is `None`), a modification,
+/// or a rename when both sides are present with different paths.
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    pub(crate) old: Option<DiffFile>,
+    pub(crate) new: Option<DiffFile>,
+    /// Content similarity in percent for renames.
+    pub(crate) similarity: Option<u8>,
+}
+
+impl Change {
+    /// The path this change is reported under.
+    pub(crate) fn path(&self) -> &str {
+        self.new
+            .as_ref()
+            .or(self.old.as_ref())
+            .map(|f| f.path.as_str())
+            .expect("a change has at least one side")
+    }
+}
+
+/// Compare two trees (either of which may be absent), recursing into subtrees.
+///
+/// The result is sorted by path and contains no renames; see [`detect_renames`].
+pub(crate) fn diff_trees(
+    git_repo: &GitRepository,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    diff_trees_at(git_repo, "", old, new, &mut changes)?;
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// The changes `commit` made to its first parent (to nothing, for a root commit), with
+/// renames found at `rename_threshold` if that is given.
+pub(crate) fn commit_changes(
+    blobs: &mut BlobCache,
+    commit: &Commit,
+    rename_threshold: Option<u8>,
+) -> Result<Vec<Change>> {
+    let git_repo = blobs.git_repo;
+    let parent = match commit.parents.first() {
+        Some(parent) => Some(read_commit(git_repo, parent)?.tree),
+        None => None,
+    };
+    let changes = diff_trees(git_repo, parent.as_deref(), Some(&commit.tree))?;
+    match rename_threshold {
+        Some(threshold) => detect_renames(blobs, changes, threshold),
+        None => Ok(changes),
+    }
+}
+
+fn diff_trees_at(
+    git_repo: &GitRepository,
+    prefix: &str,
+    old: Option<&str>,
+    new: Option<&str>,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let entries = |tree: Option<&str>| -> Result<BTreeMap<String, TreeEntry>> {
+        Ok(match tree {
+            Some(tree) => read_tree(git_repo, tree)?
+                .into_iter()
+                .map(|e| (e.name.clone(), e))
+                .collect(),
+            None => BTreeMap::new(),
+        })
+    };
+    let old_entries = entries(old)?;
+    let mut new_entries = entries(new)?;
+
+    let file = |path: &str, e: &TreeEntry| DiffFile {
+        path: path.to_string(),
+        mode: e.mode,
+        hash: e.hash.clone(),
+    };
+    for (name, o) in old_entries {
+        let path = format!("{prefix}{name}");
+        let n = new_entries.remove(&name);
+        match n {
+            Some(n) if o.is_tree() && n.is_tree() => {
+                diff_trees_at(
+                    git_repo,
+                    &format!("{path}/"),
+                    Some(&o.hash),
+                    Some(&n.hash),
+                    changes,
+                )?;
+            }
+            Some(n) if o.is_tree() == n.is_tree() => {
+                if o.hash != n.hash || o.mode != n.mode {
+                    changes.push(Change {
+                        old: Some(file(&path, &o)),
+                        new: Some(file(&path, &n)),
+                        similarity: None,
+                    });
+                }
+            }
+            n => {
+                // a deletion, or a file replaced by a directory (or vice versa)
+                if o.is_tree() {
+                    diff_trees_at(git_repo, &format!("{path}/"), Some(&o.hash), None, changes)?;
+                } else {
+                    changes.push(Change {
+                        old: Some(file(&path, &o)),
+                        new: None,
+                        similarity: None,
+                    });
+                }
+                if let Some(n) = n {
+                    add_entry(git_repo, &path, &n, changes)?;
+                }
+            }
+        }
+    }
+    for (name, n) in new_entries {
+        add_entry(git_repo, &format!("{prefix}{name}"), &n, changes)?;
+    }
+    Ok(())
+}
+
+fn add_entry(
+    git_repo: &GitRepository,
+    path: &str,
+    entry: &TreeEntry,
+    changes: &mut Vec<Change>,
+) -> Result<()> {
+    if entry.is_tree() {
+        diff_trees_at(
+            git_repo,
+            &format!("{path}/"),
+            None,
+            Some(&entry.hash),
+            changes,
+        )
+    } else {
+        changes.push(Change {
+            old: None,
+            new: Some(DiffFile {
+                path: path.to_string(),
+                mode: entry.mode,
+                hash: entry.hash.clone(),
+            }),
+            similarity: None,
+        });
+        Ok(())
+    }
+}
+
+/// A flat listing of files, as the index or the work tree has them: each path with its mode and
+/// blob hash.
+pub(crate) type Listing = BTreeMap<String, (Mode, String)>;
+
+/// Compare two listings of files, as [`diff_trees`] compares trees: the result is sorted by path
+/// and contains no renames.
+pub(crate) fn diff_listings(old: &Listing, new: &Listing) -> Vec<Change> {
+    let file = |path: &String, (mode, hash): &(Mode, String)| DiffFile {
+        path: path.clone(),
+        mode: *mode,
+        hash: hash.clone(),
+    };
+    let mut changes = Vec::new();
+    for (path, o) in old {
+        match new.get(path) {
+            Some(n) if n == o => {}
+            n => changes.push(Change {
+                old: Some(file(path, o)),
+                new: n.map(|n| file(path, n)),
+                similarity: None,
+            }),
+        }
+    }
+    for (path, n) in new.iter().filter(|(path, _)| !old.contains_key(*path)) {
+        changes.push(Change {
+            old: None,
+            new: Some(file(path, n)),
+            similarity: None,
+        });
+    }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    changes
+}
+
+/// Load blob contents, caching them since rename detection and patch output read the same
+/// blobs repeatedly.
+pub(crate) struct BlobCache<'r> {
+    git_repo: &'r GitRepository,
+    blobs: HashMap<String, Vec<u8>>,
+    /// Loaded the first time a path's textconv driver is looked up.
+    attributes: Option<Attributes<'r>>,
+}
+
+impl<'r> BlobCache<'r> {
+    pub(crate) fn new(git_repo: &'r GitRepository) -> Self {
+        Self {
+            git_repo,
+            blobs: HashMap::new(),
+            attributes: None,
+        }
+    }
+
+    /// The textconv driver of `path` and its command, if it has one.
+    pub(crate) fn textconv_driver(&mut self, path: &str) -> Result<Option<(String, String)>> {
+        let attributes = match &mut self.attributes {
+            Some(attributes) => attributes,
+            None => self.attributes.insert(Attributes::new(self.git_repo)?),
+        };
+        let Some(AttrState::Value(driver)) = attributes.get(path, "diff") else {
+            return Ok(None);
+        };
+        let command = self
+            .git_repo
+            .config_get(&format!("diff \"{driver}\""), "textconv");
+        Ok(command.map(|command| (driver, command.to_string())))
+    }
+
+    /// The blob `hash` converted to text by the textconv `command`. Results are cached under
+    /// `.git/textconv-cache`, by command and blob, since converters can be slow.
+    pub(crate) fn textconv(&mut self, command: &str, hash: &str) -> Result<Vec<u8>> {
+        let dir = repo_file(
+            self.git_repo,
+            &["textconv-cache", &hex::encode(Sha1::digest(command))],
+            false,
+        )?;
+        fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+        let cached = dir.join(hash);
+        if let Ok(text) = fs::read(&cached) {
+            return Ok(text);
+        }
+
+        // the command gets the blob in a temporary file, like git's external diff helpers
+        let input = TempPath::new(dir.join(format!("{hash}.{}.tmp", std::process::id())));
+        fs::write(input.path(), self.get(hash)?)
+            .with_context(|| format!("write {}", input.path().display()))?;
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{command} \"$@\""))
+            .arg(command)
+            .arg(input.path())
+            .stderr(Stdio::inherit())
+            .output();
+        drop(input);
+        let output = output.with_context(|| format!("run textconv command '{command}'"))?;
+        if !output.status.success() {
+            bail!("textconv command '{command}' failed for {hash}");
+        }
+
+        let tmp = TempPath::new(dir.join(format!("{hash}.{}.out", std::process::id())));
+        fs::write(tmp.path(), &output.stdout)
+            .with_context(|| format!("write {}", tmp.path().display()))?;
+        tmp.persist(&cached)
+            .with_context(|| format!("replace {}", cached.display()))?;
+        Ok(output.stdout)
+    }
+
+    /// Provide the content of the blob `hash` without it being in the object store, as for a
+    /// work tree file being compared.
+    pub(crate) fn insert(&mut self, hash: String, data: Vec<u8>) {
+        self.blobs.insert(hash, data);
+    }
+
+    pub(crate) fn get(&mut self, hash: &str) -> Result<&[u8]> {
+        if !self.blobs.contains_key(hash) {
+            let data = object_read(self.git_repo, hash)
+                .with_context(|| format!("read blob {hash}"))?
+                .serialize();
+            self.blobs.insert(hash.to_string(), data);
+        }
+        Ok(&self.blobs[hash])
+    }
+}
+
+/// Pair up deletions and additions into renames.
+///
+/// Identical blobs are matched first; the remaining pairs are scored by how many bytes of lines
+/// they have in common, and paired greedily from the most similar down to `threshold` percent.
+pub(crate) fn detect_renames(
+    blobs: &mut BlobCache,
+    changes: Vec<Change>,
+    threshold: u8,
+) -> Result<Vec<Change>> {
+    let mut result = Vec::new();
+    let mut sources = Vec::new();
+    let mut destinations = Vec::new();
+    for change in changes {
+        match (&change.old, &change.new) {
+            (Some(old), None) => sources.push(Some(old.clone())),
+            (None, Some(new)) => destinations.push(Some(new.clone())),
+            _ => result.push(change),
+        }
+    }
+
+    let rename = |old: DiffFile, new: DiffFile, similarity: u8| Change {
+        old: Some(old),
+        new: Some(new),
+        similarity: Some(similarity),
+    };
+
+    // exact renames, preferring a source with the same file name when there are several
+    for dst in destinations.iter_mut() {
+        let d = dst.as_ref().expect("destinations are only taken once");
+        let base_name = |p: &str| p.rsplit('/').next().unwrap_or(p).to_string();
+        let candidates = sources
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.as_ref().is_some_and(|s| s.hash == d.hash))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let best = candidates
+            .iter()
+            .find(|&&i| base_name(&sources[i].as_ref().unwrap().path) == base_name(&d.path))
+            .or(candidates.first());
+        if let Some(&i) = best {
+            let src = sources[i].take().unwrap();
+            result.push(rename(src, dst.take().unwrap(), 100));
+        }
+    }
+
+    let remaining_src = sources.iter().flatten().count();
+    let remaining_dst = destinations.iter().flatten().count();
+    if remaining_src * remaining_dst <= RENAME_LIMIT * RENAME_LIMIT {
+        let mut scores = Vec::new();
+        for (si, src) in sources.iter().enumerate() {
+            let Some(src) = src else { continue };
+            for (di, dst) in destinations.iter().enumerate() {
+                let Some(dst) = dst else { continue };
+                let old = blobs.get(&src.hash)?.to_vec();
+                let new = blobs.get(&dst.hash)?;
+                let score = similarity(&old, new, threshold);
+                if score >= threshold {
+                    scores.push((score, si, di));
+                }
+            }
+        }
+        // most similar first; ties resolved by path order for deterministic output
+        scores.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+        for (score, si, di) in scores {
+            if sources[si].is_some() && destinations[di].is_some() {
+                let src = sources[si].take().unwrap();
+                let dst = destinations[di].take().unwrap();
+                result.push(rename(src, dst, score));
+            }
+        }
+    }
+
+    for src in sources.into_iter().flatten() {
+        result.push(Change {
+            old: Some(src),
+            new: None,
+            similarity: None,
+        });
+    }
+    for dst in destinations.into_iter().flatten() {
+        result.push(Change {
+            old: None,
+            new: Some(dst),
+            similarity: None,
+        });
+    }
+    result.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(result)
+}
+
+/// Estimate how similar two blobs are, in percent: the bytes of lines they have in common over
+/// the size of the larger one.
+fn similarity(old: &[u8], new: &[u8], threshold: u8) -> u8 {
+    let max = old.len().max(new.len());
+    let min = old.len().min(new.len());
+    if max == 0 {
+        return 100;
+    }
+    // the size difference alone rules out reaching the threshold
+    if (max - min) * 100 > max * (100 - threshold as usize) {
+        return 0;
+    }
+
+    let mut counts: HashMap<&[u8], isize> = HashMap::new();
+    for line in split_lines(old) {
+        *counts.entry(line).or_default() += 1;
+    }
+    let mut common = 0;
+    for line in split_lines(new) {
+        if let Some(count) = counts.get_mut(line) {
+            if *count > 0 {
+                *count -= 1;
+                common += line.len();
+            }
+        }
+    }
+    (common * 100 / max) as u8
+}
+
+/// Split text into lines, each keeping its trailing newline (the last one may lack it).
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+pub(crate) fn is_binary(data: &[u8]) -> bool {
+    data[..data.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// A line-level edit produced by [`diff_lines`], with line indices into the old/new sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Compute a minimal-ish line diff between `old` and `new` with Myers' linear-space algorithm,
+/// comparing lines after normalizing their `whitespace`.
+pub(crate) fn diff_lines(old: &[&[u8]], new: &[&[u8]], whitespace: Whitespace) -> Vec<Edit> {
+    // intern lines so the inner loops compare integers
+    let mut ids: HashMap<Cow<[u8]>, usize> = HashMap::new();
+    let mut a = Vec::with_capacity(old.len());
+    let mut b = Vec::with_capacity(new.len());
+    for (lines, out) in [(old, &mut a), (new, &mut b)] {
+        for line in lines {
+            let next = ids.len();
+            out.push(*ids.entry(whitespace.normalize(line)).or_insert(next));
+        }
+    }
+
+    let mut deleted = vec![false; a.len()];
+    let mut inserted = vec![false; b.len()];
+    compare_seq(&a, &b, 0, 0, &mut deleted, &mut inserted);
+    compact(&a, old, &mut deleted, &inserted);
+    compact(&b, new, &mut inserted, &deleted);
+
+    let mut edits = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && deleted[i] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else if j < b.len() && inserted[j] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(i, j));
+            i += 1;
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// A run of changed lines `start..end` on one side of a diff (empty between two unchanged
+/// lines). The n-th group of one side corresponds to the n-th group of the other.
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(changed: &[bool]) -> Self {
+        let end = changed.iter().take_while(|c| **c).count();
+        Self { start: 0, end }
+    }
+
+    fn next(&mut self, changed: &[bool]) -> bool {
+        if self.end == changed.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, changed: &[bool]) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+
+    /// Shift the group down one line if that describes the same edit, merging it with a group
+    /// it runs into.
+    fn slide_down(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.end == lines.len() || lines[self.start] != lines[self.end] {
+            return false;
+        }
+        changed[self.start] = false;
+        changed[self.end] = true;
+        self.start += 1;
+        self.end += 1;
+        while self.end < changed.len() && changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn slide_up(&mut self, lines: &[usize], changed: &mut [bool]) -> bool {
+        if self.start == 0 || lines[self.start - 1] != lines[self.end - 1] {
+            return false;
+        }
+        self.start -= 1;
+        self.end -= 1;
+        changed[self.start] = true;
+        changed[self.end] = false;
+        while self.start > 0 && changed[self.start - 1] {
+            self.start -= 1;
+        }
+        true
+    }
+}
+
+/// Shift runs of changed lines to where they read best, like xdiff's `xdl_change_compact`: runs
+/// that can slide are lined up with a change on the `other` side if possible (so a replaced
+/// line shows as `-`/`+` next to each other), and are otherwise placed by git's indent
+/// heuristic.
+fn compact(lines: &[usize], text: &[&[u8]], changed: &mut [bool], other: &[bool]) {
+    let mut g = Group::first(changed);
+    let mut go = Group::first(other);
+    loop {
+        if g.end > g.start {
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                let size = g.end - g.start;
+                end_matching_other = None;
+                while g.slide_up(lines, changed) {
+                    go.previous(other);
+                }
+                earliest_end = g.end;
+                if go.end > go.start {
+                    end_matching_other = Some(g.end);
+                }
+                while g.slide_down(lines, changed) {
+                    go.next(other);
+                    if go.end > go.start {
+                        end_matching_other = Some(g.end);
+                    }
+                }
+                // sliding may have merged groups, in which case the new group gets another go
+                if size == g.end - g.start {
+                    break;
+                }
+            }
+
+            if g.end == earliest_end {
+                // no sliding possible
+            } else if end_matching_other.is_some() {
+                while go.end == go.start {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            } else {
+                let size = g.end - g.start;
+                let lowest = earliest_end
+                    .max(g.end.saturating_sub(size + 1))
+                    .max(g.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+                let mut best: Option<(usize, SplitScore)> = None;
+                for shift in lowest..=g.end {
+                    let mut score = SplitScore::default();
+                    score.add(&SplitMeasurement::new(text, shift));
+                    score.add(&SplitMeasurement::new(text, shift - size));
+                    if best.as_ref().is_none_or(|(_, b)| score.cmp(b) <= 0) {
+                        best = Some((shift, score));
+                    }
+                }
+                let best_shift = best.map_or(g.end, |(shift, _)| shift);
+                while g.end > best_shift {
+                    g.slide_up(lines, changed);
+                    go.previous(other);
+                }
+            }
+        }
+
+        if !g.next(changed) {
+            break;
+        }
+        go.next(other);
+    }
+}
+
+// Weights of git's indent heuristic, tuned upstream on a corpus of human-reviewed diffs.
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+const MAX_INDENT: i32 = 200;
+const MAX_BLANKS: i32 = 20;
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+/// The indentation width of `line` (tabs to multiples of 8), or `None` if it is blank.
+fn indent(line: &[u8]) -> Option<i32> {
+    let mut width = 0;
+    for &c in line {
+        match c {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            c if c.is_ascii_whitespace() || c == 0x0b => {}
+            _ => return Some(width),
+        }
+        if width >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+    None
+}
+
+/// The surroundings of a position a change could start or end at.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: Option<i32>,
+    pre_blank: i32,
+    pre_indent: Option<i32>,
+    post_blank: i32,
+    post_indent: Option<i32>,
+}
+
+impl SplitMeasurement {
+    fn new(text: &[&[u8]], split: usize) -> Self {
+        let mut m = Self {
+            end_of_file: split >= text.len(),
+            indent: text.get(split).and_then(|l| indent(l)),
+            pre_blank: 0,
+            pre_indent: None,
+            post_blank: 0,
+            post_indent: None,
+        };
+        for line in text[..split.min(text.len())].iter().rev() {
+            m.pre_indent = indent(line);
+            if m.pre_indent.is_some() {
+                break;
+            }
+            m.pre_blank += 1;
+            if m.pre_blank == MAX_BLANKS {
+                m.pre_indent = Some(0);
+                break;
+            }
+        }
+        for line in text.iter().skip(split + 1) {
+            m.post_indent = indent(line);
+            if m.post_indent.is_some() {
+                break;
+            }
+            m.post_blank += 1;
+            if m.post_blank == MAX_BLANKS {
+                m.post_indent = Some(0);
+                break;
+            }
+        }
+        m
+    }
+}
+
+#[derive(Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent.is_none() && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+        let post_blank = if m.indent.is_none() {
+            1 + m.post_blank
+        } else {
+            0
+        };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = m.indent.or(m.post_indent);
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent.unwrap_or(-1);
+        let (Some(indent), Some(pre_indent)) = (indent, m.pre_indent) else {
+            return;
+        };
+        if indent > pre_indent {
+            self.penalty += if any_blanks {
+                RELATIVE_INDENT_WITH_BLANK_PENALTY
+            } else {
+                RELATIVE_INDENT_PENALTY
+            };
+        } else if indent < pre_indent {
+            let outdent = m.post_indent.is_some_and(|post| post > indent);
+            self.penalty += match (outdent, any_blanks) {
+                (true, true) => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+                (true, false) => RELATIVE_OUTDENT_PENALTY,
+                (false, true) => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+                (false, false) => RELATIVE_DEDENT_PENALTY,
+            };
+        }
+    }
+
+    /// Negative when `self` is the better split.
+    fn cmp(&self, other: &Self) -> i32 {
+        INDENT_WEIGHT * (self.effective_indent - other.effective_indent).signum()
+            + (self.penalty - other.penalty)
+    }
+}
+
+/// Mark the lines of `a` that are deleted and the lines of `b` that are inserted, where `a` and
+/// `b` start at `a_off` and `b_off` of the full sequences.
+fn compare_seq(
+    mut a: &[usize],
+    mut b: &[usize],
+    mut a_off: usize,
+    mut b_off: usize,
+    deleted: &mut [bool],
+    inserted: &mut [bool],
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    a = &a[prefix..];
+    b = &b[prefix..];
+    a_off += prefix;
+    b_off += prefix;
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    a = &a[..a.len() - suffix];
+    b = &b[..b.len() - suffix];
+
+    if a.is_empty() || b.is_empty() {
+        deleted[a_off..a_off + a.len()].fill(true);
+        inserted[b_off..b_off + b.len()].fill(true);
+        return;
+    }
+    match middle_snake(a, b) {
+        Some((x, y)) if (x, y) != (0, 0) && (x, y) != (a.len(), b.len()) => {
+            compare_seq(&a[..x], &b[..y], a_off, b_off, deleted, inserted);
+            compare_seq(&a[x..], &b[y..], a_off + x, b_off + y, deleted, inserted);
+        }
+        _ => {
+            deleted[a_off..a_off + a.len()].fill(true);
+            inserted[b_off..b_off + b.len()].fill(true);
+        }
+    }
+}
+
+/// Find a point on an optimal edit path by searching forwards from the start and backwards from
+/// the end at the same time until the two searches overlap.
+fn middle_snake(a: &[usize], b: &[usize]) -> Option<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let v_offset = max_d;
+    let v_len = 2 * max_d as usize + 2;
+    let mut v1 = vec![-1isize; v_len];
+    let mut v2 = vec![-1isize; v_len];
+    v1[v_offset as usize + 1] = 0;
+    v2[v_offset as usize + 1] = 0;
+    let delta = n - m;
+    let front = delta % 2 != 0;
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (v_offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && v1[k1_offset - 1] < v1[k1_offset + 1]) {
+                v1[k1_offset + 1]
+            } else {
+                v1[k1_offset - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            v1[k1_offset] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let k2_offset = v_offset + delta - k1;
+                if k2_offset >= 0 && (k2_offset as usize) < v_len && v2[k2_offset as usize] != -1 {
+                    let x2 = n - v2[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (v_offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && v2[k2_offset - 1] < v2[k2_offset + 1]) {
+                v2[k2_offset + 1]
+            } else {
+                v2[k2_offset - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            v2[k2_offset] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let k1_offset = v_offset + delta - k2;
+                if k1_offset >= 0 && (k1_offset as usize) < v_len && v1[k1_offset as usize] != -1 {
+                    let x1 = v1[k1_offset as usize];
+                    let y1 = v_offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
+
+/// A group of edits shown together, with the (0-based) line ranges it covers.
+pub(crate) struct Hunk {
+    pub(crate) old_start: usize,
+    pub(crate) old_len: usize,
+    pub(crate) new_start: usize,
+    pub(crate) new_len: usize,
+    pub(crate) edits: Vec<Edit>,
+}
+
+/// Group edits into hunks with `context` lines of unchanged text around each change. Edits
+/// marked in `ignored` are only shown when they fall inside a hunk for another change.
+///
+/// Changes are merged into one hunk when their contexts would touch, following xdiff's rules
+/// for ignorable changes.
+pub(crate) fn hunks(edits: &[Edit], ignored: &[bool], context: usize) -> Vec<Hunk> {
+    // old/new line positions before each edit
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for e in edits {
+        positions.push((o, n));
+        match e {
+            Edit::Equal(..) => {
+                o += 1;
+                n += 1;
+            }
+            Edit::Delete(_) => o += 1,
+            Edit::Insert(_) => n += 1,
+        }
+    }
+    positions.push((o, n));
+
+    // runs of consecutive changed lines, as ranges of edits
+    let mut groups = Vec::new();
+    let mut i = 0;
+    while i < edits.len() {
+        let start = i;
+        while i < edits.len() && !matches!(edits[i], Edit::Equal(..)) {
+            i += 1;
+        }
+        if i > start {
+            groups.push(start..i);
+        }
+        i += 1;
+    }
+    let ignorable = |g: usize| ignored[groups[g].start];
+    // unchanged lines between two consecutive groups
+    let gap = |a: usize, b: usize| groups[b].start - groups[a].end;
+    let inserted = |g: usize| positions[groups[g].end].1 - positions[groups[g].start].1;
+
+    let mut hunks = Vec::new();
+    let mut g = 0;
+    while g < groups.len() {
+        // ignorable changes are dropped unless they're close to the change that follows
+        let mut first = g;
+        while g < groups.len() && ignorable(g) {
+            if g + 1 == groups.len() || gap(g, g + 1) >= context {
+                first = g + 1;
+            }
+            g += 1;
+        }
+        if first == groups.len() {
+            break;
+        }
+
+        let mut last = first;
+        let mut ignored_lines = 0;
+        for next in first + 1..groups.len() {
+            let prev = next - 1;
+            let distance = gap(prev, next);
+            if distance > 2 * context {
+                break;
+            }
+            if distance < context && (!ignorable(next) || last == prev) {
+                last = next;
+                ignored_lines = 0;
+            } else if distance < context {
+                ignored_lines += inserted(next);
+            } else if last != prev
+                && groups[next].start + ignored_lines - groups[last].end > 2 * context
+            {
+                break;
+            } else if !ignorable(next) {
+                last = next;
+                ignored_lines = 0;
+            } else {
+                ignored_lines += inserted(next);
+            }
+        }
+
+        // extend by `context` unchanged lines on each side
+        let mut start = groups[first].start;
+        let mut lines = 0;
+        while start > 0 && lines < context {
+            start -= 1;
+            if matches!(edits[start], Edit::Equal(..)) {
+                lines += 1;
+            }
+        }
+        let mut end = groups[last].end;
+        let mut lines = 0;
+        while end < edits.len() && lines < context {
+            if matches!(edits[end], Edit::Equal(..)) {
+                lines += 1;
+            }
+            end += 1;
+        }
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        hunks.push(Hunk {
+            old_start,
+            old_len: old_end - old_start,
+            new_start,
+            new_len: new_end - new_start,
+            edits: edits[start..end].to_vec(),
+        });
+        g = last + 1;
+    }
+    hunks
+}
+
+/// Format one side of a hunk header range (`start,len`, with git's conventions for empty and
+/// single-line ranges).
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Write one line of a hunk. With `color`, the escapes are the ones git writes: removed lines
+/// in red, added lines in green with the `+` colored on its own, and a reset after everything.
+fn write_line(out: &mut impl Write, prefix: char, line: &[u8], color: bool) -> Result<()> {
+    let text = line.strip_suffix(b"\n").unwrap_or(line);
+    match prefix {
+        _ if !color => {
+            write!(out, "{prefix}")?;
+            out.write_all(text)?;
+        }
+        '-' => {
+            write!(out, "{RED}-")?;
+            out.write_all(text)?;
+            write!(out, "{RESET}")?;
+        }
+        '+' => {
+            write!(out, "{GREEN}+{RESET}")?;
+            if !text.is_empty() {
+                write!(out, "{GREEN}")?;
+                out.write_all(text)?;
+                write!(out, "{RESET}")?;
+            }
+        }
+        _ => {
+            write!(out, "{prefix}")?;
+            out.write_all(text)?;
+            write!(out, "{RESET}")?;
+        }
+    }
+    out.write_all(b"\n")?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\\ No newline at end of file")?;
+        if color {
+            write!(out, "{RESET}")?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// The text git shows after a hunk header: the last line before the hunk that starts with a
+/// letter, `_` or `$`, which in most languages is the enclosing function or section.
+fn func_name<'a>(lines: &[&'a [u8]], before: usize) -> Option<&'a [u8]> {
+    let line = lines[..before].iter().rev().find(|l| {
+        l.first()
+            .is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_' || *c == b'$')
+    })?;
+    let line = &line[..line.len().min(80)];
+    let len = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    Some(&line[..len])
+}
+
+/// Mark runs of change groups (consecutive non-equal edits) that only add or remove blank
+/// lines, for `--ignore-blank-lines`. Lines of only whitespace count as blank when whitespace is
+/// being ignored anyway.
+fn blank_only_changes(
+    edits: &[Edit],
+    old: &[&[u8]],
+    new: &[&[u8]],
+    whitespace: Whitespace,
+) -> Vec<bool> {
+    let blank = |line: &[u8]| match whitespace {
+        Whitespace::Exact => line == b"\n",
+        _ => line.iter().all(|b| b.is_ascii_whitespace()),
+    };
+    let mut ignored = vec![false; edits.len()];
+    let mut i = 0;
+    while i < edits.len() {
+        if matches!(edits[i], Edit::Equal(..)) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < edits.len() && !matches!(edits[i], Edit::Equal(..)) {
+            i += 1;
+        }
+        let all_blank = edits[start..i].iter().all(|e| match *e {
+            Edit::Delete(o) => blank(old[o]),
+            Edit::Insert(n) => blank(new[n]),
+            Edit::Equal(..) => unreachable!(),
+        });
+        ignored[start..i].fill(all_blank);
+    }
+    ignored
+}
+
+/// Write the unified diff hunks between two texts.
+pub(crate) fn write_hunks(
+    out: &mut impl Write,
+    old: &[u8],
+    new: &[u8],
+    opts: &DiffOptions,
+) -> Result<()> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = diff_lines(&old_lines, &new_lines, opts.whitespace);
+    let ignored = if opts.ignore_blank_lines {
+        blank_only_changes(&edits, &old_lines, &new_lines, opts.whitespace)
+    } else {
+        vec![false; edits.len()]
+    };
+    for hunk in hunks(&edits, &ignored, opts.context) {
+        let header = format!(
+            "@@ -{} +{} @@",
+            hunk_range(hunk.old_start, hunk.old_len),
+            hunk_range(hunk.new_start, hunk.new_len)
+        );
+        if opts.color {
+            write!(out, "{CYAN}{header}{RESET}")?;
+        } else {
+            write!(out, "{header}")?;
+        }
+        if let Some(name) = func_name(&old_lines, hunk.old_start) {
+            out.write_all(b" ")?;
+            out.write_all(name)?;
+        }
+        writeln!(out)?;
+        if opts.word_diff {
+            write_word_diff_hunk(out, &hunk.edits, &old_lines, &new_lines, opts.color)?;
+            continue;
+        }
+        for edit in hunk.edits {
+            match edit {
+                // lines equal up to ignored whitespace are shown as they are now
+                Edit::Equal(_, j) => write_line(out, ' ', new_lines[j], opts.color)?,
+                Edit::Delete(i) => write_line(out, '-', old_lines[i], opts.color)?,
+                Edit::Insert(j) => write_line(out, '+', new_lines[j], opts.color)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write a hunk in `--word-diff` form: unchanged lines as they are, and each group of changed
+/// lines re-diffed word by word, with the words marked inline.
+fn write_word_diff_hunk(
+    out: &mut impl Write,
+    edits: &[Edit],
+    old_lines: &[&[u8]],
+    new_lines: &[&[u8]],
+    color: bool,
+) -> Result<()> {
+    let mut i = 0;
+    while i < edits.len() {
+        if let Edit::Equal(_, j) = edits[i] {
+            out.write_all(new_lines[j].strip_suffix(b"\n").unwrap_or(new_lines[j]))?;
+            if color {
+                write!(out, "{RESET}")?;
+            }
+            out.write_all(b"\n")?;
+            i += 1;
+            continue;
+        }
+        let (mut old, mut new) = (Vec::new(), Vec::new());
+        while i < edits.len() {
+            match edits[i] {
+                Edit::Delete(o) => old.extend_from_slice(old_lines[o]),
+                Edit::Insert(n) => new.extend_from_slice(new_lines[n]),
+                Edit::Equal(..) => break,
+            }
+            i += 1;
+        }
+        let text = word_diff(&old, &new, color);
+        out.write_all(&text)?;
+        if !text.ends_with(b"\n") {
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Diff `old` against `new` by words (runs of non-whitespace), returning the new text with the
+/// removed and added words marked by brackets, which `color` also makes red and green.
+/// Whitespace always comes from the new text.
+fn word_diff(old: &[u8], new: &[u8], color: bool) -> Vec<u8> {
+    let (removed, added) = if color {
+        (
+            [format!("{RED}[-"), format!("-]{RESET}")],
+            [format!("{GREEN}{{+"), format!("+}}{RESET}")],
+        )
+    } else {
+        (
+            ["[-".to_string(), "-]".to_string()],
+            ["{+".to_string(), "+}".to_string()],
+        )
+    };
+    if new.is_empty() {
+        // plain removals are marked as they are, including whitespace
+        let mut out = Vec::new();
+        mark_words(&mut out, &removed, old);
+        return out;
+    }
+    let words = |text: &[u8]| {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (i, b) in text.iter().enumerate() {
+            match (start, b.is_ascii_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    words.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            words.push(s..text.len());
+        }
+        words
+    };
+    let old_words = words(old);
+    let new_words = words(new);
+    let edits = diff_lines(
+        &old_words
+            .iter()
+            .map(|r| &old[r.clone()])
+            .collect::<Vec<_>>(),
+        &new_words
+            .iter()
+            .map(|r| &new[r.clone()])
+            .collect::<Vec<_>>(),
+        Whitespace::Exact,
+    );
+
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut i = 0;
+    while i < edits.len() {
+        if let Edit::Equal(_, j) = edits[i] {
+            out.extend_from_slice(&new[pos..new_words[j].end]);
+            pos = new_words[j].end;
+            i += 1;
+            continue;
+        }
+        let (mut deleted, mut inserted) = (None, None);
+        while i < edits.len() {
+            let (side, words, at) = match edits[i] {
+                Edit::Delete(o) => (&mut deleted, &old_words, o),
+                Edit::Insert(n) => (&mut inserted, &new_words, n),
+                Edit::Equal(..) => break,
+            };
+            let start = side.map_or(words[at].start, |(start, _)| start);
+            *side = Some((start, words[at].end));
+            i += 1;
+        }
+        // an insertion goes where its words are; a pure deletion right after the previous word
+        if let Some((start, _)) = inserted {
+            out.extend_from_slice(&new[pos..start]);
+        }
+        if let Some((start, end)) = deleted {
+            mark_words(&mut out, &removed, &old[start..end]);
+        }
+        if let Some((start, end)) = inserted {
+            mark_words(&mut out, &added, &new[start..end]);
+            pos = end;
+        }
+    }
+    out.extend_from_slice(&new[pos..]);
+    out
+}
+
+/// Append `text` wrapped in the `open`/`close` markers, closing and reopening them around line
+/// breaks so every marked line stands on its own.
+fn mark_words(out: &mut Vec<u8>, [open, close]: &[String; 2], text: &[u8]) {
+    for (n, line) in text.split(|b| *b == b'\n').enumerate() {
+        if n > 0 {
+            out.push(b'\n');
+        }
+        if !line.is_empty() {
+            out.extend_from_slice(open.as_bytes());
+            out.extend_from_slice(line);
+            out.extend_from_slice(close.as_bytes());
+        }
+    }
+}
+
+/// Write the header lines of a file patch, in bold with `color`.
+fn write_meta(out: &mut impl Write, header: &[u8], color: bool) -> Result<()> {
+    if !color {
+        return Ok(out.write_all(header)?);
+    }
+    for line in split_lines(header) {
+        write!(out, "{BOLD}")?;
+        out.write_all(line.strip_suffix(b"\n").unwrap_or(line))?;
+        writeln!(out, "{RESET}")?;
+    }
+    Ok(())
+}
+
+fn short_hash(hash: &str) -> &str {
+    &hash[..7]
+}
+
+/// Write the `--summary` of `changes`: the files created and deleted, with their modes, the
+/// renames, and the mode changes.
+pub(crate) fn write_summary(out: &mut impl Write, changes: &[Change]) -> Result<()> {
+    for change in changes {
+        match (&change.old, &change.new) {
+            (None, Some(new)) => writeln!(out, " create mode {} {}", new.mode, new.path)?,
+            (Some(old), None) => writeln!(out, " delete mode {} {}", old.mode, old.path)?,
+            (Some(old), Some(new)) => {
+                if old.path != new.path {
+                    writeln!(
+                        out,
+                        " rename {} ({}%)",
+                        stat_name(change),
+                        change.similarity.unwrap_or(0)
+                    )?;
+                    if old.mode != new.mode {
+                        writeln!(out, " mode change {} => {}", old.mode, new.mode)?;
+                    }
+                } else if old.mode != new.mode {
+                    writeln!(
+                        out,
+                        " mode change {} => {} {}",
+                        old.mode, new.mode, new.path
+                    )?;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Write a `diff --git` patch for every change.
+pub(crate) fn write_patch(
+    out: &mut impl Write,
+    blobs: &mut BlobCache,
+    changes: &[Change],
+    opts: &DiffOptions,
+) -> Result<()> {
+    for change in changes {
+        write_file_patch(out, blobs, change, opts)?;
+    }
+    Ok(())
+}
+
+/// One file of a `--stat`: the lines added and removed, or for binary files the new and old
+/// sizes in bytes.
+struct FileStat {
+    name: String,
+    binary: bool,
+    added: usize,
+    deleted: usize,
+}
+
+/// The name a change is listed under in `--stat`: its path, or for a rename both paths with
+/// their common leading directories and trailing part outside braces, like `dir/{a => b}/f`.
+/// Names that need quoting are quoted like git quotes them, and a rename with one is shown
+/// whole, as `"old" => "new"`.
+fn stat_name(change: &Change) -> String {
+    let (Some(old), Some(new)) = (&change.old, &change.new) else {
+        return quote_path(change.path(), false);
+    };
+    if old.path == new.path {
+        return quote_path(&old.path, false);
+    }
+    let (quoted_old, quoted_new) = (quote_path(&old.path, false), quote_path(&new.path, false));
+    if quoted_old != old.path || quoted_new != new.path {
+        return format!("{quoted_old} => {quoted_new}");
+    }
+    let (a, b) = (old.path.as_bytes(), new.path.as_bytes());
+    // the common prefix ends at a slash
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // the common suffix starts at a slash, which may be the one ending the prefix
+    let byte = |s: &[u8], i: isize| s.get(i as usize).copied().unwrap_or(0);
+    let floor = prefix.saturating_sub(1) as isize;
+    let (mut i, mut j) = (a.len() as isize, b.len() as isize);
+    let mut suffix = 0;
+    while i >= floor && j >= floor && byte(a, i) == byte(b, j) {
+        if byte(a, i) == b'/' {
+            suffix = a.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    let middle = format!(
+        "{} => {}",
+        &old.path[prefix..prefix + a_mid],
+        &new.path[prefix..prefix + b_mid]
+    );
+    if prefix + suffix == 0 {
+        return middle;
+    }
+    format!(
+        "{}{{{middle}}}{}",
+        &old.path[..prefix],
+        &old.path[a.len() - suffix..]
+    )
+}
+
+/// Write a `--stat` summary of `changes`: a line per file with the number of changed lines and a
+/// bar of `+` and `-` scaled to fit, then the totals. The layout follows git's, for a terminal
+/// `$COLUMNS` (default 80) wide unless the options give a width.
+pub(crate) fn write_stat(
+    out: &mut impl Write,
+    blobs: &mut BlobCache,
+    changes: &[Change],
+    opts: &DiffOptions,
+) -> Result<()> {
+    let mut stats = Vec::new();
+    for change in changes {
+        let (old, new, drivers) = change_content(blobs, change, opts)?;
+        let binary = drivers.is_empty() && (is_binary(&old) || is_binary(&new));
+        let same = change.old.as_ref().map(|f| &f.hash) == change.new.as_ref().map(|f| &f.hash);
+        let (added, deleted) = if same {
+            (0, 0)
+        } else if binary {
+            (new.len(), old.len())
+        } else {
+            let edits = diff_lines(&split_lines(&old), &split_lines(&new), opts.whitespace);
+            let count = |f: fn(&Edit) -> bool| edits.iter().filter(|e| f(e)).count();
+            (
+                count(|e| matches!(e, Edit::Insert(_))),
+                count(|e| matches!(e, Edit::Delete(_))),
+            )
+        };
+        let content_only = matches!((&change.old, &change.new),
+            (Some(old), Some(new)) if old.path == new.path && old.mode == new.mode);
+        if content_only && !same && !binary && added + deleted == 0 {
+            // every difference was ignored (whitespace), so the file isn't listed
+            continue;
+        }
+        stats.push(FileStat {
+            name: stat_name(change),
+            binary,
+            added,
+            deleted,
+        });
+    }
+    if stats.is_empty() {
+        return Ok(());
+    }
+
+    let decimal_width = |n: usize| n.to_string().len();
+    let (mut max_len, mut max_change, mut number_width, mut bin_width) = (0, 0, 0, 0);
+    for stat in &stats {
+        max_len = max_len.max(stat.name.chars().count());
+        if stat.binary {
+            // "Bin XXX -> YYY bytes", with the counts of other files aligned with "Bin"
+            bin_width = bin_width.max(14 + decimal_width(stat.added) + decimal_width(stat.deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(stat.added + stat.deleted);
+        }
+    }
+    number_width = number_width.max(decimal_width(max_change));
+    let width = opts
+        .stat_width
+        .or_else(|| {
+            std::env::var("COLUMNS")
+                .ok()
+                .and_then(|c| c.parse::<usize>().ok())
+                .filter(|c| *c > 0)
+        })
+        .unwrap_or(80)
+        .max(16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        // give the graph at most 3/8 of the width (but at least 6), and the name the rest
+        let graph_share = (width * 3 / 8) as isize - number_width as isize - 6;
+        if graph_width as isize > graph_share {
+            graph_width = graph_share.max(6) as usize;
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    let (add_color, del_color, reset) = if opts.color {
+        (GREEN, RED, RESET)
+    } else {
+        ("", "", "")
+    };
+    let scale = |n: usize| {
+        if n == 0 {
+            0
+        } else {
+            1 + n * (graph_width - 1) / max_change
+        }
+    };
+    let (mut insertions, mut deletions) = (0, 0);
+    for stat in &stats {
+        // a name that is too long loses its start, up to a slash if there is one
+        let mut name = stat.name.as_str();
+        let mut prefix = "";
+        let mut len = name_width;
+        if name.chars().count() > name_width {
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count().saturating_sub(len);
+            name = &name[name
+                .char_indices()
+                .nth(skip)
+                .map_or(name.len(), |(at, _)| at)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+        write!(out, " {prefix}{name}{:padding$} | ", "")?;
+        if stat.binary {
+            write!(out, "{:>number_width$}", "Bin")?;
+            if stat.added + stat.deleted > 0 {
+                write!(
+                    out,
+                    " {del_color}{}{reset} -> {add_color}{}{reset} bytes",
+                    stat.deleted, stat.added
+                )?;
+            }
+            writeln!(out)?;
+            continue;
+        }
+        insertions += stat.added;
+        deletions += stat.deleted;
+        let (mut add, mut del) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale(add + del);
+            if total < 2 && add > 0 && del > 0 {
+                total = 2;
+            }
+            if add < del {
+                add = scale(add);
+                del = total - add;
+            } else {
+                del = scale(del);
+                add = total - del;
+            }
+        }
+        write!(out, "{:>number_width$}", stat.added + stat.deleted)?;
+        if stat.added + stat.deleted > 0 {
+            write!(out, " ")?;
+        }
+        if add > 0 {
+            write!(out, "{add_color}{}{reset}", "+".repeat(add))?;
+        }
+        if del > 0 {
+            write!(out, "{del_color}{}{reset}", "-".repeat(del))?;
+        }
+        writeln!(out)?;
+    }
+
+    let plural = |n: usize, one: &str, many: &str| {
+        if n == 1 {
+            format!("{n} {one}")
+        } else {
+            format!("{n} {many}")
+        }
+    };
+    write!(
+        out,
+        " {}",
+        plural(stats.len(), "file changed", "files changed")
+    )?;
+    if insertions > 0 || deletions == 0 {
+        write!(
+            out,
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        )?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(
+            out,
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        )?;
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+/// The old and new content of `change`, each side converted with the textconv driver of its
+/// own path, and the drivers that were used.
+fn change_content(
+    blobs: &mut BlobCache,
+    change: &Change,
+    opts: &DiffOptions,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<String>)> {
+    let mut drivers = Vec::new();
+    let mut content = |file: &Option<DiffFile>| -> Result<Vec<u8>> {
+        let Some(file) = file else {
+            return Ok(Vec::new());
+        };
+        let driver = if opts.textconv {
+            blobs.textconv_driver(&file.path)?
+        } else {
+            None
+        };
+        match driver {
+            Some((driver, command)) => {
+                let text = blobs.textconv(&command, &file.hash)?;
+                drivers.push(driver);
+                Ok(text)
+            }
+            None => Ok(blobs.get(&file.hash)?.to_vec()),
+        }
+    };
+    let old = content(&change.old)?;
+    let new = content(&change.new)?;
+    drivers.dedup();
+    Ok((old, new, drivers))
+}
+
+fn write_file_patch(
+    out: &mut impl Write,
+    blobs: &mut BlobCache,
+    change: &Change,
+    opts: &DiffOptions,
+) -> Result<()> {
+    let old_path = change
+        .old
+        .as_ref()
+        .unwrap_or_else(|| change.new.as_ref().unwrap());
+    let new_path = change
+        .new
+        .as_ref()
+        .unwrap_or_else(|| change.old.as_ref().unwrap());
+    // the header is held back until we know the content diff isn't empty
+    let mut header = Vec::new();
+    writeln!(header, "diff --git a/{} b/{}", old_path.path, new_path.path)?;
+
+    let null = blobs.git_repo.hash_algo().null().to_string();
+    let old_hash = change.old.as_ref().map_or(null.as_str(), |f| &f.hash);
+    let new_hash = change.new.as_ref().map_or(null.as_str(), |f| &f.hash);
+    let mut content_only = false;
+    let mut same_mode = false;
+    match (&change.old, &change.new) {
+        (None, Some(new)) => writeln!(header, "new file mode {}", new.mode)?,
+        (Some(old), None) => writeln!(header, "deleted file mode {}", old.mode)?,
+        (Some(old), Some(new)) => {
+            if old.path != new.path {
+                writeln!(
+                    header,
+                    "similarity index {}%",
+                    change.similarity.unwrap_or(0)
+                )?;
+                writeln!(header, "rename from {}", old.path)?;
+                writeln!(header, "rename to {}", new.path)?;
+            }
+            if old.mode != new.mode {
+                writeln!(header, "old mode {}", old.mode)?;
+                writeln!(header, "new mode {}", new.mode)?;
+            }
+            same_mode = old.mode == new.mode;
+            content_only = old.path == new.path && same_mode;
+        }
+        (None, None) => unreachable!("a change has at least one side"),
+    }
+    if old_hash == new_hash {
+        return write_meta(out, &header, opts.color);
+    }
+    let (old, new, drivers) = change_content(blobs, change, opts)?;
+    let binary = drivers.is_empty() && (is_binary(&old) || is_binary(&new));
+    let (old_index, new_index) = if opts.binary && binary {
+        (old_hash, new_hash)
+    } else {
+        (short_hash(old_hash), short_hash(new_hash))
+    };
+    // the mode goes on the index line whenever it didn't change, renamed or not
+    if same_mode {
+        writeln!(header, "index {old_index}..{new_index} {}", new_path.mode)?;
+    } else {
+        writeln!(header, "index {old_index}..{new_index}")?;
+    }
+    for driver in &drivers {
+        // the patch shows converted text, which doesn't apply to the blobs
+        writeln!(header, "textconv {driver}")?;
+    }
+    let old_name = change
+        .old
+        .as_ref()
+        .map_or("/dev/null".to_string(), |f| format!("a/{}", f.path));
+    let new_name = change
+        .new
+        .as_ref()
+        .map_or("/dev/null".to_string(), |f| format!("b/{}", f.path));
+    if binary {
+        write_meta(out, &header, opts.color)?;
+        if opts.binary {
+            write_binary_patch(out, &old, &new)?;
+        } else {
+            writeln!(out, "Binary files {old_name} and {new_name} differ")?;
+        }
+        return Ok(());
+    }
+
+    let mut hunks = Vec::new();
+    write_hunks(&mut hunks, &old, &new, opts)?;
+    if hunks.is_empty() {
+        // every difference was ignored (whitespace or blank lines), so there's nothing to show
+        if !content_only {
+            write_meta(out, &header, opts.color)?;
+        }
+        return Ok(());
+    }
+    // a tab ends names with spaces, which could otherwise be taken for a trailing timestamp
+    let tab = |name: &str| if name.contains(' ') { "\t" } else { "" };
+    writeln!(header, "--- {old_name}{}", tab(&old_name))?;
+    writeln!(header, "+++ {new_name}{}", tab(&new_name))?;
+    write_meta(out, &header, opts.color)?;
+    out.write_all(&hunks)?;
+    Ok(())
+}
+
+/// What [`write_raw`] prints for each change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RawFormat {
+    /// Both modes and blobs, the status and the paths.
+    Raw,
+    /// Only the paths (`--name-only`).
+    NameOnly,
+    /// The status and the paths (`--name-status`).
+    NameStatus,
+}
+
+/// The status letter of `change` in raw output, with the similarity for renames: `A`dded,
+/// `D`eleted, `R`enamed, `T` for a type change (between a file, a symlink and a submodule) or
+/// `M`odified.
+fn raw_status(change: &Change) -> String {
+    match (&change.old, &change.new) {
+        (None, _) => "A".to_string(),
+        (_, None) => "D".to_string(),
+        (Some(old), Some(new)) if old.path != new.path => {
+            format!("R{:03}", change.similarity.unwrap_or(0))
+        }
+        (Some(old), Some(new)) if old.mode.bits() & 0o170000 != new.mode.bits() & 0o170000 => {
+            "T".to_string()
+        }
+        _ => "M".to_string(),
+    }
+}
+
+/// A path with conflicts, as raw diff output shows it: `old` is the compared side from before the
+/// merge, if it has the path, and `new_mode` the mode of the other side if it has the file. The
+/// new side never has a blob, since an unmerged path has no single staged version.
+#[derive(Debug, Clone)]
+pub(crate) struct Unmerged {
+    pub(crate) path: String,
+    pub(crate) old: Option<(Mode, String)>,
+    pub(crate) new_mode: Option<Mode>,
+}
+
+/// Write `changes` as git's plumbing diff commands do, one record per change:
+/// `:<old mode> <new mode> <old blob> <new blob> <status>` and the path (or both paths of a
+/// rename) after a tab, with a missing side written as mode `000000` and the null id.
+/// `unmerged` paths are written the same way with the status `U`, ahead of any change of the
+/// same path. With `nul`, fields after the status and records end in NUL and paths aren't
+/// quoted.
+pub(crate) fn write_raw(
+    out: &mut impl Write,
+    algo: HashAlgo,
+    changes: &[Change],
+    unmerged: &[Unmerged],
+    format: RawFormat,
+    nul: bool,
+) -> Result<()> {
+    let null = algo.null().to_string();
+    let side = |file: Option<(Mode, &str)>| match file {
+        Some((mode, hash)) => (format!("{:06o}", mode.bits()), hash.to_string()),
+        None => ("000000".to_string(), null.clone()),
+    };
+    let (sep, end) = if nul { ('\0', '\0') } else { ('\t', '\n') };
+    let quote = |path: &str| match nul {
+        true => path.to_string(),
+        false => quote_path(path, false),
+    };
+
+    // (path, status, old side, new side, the paths as written)
+    let mut records = Vec::new();
+    for u in unmerged {
+        let old = side(u.old.as_ref().map(|(mode, hash)| (*mode, hash.as_str())));
+        let new = side(u.new_mode.map(|mode| (mode, null.as_str())));
+        records.push((u.path.as_str(), "U".to_string(), old, new, quote(&u.path)));
+    }
+    for change in changes {
+        let paths = match (&change.old, &change.new) {
+            (Some(old), Some(new)) if old.path != new.path => {
+                format!("{}{sep}{}", quote(&old.path), quote(&new.path))
+            }
+            _ => quote(change.path()),
+        };
+        records.push((
+            change.path(),
+            raw_status(change),
+            side(change.old.as_ref().map(|f| (f.mode, f.hash.as_str()))),
+            side(change.new.as_ref().map(|f| (f.mode, f.hash.as_str()))),
+            paths,
+        ));
+    }
+    // stable, so an unmerged path comes before a change of it
+    records.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (path, status, (old_mode, old_hash), (new_mode, new_hash), paths) in records {
+        match format {
+            RawFormat::NameOnly => write!(out, "{}{end}", quote(path))?,
+            RawFormat::NameStatus => write!(out, "{status}{sep}{paths}{end}")?,
+            RawFormat::Raw => write!(
+                out,
+                ":{old_mode} {new_mode} {old_hash} {new_hash} {status}{sep}{paths}{end}"
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// `path` in double quotes with C-style escapes if it has special characters, the way git
+/// quotes paths in its output; otherwise `path` as it is. Spaces only make the path quoted with
+/// `quote_spaces`, as `status` does.
+pub(crate) fn quote_path(path: &str, quote_spaces: bool) -> String {
+    let special = |b: u8| {
+        !(b' '..0x7f).contains(&b) || b == b'"' || b == b'\\' || (quote_spaces && b == b' ')
+    };
+    if !path.bytes().any(special) {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{b:03o}")),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str, whitespace: Whitespace) -> Vec<Edit> {
+        let old = split_lines(old.as_bytes());
+        let new = split_lines(new.as_bytes());
+        diff_lines(&old, &new, whitespace)
+    }
+
+    #[test]
+    fn ignore_all_space_matches_reindented_lines() {
+        let old = "if x {\nf();\n}\n";
+        let new = "if x {\n    f();\n}\n";
+        assert_eq!(
+            diff(old, new, Whitespace::Exact),
+            [
+                Edit::Equal(0, 0),
+                Edit::Delete(1),
+                Edit::Insert(1),
+                Edit::Equal(2, 2)
+            ]
+        );
+        assert_eq!(
+            diff(old, new, Whitespace::IgnoreAll),
+            [Edit::Equal(0, 0), Edit::Equal(1, 1), Edit::Equal(2, 2)]
+        );
+    }
+
+    #[test]
+    fn ignore_all_space_still_sees_content_changes() {
+        let edits = diff("a\n\tf(1);\n", "a\nf(2);\n", Whitespace::IgnoreAll);
+        assert_eq!(edits, [Edit::Equal(0, 0), Edit::Delete(1), Edit::Insert(1)]);
+    }
+
+    #[test]
+    fn ignore_space_change_only_ignores_amounts_of_space() {
+        assert_eq!(
+            diff("a  b \n", "a\tb\n", Whitespace::IgnoreChange),
+            [Edit::Equal(0, 0)]
+        );
+        // but whitespace where there was none is a change
+        assert_eq!(
+            diff("ab\n", "a b\n", Whitespace::IgnoreChange),
+            [Edit::Delete(0), Edit::Insert(0)]
+        );
+        assert_eq!(
+            diff("ab\n", "a b\n", Whitespace::IgnoreAll),
+            [Edit::Equal(0, 0)]
+        );
+    }
+}
@@ -0,0 +1,65 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{bail, Context, Result};
+
+use crate::repository::GitRepository;
+
+/// The editor command for messages, found like git does: `$GIT_EDITOR`, then `core.editor`,
+/// then `$VISUAL` (unless the terminal is dumb), then `$EDITOR`, and `vi` when none is set.
+pub(crate) fn editor_command(git_repo: &GitRepository) -> Result<String> {
+    let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+    let editor = std::env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| git_repo.config_get("core", "editor").map(str::to_string))
+        .or_else(|| std::env::var("VISUAL").ok().filter(|_| !dumb))
+        .or_else(|| std::env::var("EDITOR").ok());
+    match editor {
+        Some(editor) => Ok(editor),
+        None if dumb => bail!("Terminal is dumb, but EDITOR unset"),
+        None => Ok("vi".to_string()),
+    }
+}
+
+/// The editor command for `rebase -i` todo lists: `$GIT_SEQUENCE_EDITOR`, then
+/// `sequence.editor`, then the one for messages.
+pub(crate) fn sequence_editor_command(git_repo: &GitRepository) -> Result<String> {
+    match std::env::var("GIT_SEQUENCE_EDITOR").ok().or_else(|| {
+        git_repo
+            .config_get("sequence", "editor")
+            .map(str::to_string)
+    }) {
+        Some(editor) => Ok(editor),
+        None => editor_command(git_repo),
+    }
+}
+
+/// Let the user edit the file at `path` in their editor, waiting for it to exit. The editor
+/// command runs through the shell, so it can carry arguments like `code --wait`. An editor that
+/// fails aborts whatever wanted the edit.
+pub(crate) fn launch_editor(git_repo: &GitRepository, path: &Path) -> Result<()> {
+    run_editor(&editor_command(git_repo)?, path)
+}
+
+/// Let the user edit the todo list at `path` in their sequence editor, like
+/// [`launch_editor`].
+pub(crate) fn launch_sequence_editor(git_repo: &GitRepository, path: &Path) -> Result<()> {
+    run_editor(&sequence_editor_command(git_repo)?, path)
+}
+
+fn run_editor(editor: &str, path: &Path) -> Result<()> {
+    // like git, `:` means the file is used as it is
+    if editor == ":" {
+        return Ok(());
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(editor)
+        .arg(path)
+        .status()
+        .with_context(|| format!("unable to start editor '{editor}'"))?;
+    if !status.success() {
+        bail!("There was a problem with the editor '{editor}'.");
+    }
+    Ok(())
+}
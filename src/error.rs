@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+/// Why a call of the library API failed, for callers that handle failures differently.
+///
+/// Inside the crate, errors are `anyhow` errors; a `GitError` raised there is recovered from
+/// one at the API boundary, and whatever isn't one becomes [`GitError::Other`]. The command
+/// line turns these back into `anyhow` errors.
+///
+/// ```
+/// use git_rs::{GitError, GitRepository};
+///
+/// let dir = std::env::temp_dir().join(format!("git-rs-doc-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir)?;
+/// let err = GitRepository::open(&dir).unwrap_err();
+/// assert!(matches!(err, GitError::NotARepository(_)));
+///
+/// let repo = GitRepository::init(&dir)?;
+/// let err = repo.resolve_ref("refs/heads/missing").unwrap_err();
+/// assert!(matches!(err, GitError::RefNotFound(_)));
+/// # std::fs::remove_dir_all(&dir)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum GitError {
+    /// The path holds no repository.
+    #[error("{} does not appear to be a git repository", .0.display())]
+    NotARepository(PathBuf),
+
+    /// A name that should be a full object id isn't one.
+    #[error("{0} is not a valid object id")]
+    InvalidObjectId(String),
+
+    /// No object has the id.
+    #[error("object {0} not found")]
+    ObjectNotFound(String),
+
+    /// The object exists but can't be read as what it claims to be.
+    #[error("invalid object {id}: {reason}")]
+    InvalidObject { id: String, reason: String },
+
+    /// The ref (or the ref a symbolic ref points at) doesn't exist.
+    #[error("ref {0} not found")]
+    RefNotFound(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Any other failure, with its chain of causes.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for GitError {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<GitError>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        // an I/O error is only taken apart when no context was added to it
+        if error.chain().count() > 1 {
+            return Self::Other(error);
+        }
+        match error.downcast() {
+            Ok(error) => Self::Io(error),
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn from_anyhow_recovers_git_errors_and_bare_io_errors() {
+        let err = GitError::from(anyhow::Error::new(GitError::RefNotFound("HEAD".into())));
+        assert!(matches!(err, GitError::RefNotFound(_)));
+
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let err = GitError::from(anyhow::Error::new(io));
+        assert!(matches!(err, GitError::Io(_)));
+
+        let io = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let err = GitError::from(Err::<(), _>(io).context("reading config").unwrap_err());
+        assert!(matches!(err, GitError::Other(_)));
+        assert_eq!(format!("{err:#}"), "reading config: permission denied");
+    }
+}
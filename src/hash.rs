@@ -0,0 +1,150 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+/// The hash function that names the objects of a repository, set by `extensions.objectFormat`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgo {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgo {
+    /// The algorithm called `name` in `extensions.objectFormat`.
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha1" => Ok(Self::Sha1),
+            "sha256" => Ok(Self::Sha256),
+            _ => bail!("unknown object format '{name}'"),
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// The length of a binary object id.
+    pub(crate) fn raw_len(self) -> usize {
+        match self {
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+        }
+    }
+
+    /// The length of an object id in hex.
+    pub(crate) fn hex_len(self) -> usize {
+        self.raw_len() * 2
+    }
+
+    /// Whether `s` is a full object id in hex.
+    pub(crate) fn is_hex_id(self, s: &str) -> bool {
+        s.len() == self.hex_len() && s.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    pub(crate) fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    /// The hash of `data`.
+    pub(crate) fn digest(self, data: impl AsRef<[u8]>) -> ObjectId {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// The all-zero id, which stands for a missing object.
+    pub(crate) fn null(self) -> ObjectId {
+        match self {
+            Self::Sha1 => ObjectId::Sha1([0; 20]),
+            Self::Sha256 => ObjectId::Sha256([0; 32]),
+        }
+    }
+}
+
+/// A running hash with one of the [`HashAlgo`]s.
+#[derive(Clone)]
+pub(crate) enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn update(&mut self, data: impl AsRef<[u8]>) {
+        match self {
+            Self::Sha1(hasher) => hasher.update(data),
+            Self::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finalize(self) -> ObjectId {
+        match self {
+            Self::Sha1(hasher) => ObjectId::Sha1(hasher.finalize().into()),
+            Self::Sha256(hasher) => ObjectId::Sha256(hasher.finalize().into()),
+        }
+    }
+}
+
+/// The binary name of an object, as trees and the index store it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectId {
+    /// The id stored as `bytes`, whose length says which algorithm made it.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.len() {
+            20 => Ok(Self::Sha1(bytes.try_into().expect("length checked"))),
+            32 => Ok(Self::Sha256(bytes.try_into().expect("length checked"))),
+            n => bail!("object id of {n} bytes is neither SHA-1 nor SHA-256"),
+        }
+    }
+
+    /// Parse an object id written in hex.
+    pub(crate) fn from_hex(hex: &str) -> Result<Self> {
+        let bytes = hex::decode(hex).with_context(|| format!("invalid object id {hex}"))?;
+        Self::from_bytes(&bytes).with_context(|| format!("invalid object id {hex}"))
+    }
+
+    /// The algorithm that made the id.
+    pub(crate) fn algo(&self) -> HashAlgo {
+        match self {
+            Self::Sha1(_) => HashAlgo::Sha1,
+            Self::Sha256(_) => HashAlgo::Sha256,
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha1(bytes) => bytes,
+            Self::Sha256(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+impl std::fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self))
+    }
+}
+
+impl std::fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ObjectId({self})")
+    }
+}
@@ -0,0 +1,422 @@
+//! The smart HTTP transport, git's "stateless" form of the protocol: the service's
+//! advertisement comes from `GET <url>/info/refs?service=<service>`, and each request the client
+//! makes is a `POST <url>/<service>` that the service answers in the response.
+//!
+//! A server that wants credentials answers 401; they are then found and remembered as
+//! [`crate::credential`] describes. Redirects are followed as `http.followRedirects` says: by
+//! default only for the advertisement, whose final URL the requests after it go to. Proxies come
+//! from `http.proxy`, or else from `$http_proxy`, `$https_proxy` or `$all_proxy` unless
+//! `$no_proxy` lists the host; `http.sslVerify`, `http.sslCAInfo` and `http.sslCAPath` (or
+//! `$GIT_SSL_NO_VERIFY`, `$GIT_SSL_CAINFO` and `$GIT_SSL_CAPATH`) decide which certificates
+//! are trusted.
+
+use std::{
+    cell::RefCell,
+    fs,
+    io::{self, BufRead, BufReader, Cursor, Read, Write},
+    rc::Rc,
+};
+
+use anyhow::{bail, Context, Result};
+use ureq::{
+    config::RedirectAuthHeaders,
+    http::Response,
+    tls::{parse_pem, Certificate, PemItem, RootCerts, TlsConfig},
+    Agent, Body, Proxy, RequestBuilder, ResponseExt,
+};
+
+use crate::{
+    credential::{Credential, Helpers},
+    pkt_line::read_pkt,
+    repository::GitRepository,
+    transport::Service,
+};
+
+/// What git-rs tells HTTP servers it is. Servers (like GitHub's) treat clients whose agent
+/// doesn't start with `git/` as browsers, so it does, as other git implementations' do.
+const USER_AGENT: &str = "git/2.0 (git-rs/0.1.0)";
+
+/// The most redirects followed for one request, as many as git allows.
+const MAX_REDIRECTS: u32 = 20;
+
+/// A client for the smart HTTP service of one repository.
+struct Client {
+    /// For the advertisement, and for the requests after it, which follow redirects differently.
+    initial: Agent,
+    agent: Agent,
+    /// The repository's URL, without credentials or a trailing slash. After a redirect of the
+    /// advertisement, where it was redirected to.
+    base: String,
+    credential: Credential,
+    helpers: Helpers,
+    /// Whether the credential hasn't been approved yet: saved once a request with it succeeds,
+    /// like git saves even one that came with the URL.
+    unapproved: bool,
+    service: Service,
+    v2: bool,
+}
+
+impl Client {
+    fn new(git_repo: &GitRepository, url: &str, service: Service, v2: bool) -> Result<Self> {
+        let (base, credential) = Credential::from_url(url.trim_end_matches('/'));
+        let follow = match git_repo.config_get("http", "followRedirects") {
+            None | Some("initial") => [true, false],
+            Some(_) => [git_repo.config_bool("http", "followRedirects") == Some(true); 2],
+        };
+        let proxy = proxy(git_repo, &credential)?;
+        let tls = tls_config(git_repo)?;
+        let [initial, agent] = follow.map(|follow| {
+            Agent::config_builder()
+                .http_status_as_error(false)
+                .max_redirects(if follow { MAX_REDIRECTS } else { 0 })
+                .redirect_auth_headers(RedirectAuthHeaders::SameHost)
+                .proxy(proxy.clone())
+                .tls_config(tls.clone())
+                .user_agent(USER_AGENT)
+                .build()
+                .new_agent()
+        });
+        Ok(Self {
+            initial,
+            agent,
+            base,
+            unapproved: credential.password.is_some(),
+            credential,
+            helpers: Helpers::new(git_repo),
+            service,
+            v2,
+        })
+    }
+
+    /// The repository's URL the way git shows it in messages.
+    fn display(&self) -> String {
+        format!("{}/", self.base)
+    }
+
+    /// Get the service's advertisement, minus the `# service=` line a protocol v0 server starts
+    /// it with, following redirects (if allowed) and moving the repository to where they lead.
+    fn advertisement(&mut self) -> Result<Box<dyn Read>> {
+        let tail = format!("/info/refs?service={}", self.service);
+        let url = format!("{}{tail}", self.base);
+        let response = self.request(&url, None)?;
+        let location = response.get_uri().to_string();
+        if location != url {
+            let Some(base) = location.strip_suffix(&tail) else {
+                bail!(
+                    "unable to update url base from redirection:\n  asked for: {url}\n   \
+                     redirect: {location}"
+                );
+            };
+            eprintln!("warning: redirecting to {base}/");
+            let (base, credential) = Credential::from_url(base);
+            if (&credential.protocol, &credential.host)
+                != (&self.credential.protocol, &self.credential.host)
+            {
+                self.unapproved = credential.password.is_some();
+                self.credential = credential;
+            }
+            self.base = base;
+        }
+        let expected = format!("application/x-{}-advertisement", self.service);
+        if content_type(&response) != expected {
+            bail!(
+                "'{}' is not a smart HTTP server: dumb HTTP is not supported",
+                self.display()
+            );
+        }
+        let mut body = Vec::new();
+        response
+            .into_body()
+            .into_reader()
+            .read_to_end(&mut body)
+            .with_context(|| format!("unable to access '{}'", self.display()))?;
+        let mut input = Cursor::new(body);
+        let header = format!("# service={}\n", self.service);
+        if read_pkt(&mut input).ok().flatten().as_deref() == Some(header.as_bytes()) {
+            if read_pkt(&mut input)?.is_some() {
+                bail!("protocol error: no flush-pkt after '{}'", header.trim_end());
+            }
+        } else {
+            input.set_position(0);
+        }
+        Ok(Box::new(input))
+    }
+
+    /// Send `request` to the service, returning its response.
+    fn post(&mut self, request: &[u8]) -> Result<Box<dyn Read>> {
+        let url = format!("{}/{}", self.base, self.service);
+        let response = self.request(&url, Some(request))?;
+        let expected = format!("application/x-{}-result", self.service);
+        let content_type = content_type(&response);
+        if content_type != expected {
+            bail!(
+                "invalid content-type '{content_type}' from '{}'",
+                self.display()
+            );
+        }
+        Ok(Box::new(response.into_body().into_reader()))
+    }
+
+    /// GET `url`, or POST `body` to it, asking for credentials when the server wants them. The
+    /// advertisement is the only GET, made with the agent for the first request.
+    fn request(&mut self, url: &str, body: Option<&[u8]>) -> Result<Response<Body>> {
+        loop {
+            let response = match body {
+                None => self.headers(self.initial.get(url)).call(),
+                Some(body) => self
+                    .headers(self.agent.post(url))
+                    .header(
+                        "Content-Type",
+                        format!("application/x-{}-request", self.service),
+                    )
+                    .header("Accept", format!("application/x-{}-result", self.service))
+                    .send(body),
+            }
+            .with_context(|| format!("unable to access '{}'", self.display()))?;
+            match response.status().as_u16() {
+                200 => {
+                    if self.unapproved {
+                        self.helpers.approve(&self.credential)?;
+                        self.unapproved = false;
+                    }
+                    return Ok(response);
+                }
+                401 if self.credential.password.is_none() => {
+                    self.helpers.fill(&mut self.credential)?;
+                    self.unapproved = true;
+                }
+                401 => {
+                    self.helpers.reject(&self.credential)?;
+                    bail!("Authentication failed for '{}'", self.display());
+                }
+                404 => bail!("repository '{}' not found", self.display()),
+                code => bail!(
+                    "unable to access '{}': The requested URL returned error: {code}",
+                    self.display()
+                ),
+            }
+        }
+    }
+
+    /// Add the headers every request has: the credential if there is one yet, and the protocol
+    /// version the client wants.
+    fn headers<B>(&self, mut request: RequestBuilder<B>) -> RequestBuilder<B> {
+        if let Credential {
+            username: Some(username),
+            password: Some(password),
+            ..
+        } = &self.credential
+        {
+            let basic = base64(format!("{username}:{password}").as_bytes());
+            request = request.header("Authorization", format!("Basic {basic}"));
+        }
+        if self.v2 {
+            request = request.header("Git-Protocol", "version=2");
+        }
+        request
+    }
+}
+
+/// A conversation with a smart HTTP service: what the client reads is the response to its last
+/// request, or the advertisement before the first.
+struct Rpc {
+    client: Client,
+    response: Box<dyn Read>,
+}
+
+struct Input(Rc<RefCell<Rpc>>);
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().response.read(buf)
+    }
+}
+
+/// What the client writes is collected, and sent as one request when it flushes.
+struct Output {
+    rpc: Rc<RefCell<Rpc>>,
+    request: Vec<u8>,
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.request.is_empty() {
+            return Ok(());
+        }
+        let request = std::mem::take(&mut self.request);
+        let mut rpc = self.rpc.borrow_mut();
+        rpc.response = rpc
+            .client
+            .post(&request)
+            .map_err(|e| io::Error::other(format!("{e:#}")))?;
+        Ok(())
+    }
+}
+
+/// Start a conversation with `service` of the repository at the http(s) `url`, returning its
+/// two ends: the advertisement, then the responses, to read, and the requests to write.
+pub(crate) fn connect(
+    git_repo: &GitRepository,
+    url: &str,
+    service: Service,
+    v2: bool,
+) -> Result<(Box<dyn BufRead>, Box<dyn Write>)> {
+    let mut client = Client::new(git_repo, url, service, v2)?;
+    let response = client.advertisement()?;
+    let rpc = Rc::new(RefCell::new(Rpc { client, response }));
+    let output = Output {
+        rpc: rpc.clone(),
+        request: Vec::new(),
+    };
+    Ok((Box::new(BufReader::new(Input(rpc))), Box::new(output)))
+}
+
+fn content_type(response: &Response<Body>) -> &str {
+    response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The proxy for the host of `credential`: `http.proxy`, or else the one the environment names
+/// for its protocol the way curl reads it, unless `$no_proxy` lists the host.
+fn proxy(git_repo: &GitRepository, credential: &Credential) -> Result<Option<Proxy>> {
+    let env = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    };
+    let proxy = match git_repo.config_get("http", "proxy") {
+        Some(proxy) => Some(proxy.to_string()),
+        None => match credential.protocol.as_str() {
+            // curl ignores $HTTP_PROXY, which a CGI server sets from a request's `Proxy` header
+            "https" => env(&["https_proxy", "HTTPS_PROXY"]),
+            _ => env(&["http_proxy"]),
+        }
+        .or_else(|| env(&["all_proxy", "ALL_PROXY"])),
+    };
+    let Some(proxy) = proxy.filter(|proxy| !proxy.is_empty()) else {
+        return Ok(None);
+    };
+    let host = match credential.host.rsplit_once(':') {
+        Some((host, port)) if port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => &credential.host,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if env(&["no_proxy", "NO_PROXY"]).is_some_and(|no_proxy| bypasses_proxy(&no_proxy, host)) {
+        return Ok(None);
+    }
+    let proxy = match proxy.contains("://") {
+        true => proxy,
+        false => format!("http://{proxy}"),
+    };
+    Proxy::new(&proxy)
+        .map(Some)
+        .with_context(|| format!("invalid proxy '{proxy}'"))
+}
+
+/// Whether the `$no_proxy` list `no_proxy` has `host`: `*`, the host itself, or a domain it is
+/// in (with or without a leading dot).
+fn bypasses_proxy(no_proxy: &str, host: &str) -> bool {
+    no_proxy
+        .split([',', ' '])
+        .map(|entry| entry.trim().trim_start_matches('.'))
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            entry == "*"
+                || host.eq_ignore_ascii_case(entry)
+                || host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", entry.to_ascii_lowercase()))
+        })
+}
+
+/// How to check servers' certificates: not at all if `http.sslVerify` is off, and against the
+/// certificates of `http.sslCAInfo` and the files in `http.sslCAPath` instead of the built-in
+/// roots if either is set.
+fn tls_config(git_repo: &GitRepository) -> Result<TlsConfig> {
+    let setting = |var: &str, key: &str| {
+        std::env::var(var)
+            .ok()
+            .or_else(|| git_repo.config_get("http", key).map(str::to_string))
+            .filter(|value| !value.is_empty())
+    };
+    let verify = std::env::var_os("GIT_SSL_NO_VERIFY").is_none()
+        && git_repo.config_bool("http", "sslVerify").unwrap_or(true);
+    let mut files = Vec::new();
+    if let Some(file) = setting("GIT_SSL_CAINFO", "sslCAInfo") {
+        files.push(file.into());
+    }
+    if let Some(dir) = setting("GIT_SSL_CAPATH", "sslCAPath") {
+        for entry in fs::read_dir(&dir).with_context(|| format!("unable to read '{dir}'"))? {
+            let path = entry?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    let mut builder = TlsConfig::builder().disable_verification(!verify);
+    if !files.is_empty() {
+        let mut certs = Vec::<Certificate<'static>>::new();
+        for file in &files {
+            let pem = fs::read(file)
+                .with_context(|| format!("unable to read CA file '{}'", file.display()))?;
+            for item in parse_pem(&pem) {
+                if let PemItem::Certificate(cert) =
+                    item.with_context(|| format!("invalid CA file '{}'", file.display()))?
+                {
+                    certs.push(cert);
+                }
+            }
+        }
+        builder = builder.root_certs(RootCerts::new_with_certs(&certs));
+    }
+    Ok(builder.build())
+}
+
+/// Standard base64 with padding, for the `Authorization` header.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b"x-access-token:abc"), "eC1hY2Nlc3MtdG9rZW46YWJj");
+        assert_eq!(base64(b"a"), "YQ==");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn matches_no_proxy_lists() {
+        assert!(bypasses_proxy("*", "example.com"));
+        assert!(bypasses_proxy("localhost, example.com", "example.com"));
+        assert!(bypasses_proxy(".example.com", "git.example.com"));
+        assert!(bypasses_proxy("example.com", "git.Example.com"));
+        assert!(!bypasses_proxy("example.com", "badexample.com"));
+        assert!(!bypasses_proxy("", "example.com"));
+    }
+}
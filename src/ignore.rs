@@ -0,0 +1,248 @@
+/// A single gitignore-style pattern.
+#[derive(Debug, Clone)]
+pub(crate) struct Pattern {
+    pattern: String,
+    /// `!pattern`: a match re-includes the path.
+    negated: bool,
+    /// `pattern/`: only matches directories.
+    dir_only: bool,
+    /// The pattern contains a `/` (other than a trailing one), so it matches the full path
+    /// rather than just the last component.
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parse one line of a pattern file, returning `None` for blank lines and comments.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let mut line = line.trim_end_matches(['\n', '\r']);
+        // trailing spaces are ignored unless escaped
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, mut line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let dir_only = line.ends_with('/');
+        line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: line.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// Whether this pattern matches `path` (relative to the directory the pattern applies to).
+    pub(crate) fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch(self.pattern.as_bytes(), path.as_bytes())
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(self.pattern.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// An ordered list of patterns where the last matching pattern decides.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PatternList {
+    patterns: Vec<Pattern>,
+    /// Match regardless of case (`core.ignorecase`).
+    ignore_case: bool,
+}
+
+impl PatternList {
+    pub(crate) fn parse(text: &str) -> Self {
+        Self {
+            patterns: text.lines().filter_map(Pattern::parse).collect(),
+            ignore_case: false,
+        }
+    }
+
+    /// These patterns, matching paths regardless of case.
+    pub(crate) fn ignoring_case(mut self) -> Self {
+        for pattern in &mut self.patterns {
+            pattern.pattern.make_ascii_lowercase();
+        }
+        self.ignore_case = true;
+        self
+    }
+
+    /// `Some(true)` if the last pattern matching `path` is a positive one, `Some(false)` if it
+    /// is negated, and `None` if no pattern matches.
+    pub(crate) fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let folded;
+        let path = if self.ignore_case {
+            folded = path.to_ascii_lowercase();
+            &folded
+        } else {
+            path
+        };
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.matches(path, is_dir))
+            .map(|p| !p.negated)
+    }
+
+    /// Like `matched`, but a path whose own patterns are undecided inherits the decision of its
+    /// closest parent directory.
+    pub(crate) fn matched_with_parents(&self, path: &str, is_dir: bool) -> Option<bool> {
+        if let Some(m) = self.matched(path, is_dir) {
+            return Some(m);
+        }
+        let mut dir = path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            if let Some(m) = self.matched(parent, true) {
+                return Some(m);
+            }
+            dir = parent;
+        }
+        None
+    }
+}
+
+/// Match `text` against a glob `pattern` with git's wildmatch rules for paths: `*` and `?` do
+/// not match `/`, `**` between slashes matches any number of directories, and `[...]` is a
+/// character class.
+pub(crate) fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_from(pattern, 0, text)
+}
+
+fn match_from(pattern: &[u8], mut p: usize, text: &[u8]) -> bool {
+    let mut t = 0;
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let double = pattern.get(p + 1) == Some(&b'*');
+                let at_segment_start = p == 0 || pattern[p - 1] == b'/';
+                if double && at_segment_start {
+                    let after = p + 2;
+                    if after == pattern.len() {
+                        // trailing `**` matches everything below
+                        return true;
+                    }
+                    if pattern[after] == b'/' {
+                        // `**/` matches zero or more leading directories
+                        if match_from(pattern, after + 1, &text[t..]) {
+                            return true;
+                        }
+                        return text[t..]
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, c)| **c == b'/')
+                            .any(|(i, _)| match_from(pattern, after + 1, &text[t + i + 1..]));
+                    }
+                }
+                // a single `*` (or a `**` not on its own segment) stays within one component
+                let mut rest = p + 1;
+                while pattern.get(rest) == Some(&b'*') {
+                    rest += 1;
+                }
+                let mut i = t;
+                loop {
+                    if match_from(pattern, rest, &text[i..]) {
+                        return true;
+                    }
+                    if i == text.len() || text[i] == b'/' {
+                        return false;
+                    }
+                    i += 1;
+                }
+            }
+            b'?' => {
+                if t == text.len() || text[t] == b'/' {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                if t == text.len() || text[t] == b'/' {
+                    return false;
+                }
+                match match_class(pattern, p, text[t]) {
+                    Some((true, next)) => {
+                        p = next;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // an unterminated class matches a literal `[`
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t == text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t == text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Match `c` against the character class starting at `pattern[start]` (a `[`), returning
+/// whether it matched and the index just past the class.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> Option<(bool, usize)> {
+    let mut p = start + 1;
+    let negated = matches!(pattern.get(p), Some(b'!') | Some(b'^'));
+    if negated {
+        p += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let cur = *pattern.get(p)?;
+        if cur == b']' && !first {
+            break;
+        }
+        first = false;
+        let lo = if cur == b'\\' {
+            p += 1;
+            *pattern.get(p)?
+        } else {
+            cur
+        };
+        if pattern.get(p + 1) == Some(&b'-') && pattern.get(p + 2).is_some_and(|c| *c != b']') {
+            let hi = pattern[p + 2];
+            if lo <= c && c <= hi {
+                matched = true;
+            }
+            p += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            p += 1;
+        }
+    }
+    Some((matched != negated, p + 1))
+}
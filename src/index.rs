@@ -0,0 +1,923 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    cache_tree::CacheTree,
+    convert::Converter,
+    hash::{HashAlgo, ObjectId},
+    lockfile::LockFile,
+    objects::{write_object, Kind, Mode, TreeEntry},
+    repository::{repo_file, GitRepository},
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// Bits of the 16-bit entry flags.
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// Bits of the extended flags (index version 3 and later).
+const FLAG_SKIP_WORKTREE: u16 = 0x4000;
+
+/// One entry of the index: the cached stat data and blob of a tracked path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexEntry {
+    pub(crate) ctime: (u32, u32),
+    pub(crate) mtime: (u32, u32),
+    pub(crate) dev: u32,
+    pub(crate) ino: u32,
+    pub(crate) mode: u32,
+    pub(crate) uid: u32,
+    pub(crate) gid: u32,
+    pub(crate) size: u32,
+    pub(crate) hash: ObjectId,
+    pub(crate) flags: u16,
+    pub(crate) extended_flags: u16,
+    pub(crate) path: String,
+}
+
+impl IndexEntry {
+    /// Create an entry for `path` (relative to the work tree) from the file's metadata.
+    pub(crate) fn from_metadata(
+        path: &str,
+        meta: &fs::Metadata,
+        mode: u32,
+        hash: ObjectId,
+    ) -> Self {
+        // the on-disk format only has room for the low 32 bits of each field
+        Self {
+            ctime: (meta.ctime() as u32, meta.ctime_nsec() as u32),
+            mtime: (meta.mtime() as u32, meta.mtime_nsec() as u32),
+            dev: meta.dev() as u32,
+            ino: meta.ino() as u32,
+            mode,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            size: meta.size() as u32,
+            hash,
+            flags: 0,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Create an entry that has no stat data, e.g. for a path that isn't in the work tree.
+    pub(crate) fn without_stat(path: &str, mode: u32, hash: ObjectId) -> Self {
+        Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash,
+            flags: 0,
+            extended_flags: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Create an entry without stat data for a blob, symlink or gitlink of a tree.
+    pub(crate) fn from_tree_entry(entry: &TreeEntry) -> Result<Self> {
+        let mode = entry.mode.bits();
+        let hash = ObjectId::from_hex(&entry.hash)
+            .with_context(|| format!("bad hash {} for {}", entry.hash, entry.name))?;
+        Ok(Self::without_stat(&entry.name, mode, hash))
+    }
+
+    /// The tree entry for this entry's blob (or symlink or gitlink), named by its full path.
+    pub(crate) fn tree_entry(&self) -> TreeEntry {
+        TreeEntry {
+            mode: Mode::from_bits(self.mode),
+            name: self.path.clone(),
+            hash: self.hash_hex(),
+        }
+    }
+
+    /// Update the cached stat data from `meta`, keeping the blob, mode and flags.
+    pub(crate) fn refresh_stat(&mut self, meta: &fs::Metadata) {
+        *self = Self {
+            flags: self.flags,
+            extended_flags: self.extended_flags,
+            ..Self::from_metadata(&self.path, meta, self.mode, self.hash)
+        };
+    }
+
+    /// The merge stage (0 for a normal entry).
+    pub(crate) fn stage(&self) -> u8 {
+        ((self.flags & FLAG_STAGE_MASK) >> 12) as u8
+    }
+
+    pub(crate) fn set_stage(&mut self, stage: u8) {
+        self.flags = (self.flags & !FLAG_STAGE_MASK) | (u16::from(stage) << 12 & FLAG_STAGE_MASK);
+    }
+
+    pub(crate) fn skip_worktree(&self) -> bool {
+        self.extended_flags & FLAG_SKIP_WORKTREE != 0
+    }
+
+    pub(crate) fn set_skip_worktree(&mut self, skip: bool) {
+        if skip {
+            self.extended_flags |= FLAG_SKIP_WORKTREE;
+        } else {
+            self.extended_flags &= !FLAG_SKIP_WORKTREE;
+        }
+    }
+
+    pub(crate) fn hash_hex(&self) -> String {
+        hex::encode(self.hash)
+    }
+}
+
+/// What identifies the version of an entry: path, stage, mode and blob.
+type EntryKey = (String, u8, u32, ObjectId);
+
+fn entry_key(entry: &IndexEntry) -> EntryKey {
+    (entry.path.clone(), entry.stage(), entry.mode, entry.hash)
+}
+
+/// The staging area, stored in `.git/index`.
+#[derive(Debug, Clone)]
+pub(crate) struct Index {
+    pub(crate) version: u32,
+    pub(crate) entries: Vec<IndexEntry>,
+    /// The `TREE` extension, as of `baseline`.
+    cache_tree: Option<CacheTree>,
+    /// Optional extensions we don't understand, as `(signature, data)`. They describe the
+    /// entries of `baseline`, so they are dropped once the entries change.
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    /// The entries when the index was read (or its cache tree last updated), sorted, to tell
+    /// which paths changed since.
+    baseline: Vec<EntryKey>,
+    /// Paths that differ only in case name the same entry (`core.ignorecase`).
+    ignore_case: bool,
+    /// The hash function of the repository, which names the blobs and checksums the file.
+    algo: HashAlgo,
+    /// The trailing checksum of the file the index was read from, to tell whether another
+    /// process has rewritten it since.
+    checksum: Option<ObjectId>,
+    /// The mtime of the file the index was read from. Entries modified no earlier than that may
+    /// have changed after their stat data was taken without the stat data showing it.
+    timestamp: Option<(u32, u32)>,
+}
+
+/// The lock on the index taken by [`Index::lock`], and the work tree it describes.
+pub(crate) struct IndexLock(LockFile, PathBuf);
+
+impl IndexLock {
+    /// Write `index` in place of the locked one, releasing the lock.
+    pub(crate) fn commit(mut self, index: &Index) -> Result<()> {
+        self.0
+            .write_all(&index.serialize_smudged(&self.1))
+            .context("write index.lock")?;
+        self.0.commit()
+    }
+}
+
+impl Index {
+    /// An empty index of a repository whose objects are named with `algo`.
+    pub(crate) fn new(algo: HashAlgo) -> Self {
+        Self {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+            extensions: Vec::new(),
+            baseline: Vec::new(),
+            ignore_case: false,
+            algo,
+            checksum: None,
+            timestamp: None,
+        }
+    }
+
+    /// Read the index of `git_repo`, or an empty one if there is no index yet.
+    pub(crate) fn read(git_repo: &GitRepository) -> Result<Self> {
+        let path = repo_file(git_repo, &["index"], false)?;
+        if !path.exists() {
+            return Ok(Self::new(git_repo.hash_algo()));
+        }
+        let mut file = fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
+        let meta = file.metadata()?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .with_context(|| format!("read {}", path.display()))?;
+        let mut index = Self::parse(&buf, git_repo.hash_algo())
+            .with_context(|| format!("parse {}", path.display()))?;
+        index.ignore_case = git_repo.ignorecase();
+        index.timestamp = Some((meta.mtime() as u32, meta.mtime_nsec() as u32));
+        // a racily clean entry could have been rewritten in the same tick after its stat data
+        // was taken, so it is smudged: its content is compared instead of its stat data
+        for entry in &mut index.entries {
+            if index.timestamp.is_some_and(|t| entry.mtime >= t) {
+                entry.size = 0;
+            }
+        }
+        Ok(index)
+    }
+
+    /// Parse an index whose hashes `algo` made.
+    pub(crate) fn parse(buf: &[u8], algo: HashAlgo) -> Result<Self> {
+        let index = Self::parse_unverified(buf, algo)?;
+        if !checksum_valid(buf, algo) {
+            bail!("index file checksum mismatch");
+        }
+        Ok(index)
+    }
+
+    /// Parse an index without checking its trailing checksum.
+    pub(crate) fn parse_unverified(buf: &[u8], algo: HashAlgo) -> Result<Self> {
+        let hash_len = algo.raw_len();
+        if buf.len() < 12 + hash_len || &buf[0..4] != SIGNATURE {
+            bail!("index file has no DIRC header");
+        }
+        let (content, checksum) = buf.split_at(buf.len() - hash_len);
+
+        let be32 = |at: usize| -> Result<u32> {
+            let bytes = content.get(at..at + 4).context("index file is truncated")?;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let be16 = |at: usize| -> Result<u16> {
+            let bytes = content.get(at..at + 2).context("index file is truncated")?;
+            Ok(u16::from_be_bytes(bytes.try_into().unwrap()))
+        };
+
+        let version = be32(4)?;
+        if !(2..=3).contains(&version) {
+            bail!("unsupported index version {version}");
+        }
+        let count = be32(8)?;
+
+        // a corrupt count mustn't reserve more entries than the file could hold
+        let mut entries = Vec::with_capacity((count as usize).min(content.len() / 62));
+        let mut at = 12;
+        for _ in 0..count {
+            let mut entry = IndexEntry {
+                ctime: (be32(at)?, be32(at + 4)?),
+                mtime: (be32(at + 8)?, be32(at + 12)?),
+                dev: be32(at + 16)?,
+                ino: be32(at + 20)?,
+                mode: be32(at + 24)?,
+                uid: be32(at + 28)?,
+                gid: be32(at + 32)?,
+                size: be32(at + 36)?,
+                hash: ObjectId::from_bytes(
+                    content
+                        .get(at + 40..at + 40 + hash_len)
+                        .context("index file is truncated")?,
+                )?,
+                flags: be16(at + 40 + hash_len)?,
+                extended_flags: 0,
+                path: String::new(),
+            };
+            let mut header_len = 42 + hash_len;
+            if entry.flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    bail!("extended index entry in a version {version} index");
+                }
+                entry.extended_flags = be16(at + header_len)?;
+                header_len += 2;
+            }
+            let name = content
+                .get(at + header_len..)
+                .context("index file is truncated")?;
+            let name_len = name
+                .iter()
+                .position(|b| *b == 0)
+                .context("index entry path is not terminated")?;
+            entry.path = String::from_utf8(name[..name_len].to_vec())
+                .context("index entry path isn't utf-8")?;
+            entries.push(entry);
+            at += entry_len(header_len, name_len);
+        }
+        // the padding of the last entry can't run into the checksum
+        if at > content.len() {
+            bail!("index file is truncated");
+        }
+
+        // extensions run up to the checksum: a signature, a 32-bit size and the data
+        let mut cache_tree = None;
+        let mut extensions = Vec::new();
+        while at < content.len() {
+            let signature: [u8; 4] = content
+                .get(at..at + 4)
+                .context("index extension is truncated")?
+                .try_into()
+                .unwrap();
+            let size = be32(at + 4)? as usize;
+            let data = content
+                .get(at + 8..at + 8 + size)
+                .context("index extension is truncated")?;
+            match &signature {
+                b"TREE" => cache_tree = Some(CacheTree::parse(data, algo)?),
+                // extensions starting with an uppercase letter are optional; others change how
+                // the index must be read
+                [b'A'..=b'Z', ..] => extensions.push((signature, data.to_vec())),
+                _ => bail!(
+                    "index uses {} extension, which we do not understand",
+                    String::from_utf8_lossy(&signature)
+                ),
+            }
+            at += 8 + size;
+        }
+
+        let mut baseline = entries.iter().map(entry_key).collect::<Vec<_>>();
+        baseline.sort();
+        Ok(Self {
+            version,
+            entries,
+            cache_tree,
+            extensions,
+            baseline,
+            ignore_case: false,
+            algo,
+            checksum: Some(ObjectId::from_bytes(checksum)?),
+            timestamp: None,
+        })
+    }
+
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        // extended flags need at least version 3
+        let version = if self.entries.iter().any(|e| e.extended_flags != 0) {
+            self.version.max(3)
+        } else {
+            self.version
+        };
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(SIGNATURE);
+        buf.extend_from_slice(&version.to_be_bytes());
+        buf.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for e in &self.entries {
+            let start = buf.len();
+            for field in [
+                e.ctime.0, e.ctime.1, e.mtime.0, e.mtime.1, e.dev, e.ino, e.mode, e.uid, e.gid,
+                e.size,
+            ] {
+                buf.extend_from_slice(&field.to_be_bytes());
+            }
+            buf.extend_from_slice(e.hash.as_bytes());
+
+            let mut flags = e.flags & !(FLAG_NAME_MASK | FLAG_EXTENDED);
+            flags |= e.path.len().min(FLAG_NAME_MASK as usize) as u16;
+            let mut header_len = 42 + e.hash.as_bytes().len();
+            if e.extended_flags != 0 {
+                flags |= FLAG_EXTENDED;
+                header_len += 2;
+            }
+            buf.extend_from_slice(&flags.to_be_bytes());
+            if e.extended_flags != 0 {
+                buf.extend_from_slice(&e.extended_flags.to_be_bytes());
+            }
+            buf.extend_from_slice(e.path.as_bytes());
+            buf.resize(start + entry_len(header_len, e.path.len()), 0);
+        }
+
+        let changed = self.changed_paths();
+        let mut extensions = Vec::new();
+        if let Some(tree) = self.current_cache_tree_for(&changed) {
+            extensions.push((*b"TREE", tree.serialize()));
+        }
+        if changed.is_empty() {
+            extensions.extend(self.extensions.iter().cloned());
+        }
+        for (signature, data) in extensions {
+            buf.extend_from_slice(&signature);
+            buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            buf.extend_from_slice(&data);
+        }
+
+        let checksum = self.algo.digest(&buf);
+        buf.extend_from_slice(checksum.as_bytes());
+        buf
+    }
+
+    /// The index as it is written to the work tree `work_tree`: entries that were racily clean
+    /// in the index as read, but whose file no longer has the content their stat data vouches
+    /// for, are smudged (their size zeroed), as git does, so that they aren't trusted once the
+    /// new index is older than them.
+    fn serialize_smudged(&self, work_tree: &Path) -> Vec<u8> {
+        let Some(timestamp) = self.timestamp else {
+            return self.serialize();
+        };
+        let racily_clean = |entry: &IndexEntry| {
+            let path = work_tree.join(&entry.path);
+            entry.mtime >= timestamp
+                && entry.mode != 0o160000
+                && fs::symlink_metadata(&path).is_ok_and(|meta| stat_matches(entry, &meta))
+                && hash_file(&path, self.algo).is_ok_and(|hash| hash != entry.hash)
+        };
+        if !self.entries.iter().any(racily_clean) {
+            return self.serialize();
+        }
+        let mut index = self.clone();
+        for entry in &mut index.entries {
+            if racily_clean(entry) {
+                entry.size = 0;
+            }
+        }
+        index.serialize()
+    }
+
+    /// Paths whose entries were added, removed or changed since `baseline`. Stat data doesn't
+    /// count.
+    fn changed_paths(&self) -> BTreeSet<String> {
+        // usually sorted already, which makes this cheap
+        let mut current = self.entries.iter().collect::<Vec<_>>();
+        current.sort_by(|a, b| (a.path.as_bytes(), a.stage()).cmp(&(b.path.as_bytes(), b.stage())));
+        let mut changed = BTreeSet::new();
+        let (mut i, mut j) = (0, 0);
+        loop {
+            let order = match (current.get(i), self.baseline.get(j)) {
+                (Some(e), Some(k)) => (e.path.as_bytes(), e.stage()).cmp(&(k.0.as_bytes(), k.1)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return changed,
+            };
+            match order {
+                Ordering::Less => {
+                    changed.insert(current[i].path.clone());
+                    i += 1;
+                }
+                Ordering::Greater => {
+                    changed.insert(self.baseline[j].0.clone());
+                    j += 1;
+                }
+                Ordering::Equal => {
+                    let (entry, key) = (current[i], &self.baseline[j]);
+                    if entry.mode != key.2 || entry.hash != key.3 {
+                        changed.insert(entry.path.clone());
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+    }
+
+    fn current_cache_tree_for(&self, changed: &BTreeSet<String>) -> Option<CacheTree> {
+        let mut tree = self.cache_tree.clone()?;
+        for path in changed {
+            tree.invalidate(path);
+        }
+        Some(tree)
+    }
+
+    /// The cache tree, with the directories of entries that changed since it was recorded
+    /// marked invalid.
+    pub(crate) fn cache_tree(&self) -> Option<CacheTree> {
+        self.current_cache_tree_for(&self.changed_paths())
+    }
+
+    /// Record `tree` as the cache tree for the current entries.
+    pub(crate) fn set_cache_tree(&mut self, tree: CacheTree) {
+        // unknown extensions can't be carried over to a different set of entries
+        if !self.changed_paths().is_empty() {
+            self.extensions.clear();
+        }
+        self.baseline = self.entries.iter().map(entry_key).collect();
+        self.baseline.sort();
+        self.cache_tree = Some(tree);
+    }
+
+    /// Lock the index of `git_repo` and read it, for commands that change it: it stays locked
+    /// until the new index is committed or the lock is dropped, so that another process can't
+    /// change it in between and have its change lost.
+    pub(crate) fn lock(git_repo: &GitRepository) -> Result<(Self, IndexLock)> {
+        let lock = LockFile::acquire(repo_file(git_repo, &["index"], false)?)?;
+        let work_tree = git_repo.work_tree().to_path_buf();
+        Ok((Self::read(git_repo)?, IndexLock(lock, work_tree)))
+    }
+
+    /// Write the index only if no other process is writing it, for optional updates such as
+    /// refreshing stat data; returns whether it was written. Never waits for the lock. The index
+    /// was read without the lock, so it isn't written either if another process has changed the
+    /// file since, which would lose that change.
+    pub(crate) fn try_write(&self, git_repo: &GitRepository) -> Result<bool> {
+        let path = repo_file(git_repo, &["index"], false)?;
+        let Some(mut lock) = LockFile::try_acquire(&path)? else {
+            return Ok(false);
+        };
+        if on_disk_checksum(&path, self.algo)? != self.checksum {
+            return Ok(false);
+        }
+        lock.write_all(&self.serialize_smudged(git_repo.work_tree()))
+            .with_context(|| format!("write {}.lock", path.display()))?;
+        lock.commit()?;
+        Ok(true)
+    }
+
+    /// Sort entries by path and stage, the order the index format requires.
+    pub(crate) fn sort(&mut self) {
+        self.entries.sort_by(|a, b| {
+            a.path
+                .as_bytes()
+                .cmp(b.path.as_bytes())
+                .then(a.stage().cmp(&b.stage()))
+        });
+    }
+
+    /// Stage the current work tree version of every tracked file, as `commit -a` does: modified
+    /// files get new blobs and deleted ones are removed. Untracked files are left alone, and
+    /// files that were only touched just get fresh stat data.
+    pub(crate) fn stage_tracked(&mut self, git_repo: &GitRepository) -> Result<()> {
+        if self.entries.iter().any(|e| e.stage() != 0) {
+            bail!("cannot do a partial commit during a merge.");
+        }
+        let mut converter = Converter::new(git_repo)?;
+        let mut deleted = Vec::new();
+        for entry in self.entries.iter_mut().filter(|e| !e.skip_worktree()) {
+            // submodules are committed by their recorded commit
+            if entry.mode == 0o160000 {
+                continue;
+            }
+            match worktree_state(git_repo, entry)? {
+                WorktreeState::Unchanged(meta) => entry.refresh_stat(&meta),
+                WorktreeState::Modified(mode, meta) => {
+                    let data = converter.worktree_blob(&entry.path)?;
+                    let hash = write_object(git_repo, Kind::Blob, &data)?;
+                    entry.hash = ObjectId::from_hex(&hash)?;
+                    entry.mode = mode;
+                    entry.refresh_stat(&meta);
+                }
+                WorktreeState::Deleted => deleted.push(entry.path.clone()),
+            }
+        }
+        self.entries.retain(|e| !deleted.contains(&e.path));
+        Ok(())
+    }
+
+    /// Put `entry` in the index, replacing every entry (at any stage) for its path.
+    pub(crate) fn add(&mut self, mut entry: IndexEntry) {
+        // a path already in the index keeps the case it was added with
+        if let Some(existing) = self
+            .entries
+            .iter()
+            .find(|e| self.same_path(&e.path, &entry.path))
+        {
+            entry.path = existing.path.clone();
+        }
+        self.remove(&entry.path);
+        let at = self
+            .entries
+            .partition_point(|e| e.path.as_bytes() < entry.path.as_bytes());
+        self.entries.insert(at, entry);
+    }
+
+    /// Drop every entry for `path`.
+    pub(crate) fn remove(&mut self, path: &str) {
+        let ignore_case = self.ignore_case;
+        self.entries
+            .retain(|e| !same_path(ignore_case, &e.path, path));
+    }
+
+    /// Record a conflict at `path`: replace its entries with the versions given for stages 1
+    /// (base), 2 (ours) and 3 (theirs), as `(mode, hash)`.
+    pub(crate) fn add_conflict(&mut self, path: &str, stages: [Option<(u32, ObjectId)>; 3]) {
+        self.remove(path);
+        for (stage, version) in (1..).zip(stages) {
+            if let Some((mode, hash)) = version {
+                let mut entry = IndexEntry::without_stat(path, mode, hash);
+                entry.set_stage(stage);
+                self.entries.push(entry);
+            }
+        }
+        self.sort();
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.get_stage(path, 0)
+    }
+
+    pub(crate) fn get_stage(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|e| self.same_path(&e.path, path) && e.stage() == stage)
+    }
+
+    fn same_path(&self, a: &str, b: &str) -> bool {
+        same_path(self.ignore_case, a, b)
+    }
+}
+
+/// Whether `a` and `b` name the same path, comparing case-insensitively if `ignore_case`.
+fn same_path(ignore_case: bool, a: &str, b: &str) -> bool {
+    if ignore_case {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// Whether the trailing `algo` checksum of the index file `buf` matches its content.
+pub(crate) fn checksum_valid(buf: &[u8], algo: HashAlgo) -> bool {
+    buf.len() >= algo.raw_len() && {
+        let (content, checksum) = buf.split_at(buf.len() - algo.raw_len());
+        algo.digest(content).as_bytes() == checksum
+    }
+}
+
+/// The trailing checksum of the index file at `path`, or `None` if there is none.
+fn on_disk_checksum(path: &Path, algo: HashAlgo) -> Result<Option<ObjectId>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("open {}", path.display())),
+    };
+    let len = file.metadata()?.len();
+    if len < algo.raw_len() as u64 {
+        return Ok(None);
+    }
+    let mut checksum = vec![0; algo.raw_len()];
+    file.seek(SeekFrom::Start(len - checksum.len() as u64))?;
+    file.read_exact(&mut checksum)
+        .with_context(|| format!("read {}", path.display()))?;
+    Ok(Some(ObjectId::from_bytes(&checksum)?))
+}
+
+/// On-disk size of an entry: the header and path, NUL padded to a multiple of 8 bytes.
+fn entry_len(header_len: usize, name_len: usize) -> usize {
+    (header_len + name_len + 8) / 8 * 8
+}
+
+/// Whether `meta` still matches the stat data cached in `entry`, i.e. the file is very likely
+/// unchanged since the entry was written. A smudged entry (size zero, but not the empty blob)
+/// never matches.
+pub(crate) fn stat_matches(entry: &IndexEntry, meta: &fs::Metadata) -> bool {
+    let smudged = entry.size == 0 && entry.hash != entry.hash.algo().digest("blob 0\0");
+    !smudged
+        && entry.mtime == (meta.mtime() as u32, meta.mtime_nsec() as u32)
+        && entry.ctime == (meta.ctime() as u32, meta.ctime_nsec() as u32)
+        && entry.size == meta.size() as u32
+        && entry.ino == meta.ino() as u32
+}
+
+/// How a tracked work tree file compares to its index entry.
+#[derive(Debug)]
+pub(crate) enum WorktreeState {
+    /// Same content and mode; the stat data may still be stale.
+    Unchanged(fs::Metadata),
+    /// Different content or mode: the file's mode.
+    Modified(u32, fs::Metadata),
+    Deleted,
+}
+
+/// The index mode for a work tree file with metadata `meta`, whose index entry (if it has one)
+/// has mode `old`. Without `core.filemode` the executable bit on disk isn't trusted: a regular
+/// file keeps the mode of its entry, or is not executable if it is new.
+pub(crate) fn mode_from_metadata(
+    git_repo: &GitRepository,
+    meta: &fs::Metadata,
+    old: Option<u32>,
+) -> u32 {
+    if meta.is_symlink() {
+        0o120000
+    } else if !git_repo.filemode() {
+        old.filter(|mode| matches!(mode, 0o100644 | 0o100755))
+            .unwrap_or(0o100644)
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// Compare the work tree file of `entry` with it. Files whose stat data matches the entry are
+/// assumed unchanged; others are hashed, without writing a blob.
+pub(crate) fn worktree_state(
+    git_repo: &GitRepository,
+    entry: &IndexEntry,
+) -> Result<WorktreeState> {
+    let path = git_repo.work_tree().join(&entry.path);
+    let meta = match fs::symlink_metadata(&path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(WorktreeState::Deleted),
+        Err(e) => return Err(e).with_context(|| format!("stat {}", path.display())),
+    };
+    if meta.is_dir() {
+        return Ok(WorktreeState::Deleted);
+    }
+    let mode = mode_from_metadata(git_repo, &meta, Some(entry.mode));
+    if stat_matches(entry, &meta) && mode == entry.mode {
+        return Ok(WorktreeState::Unchanged(meta));
+    }
+    let hash = hash_file(&path, git_repo.hash_algo())?;
+    if hash == entry.hash && mode == entry.mode {
+        Ok(WorktreeState::Unchanged(meta))
+    } else {
+        Ok(WorktreeState::Modified(mode, meta))
+    }
+}
+
+/// The blob content of the work tree file at `path`: the file's bytes, or the target of a
+/// symlink.
+pub(crate) fn read_worktree_file(path: &Path) -> Result<Vec<u8>> {
+    let meta = fs::symlink_metadata(path).with_context(|| format!("stat {}", path.display()))?;
+    if meta.is_symlink() {
+        Ok(fs::read_link(path)?.into_os_string().into_encoded_bytes())
+    } else {
+        fs::read(path).with_context(|| format!("read {}", path.display()))
+    }
+}
+
+/// Hash the work tree file at `path` as an `algo` blob without writing it.
+pub(crate) fn hash_file(path: &Path, algo: HashAlgo) -> Result<ObjectId> {
+    let data = read_worktree_file(path)?;
+    let mut hasher = algo.hasher();
+    hasher.update(format!("blob {}\0", data.len()));
+    hasher.update(&data);
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// The index git writes for a few files after `write-tree` (which adds a `TREE` extension)
+    /// and `status` with the untracked cache on (an `UNTR` extension).
+    fn git_index(dir: &TempDir) -> Vec<u8> {
+        dir.git(&["init", "--quiet", "."], b"");
+        for path in ["a", "dir/b", "dir/sub/c", "other/d"] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "content\n").unwrap();
+        }
+        dir.git(&["add", "."], b"");
+        dir.git(&["write-tree"], b"");
+        dir.git(&["-c", "core.untrackedCache=true", "status"], b"");
+        fs::read(dir.path().join(".git/index")).unwrap()
+    }
+
+    #[test]
+    fn round_trips_an_index_git_wrote() {
+        let buf = git_index(&TempDir::new());
+        let index = Index::parse(&buf, HashAlgo::Sha1).unwrap();
+        assert_eq!(index.entries.len(), 4);
+        assert!(index.cache_tree().unwrap().valid.is_some());
+        let signatures = index.extensions.iter().map(|(s, _)| s).collect::<Vec<_>>();
+        assert_eq!(signatures, [b"UNTR"]);
+        assert_eq!(index.serialize(), buf);
+    }
+
+    #[test]
+    fn changed_entries_invalidate_their_directories_and_drop_unknown_extensions() {
+        let buf = git_index(&TempDir::new());
+        let mut index = Index::parse(&buf, HashAlgo::Sha1).unwrap();
+        let mut entry = index.get("dir/sub/c").unwrap().clone();
+        entry.hash = HashAlgo::Sha1.digest("other content");
+        index.add(entry);
+
+        let index = Index::parse(&index.serialize(), HashAlgo::Sha1).unwrap();
+        let tree = index.cache_tree().unwrap();
+        let dir = tree.child("dir").unwrap();
+        assert_eq!(tree.valid, None);
+        assert_eq!(dir.valid, None);
+        assert_eq!(dir.child("sub").unwrap().valid, None);
+        assert!(tree.child("other").unwrap().valid.is_some());
+        assert!(index.extensions.is_empty());
+    }
+
+    #[test]
+    fn conflict_stages_round_trip() {
+        let hash = |text: &str| HashAlgo::Sha1.digest(text);
+        let mut index = Index::new(HashAlgo::Sha1);
+        index.add(IndexEntry::without_stat("a", 0o100644, hash("a")));
+        index.add(IndexEntry::without_stat("z", 0o100644, hash("z")));
+        index.add(IndexEntry::without_stat("m", 0o100644, hash("m")));
+        index.add_conflict(
+            "m",
+            [
+                Some((0o100644, hash("base"))),
+                Some((0o100755, hash("ours"))),
+                Some((0o100644, hash("theirs"))),
+            ],
+        );
+
+        let read = Index::parse(&index.serialize(), HashAlgo::Sha1).unwrap();
+        let entries = read
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.stage(), e.mode, e.hash))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            [
+                ("a", 0, 0o100644, hash("a")),
+                ("m", 1, 0o100644, hash("base")),
+                ("m", 2, 0o100755, hash("ours")),
+                ("m", 3, 0o100644, hash("theirs")),
+                ("z", 0, 0o100644, hash("z")),
+            ]
+        );
+        assert_eq!(read.get("m"), None);
+        assert_eq!(read.get_stage("m", 2).unwrap().hash, hash("ours"));
+
+        // resolving the conflict replaces the stages with one entry
+        let mut resolved = read;
+        resolved.add(IndexEntry::without_stat("m", 0o100644, hash("merged")));
+        let read = Index::parse(&resolved.serialize(), HashAlgo::Sha1).unwrap();
+        assert_eq!(read.entries.len(), 3);
+        assert_eq!(read.get("m").unwrap().hash, hash("merged"));
+    }
+
+    #[test]
+    fn rejects_truncated_and_corrupt_indexes() {
+        let buf = git_index(&TempDir::new());
+        let content = &buf[..buf.len() - 20];
+        // cut anywhere, with a checksum-sized trailer so only the content is short
+        for len in 12..content.len() {
+            let mut cut = content[..len].to_vec();
+            cut.extend_from_slice(&[0; 20]);
+            let _ = Index::parse_unverified(&cut, HashAlgo::Sha1);
+        }
+        let mut cut = content[..12 + 40].to_vec();
+        cut.extend_from_slice(&[0; 20]);
+        let error = Index::parse_unverified(&cut, HashAlgo::Sha1).unwrap_err();
+        assert!(error.to_string().contains("truncated"), "{error}");
+
+        let mut huge = buf.clone();
+        huge[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(Index::parse_unverified(&huge, HashAlgo::Sha1).is_err());
+    }
+
+    #[test]
+    fn refuses_unknown_required_extensions() {
+        let buf = git_index(&TempDir::new());
+        let mut content = buf[..buf.len() - 20].to_vec();
+        content.extend_from_slice(b"abcd\0\0\0\0");
+        let checksum = HashAlgo::Sha1.digest(&content);
+        content.extend_from_slice(checksum.as_bytes());
+        let error = Index::parse(&content, HashAlgo::Sha1).unwrap_err();
+        assert!(error.to_string().contains("abcd"), "{error}");
+    }
+
+    #[test]
+    fn try_write_leaves_an_index_another_process_changed() {
+        let dir = TempDir::new();
+        git_index(&dir);
+        let git_repo = crate::repository::repo_open(dir.path()).unwrap();
+        let index = Index::read(&git_repo).unwrap();
+        assert!(index.try_write(&git_repo).unwrap());
+
+        let index = Index::read(&git_repo).unwrap();
+        fs::write(dir.path().join("new"), "new\n").unwrap();
+        dir.git(&["add", "new"], b"");
+        assert!(!index.try_write(&git_repo).unwrap());
+        assert!(Index::read(&git_repo).unwrap().get("new").is_some());
+    }
+
+    #[test]
+    fn racily_clean_entries_are_compared_by_content() {
+        let dir = TempDir::new();
+        dir.git(&["init", "--quiet", "."], b"");
+        let git_repo = crate::repository::repo_open(dir.path()).unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "old\n").unwrap();
+        let old = hash_file(&path, HashAlgo::Sha1).unwrap();
+        // stat data taken after a same-size rewrite, as if both happened in one tick
+        fs::write(&path, "new\n").unwrap();
+        let meta = fs::symlink_metadata(&path).unwrap();
+        let mut index = Index::new(HashAlgo::Sha1);
+        index.add(IndexEntry::from_metadata("file", &meta, 0o100644, old));
+        let index_path = dir.path().join(".git/index");
+        fs::write(&index_path, index.serialize()).unwrap();
+        let set_index_mtime = |time| {
+            fs::File::options()
+                .write(true)
+                .open(&index_path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        let state = |index: &Index| worktree_state(&git_repo, &index.entries[0]).unwrap();
+
+        // an index written in a later tick trusts the stat data
+        set_index_mtime(meta.modified().unwrap() + std::time::Duration::from_secs(1));
+        let index = Index::read(&git_repo).unwrap();
+        assert!(matches!(state(&index), WorktreeState::Unchanged(_)));
+
+        // one written in the same tick doesn't
+        set_index_mtime(meta.modified().unwrap());
+        let index = Index::read(&git_repo).unwrap();
+        assert!(matches!(state(&index), WorktreeState::Modified(..)));
+
+        // and an entry whose stat data was refreshed is smudged when the index is written
+        let (mut index, lock) = Index::lock(&git_repo).unwrap();
+        index.entries[0].refresh_stat(&meta);
+        lock.commit(&index).unwrap();
+        let written = Index::parse(&fs::read(&index_path).unwrap(), HashAlgo::Sha1).unwrap();
+        assert_eq!(written.entries[0].size, 0);
+        assert!(matches!(state(&written), WorktreeState::Modified(..)));
+    }
+}
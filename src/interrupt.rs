@@ -0,0 +1,113 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::ExitStatus;
+
+/// The exit status after SIGINT or SIGTERM, as shells report a process killed by SIGINT.
+pub(crate) const INTERRUPTED_STATUS: i32 = 130;
+
+/// How long an interrupted command gets to notice, through [`check`], and unwind on its own
+/// before its temporary files are removed for it and the process exits.
+const GRACE: Duration = Duration::from_millis(500);
+
+/// Set once SIGINT or SIGTERM arrives.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The temporary files and directories that exist right now, to remove when interrupted.
+static PENDING: Mutex<Vec<(usize, PathBuf)>> = Mutex::new(Vec::new());
+
+/// Handle SIGINT and SIGTERM by asking the running command to stop: it fails at its next
+/// [`check`], dropping its [`TempPath`]s on the way out. A command that doesn't get there in
+/// time (say it is blocked reading) has its temporary files removed for it, and the process
+/// exits with [`INTERRUPTED_STATUS`] either way.
+pub(crate) fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        thread::sleep(GRACE);
+        remove_pending();
+        std::process::exit(INTERRUPTED_STATUS);
+    })?;
+    Ok(())
+}
+
+/// Fail with [`INTERRUPTED_STATUS`] if the process was interrupted. Long loops call this for
+/// each item so that an interrupt stops them promptly.
+pub(crate) fn check() -> Result<()> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        return Err(ExitStatus(INTERRUPTED_STATUS).into());
+    }
+    Ok(())
+}
+
+fn remove_pending() {
+    let pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    for (_, path) in pending.iter() {
+        remove(path);
+    }
+}
+
+fn remove(path: &Path) {
+    if fs::remove_file(path).is_err() {
+        let _ = fs::remove_dir_all(path);
+    }
+}
+
+/// A temporary file or directory, removed when dropped (on unwinding too) unless it has been
+/// [`persist`](Self::persist)ed, and removed by the interrupt handler if the process is
+/// interrupted while it exists.
+pub(crate) struct TempPath {
+    path: PathBuf,
+    id: usize,
+    persisted: bool,
+}
+
+impl TempPath {
+    /// Take charge of `path`, which is about to be created.
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let path = path.into();
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        PENDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((id, path.clone()));
+        Self {
+            path,
+            id,
+            persisted: false,
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Rename the file to `to`, where it stays.
+    pub(crate) fn persist(mut self, to: impl AsRef<Path>) -> io::Result<()> {
+        fs::rename(&self.path, to)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        if !self.persisted {
+            remove(&self.path);
+        }
+        PENDING
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|(id, _)| *id != self.id);
+    }
+}
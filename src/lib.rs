@@ -0,0 +1,86 @@
+//! A reimplementation of git, working on the same repositories.
+//!
+//! Besides the `git-rs` command line, the crate can be embedded: [`GitRepository`] opens a
+//! repository and reads and writes its objects, refs and commits, returning values instead of
+//! printing them, and failing with a [`GitError`] that tells what went wrong.
+//!
+//! ```
+//! use git_rs::{GitRepository, Kind};
+//!
+//! # use git_rs::ConfigScope;
+//! # let dir = std::env::temp_dir().join(format!("git-rs-doc-lib-{}", std::process::id()));
+//! # {
+//! #     // a repository whose detached HEAD is a commit of the empty tree
+//! #     let mut repo = GitRepository::init(&dir)?;
+//! #     repo.config_set(ConfigScope::Local, "user", "name", Some("Doc Test"))?;
+//! #     repo.config_set(ConfigScope::Local, "user", "email", Some("doc@example.com"))?;
+//! #     let tree = repo.write_object(Kind::Tree, b"")?;
+//! #     let commit = repo.write_commit(&tree, &[], "first\n")?;
+//! #     std::fs::write(dir.join(".git/HEAD"), format!("{commit}\n"))?;
+//! # }
+//! let repo = GitRepository::open(&dir)?;
+//! let (_, head) = repo.log_iter("HEAD")?.next().expect("HEAD has a commit")?;
+//! let (kind, tree) = repo.read_object(&head.tree)?;
+//! assert_eq!(kind, Kind::Tree);
+//! println!("HEAD's tree {} is {} bytes", head.tree, tree.len());
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+mod apply;
+mod attr;
+mod binary_patch;
+mod bitmap;
+mod cache_tree;
+#[doc(hidden)]
+pub mod cli;
+mod color;
+mod commands;
+mod convert;
+mod credential;
+mod date;
+mod decorate;
+mod diff;
+mod editor;
+mod error;
+mod hash;
+mod http;
+mod ignore;
+mod index;
+mod interrupt;
+mod lockfile;
+mod mailmap;
+mod merge;
+mod message;
+mod objects;
+mod pack;
+mod pager;
+mod pkt_line;
+mod refs;
+mod repository;
+mod revwalk;
+mod signature;
+#[cfg(test)]
+mod test_util;
+mod trace;
+mod trailer;
+mod transport;
+mod worktree;
+
+pub use error::GitError;
+pub use hash::HashAlgo;
+pub use objects::{Commit, Kind};
+pub use repository::{ConfigScope, GitRepository};
+
+/// An error that only sets the exit status, for commands whose status is their answer (like
+/// `diff --exit-code` finding differences): `main` exits with it without printing anything.
+#[derive(Debug)]
+pub(crate) struct ExitStatus(pub(crate) i32);
+
+impl std::fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "exit status {}", self.0)
+    }
+}
+
+impl std::error::Error for ExitStatus {}
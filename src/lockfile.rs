@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::interrupt::TempPath;
+
+/// How long taking a lock keeps retrying while another process holds it, git's default for
+/// ref locks (`core.filesRefLockTimeout`).
+const LOCK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A file being replaced the way git replaces the files it shares with other processes: through
+/// `<path>.lock`, which is created exclusively, so only one process at a time can be rewriting
+/// `path`. The new content is written to the lock, and [`commit`](Self::commit) renames it over
+/// `path` in one step. A lock dropped without being committed is removed, leaving `path` as it
+/// was.
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock: TempPath,
+    /// Open until the lock is committed.
+    file: Option<fs::File>,
+}
+
+impl LockFile {
+    /// Lock `path`, retrying for a moment while another process holds the lock, as git does for
+    /// refs. Fails with git's message if the lock stays taken.
+    pub(crate) fn acquire(path: impl AsRef<Path>) -> Result<Self> {
+        let started = Instant::now();
+        let mut wait = Duration::from_millis(1);
+        loop {
+            if let Some(lock) = Self::try_acquire(&path)? {
+                return Ok(lock);
+            }
+            if started.elapsed() >= LOCK_TIMEOUT {
+                let lock_path = lock_path(path.as_ref());
+                anyhow::bail!(
+                    "Unable to create '{}': File exists.\n\n\
+                     Another git process seems to be running in this repository, e.g.\n\
+                     an editor opened by 'git commit'. Please make sure all processes\n\
+                     are terminated then try again. If it still fails, a git process\n\
+                     may have crashed in this repository earlier:\n\
+                     remove the file manually to continue.",
+                    lock_path.display()
+                );
+            }
+            thread::sleep(wait);
+            wait = (wait * 2).min(Duration::from_millis(20));
+        }
+    }
+
+    /// Lock `path` if no other process holds the lock, without waiting; `None` if one does.
+    pub(crate) fn try_acquire(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let lock_path = lock_path(path);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => Ok(Some(Self {
+                path: path.to_path_buf(),
+                lock: TempPath::new(lock_path),
+                file: Some(file),
+            })),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Unable to create '{}'", lock_path.display())),
+        }
+    }
+
+    /// Replace the locked file with what was written to the lock, releasing it.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        drop(self.file.take());
+        self.lock
+            .persist(&self.path)
+            .with_context(|| format!("replace {}", self.path.display()))
+    }
+}
+
+impl Write for LockFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.as_mut().expect("the lock is open").write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().expect("the lock is open").flush()
+    }
+}
+
+/// The lock file of `path`: `path` with `.lock` after its name.
+fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// Replace the file at `path` with `contents` under its lock.
+pub(crate) fn write_locked(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write_all(contents)
+        .with_context(|| format!("write {}", lock_path(path).display()))?;
+    lock.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn commit_replaces_the_file_and_drop_leaves_it() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut lock = LockFile::acquire(&path).unwrap();
+        lock.write_all(b"abandoned\n").unwrap();
+        assert!(dir.path().join("file.lock").exists());
+        drop(lock);
+        assert!(!dir.path().join("file.lock").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+
+        write_locked(&path, b"new\n").unwrap();
+        assert!(!dir.path().join("file.lock").exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn second_writer_fails_while_the_first_holds_the_lock() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut first = LockFile::acquire(&path).unwrap();
+        first.write_all(b"first\n").unwrap();
+        let second =
+            thread::scope(|s| s.spawn(|| write_locked(&path, b"second\n")).join().unwrap());
+        let err = format!("{:#}", second.unwrap_err());
+        assert!(
+            err.starts_with(&format!(
+                "Unable to create '{}': File exists.",
+                dir.path().join("file.lock").display()
+            )),
+            "{err}"
+        );
+        assert!(
+            err.contains("remove the file manually to continue"),
+            "{err}"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+
+        first.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\n");
+        assert!(!dir.path().join("file.lock").exists());
+    }
+
+    #[test]
+    fn acquire_waits_for_a_lock_released_in_time() {
+        let dir = TempDir::new();
+        let path = dir.path().join("file");
+        let first = LockFile::acquire(&path).unwrap();
+        thread::scope(|s| {
+            let second = s.spawn(|| write_locked(&path, b"second\n"));
+            thread::sleep(Duration::from_millis(20));
+            drop(first);
+            second.join().unwrap().unwrap();
+        });
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+    }
+}
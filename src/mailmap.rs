@@ -0,0 +1,148 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    objects::{object_read, read_commit, tree_lookup},
+    refs::ref_resolve,
+    repository::GitRepository,
+};
+
+/// The replacement for an identity; `None` parts are left as they are.
+#[derive(Debug, Default, Clone)]
+struct Mapping {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+/// The mappings for one commit email: a default, and more specific ones for particular names.
+#[derive(Debug, Default)]
+struct Entry {
+    mapping: Mapping,
+    /// Keyed by the lowercased commit name.
+    by_name: HashMap<String, Mapping>,
+}
+
+/// Canonical names and emails from `.mailmap` files, which map the identities recorded in
+/// commits to the ones that should be shown.
+#[derive(Debug, Default)]
+pub(crate) struct Mailmap {
+    /// Keyed by the lowercased commit email.
+    entries: HashMap<String, Entry>,
+}
+
+/// Split `Name <email>` off the front of `text`, returning the name (if any), the email and the
+/// rest of the line.
+fn name_and_email(text: &str) -> Option<(Option<String>, String, &str)> {
+    let (name, rest) = text.split_once('<')?;
+    let (email, rest) = rest.split_once('>')?;
+    let name = name.trim();
+    Some((
+        (!name.is_empty()).then(|| name.to_string()),
+        email.trim().to_string(),
+        rest,
+    ))
+}
+
+impl Mailmap {
+    /// Add the mappings in `text`. Each line has one of the forms
+    ///
+    /// ```text
+    /// Proper Name <commit@email>
+    /// <proper@email> <commit@email>
+    /// Proper Name <proper@email> <commit@email>
+    /// Proper Name <proper@email> Commit Name <commit@email>
+    /// ```
+    ///
+    /// Lines starting with `#` are comments. Later lines override earlier ones.
+    pub(crate) fn parse(&mut self, text: &str) {
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            let Some((proper_name, proper_email, rest)) = name_and_email(line) else {
+                continue;
+            };
+            let (commit_name, commit_email, mapping) = match name_and_email(rest) {
+                Some((commit_name, commit_email, _)) => (
+                    commit_name,
+                    commit_email,
+                    Mapping {
+                        name: proper_name,
+                        email: Some(proper_email),
+                    },
+                ),
+                // a single email is both the one to match and the one to show
+                None => (
+                    None,
+                    proper_email,
+                    Mapping {
+                        name: proper_name,
+                        email: None,
+                    },
+                ),
+            };
+            let entry = self.entries.entry(commit_email.to_lowercase()).or_default();
+            match commit_name {
+                Some(commit_name) => {
+                    entry.by_name.insert(commit_name.to_lowercase(), mapping);
+                }
+                None => {
+                    if mapping.name.is_some() {
+                        entry.mapping.name = mapping.name;
+                    }
+                    if mapping.email.is_some() {
+                        entry.mapping.email = mapping.email;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Load the mailmap of `git_repo`: `.mailmap` at the top of the work tree (or in the tree of
+    /// `HEAD` for bare repositories), then the file named by `mailmap.file`. Later files override
+    /// mappings from earlier ones.
+    pub(crate) fn load(git_repo: &GitRepository) -> Result<Self> {
+        let mut mailmap = Self::default();
+        if !git_repo.is_bare() {
+            let path = git_repo.work_tree().join(".mailmap");
+            if path.is_file() {
+                mailmap.parse(&fs::read_to_string(&path)?);
+            }
+        } else if let Some(head) = ref_resolve(git_repo, "HEAD")? {
+            let tree = read_commit(git_repo, &head)?.tree;
+            if let Some(entry) = tree_lookup(git_repo, &tree, ".mailmap")? {
+                let blob = object_read(git_repo, &entry.hash)?.serialize();
+                mailmap.parse(&String::from_utf8_lossy(&blob));
+            }
+        }
+        if let Some(file) = git_repo.config_get("mailmap", "file") {
+            let path = match file.strip_prefix("~/") {
+                Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+                None => PathBuf::from(file),
+            };
+            let text = fs::read_to_string(&path)
+                .with_context(|| format!("read mailmap.file {}", path.display()))?;
+            mailmap.parse(&text);
+        }
+        Ok(mailmap)
+    }
+
+    /// The canonical `(name, email)` for an identity recorded in a commit. Emails and names are
+    /// matched case-insensitively.
+    pub(crate) fn map_identity(&self, name: &str, email: &str) -> (String, String) {
+        let mapping = self.entries.get(&email.to_lowercase()).map(|entry| {
+            entry
+                .by_name
+                .get(&name.to_lowercase())
+                .unwrap_or(&entry.mapping)
+        });
+        let Some(mapping) = mapping else {
+            return (name.to_string(), email.to_string());
+        };
+        (
+            mapping.name.clone().unwrap_or_else(|| name.to_string()),
+            mapping.email.clone().unwrap_or_else(|| email.to_string()),
+        )
+    }
+}
@@ -0,0 +1,188 @@
+use crate::diff::{diff_lines, split_lines, Edit, Whitespace};
+
+/// The result of a three-way merge of texts.
+pub(crate) struct MergedText {
+    /// The merged text, with conflict markers around regions both sides changed differently.
+    pub(crate) text: Vec<u8>,
+    pub(crate) conflicts: usize,
+}
+
+/// A changed region: base lines `base.0..base.1` replaced by side lines `side.0..side.1`.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    base: (usize, usize),
+    side: (usize, usize),
+}
+
+fn changes(base: &[&[u8]], side: &[&[u8]]) -> Vec<Change> {
+    let edits = diff_lines(base, side, Whitespace::Exact);
+    let mut changes: Vec<Change> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_change = false;
+    for edit in edits {
+        match edit {
+            Edit::Equal(..) => {
+                i += 1;
+                j += 1;
+                in_change = false;
+                continue;
+            }
+            Edit::Delete(_) => i += 1,
+            Edit::Insert(_) => j += 1,
+        }
+        match changes.last_mut() {
+            Some(change) if in_change => {
+                change.base.1 = i;
+                change.side.1 = j;
+            }
+            _ => {
+                let (bi, sj) = match edit {
+                    Edit::Delete(_) => (i - 1, j),
+                    _ => (i, j - 1),
+                };
+                changes.push(Change {
+                    base: (bi, i),
+                    side: (sj, j),
+                });
+                in_change = true;
+            }
+        }
+    }
+    changes
+}
+
+/// The lines of `side` that replace base lines `lo..hi`, given the side's changes in that range
+/// (lines outside the changes are unchanged, so they map one to one).
+fn side_range(
+    changes: &[Change],
+    lo: usize,
+    hi: usize,
+    fallback: (usize, usize),
+) -> (usize, usize) {
+    match (changes.first(), changes.last()) {
+        (Some(first), Some(last)) => (
+            first.side.0 - (first.base.0 - lo),
+            last.side.1 + (hi - last.base.1),
+        ),
+        _ => fallback,
+    }
+}
+
+/// Merge the changes from `base` to `ours` and from `base` to `theirs` line by line.
+///
+/// Regions changed by only one side take that side's version; regions changed by both (or
+/// changes that touch) take the common version if both made the same change, and are otherwise
+/// a conflict, marked like git's default `merge` conflict style. Lines common to the start or
+/// end of both versions are moved out of the conflict.
+pub(crate) fn merge_text(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    ours_label: &str,
+    theirs_label: &str,
+) -> MergedText {
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+    let ours_changes = changes(&base_lines, &ours_lines);
+    let theirs_changes = changes(&base_lines, &theirs_lines);
+
+    let mut text = Vec::new();
+    let mut conflicts = 0;
+    let (mut o, mut t) = (0, 0);
+    // next base line not yet emitted, and its offset into each side
+    let mut next = 0;
+    let (mut ours_delta, mut theirs_delta) = (0isize, 0isize);
+    while o < ours_changes.len() || t < theirs_changes.len() {
+        let start = match (ours_changes.get(o), theirs_changes.get(t)) {
+            (Some(a), Some(b)) => a.base.0.min(b.base.0),
+            (Some(a), None) => a.base.0,
+            (None, Some(b)) => b.base.0,
+            (None, None) => unreachable!(),
+        };
+        // grow the region while a change from either side overlaps or touches it
+        let (o_first, t_first) = (o, t);
+        let mut end = start;
+        loop {
+            if let Some(c) = ours_changes.get(o).filter(|c| c.base.0 <= end) {
+                end = end.max(c.base.1);
+                o += 1;
+            } else if let Some(c) = theirs_changes.get(t).filter(|c| c.base.0 <= end) {
+                end = end.max(c.base.1);
+                t += 1;
+            } else {
+                break;
+            }
+        }
+
+        for line in &base_lines[next..start] {
+            text.extend_from_slice(line);
+        }
+        let shift = |delta: isize, at: usize| (at as isize + delta) as usize;
+        let ours_range = side_range(
+            &ours_changes[o_first..o],
+            start,
+            end,
+            (shift(ours_delta, start), shift(ours_delta, end)),
+        );
+        let theirs_range = side_range(
+            &theirs_changes[t_first..t],
+            start,
+            end,
+            (shift(theirs_delta, start), shift(theirs_delta, end)),
+        );
+        let ours_part = &ours_lines[ours_range.0..ours_range.1];
+        let theirs_part = &theirs_lines[theirs_range.0..theirs_range.1];
+
+        if o == o_first || ours_part == theirs_part {
+            theirs_part.iter().for_each(|l| text.extend_from_slice(l));
+        } else if t == t_first {
+            ours_part.iter().for_each(|l| text.extend_from_slice(l));
+        } else {
+            let prefix = ours_part
+                .iter()
+                .zip(theirs_part)
+                .take_while(|(a, b)| a == b)
+                .count();
+            let suffix = ours_part[prefix..]
+                .iter()
+                .rev()
+                .zip(theirs_part[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            ours_part[..prefix]
+                .iter()
+                .for_each(|l| text.extend_from_slice(l));
+            conflicts += 1;
+            let mut section = |marker: &str, lines: &[&[u8]]| {
+                text.extend_from_slice(marker.as_bytes());
+                for line in lines {
+                    text.extend_from_slice(line);
+                }
+                if !text.ends_with(b"\n") {
+                    text.push(b'\n');
+                }
+            };
+            section(
+                &format!("<<<<<<< {ours_label}\n"),
+                &ours_part[prefix..ours_part.len() - suffix],
+            );
+            section(
+                "=======\n",
+                &theirs_part[prefix..theirs_part.len() - suffix],
+            );
+            text.extend_from_slice(format!(">>>>>>> {theirs_label}\n").as_bytes());
+            ours_part[ours_part.len() - suffix..]
+                .iter()
+                .for_each(|l| text.extend_from_slice(l));
+        }
+
+        ours_delta = ours_range.1 as isize - end as isize;
+        theirs_delta = theirs_range.1 as isize - end as isize;
+        next = end;
+    }
+    for line in &base_lines[next..] {
+        text.extend_from_slice(line);
+    }
+    MergedText { text, conflicts }
+}
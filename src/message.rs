@@ -0,0 +1,40 @@
+/// Clean up `text` the way git cleans up commit messages: strip trailing whitespace from every
+/// line, collapse runs of blank lines into one, drop blank lines at the start and end, and end
+/// the last line with a newline. With `strip_comments`, lines starting with `#` are dropped
+/// first. Text with nothing left is returned empty.
+pub(crate) fn stripspace(text: &str, strip_comments: bool) -> String {
+    let mut out = String::new();
+    let mut blank = false;
+    for line in text.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !out.is_empty() {
+            out.push('\n');
+        }
+        blank = false;
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Turn every line of `text` into a `#` comment, as `stripspace --comment-lines` does: `# `
+/// before the line, or just `#` before an empty line or one starting with a tab.
+pub(crate) fn comment_lines(text: &str) -> String {
+    let mut out = String::new();
+    for line in text.lines() {
+        match line.is_empty() || line.starts_with('\t') {
+            true => out.push('#'),
+            false => out.push_str("# "),
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
@@ -1,30 +1,36 @@
 use std::{
+    collections::{BTreeSet, HashSet},
     ffi::CStr,
     fmt::Display,
     fs,
     io::{BufRead, BufReader, Read, Write},
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use anyhow::{bail, Context, Result};
 use clap::ValueEnum;
-use flate2::{
-    read::{GzDecoder, ZlibDecoder},
-    write::ZlibEncoder,
-    Compression,
-};
-use sha1::{Digest, Sha1};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 
 use crate::{
-    commands::hash_object::HashWriter,
-    repository::{repo_file, GitRepository},
+    commands::{commit_tree::kvlm_parse, hash_object::HashWriter},
+    hash::{HashAlgo, ObjectId},
+    interrupt::{self, TempPath},
+    pack::{packed_contains, packed_with_prefix, read_packed},
+    refs::ref_resolve,
+    repository::{repo_file, repo_path, GitRepository},
+    signature::Signature,
+    trace::{trace, TRACE},
+    trailer::parse_trailers,
 };
 
-#[derive(Debug)]
-pub(crate) enum Kind {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
     Blob,
     Tree,
     Commit,
+    Tag,
 }
 
 impl std::fmt::Display for Kind {
@@ -33,6 +39,21 @@ impl std::fmt::Display for Kind {
             Kind::Blob => write!(f, "blob"),
             Kind::Tree => write!(f, "tree"),
             Kind::Commit => write!(f, "commit"),
+            Kind::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+impl std::str::FromStr for Kind {
+    type Err = anyhow::Error;
+
+    fn from_str(kind: &str) -> Result<Self> {
+        match kind {
+            "blob" => Ok(Kind::Blob),
+            "tree" => Ok(Kind::Tree),
+            "commit" => Ok(Kind::Commit),
+            "tag" => Ok(Kind::Tag),
+            _ => bail!("unknown object type '{kind}'"),
         }
     }
 }
@@ -56,13 +77,22 @@ impl Object<()> {
         })
     }
 
-    pub fn read(object_hash: &str) -> Result<Object<impl BufRead>> {
-        let f = std::fs::File::open(format!(
-            ".git/objects/{}/{}",
-            &object_hash[0..2],
-            &object_hash[2..]
-        ))
-        .context("read in .git/objects")?;
+    pub fn read(git_repo: &GitRepository, object_hash: &str) -> Result<Object<Box<dyn BufRead>>> {
+        let objects = repo_path(git_repo, &["objects"])?;
+        let f = match std::fs::File::open(objects.join(&object_hash[0..2]).join(&object_hash[2..]))
+        {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let (kind, data) = read_packed(&objects, git_repo.hash_algo(), object_hash)?
+                    .with_context(|| format!("object {object_hash} not found"))?;
+                return Ok(Object {
+                    kind,
+                    expected_size: data.len() as u64,
+                    reader: Box::new(std::io::Cursor::new(data)),
+                });
+            }
+            Err(e) => return Err(e).context("read in .git/objects"),
+        };
         let decoder = ZlibDecoder::new(f);
         let mut reader = BufReader::new(decoder);
         let mut buf = Vec::new();
@@ -81,6 +111,7 @@ impl Object<()> {
             "blob" => Kind::Blob,
             "tree" => Kind::Tree,
             "commit" => Kind::Commit,
+            "tag" => Kind::Tag,
             _ => anyhow::bail!("we do not yet know how to print a '{kind}'"),
         };
 
@@ -91,39 +122,56 @@ impl Object<()> {
         Ok(Object {
             kind,
             expected_size: size,
-            reader,
+            reader: Box::new(reader),
         })
     }
 }
 
 impl<R: Read> Object<R> {
-    pub(crate) fn write(mut self, writer: impl Write) -> Result<[u8; 20]> {
+    pub(crate) fn write(mut self, writer: impl Write, algo: HashAlgo) -> Result<ObjectId> {
         let writer = ZlibEncoder::new(writer, Compression::default());
         let mut writer = HashWriter {
             writer,
-            hasher: Sha1::new(),
+            hasher: algo.hasher(),
         };
         write!(writer, "{} {}\0", self.kind, self.expected_size)?;
         std::io::copy(&mut self.reader, &mut writer)?;
         let _ = writer.writer.finish()?;
-        let hash = writer.hasher.finalize();
-        Ok(hash.into())
+        Ok(writer.hasher.finalize())
     }
 
-    /// write the tree object to the objects directory
-    pub(crate) fn write_to_objects(self) -> Result<[u8; 20]> {
-        let tmp = "temporary";
+    /// Stream the object into the object store of `git_repo`, returning its id.
+    ///
+    /// The object is first streamed into a uniquely named temporary file under `.git/objects`
+    /// and then installed like [`write_object`] does, so concurrent writers never observe a
+    /// partial object, and unless `core.checkCollisions` is turned off, an existing copy is
+    /// checked against it.
+    pub(crate) fn write_to_objects(self, git_repo: &GitRepository) -> Result<ObjectId> {
+        let check = git_repo.config_bool("core", "checkcollisions") != Some(false);
+        let (kind, size) = (self.kind, self.expected_size);
+        let objects = repo_path(git_repo, &["objects"])?;
+        let tmp = tmp_object_file(&objects);
         let hash = self
-            .write(std::fs::File::create(tmp).context("write blog object for tree")?)
-            .context("stream file into tree object file")?;
+            .write(
+                std::fs::File::create(tmp.path())
+                    .with_context(|| format!("create {}", tmp.path().display()))?,
+                git_repo.hash_algo(),
+            )
+            .with_context(|| format!("stream {kind} object into {}", tmp.path().display()))?;
         let hash_hex = hex::encode(hash);
-        std::fs::create_dir_all(format!(".git/objects/{}/", &hash_hex[..2]))
+        let path = objects.join(&hash_hex[..2]).join(&hash_hex[2..]);
+        if path.exists() {
+            if check {
+                let written = inflate_object_file(tmp.path())?;
+                drop(tmp);
+                verify_existing_object(&path, &hash_hex, &written)?;
+            }
+            return Ok(hash);
+        }
+        std::fs::create_dir_all(objects.join(&hash_hex[..2]))
             .context("create subdir of .git/objects")?;
-        std::fs::rename(
-            tmp,
-            format!(".git/objects/{}/{}", &hash_hex[..2], &hash_hex[2..]),
-        )
-        .context("move blob file into .git/objects")?;
+        install_object_file(tmp, &path).with_context(|| format!("write object {hash_hex}"))?;
+        trace!(TRACE, "object write: {hash_hex} {kind} {size}");
         Ok(hash)
     }
 }
@@ -174,7 +222,7 @@ impl GitObject for GitCommit {
     }
 
     fn format(&self) -> &str {
-        return "commit";
+        "commit"
     }
 }
 
@@ -197,19 +245,13 @@ impl GitObject for GitTree {
     }
 
     fn format(&self) -> &str {
-        return "tree";
+        "tree"
     }
 }
 struct GitTag {
     data: Vec<u8>,
 }
 
-impl GitTag {
-    fn build<R: std::io::Read>(reader: R) -> Result<Self> {
-        todo!()
-    }
-}
-
 impl GitObject for GitTag {
     fn deserialize(buf: &[u8]) -> Box<dyn GitObject>
     where
@@ -223,7 +265,7 @@ impl GitObject for GitTag {
     }
 
     fn format(&self) -> &str {
-        return "tag";
+        "tag"
     }
 }
 
@@ -231,12 +273,6 @@ struct GitBlob {
     data: Vec<u8>,
 }
 
-impl GitBlob {
-    fn build<R: std::io::Read>(reader: R) -> Result<Self> {
-        todo!()
-    }
-}
-
 impl GitObject for GitBlob {
     fn deserialize(buf: &[u8]) -> Box<dyn GitObject>
     where
@@ -250,38 +286,44 @@ impl GitObject for GitBlob {
     }
 
     fn format(&self) -> &str {
-        return "blob";
+        "blob"
     }
 }
 
 pub fn object_read(git_repo: &GitRepository, sha: &str) -> Result<Box<dyn GitObject>> {
-    let path = repo_file(git_repo, &[&sha[0..2], &sha[2..]], false)?;
-    if !path.is_file() {
-        bail!("Object {} not found", sha);
-    }
-    let f = fs::File::open(path)?;
-    let mut d = GzDecoder::new(f);
-    // let mut decompressed = Vec::new();
-    // d.read_to_end(&mut decompressed)?;
-    let mut reader = BufReader::new(d);
+    let path = repo_file(git_repo, &["objects", &sha[0..2], &sha[2..]], false)?;
+    let (obj_type, data) = if path.is_file() {
+        let f = fs::File::open(path)?;
+        let mut reader = BufReader::new(ZlibDecoder::new(f));
 
-    let mut obj_type = Vec::new();
-    let obj_type_len = reader.read_until(b' ', &mut obj_type)?;
-    obj_type.pop();
-    let obj_type = std::str::from_utf8(&obj_type)?;
+        let mut obj_type = Vec::new();
+        reader.read_until(b' ', &mut obj_type)?;
+        obj_type.pop();
+        let obj_type = String::from_utf8(obj_type)?;
 
-    let mut obj_size = Vec::new();
-    let mut obj_size_len = reader.read_until(b'0', &mut obj_size)?;
-    obj_size.pop();
-    let obj_size = std::str::from_utf8(&obj_size)?.parse::<usize>()?;
+        let mut obj_size = Vec::new();
+        reader.read_until(0, &mut obj_size)?;
+        obj_size.pop();
+        let obj_size = std::str::from_utf8(&obj_size)?.parse::<usize>()?;
 
-    let mut data = Vec::new();
-    let data_len = reader.read_to_end(&mut data)?;
-    if obj_size != data_len {
-        bail!("Malformed object {}: bad length", sha);
-    }
+        let mut data = Vec::new();
+        let data_len = reader.read_to_end(&mut data)?;
+        if obj_size != data_len {
+            bail!("Malformed object {}: bad length", sha);
+        }
+        (obj_type, data)
+    } else if let Some((kind, data)) = read_packed(
+        &repo_path(git_repo, &["objects"])?,
+        git_repo.hash_algo(),
+        sha,
+    )? {
+        (kind.to_string(), data)
+    } else {
+        bail!("Object {} not found", sha);
+    };
+    trace!(TRACE, "object read: {sha} {obj_type} {}", data.len());
 
-    match obj_type {
+    match obj_type.as_str() {
         "commit" => Ok(GitCommit::deserialize(&data)),
         "tree" => Ok(GitTree::deserialize(&data)),
         "tag" => Ok(GitTag::deserialize(&data)),
@@ -290,56 +332,949 @@ pub fn object_read(git_repo: &GitRepository, sha: &str) -> Result<Box<dyn GitObj
     }
 }
 
-fn object_write(obj: &dyn GitObject, git_repo: Option<GitRepository>) -> Result<Vec<u8>> {
+/// Whether the object `sha` is in the object store of `git_repo`, loose or packed. Nothing is
+/// read but the pack indexes.
+pub(crate) fn object_exists(git_repo: &GitRepository, sha: &str) -> Result<bool> {
+    if sha.len() > 2 && repo_path(git_repo, &["objects", &sha[..2], &sha[2..]])?.is_file() {
+        return Ok(true);
+    }
+    packed_contains(
+        &repo_path(git_repo, &["objects"])?,
+        git_repo.hash_algo(),
+        sha,
+    )
+}
+
+/// The kind of the object `sha`. Only the header of a loose object is inflated, so finding out
+/// that a large blob is a blob stays cheap.
+pub(crate) fn object_kind(git_repo: &GitRepository, sha: &str) -> Result<Kind> {
+    object_header(git_repo, sha).map(|(kind, _)| kind)
+}
+
+/// The kind and size of the object `sha`, reading only the header of a loose object.
+pub(crate) fn object_header(git_repo: &GitRepository, sha: &str) -> Result<(Kind, usize)> {
+    let path = repo_file(git_repo, &["objects", &sha[0..2], &sha[2..]], false)?;
+    if !path.is_file() {
+        return match read_packed(
+            &repo_path(git_repo, &["objects"])?,
+            git_repo.hash_algo(),
+            sha,
+        )? {
+            Some((kind, data)) => Ok((kind, data.len())),
+            None => bail!("Object {} not found", sha),
+        };
+    }
+    let f = fs::File::open(&path).with_context(|| format!("open {}", path.display()))?;
+    let mut header = Vec::new();
+    BufReader::new(ZlibDecoder::new(f))
+        .take(32)
+        .read_until(0, &mut header)
+        .with_context(|| format!("inflate {}", path.display()))?;
+    let header = String::from_utf8_lossy(header.strip_suffix(b"\0").unwrap_or(&header));
+    let (kind, size) = header
+        .split_once(' ')
+        .with_context(|| format!("object {sha}: malformed header"))?;
+    let kind = kind.parse().with_context(|| format!("object {sha}"))?;
+    let size = size
+        .parse()
+        .with_context(|| format!("object {sha}: bad size {size}"))?;
+    Ok((kind, size))
+}
+
+fn object_write(
+    obj: &dyn GitObject,
+    git_repo: Option<&GitRepository>,
+    write: bool,
+) -> Result<Vec<u8>> {
+    let kind = match obj.format() {
+        "blob" => Kind::Blob,
+        "tree" => Kind::Tree,
+        "commit" => Kind::Commit,
+        _ => Kind::Tag,
+    };
     let data = obj.serialize();
+    let sha = match git_repo {
+        Some(repo) if write => write_object(repo, kind, &data)?,
+        Some(repo) => hash_object(repo.hash_algo(), kind, &data),
+        None => hash_object(HashAlgo::default(), kind, &data),
+    };
+    Ok(hex::decode(sha)?)
+}
+
+/// Write an object of `kind` with contents `data` to the object store of `git_repo`, returning
+/// its hash. An object that already exists isn't written again, but unless
+/// `core.checkCollisions` is turned off, its file is checked to hold the same content.
+pub(crate) fn write_object(git_repo: &GitRepository, kind: Kind, data: &[u8]) -> Result<String> {
+    let check = git_repo.config_bool("core", "checkcollisions") != Some(false);
+    let objects = repo_path(git_repo, &["objects"])?;
+    write_loose_object(&objects, git_repo.hash_algo(), kind, data, check)
+}
+
+/// The hash of an object of `kind` holding `data`, named with `algo`.
+pub(crate) fn hash_object(algo: HashAlgo, kind: Kind, data: &[u8]) -> String {
+    let mut hasher = algo.hasher();
+    hasher.update(format!("{kind} {}\0", data.len()));
+    hasher.update(data);
+    hasher.finalize().to_string()
+}
+
+/// Write an object as a loose file under `objects_dir`, which need not be the repository's own
+/// object directory, returning its `algo` hash. With `check`, an existing copy is read back and
+/// must hold exactly this object.
+pub(crate) fn write_loose_object(
+    objects_dir: &Path,
+    algo: HashAlgo,
+    kind: Kind,
+    data: &[u8],
+    check: bool,
+) -> Result<String> {
+    let sha = hash_object(algo, kind, data);
+    let dir = objects_dir.join(&sha[..2]);
+    let path = dir.join(&sha[2..]);
+    let mut object = format!("{kind} {}\0", data.len()).into_bytes();
+    object.extend_from_slice(data);
+    if path.exists() {
+        if check {
+            verify_existing_object(&path, &sha, &object)?;
+        }
+        return Ok(sha);
+    }
+    fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    // write to a temporary file first so readers never see a partial object
+    let tmp = tmp_object_file(&dir);
+    let mut f = ZlibEncoder::new(
+        fs::File::create(tmp.path()).with_context(|| format!("create {}", tmp.path().display()))?,
+        Compression::default(),
+    );
+    f.write_all(&object)?;
+    f.finish()?;
+    install_object_file(tmp, &path).with_context(|| format!("write object {sha}"))?;
+    trace!(TRACE, "object write: {sha} {kind} {}", data.len());
+    Ok(sha)
+}
+
+/// A temporary file in `dir` whose name no other writer, in this process or another, uses.
+fn tmp_object_file(dir: &Path) -> TempPath {
+    static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    TempPath::new(dir.join(format!(
+        "tmp_obj_{}_{}",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )))
+}
+
+/// The uncompressed header and content of the loose object file at `path`.
+fn inflate_object_file(path: &Path) -> Result<Vec<u8>> {
+    let file = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut data = Vec::new();
+    ZlibDecoder::new(file)
+        .read_to_end(&mut data)
+        .with_context(|| format!("inflate {}", path.display()))?;
+    Ok(data)
+}
+
+/// Check that the loose object file at `path`, found where the object `sha` goes, holds
+/// `object` (header and content, uncompressed). Anything else means the file is corrupt, or in
+/// theory that two objects collide, and keeping it would silently lose the new object.
+fn verify_existing_object(path: &Path, sha: &str, object: &[u8]) -> Result<()> {
+    let existing = inflate_object_file(path);
+    if existing.as_deref().ok() != Some(object) {
+        bail!(
+            "object {sha} already exists with different content; {} may be corrupt",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Move the finished temporary object file `tmp` to `path` durably: the file is made read-only
+/// and synced to disk before the rename, and its directory after, so that a crash can't leave
+/// an object that later reads as corrupt.
+fn install_object_file(tmp: TempPath, path: &Path) -> Result<()> {
+    let tmp_path = tmp.path().to_path_buf();
+    let file = fs::File::open(&tmp_path).with_context(|| format!("open {}", tmp_path.display()))?;
+    file.set_permissions(fs::Permissions::from_mode(0o444))
+        .with_context(|| format!("make {} read-only", tmp_path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("sync {}", tmp_path.display()))?;
+    tmp.persist(path)
+        .with_context(|| format!("rename {} into place", tmp_path.display()))?;
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("sync {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// The ids of the objects in the store of `git_repo`, as [`object_ids`] lists them.
+pub(crate) struct ObjectIds {
+    hash_algo: HashAlgo,
+    /// The fan-out directories still to list, with their two hex digits.
+    fanouts: std::vec::IntoIter<(String, PathBuf)>,
+    /// The fan-out directory being listed.
+    loose: Option<(String, fs::ReadDir)>,
+    packed: std::vec::IntoIter<String>,
+}
+
+impl Iterator for ObjectIds {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = interrupt::check() {
+            return Some(Err(e));
+        }
+        loop {
+            if let Some((fanout, entries)) = &mut self.loose {
+                match entries.next() {
+                    Some(Ok(entry)) => {
+                        let sha = format!("{fanout}{}", entry.file_name().to_string_lossy());
+                        // temporary files of writers in progress aren't objects
+                        if self.hash_algo.is_hex_id(&sha) {
+                            return Some(Ok(sha));
+                        }
+                        continue;
+                    }
+                    Some(Err(e)) => return Some(Err(e.into())),
+                    None => self.loose = None,
+                }
+            }
+            let Some((fanout, dir)) = self.fanouts.next() else {
+                return self.packed.next().map(Ok);
+            };
+            match fs::read_dir(&dir) {
+                Ok(entries) => self.loose = Some((fanout, entries)),
+                Err(e) => {
+                    return Some(Err(
+                        anyhow::Error::new(e).context(format!("read {}", dir.display()))
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Every object id in the store of `git_repo`: the loose objects, one fan-out directory after
+/// another, then the objects of each pack. Only directories and pack indexes are read, never
+/// objects. The ids come in the order they are stored, and an object that is both loose and
+/// packed comes twice; [`all_objects`] sorts them.
+pub(crate) fn object_ids(git_repo: &GitRepository) -> Result<ObjectIds> {
+    let objects = repo_path(git_repo, &["objects"])?;
+    let mut fanouts = Vec::new();
+    for dir in fs::read_dir(&objects).with_context(|| format!("read {}", objects.display()))? {
+        let dir = dir?;
+        let fanout = dir.file_name().to_string_lossy().into_owned();
+        if fanout.len() == 2 && fanout.chars().all(|c| c.is_ascii_hexdigit()) {
+            fanouts.push((fanout, dir.path()));
+        }
+    }
+    fanouts.sort();
+    Ok(ObjectIds {
+        hash_algo: git_repo.hash_algo(),
+        fanouts: fanouts.into_iter(),
+        loose: None,
+        packed: packed_with_prefix(&objects, git_repo.hash_algo(), "")?.into_iter(),
+    })
+}
+
+/// Every object id in the store of `git_repo`, loose and packed, once each and sorted.
+pub(crate) fn all_objects(git_repo: &GitRepository) -> Result<BTreeSet<String>> {
+    object_ids(git_repo)?.collect()
+}
+
+/// Expand an abbreviated object hash (at least 4 hex digits) to the full hash, looking at loose
+/// and packed objects. Returns `None` if no object matches and fails if several do.
+pub(crate) fn object_resolve_prefix(
+    git_repo: &GitRepository,
+    prefix: &str,
+) -> Result<Option<String>> {
+    let prefix = prefix.to_ascii_lowercase();
+    let hex_len = git_repo.hash_algo().hex_len();
+    if prefix.len() < 4 || prefix.len() > hex_len || !prefix.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return Ok(None);
+    }
+    let objects = repo_path(git_repo, &["objects"])?;
+    let mut found = packed_with_prefix(&objects, git_repo.hash_algo(), &prefix)?;
+    if let Ok(entries) = fs::read_dir(objects.join(&prefix[..2])) {
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            let sha = format!("{}{name}", &prefix[..2]);
+            if sha.len() == hex_len && sha.starts_with(&prefix) {
+                found.push(sha);
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    match found.len() {
+        0 => Ok(None),
+        1 => Ok(found.pop()),
+        _ => bail!("short object ID {prefix} is ambiguous"),
+    }
+}
+
+/// Resolve a revision name to an object hash.
+///
+/// Accepts a full or abbreviated hash, `HEAD`, or a ref name (looked up as given and under
+/// `refs/`, `refs/tags/`, `refs/heads/` and `refs/remotes/`), optionally followed by any number of
+/// `^<n>` (n-th parent), `~<n>` (n-th first-parent ancestor), `^{<type>}` (peeled to that type)
+/// and `^{}` (tags peeled to what they tag) suffixes. The object found is peeled to the kind `tp`
+/// (see [`peel_to`]), so a tag names its commit where a commit is needed, unless the name ends in
+/// a `^{...}` that says what it wants itself.
+pub(crate) fn object_find(
+    git_repo: &GitRepository,
+    name: String,
+    tp: ObjectType,
+) -> Result<String> {
+    let split = name.find(['^', '~']).unwrap_or(name.len());
+    let (base, mut suffix) = name.split_at(split);
+
+    let mut sha = if git_repo.hash_algo().is_hex_id(base) {
+        base.to_ascii_lowercase()
+    } else {
+        let candidates = [
+            base.to_string(),
+            format!("refs/{base}"),
+            format!("refs/tags/{base}"),
+            format!("refs/heads/{base}"),
+            format!("refs/remotes/{base}"),
+        ];
+        let mut found = None;
+        for candidate in candidates {
+            if let Some(sha) = ref_resolve(git_repo, &candidate)? {
+                found = Some(sha);
+                break;
+            }
+        }
+        if found.is_none() {
+            found = object_resolve_prefix(git_repo, base)?;
+        }
+        found.with_context(|| format!("unknown revision {name}"))?
+    };
+
+    let mut peeled = false;
+    while let Some(op) = suffix.chars().next() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let end = rest
+                .find('}')
+                .with_context(|| format!("{name}: missing '}}'"))?;
+            let target = match &rest[..end] {
+                "" => None,
+                kind => Some(
+                    kind.parse::<Kind>()
+                        .with_context(|| format!("{name}: unknown peel suffix ^{{{kind}}}"))?,
+                ),
+            };
+            sha = peel(git_repo, &sha, target).with_context(|| format!("{name}: can't peel"))?;
+            suffix = &rest[end + 1..];
+            peeled = true;
+            continue;
+        }
+        if op != '^' && op != '~' {
+            bail!("unknown revision {name}");
+        }
+        peeled = false;
+        sha = peel_to(git_repo, &sha, Kind::Commit)?;
+        let digits = suffix[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(suffix.len(), |i| i + 1);
+        let n = match &suffix[1..digits] {
+            "" => 1,
+            n => n.parse::<usize>()?,
+        };
+        suffix = &suffix[digits..];
+        if op == '^' {
+            if n == 0 {
+                continue;
+            }
+            let commit = read_commit(git_repo, &sha)?;
+            sha = commit
+                .parents
+                .get(n - 1)
+                .with_context(|| format!("{name}: commit {sha} has no parent {n}"))?
+                .clone();
+        } else {
+            for _ in 0..n {
+                let commit = read_commit(git_repo, &sha)?;
+                sha = commit
+                    .parents
+                    .first()
+                    .with_context(|| format!("{name}: commit {sha} has no parent"))?
+                    .clone();
+            }
+        }
+    }
+    if peeled {
+        return Ok(sha);
+    }
+    let kind = match tp {
+        ObjectType::Blob => Kind::Blob,
+        ObjectType::Tree => Kind::Tree,
+        ObjectType::Commit => Kind::Commit,
+        ObjectType::Tag => Kind::Tag,
+    };
+    peel_to(git_repo, &sha, kind).with_context(|| format!("{name} is not a {tp}"))
+}
+
+/// Follow `hash` until it reaches an object of kind `target`: tags are dereferenced to the
+/// object they tag, and commits to their tree. Fails if `target` can't be reached that way,
+/// for example when a blob is asked for a commit.
+pub(crate) fn peel_to(git_repo: &GitRepository, hash: &str, target: Kind) -> Result<String> {
+    peel(git_repo, hash, Some(target))
+}
+
+/// Follow `hash` as [`peel_to`] does to `target`, or with `None` through tags only, to the first
+/// object that isn't one.
+fn peel(git_repo: &GitRepository, hash: &str, target: Option<Kind>) -> Result<String> {
+    let mut hash = hash.to_string();
+    loop {
+        let obj = object_read(git_repo, &hash)?;
+        let kind = obj.format();
+        let done = match target {
+            Some(target) => kind == target.to_string(),
+            None => kind != "tag",
+        };
+        if done {
+            return Ok(hash);
+        }
+        hash = match kind {
+            "tag" => {
+                let kvlm = kvlm_parse(&obj.serialize())?;
+                let object = kvlm
+                    .get(b"object".as_slice())
+                    .and_then(|v| v.first())
+                    .with_context(|| format!("tag {hash} has no object header"))?;
+                String::from_utf8(object.clone()).context("tag object isn't utf-8")?
+            }
+            "commit" if target == Some(Kind::Tree) => Commit::parse(&obj.serialize())?.tree,
+            _ => bail!(
+                "object {hash} is a {kind}, which can't be peeled to a {}",
+                target.map_or("non-tag".to_string(), |t| t.to_string())
+            ),
+        };
+    }
+}
+
+/// The mode of a tree entry, which says what kind of thing the entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Mode {
+    /// A subtree (`40000`).
+    Dir,
+    /// A non-executable file (`100644`).
+    RegularFile,
+    /// An executable file (`100755`).
+    Executable,
+    /// A symbolic link, its target being the blob (`120000`).
+    Symlink,
+    /// A submodule commit (`160000`).
+    Gitlink,
+}
+
+impl Mode {
+    /// The mode for octal mode bits, canonicalized like git does: a regular file with any
+    /// executable bit is `Executable`, and other permission bits are dropped.
+    pub(crate) fn from_bits(bits: u32) -> Self {
+        match bits & 0o170000 {
+            0o040000 => Self::Dir,
+            0o120000 => Self::Symlink,
+            0o160000 => Self::Gitlink,
+            _ if bits & 0o100 != 0 => Self::Executable,
+            _ => Self::RegularFile,
+        }
+    }
+
+    /// The octal mode bits, as index entries store them.
+    pub(crate) fn bits(self) -> u32 {
+        match self {
+            Self::Dir => 0o040000,
+            Self::RegularFile => 0o100644,
+            Self::Executable => 0o100755,
+            Self::Symlink => 0o120000,
+            Self::Gitlink => 0o160000,
+        }
+    }
+
+    pub(crate) fn is_tree(self) -> bool {
+        self == Self::Dir
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = anyhow::Error;
+
+    /// Parse a mode as trees write it, canonicalizing it like `from_bits` (so the `100664` of
+    /// old trees is a regular file).
+    fn from_str(mode: &str) -> Result<Self> {
+        let bits = u32::from_str_radix(mode, 8).with_context(|| format!("invalid mode {mode}"))?;
+        Ok(Self::from_bits(bits))
+    }
+}
+
+impl Display for Mode {
+    /// The mode as trees write it, without a leading zero for `Dir`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:o}", self.bits())
+    }
+}
+
+/// An entry of a tree object.
+#[derive(Debug, Clone)]
+pub(crate) struct TreeEntry {
+    pub(crate) mode: Mode,
+    pub(crate) name: String,
+    pub(crate) hash: String,
+}
+
+impl TreeEntry {
+    pub(crate) fn is_tree(&self) -> bool {
+        self.mode.is_tree()
+    }
+}
+
+/// Parse the body of a tree object: `{mode} {name}\0{binary hash}` repeated, the hashes being as
+/// long as `algo` makes them.
+pub(crate) fn parse_tree(data: &[u8], algo: HashAlgo) -> Result<Vec<TreeEntry>> {
+    let hash_len = algo.raw_len();
+    let mut entries = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let nul = rest
+            .iter()
+            .position(|b| *b == 0)
+            .context("tree entry is missing its name terminator")?;
+        let mode_and_name = std::str::from_utf8(&rest[..nul]).context("tree entry isn't utf-8")?;
+        let (mode, name) = mode_and_name
+            .split_once(' ')
+            .context("tree entry has no mode")?;
+        let hash = rest
+            .get(nul + 1..nul + 1 + hash_len)
+            .context("tree entry hash is truncated")?;
+        entries.push(TreeEntry {
+            mode: mode.parse()?,
+            name: name.to_string(),
+            hash: hex::encode(hash),
+        });
+        rest = &rest[nul + 1 + hash_len..];
+    }
+    Ok(entries)
+}
+
+/// Read the tree object `sha` and parse its entries.
+pub(crate) fn read_tree(git_repo: &GitRepository, sha: &str) -> Result<Vec<TreeEntry>> {
+    let obj = object_read(git_repo, sha)?;
+    if obj.format() != "tree" {
+        bail!("object {sha} is a {}, not a tree", obj.format());
+    }
+    parse_tree(&obj.serialize(), git_repo.hash_algo()).with_context(|| format!("parse tree {sha}"))
+}
+
+/// List every non-tree entry below the tree `sha`, with names as full paths under `prefix`.
+pub(crate) fn read_tree_recursive(
+    git_repo: &GitRepository,
+    sha: &str,
+    prefix: &str,
+) -> Result<Vec<TreeEntry>> {
     let mut result = Vec::new();
-    result.extend_from_slice(obj.format().as_bytes());
-    result.push(b' ');
-    result.extend_from_slice(&data.len().to_be_bytes());
-    result.push(0);
-    result.extend_from_slice(&data);
-
-    use sha1::{Digest, Sha1};
-
-    let mut hasher = Sha1::new();
-    hasher.update(&result);
-    let result = hasher.finalize();
-    if let Some(repo) = git_repo {
-        let path = repo_file(
-            &repo,
-            &[
-                "objects",
-                std::str::from_utf8(&result[0..2])?,
-                std::str::from_utf8(&result[2..])?,
-            ],
-            true,
-        )?;
-        if !path.exists() {
-            let mut f = std::fs::File::options()
-                .write(true)
-                .create(true)
-                .open(path)?;
-            f.write_all(&result)?;
+    for entry in read_tree(git_repo, sha)? {
+        let path = format!("{prefix}{}", entry.name);
+        if entry.is_tree() {
+            result.extend(read_tree_recursive(
+                git_repo,
+                &entry.hash,
+                &format!("{path}/"),
+            )?);
+        } else {
+            result.push(TreeEntry {
+                name: path,
+                ..entry
+            });
+        }
+    }
+    Ok(result)
+}
+
+/// Find the entry at `path` (slash separated) below the tree `sha`.
+pub(crate) fn tree_lookup(
+    git_repo: &GitRepository,
+    sha: &str,
+    path: &str,
+) -> Result<Option<TreeEntry>> {
+    let mut tree = sha.to_string();
+    let mut components = path.split('/').filter(|c| !c.is_empty()).peekable();
+    while let Some(component) = components.next() {
+        let Some(entry) = read_tree(git_repo, &tree)?
+            .into_iter()
+            .find(|e| e.name == component)
+        else {
+            return Ok(None);
+        };
+        if components.peek().is_none() {
+            return Ok(Some(entry));
+        }
+        if !entry.is_tree() {
+            return Ok(None);
+        }
+        tree = entry.hash;
+    }
+    Ok(None)
+}
+
+/// How many symlinks [`tree_lookup_follow_symlinks`] follows before giving up, as git does.
+const MAX_SYMLINKS: usize = 40;
+
+/// Find the entry at `path` below the tree `sha` like [`tree_lookup`], but follow the symlinks
+/// met on the way, including one at `path` itself, to the entries they point at. A symlink that
+/// leads outside the tree is an error.
+pub(crate) fn tree_lookup_follow_symlinks(
+    git_repo: &GitRepository,
+    sha: &str,
+    path: &str,
+) -> Result<Option<TreeEntry>> {
+    let mut components = normalize_tree_path(path.split('/'), path)?;
+    let mut links = 0;
+    'restart: loop {
+        let mut tree = sha.to_string();
+        for i in 0..components.len() {
+            let Some(entry) = read_tree(git_repo, &tree)?
+                .into_iter()
+                .find(|e| e.name == components[i])
+            else {
+                return Ok(None);
+            };
+            if entry.mode == Mode::Symlink {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    bail!("too many levels of symbolic links resolving '{path}'");
+                }
+                let link = components[..=i].join("/");
+                let target = String::from_utf8(object_read(git_repo, &entry.hash)?.serialize())
+                    .with_context(|| format!("symlink '{link}' target isn't utf-8"))?;
+                if target.starts_with('/') {
+                    bail!("symlink '{link}' points outside the tree: {target}");
+                }
+                // the target is relative to the directory holding the link
+                let joined = components[..i]
+                    .iter()
+                    .map(String::as_str)
+                    .chain(target.split('/'))
+                    .chain(components[i + 1..].iter().map(String::as_str));
+                components = normalize_tree_path(joined, &link)?;
+                continue 'restart;
+            }
+            if i + 1 == components.len() {
+                return Ok(Some(entry));
+            }
+            if !entry.is_tree() {
+                return Ok(None);
+            }
+            tree = entry.hash;
+        }
+        return Ok(None);
+    }
+}
+
+/// The path made of `components` with empty and `.` components dropped and each `..` removing
+/// the component before it. `what` names the path in the error for a `..` above the root.
+fn normalize_tree_path<'a>(
+    components: impl Iterator<Item = &'a str>,
+    what: &str,
+) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    for component in components {
+        match component {
+            "" | "." => {}
+            ".." => {
+                if normalized.pop().is_none() {
+                    bail!("symlink '{what}' points outside the tree");
+                }
+            }
+            _ => normalized.push(component.to_string()),
+        }
+    }
+    Ok(normalized)
+}
+
+/// The subject of a commit or tag message: its first paragraph, joined into one line.
+pub(crate) fn subject(message: &[u8]) -> String {
+    String::from_utf8_lossy(message)
+        .lines()
+        .skip_while(|l| l.trim().is_empty())
+        .take_while(|l| !l.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The parsed headers of a commit object.
+#[derive(Debug, Clone)]
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub committer: String,
+    pub message: String,
+}
+
+impl Commit {
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        let kvlm = kvlm_parse(data)?;
+        let field = |key: &str| -> Vec<String> {
+            kvlm.get(key.as_bytes())
+                .map(|values| {
+                    values
+                        .iter()
+                        .map(|v| String::from_utf8_lossy(v).into_owned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Ok(Commit {
+            tree: field("tree").pop().context("commit has no tree header")?,
+            parents: field("parent"),
+            author: field("author").pop().unwrap_or_default(),
+            committer: field("committer").pop().unwrap_or_default(),
+            message: field("").pop().unwrap_or_default(),
+        })
+    }
+
+    /// The trailers at the end of the message, like `Signed-off-by` or `Co-authored-by`, as
+    /// `(key, value)` pairs in order; see [`parse_trailers`].
+    pub(crate) fn trailers(&self) -> Vec<(String, String)> {
+        parse_trailers(&self.message)
+            .into_iter()
+            .map(|trailer| (trailer.key, trailer.value))
+            .collect()
+    }
+
+    /// The committer timestamp, or 0 if the committer header is missing or malformed.
+    pub(crate) fn commit_time(&self) -> i64 {
+        Signature::parse(&self.committer).map_or(0, |committer| committer.time)
+    }
+}
+
+/// Read the commit object `sha` and parse it.
+pub(crate) fn read_commit(git_repo: &GitRepository, sha: &str) -> Result<Commit> {
+    let obj = object_read(git_repo, sha)?;
+    if obj.format() != "commit" {
+        bail!("object {sha} is a {}, not a commit", obj.format());
+    }
+    Commit::parse(&obj.serialize()).with_context(|| format!("parse commit {sha}"))
+}
+
+/// Whether `ancestor` is `commit` itself or reachable from it through parent links.
+pub(crate) fn is_ancestor(git_repo: &GitRepository, ancestor: &str, commit: &str) -> Result<bool> {
+    let mut seen = HashSet::new();
+    let mut pending = vec![commit.to_string()];
+    while let Some(sha) = pending.pop() {
+        if sha == ancestor {
+            return Ok(true);
+        }
+        if seen.insert(sha.clone()) {
+            pending.extend(read_commit(git_repo, &sha)?.parents);
         }
     }
-    Ok(result.to_vec())
+    Ok(false)
 }
 
-pub(crate) fn object_find(git_repo: &GitRepository, sha: String, tp: ObjectType) -> Result<String> {
-    return Ok(sha);
+/// All commits reachable from `start`, `start` included.
+pub(crate) fn ancestors(git_repo: &GitRepository, start: &[String]) -> Result<HashSet<String>> {
+    let mut seen = HashSet::new();
+    let mut pending = start.to_vec();
+    while let Some(sha) = pending.pop() {
+        if seen.insert(sha.clone()) {
+            pending.extend(read_commit(git_repo, &sha)?.parents);
+        }
+    }
+    Ok(seen)
+}
+
+/// The best common ancestor of `a` and `b`: a commit reachable from both that no other such
+/// commit descends from. Of several, the most recently committed is taken. `None` when the
+/// histories are unrelated.
+pub(crate) fn merge_base(git_repo: &GitRepository, a: &str, b: &str) -> Result<Option<String>> {
+    let from_a = ancestors(git_repo, &[a.to_string()])?;
+    let common = ancestors(git_repo, &[b.to_string()])?
+        .into_iter()
+        .filter(|sha| from_a.contains(sha))
+        .collect::<HashSet<_>>();
+    let mut parents = Vec::new();
+    for sha in &common {
+        parents.extend(read_commit(git_repo, sha)?.parents);
+    }
+    let below = ancestors(git_repo, &parents)?;
+    let mut best = None;
+    for sha in common.into_iter().filter(|sha| !below.contains(sha)) {
+        let time = read_commit(git_repo, &sha)?.commit_time();
+        if best.as_ref().is_none_or(|(_, t)| time > *t) {
+            best = Some((sha, time));
+        }
+    }
+    Ok(best.map(|(sha, _)| sha))
 }
 
+/// Resolve a tree-ish (a tree, or a commit whose tree is used) to a tree hash.
+pub(crate) fn tree_ish(git_repo: &GitRepository, name: &str) -> Result<String> {
+    object_find(git_repo, name.to_string(), ObjectType::Tree)
+}
+
+/// Hash `data` as an object of `object_type` with the hash function of `git_repo`, writing it
+/// there with `write`. Without a repository it is named with SHA-1, as git does.
 pub(crate) fn object_hash(
-    git_repo: Option<GitRepository>,
-    file: PathBuf,
+    git_repo: Option<&GitRepository>,
+    write: bool,
+    data: &[u8],
     object_type: ObjectType,
 ) -> Result<Vec<u8>> {
-    let data = std::fs::read(file)?;
     let obj = match object_type {
-        ObjectType::Blob => GitBlob::deserialize(&data),
-        ObjectType::Tree => GitTree::deserialize(&data),
-        ObjectType::Commit => GitCommit::deserialize(&data),
-        ObjectType::Tag => GitTag::deserialize(&data),
+        ObjectType::Blob => GitBlob::deserialize(data),
+        ObjectType::Tree => GitTree::deserialize(data),
+        ObjectType::Commit => GitCommit::deserialize(data),
+        ObjectType::Tag => GitTag::deserialize(data),
     };
-    return object_write(obj.as_ref(), git_repo);
+    object_write(obj.as_ref(), git_repo, write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{repository::repo_open, test_util::TempDir};
+
+    /// A repository with a commit, an annotated tag of it, and a tag of that tag.
+    fn tagged_repo() -> (TempDir, GitRepository) {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"], b"");
+        fs::write(dir.path().join("file"), "contents\n").unwrap();
+        dir.git(&["add", "file"], b"");
+        dir.git(&["commit", "-q", "-m", "commit"], b"");
+        dir.git(&["tag", "-a", "v1", "-m", "tag"], b"");
+        dir.git(&["tag", "-a", "v1-again", "-m", "tag of a tag", "v1"], b"");
+        let git_repo = repo_open(dir.path()).unwrap();
+        (dir, git_repo)
+    }
+
+    fn rev_parse(dir: &TempDir, rev: &str) -> String {
+        dir.git(&["rev-parse", rev], b"").trim().to_string()
+    }
+
+    #[test]
+    fn peels_annotated_tags_to_commits() {
+        let (dir, git_repo) = tagged_repo();
+        let commit = rev_parse(&dir, "HEAD");
+        for tag in ["v1", "v1-again"] {
+            let tag = rev_parse(&dir, tag);
+            assert_eq!(peel_to(&git_repo, &tag, Kind::Commit).unwrap(), commit);
+        }
+        assert_eq!(peel_to(&git_repo, &commit, Kind::Commit).unwrap(), commit);
+    }
+
+    #[test]
+    fn peels_commits_and_tags_to_trees() {
+        let (dir, git_repo) = tagged_repo();
+        let tree = rev_parse(&dir, "HEAD^{tree}");
+        for rev in ["HEAD", "v1", "v1-again"] {
+            let hash = rev_parse(&dir, rev);
+            assert_eq!(peel_to(&git_repo, &hash, Kind::Tree).unwrap(), tree);
+        }
+    }
+
+    #[test]
+    fn refuses_to_peel_down_to_a_kind_it_cant_reach() {
+        let (dir, git_repo) = tagged_repo();
+        let tree = rev_parse(&dir, "HEAD^{tree}");
+        let blob = rev_parse(&dir, "HEAD:file");
+        assert!(peel_to(&git_repo, &tree, Kind::Commit).is_err());
+        assert!(peel_to(&git_repo, &blob, Kind::Tree).is_err());
+        let commit = rev_parse(&dir, "HEAD");
+        assert!(peel_to(&git_repo, &commit, Kind::Tag).is_err());
+    }
+
+    #[test]
+    fn parses_and_writes_canonical_modes() {
+        let modes = [
+            ("40000", Mode::Dir),
+            ("100644", Mode::RegularFile),
+            ("100755", Mode::Executable),
+            ("120000", Mode::Symlink),
+            ("160000", Mode::Gitlink),
+        ];
+        for (text, mode) in modes {
+            assert_eq!(text.parse::<Mode>().unwrap(), mode);
+            assert_eq!(mode.to_string(), text);
+            assert_eq!(Mode::from_bits(mode.bits()), mode);
+            assert_eq!(mode.is_tree(), mode == Mode::Dir);
+        }
+    }
+
+    #[test]
+    fn canonicalizes_other_modes() {
+        assert_eq!("100664".parse::<Mode>().unwrap(), Mode::RegularFile);
+        assert_eq!("040000".parse::<Mode>().unwrap(), Mode::Dir);
+        assert_eq!(Mode::from_bits(0o100700), Mode::Executable);
+        assert_eq!(Mode::from_bits(0o100600), Mode::RegularFile);
+        assert!("10064x".parse::<Mode>().is_err());
+        assert!("".parse::<Mode>().is_err());
+    }
+
+    /// Replace the loose object file at `path` with a valid one holding `object` instead.
+    fn overwrite_object_file(path: &Path, object: &[u8]) {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        let mut f = ZlibEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+        f.write_all(object).unwrap();
+        f.finish().unwrap();
+    }
+
+    #[test]
+    fn writes_loose_objects_read_only() {
+        let dir = TempDir::new();
+        let sha = write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true)
+            .unwrap();
+        assert_eq!(sha, hash_object(HashAlgo::Sha1, Kind::Blob, b"contents\n"));
+        let path = dir.path().join(&sha[..2]).join(&sha[2..]);
+        assert_eq!(inflate_object_file(&path).unwrap(), b"blob 9\0contents\n");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o444
+        );
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+
+        // writing it again finds the same content
+        write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_keep_an_existing_object_with_other_content() {
+        let dir = TempDir::new();
+        let sha = write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true)
+            .unwrap();
+        let path = dir.path().join(&sha[..2]).join(&sha[2..]);
+        overwrite_object_file(&path, b"blob 9\0CONTENTS\n");
+
+        let err = write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains(&format!(
+                "object {sha} already exists with different content"
+            )),
+            "{err}"
+        );
+        // unchecked, the existing file is trusted and left alone
+        write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", false).unwrap();
+        assert_eq!(inflate_object_file(&path).unwrap(), b"blob 9\0CONTENTS\n");
+    }
+
+    #[test]
+    fn refuses_to_keep_an_unreadable_existing_object() {
+        let dir = TempDir::new();
+        let sha = write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true)
+            .unwrap();
+        let path = dir.path().join(&sha[..2]).join(&sha[2..]);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(&path, b"not zlib").unwrap();
+        assert!(
+            write_loose_object(dir.path(), HashAlgo::Sha1, Kind::Blob, b"contents\n", true)
+                .is_err()
+        );
+    }
 }
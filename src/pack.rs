@@ -0,0 +1,1127 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+use crate::{
+    hash::{HashAlgo, Hasher, ObjectId},
+    interrupt::{self, TempPath},
+    objects::{hash_object, object_read, Kind},
+    repository::{repo_path, GitRepository},
+};
+use anyhow::{bail, Context, Result};
+use flate2::{bufread::ZlibDecoder, write::ZlibEncoder, Compression, Crc};
+
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+
+/// The `.idx` file of a pack (version 2).
+pub(crate) struct PackIndex {
+    fanout: [u32; 256],
+    hashes: Vec<ObjectId>,
+    offsets: Vec<u64>,
+    /// The checksum of the pack the index belongs to.
+    pack_checksum: ObjectId,
+}
+
+impl PackIndex {
+    /// Parse the index of a pack of `algo` objects, whose ids and checksums are that long.
+    pub(crate) fn parse(buf: &[u8], algo: HashAlgo) -> Result<Self> {
+        if buf.len() < 8 + 256 * 4 || &buf[0..4] != IDX_MAGIC {
+            bail!("pack index has no version 2 header");
+        }
+        let version = u32::from_be_bytes(buf[4..8].try_into().unwrap());
+        if version != 2 {
+            bail!("unsupported pack index version {version}");
+        }
+
+        let mut fanout = [0; 256];
+        for (i, slot) in fanout.iter_mut().enumerate() {
+            let at = 8 + i * 4;
+            *slot = u32::from_be_bytes(buf[at..at + 4].try_into().unwrap());
+        }
+        // each count includes the ones before it; lookups slice the hashes by them
+        if fanout.windows(2).any(|pair| pair[0] > pair[1]) {
+            bail!("pack index fanout table is not monotonic");
+        }
+        let count = fanout[255] as usize;
+        let hash_len = algo.raw_len();
+
+        let hashes_at = 8 + 256 * 4;
+        let offsets_at = hashes_at + count * hash_len + count * 4;
+        let large_offsets_at = offsets_at + count * 4;
+        if buf.len() < large_offsets_at + 2 * hash_len {
+            bail!("pack index is truncated");
+        }
+
+        let hashes = buf[hashes_at..hashes_at + count * hash_len]
+            .chunks_exact(hash_len)
+            .map(ObjectId::from_bytes)
+            .collect::<Result<_>>()?;
+        let offsets = buf[offsets_at..large_offsets_at]
+            .chunks_exact(4)
+            .map(|o| {
+                let o = u32::from_be_bytes(o.try_into().unwrap());
+                if o & 0x8000_0000 == 0 {
+                    return Ok(o as u64);
+                }
+                // the msb marks an index into the 8-byte offset table
+                let at = large_offsets_at + (o & 0x7fff_ffff) as usize * 8;
+                let large = buf
+                    .get(at..at + 8)
+                    .context("pack index large offset out of range")?;
+                Ok(u64::from_be_bytes(large.try_into().unwrap()))
+            })
+            .collect::<Result<_>>()?;
+
+        // the trailer is the pack's checksum, then the index's own
+        let trailer = buf.len() - 2 * hash_len;
+        Ok(Self {
+            fanout,
+            hashes,
+            offsets,
+            pack_checksum: ObjectId::from_bytes(&buf[trailer..trailer + hash_len])?,
+        })
+    }
+
+    /// All object hashes in the pack, sorted.
+    pub(crate) fn hashes(&self) -> &[ObjectId] {
+        &self.hashes
+    }
+
+    /// The pack offsets of the objects, in the order of [`hashes`](Self::hashes).
+    pub(crate) fn offsets(&self) -> &[u64] {
+        &self.offsets
+    }
+
+    pub(crate) fn pack_checksum(&self) -> &ObjectId {
+        &self.pack_checksum
+    }
+
+    /// Look up where `hash` is in [`hashes`](Self::hashes), using the fanout table to narrow the
+    /// binary search.
+    pub(crate) fn position(&self, hash: &ObjectId) -> Option<usize> {
+        let first = hash.as_bytes()[0] as usize;
+        let lo = if first == 0 {
+            0
+        } else {
+            self.fanout[first - 1] as usize
+        };
+        let hi = self.fanout[first] as usize;
+        self.hashes
+            .get(lo..hi)?
+            .binary_search(hash)
+            .ok()
+            .map(|i| lo + i)
+    }
+
+    /// Look up the pack offset of `hash`.
+    pub(crate) fn find(&self, hash: &ObjectId) -> Option<u64> {
+        self.position(hash).map(|i| self.offsets[i])
+    }
+}
+
+/// Where the bytes of a `.pack` file come from.
+enum PackData {
+    /// Seek and read through the file handle for every object.
+    File(fs::File),
+    /// Read straight out of a read-only mapping of the whole file.
+    #[cfg(feature = "mmap")]
+    Mmap(memmap2::Mmap),
+}
+
+impl PackData {
+    fn open(path: &Path) -> Result<Self> {
+        let f = fs::File::open(path).with_context(|| format!("open {}", path.display()))?;
+        // SAFETY: pack files are immutable once written; git only ever replaces them by writing
+        // a new file and renaming it into place. If mapping fails we fall back to plain reads.
+        #[cfg(feature = "mmap")]
+        if let Ok(map) = unsafe { memmap2::Mmap::map(&f) } {
+            return Ok(PackData::Mmap(map));
+        }
+        Ok(PackData::File(f))
+    }
+
+    fn len(&self) -> Result<u64> {
+        match self {
+            PackData::File(f) => Ok(f.metadata().context("stat pack file")?.len()),
+            #[cfg(feature = "mmap")]
+            PackData::Mmap(map) => Ok(map.len() as u64),
+        }
+    }
+
+    fn reader_at(&self, offset: u64) -> Result<Box<dyn BufRead + '_>> {
+        match self {
+            PackData::File(f) => {
+                let mut f = f;
+                f.seek(SeekFrom::Start(offset))
+                    .context("seek to pack entry")?;
+                Ok(Box::new(BufReader::new(f)))
+            }
+            #[cfg(feature = "mmap")]
+            PackData::Mmap(map) => Ok(Box::new(
+                map.get(offset as usize..)
+                    .context("pack entry offset out of range")?,
+            )),
+        }
+    }
+}
+
+/// A `.pack` file together with its `.idx`.
+pub(crate) struct Pack {
+    index: PackIndex,
+    data: PackData,
+    /// The `.idx` file the pack was opened through.
+    idx_path: PathBuf,
+    /// The hash function that names the objects, and delta bases by id.
+    algo: HashAlgo,
+    /// The size of the `.pack` file, the most space reserved up front for an entry's data, as
+    /// the size in its header can't be trusted that far.
+    len: u64,
+}
+
+impl Pack {
+    /// Open the pack of `algo` objects belonging to the index file at `idx_path`.
+    pub(crate) fn open(idx_path: &Path, algo: HashAlgo) -> Result<Self> {
+        let idx = fs::read(idx_path).with_context(|| format!("read {}", idx_path.display()))?;
+        let index = PackIndex::parse(&idx, algo)
+            .with_context(|| format!("parse {}", idx_path.display()))?;
+        let data = PackData::open(&idx_path.with_extension("pack"))?;
+        let len = data.len()?;
+
+        let mut header = [0; 12];
+        data.reader_at(0)?
+            .read_exact(&mut header)
+            .context("read pack header")?;
+        if &header[0..4] != PACK_MAGIC {
+            bail!(
+                "{} is not a pack file",
+                idx_path.with_extension("pack").display()
+            );
+        }
+        Ok(Self {
+            index,
+            data,
+            idx_path: idx_path.to_path_buf(),
+            algo,
+            len,
+        })
+    }
+
+    /// Read and fully resolve the object `hash`, if it lives in this pack.
+    pub(crate) fn read(&self, hash: &ObjectId) -> Result<Option<(Kind, Vec<u8>)>> {
+        match self.index.find(hash) {
+            Some(offset) => self.read_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the object at `offset`, following its chain of delta bases down to a whole object
+    /// and applying the deltas back up. A chain longer than [`MAX_DELTA_DEPTH`] is refused, which
+    /// also ends one that loops back on itself.
+    fn read_at(&self, offset: u64) -> Result<(Kind, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let mut entry_offset = offset;
+        let (kind, mut data) = loop {
+            // the base is only looked at after inflating, since that moves the shared cursor
+            let mut reader = self.data.reader_at(entry_offset)?;
+            let (kind, size, base, _) = read_entry_header(&mut reader, entry_offset, self.algo)?;
+            let mut data = Vec::with_capacity(size.min(self.len) as usize);
+            ZlibDecoder::new(reader)
+                .read_to_end(&mut data)
+                .context("inflate pack entry")?;
+            if data.len() as u64 != size {
+                bail!("pack entry at offset {entry_offset} has bad length");
+            }
+            entry_offset = match base {
+                None => break (entry_kind(kind, entry_offset)?, data),
+                Some(DeltaBase::Offset(base_offset)) => base_offset,
+                Some(DeltaBase::Id(base_id)) => self
+                    .index
+                    .find(&base_id)
+                    .with_context(|| format!("delta base {base_id} missing"))?,
+            };
+            deltas.push(data);
+            if deltas.len() > MAX_DELTA_DEPTH {
+                bail!("delta chain of the pack entry at offset {offset} is too deep");
+            }
+        };
+        while let Some(delta) = deltas.pop() {
+            data = apply_delta(&data, &delta)?;
+        }
+        Ok((kind, data))
+    }
+}
+
+/// The longest chain of deltas followed to read an object, as in git.
+const MAX_DELTA_DEPTH: usize = 10000;
+
+/// Where the base of a delta entry is.
+enum DeltaBase {
+    /// At this offset in the same pack (OFS_DELTA).
+    Offset(u64),
+    /// The object with this id (REF_DELTA).
+    Id(ObjectId),
+}
+
+/// Read the header of the pack entry at `offset`: its type, the size of its data, where its
+/// base is if it is a delta, and how many bytes the header took. A size or base offset that
+/// doesn't fit in 64 bits is a bad header rather than one that wraps, and a base has to come
+/// before its delta.
+fn read_entry_header(
+    reader: &mut impl Read,
+    offset: u64,
+    algo: HashAlgo,
+) -> Result<(u8, u64, Option<DeltaBase>, u64)> {
+    let bad = || format!("bad pack header at offset {offset}");
+
+    // 3 bits of type and a little-endian varint size
+    let mut len = 1;
+    let mut byte = read_byte(reader)?;
+    let kind = (byte >> 4) & 0b111;
+    let mut size = (byte & 0x0f) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+        len += 1;
+        let bits = (byte & 0x7f) as u64;
+        size |= bits
+            .checked_shl(shift)
+            .filter(|shifted| shifted >> shift == bits)
+            .with_context(bad)?;
+        shift += 7;
+    }
+
+    let base = match kind {
+        6 => {
+            // OFS_DELTA: a big-endian offset varint, back from this entry
+            let mut byte = read_byte(reader)?;
+            len += 1;
+            let mut rel = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = read_byte(reader)?;
+                len += 1;
+                rel = rel
+                    .checked_add(1)
+                    .and_then(|rel| rel.checked_mul(0x80))
+                    .with_context(bad)?
+                    | (byte & 0x7f) as u64;
+            }
+            let base_offset = offset
+                .checked_sub(rel)
+                .filter(|_| rel > 0)
+                .context("pack delta base offset out of range")?;
+            Some(DeltaBase::Offset(base_offset))
+        }
+        7 => {
+            let mut base_id = vec![0; algo.raw_len()];
+            reader
+                .read_exact(&mut base_id)
+                .context("read delta base hash")?;
+            len += base_id.len() as u64;
+            Some(DeltaBase::Id(ObjectId::from_bytes(&base_id)?))
+        }
+        _ => None,
+    };
+    Ok((kind, size, base, len))
+}
+
+/// The kind of a whole (not deltified) pack entry of type `kind`.
+fn entry_kind(kind: u8, offset: u64) -> Result<Kind> {
+    Ok(match kind {
+        1 => Kind::Commit,
+        2 => Kind::Tree,
+        3 => Kind::Blob,
+        4 => Kind::Tag,
+        _ => bail!("unknown pack entry type {kind} at offset {offset}"),
+    })
+}
+
+fn read_byte(reader: &mut impl Read) -> Result<u8> {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .context("read pack entry header")?;
+    Ok(byte[0])
+}
+
+/// Apply a git delta to `base`.
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    fn varint(delta: &[u8], pos: &mut usize) -> Result<usize> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*pos).context("truncated delta header")?;
+            *pos += 1;
+            let bits = (byte & 0x7f) as usize;
+            value |= bits
+                .checked_shl(shift)
+                .filter(|shifted| shifted >> shift == bits)
+                .context("bad delta header")?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    let mut pos = 0;
+    let base_size = varint(delta, &mut pos)?;
+    if base_size != base.len() {
+        bail!("delta base size mismatch");
+    }
+    let result_size = varint(delta, &mut pos)?;
+
+    // the header's size is only believed as far as the inputs could make it without repeats
+    let mut result = Vec::with_capacity(result_size.min(base.len() + delta.len()));
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            // copy from base: the low bits select which offset/size bytes follow
+            let mut offset = 0;
+            let mut size = 0;
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    offset |= (*delta.get(pos).context("truncated delta")? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    size |= (*delta.get(pos).context("truncated delta")? as usize) << (i * 8);
+                    pos += 1;
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let chunk = base
+                .get(offset..offset + size)
+                .context("delta copy out of range")?;
+            result.extend_from_slice(chunk);
+        } else if op != 0 {
+            // insert the next `op` bytes verbatim
+            let chunk = delta
+                .get(pos..pos + op as usize)
+                .context("truncated delta insert")?;
+            result.extend_from_slice(chunk);
+            pos += op as usize;
+        } else {
+            bail!("invalid delta opcode 0");
+        }
+    }
+    if result.len() != result_size {
+        bail!("delta result size mismatch");
+    }
+    Ok(result)
+}
+
+/// Blocks of the base this long are indexed to find copies for `create_delta`.
+const DELTA_BLOCK: usize = 16;
+
+/// Make a git delta that turns `base` into `target`. Matches are found by looking up each
+/// block-sized window of the target among the aligned blocks of the base, then extended as far
+/// as they go; everything else is inserted.
+pub(crate) fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    fn varint(out: &mut Vec<u8>, mut value: usize) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+    fn insert(out: &mut Vec<u8>, data: &[u8]) {
+        for chunk in data.chunks(0x7f) {
+            out.push(chunk.len() as u8);
+            out.extend_from_slice(chunk);
+        }
+    }
+
+    let mut blocks = HashMap::new();
+    for (n, block) in base.chunks_exact(DELTA_BLOCK).enumerate() {
+        blocks.entry(block).or_insert(n * DELTA_BLOCK);
+    }
+
+    let mut out = Vec::new();
+    varint(&mut out, base.len());
+    varint(&mut out, target.len());
+    // start of the target bytes not yet covered by an instruction
+    let mut pending = 0;
+    let mut pos = 0;
+    while pos + DELTA_BLOCK <= target.len() {
+        let Some(&start) = blocks.get(&target[pos..pos + DELTA_BLOCK]) else {
+            pos += 1;
+            continue;
+        };
+        let len = base[start..]
+            .iter()
+            .zip(&target[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        insert(&mut out, &target[pending..pos]);
+        // copies are limited to 64KiB, the most older readers accept
+        for offset in (start..start + len).step_by(0x10000) {
+            let size = (start + len - offset).min(0x10000);
+            let mut op = 0x80;
+            let mut args = Vec::new();
+            for i in 0..4 {
+                let byte = (offset >> (i * 8)) as u8;
+                if byte != 0 {
+                    op |= 1 << i;
+                    args.push(byte);
+                }
+            }
+            for i in 0..3 {
+                let byte = (size >> (i * 8)) as u8;
+                if byte != 0 {
+                    op |= 0x10 << i;
+                    args.push(byte);
+                }
+            }
+            out.push(op);
+            out.extend_from_slice(&args);
+        }
+        pos += len;
+        pending = pos;
+    }
+    insert(&mut out, &target[pending..]);
+    out
+}
+
+/// Where the objects of a pack [`write_pack`] wrote start, with the CRC-32 of each one's bytes,
+/// in the order they were given, and the pack's checksum: what its index records.
+pub(crate) struct PackLayout {
+    entries: Vec<(u64, u32)>,
+    checksum: ObjectId,
+}
+
+/// Write a version 2 pack holding the objects `hashes` of `git_repo`, each stored whole and
+/// checksummed with the repository's hash function.
+pub(crate) fn write_pack(
+    out: &mut impl Write,
+    git_repo: &GitRepository,
+    hashes: &[String],
+) -> Result<PackLayout> {
+    let mut hasher = git_repo.hash_algo().hasher();
+    let mut emit = |out: &mut dyn Write, data: &[u8]| -> Result<()> {
+        hasher.update(data);
+        Ok(out.write_all(data)?)
+    };
+    let count = u32::try_from(hashes.len()).context("too many objects for one pack")?;
+    emit(out, PACK_MAGIC)?;
+    emit(out, &2u32.to_be_bytes())?;
+    emit(out, &count.to_be_bytes())?;
+    let mut offset = 12;
+    let mut entries = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        interrupt::check()?;
+        let obj = object_read(git_repo, hash)?;
+        let kind: u8 = match obj.format() {
+            "commit" => 1,
+            "tree" => 2,
+            "blob" => 3,
+            "tag" => 4,
+            other => bail!("object {hash} has unknown type {other}"),
+        };
+        let data = obj.serialize();
+        // type and size: 3 bits of type and 4 of size, then 7 bits of size per byte
+        let mut header = Vec::new();
+        let mut byte = (kind << 4) | (data.len() & 0x0f) as u8;
+        let mut size = data.len() >> 4;
+        while size > 0 {
+            header.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        header.push(byte);
+        let mut encoder = ZlibEncoder::new(header, Compression::default());
+        encoder.write_all(&data)?;
+        let entry = encoder.finish()?;
+        let mut crc = Crc::new();
+        crc.update(&entry);
+        entries.push((offset, crc.sum()));
+        offset += entry.len() as u64;
+        emit(out, &entry)?;
+    }
+    let checksum = hasher.finalize();
+    out.write_all(checksum.as_bytes())?;
+    Ok(PackLayout { entries, checksum })
+}
+
+/// Write the version 2 index of a pack of the `algo` objects `hashes` laid out as `layout` says.
+fn write_pack_index(
+    out: &mut impl Write,
+    algo: HashAlgo,
+    hashes: &[String],
+    layout: &PackLayout,
+) -> Result<()> {
+    let mut objects = hashes
+        .iter()
+        .zip(&layout.entries)
+        .map(|(hash, &entry)| Ok((hex::decode(hash)?, entry)))
+        .collect::<Result<Vec<_>>>()?;
+    objects.sort();
+
+    let mut idx = IDX_MAGIC.to_vec();
+    idx.extend_from_slice(&2u32.to_be_bytes());
+    for first in 0..=255u8 {
+        let count = objects.partition_point(|(hash, _)| hash[0] <= first) as u32;
+        idx.extend_from_slice(&count.to_be_bytes());
+    }
+    for (hash, _) in &objects {
+        idx.extend_from_slice(hash);
+    }
+    for (_, (_, crc)) in &objects {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    // offsets that don't fit in 31 bits go in a table of 8-byte ones after the others
+    let mut large = Vec::new();
+    for (_, (offset, _)) in &objects {
+        match u32::try_from(*offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => idx.extend_from_slice(&offset.to_be_bytes()),
+            _ => {
+                let at = 0x8000_0000 | (large.len() / 8) as u32;
+                idx.extend_from_slice(&at.to_be_bytes());
+                large.extend_from_slice(&offset.to_be_bytes());
+            }
+        }
+    }
+    idx.extend_from_slice(&large);
+    idx.extend_from_slice(layout.checksum.as_bytes());
+    let checksum = algo.digest(&idx);
+    idx.extend_from_slice(checksum.as_bytes());
+    Ok(out.write_all(&idx)?)
+}
+
+/// Store the objects `hashes` of `git_repo` as a new pack in `objects/pack`, returning its name
+/// (`pack-<checksum>`). The index is put in place last, as packs are found through it.
+pub(crate) fn write_pack_files(git_repo: &GitRepository, hashes: &[String]) -> Result<String> {
+    let dir = repo_path(git_repo, &["objects", "pack"])?;
+    fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let tmp_pack = TempPath::new(dir.join(format!("tmp_pack_{}", std::process::id())));
+    let tmp_idx = TempPath::new(dir.join(format!("tmp_idx_{}", std::process::id())));
+
+    let mut pack = std::io::BufWriter::new(
+        fs::File::create(tmp_pack.path())
+            .with_context(|| format!("create {}", tmp_pack.path().display()))?,
+    );
+    let layout = write_pack(&mut pack, git_repo, hashes)?;
+    pack.into_inner()
+        .map_err(|e| e.into_error())?
+        .sync_all()
+        .context("sync the new pack")?;
+    let mut idx = fs::File::create(tmp_idx.path())
+        .with_context(|| format!("create {}", tmp_idx.path().display()))?;
+    write_pack_index(&mut idx, git_repo.hash_algo(), hashes, &layout)?;
+    idx.sync_all().context("sync the new pack index")?;
+
+    let name = format!("pack-{}", layout.checksum);
+    let path = dir.join(&name);
+    tmp_pack
+        .persist(path.with_extension("pack"))
+        .with_context(|| format!("write {name}.pack"))?;
+    tmp_idx
+        .persist(path.with_extension("idx"))
+        .with_context(|| format!("write {name}.idx"))?;
+    Ok(name)
+}
+
+/// Hashes everything consumed from the wrapped reader, for checking a pack's trailing checksum.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: BufRead> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for HashingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the consumed bytes are still buffered, so this doesn't read
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hasher.update(&buf[..amt.min(buf.len())]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Read a whole pack from `reader`, as sent by `git push`, check its trailing checksum, and
+/// return its objects with their deltas resolved. Only the pack is consumed from `reader`.
+///
+/// The pack's objects are named with `algo`, which also made its checksum. Thin packs have
+/// deltas against objects the receiver already has; `external` looks those up.
+pub(crate) fn read_pack_stream(
+    reader: &mut impl BufRead,
+    algo: HashAlgo,
+    external: impl Fn(&str) -> Result<Option<(Kind, Vec<u8>)>>,
+) -> Result<Vec<(Kind, Vec<u8>)>> {
+    enum Entry {
+        Whole(Kind, Vec<u8>),
+        OffsetDelta(u64, Vec<u8>),
+        RefDelta(String, Vec<u8>),
+    }
+
+    let mut reader = HashingReader {
+        inner: reader,
+        hasher: algo.hasher(),
+    };
+    let mut header = [0; 12];
+    reader.read_exact(&mut header).context("read pack header")?;
+    if &header[0..4] != PACK_MAGIC {
+        bail!("protocol error: expected a pack");
+    }
+    let version = u32::from_be_bytes(header[4..8].try_into()?);
+    if version != 2 && version != 3 {
+        bail!("unsupported pack version {version}");
+    }
+    let count = u32::from_be_bytes(header[8..12].try_into()?);
+
+    let mut offset: u64 = 12;
+    // the count and sizes are the sender's word, so nothing is reserved for them up front
+    let mut offsets = Vec::new();
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let start = offset;
+        let (kind, size, base, header_len) = read_entry_header(&mut reader, start, algo)?;
+
+        // inflate exactly this entry's stream, leaving the rest of the pack unread
+        let mut decoder = ZlibDecoder::new(&mut reader);
+        let mut data = Vec::new();
+        decoder
+            .read_to_end(&mut data)
+            .context("inflate pack entry")?;
+        let compressed = decoder.total_in();
+        if data.len() as u64 != size {
+            bail!("pack entry at offset {start} has bad length");
+        }
+        offsets.push(start);
+        offset = start + header_len + compressed;
+
+        entries.push(match base {
+            Some(DeltaBase::Offset(base_offset)) => Entry::OffsetDelta(base_offset, data),
+            Some(DeltaBase::Id(base_id)) => Entry::RefDelta(base_id.to_string(), data),
+            None => Entry::Whole(entry_kind(kind, start)?, data),
+        });
+    }
+    let hash = reader.hasher.finalize();
+    let mut trailer = vec![0; algo.raw_len()];
+    reader
+        .inner
+        .read_exact(&mut trailer)
+        .context("read pack checksum")?;
+    if hash.as_bytes() != trailer {
+        bail!("pack checksum mismatch");
+    }
+
+    // resolve deltas in passes, as a base may come after the deltas against it
+    let mut resolved: Vec<Option<(Kind, Vec<u8>)>> = entries.iter().map(|_| None).collect();
+    let mut by_hash: HashMap<String, usize> = HashMap::new();
+    let mut remaining = entries.len();
+    while remaining > 0 {
+        let before = remaining;
+        for (i, entry) in entries.iter().enumerate() {
+            if resolved[i].is_some() {
+                continue;
+            }
+            let object: Option<(Kind, Vec<u8>)> = match entry {
+                Entry::Whole(kind, data) => Some((*kind, data.to_vec())),
+                Entry::OffsetDelta(base_offset, delta) => {
+                    let base = offsets
+                        .binary_search(base_offset)
+                        .ok()
+                        .with_context(|| format!("no pack entry at offset {base_offset}"))?;
+                    match &resolved[base] {
+                        Some((kind, base)) => Some((*kind, apply_delta(base, delta)?)),
+                        None => None,
+                    }
+                }
+                Entry::RefDelta(base_hash, delta) => {
+                    match by_hash
+                        .get(base_hash)
+                        .and_then(|&base| resolved[base].as_ref())
+                    {
+                        Some((kind, base)) => Some((*kind, apply_delta(base, delta)?)),
+                        None => match external(base_hash)? {
+                            Some((kind, base)) => Some((kind, apply_delta(&base, delta)?)),
+                            None => None,
+                        },
+                    }
+                }
+            };
+            if let Some((kind, data)) = object {
+                by_hash.insert(hash_object(algo, kind, &data), i);
+                resolved[i] = Some((kind, data));
+                remaining -= 1;
+            }
+        }
+        if remaining == before {
+            bail!("pack has {remaining} unresolved deltas");
+        }
+    }
+    Ok(resolved.into_iter().flatten().collect())
+}
+
+/// Open every pack of `algo` objects under `objects_dir/pack`, reusing those of `known` that are
+/// still there.
+fn open_packs(objects_dir: &Path, algo: HashAlgo, known: Vec<Pack>) -> Result<Vec<Pack>> {
+    let pack_dir = objects_dir.join("pack");
+    if !pack_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut idx_paths = fs::read_dir(&pack_dir)
+        .with_context(|| format!("open directory {}", pack_dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    idx_paths.retain(|p| p.extension().is_some_and(|ext| ext == "idx"));
+    idx_paths.sort();
+    let mut known = known
+        .into_iter()
+        .map(|pack| (pack.idx_path.clone(), pack))
+        .collect::<HashMap<_, _>>();
+    idx_paths
+        .iter()
+        .map(|p| known.remove(p).map_or_else(|| Pack::open(p, algo), Ok))
+        .collect()
+}
+
+/// Run `f` on the packs of `objects_dir`, whose objects are named with `algo`.
+///
+/// Packs are opened once per objects directory and kept for the lifetime of the process, so
+/// repeated lookups only pay for the index search and the entry itself. With `rescan`, the pack
+/// directory is listed again first, as git's `reprepare_packed_git` does after a lookup misses,
+/// to pick up packs another process wrote since.
+fn with_packs<T>(
+    objects_dir: &Path,
+    algo: HashAlgo,
+    rescan: bool,
+    f: impl FnOnce(&[Pack]) -> Result<T>,
+) -> Result<T> {
+    static PACKS: OnceLock<Mutex<HashMap<PathBuf, Vec<Pack>>>> = OnceLock::new();
+
+    let mut packs = PACKS
+        .get_or_init(Default::default)
+        .lock()
+        .expect("pack cache lock poisoned");
+    let packs = match packs.entry(objects_dir.to_path_buf()) {
+        std::collections::hash_map::Entry::Occupied(e) if rescan => {
+            let packs = e.into_mut();
+            *packs = open_packs(objects_dir, algo, std::mem::take(packs))?;
+            packs
+        }
+        std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+        std::collections::hash_map::Entry::Vacant(e) => {
+            e.insert(open_packs(objects_dir, algo, Vec::new())?)
+        }
+    };
+    f(packs)
+}
+
+/// Look `hash` up in the packs of `objects_dir`, whose objects are named with `algo`.
+pub(crate) fn read_packed(
+    objects_dir: &Path,
+    algo: HashAlgo,
+    hash: &str,
+) -> Result<Option<(Kind, Vec<u8>)>> {
+    if !algo.is_hex_id(hash) {
+        bail!("invalid object hash {hash}");
+    }
+    let hash = ObjectId::from_hex(hash)?;
+    let find = |packs: &[Pack]| {
+        for pack in packs {
+            if let Some(object) = pack.read(&hash)? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    };
+    match with_packs(objects_dir, algo, false, find)? {
+        Some(object) => Ok(Some(object)),
+        None => with_packs(objects_dir, algo, true, find),
+    }
+}
+
+/// Whether `hash` is in one of the packs of `objects_dir`, found through the pack indexes alone.
+pub(crate) fn packed_contains(objects_dir: &Path, algo: HashAlgo, hash: &str) -> Result<bool> {
+    if !algo.is_hex_id(hash) {
+        return Ok(false);
+    }
+    let hash = ObjectId::from_hex(hash)?;
+    let find = |packs: &[Pack]| Ok(packs.iter().any(|pack| pack.index.find(&hash).is_some()));
+    Ok(with_packs(objects_dir, algo, false, find)? || with_packs(objects_dir, algo, true, find)?)
+}
+
+/// The hashes of packed objects that start with the hex `prefix`.
+pub(crate) fn packed_with_prefix(
+    objects_dir: &Path,
+    algo: HashAlgo,
+    prefix: &str,
+) -> Result<Vec<String>> {
+    let find = |packs: &[Pack]| {
+        let mut found = Vec::new();
+        for pack in packs {
+            found.extend(
+                pack.index
+                    .hashes()
+                    .iter()
+                    .map(ObjectId::to_string)
+                    .filter(|h| h.starts_with(prefix)),
+            );
+        }
+        Ok(found)
+    };
+    match with_packs(objects_dir, algo, false, find)? {
+        found if found.is_empty() => with_packs(objects_dir, algo, true, find),
+        found => Ok(found),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A pack that git wrote.
+    struct GitPack {
+        /// Where the pack is, removed when the pack is dropped.
+        _dir: TempDir,
+        idx: PathBuf,
+        blobs: Vec<(ObjectId, Vec<u8>)>,
+    }
+
+    /// A pack of 100 similar blobs, so that most are deltas.
+    fn git_pack() -> GitPack {
+        let dir = TempDir::new();
+        dir.git(&["init", "--quiet", "."], b"");
+        let blobs = (0..100)
+            .map(|i| format!("{}blob {i}\n", "a shared line\n".repeat(50)).into_bytes())
+            .collect::<Vec<_>>();
+        let paths = (0..blobs.len())
+            .map(|i| {
+                let path = format!("f{i}");
+                fs::write(dir.path().join(&path), &blobs[i]).unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+        let mut args = vec!["hash-object", "-w"];
+        args.extend(paths.iter().map(String::as_str));
+        let hashes = dir.git(&args, b"");
+        let pack = dir.git(&["pack-objects", "pack"], hashes.as_bytes());
+
+        let blobs = hashes
+            .lines()
+            .map(|hash| ObjectId::from_hex(hash).unwrap())
+            .zip(blobs)
+            .collect();
+        let idx = dir.path().join(format!("pack-{}.idx", pack.trim()));
+        GitPack {
+            _dir: dir,
+            idx,
+            blobs,
+        }
+    }
+
+    #[test]
+    fn reads_every_object_of_a_git_pack() {
+        let git_pack = git_pack();
+        let pack = Pack::open(&git_pack.idx, HashAlgo::Sha1).unwrap();
+        for (hash, data) in &git_pack.blobs {
+            assert_eq!(pack.read(hash).unwrap(), Some((Kind::Blob, data.clone())));
+        }
+        assert_eq!(pack.read(&HashAlgo::Sha1.null()).unwrap(), None);
+    }
+
+    /// Write a pack of the raw `entries`, each indexed under its id, to `dir`, and open it.
+    fn handmade_pack(dir: &TempDir, entries: &[(&ObjectId, Vec<u8>)]) -> Pack {
+        let mut pack = PACK_MAGIC.to_vec();
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        let mut layout = Vec::new();
+        for (_, entry) in entries {
+            layout.push((pack.len() as u64, 0));
+            pack.extend_from_slice(entry);
+        }
+        let checksum = HashAlgo::Sha1.digest(&pack);
+        pack.extend_from_slice(checksum.as_bytes());
+        let idx = dir.path().join("pack-handmade.idx");
+        fs::write(idx.with_extension("pack"), pack).unwrap();
+        let hashes = entries
+            .iter()
+            .map(|(id, _)| id.to_string())
+            .collect::<Vec<_>>();
+        let layout = PackLayout {
+            entries: layout,
+            checksum,
+        };
+        let mut out = Vec::new();
+        write_pack_index(&mut out, HashAlgo::Sha1, &hashes, &layout).unwrap();
+        fs::write(&idx, out).unwrap();
+        Pack::open(&idx, HashAlgo::Sha1).unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn rejects_deltas_that_are_their_own_base() {
+        let dir = TempDir::new();
+        let id = HashAlgo::Sha1.digest(b"itself");
+        // an empty delta against an empty base
+        let delta = deflate(&[0, 0]);
+
+        // a REF_DELTA naming its own id
+        let mut entry = vec![0x72];
+        entry.extend_from_slice(id.as_bytes());
+        entry.extend_from_slice(&delta);
+        let error = handmade_pack(&dir, &[(&id, entry)]).read(&id).unwrap_err();
+        assert!(error.to_string().contains("too deep"), "{error}");
+
+        // an OFS_DELTA zero bytes back
+        let mut entry = vec![0x62, 0x00];
+        entry.extend_from_slice(&delta);
+        let error = handmade_pack(&dir, &[(&id, entry)]).read(&id).unwrap_err();
+        assert!(error.to_string().contains("out of range"), "{error}");
+    }
+
+    #[test]
+    fn rejects_sizes_too_large_for_64_bits() {
+        let dir = TempDir::new();
+        let id = HashAlgo::Sha1.digest(b"huge");
+        let mut entry = vec![0xb0];
+        entry.extend_from_slice(&[0xff; 11]);
+        entry.push(0x01);
+        entry.extend_from_slice(&deflate(b""));
+        let error = handmade_pack(&dir, &[(&id, entry)]).read(&id).unwrap_err();
+        assert!(error.to_string().contains("bad pack header"), "{error}");
+    }
+
+    #[test]
+    fn rejects_pack_streams_with_bad_headers() {
+        let read = |entries: &[u8]| {
+            let mut pack = PACK_MAGIC.to_vec();
+            pack.extend_from_slice(&2u32.to_be_bytes());
+            pack.extend_from_slice(&u32::MAX.to_be_bytes());
+            pack.extend_from_slice(entries);
+            read_pack_stream(&mut &pack[..], HashAlgo::Sha1, |_| Ok(None))
+                .unwrap_err()
+                .to_string()
+        };
+        // a count of 2^32 - 1 objects, none of them sent
+        let error = read(b"");
+        assert!(error.contains("read pack entry header"), "{error}");
+        // a size with twelve continuation bytes
+        let error = read(&[0xb0; 13]);
+        assert!(error.contains("bad pack header"), "{error}");
+        // a base offset with twelve continuation bytes
+        let mut entry = vec![0x60];
+        entry.extend_from_slice(&[0xff; 12]);
+        entry.push(0x01);
+        let error = read(&entry);
+        assert!(error.contains("bad pack header"), "{error}");
+    }
+
+    #[test]
+    fn rejects_an_index_whose_fanout_decreases() {
+        let git_pack = git_pack();
+        let mut idx = fs::read(&git_pack.idx).unwrap();
+        // the count of hashes starting with 0x00 becomes larger than that of 0x00 and 0x01
+        let at = 8;
+        idx[at..at + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let error = PackIndex::parse(&idx, HashAlgo::Sha1).err().unwrap();
+        assert!(error.to_string().contains("fanout"), "{error}");
+    }
+
+    #[test]
+    fn finds_objects_packed_after_the_packs_were_first_read() {
+        let dir = TempDir::new();
+        dir.git(&["init", "--quiet", "."], b"");
+        let objects = dir.path().join(".git/objects");
+        let pack = |hashes: &str| {
+            let name = dir.git(
+                &["pack-objects", "-q", ".git/objects/pack/pack"],
+                hashes.as_bytes(),
+            );
+            objects.join(format!("pack/pack-{}", name.trim()))
+        };
+        let first = dir.git(&["hash-object", "-w", "--stdin"], b"first\n");
+        let old = pack(&first);
+        assert!(read_packed(&objects, HashAlgo::Sha1, first.trim())
+            .unwrap()
+            .is_some());
+
+        // another process replaces the pack with one that also has a new object
+        let second = dir.git(&["hash-object", "-w", "--stdin"], b"second\n");
+        pack(&format!("{first}{second}"));
+        for ext in ["idx", "pack"] {
+            fs::remove_file(old.with_extension(ext)).unwrap();
+        }
+        assert_eq!(
+            read_packed(&objects, HashAlgo::Sha1, second.trim()).unwrap(),
+            Some((Kind::Blob, b"second\n".to_vec()))
+        );
+        assert!(packed_contains(&objects, HashAlgo::Sha1, first.trim()).unwrap());
+        assert_eq!(
+            packed_with_prefix(&objects, HashAlgo::Sha1, &second[..8]).unwrap(),
+            [second.trim()]
+        );
+    }
+
+    /// The number of read system calls this thread made so far.
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    fn read_syscalls() -> u64 {
+        let io = fs::read_to_string("/proc/thread-self/io").unwrap();
+        io.lines()
+            .find_map(|line| line.strip_prefix("syscr: "))
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[cfg(all(feature = "mmap", target_os = "linux"))]
+    #[test]
+    fn mapped_lookups_make_no_read_calls() {
+        let GitPack { idx, blobs, .. } = &git_pack();
+        let mapped = Pack::open(idx, HashAlgo::Sha1).unwrap();
+        assert!(matches!(mapped.data, PackData::Mmap(_)));
+        let file = Pack {
+            index: PackIndex::parse(&fs::read(idx).unwrap(), HashAlgo::Sha1).unwrap(),
+            data: PackData::File(fs::File::open(idx.with_extension("pack")).unwrap()),
+            idx_path: idx.clone(),
+            algo: HashAlgo::Sha1,
+            len: fs::metadata(idx.with_extension("pack")).unwrap().len(),
+        };
+
+        let lookups = |pack: &Pack| {
+            let before = read_syscalls();
+            for (hash, data) in blobs {
+                assert_eq!(pack.read(hash).unwrap(), Some((Kind::Blob, data.clone())));
+            }
+            read_syscalls() - before
+        };
+        // reading the counter makes read calls of its own
+        let counting = {
+            let before = read_syscalls();
+            read_syscalls() - before
+        };
+        let (through_file, through_map) = (lookups(&file), lookups(&mapped));
+        assert!(through_file >= blobs.len() as u64, "{through_file} reads");
+        assert_eq!(through_map, counting);
+    }
+}
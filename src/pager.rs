@@ -0,0 +1,68 @@
+use std::{
+    io::{BufWriter, ErrorKind, IsTerminal, Write},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{Context, Result};
+
+/// The pager program from `$GIT_PAGER` or `$PAGER`, `less -R` by default.
+pub(crate) fn pager_program() -> String {
+    std::env::var("GIT_PAGER")
+        .or_else(|_| std::env::var("PAGER"))
+        .unwrap_or_else(|_| "less -R".to_string())
+}
+
+/// The pager command, or `None` if paging is turned off by an empty value or `cat`.
+fn pager_command() -> Option<String> {
+    let command = pager_program();
+    let command = command.trim();
+    (!command.is_empty() && command != "cat").then(|| command.to_string())
+}
+
+fn spawn_pager(command: &str) -> Option<Child> {
+    let mut pager = Command::new("sh");
+    pager.arg("-c").arg(command).stdin(Stdio::piped());
+    // like git: quit if the output fits on one screen, keep colors, don't clear the screen
+    if std::env::var_os("LESS").is_none() {
+        pager.env("LESS", "FRX");
+    }
+    pager.spawn().ok()
+}
+
+/// Run `write` with the output of a command that may be long. When `enabled` and stdout is a
+/// terminal, the output goes through a pager; otherwise (or if the pager can't be started) it
+/// is written to stdout directly.
+pub(crate) fn paged(enabled: bool, write: impl FnOnce(&mut dyn Write) -> Result<()>) -> Result<()> {
+    let pager = pager_command()
+        .filter(|_| enabled && std::io::stdout().is_terminal())
+        .and_then(|command| spawn_pager(&command));
+    let Some(mut pager) = pager else {
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        let result = write(&mut stdout).and_then(|()| Ok(stdout.flush()?));
+        // the reader of a pipe, like `head`, stopped before the end
+        return ignore_broken_pipe(result);
+    };
+
+    let mut input = BufWriter::new(pager.stdin.take().expect("the pager's stdin is piped"));
+    let result = write(&mut input).and_then(|()| Ok(input.flush()?));
+    // closing its input lets the pager finish
+    drop(input);
+    pager.wait().context("wait for the pager")?;
+    // the pager was quit before it read everything
+    ignore_broken_pipe(result)
+}
+
+/// `result`, or success if it failed only because the reader of the output went away.
+fn ignore_broken_pipe(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e)
+            if e.chain()
+                .filter_map(|e| e.downcast_ref::<std::io::Error>())
+                .any(|e| e.kind() == ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
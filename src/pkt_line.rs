@@ -0,0 +1,194 @@
+use std::{
+    io::{Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::trace::{trace, TRACE_PACKET};
+
+/// The most data a pkt-line can carry: 65520 bytes, less the 4-byte length.
+const MAX_DATA: usize = 65516;
+
+/// Who is talking in packet traces, like `upload-pack`; `git` unless set.
+static TRACE_IDENTITY: OnceLock<&str> = OnceLock::new();
+/// Set once the pack data starts, which isn't traced.
+static TRACE_DONE: AtomicBool = AtomicBool::new(false);
+
+/// Name the side of the conversation this process is in packet traces.
+pub(crate) fn set_trace_identity(identity: &'static str) {
+    let _ = TRACE_IDENTITY.set(identity);
+}
+
+/// Trace a pkt-line the way git does: who sent it (`>`) or received it (`<`), then its data
+/// without newlines and with unprintable bytes in octal. The pack data that may follow is
+/// binary, so the trace ends with `PACK ...` where it starts.
+fn trace_packet(data: &[u8], write: bool) {
+    if !TRACE_PACKET.enabled() || TRACE_DONE.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut text = String::new();
+    if data.starts_with(b"PACK") || data.get(1..).is_some_and(|d| d.starts_with(b"PACK")) {
+        text.push_str("PACK ...");
+        TRACE_DONE.store(true, Ordering::Relaxed);
+    } else {
+        for &byte in data.iter().filter(|&&b| b != b'\n') {
+            match byte {
+                0x20..=0x7e => text.push(byte as char),
+                _ => text.push_str(&format!("\\{byte:o}")),
+            }
+        }
+    }
+    let identity = TRACE_IDENTITY.get().unwrap_or(&"git");
+    let direction = if write { '>' } else { '<' };
+    trace!(TRACE_PACKET, "{identity:>12}{direction} {text}");
+}
+
+/// Write `data` as one pkt-line: its length plus 4, in 4 hex digits, then the data.
+pub(crate) fn write_pkt(out: &mut impl Write, data: &[u8]) -> Result<()> {
+    if data.len() > MAX_DATA {
+        bail!("pkt-line of {} bytes is too long", data.len());
+    }
+    trace_packet(data, true);
+    write!(out, "{:04x}", data.len() + 4)?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Write a delim-pkt, which separates the sections of a protocol v2 message.
+pub(crate) fn write_delim(out: &mut impl Write) -> Result<()> {
+    trace_packet(b"0001", true);
+    out.write_all(b"0001")?;
+    Ok(())
+}
+
+/// Write a flush-pkt, which ends a section of the conversation.
+pub(crate) fn write_flush(out: &mut impl Write) -> Result<()> {
+    trace_packet(b"0000", true);
+    out.write_all(b"0000")?;
+    Ok(())
+}
+
+/// A packet as protocol v2 reads them: a pkt-line of data, or one of the special packets that
+/// end a message (flush-pkt), a section of one (delim-pkt) or a response (response-end-pkt).
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+/// Read one packet.
+pub(crate) fn read_packet(input: &mut impl Read) -> Result<Packet> {
+    let mut len = [0; 4];
+    input.read_exact(&mut len).context("read pkt-line length")?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .with_context(|| format!("bad pkt-line length {:?}", String::from_utf8_lossy(&len)))?;
+    match len {
+        0 => {
+            trace_packet(b"0000", false);
+            Ok(Packet::Flush)
+        }
+        1 => {
+            trace_packet(b"0001", false);
+            Ok(Packet::Delim)
+        }
+        2 => {
+            trace_packet(b"0002", false);
+            Ok(Packet::ResponseEnd)
+        }
+        3 => bail!("bad pkt-line length {len}"),
+        _ => {
+            let mut data = vec![0; len - 4];
+            input.read_exact(&mut data).context("read pkt-line")?;
+            trace_packet(&data, false);
+            Ok(Packet::Data(data))
+        }
+    }
+}
+
+/// Read one pkt-line, returning `None` for a flush-pkt.
+pub(crate) fn read_pkt(input: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    match read_packet(input)? {
+        Packet::Data(data) => Ok(Some(data)),
+        Packet::Flush => Ok(None),
+        Packet::Delim => bail!("protocol error: unexpected delim-pkt"),
+        Packet::ResponseEnd => bail!("protocol error: unexpected response-end-pkt"),
+    }
+}
+
+/// Read one pkt-line of text, without its trailing newline. `None` is a flush-pkt.
+pub(crate) fn read_pkt_text(input: &mut impl Read) -> Result<Option<String>> {
+    let Some(data) = read_pkt(input)? else {
+        return Ok(None);
+    };
+    let text = String::from_utf8(data).context("pkt-line isn't utf-8")?;
+    Ok(Some(text.strip_suffix('\n').unwrap_or(&text).to_string()))
+}
+
+/// Write a ref advertisement, the first thing a server sends: one pkt-line per ref, with the
+/// `capabilities` after the first one, then a flush-pkt.
+pub(crate) fn write_advertisement(
+    out: &mut impl Write,
+    refs: &[(String, String)],
+    capabilities: &str,
+) -> Result<()> {
+    match refs.split_first() {
+        Some(((name, hash), rest)) => {
+            write_pkt(out, format!("{hash} {name}\0{capabilities}\n").as_bytes())?;
+            for (name, hash) in rest {
+                write_pkt(out, format!("{hash} {name}\n").as_bytes())?;
+            }
+        }
+        // an empty repository still has to send its capabilities
+        None => write_pkt(
+            out,
+            format!("{} capabilities^{{}}\0{capabilities}\n", "0".repeat(40)).as_bytes(),
+        )?,
+    }
+    write_flush(out)?;
+    Ok(out.flush()?)
+}
+
+/// The bands of a side-band stream.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Band {
+    Data = 1,
+    Progress = 2,
+    Error = 3,
+}
+
+/// Writes to one band of a side-band stream, in pkt-lines of at most `max` bytes of data
+/// (999 for `side-band`, 65515 for `side-band-64k`).
+pub(crate) struct Sideband<W> {
+    out: W,
+    band: Band,
+    max: usize,
+}
+
+impl<W: Write> Sideband<W> {
+    pub(crate) fn new(out: W, band: Band, max: usize) -> Self {
+        Self { out, band, max }
+    }
+}
+
+impl<W: Write> Write for Sideband<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.max);
+        let mut pkt = Vec::with_capacity(len + 1);
+        pkt.push(self.band as u8);
+        pkt.extend_from_slice(&buf[..len]);
+        write_pkt(&mut self.out, &pkt).map_err(std::io::Error::other)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
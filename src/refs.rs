@@ -0,0 +1,584 @@
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    commands::commit_tree::identity,
+    lockfile::{write_locked, LockFile},
+    objects::object_exists,
+    repository::{is_common_path, repo_path, GitRepository, PER_WORKTREE_REFS},
+    trace::{trace, TRACE},
+};
+
+/// Resolve the ref `name` (e.g. `HEAD` or `refs/heads/master`) to an object hash.
+///
+/// Symbolic refs (`ref: <target>`) are followed, and refs missing from the ref directories are
+/// looked up in `packed-refs`. Returns `None` when the ref, or the ref it eventually points at,
+/// does not exist yet (as for `HEAD` in a fresh repository).
+pub(crate) fn ref_resolve(git_repo: &GitRepository, name: &str) -> Result<Option<String>> {
+    let mut name = name.to_string();
+    // bound the number of hops so a symref cycle can't loop forever
+    for _ in 0..10 {
+        let path = repo_path(git_repo, &[&name])?;
+        if !path.is_file() {
+            return Ok(packed_refs(git_repo)?.remove(&name));
+        }
+        let data =
+            fs::read_to_string(&path).with_context(|| format!("read ref {}", path.display()))?;
+        let data = data.trim();
+        match data.strip_prefix("ref: ") {
+            Some(target) => name = target.to_string(),
+            None => return Ok(Some(data.to_string())),
+        }
+    }
+    bail!("too many levels of symbolic refs resolving {name}");
+}
+
+/// Read `packed-refs`, mapping ref names to hashes. Peeled (`^<hash>`) lines are skipped.
+pub(crate) fn packed_refs(git_repo: &GitRepository) -> Result<BTreeMap<String, String>> {
+    let path = repo_path(git_repo, &["packed-refs"])?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+    let mut refs = BTreeMap::new();
+    for line in text.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let (hash, name) = line
+            .split_once(' ')
+            .with_context(|| format!("malformed packed-refs line {line:?}"))?;
+        refs.insert(name.to_string(), hash.to_string());
+    }
+    Ok(refs)
+}
+
+/// List every ref under `refs/`, loose or packed, with the hash it resolves to, sorted by name.
+/// Symbolic refs that don't resolve are left out.
+pub(crate) fn ref_list(git_repo: &GitRepository) -> Result<Vec<(String, String)>> {
+    let mut refs = packed_refs(git_repo)?;
+    let mut loose = Vec::new();
+    collect_loose_refs(&repo_path(git_repo, &["refs"])?, "refs", &mut loose)?;
+    // the shared `refs/` may hold the main worktree's own refs, which aren't this worktree's
+    loose.retain(|name| is_common_path(Path::new(name)));
+    for prefix in PER_WORKTREE_REFS {
+        collect_loose_refs(&repo_path(git_repo, &[prefix])?, prefix, &mut loose)?;
+    }
+    for name in loose {
+        // loose refs take precedence over packed ones
+        match ref_resolve(git_repo, &name)? {
+            Some(hash) => refs.insert(name, hash),
+            None => refs.remove(&name),
+        };
+    }
+    Ok(refs.into_iter().collect())
+}
+
+fn collect_loose_refs(dir: &Path, prefix: &str, out: &mut Vec<String>) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = format!("{prefix}/{}", entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_refs(&entry.path(), &name, out)?;
+        } else if check_ref_format(&name, true) {
+            // which leaves out the `.lock` files of refs being written
+            out.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// The ref that writing to `name` writes: `name` itself, or for a symbolic ref the ref it
+/// eventually points at.
+fn deref(git_repo: &GitRepository, name: &str) -> Result<String> {
+    let mut name = name.to_string();
+    let mut hops = 0;
+    loop {
+        let path = repo_path(git_repo, &[&name])?;
+        let Ok(data) = fs::read_to_string(&path) else {
+            return Ok(name);
+        };
+        let Some(target) = data.trim().strip_prefix("ref: ") else {
+            return Ok(name);
+        };
+        hops += 1;
+        if hops > 10 {
+            bail!("too many levels of symbolic refs updating {name}");
+        }
+        name = target.to_string();
+    }
+}
+
+/// Point the ref `name` at `hash` in a [`RefTransaction`] of its own, recording `message` in
+/// the reflogs. Symbolic refs are followed, so updating `HEAD` moves the current branch (or
+/// HEAD itself when it is detached). With `old`, the ref must still be at `old` (or not exist,
+/// for the null id), so that a change another process made in between isn't overwritten.
+pub(crate) fn ref_update(
+    git_repo: &GitRepository,
+    name: &str,
+    hash: &str,
+    old: Option<&str>,
+    message: &str,
+) -> Result<()> {
+    let mut transaction = RefTransaction::new(git_repo, message);
+    transaction.update(name, hash, old)?;
+    transaction.commit()
+}
+
+/// Delete the ref `name` in a [`RefTransaction`] of its own: its loose file, its `packed-refs`
+/// entry (with the peeled line after it) and its reflog. Deleting a ref that doesn't exist is
+/// not an error.
+pub(crate) fn ref_delete(git_repo: &GitRepository, name: &str) -> Result<()> {
+    let mut transaction = RefTransaction::new(git_repo, "");
+    transaction.delete(name, None)?;
+    transaction.commit()
+}
+
+/// The `packed-refs` file `text` without the refs `names` and their peeled lines.
+fn without_packed_refs(text: &str, names: &[&str]) -> String {
+    let mut kept = String::new();
+    let mut dropping = false;
+    for line in text.lines() {
+        if line.starts_with('^') && dropping {
+            continue;
+        }
+        dropping = line
+            .split_once(' ')
+            .is_some_and(|(_, n)| names.contains(&n));
+        if !dropping {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    kept
+}
+
+/// What a [`RefTransaction`] does to a ref.
+enum RefChange {
+    /// Point it at this hash.
+    Update(String),
+    Delete,
+    /// Only check its value.
+    Verify,
+}
+
+/// A ref change queued in a [`RefTransaction`].
+struct QueuedChange {
+    /// The ref changed, with symbolic refs followed.
+    name: String,
+    change: RefChange,
+    /// The hash the ref must be at, or the null id if it must not exist.
+    expected: Option<String>,
+    /// The lock on the ref while the transaction is prepared.
+    lock: Option<LockFile>,
+    /// What the ref was at when the transaction was prepared.
+    previous: Option<String>,
+}
+
+/// Changes to several refs that happen together: either all of them or, if any can't, none.
+///
+/// Changes are queued with [`update`](Self::update) and friends. [`prepare`](Self::prepare)
+/// locks every ref (in name order, so transactions can't deadlock each other), checks each is
+/// at the value expected of it and writes the new values to the locks. [`commit`](Self::commit)
+/// then moves them into place one after the other, putting back the refs already changed if one
+/// fails, and records the changes in the reflogs. Dropping a transaction instead releases its
+/// locks without changing anything.
+pub(crate) struct RefTransaction<'a> {
+    git_repo: &'a GitRepository,
+    /// The reason recorded in the reflogs.
+    message: String,
+    changes: Vec<QueuedChange>,
+    /// The lock on `packed-refs`, holding it without the refs being deleted, if any were packed.
+    packed: Option<LockFile>,
+    prepared: bool,
+}
+
+impl<'a> RefTransaction<'a> {
+    pub(crate) fn new(git_repo: &'a GitRepository, message: &str) -> Self {
+        Self {
+            git_repo,
+            message: message.to_string(),
+            changes: Vec::new(),
+            packed: None,
+            prepared: false,
+        }
+    }
+
+    /// Queue pointing `name` at `new`, or deleting it if `new` is the null id. With `old`, the
+    /// ref must be at `old` (or not exist, for the null id) when the transaction is prepared.
+    pub(crate) fn update(&mut self, name: &str, new: &str, old: Option<&str>) -> Result<()> {
+        let change = match self.git_repo.hash_algo().null().to_string() == new {
+            true => RefChange::Delete,
+            false => RefChange::Update(new.to_string()),
+        };
+        self.queue(name, change, old)
+    }
+
+    /// Queue creating `name` at `new`; the ref must not exist yet.
+    pub(crate) fn create(&mut self, name: &str, new: &str) -> Result<()> {
+        let null = self.git_repo.hash_algo().null().to_string();
+        self.queue(name, RefChange::Update(new.to_string()), Some(&null))
+    }
+
+    /// Queue deleting `name`, which must be at `old` if that is given.
+    pub(crate) fn delete(&mut self, name: &str, old: Option<&str>) -> Result<()> {
+        self.queue(name, RefChange::Delete, old)
+    }
+
+    /// Queue checking that `name` is at `old`, or doesn't exist for the null id.
+    pub(crate) fn verify(&mut self, name: &str, old: &str) -> Result<()> {
+        self.queue(name, RefChange::Verify, Some(old))
+    }
+
+    fn queue(&mut self, name: &str, change: RefChange, old: Option<&str>) -> Result<()> {
+        if self.prepared {
+            bail!("prepared transactions can only be closed");
+        }
+        if !check_ref_format(name, true) {
+            bail!("invalid ref format: {name}");
+        }
+        let name = deref(self.git_repo, name)?;
+        if self.changes.iter().any(|queued| queued.name == name) {
+            bail!("multiple updates for ref '{name}' not allowed");
+        }
+        self.changes.push(QueuedChange {
+            name,
+            change,
+            expected: old.map(str::to_string),
+            lock: None,
+            previous: None,
+        });
+        Ok(())
+    }
+
+    /// Lock every ref to be changed, check they are at the values expected and write their new
+    /// values to the locks. Nothing can be queued afterwards.
+    pub(crate) fn prepare(&mut self) -> Result<()> {
+        if self.prepared {
+            return Ok(());
+        }
+        let git_repo = self.git_repo;
+        let null = git_repo.hash_algo().null().to_string();
+        self.changes.sort_by(|a, b| a.name.cmp(&b.name));
+        for queued in &mut self.changes {
+            let name = &queued.name;
+            let path = repo_path(git_repo, &[name])?;
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)
+                    .with_context(|| format!("create {}", parent.display()))?;
+            }
+            let mut lock = LockFile::acquire(&path)
+                .map_err(|e| anyhow::anyhow!("cannot lock ref '{name}': {e}"))?;
+            let current = ref_resolve(git_repo, name)?;
+            match (&queued.expected, &current) {
+                (Some(expected), Some(_)) if *expected == null => {
+                    bail!("cannot lock ref '{name}': reference already exists")
+                }
+                (Some(expected), None) if *expected != null => {
+                    bail!("cannot lock ref '{name}': unable to resolve reference '{name}'")
+                }
+                (Some(expected), Some(current)) if expected != current => {
+                    bail!("cannot lock ref '{name}': is at {current} but expected {expected}")
+                }
+                _ => {}
+            }
+            if let RefChange::Update(hash) = &queued.change {
+                if !object_exists(git_repo, hash)? {
+                    bail!(
+                        "cannot update ref '{name}': trying to write ref '{name}' with nonexistent object {hash}"
+                    );
+                }
+                lock.write_all(format!("{hash}\n").as_bytes())
+                    .with_context(|| format!("write ref {name}"))?;
+            }
+            queued.lock = Some(lock);
+            queued.previous = current;
+        }
+
+        let deleted = self
+            .changes
+            .iter()
+            .filter(|queued| matches!(queued.change, RefChange::Delete))
+            .map(|queued| queued.name.as_str())
+            .collect::<Vec<_>>();
+        if !deleted.is_empty() {
+            // lock before reading, so no other writer's change lands between reading the file
+            // and replacing it
+            let path = repo_path(git_repo, &["packed-refs"])?;
+            let mut lock = LockFile::acquire(&path)?;
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+            };
+            let kept = without_packed_refs(&text, &deleted);
+            // unless a deleted ref was packed, the lock is dropped with the file untouched
+            if kept != text {
+                lock.write_all(kept.as_bytes())
+                    .with_context(|| format!("write {}", path.display()))?;
+                self.packed = Some(lock);
+            }
+        }
+        self.prepared = true;
+        Ok(())
+    }
+
+    /// Make the changes, preparing the transaction first if it isn't yet.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        self.prepare()?;
+        let git_repo = self.git_repo;
+        let null = git_repo.hash_algo().null().to_string();
+        let mut done = Vec::new();
+        let mut result = Ok(());
+        for (i, queued) in self.changes.iter_mut().enumerate() {
+            let lock = queued.lock.take().expect("prepared refs are locked");
+            let path = repo_path(git_repo, &[&queued.name])?;
+            result = match &queued.change {
+                RefChange::Update(hash) => {
+                    trace!(
+                        TRACE,
+                        "ref update: {} {} -> {hash}",
+                        queued.name,
+                        queued.previous.as_deref().unwrap_or(&null)
+                    );
+                    lock.commit()
+                }
+                RefChange::Delete => {
+                    trace!(TRACE, "ref delete: {}", queued.name);
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                            Err(e).with_context(|| format!("delete ref {}", queued.name))
+                        }
+                        _ => Ok(()),
+                    }
+                }
+                RefChange::Verify => Ok(()),
+            };
+            if result.is_err() {
+                break;
+            }
+            done.push(i);
+        }
+        if result.is_ok() {
+            if let Some(packed) = self.packed.take() {
+                result = packed.commit();
+            }
+        }
+        if let Err(e) = result {
+            self.rollback(&done);
+            return Err(e);
+        }
+        self.write_reflogs()
+    }
+
+    /// Put the refs of `changes` back to what they were before the transaction.
+    fn rollback(&self, changes: &[usize]) {
+        for &i in changes {
+            let queued = &self.changes[i];
+            let Ok(path) = repo_path(self.git_repo, &[&queued.name]) else {
+                continue;
+            };
+            let restored = match &queued.previous {
+                Some(hash) => write_ref_file(&path, &format!("{hash}\n")),
+                None => fs::remove_file(&path).map_err(Into::into),
+            };
+            if let Err(e) = restored {
+                eprintln!("error: cannot restore ref '{}': {e:#}", queued.name);
+            }
+        }
+    }
+
+    /// Record the changes in the reflogs of the refs changed, and of `HEAD` for its branch. The
+    /// reflogs of deleted refs go away with them.
+    fn write_reflogs(&self) -> Result<()> {
+        let git_repo = self.git_repo;
+        let null = git_repo.hash_algo().null().to_string();
+        let head_branch = match resolve_head(git_repo) {
+            Ok(Head::Branch(branch, _)) => Some(branch),
+            _ => None,
+        };
+        for queued in &self.changes {
+            let old = queued.previous.as_deref().unwrap_or(&null);
+            match &queued.change {
+                RefChange::Update(new) => {
+                    append_reflog(git_repo, &queued.name, old, new, &self.message)?;
+                    if head_branch.as_deref() == Some(&queued.name) {
+                        append_reflog(git_repo, "HEAD", old, new, &self.message)?;
+                    }
+                }
+                RefChange::Delete => {
+                    let path = repo_path(git_repo, &["logs", &queued.name])?;
+                    match fs::remove_file(&path) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(e).with_context(|| format!("remove {}", path.display()))
+                        }
+                        _ => {}
+                    }
+                }
+                RefChange::Verify => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Append to the reflog of `name` that it moved from `old` to `new` because of `message`. Refs
+/// that have no reflog yet get one if they are branches, remote-tracking refs, notes or `HEAD`
+/// and `core.logAllRefUpdates` is on (the default outside bare repositories), or any ref for
+/// `core.logAllRefUpdates=always`.
+fn append_reflog(
+    git_repo: &GitRepository,
+    name: &str,
+    old: &str,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    let path = repo_path(git_repo, &["logs", name])?;
+    let create = match git_repo.config_get("core", "logallrefupdates") {
+        Some(value) if value.eq_ignore_ascii_case("always") => true,
+        _ => {
+            git_repo
+                .config_bool("core", "logallrefupdates")
+                .unwrap_or(!git_repo.is_bare())
+                && (name == "HEAD"
+                    || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                        .iter()
+                        .any(|prefix| name.starts_with(prefix)))
+        }
+    };
+    if !(create || path.exists()) {
+        return Ok(());
+    }
+    let committer = identity(git_repo, "committer")?;
+    let mut line = format!("{old} {new} {committer}");
+    if !message.is_empty() {
+        line.push('\t');
+        line.push_str(&message.replace('\n', " "));
+    }
+    line.push('\n');
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("write {}", path.display()))
+}
+
+/// Replace the ref file at `path` under its `.lock` file, so readers never see it half written
+/// and concurrent writers don't interleave.
+fn write_ref_file(path: &Path, contents: &str) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+    }
+    write_locked(path, contents.as_bytes())
+}
+
+/// What `HEAD` points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Head {
+    /// On a branch, by full ref name, with the commit it is at (`None` before its first commit).
+    Branch(String, Option<String>),
+    /// Detached at a commit.
+    Detached(String),
+}
+
+impl Head {
+    /// The commit HEAD is at, if any.
+    pub(crate) fn commit(&self) -> Option<&str> {
+        match self {
+            Head::Branch(_, commit) => commit.as_deref(),
+            Head::Detached(commit) => Some(commit),
+        }
+    }
+
+    /// What reflog messages like `checkout: moving from <name> to ...` call it: the short name
+    /// of the branch, or the commit when detached.
+    pub(crate) fn reflog_name(&self) -> &str {
+        match self {
+            Head::Branch(branch, _) => branch.strip_prefix("refs/heads/").unwrap_or(branch),
+            Head::Detached(commit) => commit,
+        }
+    }
+}
+
+/// Read `HEAD`: either a symbolic ref to a branch, or a commit hash when detached.
+pub(crate) fn resolve_head(git_repo: &GitRepository) -> Result<Head> {
+    let path = repo_path(git_repo, &["HEAD"])?;
+    let data = fs::read_to_string(&path).with_context(|| format!("read {}", path.display()))?;
+    let data = data.trim();
+    match data.strip_prefix("ref: ") {
+        Some(branch) => Ok(Head::Branch(
+            branch.to_string(),
+            ref_resolve(git_repo, branch)?,
+        )),
+        None if git_repo.hash_algo().is_hex_id(data) => Ok(Head::Detached(data.to_string())),
+        None => bail!("HEAD is neither a symbolic ref nor a commit: {data}"),
+    }
+}
+
+/// Point `HEAD` at a branch (attaching it) or directly at a commit (detaching it). Only the
+/// name of a branch is written; its commit, if known, is what `HEAD`'s reflog records it moved
+/// to, with `message`.
+pub(crate) fn write_head(git_repo: &GitRepository, head: &Head, message: &str) -> Result<()> {
+    let old = resolve_head(git_repo)
+        .ok()
+        .and_then(|old| old.commit().map(str::to_string))
+        .unwrap_or_else(|| git_repo.hash_algo().null().to_string());
+    let contents = match head {
+        Head::Branch(branch, _) => format!("ref: {branch}\n"),
+        Head::Detached(commit) => format!("{commit}\n"),
+    };
+    trace!(TRACE, "ref update: HEAD {}", contents.trim_end());
+    write_ref_file(&repo_path(git_repo, &["HEAD"])?, &contents).context("update HEAD")?;
+    match head.commit() {
+        Some(new) => append_reflog(git_repo, "HEAD", &old, new, message),
+        None => Ok(()),
+    }
+}
+
+/// Whether `name` is a well-formed ref name by git's rules: its `/`-separated components are
+/// non-empty, don't start with `.` or end in `.lock`, and contain no `..`, no `@{`, no control
+/// characters and none of space, `~`, `^`, `:`, `?`, `*`, `[` or `\`. The whole name doesn't end
+/// in `.`, isn't `@`, and has at least two components unless `allow_onelevel`.
+pub(crate) fn check_ref_format(name: &str, allow_onelevel: bool) -> bool {
+    if name == "@" || name.ends_with('.') || name.contains("..") || name.contains("@{") {
+        return false;
+    }
+    let bad_char = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    if name.contains(bad_char) {
+        return false;
+    }
+    let components: Vec<&str> = name.split('/').collect();
+    let bad_component = |c: &&str| c.is_empty() || c.starts_with('.') || c.ends_with(".lock");
+    !components.iter().any(bad_component) && (allow_onelevel || components.len() > 1)
+}
+
+/// Whether `name` can name a branch: `refs/heads/<name>` must be a valid ref, and the name
+/// can't start with `-` or be `HEAD`.
+pub(crate) fn valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-')
+        && name != "HEAD"
+        && check_ref_format(&format!("refs/heads/{name}"), false)
+}
+
+/// `name` with leading `/`s removed and runs of `/` collapsed into one, as
+/// `check-ref-format --normalize` tidies a name before checking it.
+pub(crate) fn normalize_ref_name(name: &str) -> String {
+    let components: Vec<&str> = name.split('/').filter(|c| !c.is_empty()).collect();
+    let mut normalized = components.join("/");
+    if name.ends_with('/') && !normalized.is_empty() {
+        // keep the trailing slash, which still makes the name invalid
+        normalized.push('/');
+    }
+    normalized
+}
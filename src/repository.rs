@@ -1,17 +1,89 @@
 use anyhow::{bail, Context, Result};
 use ini::Ini;
 
+use crate::{
+    commands::commit_tree::{identity, write_commit_object},
+    error::GitError,
+    hash::HashAlgo,
+    lockfile::write_locked,
+    objects::{self, Commit, Kind, ObjectType},
+    refs,
+    revwalk::RevWalk,
+};
+
 use std::{
+    collections::HashSet,
     fs,
     io::Write,
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
+/// The `[extensions]` of a version 1 repository that we understand (lowercased):
+/// - `objectformat` names the hash function of the objects;
+/// - `worktreeconfig` lets each worktree have its own `config.worktree`;
+/// - `preciousobjects` forbids deleting objects, so `prune` and `repack -d` refuse to run.
+const KNOWN_EXTENSIONS: &[&str] = &["objectformat", "worktreeconfig", "preciousobjects"];
+
+/// The paths of a linked worktree's git directory that live in the common git directory shared
+/// by all worktrees (`true`), or that stay in the worktree's own one although under a shared
+/// path (`false`), as in git's `common_list`. The longest matching prefix decides; paths not
+/// listed, like `HEAD` and `index`, belong to each worktree.
+const COMMON_PATHS: &[(&str, bool)] = &[
+    ("branches", true),
+    ("common", true),
+    ("config", true),
+    ("hooks", true),
+    ("info", true),
+    ("info/sparse-checkout", false),
+    ("logs", true),
+    ("logs/HEAD", false),
+    ("logs/refs/bisect", false),
+    ("logs/refs/rewritten", false),
+    ("logs/refs/worktree", false),
+    ("lost-found", true),
+    ("objects", true),
+    ("packed-refs", true),
+    ("refs", true),
+    ("refs/bisect", false),
+    ("refs/rewritten", false),
+    ("refs/worktree", false),
+    ("remotes", true),
+    ("rr-cache", true),
+    ("shallow", true),
+    ("svn", true),
+    ("worktrees", true),
+];
+
+/// The ref namespaces each worktree has its own copy of.
+pub(crate) const PER_WORKTREE_REFS: &[&str] = &["refs/bisect", "refs/rewritten", "refs/worktree"];
+
+/// The config file a setting is read from or written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigScope {
+    /// The user's own `~/.gitconfig` and `$XDG_CONFIG_HOME/git/config`, which every
+    /// repository reads.
+    Global,
+    /// The repository's `config`, shared by all its worktrees.
+    Local,
+    /// The current worktree's `config.worktree`, which only counts with
+    /// `extensions.worktreeConfig` and overrides the repository's.
+    Worktree,
+}
+
 #[derive(Debug, Default)]
 pub struct GitRepository {
     work_tree: PathBuf,
     git_dir: PathBuf,
+    /// The git directory shared by all worktrees; the same as `git_dir` except in a linked
+    /// worktree, whose `git_dir` is `worktrees/<id>` inside it.
+    common_dir: PathBuf,
     config: ini::Ini,
+    /// The worktree's `config.worktree`, empty unless `extensions.worktreeConfig` is on.
+    worktree_config: ini::Ini,
+    /// The user's global config, which the repository's overrides.
+    global_config: ini::Ini,
+    hash_algo: HashAlgo,
 }
 
 impl GitRepository {
@@ -19,23 +91,243 @@ impl GitRepository {
         Self::default()
     }
 
-    pub fn build(&mut self, path: impl AsRef<Path>, force: bool) -> Result<()> {
+    pub fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    /// The git directory shared by all worktrees, which holds the objects and branches.
+    pub(crate) fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    /// The hash function naming this repository's objects.
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.hash_algo
+    }
+
+    /// Whether this is a bare repository, without a work tree.
+    pub fn is_bare(&self) -> bool {
+        self.work_tree == self.git_dir
+    }
+
+    /// Look up `section.key` in the config, matching the key case-insensitively the way git
+    /// does. The worktree's config wins over the repository's, which wins over the user's.
+    pub fn config_get(&self, section: &str, key: &str) -> Option<&str> {
+        self.config_get_in(ConfigScope::Worktree, section, key)
+            .or_else(|| self.config_get_in(ConfigScope::Local, section, key))
+            .or_else(|| self.config_get_in(ConfigScope::Global, section, key))
+    }
+
+    /// Like `config_get`, looking only in the config file of `scope`.
+    pub fn config_get_in(&self, scope: ConfigScope, section: &str, key: &str) -> Option<&str> {
+        self.scope_config(scope)
+            .section(Some(section))?
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+
+    /// Every value of the multi-valued `section.key`, like the `fetch` refspecs of a remote:
+    /// the user's first, then the repository's and the worktree's.
+    pub fn config_get_all(&self, section: &str, key: &str) -> Vec<&str> {
+        [
+            ConfigScope::Global,
+            ConfigScope::Local,
+            ConfigScope::Worktree,
+        ]
+        .into_iter()
+        .filter_map(|scope| self.scope_config(scope).section(Some(section)))
+        .flat_map(|props| props.iter())
+        .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+        .map(|(_, v)| v)
+        .collect()
+    }
+
+    /// Every config setting as `git config -l` names it: `section.key`, or
+    /// `section.subsection.key` for sections like `[remote "origin"]`, with its value. The
+    /// user's settings come first, then the repository's and the worktree's that override them.
+    pub fn config_entries(&self) -> Vec<(String, String)> {
+        let mut entries = self.config_entries_in(ConfigScope::Global);
+        entries.extend(self.config_entries_in(ConfigScope::Local));
+        entries.extend(self.config_entries_in(ConfigScope::Worktree));
+        entries
+    }
+
+    /// Like `config_entries`, listing only the config file of `scope`.
+    pub fn config_entries_in(&self, scope: ConfigScope) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        for (section, props) in self.scope_config(scope).iter() {
+            let Some(section) = section else { continue };
+            let section = match section.split_once(' ') {
+                Some((name, sub)) => {
+                    format!("{}.{}", name.to_ascii_lowercase(), sub.trim_matches('"'))
+                }
+                None => section.to_ascii_lowercase(),
+            };
+            for (key, value) in props.iter() {
+                entries.push((
+                    format!("{section}.{}", key.to_ascii_lowercase()),
+                    value.to_string(),
+                ));
+            }
+        }
+        entries
+    }
+
+    /// The config of `scope`.
+    fn scope_config(&self, scope: ConfigScope) -> &Ini {
+        match scope {
+            ConfigScope::Global => &self.global_config,
+            ConfigScope::Local => &self.config,
+            ConfigScope::Worktree => &self.worktree_config,
+        }
+    }
+
+    /// Whether `extensions.worktreeConfig` gives each worktree its own `config.worktree`.
+    fn has_worktree_config(&self) -> bool {
+        self.extension_enabled("worktreeconfig")
+    }
+
+    /// Whether `extensions.preciousObjects` forbids deleting objects, for instance because
+    /// other repositories borrow them through alternates.
+    pub(crate) fn precious_objects(&self) -> bool {
+        self.extension_enabled("preciousobjects")
+    }
+
+    /// Read the worktree's `config.worktree` if `extensions.worktreeConfig` is on. It lives in
+    /// the worktree's own git directory, which is the common one for the main worktree.
+    fn load_worktree_config(&mut self) -> Result<()> {
+        let path = self.git_dir.join("config.worktree");
+        if self.has_worktree_config() && path.exists() {
+            self.worktree_config = Ini::load_from_file(&path)
+                .with_context(|| format!("read config {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Read the user's global config files, the later overriding the earlier.
+    fn load_global_config(&mut self) -> Result<()> {
+        for path in global_config_paths() {
+            if !path.is_file() {
+                continue;
+            }
+            let config = Ini::load_from_file(&path)
+                .with_context(|| format!("read config {}", path.display()))?;
+            for (section, props) in config.iter() {
+                for (key, value) in props.iter() {
+                    self.global_config.with_section(section).set(key, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `config_get`, interpreting the value as a git boolean.
+    pub fn config_bool(&self, section: &str, key: &str) -> Option<bool> {
+        parse_bool(self.config_get(section, key)?)
+    }
+
+    /// Whether the repository extension `extensions.<name>` is turned on. Extensions describe
+    /// the repository itself, so only its own config is read, never the user's.
+    fn extension_enabled(&self, name: &str) -> bool {
+        self.config_get_in(ConfigScope::Local, "extensions", name)
+            .and_then(parse_bool)
+            == Some(true)
+    }
+
+    /// The config file `git config` uses for `scope`. Without `extensions.worktreeConfig` the
+    /// worktree's config is the repository's, which git only allows while there are no linked
+    /// worktrees.
+    pub fn resolve_scope(&self, scope: ConfigScope) -> Result<ConfigScope, GitError> {
+        match scope {
+            ConfigScope::Worktree if !self.has_worktree_config() => {
+                if self.has_linked_worktrees() {
+                    return Err(GitError::Other(anyhow::anyhow!(
+                        "--worktree cannot be used with multiple working trees unless the config\n\
+                         extension worktreeConfig is enabled. Please read \"CONFIGURATION FILE\"\n\
+                         section in \"git help worktree\" for details"
+                    )));
+                }
+                Ok(ConfigScope::Local)
+            }
+            scope => Ok(scope),
+        }
+    }
+
+    /// Set `section.key` to `value` (or remove it for `None`) in the config of `scope`, as
+    /// [`Self::resolve_scope`] picks it, and write that file back to disk.
+    pub fn config_set(
+        &mut self,
+        scope: ConfigScope,
+        section: &str,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<(), GitError> {
+        let (config, path) = match self.resolve_scope(scope)? {
+            ConfigScope::Global => {
+                let path = global_config_write_path().context("$HOME not set")?;
+                (&mut self.global_config, path)
+            }
+            ConfigScope::Local => {
+                let path = repo_path(self, &["config"])?;
+                (&mut self.config, path)
+            }
+            ConfigScope::Worktree => {
+                let path = self.git_dir.join("config.worktree");
+                (&mut self.worktree_config, path)
+            }
+        };
+        match value {
+            Some(value) => {
+                config.with_section(Some(section)).set(key, value);
+            }
+            None => {
+                config.delete_from(Some(section), key);
+                if config
+                    .section(Some(section))
+                    .is_some_and(|props| props.is_empty())
+                {
+                    config.delete(Some(section));
+                }
+            }
+        }
+        Ok(write_config(config, &path)?)
+    }
+
+    /// Whether any worktree besides the main one has been added.
+    fn has_linked_worktrees(&self) -> bool {
+        fs::read_dir(self.common_dir.join("worktrees"))
+            .is_ok_and(|mut entries| entries.next().is_some())
+    }
+
+    /// Whether the executable bit of work tree files can be trusted: `core.filemode`, which is
+    /// on unless the config turns it off.
+    pub fn filemode(&self) -> bool {
+        self.config_bool("core", "filemode").unwrap_or(true)
+    }
+
+    /// Whether the work tree's filesystem folds case (`core.ignorecase`), so that paths differing
+    /// only in case are the same file.
+    pub fn ignorecase(&self) -> bool {
+        self.config_bool("core", "ignorecase").unwrap_or(false)
+    }
+
+    pub(crate) fn build(&mut self, path: impl AsRef<Path>, force: bool) -> Result<()> {
         self.work_tree = path.as_ref().to_path_buf();
         // println!("work_tree = {}", work_tree.display());
         self.git_dir = path.as_ref().join(".git");
+        self.common_dir = self.git_dir.clone();
 
         if !(force || self.git_dir.is_dir()) {
             bail!("Not a Git repository {}", path.as_ref().display());
         }
 
-        let config_path = repo_file(self, &[&self.git_dir], false)?;
-        let mut config;
+        let config_path = repo_file(self, &["config"], false)?;
         if config_path.exists() {
-            config = Ini::load_from_str(config_path.to_str().context("Invalid config path")?)?;
+            self.config = Ini::load_from_file(&config_path)
+                .with_context(|| format!("read config {}", config_path.display()))?;
         } else if !force {
             bail!("Configuration file missing");
-        } else {
-            config = Ini::new();
         }
 
         // TODO: create .git/config
@@ -44,31 +336,211 @@ impl GitRepository {
                 .config
                 .section(Some("core"))
                 .context("Failed to get section `core`")?;
-            let version = core
-                .get("repositoryformatversion")
-                .context("Failed to get `repositoryformatversion`")?
-                .parse::<u8>()?;
-            if version != 0 {
-                bail!("Unsupported repositoryformatversion: {version}");
+            core.get("repositoryformatversion")
+                .context("Failed to get `repositoryformatversion`")?;
+            self.hash_algo = self.check_format()?;
+            self.load_worktree_config()?;
+        }
+        self.load_global_config()
+    }
+
+    /// Check that we can work in a repository of the format its config declares, returning the
+    /// hash function of its objects. Version 0 ignores `[extensions]`. Version 1 may only use
+    /// the extensions in [`KNOWN_EXTENSIONS`]: any other could change what reading or writing
+    /// the repository correctly means, so ignoring it might corrupt the repository.
+    fn check_format(&self) -> Result<HashAlgo> {
+        let version = match self.config_get("core", "repositoryformatversion") {
+            Some(version) => version
+                .parse::<u8>()
+                .with_context(|| format!("invalid repositoryformatversion {version}"))?,
+            None => 0,
+        };
+        match version {
+            0 => return Ok(HashAlgo::Sha1),
+            1 => {}
+            _ => bail!("Unsupported repositoryformatversion: {version}"),
+        }
+        if let Some(extensions) = self.config.section(Some("extensions")) {
+            for (name, _) in extensions.iter() {
+                if !KNOWN_EXTENSIONS.contains(&name.to_ascii_lowercase().as_str()) {
+                    bail!("unknown repository extension found: {name}");
+                }
             }
         }
-        Ok(())
+        match self.config_get_in(ConfigScope::Local, "extensions", "objectformat") {
+            Some(name) => HashAlgo::from_name(name),
+            None => Ok(HashAlgo::Sha1),
+        }
+    }
+}
+
+/// The library interface: what commands do with a repository, returning values rather than
+/// printing them.
+impl GitRepository {
+    /// Open the repository at `path`, a work tree containing `.git` or a bare repository.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        Ok(repo_open(path)?)
+    }
+
+    /// Create a repository at `path` (or reinitialize the one there) and open it.
+    pub fn init(path: impl AsRef<Path>) -> Result<Self, GitError> {
+        let (repo, _) = repo_create(path, None)?;
+        Ok(repo)
+    }
+
+    /// The kind and content of the object named by the full id `id`, loose or packed.
+    pub fn read_object(&self, id: &str) -> Result<(Kind, Vec<u8>), GitError> {
+        if !self.hash_algo.is_hex_id(id) {
+            return Err(GitError::InvalidObjectId(id.to_string()));
+        }
+        if !objects::object_exists(self, id)? {
+            return Err(GitError::ObjectNotFound(id.to_string()));
+        }
+        let invalid = |e: anyhow::Error| GitError::InvalidObject {
+            id: id.to_string(),
+            reason: format!("{e:#}"),
+        };
+        let object = objects::object_read(self, id).map_err(invalid)?;
+        let kind = object.format().parse().map_err(invalid)?;
+        Ok((kind, object.serialize()))
+    }
+
+    /// The id of every object in the store, loose or packed, each once. Only the fan-out
+    /// directories and the pack indexes are read, never the objects, and the ids come in the
+    /// order they are stored in.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use git_rs::{GitRepository, Kind};
+    ///
+    /// let dir = std::env::temp_dir().join(format!("git-rs-objects-{}", std::process::id()));
+    /// let repo = GitRepository::init(&dir)?;
+    /// let mut written = BTreeSet::new();
+    /// for data in ["one\n", "two\n", "three\n", "one\n"] {
+    ///     written.insert(repo.write_object(Kind::Blob, data.as_bytes())?);
+    /// }
+    /// let found = repo.object_iter()?.collect::<Result<BTreeSet<_>, _>>()?;
+    /// assert_eq!(found, written);
+    /// # std::fs::remove_dir_all(&dir)?;
+    /// # Ok::<(), git_rs::GitError>(())
+    /// ```
+    pub fn object_iter(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<String, GitError>> + '_, GitError> {
+        let mut seen = HashSet::new();
+        Ok(objects::object_ids(self)?
+            .filter(move |id| id.as_ref().map_or(true, |id| seen.insert(id.clone())))
+            .map(|id| id.map_err(GitError::from)))
+    }
+
+    /// Store `data` as an object of `kind`, returning its id.
+    pub fn write_object(&self, kind: Kind, data: &[u8]) -> Result<String, GitError> {
+        Ok(objects::write_object(self, kind, data)?)
+    }
+
+    /// The object the ref `name` (like `HEAD` or `refs/heads/main`) points at, following
+    /// symbolic refs.
+    pub fn resolve_ref(&self, name: &str) -> Result<String, GitError> {
+        refs::ref_resolve(self, name)?.ok_or_else(|| GitError::RefNotFound(name.to_string()))
     }
+
+    /// The commits reachable from the revision `rev` with their ids, newest first, as `log`
+    /// shows them.
+    pub fn log_iter(
+        &self,
+        rev: &str,
+    ) -> Result<impl Iterator<Item = Result<(String, Commit), GitError>> + '_, GitError> {
+        let mut walk = RevWalk::new(self);
+        walk.push(&objects::object_find(
+            self,
+            rev.to_string(),
+            ObjectType::Commit,
+        )?)?;
+        Ok(walk.map(|commit| commit.map_err(GitError::from)))
+    }
+
+    /// Write a commit of `tree` with `parents` and `message`, returning its id. The author and
+    /// committer are taken from the environment and config, as `commit` takes them; no ref is
+    /// updated.
+    pub fn write_commit(
+        &self,
+        tree: &str,
+        parents: &[String],
+        message: &str,
+    ) -> Result<String, GitError> {
+        let author = identity(self, "author")?.to_string();
+        let committer = identity(self, "committer")?.to_string();
+        Ok(write_commit_object(
+            self, tree, parents, &author, &committer, message,
+        )?)
+    }
+}
+
+/// Compute path under repo's gitdir. In a linked worktree, paths shared by all worktrees, like
+/// `objects` and `refs/heads`, are under the common git directory instead.
+///
+/// The components must be relative: joining an absolute one would silently replace the gitdir,
+/// so it is an error instead.
+pub fn repo_path(git_repo: &GitRepository, paths: &[impl AsRef<Path>]) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for p in paths.iter().map(|p| p.as_ref()) {
+        if p.has_root() {
+            bail!(
+                "path component {} is absolute, not relative to the gitdir",
+                p.display()
+            );
+        }
+        relative.push(p);
+    }
+    Ok(match is_common_path(&relative) {
+        true => git_repo.common_dir.join(relative),
+        false => git_repo.git_dir.join(relative),
+    })
 }
 
-/// Compute path under repo's gitdir.
-pub fn repo_path(git_repo: &GitRepository, paths: &[impl AsRef<Path>]) -> PathBuf {
-    let path = paths
+/// Whether `path`, relative to the git directory, is shared by all worktrees.
+pub(crate) fn is_common_path(path: &Path) -> bool {
+    COMMON_PATHS
         .iter()
-        .map(|p| p.as_ref())
-        .fold(PathBuf::new(), |acc, p| acc.join(p));
-    git_repo.git_dir.join(path)
+        .filter(|(prefix, _)| path.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .is_some_and(|(_, common)| *common)
+}
+
+/// Turn `path`, relative to the current directory, into a `/`-separated path relative to the
+/// work tree, as stored in the index. The path doesn't have to exist.
+pub fn worktree_path(git_repo: &GitRepository, path: impl AsRef<Path>) -> Result<String> {
+    let path = std::env::current_dir()?.canonicalize()?.join(path);
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            std::path::Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    let relative = normalized
+        .strip_prefix(git_repo.work_tree())
+        .with_context(|| format!("{} is outside repository", path.display()))?;
+    let relative = relative
+        .to_str()
+        .with_context(|| format!("{} isn't valid utf-8", relative.display()))?;
+    Ok(relative.to_string())
+}
+
+/// Whether optional writes, like refreshing the index during `status`, may be done. Disabled by
+/// `--no-optional-locks` (which sets `GIT_OPTIONAL_LOCKS=0`) so that such commands never get in
+/// the way of concurrent writers.
+pub fn optional_locks_allowed() -> bool {
+    std::env::var("GIT_OPTIONAL_LOCKS").map_or(true, |v| v != "0")
 }
 
 /// Same as repo_path, but create dirname(*path) if absent.
 ///
 /// # Example
-/// ```
+/// ```text
 /// This will create `.git/refs/remotes/origin`.
 /// repo_file(r, &["refs", "remotes", "origin", "HEAD"])
 /// ```
@@ -78,14 +550,14 @@ pub fn repo_file(
     mkdir: bool,
 ) -> Result<PathBuf> {
     match repo_dir(git_repo, &paths[0..paths.len() - 1], mkdir) {
-        Ok(_) => Ok(repo_path(git_repo, paths)),
+        Ok(_) => repo_path(git_repo, paths),
         Err(e) => Err(e),
     }
 }
 
 /// Same as `repo_path``, but mkdir `paths`` if absent if `mkdir`.
 fn repo_dir(git_repo: &GitRepository, paths: &[impl AsRef<Path>], mkdir: bool) -> Result<PathBuf> {
-    let path = repo_path(git_repo, paths);
+    let path = repo_path(git_repo, paths)?;
     if path.exists() {
         if path.is_dir() {
             return Ok(path);
@@ -96,13 +568,30 @@ fn repo_dir(git_repo: &GitRepository, paths: &[impl AsRef<Path>], mkdir: bool) -
 
     if mkdir {
         fs::create_dir_all(&path)?;
-        println!("create dir {} in repo_dir", path.display());
         return Ok(path);
     }
     Ok(PathBuf::new())
 }
 
-pub fn repo_create(path: impl AsRef<Path>) -> Result<GitRepository> {
+/// Whether the filesystem holding the existing file `path` keeps the executable bit, found by
+/// flipping it and looking whether the change stuck.
+fn probe_filemode(path: &Path) -> Result<bool> {
+    let mode = fs::metadata(path)?.permissions().mode();
+    fs::set_permissions(path, fs::Permissions::from_mode(mode ^ 0o100))?;
+    let kept = fs::metadata(path)?.permissions().mode() & 0o100 != mode & 0o100;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(kept)
+}
+
+/// Create a repository at `path`, or reinitialize the one already there like `git init` does:
+/// the standard directories and files are created where missing, and existing objects, refs,
+/// `HEAD` and config are left alone. A new repository names its objects with `object_format`,
+/// SHA-1 by default; an existing one can't change its format. Returns the repository and
+/// whether it already existed.
+pub fn repo_create(
+    path: impl AsRef<Path>,
+    object_format: Option<HashAlgo>,
+) -> Result<(GitRepository, bool)> {
     let mut git_repo = GitRepository::new();
     git_repo.build(path.as_ref(), true)?;
 
@@ -110,68 +599,347 @@ pub fn repo_create(path: impl AsRef<Path>) -> Result<GitRepository> {
         if !git_repo.work_tree.is_dir() {
             bail!("{} is not a directory", path.as_ref().display());
         }
-        if git_repo.git_dir.exists() && fs::read_dir(&git_repo.git_dir).iter().count() > 0 {
-            bail!("{} is not emptry", git_repo.git_dir.display());
-        }
     } else {
         fs::create_dir_all(&git_repo.work_tree)?;
-        println!("create dir {}", git_repo.work_tree.display());
     }
+    let existed = git_repo.git_dir.join("HEAD").is_file();
 
     repo_dir(&git_repo, &["branches"], true)?;
     repo_dir(&git_repo, &["objects"], true)?;
     repo_dir(&git_repo, &["refs", "tags"], true)?;
     repo_dir(&git_repo, &["refs", "heads"], true)?;
 
-    let mut f = fs::File::options()
-        .write(true)
-        .create(true)
-        .open(repo_file(&git_repo, &["description"], false)?)?;
-    f.write_all(b"Unnamed repository; edit this file 'description' to name the repository.\n")?;
+    let description = repo_file(&git_repo, &["description"], false)?;
+    if !description.exists() {
+        let mut f = fs::File::create(description)?;
+        f.write_all(b"Unnamed repository; edit this file 'description' to name the repository.\n")?;
+    }
 
-    let mut f = fs::File::options()
-        .write(true)
-        .create(true)
-        .open(repo_file(&git_repo, &["HEAD"], false)?)?;
-    f.write_all(b"ref: refs/heads/master\n")?;
+    let head = repo_file(&git_repo, &["HEAD"], false)?;
+    if !head.exists() {
+        let mut f = fs::File::create(&head)?;
+        f.write_all(b"ref: refs/heads/master\n")?;
+    }
 
     let config_path = repo_file(&git_repo, &["config"], false)?;
+    if config_path.exists() {
+        let existing = git_repo.check_format()?;
+        if object_format.is_some_and(|format| format != existing) {
+            bail!("attempt to reinitialize repository with different hash");
+        }
+    } else {
+        let object_format = object_format.unwrap_or_default();
+        let filemode = probe_filemode(&head)?;
+        // a filesystem that folds case finds HEAD under another spelling
+        let ignorecase = git_repo.git_dir.join("hEaD").exists();
 
-    let mut conf = Ini::new();
-    conf.with_section(Some("core"))
-        .set("repositoryformatversion", "0")
-        .set("filemode", "false")
-        .set("bare", "false");
-    conf.write_to_file(config_path.to_str().context("Invalid config path")?)?;
+        let mut conf = Ini::new();
+        // other object formats need a version 1 repository, which older git refuses to open
+        let version = match object_format {
+            HashAlgo::Sha1 => "0",
+            _ => "1",
+        };
+        conf.with_section(Some("core"))
+            .set("repositoryformatversion", version)
+            .set("filemode", filemode.to_string())
+            .set("bare", "false");
+        if ignorecase {
+            conf.with_section(Some("core")).set("ignorecase", "true");
+        }
+        if object_format != HashAlgo::Sha1 {
+            conf.with_section(Some("extensions"))
+                .set("objectformat", object_format.name());
+        }
+        write_config(&conf, &config_path)?;
+        git_repo.config = conf;
+        git_repo.hash_algo = object_format;
+    }
 
-    Ok(git_repo)
+    Ok((git_repo, existed))
 }
 
-/// Find the root of current repository.
-pub fn repo_find(path: impl AsRef<Path>, required: bool) -> Result<GitRepository> {
-    fn get_real_path(path: impl AsRef<Path>) -> Result<PathBuf> {
-        let path = if path.as_ref().is_symlink() {
-            path.as_ref().read_link()?
-        } else {
-            path.as_ref().to_path_buf()
-        };
-        Ok(path)
+/// Write `config` to the config file at `path`, under its lock.
+fn write_config(config: &Ini, path: &Path) -> Result<()> {
+    let mut data = Vec::new();
+    config.write_to(&mut data).context("serialize config")?;
+    write_locked(path, &data).with_context(|| format!("write config {}", path.display()))
+}
+
+/// Open the repository at `path`, which is either a work tree containing `.git` or a bare
+/// repository. `.git` may also be a file pointing at the git directory (`gitdir: <path>`), as
+/// it is in submodules.
+pub fn repo_open(path: impl AsRef<Path>) -> Result<GitRepository> {
+    let path = path.as_ref();
+    if path.join(".git").is_dir() {
+        let mut repo = GitRepository::new();
+        repo.build(path, false)?;
+        return Ok(repo);
+    }
+    let git_dir = if path.join(".git").is_file() {
+        read_gitfile(&path.join(".git"))?
+    } else {
+        path.to_path_buf()
+    };
+    if !is_git_dir(&git_dir) {
+        return Err(GitError::NotARepository(path.to_path_buf()).into());
+    }
+    open_git_dir(git_dir, path.to_path_buf())
+}
+
+/// The git directory a `.git` file (`gitdir: <path>`) points at, as submodules and linked
+/// worktrees have. A relative path is relative to the directory holding the file.
+fn read_gitfile(gitfile: &Path) -> Result<PathBuf> {
+    let data =
+        fs::read_to_string(gitfile).with_context(|| format!("read {}", gitfile.display()))?;
+    let Some(dir) = data.trim_end().strip_prefix("gitdir: ") else {
+        bail!("invalid gitfile format: {}", gitfile.display());
+    };
+    Ok(gitfile.parent().unwrap_or(Path::new("")).join(dir))
+}
+
+/// The git directory shared by all worktrees of `git_dir`: the one its `commondir` file names,
+/// relative to it, for a linked worktree, or else `git_dir` itself.
+fn common_dir(git_dir: &Path) -> PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(dir) => {
+            let dir = git_dir.join(dir.trim_end());
+            dir.canonicalize().unwrap_or(dir)
+        }
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+/// The user's global config files, in the order git reads them: `$XDG_CONFIG_HOME/git/config`
+/// (`~/.config/git/config` without it), then `~/.gitconfig`. `$GIT_CONFIG_GLOBAL` replaces both.
+fn global_config_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(path)];
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let xdg = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+        _ => home.as_ref().map(|home| home.join(".config")),
+    };
+    xdg.map(|dir| dir.join("git/config"))
+        .into_iter()
+        .chain(home.map(|home| home.join(".gitconfig")))
+        .collect()
+}
+
+/// The global config file `git config --global` writes: `~/.gitconfig`, unless only the XDG one
+/// exists.
+fn global_config_write_path() -> Option<PathBuf> {
+    let paths = global_config_paths();
+    match paths.as_slice() {
+        [xdg, home] if !home.exists() && xdg.exists() => Some(xdg.clone()),
+        _ => paths.last().cloned(),
+    }
+}
+
+/// The git boolean `value` is: `true`, `yes`, `on`, `1` or empty, or `false`, `no`, `off` or
+/// `0`, in any case.
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" | "" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
     }
-    let path = get_real_path(path)?;
+}
+
+/// Whether `git_dir` looks like a git directory: it has a `HEAD` and, itself or through its
+/// common directory, an object store.
+fn is_git_dir(git_dir: &Path) -> bool {
+    git_dir.join("HEAD").is_file() && common_dir(git_dir).join("objects").is_dir()
+}
+
+/// Open the git directory `git_dir`, whose work tree is `work_tree` (the git directory itself
+/// for a bare repository).
+fn open_git_dir(git_dir: PathBuf, work_tree: PathBuf) -> Result<GitRepository> {
+    let mut repo = GitRepository {
+        work_tree,
+        common_dir: common_dir(&git_dir),
+        git_dir,
+        config: Ini::new(),
+        worktree_config: Ini::new(),
+        global_config: Ini::new(),
+        hash_algo: HashAlgo::Sha1,
+    };
+    repo.load_global_config()?;
+    let config_path = repo_file(&repo, &["config"], false)?;
+    if config_path.exists() {
+        repo.config = Ini::load_from_file(&config_path)
+            .with_context(|| format!("read config {}", config_path.display()))?;
+        repo.hash_algo = repo.check_format()?;
+        repo.load_worktree_config()?;
+    }
+    Ok(repo)
+}
+
+/// Set up the repository commands work in, the way git does. The git directory is `$GIT_DIR`
+/// (which `--git-dir` sets) if it is set, or else found from the current directory by
+/// [`repo_find`]. `$GIT_WORK_TREE` (which `--work-tree` sets) overrides the work tree; with only
+/// `$GIT_DIR` set, the current directory is the top of the work tree, unless `core.bare` says
+/// there is none.
+///
+/// Paths given to commands are relative to the current directory, so when it is outside the
+/// work tree, the top of the work tree becomes the current directory, as in git.
+pub fn repo_setup(required: bool) -> Result<GitRepository> {
+    let work_tree = std::env::var_os("GIT_WORK_TREE").map(PathBuf::from);
+    let mut repo = match std::env::var_os("GIT_DIR").map(PathBuf::from) {
+        Some(git_dir) => {
+            let git_dir = git_dir
+                .canonicalize()
+                .with_context(|| format!("not a git repository: '{}'", git_dir.display()))?;
+            if !is_git_dir(&git_dir) {
+                bail!("not a git repository: '{}'", git_dir.display());
+            }
+            let repo = open_git_dir(git_dir.clone(), std::env::current_dir()?.canonicalize()?)?;
+            match work_tree.is_none() && repo.config_bool("core", "bare") == Some(true) {
+                true => GitRepository {
+                    work_tree: git_dir,
+                    ..repo
+                },
+                false => repo,
+            }
+        }
+        None => repo_find(".", required)?,
+    };
+    if let Some(work_tree) = work_tree {
+        repo.work_tree = work_tree
+            .canonicalize()
+            .with_context(|| format!("cannot chdir to '{}'", work_tree.display()))?;
+    }
+    if !repo.is_bare()
+        && !std::env::current_dir()?
+            .canonicalize()?
+            .starts_with(&repo.work_tree)
+    {
+        std::env::set_current_dir(&repo.work_tree)
+            .with_context(|| format!("cannot chdir to '{}'", repo.work_tree.display()))?;
+    }
+    Ok(repo)
+}
+
+/// Find the root of current repository, or the bare repository `path` is in.
+pub fn repo_find(path: impl AsRef<Path>, required: bool) -> Result<GitRepository> {
+    // canonicalize so walking up through parents ends at the filesystem root
+    let path = path
+        .as_ref()
+        .canonicalize()
+        .with_context(|| format!("resolve {}", path.as_ref().display()))?;
 
     if path.join(".git").is_dir() {
         let mut repo = GitRepository::new();
         repo.build(path, false)?;
         return Ok(repo);
     }
+    // a linked worktree (or a submodule) has a `.git` file pointing at its git directory
+    if path.join(".git").is_file() {
+        let git_dir = read_gitfile(&path.join(".git"))?;
+        if !is_git_dir(&git_dir) {
+            bail!("not a git repository: {}", git_dir.display());
+        }
+        return open_git_dir(git_dir, path);
+    }
+    // a bare repository is found by being in its git directory
+    if path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir() {
+        return open_git_dir(path.clone(), path);
+    }
 
-    let parent = get_real_path(path.join(".."))?;
-    if parent == path {
+    let Some(parent) = path.parent() else {
         if required {
             bail!("No git directory");
         }
         return Ok(Default::default());
+    };
+
+    repo_find(parent, required)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn repo_path_rejects_absolute_components() {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"], b"");
+        let git_repo = repo_open(dir.path()).unwrap();
+
+        let err = repo_path(&git_repo, &["refs", "/etc/hostname"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "path component /etc/hostname is absolute, not relative to the gitdir"
+        );
+        assert!(repo_path(&git_repo, &["/objects"]).is_err());
+
+        assert_eq!(
+            repo_path(&git_repo, &["refs", "heads/main"]).unwrap(),
+            git_repo.git_dir.join("refs/heads/main")
+        );
     }
 
-    return repo_find(parent, required);
+    /// A new repository whose config sets `repositoryformatversion` to `version` and each of
+    /// `extensions` (`name=value`).
+    fn repo_with_format(version: &str, extensions: &[&str]) -> TempDir {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"], b"");
+        dir.git(&["config", "core.repositoryformatversion", version], b"");
+        for extension in extensions {
+            let (name, value) = extension.split_once('=').unwrap();
+            dir.git(&["config", &format!("extensions.{name}"), value], b"");
+        }
+        dir
+    }
+
+    #[test]
+    fn version_0_ignores_extensions() {
+        let dir = repo_with_format("0", &["noSuchThing=true", "objectFormat=sha256"]);
+        assert_eq!(repo_open(dir.path()).unwrap().hash_algo, HashAlgo::Sha1);
+    }
+
+    #[test]
+    fn version_1_accepts_known_extensions() {
+        let dir = repo_with_format("1", &[]);
+        assert_eq!(repo_open(dir.path()).unwrap().hash_algo, HashAlgo::Sha1);
+
+        let dir = repo_with_format(
+            "1",
+            &[
+                "objectFormat=sha1",
+                "worktreeConfig=true",
+                "preciousObjects=true",
+            ],
+        );
+        let git_repo = repo_open(dir.path()).unwrap();
+        assert_eq!(git_repo.hash_algo, HashAlgo::Sha1);
+        assert!(git_repo.has_worktree_config());
+
+        let dir = repo_with_format("1", &["objectformat=sha256"]);
+        assert_eq!(repo_open(dir.path()).unwrap().hash_algo, HashAlgo::Sha256);
+    }
+
+    #[test]
+    fn version_1_refuses_unknown_extensions() {
+        let dir = repo_with_format("1", &["objectFormat=sha1", "noSuchThing=true"]);
+        let err = repo_open(dir.path()).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("unknown repository extension found: noSuchThing"),
+            "{err:#}"
+        );
+
+        let dir = repo_with_format("1", &["objectFormat=md5"]);
+        assert!(repo_open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn later_versions_are_refused() {
+        let dir = repo_with_format("2", &[]);
+        let err = repo_open(dir.path()).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Unsupported repositoryformatversion: 2"),
+            "{err:#}"
+        );
+    }
 }
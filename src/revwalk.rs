@@ -0,0 +1,269 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use anyhow::Result;
+
+use crate::{
+    diff::{detect_renames, diff_trees, BlobCache, Change},
+    interrupt,
+    objects::{read_commit, read_tree, Commit, Mode, TreeEntry},
+    repository::GitRepository,
+};
+
+/// Similarity in percent needed for `--follow` to take an added file as a rename.
+const FOLLOW_THRESHOLD: u8 = 50;
+
+/// Limits a walk to the commits that change some paths, with git's default history
+/// simplification: a commit the same as one of its parents at those paths (TREESAME) is hidden,
+/// and for a merge only that parent's history is followed. Following a file across renames
+/// does without the simplification, as in git.
+struct PathLimit {
+    paths: Vec<String>,
+    /// Follow the single path back across renames.
+    follow: bool,
+    /// Trees read so far, since neighbouring commits mostly share their subtrees.
+    trees: HashMap<String, Vec<TreeEntry>>,
+}
+
+impl PathLimit {
+    /// The mode and hash at `path` below the tree `tree`; the empty path is the tree itself.
+    fn lookup(
+        &mut self,
+        git_repo: &GitRepository,
+        tree: &str,
+        path: &str,
+    ) -> Result<Option<(Mode, String)>> {
+        let mut found = (Mode::Dir, tree.to_string());
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            if !found.0.is_tree() {
+                return Ok(None);
+            }
+            if !self.trees.contains_key(&found.1) {
+                let entries = read_tree(git_repo, &found.1)?;
+                self.trees.insert(found.1.clone(), entries);
+            }
+            let Some(entry) = self.trees[&found.1].iter().find(|e| e.name == component) else {
+                return Ok(None);
+            };
+            found = (entry.mode, entry.hash.clone());
+        }
+        Ok(Some(found))
+    }
+
+    /// What each limited path is in the tree `tree`.
+    fn ids(&mut self, git_repo: &GitRepository, tree: &str) -> Result<Vec<Option<(Mode, String)>>> {
+        let paths = self.paths.clone();
+        paths
+            .iter()
+            .map(|path| self.lookup(git_repo, tree, path))
+            .collect()
+    }
+
+    /// When `commit` added the followed file as a rename, follow the old name from its parent on.
+    fn follow_rename(&mut self, git_repo: &GitRepository, commit: &Commit) -> Result<()> {
+        let Some(parent) = commit.parents.first() else {
+            return Ok(());
+        };
+        let parent_tree = read_commit(git_repo, parent)?.tree;
+        if self.ids(git_repo, &parent_tree)?[0].is_some() {
+            return Ok(());
+        }
+        let changes = diff_trees(git_repo, Some(&parent_tree), Some(&commit.tree))?;
+        let renames = detect_renames(&mut BlobCache::new(git_repo), changes, FOLLOW_THRESHOLD)?;
+        let renamed = renames.into_iter().find_map(|change| match change {
+            Change {
+                old: Some(old),
+                new: Some(new),
+                ..
+            } if new.path == self.paths[0] && old.path != new.path => Some(old.path),
+            _ => None,
+        });
+        if let Some(old) = renamed {
+            self.paths = vec![old];
+        }
+        Ok(())
+    }
+}
+
+/// Walks the commits reachable from a set of starting commits, newest committer date first
+/// (`git log`'s default order). Commits with equal dates come out in the order they were found.
+pub(crate) struct RevWalk<'a> {
+    git_repo: &'a GitRepository,
+    queue: BinaryHeap<(i64, Reverse<u64>, String)>,
+    pending: HashMap<String, Commit>,
+    seen: HashSet<String>,
+    found: u64,
+    limit: Option<PathLimit>,
+    since: Option<i64>,
+    /// Walk on from merges to their first parent only.
+    first_parent: bool,
+}
+
+impl<'a> RevWalk<'a> {
+    pub(crate) fn new(git_repo: &'a GitRepository) -> Self {
+        Self {
+            git_repo,
+            queue: BinaryHeap::new(),
+            pending: HashMap::new(),
+            seen: HashSet::new(),
+            found: 0,
+            limit: None,
+            since: None,
+            first_parent: false,
+        }
+    }
+
+    /// Only follow the first parent of merges, as `--first-parent` does, leaving out the
+    /// commits that were merged in.
+    pub(crate) fn first_parent_only(&mut self) {
+        self.first_parent = true;
+    }
+
+    /// Stop at commits older (by committer date) than `since`: like git's `--since`, they are
+    /// neither yielded nor walked past, even if their parents are newer.
+    pub(crate) fn stop_before(&mut self, since: i64) {
+        self.since = Some(since);
+    }
+
+    /// Only yield commits that change one of `paths` (relative to the top of the work tree, a
+    /// directory standing for everything below it). With `follow`, the single path is followed
+    /// back across renames.
+    pub(crate) fn limit_to_paths(&mut self, paths: Vec<String>, follow: bool) {
+        self.limit = Some(PathLimit {
+            paths,
+            follow,
+            trees: HashMap::new(),
+        });
+    }
+
+    /// The parents of `commit` to walk on to, and whether it is shown, under the path limit.
+    fn simplify(&mut self, commit: &Commit) -> Result<(Vec<String>, bool)> {
+        let parents = match self.first_parent {
+            true => &commit.parents[..commit.parents.len().min(1)],
+            false => &commit.parents[..],
+        };
+        let Some(limit) = self.limit.as_mut() else {
+            return Ok((parents.to_vec(), true));
+        };
+        let ids = limit.ids(self.git_repo, &commit.tree)?;
+        if commit.parents.is_empty() {
+            return Ok((Vec::new(), ids.iter().any(Option::is_some)));
+        }
+        if limit.follow {
+            // like git, following a file walks every parent and only looks at the changes of
+            // ordinary commits
+            if parents.len() > 1 {
+                return Ok((parents.to_vec(), false));
+            }
+            let tree = read_commit(self.git_repo, &parents[0])?.tree;
+            let shown = limit.ids(self.git_repo, &tree)? != ids;
+            if shown {
+                limit.follow_rename(self.git_repo, commit)?;
+            }
+            return Ok((parents.to_vec(), shown));
+        }
+        for parent in parents {
+            let tree = read_commit(self.git_repo, parent)?.tree;
+            if limit.ids(self.git_repo, &tree)? == ids {
+                return Ok((vec![parent.clone()], false));
+            }
+        }
+        Ok((parents.to_vec(), true))
+    }
+
+    /// Add a starting commit. Commits already seen are ignored.
+    pub(crate) fn push(&mut self, hash: &str) -> Result<()> {
+        if !self.seen.insert(hash.to_string()) {
+            return Ok(());
+        }
+        let commit = read_commit(self.git_repo, hash)?;
+        self.queue
+            .push((commit.commit_time(), Reverse(self.found), hash.to_string()));
+        self.found += 1;
+        self.pending.insert(hash.to_string(), commit);
+        Ok(())
+    }
+
+    fn next_commit(&mut self) -> Result<Option<(String, Commit)>> {
+        loop {
+            interrupt::check()?;
+            let Some((_, _, hash)) = self.queue.pop() else {
+                return Ok(None);
+            };
+            let commit = self
+                .pending
+                .remove(&hash)
+                .expect("queued commits are pending");
+            if self.since.is_some_and(|since| commit.commit_time() < since) {
+                continue;
+            }
+            let (parents, shown) = self.simplify(&commit)?;
+            for parent in &parents {
+                self.push(parent)?;
+            }
+            if shown {
+                return Ok(Some((hash, commit)));
+            }
+        }
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_commit().transpose()
+    }
+}
+
+/// Reorder walked commits so that no commit comes before any of its children, keeping the
+/// commits of one line of history together, as `--topo-order` does. This is git's Kahn-style
+/// sort: a commit is emitted once all its children are, newest line first, so clock skew
+/// between commits can't put a parent first.
+pub(crate) fn topo_sort(commits: Vec<(String, Commit)>) -> Vec<(String, Commit)> {
+    let mut children = HashMap::<&str, usize>::new();
+    for (hash, _) in &commits {
+        children.insert(hash, 0);
+    }
+    for (_, commit) in &commits {
+        for parent in &commit.parents {
+            if let Some(count) = children.get_mut(parent.as_str()) {
+                *count += 1;
+            }
+        }
+    }
+
+    let position = commits
+        .iter()
+        .enumerate()
+        .map(|(i, (hash, _))| (hash.as_str(), i))
+        .collect::<HashMap<_, _>>();
+    // a stack, with the first tip on top
+    let mut ready = commits
+        .iter()
+        .filter(|(hash, _)| children[hash.as_str()] == 0)
+        .map(|(hash, _)| position[hash.as_str()])
+        .rev()
+        .collect::<Vec<_>>();
+    let mut order = Vec::with_capacity(commits.len());
+    while let Some(i) = ready.pop() {
+        order.push(i);
+        for parent in &commits[i].1.parents {
+            let Some(count) = children.get_mut(parent.as_str()) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                ready.push(position[parent.as_str()]);
+            }
+        }
+    }
+
+    let mut commits = commits.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .into_iter()
+        .map(|i| commits[i].take().expect("each commit is emitted once"))
+        .collect()
+}
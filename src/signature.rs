@@ -0,0 +1,224 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::date::{parse_iso, parse_rfc2822, parse_tz, DateFormat};
+
+/// Who made a commit or tag, and when, as author, committer and tagger headers record it:
+/// `Name <email> <unix time> <+hhmm>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Signature {
+    pub(crate) name: String,
+    pub(crate) email: String,
+    /// Seconds since the Unix epoch.
+    pub(crate) time: i64,
+    /// The timezone's offset from UTC in minutes, east of Greenwich being positive.
+    pub(crate) offset: i32,
+}
+
+impl Signature {
+    /// The timezone as headers write it, like `+0200` or `-0530`.
+    pub(crate) fn tz(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let minutes = self.offset.unsigned_abs();
+        format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+    }
+
+    /// The date in `format`, relative to the unix time `now` if it is relative.
+    pub(crate) fn date(&self, format: DateFormat, now: i64) -> String {
+        format.format(self.time, &self.tz(), now)
+    }
+
+    /// Parse an author, committer or tagger header back into a signature, undoing `Display`.
+    /// The name may hold spaces, a header without an `<email>` parses with an empty one, and a
+    /// missing or malformed date is the epoch in UTC. Only an empty header isn't a signature.
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
+        }
+        let (name, email, date) = match line.split_once('<') {
+            Some((name, rest)) => {
+                let (email, date) = rest.split_once('>').unwrap_or((rest, ""));
+                (name.trim_end(), email, date)
+            }
+            // without an email to end at, the date is the last words, if they look like one
+            None => {
+                let mut name = line;
+                let mut words = 0;
+                while words < 2 {
+                    let Some((rest, word)) = name.rsplit_once(' ') else {
+                        break;
+                    };
+                    if word.parse::<i64>().is_err() && parse_tz(word).is_none() {
+                        break;
+                    }
+                    name = rest.trim_end();
+                    words += 1;
+                }
+                (name, "", line[name.len()..].trim_start())
+            }
+        };
+        let mut date = date.split_whitespace();
+        Some(Self {
+            name: name.to_string(),
+            email: email.to_string(),
+            time: date.next().and_then(|t| t.parse().ok()).unwrap_or(0),
+            offset: date
+                .next()
+                .and_then(parse_tz)
+                .map_or(0, |tz| (tz / 60) as i32),
+        })
+    }
+
+    /// Parse a date the way `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE` take them, into a unix
+    /// time and a timezone offset in minutes: git's internal `<unix time> <+hhmm>` (optionally
+    /// after `@`), RFC 2822 like `Thu, 15 Oct 2026 12:34:56 +0200`, or ISO 8601 like
+    /// `2026-10-15T12:34:56+0200`. Without a timezone, the date is in UTC, and without a time of
+    /// day, it is the current one.
+    pub(crate) fn parse_date(date: &str) -> Option<(i64, i32)> {
+        let date = date.trim();
+        let internal = date.strip_prefix('@').unwrap_or(date);
+        let mut parts = internal.split_whitespace();
+        if let Some(Ok(time)) = parts.next().map(str::parse::<i64>) {
+            let offset = match parts.next() {
+                Some(tz) => parse_tz(tz)?,
+                None => 0,
+            };
+            return parts
+                .next()
+                .is_none()
+                .then_some((time, (offset / 60) as i32));
+        }
+        if let Some(parsed) = parse_rfc2822(date) {
+            let (time, tz) = parsed.split_once(' ')?;
+            return Some((time.parse().ok()?, (parse_tz(tz)? / 60) as i32));
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let (time, offset) = parse_iso(date, now)?;
+        Some((time, (offset / 60) as i32))
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            self.tz()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_writes_the_time_and_offset() {
+        let signature = Signature {
+            name: "A U Thor".to_string(),
+            email: "author@example.com".to_string(),
+            time: 1_700_000_000,
+            offset: -(5 * 60 + 30),
+        };
+        assert_eq!(signature.tz(), "-0530");
+        assert_eq!(
+            signature.to_string(),
+            "A U Thor <author@example.com> 1700000000 -0530"
+        );
+        let east = Signature {
+            offset: 12 * 60 + 45,
+            ..signature
+        };
+        assert_eq!(east.tz(), "+1245");
+    }
+
+    fn signature(name: &str, email: &str, time: i64, offset: i32) -> Signature {
+        Signature {
+            name: name.to_string(),
+            email: email.to_string(),
+            time,
+            offset,
+        }
+    }
+
+    #[test]
+    fn parse_a_normal_line() {
+        let line = "A U Thor <author@example.com> 1700000000 +0200";
+        let parsed = Signature::parse(line).unwrap();
+        assert_eq!(
+            parsed,
+            signature("A U Thor", "author@example.com", 1_700_000_000, 120)
+        );
+        assert_eq!(parsed.to_string(), line);
+    }
+
+    #[test]
+    fn parse_a_name_with_several_spaces() {
+        let line = "Jean  de la   Fontaine <jean@example.com> 1700000000 +0000";
+        let parsed = Signature::parse(line).unwrap();
+        assert_eq!(parsed.name, "Jean  de la   Fontaine");
+        assert_eq!(parsed.email, "jean@example.com");
+        assert_eq!(parsed.to_string(), line);
+    }
+
+    #[test]
+    fn parse_a_negative_offset() {
+        let line = "A U Thor <author@example.com> 1700000000 -0930";
+        let parsed = Signature::parse(line).unwrap();
+        assert_eq!(parsed.offset, -(9 * 60 + 30));
+        assert_eq!(parsed.tz(), "-0930");
+        assert_eq!(parsed.to_string(), line);
+    }
+
+    #[test]
+    fn parse_tolerates_missing_parts() {
+        assert_eq!(
+            Signature::parse("No Email 1700000000 -0100"),
+            Some(signature("No Email", "", 1_700_000_000, -60))
+        );
+        assert_eq!(
+            Signature::parse("Just A Name"),
+            Some(signature("Just A Name", "", 0, 0))
+        );
+        assert_eq!(
+            Signature::parse("A U Thor <author@example.com>"),
+            Some(signature("A U Thor", "author@example.com", 0, 0))
+        );
+        assert_eq!(Signature::parse("  "), None);
+    }
+
+    #[test]
+    fn parse_date_takes_the_formats_of_the_date_variables() {
+        assert_eq!(
+            Signature::parse_date("1700000000 +0100"),
+            Some((1_700_000_000, 60))
+        );
+        assert_eq!(
+            Signature::parse_date("@1700000000 -0230"),
+            Some((1_700_000_000, -150))
+        );
+        assert_eq!(
+            Signature::parse_date("1700000000"),
+            Some((1_700_000_000, 0))
+        );
+        assert_eq!(
+            Signature::parse_date("Thu, 15 Oct 2026 12:34:56 +0200"),
+            Some((1_792_060_496, 120))
+        );
+        assert_eq!(
+            Signature::parse_date("2026-10-15T12:34:56-0700"),
+            Some((1_792_092_896, -420))
+        );
+        assert_eq!(Signature::parse_date("1700000000 +0100 extra"), None);
+        assert_eq!(Signature::parse_date("1700000000 nonsense"), None);
+        assert_eq!(Signature::parse_date("not a date"), None);
+    }
+}
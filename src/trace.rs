@@ -0,0 +1,127 @@
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Commands, object reads and writes, and ref updates.
+pub(crate) static TRACE: TraceKey = TraceKey::new("GIT_TRACE", "trace");
+/// The pkt-lines sent and received, in the format of git's own packet traces.
+pub(crate) static TRACE_PACKET: TraceKey = TraceKey::new("GIT_TRACE_PACKET", "packet");
+
+/// Where the lines of a trace go.
+enum Target {
+    Stderr,
+    File(Mutex<File>),
+}
+
+/// A trace turned on by its environment variable, as git's are: `1`, `2` or `true` write it to
+/// stderr, and an absolute path appends it to that file. It is off when the variable is unset,
+/// empty, `0` or `false`.
+pub(crate) struct TraceKey {
+    var: &'static str,
+    prefix: &'static str,
+    target: OnceLock<Option<Target>>,
+}
+
+impl TraceKey {
+    const fn new(var: &'static str, prefix: &'static str) -> Self {
+        Self {
+            var,
+            prefix,
+            target: OnceLock::new(),
+        }
+    }
+
+    fn target(&self) -> Option<&Target> {
+        self.target.get_or_init(|| self.open()).as_ref()
+    }
+
+    fn open(&self) -> Option<Target> {
+        let value = std::env::var(self.var).ok()?;
+        match value.to_ascii_lowercase().as_str() {
+            "" | "0" | "false" => None,
+            "1" | "2" | "true" => Some(Target::Stderr),
+            _ if value.starts_with('/') => {
+                match OpenOptions::new().append(true).create(true).open(&value) {
+                    Ok(file) => Some(Target::File(Mutex::new(file))),
+                    Err(e) => {
+                        eprintln!("warning: could not open '{value}' for tracing: {e}");
+                        None
+                    }
+                }
+            }
+            _ => {
+                eprintln!(
+                    "warning: unknown trace value for '{}': {value}\n         If you want to \
+                     trace into a file, then please set {} to an absolute pathname (starting \
+                     with /)",
+                    self.var, self.var
+                );
+                None
+            }
+        }
+    }
+
+    /// Whether the trace is on. The [`trace!`] macro checks it before formatting anything.
+    pub(crate) fn enabled(&self) -> bool {
+        self.target().is_some()
+    }
+
+    /// Write `message` as one line of the trace, after the time of day (in UTC) and the source
+    /// location it comes from, padded to line the messages up as git does.
+    pub(crate) fn write(&self, file: &str, line: u32, message: fmt::Arguments) {
+        let Some(target) = self.target() else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let secs = now.as_secs() % 86400;
+        let location = format!(
+            "{:02}:{:02}:{:02}.{:06} {file}:{line}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            now.subsec_micros()
+        );
+        let out = format!("{location:40}{}: {message}\n", self.prefix);
+        // a failing trace mustn't fail the command
+        let _ = match target {
+            Target::Stderr => std::io::stderr().write_all(out.as_bytes()),
+            Target::File(file) => match file.lock() {
+                Ok(mut file) => file.write_all(out.as_bytes()),
+                Err(_) => Ok(()),
+            },
+        };
+    }
+}
+
+/// Write a line to a [`TraceKey`], like `trace!(TRACE, "ref update: {name}")`. The message is
+/// only formatted when the trace is on.
+macro_rules! trace {
+    ($key:expr, $($arg:tt)*) => {
+        if $key.enabled() {
+            $key.write(file!(), line!(), format_args!($($arg)*));
+        }
+    };
+}
+pub(crate) use trace;
+
+/// `args` as a shell would need them, the way git quotes the commands it traces: arguments
+/// with characters other than letters, digits and `-_+=:,./@%` are single-quoted.
+pub(crate) fn quote_args(args: &[String]) -> String {
+    let quote = |arg: &String| {
+        let plain = !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_+=:,./@%".contains(c));
+        match plain {
+            true => arg.clone(),
+            false => format!("'{}'", arg.replace('\'', r"'\''").replace('!', r"'\!'")),
+        }
+    };
+    args.iter().map(quote).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,269 @@
+use std::ops::Range;
+
+/// Prefixes of lines git itself adds to messages. A trailer block that contains one of them only
+/// needs a quarter of its lines to be trailers.
+const GIT_GENERATED_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+
+/// A `Key: value` line at the end of a commit message, such as `Signed-off-by`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Trailer {
+    pub(crate) key: String,
+    pub(crate) value: String,
+}
+
+impl Trailer {
+    /// Parse `Key: value` (or `Key=value`, as accepted on the command line).
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let at = separator(text, ":=")?;
+        Some(Self {
+            key: text[..at].trim().to_string(),
+            value: text[at + 1..].trim().to_string(),
+        })
+    }
+
+    /// Whether `self` and `other` are the same trailer; keys compare case-insensitively.
+    fn same_as(&self, other: &Trailer) -> bool {
+        self.key.eq_ignore_ascii_case(&other.key) && self.value == other.value
+    }
+}
+
+impl std::fmt::Display for Trailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+/// The position of the separator in a `Key: value` line, if the line has that shape: a
+/// non-empty key of letters, digits and `-`, optionally followed by whitespace.
+fn separator(line: &str, separators: &str) -> Option<usize> {
+    let mut whitespace_found = false;
+    for (i, c) in line.char_indices() {
+        if separators.contains(c) {
+            return (i > 0).then_some(i);
+        }
+        if !whitespace_found && (c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if i > 0 && c.is_whitespace() {
+            whitespace_found = true;
+            continue;
+        }
+        return None;
+    }
+    None
+}
+
+/// Lines of `message`, each with its line ending.
+fn lines(message: &str) -> Vec<&str> {
+    message.split_inclusive('\n').collect()
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+fn is_comment(line: &str) -> bool {
+    line.starts_with('#')
+}
+
+/// The end of the part of `lines` that trailers are looked for in: before a `---` patch divider,
+/// and before trailing comment and blank lines.
+fn message_end(lines: &[&str]) -> usize {
+    let mut end = lines
+        .iter()
+        .position(|l| l.trim_end() == "---" || l.starts_with("--- "))
+        .unwrap_or(lines.len());
+    while end > 0 && (is_blank(lines[end - 1]) || is_comment(lines[end - 1])) {
+        end -= 1;
+    }
+    end
+}
+
+/// The lines of `lines[..end]` that form the trailer block, following git's rules: the block is
+/// the last paragraph, which can't be the first one (the subject). Every line in it must be a
+/// trailer or a continuation line (starting with whitespace), except that if a git-generated
+/// trailer is present, only a quarter of the lines need to be trailers.
+fn trailer_block(lines: &[&str], end: usize) -> Option<Range<usize>> {
+    let title_end = lines
+        .iter()
+        .position(|l| is_blank(l))
+        .unwrap_or(lines.len());
+    let (mut trailer_lines, mut non_trailer_lines, mut continuation_lines) = (0, 0, 0);
+    let mut recognized_prefix = false;
+    for at in (title_end..end).rev() {
+        let line = lines[at];
+        if is_comment(line) {
+            continue;
+        }
+        if is_blank(line) {
+            let start = at + 1;
+            if recognized_prefix && trailer_lines * 3 >= non_trailer_lines {
+                return Some(start..end);
+            }
+            if trailer_lines > 0 && non_trailer_lines == 0 {
+                return Some(start..end);
+            }
+            return None;
+        }
+        if GIT_GENERATED_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            trailer_lines += 1;
+            recognized_prefix = true;
+            continue;
+        }
+        if separator(line, ":").is_some() {
+            trailer_lines += 1;
+            // continuation lines belong to this trailer
+            continuation_lines = 0;
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            continuation_lines += 1;
+            continue;
+        }
+        non_trailer_lines += 1 + continuation_lines;
+        continuation_lines = 0;
+    }
+    None
+}
+
+/// The trailers at the end of `message`. Continuation lines are folded into their trailer's
+/// value, and lines of the block that aren't trailers are left out.
+pub(crate) fn parse_trailers(message: &str) -> Vec<Trailer> {
+    let lines = lines(message);
+    let Some(block) = trailer_block(&lines, message_end(&lines)) else {
+        return Vec::new();
+    };
+    let mut trailers: Vec<Trailer> = Vec::new();
+    let mut last_was_trailer = false;
+    for line in &lines[block] {
+        if is_comment(line) {
+            continue;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some(trailer) = trailers.last_mut().filter(|_| last_was_trailer) {
+                trailer.value.push(' ');
+                trailer.value.push_str(line.trim());
+            }
+            continue;
+        }
+        let trailer = separator(line, ":").and_then(|_| Trailer::parse(line));
+        last_was_trailer = trailer.is_some();
+        trailers.extend(trailer);
+    }
+    trailers
+}
+
+/// Add `trailer` to the end of the trailer block of `message`, starting a new block if there is
+/// none. Nothing is added if the message already has the same trailer, so adding is idempotent.
+pub(crate) fn add_trailer(message: &str, trailer: &Trailer) -> String {
+    if parse_trailers(message).iter().any(|t| t.same_as(trailer)) {
+        return message.to_string();
+    }
+    let lines = lines(message);
+    let end = message_end(&lines);
+    let has_block = trailer_block(&lines, end).is_some();
+
+    let mut out: String = lines[..end].concat();
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if !has_block && !out.is_empty() {
+        out.push('\n');
+    }
+    out.push_str(&format!("{trailer}\n"));
+    out.push_str(&lines[end..].concat());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trailer(key: &str, value: &str) -> Trailer {
+        Trailer {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_the_last_paragraph() {
+        let message = "Subject\n\nBody text.\n\nReviewed-by: Ann <ann@example.com>\nFixes: #12\n";
+        assert_eq!(
+            parse_trailers(message),
+            [
+                trailer("Reviewed-by", "Ann <ann@example.com>"),
+                trailer("Fixes", "#12")
+            ]
+        );
+    }
+
+    #[test]
+    fn colons_in_the_body_are_not_trailers() {
+        // a paragraph with prose around a colon-ish line isn't a trailer block
+        let message = "Subject\n\nNote: this is prose,\nwhich goes on here.\n";
+        assert_eq!(parse_trailers(message), []);
+        // nor is a time, a URL or a key with spaces
+        for line in [
+            "At 10:30 it broke",
+            "see https://example.com/x",
+            "Two words: no",
+        ] {
+            assert_eq!(
+                parse_trailers(&format!("Subject\n\n{line}\n")),
+                [],
+                "{line}"
+            );
+        }
+        // and the subject is never one
+        assert_eq!(parse_trailers("Fix: the bug\n"), []);
+        // while a colon-ish line earlier in the body doesn't hide the real block
+        let message = "Subject\n\nCaveat: see below.\n\nAcked-by: Bob\n";
+        assert_eq!(parse_trailers(message), [trailer("Acked-by", "Bob")]);
+    }
+
+    #[test]
+    fn continuation_lines_and_git_generated_blocks() {
+        let message = "Subject\n\nLong-trailer: first part\n  second part\n";
+        assert_eq!(
+            parse_trailers(message),
+            [trailer("Long-trailer", "first part second part")]
+        );
+        // with a git-generated trailer, a quarter of the lines being trailers is enough
+        let message = "Subject\n\nsome text\nmore text\nSigned-off-by: Ann <a@b>\n";
+        assert_eq!(
+            parse_trailers(message),
+            [trailer("Signed-off-by", "Ann <a@b>")]
+        );
+    }
+
+    #[test]
+    fn co_authored_by_after_a_body() {
+        let message = "Subject\n\nPaired on this.\n\n\
+                       Co-authored-by: Bob Builder <bob@example.com>\n\
+                       Co-authored-by: Ann <ann@example.com>\n";
+        assert_eq!(
+            parse_trailers(message),
+            [
+                trailer("Co-authored-by", "Bob Builder <bob@example.com>"),
+                trailer("Co-authored-by", "Ann <ann@example.com>")
+            ]
+        );
+    }
+
+    #[test]
+    fn trailers_end_before_a_patch_and_comments() {
+        let message = "Subject\n\nFixes: #1\n# a comment\n---\nNot-a-trailer: x\n";
+        assert_eq!(parse_trailers(message), [trailer("Fixes", "#1")]);
+    }
+
+    #[test]
+    fn adding_starts_or_extends_the_block_once() {
+        let signoff = trailer("Signed-off-by", "Ann <a@b>");
+        let added = add_trailer("Subject\n\nBody.\n", &signoff);
+        assert_eq!(added, "Subject\n\nBody.\n\nSigned-off-by: Ann <a@b>\n");
+        assert_eq!(add_trailer(&added, &signoff), added);
+        let extended = add_trailer("Subject\n\nFixes: #1\n", &signoff);
+        assert_eq!(extended, "Subject\n\nFixes: #1\nSigned-off-by: Ann <a@b>\n");
+    }
+}
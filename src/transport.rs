@@ -0,0 +1,820 @@
+//! The client side of the git protocol: reaching the `git-upload-pack` or `git-receive-pack`
+//! behind a remote URL and talking to it in pkt-lines over the service's stdin and stdout.
+//!
+//! Local repositories run the service as a child process; ssh remotes run it on the other host
+//! through the system `ssh`. Either way the conversation is the same byte stream, so fetching
+//! and pushing don't need to know which transport carries it. Over HTTP it is the same stream,
+//! cut into requests where the client flushes (see [`crate::http`]).
+//!
+//! Fetches ask for protocol v2 (unless `protocol.version` says otherwise), where the client
+//! lists only the refs it is interested in, and fall back to v0 when the server doesn't speak
+//! it. Pushes always use v0.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader, BufWriter, Cursor, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    http,
+    objects::{object_exists, object_read, write_object, Kind},
+    pack::read_pack_stream,
+    pkt_line::{read_packet, read_pkt, read_pkt_text, write_delim, write_flush, write_pkt, Packet},
+    repository::GitRepository,
+    revwalk::RevWalk,
+};
+
+/// What git-rs calls itself in the `agent` capability.
+pub(crate) const AGENT: &str = "agent=git-rs/0.1.0";
+
+/// Where a remote repository is.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum RemoteUrl {
+    /// A repository on this machine, from a path or a `file://` URL.
+    Local(PathBuf),
+    /// A repository reached through ssh, from `ssh://[user@]host[:port]/path` or the scp-like
+    /// `[user@]host:path`. `host` keeps the user, as ssh takes them together.
+    Ssh {
+        host: String,
+        port: Option<String>,
+        path: String,
+    },
+    /// A repository behind a smart HTTP server, from an `http://` or `https://` URL.
+    Http(String),
+}
+
+impl RemoteUrl {
+    /// Tell which transport `url` needs, the way git does: a URL scheme decides, and otherwise
+    /// a colon before the first slash makes an scp-like ssh address, unless the whole thing is
+    /// a path that exists.
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        if let Some(path) = url.strip_prefix("file://") {
+            return Ok(Self::Local(PathBuf::from(path)));
+        }
+        for scheme in ["ssh://", "git+ssh://", "ssh+git://"] {
+            let Some(rest) = url.strip_prefix(scheme) else {
+                continue;
+            };
+            let (authority, path) = rest
+                .split_once('/')
+                .with_context(|| format!("no path in ssh URL {url}"))?;
+            // `ssh://host/~user/repo` is relative to a home directory
+            let path = match path.strip_prefix('~') {
+                Some(home) => format!("~{home}"),
+                None => format!("/{path}"),
+            };
+            // a port comes after the last colon, unless that is inside an IPv6 `[...]`
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) if !port.contains(']') => (host, Some(port.to_string())),
+                _ => (authority, None),
+            };
+            return Self::ssh(host, port.filter(|port| !port.is_empty()), path);
+        }
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http(url.to_string()));
+        }
+        if let Some((scheme, _)) = url.split_once("://") {
+            bail!("unsupported transport '{scheme}' for {url}");
+        }
+        match url.split_once(':') {
+            Some((host, path))
+                if !host.is_empty() && !host.contains('/') && !Path::new(url).exists() =>
+            {
+                Self::ssh(host, None, path.to_string())
+            }
+            _ => Ok(Self::Local(PathBuf::from(url))),
+        }
+    }
+
+    /// An ssh address, unless a part of it would look like an option to ssh or to the remote
+    /// command: like git, such a URL (`ssh://-oProxyCommand=.../repo`, say) is refused rather
+    /// than letting it run commands here.
+    fn ssh(host: &str, port: Option<String>, path: String) -> Result<Self> {
+        let host = host.replace(['[', ']'], "");
+        if host.starts_with('-') {
+            bail!("strange hostname '{host}' blocked");
+        }
+        if let Some(port) = port.as_ref().filter(|port| port.starts_with('-')) {
+            bail!("strange port '{port}' blocked");
+        }
+        if path.starts_with('-') {
+            bail!("strange pathname '{path}' blocked");
+        }
+        Ok(Self::Ssh { host, port, path })
+    }
+}
+
+/// The git services a client talks to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Service {
+    UploadPack,
+    ReceivePack,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Service::UploadPack => write!(f, "git-upload-pack"),
+            Service::ReceivePack => write!(f, "git-receive-pack"),
+        }
+    }
+}
+
+/// Quote `text` for the shell the way git does, in single quotes.
+fn sq_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The command running ssh, found like git does: `$GIT_SSH_COMMAND` and `core.sshCommand` are
+/// shell commands, `$GIT_SSH` a program, and plain `ssh` is the default. Returns the command
+/// and whether it is a shell command.
+fn ssh_command(git_repo: &GitRepository) -> (String, bool) {
+    if let Ok(command) = std::env::var("GIT_SSH_COMMAND") {
+        return (command, true);
+    }
+    if let Some(command) = git_repo.config_get("core", "sshCommand") {
+        return (command.to_string(), true);
+    }
+    match std::env::var("GIT_SSH") {
+        Ok(program) => (program, false),
+        Err(_) => ("ssh".to_string(), false),
+    }
+}
+
+/// Whether the ssh command takes OpenSSH's options (`-p <port>`): `ssh.variant` or
+/// `$GIT_SSH_VARIANT` say so, or else the program is called `ssh`.
+fn ssh_takes_options(git_repo: &GitRepository, command: &str, shell: bool) -> bool {
+    let variant = std::env::var("GIT_SSH_VARIANT")
+        .ok()
+        .or_else(|| git_repo.config_get("ssh", "variant").map(str::to_string));
+    match variant.as_deref() {
+        Some("ssh") => true,
+        Some(_) => false,
+        None => {
+            let program = match shell {
+                true => command.split_whitespace().next().unwrap_or_default(),
+                false => command,
+            };
+            Path::new(program)
+                .file_name()
+                .is_some_and(|name| name == "ssh")
+        }
+    }
+}
+
+/// A running conversation with a git service.
+pub(crate) struct Connection {
+    /// The service's process; none over HTTP, where each request is answered on its own.
+    child: Option<Child>,
+    service: Service,
+    /// What the service sends.
+    pub(crate) input: Box<dyn BufRead>,
+    /// What the service receives; flushed when a request is complete.
+    pub(crate) output: Box<dyn Write>,
+}
+
+impl Connection {
+    /// Start `service` for the repository at `url`, running `program` for it (by default
+    /// `git-upload-pack` or `git-receive-pack`, which git's remotes set with
+    /// `remote.<name>.uploadpack` and `remote.<name>.receivepack`; over HTTP the server picks
+    /// it). Whatever the service prints on stderr goes to ours.
+    pub(crate) fn open(
+        git_repo: &GitRepository,
+        url: &str,
+        service: Service,
+        program: Option<&str>,
+    ) -> Result<Self> {
+        let default = service.to_string();
+        let program = program.unwrap_or(&default);
+        // the server learns which version the client wants from $GIT_PROTOCOL
+        let v2 = service == Service::UploadPack
+            && git_repo.config_get("protocol", "version").unwrap_or("2") == "2";
+        let mut command = match RemoteUrl::parse(url)? {
+            RemoteUrl::Local(path) => {
+                if !path.exists() {
+                    bail!(
+                        "'{}' does not appear to be a git repository",
+                        path.display()
+                    );
+                }
+                let mut command = Command::new("sh");
+                command
+                    .arg("-c")
+                    .arg(format!("{program} {}", sq_quote(&path.to_string_lossy())));
+                command
+            }
+            RemoteUrl::Ssh { host, port, path } => {
+                let (ssh, shell) = ssh_command(git_repo);
+                let openssh = ssh_takes_options(git_repo, &ssh, shell);
+                let mut args = Vec::new();
+                // OpenSSH only passes the variable on when asked to
+                if v2 && openssh {
+                    args.extend(["-o".to_string(), "SendEnv=GIT_PROTOCOL".to_string()]);
+                }
+                if let Some(port) = port {
+                    if !openssh {
+                        bail!("ssh variant '{ssh}' does not support setting port");
+                    }
+                    args.extend(["-p".to_string(), port]);
+                }
+                // the host was checked, but an option can't sneak in after `--` regardless
+                if openssh {
+                    args.push("--".to_string());
+                }
+                args.push(host);
+                args.push(format!("{program} {}", sq_quote(&path)));
+                let mut command = match shell {
+                    true => {
+                        let mut command = Command::new("sh");
+                        command.arg("-c").arg(format!("{ssh} \"$@\"")).arg(&ssh);
+                        command
+                    }
+                    false => Command::new(&ssh),
+                };
+                command.args(args);
+                command
+            }
+            RemoteUrl::Http(url) => {
+                let (input, output) = http::connect(git_repo, &url, service, v2)?;
+                return Ok(Self {
+                    child: None,
+                    service,
+                    input,
+                    output,
+                });
+            }
+        };
+        if v2 {
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("run {program} for {url}"))?;
+        let input = Box::new(BufReader::new(child.stdout.take().context("no stdout")?));
+        let output = Box::new(BufWriter::new(child.stdin.take().context("no stdin")?));
+        Ok(Self {
+            child: Some(child),
+            service,
+            input,
+            output,
+        })
+    }
+
+    /// Tell the service there is nothing (more) to ask for, with a flush-pkt, and end the
+    /// conversation. Over HTTP there is no service waiting to be told.
+    pub(crate) fn disconnect(mut self) -> Result<()> {
+        if self.child.is_none() {
+            return Ok(());
+        }
+        write_flush(&mut self.output)?;
+        self.output.flush()?;
+        self.finish()
+    }
+
+    /// End the conversation: close our side and wait for the service to exit.
+    pub(crate) fn finish(self) -> Result<()> {
+        let Self {
+            child,
+            service,
+            input,
+            output,
+        } = self;
+        drop(output);
+        drop(input);
+        let Some(mut child) = child else {
+            return Ok(());
+        };
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{service} exited with {status}");
+        }
+        Ok(())
+    }
+}
+
+/// The versions of the git protocol.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// The original protocol (and version 1, which only says so first): the server lists all
+    /// its refs at once.
+    #[default]
+    V0,
+    /// The client sends commands such as `ls-refs` and `fetch` to the server.
+    V2,
+}
+
+/// What a service says first: its refs and what it can do.
+#[derive(Debug, Default)]
+pub(crate) struct Advertisement {
+    pub(crate) protocol: Protocol,
+    /// The refs and their hashes, in the order sent, with the peeled `<tag>^{}` entries of
+    /// annotated tags.
+    pub(crate) refs: Vec<(String, String)>,
+    /// The capabilities; in protocol v2 one per line as the server sent them, along with the
+    /// `symref=` entries of the v0 form for the symbolic refs `ls-refs` found.
+    pub(crate) capabilities: Vec<String>,
+}
+
+impl Advertisement {
+    /// Read a protocol v0 ref advertisement, which lists one ref per pkt-line with the
+    /// capabilities after the first, and ends with a flush-pkt; or, from a server that speaks
+    /// protocol v2, its capability advertisement, leaving the refs to [`Self::list_refs`].
+    pub(crate) fn read(input: &mut impl Read) -> Result<Self> {
+        let mut advertisement = Self::default();
+        while let Some(line) = read_pkt_text(input)? {
+            if let Some(message) = line.strip_prefix("ERR ") {
+                bail!("remote error: {message}");
+            }
+            // a version 1 server says so first
+            if line == "version 1" {
+                continue;
+            }
+            if line == "version 2" {
+                advertisement.protocol = Protocol::V2;
+                continue;
+            }
+            if advertisement.protocol == Protocol::V2 {
+                advertisement.capabilities.push(line);
+                continue;
+            }
+            let (line, capabilities) = match line.split_once('\0') {
+                Some((line, capabilities)) => (line, Some(capabilities)),
+                None => (line.as_str(), None),
+            };
+            if let Some(capabilities) = capabilities {
+                advertisement.capabilities = capabilities.split(' ').map(str::to_string).collect();
+            }
+            let Some((hash, name)) = line.split_once(' ') else {
+                bail!("protocol error: unexpected {line:?}");
+            };
+            // an empty repository advertises only its capabilities
+            if name != "capabilities^{}" {
+                advertisement
+                    .refs
+                    .push((name.to_string(), hash.to_string()));
+            }
+        }
+        Ok(advertisement)
+    }
+
+    /// Ask a protocol v2 server for its refs that start with one of `prefixes` (all of them if
+    /// there are none), with `ls-refs`, and add them as a v0 advertisement would have them. A v0
+    /// advertisement already has its refs.
+    pub(crate) fn list_refs(
+        &mut self,
+        git_repo: &GitRepository,
+        connection: &mut Connection,
+        prefixes: &[String],
+    ) -> Result<()> {
+        if self.protocol != Protocol::V2 {
+            return Ok(());
+        }
+        let out = &mut connection.output;
+        self.write_command(git_repo, out, "ls-refs")?;
+        for arg in ["peel", "symrefs"] {
+            write_pkt(out, format!("{arg}\n").as_bytes())?;
+        }
+        for prefix in prefixes {
+            write_pkt(out, format!("ref-prefix {prefix}\n").as_bytes())?;
+        }
+        write_flush(out)?;
+        out.flush()?;
+
+        while let Some(line) = read_pkt_text(&mut connection.input)? {
+            let mut fields = line.split(' ');
+            let (Some(hash), Some(name)) = (fields.next(), fields.next()) else {
+                bail!("protocol error: unexpected ls-refs line {line:?}");
+            };
+            self.refs.push((name.to_string(), hash.to_string()));
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    self.capabilities.push(format!("symref={name}:{target}"));
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    self.refs.push((format!("{name}^{{}}"), peeled.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start the protocol v2 command `command`: its name, the capabilities that go with it,
+    /// and the delim-pkt before its arguments.
+    fn write_command(
+        &self,
+        git_repo: &GitRepository,
+        out: &mut impl Write,
+        command: &str,
+    ) -> Result<()> {
+        write_pkt(out, format!("command={command}\n").as_bytes())?;
+        write_pkt(out, format!("{AGENT}\n").as_bytes())?;
+        if self
+            .capabilities
+            .iter()
+            .any(|c| c.starts_with("object-format="))
+        {
+            let format = git_repo.hash_algo().name();
+            write_pkt(out, format!("object-format={format}\n").as_bytes())?;
+        }
+        write_delim(out)
+    }
+
+    /// Whether the service advertised the capability `name`.
+    pub(crate) fn has(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|c| c == name)
+    }
+
+    /// The target of the symbolic ref `name` (like `HEAD`), from a `symref=` capability.
+    pub(crate) fn symref(&self, name: &str) -> Option<&str> {
+        self.capabilities.iter().find_map(|c| {
+            let (symref, target) = c.strip_prefix("symref=")?.split_once(':')?;
+            (symref == name).then_some(target)
+        })
+    }
+
+    /// The hash of the ref `name`, if it was advertised.
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, hash)| hash.as_str())
+    }
+}
+
+/// Pass a progress message from the remote on to stderr the way git does: each line after
+/// `remote: `, and padded (or cleared to the end on a terminal) so a line that `\r` rewrites
+/// leaves nothing of the one before.
+fn show_remote_message(message: &[u8]) -> std::io::Result<()> {
+    let dumb = std::env::var("TERM").map_or(true, |term| term == "dumb");
+    let suffix = match std::io::stderr().is_terminal() && !dumb {
+        true => "\x1b[K",
+        false => "        ",
+    };
+    let stderr = std::io::stderr();
+    let mut stderr = stderr.lock();
+    for line in message.split_inclusive(|&b| b == b'\n' || b == b'\r') {
+        let (text, end) = match line.split_last() {
+            Some((&end, text)) if end == b'\n' || end == b'\r' => (text, Some(end)),
+            _ => (line, None),
+        };
+        stderr.write_all(b"remote: ")?;
+        stderr.write_all(text)?;
+        if !text.is_empty() {
+            stderr.write_all(suffix.as_bytes())?;
+        }
+        if let Some(end) = end {
+            stderr.write_all(&[end])?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the data band of a side-band stream as one byte stream, passing progress messages on
+/// to stderr and failing on an error message. A flush-pkt ends the stream.
+pub(crate) struct Demux<'a, R> {
+    input: &'a mut R,
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a, R: Read> Demux<'a, R> {
+    pub(crate) fn new(input: &'a mut R) -> Self {
+        Self {
+            input,
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        }
+    }
+
+    /// Read the rest of the stream, up to its flush-pkt.
+    pub(crate) fn finish(mut self) -> Result<()> {
+        while !self.fill_buf()?.is_empty() {
+            self.pos = self.buf.len();
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Demux<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: Read> BufRead for Demux<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        while self.pos == self.buf.len() && !self.done {
+            let Some(pkt) = read_pkt(self.input).map_err(std::io::Error::other)? else {
+                self.done = true;
+                break;
+            };
+            match pkt.split_first() {
+                Some((1, data)) => {
+                    self.buf = data.to_vec();
+                    self.pos = 0;
+                }
+                Some((2, message)) => show_remote_message(message)?,
+                Some((3, message)) => {
+                    return Err(std::io::Error::other(format!(
+                        "remote error: {}",
+                        String::from_utf8_lossy(message).trim_end()
+                    )))
+                }
+                _ => {
+                    return Err(std::io::Error::other(
+                        "protocol error: bad side-band packet",
+                    ))
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// Read a pack from `input` into the object store of `git_repo`. Deltas in a thin pack may be
+/// against objects the repository already has.
+pub(crate) fn receive_pack(git_repo: &GitRepository, input: &mut impl BufRead) -> Result<usize> {
+    let objects = read_pack_stream(input, git_repo.hash_algo(), |hash| {
+        let Ok(obj) = object_read(git_repo, hash) else {
+            return Ok(None);
+        };
+        Ok(Some((obj.format().parse::<Kind>()?, obj.serialize())))
+    })?;
+    for (kind, data) in &objects {
+        write_object(git_repo, *kind, data)?;
+    }
+    Ok(objects.len())
+}
+
+/// The most commits beyond the ref tips offered as `have`s in a fetch.
+const MAX_HAVES: usize = 256;
+
+/// Fetch the objects `wants` over an upload-pack conversation whose advertisement has been
+/// read, telling the server which commits `git_repo` has so that it sends only what is
+/// missing, and store them. The haves are offered all at once, followed by `done`, so that the
+/// server answers with the pack straight away. Without `progress` the server is asked to keep
+/// quiet about its work.
+pub(crate) fn fetch_pack(
+    git_repo: &GitRepository,
+    connection: &mut Connection,
+    advertisement: &Advertisement,
+    wants: &[String],
+    haves: &[String],
+    progress: bool,
+) -> Result<()> {
+    match advertisement.protocol {
+        Protocol::V0 => fetch_pack_v0(git_repo, connection, advertisement, wants, haves, progress)?,
+        Protocol::V2 => fetch_pack_v2(git_repo, connection, advertisement, wants, haves, progress)?,
+    }
+    for want in wants {
+        if !object_exists(git_repo, want)? {
+            bail!("remote did not send all necessary objects");
+        }
+    }
+    Ok(())
+}
+
+/// [`fetch_pack`] in protocol v0, where the capabilities go with the first want and the server
+/// answers `done` with its ACKs or a NAK before the pack.
+fn fetch_pack_v0(
+    git_repo: &GitRepository,
+    connection: &mut Connection,
+    advertisement: &Advertisement,
+    wants: &[String],
+    haves: &[String],
+    progress: bool,
+) -> Result<()> {
+    let mut capabilities = Vec::new();
+    let sideband = if advertisement.has("side-band-64k") {
+        capabilities.push("side-band-64k");
+        true
+    } else if advertisement.has("side-band") {
+        capabilities.push("side-band");
+        true
+    } else {
+        false
+    };
+    for capability in ["thin-pack", "ofs-delta", "include-tag"] {
+        if advertisement.has(capability) {
+            capabilities.push(capability);
+        }
+    }
+    if !progress && advertisement.has("no-progress") {
+        capabilities.push("no-progress");
+    }
+    capabilities.push(AGENT);
+
+    let out = &mut connection.output;
+    for (i, want) in wants.iter().enumerate() {
+        match i {
+            0 => write_pkt(
+                out,
+                format!("want {want} {}\n", capabilities.join(" ")).as_bytes(),
+            )?,
+            _ => write_pkt(out, format!("want {want}\n").as_bytes())?,
+        }
+    }
+    write_flush(out)?;
+    for have in haves {
+        write_pkt(out, format!("have {have}\n").as_bytes())?;
+    }
+    write_pkt(out, b"done\n")?;
+    out.flush()?;
+
+    // without multi_ack the server may ACK several haves before the pack starts, so whatever
+    // follows the last ACK or NAK is put back in front of the rest as the start of the pack
+    let mut acked = false;
+    let start = loop {
+        let mut head = [0; 4];
+        connection
+            .input
+            .read_exact(&mut head)
+            .context("read fetch response")?;
+        if &head == b"PACK" {
+            break head.to_vec();
+        }
+        let pkt = read_pkt(&mut Cursor::new(head).chain(&mut connection.input))?
+            .context("protocol error: unexpected flush-pkt")?;
+        if pkt.starts_with(b"ACK ") || pkt == b"NAK\n" {
+            acked = true;
+            continue;
+        }
+        if let Some(message) = pkt.strip_prefix(b"ERR ") {
+            bail!(
+                "remote error: {}",
+                String::from_utf8_lossy(message).trim_end()
+            );
+        }
+        if !acked || !sideband {
+            bail!("protocol error: expected ACK or NAK");
+        }
+        let mut start = format!("{:04x}", pkt.len() + 4).into_bytes();
+        start.extend(pkt);
+        break start;
+    };
+    let mut input = BufReader::new(Cursor::new(start).chain(&mut connection.input));
+    if sideband {
+        let mut demux = Demux::new(&mut input);
+        receive_pack(git_repo, &mut demux)?;
+        demux.finish()?;
+    } else {
+        receive_pack(git_repo, &mut input)?;
+    }
+    Ok(())
+}
+
+/// [`fetch_pack`] in protocol v2: a `fetch` command, answered by a response in sections of
+/// which only the `packfile`, always in side-band, matters here.
+fn fetch_pack_v2(
+    git_repo: &GitRepository,
+    connection: &mut Connection,
+    advertisement: &Advertisement,
+    wants: &[String],
+    haves: &[String],
+    progress: bool,
+) -> Result<()> {
+    let out = &mut connection.output;
+    advertisement.write_command(git_repo, out, "fetch")?;
+    for arg in ["thin-pack", "ofs-delta", "include-tag"] {
+        write_pkt(out, format!("{arg}\n").as_bytes())?;
+    }
+    if !progress {
+        write_pkt(out, b"no-progress\n")?;
+    }
+    for want in wants {
+        write_pkt(out, format!("want {want}\n").as_bytes())?;
+    }
+    for have in haves {
+        write_pkt(out, format!("have {have}\n").as_bytes())?;
+    }
+    write_pkt(out, b"done\n")?;
+    write_flush(out)?;
+    out.flush()?;
+
+    loop {
+        let header = match read_packet(&mut connection.input)? {
+            Packet::Data(data) => String::from_utf8_lossy(&data).trim_end().to_string(),
+            Packet::Delim => continue,
+            Packet::Flush | Packet::ResponseEnd => {
+                bail!("protocol error: no packfile in the fetch response")
+            }
+        };
+        if let Some(message) = header.strip_prefix("ERR ") {
+            bail!("remote error: {message}");
+        }
+        if header == "packfile" {
+            break;
+        }
+        // the other sections (acknowledgments, shallow-info, wanted-refs) end at a delim-pkt
+        loop {
+            match read_packet(&mut connection.input)? {
+                Packet::Data(_) => {}
+                Packet::Delim => break,
+                Packet::Flush | Packet::ResponseEnd => {
+                    bail!("protocol error: no packfile in the fetch response")
+                }
+            }
+        }
+    }
+    let mut demux = Demux::new(&mut connection.input);
+    receive_pack(git_repo, &mut demux)?;
+    demux.finish()
+}
+
+/// The commits to offer as `have`s: the tips of `tips` first, where what the remote already has
+/// most likely is, then their history, newest first, up to [`MAX_HAVES`] more.
+pub(crate) fn haves(git_repo: &GitRepository, tips: &[String]) -> Result<Vec<String>> {
+    let mut haves = Vec::new();
+    let mut walk = RevWalk::new(git_repo);
+    for tip in tips {
+        if !haves.contains(tip)
+            && object_read(git_repo, tip).is_ok_and(|obj| obj.format() == "commit")
+        {
+            haves.push(tip.clone());
+            walk.push(tip)?;
+        }
+    }
+    for commit in walk.take(MAX_HAVES) {
+        let (hash, _) = commit?;
+        if !haves.contains(&hash) {
+            haves.push(hash);
+        }
+    }
+    Ok(haves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh(host: &str, port: Option<&str>, path: &str) -> RemoteUrl {
+        RemoteUrl::Ssh {
+            host: host.to_string(),
+            port: port.map(str::to_string),
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_remote_urls() {
+        assert_eq!(
+            RemoteUrl::parse("git@example.com:user/repo.git").unwrap(),
+            ssh("git@example.com", None, "user/repo.git")
+        );
+        assert_eq!(
+            RemoteUrl::parse("ssh://git@example.com:2222/srv/repo.git").unwrap(),
+            ssh("git@example.com", Some("2222"), "/srv/repo.git")
+        );
+        assert_eq!(
+            RemoteUrl::parse("ssh://example.com/~user/repo").unwrap(),
+            ssh("example.com", None, "~user/repo")
+        );
+        assert_eq!(
+            RemoteUrl::parse("ssh://[::1]:22/repo").unwrap(),
+            ssh("::1", Some("22"), "/repo")
+        );
+        assert_eq!(
+            RemoteUrl::parse("file:///srv/repo").unwrap(),
+            RemoteUrl::Local(PathBuf::from("/srv/repo"))
+        );
+        assert_eq!(
+            RemoteUrl::parse("../a:b/repo").unwrap(),
+            RemoteUrl::Local(PathBuf::from("../a:b/repo"))
+        );
+        assert_eq!(
+            RemoteUrl::parse("https://example.com/repo.git").unwrap(),
+            RemoteUrl::Http("https://example.com/repo.git".to_string())
+        );
+        assert!(RemoteUrl::parse("ftp://example.com/repo").is_err());
+        for url in [
+            "ssh://-oProxyCommand=touch%20pwned/repo",
+            "ssh://[-oProxyCommand=x]/repo",
+            "ssh://host:-1/repo",
+            "-oProxyCommand=x:repo",
+            "host:-repo",
+        ] {
+            assert!(RemoteUrl::parse(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(sq_quote("/srv/it's"), r"'/srv/it'\''s'");
+    }
+}
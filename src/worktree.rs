@@ -0,0 +1,711 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    ignore::PatternList,
+    index::Index,
+    interrupt::{self, TempPath},
+    repository::{optional_locks_allowed, repo_file, GitRepository},
+};
+
+/// Run `f` on every item with a bounded pool of threads, returning the results in item order.
+pub(crate) fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    f: impl Fn(&T) -> Result<R> + Sync,
+) -> Result<Vec<R>> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if workers <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|s| {
+        let handles = (0..workers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(i) else {
+                            return Ok::<_, anyhow::Error>(done);
+                        };
+                        done.push((i, f(item)?));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut results = Vec::with_capacity(items.len());
+        for handle in handles {
+            results.extend(handle.join().expect("work tree scanning thread panicked")?);
+        }
+        Ok::<_, anyhow::Error>(results)
+    })?;
+    results.sort_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, r)| r).collect())
+}
+
+/// The ignore rules in effect in a directory: pattern lists with the directory they apply to,
+/// from lowest to highest precedence.
+#[derive(Clone)]
+struct Rules {
+    lists: Vec<(String, Arc<PatternList>)>,
+    /// Identifies the rules, so cached listings made under other rules aren't used.
+    stamp: [u8; 20],
+    /// Patterns match regardless of case (`core.ignorecase`).
+    ignore_case: bool,
+}
+
+impl Rules {
+    /// The rules of `dir` (`""` or ending in `/`): these ones plus its own `.gitignore`.
+    fn enter(&self, dir: &str, gitignore: Option<String>) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(self.stamp);
+        let mut lists = self.lists.clone();
+        if let Some(text) = gitignore {
+            hasher.update(&text);
+            let mut list = PatternList::parse(&text);
+            if self.ignore_case {
+                list = list.ignoring_case();
+            }
+            lists.push((dir.to_string(), Arc::new(list)));
+        }
+        Self {
+            lists,
+            stamp: hasher.finalize().into(),
+            ignore_case: self.ignore_case,
+        }
+    }
+
+    /// Whether `path` is ignored; the deepest `.gitignore` with a matching pattern decides.
+    fn ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.lists
+            .iter()
+            .rev()
+            .find_map(|(base, list)| list.matched(path.strip_prefix(base.as_str())?, is_dir))
+            .unwrap_or(false)
+    }
+}
+
+/// What a directory holds, apart from tracked files and ignored paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Listing {
+    mtime: (i64, i64),
+    /// The ignore rules and the tracked paths below the directory the listing was made with.
+    rules: [u8; 20],
+    tracked: [u8; 20],
+    /// The untracked files, and nested repositories as `name/`.
+    files: Vec<String>,
+    dirs: Vec<String>,
+}
+
+/// Directory listings from an earlier scan, stored in `.git/untracked-cache`. A listing is
+/// reused while its directory's mtime, ignore rules and tracked files stay the same, so
+/// unchanged directories don't have to be read again.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct UntrackedCache {
+    dirs: HashMap<String, Listing>,
+}
+
+const CACHE_HEADER: &str = "git-rs untracked cache 2";
+
+impl UntrackedCache {
+    /// Load the cache of `git_repo`; a missing or unreadable cache is empty.
+    pub(crate) fn load(git_repo: &GitRepository) -> Result<Self> {
+        let path = repo_file(git_repo, &["untracked-cache"], false)?;
+        let Ok(data) = fs::read(&path) else {
+            return Ok(Self::default());
+        };
+        Ok(Self::parse(git_repo, &data).unwrap_or_default())
+    }
+
+    /// After the header and the work tree come the number of records and the records, as NUL
+    /// separated fields: the directory, its mtime, the rules and tracked stamps, then each list
+    /// as a count followed by the names.
+    fn parse(git_repo: &GitRepository, data: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(data).ok()?;
+        let mut fields = text.split('\0');
+        // a cache copied along with the repository describes other directories
+        if fields.next()? != CACHE_HEADER || fields.next()? != git_repo.work_tree().to_str()? {
+            return None;
+        }
+        let count = fields.next()?.parse::<usize>().ok()?;
+        let mut dirs = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let dir = fields.next()?;
+            let mut number = || fields.next()?.parse::<i64>().ok();
+            let mtime = (number()?, number()?);
+            let mut stamp =
+                || -> Option<[u8; 20]> { hex::decode(fields.next()?).ok()?.try_into().ok() };
+            let (rules, tracked) = (stamp()?, stamp()?);
+            let mut list = || -> Option<Vec<String>> {
+                let count = fields.next()?.parse::<usize>().ok()?;
+                (0..count)
+                    .map(|_| fields.next().map(str::to_string))
+                    .collect()
+            };
+            let (files, subdirs) = (list()?, list()?);
+            dirs.insert(
+                dir.to_string(),
+                Listing {
+                    mtime,
+                    rules,
+                    tracked,
+                    files,
+                    dirs: subdirs,
+                },
+            );
+        }
+        Some(Self { dirs })
+    }
+
+    fn serialize(&self, git_repo: &GitRepository) -> Vec<u8> {
+        let mut fields = vec![
+            CACHE_HEADER.to_string(),
+            git_repo.work_tree().to_string_lossy().into_owned(),
+            self.dirs.len().to_string(),
+        ];
+        let mut dirs = self.dirs.iter().collect::<Vec<_>>();
+        dirs.sort_by_key(|(dir, _)| dir.as_str());
+        for (dir, listing) in dirs {
+            fields.push(dir.clone());
+            fields.push(listing.mtime.0.to_string());
+            fields.push(listing.mtime.1.to_string());
+            fields.push(hex::encode(listing.rules));
+            fields.push(hex::encode(listing.tracked));
+            for list in [&listing.files, &listing.dirs] {
+                fields.push(list.len().to_string());
+                fields.extend(list.iter().cloned());
+            }
+        }
+        fields.join("\0").into_bytes()
+    }
+
+    /// Save the cache, unless optional locks are disabled. The file is replaced atomically, so
+    /// concurrent scans never wait for each other.
+    pub(crate) fn save(&self, git_repo: &GitRepository) -> Result<()> {
+        if !optional_locks_allowed() {
+            return Ok(());
+        }
+        let path = repo_file(git_repo, &["untracked-cache"], false)?;
+        let tmp = TempPath::new(path.with_extension(format!("tmp{}", std::process::id())));
+        fs::write(tmp.path(), self.serialize(git_repo))
+            .with_context(|| format!("write {}", tmp.path().display()))?;
+        tmp.persist(&path)
+            .with_context(|| format!("replace {}", path.display()))
+    }
+}
+
+/// What a walk for untracked files leaves out, and the listings of an earlier walk it reuses.
+struct Untracked<'a> {
+    /// Every path in the index, folded to lower case under `core.ignorecase` like the keys of
+    /// `tracked_dirs`.
+    tracked_files: HashSet<Cow<'a, str>>,
+    /// The stamps of [`tracked_dirs`].
+    tracked_dirs: HashMap<String, [u8; 20]>,
+    ignore_case: bool,
+    cache: &'a UntrackedCache,
+    /// Directories modified in the same second as the walk started may change again without
+    /// their mtime changing, so they aren't cached.
+    started: i64,
+}
+
+/// The paths a walk found below one directory, and the listings made on the way.
+#[derive(Default)]
+struct Found {
+    paths: Vec<String>,
+    listings: HashMap<String, Listing>,
+}
+
+impl Untracked<'_> {
+    /// `path` as it is looked up in `tracked_files` and `tracked_dirs`.
+    fn key<'p>(&self, path: &'p str) -> Cow<'p, str> {
+        fold_case(self.ignore_case, path)
+    }
+
+    /// The listing of `dir` (`""` or ending in `/`), from the cache if it is still valid.
+    fn list(
+        &self,
+        git_repo: &GitRepository,
+        dir: &str,
+        rules: &Rules,
+        found: &mut Found,
+    ) -> Result<Option<Listing>> {
+        let full = git_repo.work_tree().join(dir);
+        let Ok(meta) = fs::metadata(&full) else {
+            // gone since its parent was listed
+            return Ok(None);
+        };
+        let mtime = (meta.mtime(), meta.mtime_nsec());
+        let tracked = self
+            .tracked_dirs
+            .get(self.key(dir).as_ref())
+            .copied()
+            .unwrap_or_default();
+        if let Some(cached) = self.cache.dirs.get(dir).filter(|cached| {
+            cached.mtime == mtime && cached.rules == rules.stamp && cached.tracked == tracked
+        }) {
+            found.listings.insert(dir.to_string(), cached.clone());
+            return Ok(Some(cached.clone()));
+        }
+
+        let mut listing = Listing {
+            mtime,
+            rules: rules.stamp,
+            tracked,
+            files: Vec::new(),
+            dirs: Vec::new(),
+        };
+        for (name, is_dir, entry) in unignored_entries(git_repo, dir, rules)? {
+            if self
+                .tracked_files
+                .contains(&self.key(&format!("{dir}{name}")))
+            {
+                continue;
+            }
+            match (is_dir, is_dir && is_repository(&entry)) {
+                (false, _) => listing.files.push(name),
+                (true, true) => listing.files.push(format!("{name}/")),
+                (true, false) => listing.dirs.push(name),
+            }
+        }
+        listing.files.sort();
+        listing.dirs.sort();
+        if mtime.0 < self.started {
+            found.listings.insert(dir.to_string(), listing.clone());
+        }
+        Ok(Some(listing))
+    }
+}
+
+/// The rules for the directory `dir` inside a directory with `rules`.
+fn rules_for(git_repo: &GitRepository, dir: &str, rules: &Rules) -> Rules {
+    let gitignore = fs::read_to_string(git_repo.work_tree().join(dir).join(".gitignore"));
+    rules.enter(dir, gitignore.ok())
+}
+
+/// The entries of the work tree directory `dir` (`""` or ending in `/`), as their names,
+/// whether they are directories, and the entries themselves. `.git` and names that aren't UTF-8
+/// are left out.
+fn dir_entries(git_repo: &GitRepository, dir: &str) -> Result<Vec<(String, bool, fs::DirEntry)>> {
+    let full = git_repo.work_tree().join(dir);
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&full).with_context(|| format!("read {}", full.display()))? {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name == ".git" {
+            continue;
+        }
+        let is_dir = entry.file_type()?.is_dir();
+        entries.push((name, is_dir, entry));
+    }
+    Ok(entries)
+}
+
+/// The [`dir_entries`] of `dir` that `rules` don't ignore.
+///
+/// Every walk of the work tree that leaves ignored files out lists directories through here, so
+/// an ignored directory is dropped from its parent's listing and never read itself.
+fn unignored_entries(
+    git_repo: &GitRepository,
+    dir: &str,
+    rules: &Rules,
+) -> Result<Vec<(String, bool, fs::DirEntry)>> {
+    let mut entries = dir_entries(git_repo, dir)?;
+    entries.retain(|(name, is_dir, _)| !rules.ignored(&format!("{dir}{name}"), *is_dir));
+    Ok(entries)
+}
+
+/// Whether the directory `entry` is a nested repository, which walks don't descend into.
+fn is_repository(entry: &fs::DirEntry) -> bool {
+    entry.path().join(".git").exists()
+}
+
+/// A file found by a [`WorktreeWalker`], with its stat data.
+pub(crate) struct WalkEntry {
+    /// The path from the top of the work tree.
+    pub(crate) path: String,
+    /// The file's metadata, not following symlinks.
+    pub(crate) metadata: fs::Metadata,
+}
+
+/// What a [`WorktreeWalker`] does with the files the ignore rules match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ignored {
+    /// Leave them out, without reading ignored directories.
+    Skip,
+    /// Report them like any other file.
+    Include,
+    /// Report only them, including every file below an ignored directory.
+    Only,
+}
+
+/// Whether the `/`-separated path `path`, like a work tree path or a ref name, is `spec` or below
+/// it (everything for an empty `spec`).
+pub(crate) fn path_matches(spec: &str, path: &str) -> bool {
+    spec.is_empty()
+        || path == spec
+        || path
+            .strip_prefix(spec)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Walks the work tree for the files that aren't ignored. Ignored directories are pruned before
+/// they are read, so a `target/` or `node_modules/` in `.gitignore` costs a pattern match
+/// rather than a listing of everything below it.
+pub(crate) struct WorktreeWalker<'a> {
+    git_repo: &'a GitRepository,
+    parallel: bool,
+    ignored: Ignored,
+}
+
+impl<'a> WorktreeWalker<'a> {
+    pub(crate) fn new(git_repo: &'a GitRepository) -> Self {
+        Self {
+            git_repo,
+            parallel: false,
+            ignored: Ignored::Skip,
+        }
+    }
+
+    /// Walk the subdirectories of the starting directory on a pool of threads.
+    pub(crate) fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Report ignored files too, or only them, rather than pruning them.
+    pub(crate) fn ignored(mut self, ignored: Ignored) -> Self {
+        self.ignored = ignored;
+        self
+    }
+
+    /// The files below `dir` (`""` for the whole work tree, or a path ending in `/`) that aren't
+    /// ignored, sorted by path. A nested repository is a single entry for its directory, which
+    /// isn't descended into; directories are otherwise not entries of their own.
+    pub(crate) fn walk(&self, dir: &str) -> Result<Vec<WalkEntry>> {
+        let found = self.find(dir, None)?;
+        let stat = |path: &String| {
+            let full = self.git_repo.work_tree().join(path);
+            let metadata =
+                fs::symlink_metadata(&full).with_context(|| format!("stat {}", full.display()))?;
+            Ok(WalkEntry {
+                path: path.clone(),
+                metadata,
+            })
+        };
+        match self.parallel {
+            true => parallel_map(&found.paths, stat),
+            false => found.paths.iter().map(stat).collect(),
+        }
+    }
+
+    /// The untracked files of the work tree, sorted; ignored files are left out whatever
+    /// [`Self::ignored`] asks for. Directories without tracked files are reported as `dir/`
+    /// rather than file by file, and so are nested repositories.
+    ///
+    /// `cache` lets directories that didn't change since the last walk skip being read. The
+    /// cache is updated with this walk's listings.
+    pub(crate) fn untracked(
+        &self,
+        index: &Index,
+        cache: &mut UntrackedCache,
+    ) -> Result<Vec<String>> {
+        let ignore_case = self.git_repo.ignorecase();
+        let untracked = Untracked {
+            tracked_files: index
+                .entries
+                .iter()
+                .map(|e| fold_case(ignore_case, &e.path))
+                .collect(),
+            tracked_dirs: tracked_dirs(index)
+                .into_iter()
+                .map(|(dir, stamp)| (fold_case(ignore_case, &dir).into_owned(), stamp))
+                .collect(),
+            ignore_case,
+            cache,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64),
+        };
+        let found = self.find("", Some(&untracked))?;
+        // what is below a directory without tracked files is reported as that directory
+        let mut paths = found
+            .paths
+            .into_iter()
+            .map(|path| {
+                let top = path
+                    .match_indices('/')
+                    .map(|(end, _)| &path[..=end])
+                    .find(|dir| {
+                        !untracked
+                            .tracked_dirs
+                            .contains_key(untracked.key(dir).as_ref())
+                    })
+                    .map(str::to_string);
+                top.unwrap_or(path)
+            })
+            .collect::<Vec<_>>();
+        paths.dedup();
+        cache.dirs = found.listings;
+        Ok(paths)
+    }
+
+    /// The paths below `dir` this walk reports, sorted, or for a walk for `untracked` files the
+    /// untracked ones along with the listings made on the way.
+    fn find(&self, dir: &str, untracked: Option<&Untracked>) -> Result<Found> {
+        // the .gitignore files of the directories above `dir` apply in it too
+        let mut rules = global_rules(self.git_repo)?;
+        let mut parent = 0;
+        while let Some(slash) = dir[parent..].find('/') {
+            rules = rules_for(self.git_repo, &dir[..parent], &rules);
+            parent += slash + 1;
+        }
+        let rules = rules_for(self.git_repo, dir, &rules);
+
+        // a directory only holds ignored files when it is ignored itself
+        let in_ignored = self.ignored == Ignored::Only
+            && dir
+                .match_indices('/')
+                .any(|(end, _)| rules.ignored(&dir[..end], true));
+        let mut found = Found::default();
+        let mut subdirs = Vec::new();
+        self.list(dir, &rules, in_ignored, untracked, &mut found, &mut subdirs)?;
+        let walk = |(sub, in_ignored): &(String, bool)| {
+            let mut found = Found::default();
+            self.walk_below(sub, &rules, *in_ignored, untracked, &mut found)?;
+            Ok(found)
+        };
+        let nested = match self.parallel {
+            true => parallel_map(&subdirs, walk)?,
+            false => subdirs.iter().map(walk).collect::<Result<_>>()?,
+        };
+        for inner in nested {
+            found.paths.extend(inner.paths);
+            found.listings.extend(inner.listings);
+        }
+        found.paths.sort_unstable();
+        Ok(found)
+    }
+
+    /// Add the paths below the directory `dir` inside a directory with `rules` to `found`.
+    /// `in_ignored` tells that `dir` is ignored, and so is everything in it.
+    fn walk_below(
+        &self,
+        dir: &str,
+        rules: &Rules,
+        in_ignored: bool,
+        untracked: Option<&Untracked>,
+        found: &mut Found,
+    ) -> Result<()> {
+        interrupt::check()?;
+        let rules = rules_for(self.git_repo, dir, rules);
+        let mut subdirs = Vec::new();
+        self.list(dir, &rules, in_ignored, untracked, found, &mut subdirs)?;
+        for (sub, in_ignored) in subdirs {
+            self.walk_below(&sub, &rules, in_ignored, untracked, found)?;
+        }
+        Ok(())
+    }
+
+    /// Add the paths of `dir` to `found` and the subdirectories to walk (ending in `/`) to
+    /// `subdirs`, with whether they are ignored.
+    fn list(
+        &self,
+        dir: &str,
+        rules: &Rules,
+        in_ignored: bool,
+        untracked: Option<&Untracked>,
+        found: &mut Found,
+        subdirs: &mut Vec<(String, bool)>,
+    ) -> Result<()> {
+        if let Some(untracked) = untracked {
+            if let Some(listing) = untracked.list(self.git_repo, dir, rules, found)? {
+                let files = listing.files.iter().map(|name| format!("{dir}{name}"));
+                found.paths.extend(files);
+                let dirs = listing
+                    .dirs
+                    .iter()
+                    .map(|name| (format!("{dir}{name}/"), false));
+                subdirs.extend(dirs);
+            }
+            return Ok(());
+        }
+        let entries = match self.ignored {
+            Ignored::Skip => unignored_entries(self.git_repo, dir, rules)?,
+            Ignored::Include | Ignored::Only => dir_entries(self.git_repo, dir)?,
+        };
+        for (name, is_dir, entry) in entries {
+            let path = format!("{dir}{name}");
+            let ignored =
+                self.ignored == Ignored::Only && (in_ignored || rules.ignored(&path, is_dir));
+            if is_dir && !is_repository(&entry) {
+                subdirs.push((format!("{path}/"), ignored));
+                continue;
+            }
+            if self.ignored == Ignored::Only && !ignored {
+                continue;
+            }
+            found.paths.push(path);
+        }
+        Ok(())
+    }
+}
+
+/// The ignore rules that apply everywhere: `.git/info/exclude` and `core.excludesFile`.
+fn global_rules(git_repo: &GitRepository) -> Result<Rules> {
+    let ignore_case = git_repo.ignorecase();
+    let mut rules = Rules {
+        lists: Vec::new(),
+        stamp: [u8::from(ignore_case); 20],
+        ignore_case,
+    };
+    let exclude = repo_file(git_repo, &["info", "exclude"], false)?;
+    rules = rules.enter("", fs::read_to_string(exclude).ok());
+    if let Some(file) = git_repo.config_get("core", "excludesFile") {
+        let path = match file.strip_prefix("~/") {
+            Some(rest) => {
+                std::path::PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest)
+            }
+            None => std::path::PathBuf::from(file),
+        };
+        rules = rules.enter("", fs::read_to_string(path).ok());
+    }
+    Ok(rules)
+}
+
+/// `path` in lower case if `ignore_case`, so that paths differing only in case compare equal.
+fn fold_case(ignore_case: bool, path: &str) -> Cow<'_, str> {
+    if ignore_case {
+        Cow::Owned(path.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(path)
+    }
+}
+
+/// Every directory (with a trailing `/`) that has index entries below it, with a hash of the
+/// names of its tracked files and of its subdirectories that have tracked files. Those names are
+/// what a directory's listing depends on besides the directory itself.
+fn tracked_dirs(index: &Index) -> HashMap<String, [u8; 20]> {
+    let mut dirs = HashMap::new();
+    // the directories of the previous entry, with their names so far; the entries are sorted,
+    // so a directory's entries are all next to each other
+    let mut open = vec![("", Sha1::new())];
+    for entry in &index.entries {
+        let path = entry.path.as_str();
+        while !path.starts_with(open.last().expect("the top is never closed").0) {
+            let (dir, hasher) = open.pop().expect("the top is never closed");
+            dirs.insert(dir.to_string(), hasher.finalize().into());
+        }
+        let mut start = open.last().expect("the top is never closed").0.len();
+        while let Some(slash) = path[start..].find('/') {
+            let end = start + slash + 1;
+            let parent = &mut open.last_mut().expect("the top is never closed").1;
+            parent.update(&path[start..end]);
+            parent.update("\0");
+            open.push((&path[..end], Sha1::new()));
+            start = end;
+        }
+        let parent = &mut open.last_mut().expect("the top is never closed").1;
+        parent.update(&path[start..]);
+        parent.update("\0");
+    }
+    if !index.entries.is_empty() {
+        dirs.extend(
+            open.into_iter()
+                .map(|(dir, hasher)| (dir.to_string(), hasher.finalize().into())),
+        );
+    }
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{repository::repo_open, test_util::TempDir};
+
+    /// A work tree ignoring `target/` and `*.log`, where `target/` has a `.gitignore` of its own
+    /// that would bring `target/keep` back if the directory were read.
+    fn fixture() -> TempDir {
+        let dir = TempDir::new();
+        dir.git(&["init", "-q"], b"");
+        for (path, contents) in [
+            (".gitignore", "target/\n*.log\n"),
+            ("a", "a\n"),
+            ("dir/b", "b\n"),
+            ("dir/c.log", "log\n"),
+            ("target/.gitignore", "!keep\n"),
+            ("target/keep", "keep\n"),
+            ("target/deep/x", "x\n"),
+        ] {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn paths(walker: &WorktreeWalker, dir: &str) -> Vec<String> {
+        let entries = walker.walk(dir).unwrap();
+        entries.into_iter().map(|entry| entry.path).collect()
+    }
+
+    #[test]
+    fn walk_prunes_ignored_directories() {
+        let dir = fixture();
+        let git_repo = repo_open(dir.path()).unwrap();
+        let walker = WorktreeWalker::new(&git_repo);
+        assert_eq!(paths(&walker, ""), [".gitignore", "a", "dir/b"]);
+        assert_eq!(paths(&walker, "dir/"), ["dir/b"]);
+        assert_eq!(
+            paths(&walker.parallel(true), ""),
+            [".gitignore", "a", "dir/b"]
+        );
+    }
+
+    #[test]
+    fn walk_matches_the_unpruned_walk_minus_ignored_paths() {
+        let dir = fixture();
+        let git_repo = repo_open(dir.path()).unwrap();
+        let all = paths(
+            &WorktreeWalker::new(&git_repo).ignored(Ignored::Include),
+            "",
+        );
+        assert_eq!(
+            all,
+            [
+                ".gitignore",
+                "a",
+                "dir/b",
+                "dir/c.log",
+                "target/.gitignore",
+                "target/deep/x",
+                "target/keep"
+            ]
+        );
+        let ignored = paths(&WorktreeWalker::new(&git_repo).ignored(Ignored::Only), "");
+        let mut rest = all.clone();
+        rest.retain(|path| !ignored.contains(path));
+        assert_eq!(paths(&WorktreeWalker::new(&git_repo), ""), rest);
+
+        let by_git = dir.git(
+            &["ls-files", "--others", "--ignored", "--exclude-standard"],
+            b"",
+        );
+        assert_eq!(ignored, by_git.lines().collect::<Vec<_>>());
+    }
+}
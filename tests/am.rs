@@ -0,0 +1,48 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn format_patch_then_am_reproduces_the_commits() {
+    let repo = Repo::init();
+    repo.write("a", "one\ntwo\nthree\n");
+    let base = repo.commit_all("base");
+    repo.git(&["checkout", "--quiet", "-b", "topic"]);
+    for (i, author) in ["Ann <ann@example.com>", "Bob Ross <bob@example.org>"]
+        .into_iter()
+        .enumerate()
+    {
+        repo.write("a", format!("one\ntwo {i}\nthree\n"));
+        repo.write(&format!("new{i}"), format!("file {i}\n"));
+        repo.git(&["add", "--all"]);
+        repo.git(&[
+            "commit",
+            "--quiet",
+            "--author",
+            author,
+            "--date",
+            &format!("{} +0200", 1600000000 + i),
+            "-m",
+            &format!("change {i}\n\nWith a body\nof two lines."),
+        ]);
+    }
+
+    let mbox = repo.run(&["format-patch", "--stdout", &format!("{base}..topic")]);
+    repo.write(".git/patches.mbox", &mbox);
+    repo.git(&["checkout", "--quiet", "-b", "fresh", &base]);
+    repo.run(&["am", ".git/patches.mbox"]);
+
+    assert_eq!(
+        repo.rev_parse("HEAD^{tree}"),
+        repo.rev_parse("topic^{tree}")
+    );
+    let log = |rev: &str| {
+        repo.git(&[
+            "log",
+            "--format=%an <%ae> %ad%n%B",
+            &format!("{base}..{rev}"),
+        ])
+    };
+    assert_eq!(log("HEAD"), log("topic"));
+    assert!(log("HEAD").starts_with("Bob Ross <bob@example.org> "));
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with `f` holding the lines 1 to 20 committed, and `.git/p.patch` changing its
+/// lines 3 and 17 and adding `n`, written by git.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    let lines: String = (1..=20).map(|n| format!("{n}\n")).collect();
+    repo.write("f", &lines);
+    repo.commit_all("one");
+    repo.write(
+        "f",
+        lines
+            .replace("\n3\n", "\nthree\n")
+            .replace("\n17\n", "\nseventeen\n"),
+    );
+    repo.write("n", "new\n");
+    repo.git(&["add", "--intent-to-add", "n"]);
+    let patch = repo.git(&["diff"]);
+    repo.write(".git/p.patch", patch);
+    repo.git(&["reset", "--quiet", "--hard"]);
+    repo
+}
+
+/// The file `f` of the fixture with `replacements` made, as `(line, replacement)`.
+fn lines_with(replacements: &[(&str, &str)]) -> String {
+    (1..=20)
+        .map(|n| {
+            let line = n.to_string();
+            let line = replacements
+                .iter()
+                .find(|(from, _)| *from == line)
+                .map_or(line, |(_, to)| to.to_string());
+            format!("{line}\n")
+        })
+        .collect()
+}
+
+#[test]
+fn applies_cleanly_and_in_reverse() {
+    let repo = fixture();
+    repo.run(&["apply", ".git/p.patch"]);
+    assert_eq!(
+        repo.read("f"),
+        lines_with(&[("3", "three"), ("17", "seventeen")])
+    );
+    assert_eq!(repo.read("n"), "new\n");
+    // the index is left alone
+    assert_eq!(repo.git(&["status", "--porcelain"]), " M f\n?? n\n");
+
+    repo.run(&["apply", "-R", ".git/p.patch"]);
+    assert_eq!(repo.read("f"), lines_with(&[]));
+    assert!(!repo.join("n").exists());
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+    // the patch can come from standard input
+    let patch = repo.read(".git/p.patch");
+    repo.run_with_input(&["apply"], patch.as_bytes());
+    assert_eq!(repo.read("n"), "new\n");
+}
+
+#[test]
+fn check_changes_nothing() {
+    let repo = fixture();
+    assert_eq!(repo.run(&["apply", "--check", ".git/p.patch"]), "");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+    repo.write("f", lines_with(&[("16", "sixteen")]));
+    let stderr = repo.fails(&["apply", "--check", ".git/p.patch"]);
+    assert_eq!(
+        stderr,
+        "error: patch failed: f:14\nerror: f: patch does not apply\n"
+    );
+    assert_eq!(repo.read("f"), lines_with(&[("16", "sixteen")]));
+    assert!(!repo.join("n").exists());
+}
+
+#[test]
+fn a_conflicting_hunk_fails_the_whole_patch() {
+    let repo = fixture();
+    repo.write("f", lines_with(&[("16", "sixteen")]));
+    let stderr = repo.fails(&["apply", ".git/p.patch"]);
+    assert!(
+        stderr.contains("error: f: patch does not apply"),
+        "{stderr}"
+    );
+    assert_eq!(repo.read("f"), lines_with(&[("16", "sixteen")]));
+    assert!(!repo.join("n").exists());
+}
+
+#[test]
+fn reject_applies_what_it_can() {
+    let repo = fixture();
+    repo.write("f", lines_with(&[("16", "sixteen")]));
+    let output = repo
+        .git_rs(&["apply", "--reject", ".git/p.patch"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Applying patch f with 1 reject...\n"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("Rejected hunk #2.\n"), "{stderr}");
+
+    assert_eq!(
+        repo.read("f"),
+        lines_with(&[("3", "three"), ("16", "sixteen")])
+    );
+    assert_eq!(repo.read("n"), "new\n");
+    assert_eq!(
+        repo.read("f.rej"),
+        "diff a/f b/f\t(rejected hunks)\n\
+         @@ -14,7 +14,7 @@\n 14\n 15\n 16\n-17\n+seventeen\n 18\n 19\n 20\n"
+    );
+}
+
+#[test]
+fn hunks_apply_at_an_offset_and_with_less_context() {
+    let repo = fixture();
+    // two lines more at the top move every hunk down
+    let moved = format!("0\n00\n{}", lines_with(&[]));
+    repo.write("f", &moved);
+    repo.run(&["apply", ".git/p.patch"]);
+    assert_eq!(
+        repo.read("f"),
+        format!(
+            "0\n00\n{}",
+            lines_with(&[("3", "three"), ("17", "seventeen")])
+        )
+    );
+
+    // a changed outer context line only matches with -C
+    let repo = fixture();
+    repo.write("f", lines_with(&[("20", "twenty")]));
+    repo.fails(&["apply", ".git/p.patch"]);
+    repo.run(&["apply", "-C2", ".git/p.patch"]);
+    assert_eq!(
+        repo.read("f"),
+        lines_with(&[("3", "three"), ("17", "seventeen"), ("20", "twenty")])
+    );
+}
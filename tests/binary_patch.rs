@@ -0,0 +1,102 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// Bytes that look like an image: a PNG signature, then data with NULs in it.
+fn image(seed: u32, len: usize) -> Vec<u8> {
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut state = seed;
+    bytes.extend((0..len).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+    }));
+    bytes
+}
+
+/// A repository where the last commit changes `image.png`, with the mail format-patch writes
+/// for it.
+fn fixture(old: &[u8], new: &[u8]) -> (Repo, String) {
+    let repo = Repo::init();
+    repo.write("image.png", old);
+    repo.commit_all("add the image");
+    repo.write("image.png", new);
+    repo.commit_all("change the image");
+    let patch = repo.run(&["format-patch", "--stdout", "HEAD~1..HEAD"]);
+    assert!(patch.contains("GIT binary patch\n"), "{patch}");
+    (repo, patch)
+}
+
+/// Check that the patch from `old` to `new` has a forward hunk of `kind` (`literal` or `delta`)
+/// and that it applies in both directions.
+fn round_trip(old: &[u8], new: &[u8], kind: &str) {
+    let (repo, patch) = fixture(old, new);
+    assert!(
+        patch.contains(&format!("GIT binary patch\n{kind} ")),
+        "{patch}"
+    );
+    repo.git(&["checkout", "-q", "HEAD~1", "--", "image.png"]);
+    repo.run_with_input(&["apply"], patch.as_bytes());
+    assert_eq!(fs::read(repo.join("image.png")).unwrap(), new);
+
+    repo.run_with_input(&["apply", "-R"], patch.as_bytes());
+    assert_eq!(fs::read(repo.join("image.png")).unwrap(), old);
+
+    // git applies it the same way
+    repo.git_with_input(&["apply"], patch.as_bytes());
+    assert_eq!(fs::read(repo.join("image.png")).unwrap(), new);
+}
+
+#[test]
+fn a_small_change_round_trips_as_a_delta() {
+    let old = image(1, 4000);
+    let mut new = old.clone();
+    new[2000..2010].copy_from_slice(b"0123456789");
+    round_trip(&old, &new, "delta");
+}
+
+#[test]
+fn a_rewrite_round_trips_as_a_literal() {
+    round_trip(&image(1, 300), &image(2, 500), "literal");
+}
+
+#[test]
+fn applies_binary_patches_git_wrote() {
+    let old = image(1, 4000);
+    let mut new = old.clone();
+    new.truncate(3000);
+    let (repo, _) = fixture(&old, &new);
+    let patch = repo.git(&["diff", "--binary", "HEAD~1", "HEAD"]);
+    repo.git(&["checkout", "-q", "HEAD~1", "--", "image.png"]);
+    repo.run_with_input(&["apply"], patch.as_bytes());
+    assert_eq!(fs::read(repo.join("image.png")).unwrap(), new);
+}
+
+#[test]
+fn refuses_a_patch_for_other_content() {
+    let (repo, patch) = fixture(&image(1, 300), &image(2, 300));
+    repo.write("image.png", image(3, 300));
+    let err = repo.fails_with_input(&["apply"], patch.as_bytes());
+    assert!(err.contains("image.png"), "{err}");
+    assert_eq!(fs::read(repo.join("image.png")).unwrap(), image(3, 300));
+}
+
+#[test]
+fn format_patch_takes_git_binary_options() {
+    let (repo, patch) = fixture(&image(1, 300), &image(2, 500));
+    let args = ["format-patch", "--stdout", "HEAD~1..HEAD"];
+    assert_eq!(repo.run(&[&args[..], &["--binary"]].concat()), patch);
+
+    let without = repo.run(&[&args[..], &["--no-binary"]].concat());
+    assert!(!without.contains("GIT binary patch"), "{without}");
+    assert!(
+        without.contains("\nBinary files a/image.png and b/image.png differ\n"),
+        "{without}"
+    );
+    // the last one given wins
+    assert_eq!(
+        repo.run(&[&args[..], &["--no-binary", "--binary"]].concat()),
+        patch
+    );
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::Repo;
+
+/// A history of eight commits `c1`..`c8`, each writing its number to `n`, returning their hashes.
+fn history() -> (Repo, Vec<String>) {
+    let repo = Repo::init();
+    let commits = (1..=8)
+        .map(|n| {
+            repo.write("n", format!("{n}\n"));
+            repo.commit_all(&format!("c{n}"))
+        })
+        .collect();
+    (repo, commits)
+}
+
+/// The number in `n` of the commit checked out.
+fn checked_out(repo: &Repo) -> u32 {
+    repo.read("n").trim().parse().unwrap()
+}
+
+#[test]
+fn finds_the_first_bad_commit_by_hand() {
+    let (repo, commits) = history();
+    repo.run(&["bisect", "start", "HEAD", "HEAD~7"]);
+    // the bug came in with c6
+    let found = loop {
+        let verdict = if checked_out(&repo) >= 6 {
+            "bad"
+        } else {
+            "good"
+        };
+        let out = repo.run(&["bisect", verdict]);
+        if out.contains("is the first bad commit") {
+            break out;
+        }
+    };
+    assert!(
+        found.starts_with(&format!("{} is the first bad commit\n", commits[5])),
+        "{found}"
+    );
+    assert!(found.contains("\n    c6\n"), "{found}");
+
+    repo.run(&["bisect", "reset"]);
+    assert_eq!(
+        repo.git(&["symbolic-ref", "HEAD"]).trim(),
+        "refs/heads/master"
+    );
+    assert_eq!(checked_out(&repo), 8);
+    assert!(!repo.join(".git/BISECT_START").exists());
+}
+
+#[test]
+fn run_passes_the_command_its_arguments_as_given() {
+    let (repo, commits) = history();
+    repo.run(&["bisect", "start", "HEAD", "HEAD~7"]);
+    let out = repo.run(&["bisect", "run", "sh", "-c", "test $(cat n) -lt 3"]);
+    assert!(
+        out.contains(&format!("{} is the first bad commit\n", commits[2])),
+        "{out}"
+    );
+}
+
+#[test]
+fn run_skips_untestable_commits() {
+    let (repo, commits) = history();
+    repo.run(&["bisect", "start", "HEAD", "HEAD~7"]);
+    // c4 can't be tested; the bug came in with c6
+    let script = "n=$(cat n); test $n -eq 4 && exit 125; test $n -lt 6";
+    let out = repo.run(&["bisect", "run", "sh", "-c", script]);
+    assert!(
+        out.contains(&format!("{} is the first bad commit\n", commits[5])),
+        "{out}"
+    );
+}
+
+#[test]
+fn run_lists_the_candidates_when_skips_hide_the_answer() {
+    let (repo, commits) = history();
+    repo.run(&["bisect", "start", "HEAD", "HEAD~7"]);
+    // c4 can't be tested and the bug came in with c5, so either may have brought it
+    let script = "n=$(cat n); test $n -eq 4 && exit 125; test $n -lt 5";
+    let output = repo
+        .git_rs(&["bisect", "run", "sh", "-c", script])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        out.contains("The first bad commit could be any of:\n"),
+        "{out}"
+    );
+    assert!(out.contains(&format!("{}\n", commits[3])), "{out}");
+    assert!(out.contains(&format!("{}\n", commits[4])), "{out}");
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn contains_lists_only_branches_with_the_commit() {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    let base = repo.commit_all("base");
+    repo.git(&["branch", "old"]);
+    repo.write("a", "2\n");
+    let feature = repo.commit_all("feature");
+    repo.git(&["branch", "with-feature"]);
+
+    assert_eq!(
+        repo.run(&["branch", "--contains", &feature]),
+        "* master\n  with-feature\n"
+    );
+    assert_eq!(
+        repo.run(&["branch", "--contains", &base]),
+        "* master\n  old\n  with-feature\n"
+    );
+    // a commit on another branch only
+    repo.git(&["checkout", "--quiet", "old"]);
+    repo.write("b", "b\n");
+    let side = repo.commit_all("side");
+    assert_eq!(repo.run(&["branch", "--contains", &side]), "* old\n");
+}
+
+/// A clone of a one-commit repository, with two commits of its own on `master`.
+fn ahead_clone() -> (Repo, Repo) {
+    let server = Repo::init();
+    server.write("a", "a\n");
+    server.commit_all("upstream");
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", &server.path.display().to_string(), "."]);
+    clone.git(&["config", "advice.statusHints", "false"]);
+    for n in 0..2 {
+        clone.write("b", format!("{n}\n"));
+        clone.commit_all(&format!("local {n}"));
+    }
+    (server, clone)
+}
+
+#[test]
+fn status_counts_commits_ahead_of_the_upstream() {
+    let (server, clone) = ahead_clone();
+    let status = clone.run(&["status"]);
+    assert!(
+        status.contains("Your branch is ahead of 'origin/master' by 2 commits.\n"),
+        "{status}"
+    );
+    assert_eq!(status, clone.git(&["status"]));
+
+    server.write("a", "changed\n");
+    server.commit_all("upstream again");
+    clone.git(&["fetch", "-q"]);
+    let status = clone.run(&["status"]);
+    assert!(
+        status.contains("and have 2 and 1 different commits each, respectively.\n"),
+        "{status}"
+    );
+    assert_eq!(status, clone.git(&["status"]));
+}
+
+#[test]
+fn very_verbose_shows_the_upstream_and_its_distance() {
+    let (_server, clone) = ahead_clone();
+    let listed = clone.run(&["branch", "-vv"]);
+    assert!(
+        listed.contains(" [origin/master: ahead 2] local 1\n"),
+        "{listed}"
+    );
+    assert_eq!(listed, clone.git(&["branch", "-vv"]));
+}
+
+#[test]
+fn set_upstream_to_writes_the_tracking_config() {
+    let (_server, clone) = ahead_clone();
+    clone.git(&["branch", "side", "HEAD~1"]);
+    let out = clone.run(&["branch", "--set-upstream-to=origin/master", "side"]);
+    assert_eq!(out, "branch 'side' set up to track 'origin/master'.\n");
+    assert_eq!(
+        clone.git(&["config", "--get-regexp", "^branch\\.side\\."]),
+        "branch.side.remote origin\nbranch.side.merge refs/heads/master\n"
+    );
+    assert!(clone
+        .run(&["branch", "-vv"])
+        .contains("[origin/master: ahead 1] local 0\n"));
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use std::os::unix::fs::symlink;
+
+use common::Repo;
+
+/// A commit with a nested file, a symlink to it, a symlink to a directory, and one pointing
+/// outside the tree.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("src/deep/main.rs", "fn main() {}\n");
+    repo.write("docs/readme", "read me\n");
+    symlink("src/deep/main.rs", repo.join("link")).unwrap();
+    symlink("../docs", repo.join("src/docs")).unwrap();
+    symlink("../../outside", repo.join("src/escape")).unwrap();
+    repo.commit_all("files");
+    repo
+}
+
+#[test]
+fn shows_the_blob_at_a_nested_path() {
+    let repo = fixture();
+    assert_eq!(
+        repo.run(&["cat-file", "blob", "HEAD:src/deep/main.rs"]),
+        "fn main() {}\n"
+    );
+    let err = repo.fails(&["cat-file", "blob", "HEAD:src/missing"]);
+    assert!(err.contains("src/missing"), "{err}");
+}
+
+#[test]
+fn follow_symlinks_resolves_links_inside_the_tree() {
+    let repo = fixture();
+    // without it, the link itself
+    assert_eq!(
+        repo.run(&["cat-file", "blob", "HEAD:link"]),
+        "src/deep/main.rs"
+    );
+    assert_eq!(
+        repo.run(&["cat-file", "--follow-symlinks", "blob", "HEAD:link"]),
+        "fn main() {}\n"
+    );
+    // through a linked directory
+    assert_eq!(
+        repo.run(&[
+            "cat-file",
+            "--follow-symlinks",
+            "blob",
+            "HEAD:src/docs/readme"
+        ]),
+        "read me\n"
+    );
+}
+
+#[test]
+fn follow_symlinks_refuses_links_out_of_the_tree() {
+    let repo = fixture();
+    let err = repo.fails(&["cat-file", "--follow-symlinks", "blob", "HEAD:src/escape"]);
+    assert!(!err.is_empty());
+}
+
+/// A repository whose first commit is both packed and loose and whose second is only loose.
+fn packed_and_loose() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("dir/b", "b\n");
+    repo.commit_all("one");
+    // without -d the loose copies stay
+    repo.git(&["repack", "-a", "-q"]);
+    repo.write("c", "c\n");
+    repo.commit_all("two");
+    repo
+}
+
+#[test]
+fn batch_all_objects_lists_each_object_once_in_oid_order() {
+    let repo = packed_and_loose();
+    let args = ["cat-file", "--batch-check", "--batch-all-objects"];
+    let out = repo.run(&args);
+    assert_eq!(out, repo.git(&args));
+    // two commits, three trees, three blobs
+    assert_eq!(out.lines().count(), 8, "{out}");
+    let head = repo.rev_parse("HEAD");
+    assert!(out.contains(&format!("{head} commit ")), "{out}");
+
+    let mut buffered = args.to_vec();
+    buffered.push("--buffer");
+    assert_eq!(repo.run(&buffered), out);
+}
+
+#[test]
+fn batch_all_objects_unordered_lists_the_same_objects() {
+    let repo = packed_and_loose();
+    let args = [
+        "cat-file",
+        "--batch-check",
+        "--batch-all-objects",
+        "--unordered",
+    ];
+    let mut lines: Vec<_> = repo.run(&args).lines().map(str::to_string).collect();
+    lines.sort();
+    let ordered = repo.run(&["cat-file", "--batch-check", "--batch-all-objects"]);
+    assert_eq!(lines, ordered.lines().collect::<Vec<_>>());
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use common::Repo;
+
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write(
+        ".gitattributes",
+        "*.txt text\n*.bin -text diff=hex\nsub/** eol=crlf\n",
+    );
+    repo.write("sub/.gitattributes", "*.txt -text\n");
+    repo
+}
+
+#[test]
+fn reports_set_and_unspecified() {
+    let repo = fixture();
+    let out = repo.run(&["check-attr", "text", "--", "notes.txt", "main.rs"]);
+    assert_eq!(out, "notes.txt: text: set\nmain.rs: text: unspecified\n");
+    assert_eq!(
+        out,
+        repo.git(&["check-attr", "text", "--", "notes.txt", "main.rs"])
+    );
+}
+
+#[test]
+fn reports_unset_values_and_deeper_files() {
+    let repo = fixture();
+    let args = [
+        "check-attr",
+        "text",
+        "diff",
+        "eol",
+        "--",
+        "a.bin",
+        "sub/deep/x.txt",
+        "sub/x.txt",
+    ];
+    let out = repo.run(&args);
+    assert!(
+        out.contains("a.bin: text: unset\na.bin: diff: hex\n"),
+        "{out}"
+    );
+    assert!(out.contains("sub/x.txt: text: unset\n"), "{out}");
+    assert_eq!(out, repo.git(&args));
+}
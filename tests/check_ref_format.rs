@@ -0,0 +1,74 @@
+mod common;
+
+use std::process::Command;
+
+use common::Repo;
+
+/// Run `command` and return whether it succeeded and what it printed.
+fn outcome(mut command: Command) -> (bool, String) {
+    let output = command.output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+/// Check that git-rs and git agree on `args` for `check-ref-format`, returning whether the name
+/// was accepted.
+fn agrees(repo: &Repo, args: &[&str]) -> bool {
+    let mut ours = repo.git_rs(&["check-ref-format"]);
+    ours.args(args);
+    let mut git = repo.command("git");
+    git.arg("check-ref-format").args(args);
+    let ours = outcome(ours);
+    assert_eq!(ours, outcome(git), "{args:?}");
+    ours.0
+}
+
+#[test]
+fn applies_gits_rules() {
+    let repo = Repo::init();
+    let valid = [
+        "refs/heads/main",
+        "refs/tags/v1.0",
+        "heads/feature/x-y_z",
+        "refs/heads/a@b",
+    ];
+    let invalid = [
+        "main",
+        "refs/heads//main",
+        "refs/heads/.hidden",
+        "refs/heads/a..b",
+        "refs/heads/a@{1}",
+        "refs/heads/x.lock",
+        "refs/heads/end.",
+        "refs/heads/end/",
+        "refs/heads/with space",
+        "refs/heads/with~tilde",
+        "refs/heads/with^caret",
+        "refs/heads/with:colon",
+        "refs/heads/with?question",
+        "refs/heads/with*star",
+        "refs/heads/with[bracket",
+        "refs/heads/back\\slash",
+        "refs/heads/ctrl\x01",
+        "@",
+        "/refs/heads/main",
+    ];
+    for name in valid {
+        assert!(agrees(&repo, &[name]), "{name}");
+    }
+    for name in invalid {
+        assert!(!agrees(&repo, &[name]), "{name}");
+    }
+}
+
+#[test]
+fn options_change_what_is_accepted() {
+    let repo = Repo::init();
+    assert!(agrees(&repo, &["--allow-onelevel", "main"]));
+    assert!(agrees(&repo, &["--normalize", "/refs//heads/main"]));
+    assert!(agrees(&repo, &["--branch", "feature"]));
+    assert!(!agrees(&repo, &["--branch", "-feature"]));
+    assert!(!agrees(&repo, &["--branch", "a..b"]));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn sparse_checkout_writes_only_matching_files() {
+    let repo = Repo::init();
+    repo.write("src/main.rs", "fn main() {}\n");
+    repo.write("src/lib/mod.rs", "\n");
+    repo.write("docs/guide.md", "guide\n");
+    repo.write("README", "readme\n");
+    repo.commit_all("files");
+    repo.run(&["branch", "other"]);
+
+    repo.git(&["config", "core.sparseCheckout", "true"]);
+    repo.write(".git/info/sparse-checkout", "src/*\n");
+    repo.run(&["checkout", "--force", "other"]);
+
+    assert!(repo.join("src/main.rs").exists());
+    assert!(repo.join("src/lib/mod.rs").exists());
+    assert!(!repo.join("docs/guide.md").exists());
+    assert!(!repo.join("README").exists());
+    // the files left out are still in the index, marked skip-worktree
+    assert_eq!(
+        repo.git(&["ls-files", "-t"]),
+        "S README\nS docs/guide.md\nH src/lib/mod.rs\nH src/main.rs\n"
+    );
+    assert_eq!(repo.run(&["status", "--porcelain"]), "");
+}
+
+#[test]
+fn commits_on_a_detached_head_and_reattaches() {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    let first = repo.commit_all("first");
+    repo.write("a", "2\n");
+    let second = repo.commit_all("second");
+
+    repo.run(&["checkout", &first]);
+    assert_eq!(repo.read(".git/HEAD"), format!("{first}\n"));
+    assert_eq!(repo.read("a"), "1\n");
+
+    repo.write("a", "3\n");
+    let detached = repo.commit_all("on a detached head");
+    // HEAD moved on by itself, and no branch was created or moved
+    assert_eq!(repo.read(".git/HEAD"), format!("{detached}\n"));
+    assert_eq!(repo.rev_parse("HEAD^"), first);
+    assert_eq!(
+        repo.git(&["for-each-ref", "--format=%(refname)", "refs/heads"]),
+        "refs/heads/master\n"
+    );
+    assert_eq!(repo.rev_parse("master"), second);
+
+    repo.run(&["checkout", "master"]);
+    assert_eq!(repo.read(".git/HEAD"), "ref: refs/heads/master\n");
+    assert_eq!(repo.read("a"), "2\n");
+}
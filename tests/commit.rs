@@ -0,0 +1,197 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn refuses_an_empty_commit() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let first = repo.commit_all("first");
+
+    let error = repo.fails(&["commit", "-m", "nothing"]);
+    assert!(error.contains("nothing to commit"), "{error}");
+    assert_eq!(repo.rev_parse("HEAD"), first);
+
+    // nor is anything committed when a change was staged and then undone
+    repo.write("a", "b\n");
+    repo.git(&["add", "a"]);
+    repo.write("a", "a\n");
+    repo.git(&["add", "a"]);
+    repo.fails(&["commit", "-m", "nothing either"]);
+    assert_eq!(repo.rev_parse("HEAD"), first);
+}
+
+#[test]
+fn refuses_an_empty_first_commit() {
+    let repo = Repo::init();
+    repo.fails(&["commit", "-m", "empty"]);
+    assert!(!repo.join(".git/refs/heads/master").exists());
+}
+
+#[test]
+fn allow_empty_commits_the_same_tree() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let first = repo.commit_all("first");
+
+    repo.run(&["commit", "--allow-empty", "-m", "empty"]);
+    assert_eq!(repo.rev_parse("HEAD^"), first);
+    assert_eq!(
+        repo.rev_parse("HEAD^{tree}"),
+        repo.rev_parse(&format!("{first}^{{tree}}"))
+    );
+    assert_eq!(repo.git(&["log", "-1", "--format=%s"]), "empty\n");
+}
+
+/// A repository with one commit and a change to `a` staged, and `script` as `fake-editor`.
+fn staged(script: &str) -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("first");
+    repo.write("a", "b\n");
+    repo.git(&["add", "a"]);
+    repo.write("fake-editor", script);
+    repo
+}
+
+/// Run `git-rs commit` with `editor` as `GIT_EDITOR`.
+fn commit_with_editor(repo: &Repo, editor: &str) -> std::process::Output {
+    repo.git_rs(&["commit"])
+        .env("GIT_EDITOR", editor)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn editor_writes_the_message() {
+    // keep the template, and write a message with a comment and surplus blank lines and
+    // whitespace for cleanup to remove
+    let repo = staged(
+        r#"cp "$1" template
+printf 'edited\n\n\nbody  \n# a comment\n' > "$1"
+"#,
+    );
+    assert!(commit_with_editor(&repo, "sh fake-editor").status.success());
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "edited\n\nbody\n\n"
+    );
+    assert_eq!(
+        repo.read(".git/COMMIT_EDITMSG"),
+        "edited\n\n\nbody  \n# a comment\n"
+    );
+
+    let template = repo.read("template");
+    assert!(template.starts_with('\n'), "{template}");
+    assert!(template.contains("\n# On branch master\n"), "{template}");
+    assert!(template.contains("\n#\tmodified:   a\n"), "{template}");
+}
+
+#[test]
+fn editor_command_can_have_arguments() {
+    let repo = staged(r#"printf '%s\n' "$1" > "$2""#);
+    let output = commit_with_editor(&repo, "sh fake-editor 'from an argument'");
+    assert!(output.status.success());
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%s"]),
+        "from an argument\n"
+    );
+}
+
+#[test]
+fn failing_editor_aborts_the_commit() {
+    let repo = staged("echo ignored > \"$1\"\nexit 1\n");
+    let head = repo.rev_parse("HEAD");
+    assert!(!commit_with_editor(&repo, "sh fake-editor").status.success());
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn empty_message_aborts_the_commit() {
+    let repo = staged(r#"printf '# only a comment\n\n' > "$1""#);
+    let head = repo.rev_parse("HEAD");
+    let output = commit_with_editor(&repo, "sh fake-editor");
+    assert!(!output.status.success());
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        err.contains("Aborting commit due to empty commit message"),
+        "{err}"
+    );
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn aborted_commit_all_leaves_the_index_alone() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let head = repo.commit_all("first");
+    repo.write("a", "changed\n");
+    repo.write("fake-editor", r#"printf '# only a comment\n' > "$1""#);
+    let output = repo
+        .git_rs(&["commit", "-a"])
+        .env("GIT_EDITOR", "sh fake-editor")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    repo.fails(&["commit", "-a", "-m", ""]);
+
+    assert_eq!(repo.rev_parse("HEAD"), head);
+    // the change is still only in the work tree
+    assert_eq!(repo.git(&["diff", "--cached", "--name-only"]), "");
+    assert_eq!(repo.git(&["diff", "--name-only"]), "a\n");
+}
+
+#[test]
+fn a_held_ref_lock_keeps_the_branch() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let head = repo.commit_all("one");
+    let branch = repo.git(&["symbolic-ref", "HEAD"]);
+    let lock = format!(".git/{}.lock", branch.trim());
+    repo.write(&lock, "");
+    repo.write("a", "changed\n");
+    repo.git(&["add", "a"]);
+
+    let stderr = repo.fails(&["commit", "-m", "two"]);
+    assert!(stderr.contains("File exists."), "{stderr}");
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn signoff_appends_the_committer_trailer() {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    repo.git(&["add", "a"]);
+    repo.run(&["commit", "-s", "-m", "one"]);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "one\n\nSigned-off-by: C O Mitter <committer@example.com>\n\n"
+    );
+
+    // an existing trailer block is extended, and the same sign-off isn't added twice
+    repo.write("a", "2\n");
+    repo.git(&["add", "a"]);
+    let message = "two\n\nCo-authored-by: Co Author <co@example.com>";
+    repo.run(&["commit", "-s", "-m", message]);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        format!("{message}\nSigned-off-by: C O Mitter <committer@example.com>\n\n")
+    );
+    repo.write("a", "3\n");
+    repo.git(&["add", "a"]);
+    let message = "three\n\nSigned-off-by: C O Mitter <committer@example.com>";
+    repo.run(&["commit", "-s", "-m", message]);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        format!("{message}\n\n")
+    );
+    assert_eq!(
+        repo.git(&[
+            "log",
+            "--format=%(trailers:key=Co-authored-by,valueonly)",
+            "-1",
+            "HEAD~1"
+        ]),
+        "Co Author <co@example.com>\n\n"
+    );
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn reads_the_message_from_stdin() {
+    let repo = Repo::init();
+    repo.write("file", "contents\n");
+    let parent = repo.commit_all("first");
+    let tree = repo.rev_parse("HEAD^{tree}");
+
+    let message = "subject\n\nbody line\n";
+    let commit = repo.run_with_input(&["commit-tree", &tree, "-p", &parent], message.as_bytes());
+    let commit = commit.trim();
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B", commit]),
+        format!("{message}\n")
+    );
+    assert_eq!(repo.rev_parse(&format!("{commit}^")), parent);
+    // git writes the same commit from the same input
+    let expected = repo.git_with_input(&["commit-tree", &tree, "-p", &parent], message.as_bytes());
+    assert_eq!(commit, expected.trim());
+}
+
+#[test]
+fn prefers_the_message_flag() {
+    let repo = Repo::init();
+    repo.write("file", "contents\n");
+    repo.commit_all("first");
+    let tree = repo.rev_parse("HEAD^{tree}");
+
+    let commit = repo.run_with_input(&["commit-tree", &tree, "-m", "from flag"], b"ignored\n");
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B", commit.trim()]),
+        "from flag\n\n"
+    );
+}
+
+#[test]
+fn signatures_carry_the_dates_from_the_environment() {
+    let repo = Repo::init();
+    repo.write("file", "contents\n");
+    repo.commit_all("first");
+    let tree = repo.rev_parse("HEAD^{tree}");
+
+    for (author_date, committer_date, author, committer) in [
+        (
+            "1700000000 +0000",
+            "1700000100 -0530",
+            "1700000000 +0000",
+            "1700000100 -0530",
+        ),
+        (
+            "Thu, 15 Oct 2026 12:34:56 +0200",
+            "2026-10-15T12:34:56-0700",
+            "1792060496 +0200",
+            "1792092896 -0700",
+        ),
+        (
+            "@1700000000 +1245",
+            "1700000000",
+            "1700000000 +1245",
+            "1700000000 +0000",
+        ),
+    ] {
+        let mut command = repo.git_rs(&["commit-tree", &tree, "-m", "dated"]);
+        command
+            .env("GIT_AUTHOR_DATE", author_date)
+            .env("GIT_COMMITTER_DATE", committer_date);
+        let output = command.output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let commit = String::from_utf8(output.stdout).unwrap();
+        let raw = repo.git(&["cat-file", "commit", commit.trim()]);
+        let header = |name: &str| {
+            raw.lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            header("author "),
+            format!("A U Thor <author@example.com> {author}")
+        );
+        assert_eq!(
+            header("committer "),
+            format!("C O Mitter <committer@example.com> {committer}")
+        );
+        // and git reads the same moment back
+        let (time, tz) = committer.split_once(' ').unwrap();
+        assert_eq!(
+            repo.git(&[
+                "log",
+                "-1",
+                "--format=%ct %cd",
+                "--date=format:%z",
+                commit.trim()
+            ]),
+            format!("{time} {tz}\n")
+        );
+    }
+}
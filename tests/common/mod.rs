@@ -0,0 +1,198 @@
+//! Helpers for the integration tests: throwaway repositories, and running `git-rs` (and git, to
+//! set up fixtures and check results) in them.
+
+// each test crate uses only some of the helpers
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A fixed date for commits, so that their hashes don't depend on when the tests run.
+pub const DATE: &str = "1700000000 +0000";
+
+/// A temporary directory, removed again when dropped.
+pub struct Repo {
+    pub path: PathBuf,
+}
+
+impl Repo {
+    /// An empty directory.
+    pub fn empty() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "git-rs-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        if path.exists() {
+            fs::remove_dir_all(&path).unwrap();
+        }
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// A new repository, made by `git-rs init`.
+    pub fn init() -> Self {
+        let repo = Self::empty();
+        repo.run(&["init", "--quiet"]);
+        repo
+    }
+
+    /// A command running `program` in the repository, with a fixed identity and date and
+    /// without the environment of whoever runs the tests.
+    pub fn command(&self, program: impl AsRef<std::ffi::OsStr>) -> Command {
+        let mut command = Command::new(program);
+        command.current_dir(&self.path);
+        for (var, _) in std::env::vars_os() {
+            if var.to_string_lossy().starts_with("GIT_") {
+                command.env_remove(var);
+            }
+        }
+        command
+            .env("HOME", &self.path)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", DATE)
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", DATE)
+            .env("GIT_PAGER", "cat")
+            .env_remove("http_proxy")
+            .env_remove("https_proxy")
+            .env_remove("HTTPS_PROXY")
+            .env_remove("all_proxy")
+            .env_remove("ALL_PROXY")
+            .env_remove("EDITOR")
+            .env_remove("VISUAL")
+            .env("TERM", "dumb");
+        command
+    }
+
+    /// A command running `git-rs` with `args`.
+    pub fn git_rs(&self, args: &[&str]) -> Command {
+        let mut command = self.command(env!("CARGO_BIN_EXE_git-rs"));
+        command.args(args);
+        command
+    }
+
+    /// Run `git-rs` with `args`, which must succeed, and return its output.
+    pub fn run(&self, args: &[&str]) -> String {
+        output(self.git_rs(args))
+    }
+
+    /// Run `git-rs` with `args` and `stdin` as its input; it must succeed.
+    pub fn run_with_input(&self, args: &[&str], stdin: &[u8]) -> String {
+        with_input(self.git_rs(args), stdin)
+    }
+
+    /// Run `git-rs` with `args`, which must fail, and return its error output.
+    pub fn fails(&self, args: &[&str]) -> String {
+        let output = self.git_rs(args).output().unwrap();
+        assert!(
+            !output.status.success(),
+            "git-rs {args:?} succeeded:\n{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    /// Run `git-rs` with `args` and `stdin` as its input, which must fail, and return its error
+    /// output.
+    pub fn fails_with_input(&self, args: &[&str], stdin: &[u8]) -> String {
+        let mut child = self
+            .git_rs(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(!output.status.success(), "git-rs {args:?} succeeded");
+        String::from_utf8_lossy(&output.stderr).into_owned()
+    }
+
+    /// Run git with `args`, which must succeed, and return its output.
+    pub fn git(&self, args: &[&str]) -> String {
+        let mut command = self.command("git");
+        command.args(args);
+        output(command)
+    }
+
+    /// Run git with `args` and `stdin` as its input; it must succeed.
+    pub fn git_with_input(&self, args: &[&str], stdin: &[u8]) -> String {
+        let mut command = self.command("git");
+        command.args(args);
+        with_input(command, stdin)
+    }
+
+    /// Write `contents` to the file `path` of the work tree, creating its directories.
+    pub fn write(&self, path: &str, contents: impl AsRef<[u8]>) {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// The contents of the file `path`.
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.path.join(path)).unwrap()
+    }
+
+    /// Stage everything and commit it with `git-rs`, returning the new commit's hash.
+    pub fn commit_all(&self, message: &str) -> String {
+        self.git(&["add", "--all"]);
+        self.run(&["commit", "-m", message]);
+        self.rev_parse("HEAD")
+    }
+
+    /// The object `rev` names, as real git resolves it.
+    pub fn rev_parse(&self, rev: &str) -> String {
+        self.git(&["rev-parse", rev]).trim().to_string()
+    }
+
+    /// The full path of `path` in the work tree.
+    pub fn join(&self, path: &str) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for Repo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The output of `command`, which must succeed.
+fn output(mut command: Command) -> String {
+    let output = command.output().unwrap();
+    check(&command, output)
+}
+
+/// The output of `command` given `stdin` as its input, which must succeed.
+fn with_input(mut command: Command, stdin: &[u8]) -> String {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    check(&command, child.wait_with_output().unwrap())
+}
+
+fn check(command: &Command, output: Output) -> String {
+    assert!(
+        output.status.success(),
+        "{command:?} exited with {}:\n{}{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
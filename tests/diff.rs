@@ -0,0 +1,251 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with `a.c` committed as `old` and changed to `new` in the work tree.
+fn changed(old: &str, new: &str) -> Repo {
+    let repo = Repo::init();
+    repo.write("a.c", old);
+    repo.commit_all("old");
+    repo.write("a.c", new);
+    repo
+}
+
+#[test]
+fn ignore_all_space_hides_indentation_changes() {
+    let repo = changed("int f() {\nreturn 1;\n}\n", "int f() {\n\treturn 1;\n}\n");
+    assert_ne!(repo.run(&["diff"]), "");
+    assert_eq!(repo.run(&["diff", "-w"]), "");
+    assert_eq!(repo.run(&["diff", "-b"]), repo.git(&["diff", "-b"]));
+}
+
+#[test]
+fn ignore_all_space_keeps_real_changes() {
+    let repo = changed("int f() {\nreturn 1;\n}\n", "int f() {\n\treturn 2;\n}\n");
+    let diff = repo.run(&["diff", "-w"]);
+    assert!(diff.contains("-return 1;\n+\treturn 2;\n"), "{diff}");
+    assert_eq!(diff, repo.git(&["diff", "-w"]));
+}
+
+#[test]
+fn ignore_space_change_keeps_added_space() {
+    let repo = changed("a b\nc\n", "a    b\nc\n");
+    assert_eq!(repo.run(&["diff", "-b"]), "");
+    let repo = changed("ab\nc\n", "a b\nc\n");
+    assert_eq!(repo.run(&["diff", "-b"]), repo.git(&["diff", "-b"]));
+}
+
+#[test]
+fn ignore_blank_lines() {
+    let repo = changed("a\nb\n", "a\n\nb\n");
+    assert_eq!(repo.run(&["diff", "--ignore-blank-lines"]), "");
+}
+
+#[test]
+fn word_diff_marks_changed_words() {
+    let repo = changed("the quick brown fox\n", "the slow brown dog\n");
+    let diff = repo.run(&["diff", "--word-diff"]);
+    assert!(
+        diff.contains("the [-quick-]{+slow+} brown [-fox-]{+dog+}\n"),
+        "{diff}"
+    );
+    assert_eq!(diff, repo.git(&["diff", "--word-diff"]));
+}
+
+#[test]
+fn color_never_writes_no_escapes() {
+    let repo = changed("a\nb\n", "a\nc\n");
+    repo.git(&["config", "color.ui", "always"]);
+    let diff = repo.run(&["diff", "--color=never"]);
+    assert!(!diff.contains('\x1b'), "{diff:?}");
+    assert_eq!(diff, repo.git(&["diff", "--color=never"]));
+}
+
+#[test]
+fn color_always_colors_added_and_removed_lines() {
+    let repo = changed("a\nb\n", "a\nc\n");
+    let diff = repo.run(&["diff", "--color=always"]);
+    assert!(
+        diff.contains("\x1b[32m+\x1b[m\x1b[32mc\x1b[m\n"),
+        "{diff:?}"
+    );
+    assert!(diff.contains("\x1b[31m-b\x1b[m\n"), "{diff:?}");
+    assert!(diff.contains("\x1b[36m@@ -1,2 +1,2 @@\x1b[m"), "{diff:?}");
+    assert_eq!(diff, repo.git(&["diff", "--color=always"]));
+}
+
+#[test]
+fn color_config_applies_to_piped_output_only_when_always() {
+    let repo = changed("a\nb\n", "a\nc\n");
+    repo.git(&["config", "color.diff", "auto"]);
+    assert!(!repo.run(&["diff"]).contains('\x1b'));
+    repo.git(&["config", "color.diff", "always"]);
+    assert!(repo
+        .run(&["diff"])
+        .contains("\x1b[32m+\x1b[m\x1b[32mc\x1b[m\n"));
+}
+
+#[test]
+fn stat_counts_changed_lines() {
+    let repo = Repo::init();
+    repo.write("kept", "1\n2\n3\n4\n");
+    repo.write("removed", "x\ny\n");
+    repo.write("dir/edited", "a\nb\nc\n");
+    repo.commit_all("old");
+    repo.write("kept", "1\n2\n3\n4\n5\n6\n");
+    repo.write("dir/edited", "a\nB\nc\nd\n");
+    repo.write("added", "new\n");
+    std::fs::remove_file(repo.join("removed")).unwrap();
+    repo.git(&["add", "--all"]);
+    repo.run(&["commit", "-m", "new"]);
+
+    let stat = repo.run(&["diff", "--stat", "HEAD~1", "HEAD"]);
+    assert_eq!(
+        stat,
+        " added      | 1 +\n dir/edited | 3 ++-\n kept       | 2 ++\n removed    | 2 --\n \
+         4 files changed, 5 insertions(+), 3 deletions(-)\n"
+    );
+    assert_eq!(stat, repo.git(&["diff", "--stat", "HEAD~1", "HEAD"]));
+}
+
+#[test]
+fn stat_quotes_special_names_like_git() {
+    let repo = Repo::init();
+    let lines = (1..=10).map(|n| format!("line {n}\n")).collect::<String>();
+    repo.write("a\"b", "1\n");
+    repo.write("tab\tx", "1\n");
+    repo.write("dir/old\\name", &lines);
+    repo.commit_all("old");
+    repo.write("a\"b", "2\n");
+    repo.write("tab\tx", "2\n");
+    std::fs::rename(repo.join("dir/old\\name"), repo.join("dir/new")).unwrap();
+    repo.commit_all("new");
+
+    let stat = repo.run(&["diff", "--stat", "HEAD~1", "HEAD"]);
+    assert!(stat.starts_with(" \"a\\\"b\" "), "{stat}");
+    assert!(stat.contains(" \"dir/old\\\\name\" => dir/new "), "{stat}");
+    assert!(stat.contains(" \"tab\\tx\" "), "{stat}");
+    assert_eq!(stat, repo.git(&["diff", "--stat", "HEAD~1", "HEAD"]));
+}
+
+#[test]
+fn stat_scales_long_bars() {
+    let lines: String = (0..200).map(|n| format!("{n}\n")).collect();
+    let repo = changed("a\n", &lines);
+    let stat = repo.run(&["diff", "--stat"]);
+    assert_eq!(stat, repo.git(&["diff", "--stat"]));
+    assert!(stat.starts_with(" a.c | 201 "), "{stat}");
+    assert!(
+        stat.ends_with(" 1 file changed, 200 insertions(+), 1 deletion(-)\n"),
+        "{stat}"
+    );
+}
+
+/// A repository where `a` has a committed, a staged and an unstaged version.
+fn three_versions() -> Repo {
+    let repo = changed("committed\n", "staged\n");
+    repo.git(&["add", "a.c"]);
+    repo.write("a.c", "work tree\n");
+    repo
+}
+
+#[test]
+fn compares_the_index_with_the_work_tree() {
+    let repo = three_versions();
+    let diff = repo.run(&["diff"]);
+    assert!(diff.contains("-staged\n+work tree\n"), "{diff}");
+    assert_eq!(diff, repo.git(&["diff"]));
+}
+
+#[test]
+fn cached_compares_head_with_the_index() {
+    let repo = three_versions();
+    let diff = repo.run(&["diff", "--cached"]);
+    assert!(diff.contains("-committed\n+staged\n"), "{diff}");
+    assert_eq!(diff, repo.git(&["diff", "--cached"]));
+}
+
+#[test]
+fn a_commit_compares_it_with_the_work_tree() {
+    let repo = three_versions();
+    let diff = repo.run(&["diff", "HEAD"]);
+    assert!(diff.contains("-committed\n+work tree\n"), "{diff}");
+    assert_eq!(diff, repo.git(&["diff", "HEAD"]));
+}
+
+#[test]
+fn work_tree_comparisons_include_new_and_deleted_files() {
+    let repo = three_versions();
+    repo.write("new", "new\n");
+    repo.git(&["add", "new"]);
+    std::fs::remove_file(repo.join("new")).unwrap();
+    std::fs::remove_file(repo.join("a.c")).unwrap();
+    for args in [&["diff"][..], &["diff", "--cached"], &["diff", "HEAD"]] {
+        assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+    }
+}
+
+/// The exit code of `git-rs` with `args`, and what it printed.
+fn exit_code(repo: &Repo, args: &[&str]) -> (Option<i32>, String) {
+    let output = repo.git_rs(args).output().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+#[test]
+fn exit_code_says_whether_there_are_changes() {
+    let repo = changed("a\n", "b\n");
+    let (code, out) = exit_code(&repo, &["diff", "--exit-code"]);
+    assert_eq!(code, Some(1));
+    assert!(out.contains("-a\n+b\n"), "{out}");
+    assert_eq!(
+        exit_code(&repo, &["diff", "--quiet"]),
+        (Some(1), String::new())
+    );
+
+    repo.git(&["add", "a.c"]);
+    assert_eq!(
+        exit_code(&repo, &["diff", "--exit-code"]),
+        (Some(0), String::new())
+    );
+    assert_eq!(
+        exit_code(&repo, &["diff", "--quiet", "--cached"]).0,
+        Some(1)
+    );
+    assert_eq!(
+        exit_code(&repo, &["diff", "--quiet", "HEAD", "HEAD"]).0,
+        Some(0)
+    );
+}
+
+#[test]
+fn differences_are_not_reported_as_errors() {
+    let repo = changed("a\n", "b\n");
+    let output = repo.git_rs(&["diff", "--quiet"]).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn renames_match_git_at_each_threshold() {
+    let repo = Repo::init();
+    let lines = (1..=10).map(|n| format!("line {n}\n")).collect::<String>();
+    repo.write("old", &lines);
+    repo.commit_all("old");
+    std::fs::remove_file(repo.join("old")).unwrap();
+    repo.write("new", lines.replace("line 5\n", "five\n"));
+    repo.commit_all("renamed");
+    for args in [
+        &["diff", "-M", "HEAD~1", "HEAD"][..],
+        &["diff", "-M50", "HEAD~1", "HEAD"],
+        &["diff", "-M95%", "HEAD~1", "HEAD"],
+        &["diff", "--find-renames=80", "HEAD~1", "HEAD"],
+    ] {
+        assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+    }
+    let diff = repo.run(&["diff", "-M50", "HEAD~1", "HEAD"]);
+    assert!(diff.contains("rename to new\nindex "), "{diff}");
+    assert!(diff.contains(" 100644\n--- a/old\n"), "{diff}");
+}
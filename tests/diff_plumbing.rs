@@ -0,0 +1,59 @@
+mod common;
+
+use common::Repo;
+use std::{fs, os::unix::fs::PermissionsExt};
+
+/// A repository with a change of each kind: `a` modified in both the index and the work tree,
+/// `new` added, `b` deleted from the work tree and `c` made executable.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("b", "b\n");
+    repo.write("c", "c\n");
+    repo.commit_all("one");
+    repo.write("a", "a2\n");
+    repo.write("new", "new\n");
+    repo.git(&["add", "a", "new"]);
+    repo.write("a", "a3\n");
+    fs::remove_file(repo.join("b")).unwrap();
+    fs::set_permissions(repo.join("c"), fs::Permissions::from_mode(0o755)).unwrap();
+    repo
+}
+
+fn assert_same(repo: &Repo, args: &[&str]) {
+    assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+}
+
+#[test]
+fn diff_files_matches_git() {
+    let repo = fixture();
+    let out = repo.run(&["diff-files"]);
+    assert_eq!(out.lines().count(), 3, "{out}");
+    assert!(out.contains(" M\ta\n"), "{out}");
+    assert!(out.contains(":100644 100755 "), "{out}");
+    for args in [
+        &["diff-files"][..],
+        &["diff-files", "-z"],
+        &["diff-files", "--name-status"],
+        &["diff-files", "--name-only"],
+    ] {
+        assert_same(&repo, args);
+    }
+}
+
+#[test]
+fn diff_index_matches_git() {
+    let repo = fixture();
+    let out = repo.run(&["diff-index", "--cached", "--name-status", "HEAD"]);
+    assert_eq!(out, "M\ta\nA\tnew\n");
+    for args in [
+        &["diff-index", "HEAD"][..],
+        &["diff-index", "--cached", "HEAD"],
+        &["diff-index", "-z", "--cached", "HEAD"],
+        &["diff-index", "--name-status", "HEAD"],
+        &["diff-index", "-z", "--cached", "--name-only", "HEAD"],
+        &["diff-index", "--cached", "HEAD^{tree}"],
+    ] {
+        assert_same(&repo, args);
+    }
+}
@@ -0,0 +1,311 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+/// A repository to fetch from, with `master`, a `topic` branch and an annotated tag.
+fn upstream() -> Repo {
+    let repo = Repo::init();
+    repo.write("file", "1\n");
+    repo.commit_all("first");
+    repo.git(&["tag", "-a", "v1", "-m", "v1"]);
+    repo.git(&["branch", "topic"]);
+    repo.write("file", "2\n");
+    repo.commit_all("second");
+    repo
+}
+
+/// Run `git-rs` with `args` in `repo`, which must succeed, and return its error output, where
+/// fetch and clone report.
+fn stderr(repo: &Repo, args: &[&str]) -> String {
+    let output = repo.git_rs(args).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(output.status.success(), "git-rs {args:?} failed:\n{stderr}");
+    stderr
+}
+
+#[test]
+fn clone_matches_git() {
+    let upstream = upstream();
+    let dir = Repo::empty();
+    let url = upstream.path.to_str().unwrap();
+    let cloned = stderr(&dir, &["clone", url, "ours"]);
+    assert_eq!(cloned, "Cloning into 'ours'...\n");
+    dir.git(&["clone", "-q", url, "theirs"]);
+
+    let refs = |name: &str| {
+        dir.git(&[
+            "-C",
+            name,
+            "for-each-ref",
+            "--format=%(refname) %(objectname) %(symref)",
+        ])
+    };
+    assert_eq!(refs("ours"), refs("theirs"));
+    let config = |name: &str| {
+        dir.git(&[
+            "-C",
+            name,
+            "config",
+            "--local",
+            "--get-regexp",
+            "^(remote|branch)\\.",
+        ])
+    };
+    assert_eq!(config("ours"), config("theirs"));
+    assert_eq!(dir.read("ours/file"), "2\n");
+    assert!(dir.git(&["-C", "ours", "status", "--porcelain"]).is_empty());
+    dir.git(&["-C", "ours", "fsck", "--strict"]);
+
+    let message = dir.fails(&["clone", url, "ours"]);
+    assert!(
+        message.contains("destination path 'ours' already exists and is not an empty directory.")
+    );
+}
+
+#[test]
+fn clone_checks_out_the_branch_asked_for() {
+    let upstream = upstream();
+    let clone = Repo::empty();
+    let url = upstream.path.to_str().unwrap();
+    stderr(
+        &clone,
+        &["clone", "-q", "-b", "topic", "-o", "up", url, "."],
+    );
+    assert_eq!(clone.read("file"), "1\n");
+    assert_eq!(clone.git(&["symbolic-ref", "HEAD"]), "refs/heads/topic\n");
+    assert_eq!(clone.git(&["config", "branch.topic.remote"]), "up\n");
+    assert_eq!(clone.rev_parse("up/master"), upstream.rev_parse("master"));
+
+    let message = clone.fails(&["clone", "-b", "nope", url, "missing"]);
+    assert!(message.contains("Remote branch nope not found in upstream origin"));
+    assert!(!clone.join("missing").exists());
+}
+
+#[test]
+fn fetch_matches_git() {
+    let upstream = upstream();
+    let url = upstream.path.to_str().unwrap();
+    let ours = Repo::empty();
+    ours.git(&["clone", "-q", url, "."]);
+    let theirs = Repo::empty();
+    theirs.git(&["clone", "-q", url, "."]);
+    upstream.write("file", "3\n");
+    upstream.commit_all("third");
+    upstream.git(&["tag", "v2"]);
+    upstream.git(&["branch", "-f", "topic", "HEAD"]);
+    upstream.git(&["branch", "new"]);
+
+    let fetched = stderr(&ours, &["fetch"]);
+    let expected = theirs.command("git").arg("fetch").output().unwrap();
+    assert_eq!(fetched, String::from_utf8_lossy(&expected.stderr));
+    assert_eq!(ours.read(".git/FETCH_HEAD"), theirs.read(".git/FETCH_HEAD"));
+    let refs = |repo: &Repo| repo.git(&["for-each-ref"]);
+    assert_eq!(refs(&ours), refs(&theirs));
+    ours.git(&["fsck", "--strict"]);
+    assert_eq!(stderr(&ours, &["fetch"]), "");
+}
+
+#[test]
+fn fetch_prunes_like_git() {
+    let upstream = upstream();
+    let url = upstream.path.to_str().unwrap();
+    let ours = Repo::empty();
+    ours.git(&["clone", "-q", url, "."]);
+    let theirs = Repo::empty();
+    theirs.git(&["clone", "-q", url, "."]);
+    upstream.git(&["branch", "-D", "topic"]);
+    for repo in [&ours, &theirs] {
+        repo.git(&["update-ref", "refs/other/topic", "HEAD"]);
+    }
+
+    let pruned = stderr(&ours, &["fetch", "--prune"]);
+    assert!(pruned.contains(" - [deleted]         (none)     -> origin/topic"));
+    let expected = theirs
+        .command("git")
+        .args(["fetch", "--prune"])
+        .output()
+        .unwrap();
+    assert_eq!(pruned, String::from_utf8_lossy(&expected.stderr));
+    let refs = |repo: &Repo| repo.git(&["for-each-ref"]);
+    assert_eq!(refs(&ours), refs(&theirs));
+}
+
+#[test]
+fn fetch_prune_follows_config() {
+    let upstream = upstream();
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", upstream.path.to_str().unwrap(), "."]);
+    upstream.git(&["branch", "-D", "topic"]);
+
+    clone.git(&["config", "fetch.prune", "true"]);
+    stderr(&clone, &["fetch", "--no-prune"]);
+    clone.rev_parse("origin/topic");
+    clone.git(&["config", "remote.origin.prune", "false"]);
+    stderr(&clone, &["fetch"]);
+    clone.rev_parse("origin/topic");
+    clone.git(&["config", "--unset", "remote.origin.prune"]);
+    assert!(stderr(&clone, &["fetch"]).contains("[deleted]"));
+    assert!(!clone.git(&["for-each-ref"]).contains("origin/topic"));
+}
+
+#[test]
+fn fetch_refuses_non_fast_forwards_unless_forced() {
+    let upstream = upstream();
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", upstream.path.to_str().unwrap(), "."]);
+    let before = clone.rev_parse("origin/master");
+    upstream.git(&["reset", "-q", "--hard", "HEAD~1"]);
+    upstream.write("file", "other\n");
+    upstream.commit_all("rewritten");
+
+    let message = clone.fails(&["fetch", "origin", "master:refs/remotes/origin/master"]);
+    assert!(
+        message.contains(" ! [rejected]        master     -> origin/master  (non-fast-forward)")
+    );
+    assert_eq!(clone.rev_parse("origin/master"), before);
+
+    let fetched = stderr(&clone, &["fetch"]);
+    assert!(fetched.contains("(forced update)"), "{fetched}");
+    assert_eq!(
+        clone.rev_parse("origin/master"),
+        upstream.rev_parse("master")
+    );
+}
+
+#[test]
+fn fetches_over_ssh() {
+    let upstream = upstream();
+    let dir = Repo::empty();
+    // stands in for ssh: runs the remote command here and logs how it was called
+    let ssh = dir.join("ssh");
+    fs::write(
+        &ssh,
+        format!(
+            "#!/bin/sh\necho \"$*\" >> {}/ssh.log\n\
+             while [ \"${{1#-}}\" != \"$1\" ]; do [ \"$1\" = -- ] && {{ shift; break; }}; shift 2; done\nshift\nexec sh -c \"$*\"\n",
+            dir.path.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let url = format!("ssh://git@example.com:2222{}", upstream.path.display());
+    let output = dir
+        .git_rs(&["clone", "-q", &url, "clone"])
+        .env("GIT_SSH_COMMAND", &ssh)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        dir.read("ssh.log"),
+        format!(
+            "-o SendEnv=GIT_PROTOCOL -p 2222 -- git@example.com git-upload-pack '{}'\n",
+            upstream.path.display()
+        )
+    );
+    assert_eq!(dir.read("clone/file"), "2\n");
+
+    // a program that isn't called ssh doesn't get OpenSSH's options
+    fs::rename(&ssh, dir.join("plink")).unwrap();
+    let output = dir
+        .git_rs(&["clone", "-q", &url, "other"])
+        .env("GIT_SSH", dir.join("plink"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("does not support setting port"));
+}
+
+#[test]
+fn refuses_ssh_urls_that_smuggle_options() {
+    let dir = Repo::empty();
+    let ssh = dir.join("ssh");
+    fs::write(
+        &ssh,
+        format!("#!/bin/sh\necho \"$*\" >> {}/ssh.log\n", dir.path.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    for (url, message) in [
+        (
+            "ssh://-oProxyCommand=touch%20pwned/repo",
+            "strange hostname '-oProxyCommand=touch%20pwned' blocked",
+        ),
+        (
+            "-oProxyCommand=touch:repo",
+            "strange hostname '-oProxyCommand=touch' blocked",
+        ),
+    ] {
+        let output = dir
+            .git_rs(&["clone", "--", url, "clone"])
+            .env("GIT_SSH", &ssh)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(message), "{stderr}");
+    }
+    assert!(!dir.join("ssh.log").exists());
+    assert!(!dir.join("pwned").exists());
+}
+
+/// Run the fetch `args` in a clone of `upstream` with packet tracing, after a new commit
+/// upstream, returning the trace.
+fn traced_fetch(upstream: &Repo, clone: &Repo, args: &[&str]) -> String {
+    upstream.git(&["commit", "-q", "--allow-empty", "-m", "more"]);
+    let output = clone
+        .git_rs(args)
+        .env("GIT_TRACE_PACKET", "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(
+        clone.rev_parse("origin/master"),
+        upstream.rev_parse("master")
+    );
+    stderr
+}
+
+#[test]
+fn fetch_speaks_protocol_v2() {
+    let upstream = upstream();
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", upstream.path.to_str().unwrap(), "."]);
+    let trace = traced_fetch(&upstream, &clone, &["fetch", "origin", "master"]);
+    for line in [
+        "git< version 2",
+        "git> command=ls-refs",
+        "git> ref-prefix refs/heads/master",
+        "git> command=fetch",
+        "git< packfile",
+    ] {
+        assert!(trace.contains(line), "no {line:?} in\n{trace}");
+    }
+    // only the refs asked for are listed
+    assert!(!trace.contains("refs/heads/topic"), "{trace}");
+    clone.git(&["fsck", "--strict"]);
+}
+
+#[test]
+fn fetch_falls_back_to_protocol_v0() {
+    let upstream = upstream();
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", upstream.path.to_str().unwrap(), "."]);
+    clone.git(&["config", "protocol.version", "0"]);
+    let trace = traced_fetch(&upstream, &clone, &["fetch"]);
+    assert!(!trace.contains("version 2"), "{trace}");
+
+    // git-rs upload-pack only speaks v0, whatever the client asks for
+    clone.git(&["config", "--unset", "protocol.version"]);
+    let upload_pack = concat!(env!("CARGO_BIN_EXE_git-rs"), " upload-pack");
+    let trace = traced_fetch(&upstream, &clone, &["fetch", "--upload-pack", upload_pack]);
+    assert!(!trace.contains("version 2"), "{trace}");
+    assert!(trace.contains("refs/heads/topic"), "{trace}");
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+fn chmod(repo: &Repo, path: &str, mode: u32) {
+    fs::set_permissions(repo.join(path), fs::Permissions::from_mode(mode)).unwrap();
+}
+
+/// A repository with `plain` committed as a regular file and `script` as an executable, whose
+/// executable bits were then flipped in the work tree.
+fn flipped() -> Repo {
+    let repo = Repo::init();
+    repo.write("plain", "plain\n");
+    repo.write("script", "script\n");
+    chmod(&repo, "script", 0o755);
+    repo.commit_all("files");
+    chmod(&repo, "plain", 0o755);
+    chmod(&repo, "script", 0o644);
+    repo
+}
+
+#[test]
+fn executable_bit_changes_count_by_default() {
+    let repo = flipped();
+    assert_eq!(
+        repo.run(&["status", "--porcelain"]),
+        " M plain\n M script\n"
+    );
+    let diff = repo.run(&["diff"]);
+    assert!(
+        diff.contains("old mode 100644\nnew mode 100755\n"),
+        "{diff}"
+    );
+    assert_eq!(diff, repo.git(&["diff"]));
+    let tree = repo.run(&["write-tree"]);
+    assert_ne!(tree.trim(), repo.rev_parse("HEAD^{tree}"));
+}
+
+#[test]
+fn filemode_false_ignores_the_executable_bit() {
+    let repo = flipped();
+    repo.git(&["config", "core.fileMode", "false"]);
+    assert_eq!(repo.run(&["status", "--porcelain"]), "");
+    assert_eq!(repo.run(&["diff"]), "");
+    // the tree keeps the modes the index has
+    assert_eq!(
+        repo.run(&["write-tree"]).trim(),
+        repo.rev_parse("HEAD^{tree}")
+    );
+
+    // turning it back on over the same work tree shows the changes again
+    repo.git(&["config", "core.fileMode", "true"]);
+    assert_eq!(
+        repo.run(&["status", "--porcelain"]),
+        " M plain\n M script\n"
+    );
+}
+
+#[test]
+fn filemode_false_still_sees_content_changes() {
+    let repo = flipped();
+    repo.git(&["config", "core.fileMode", "false"]);
+    repo.write("script", "changed\n");
+    assert_eq!(repo.run(&["status", "--porcelain"]), " M script\n");
+    let diff = repo.run(&["diff"]);
+    assert!(!diff.contains("mode"), "{diff}");
+    assert_eq!(diff, repo.git(&["diff"]));
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn lists_branches_with_a_custom_format() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let first = repo.commit_all("first commit");
+    repo.git(&["branch", "older"]);
+    repo.write("a", "b\n");
+    let second = repo.commit_all("second commit");
+    repo.git(&["tag", "v1"]);
+
+    let format = "%(refname:short) %(objectname:short) %(subject) %(authordate:unix)";
+    let listed = repo.run(&["for-each-ref", "--format", format, "refs/heads/*"]);
+    assert_eq!(
+        listed,
+        format!(
+            "master {} second commit 1700000000\nolder {} first commit 1700000000\n",
+            &second[..7],
+            &first[..7]
+        )
+    );
+    assert_eq!(
+        listed,
+        repo.git(&["for-each-ref", "--format", format, "refs/heads/*"])
+    );
+    // a prefix matches the refs below it too
+    assert_eq!(
+        repo.run(&["for-each-ref", "--format", "%(refname)", "refs/tags"]),
+        "refs/tags/v1\n"
+    );
+}
+
+#[test]
+fn star_atoms_show_what_annotated_tags_point_at() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let commit = repo.commit_all("commit");
+    repo.git(&["tag", "-a", "v1", "-m", "annotated"]);
+    repo.git(&["tag", "light"]);
+    let tag = repo.rev_parse("v1");
+
+    let format = "%(refname:short) %(objectname) %(objecttype) %(*objectname) %(*objecttype)";
+    let listed = repo.run(&["for-each-ref", "--format", format, "refs/tags"]);
+    assert_eq!(
+        listed,
+        format!("light {commit} commit  \nv1 {tag} tag {commit} commit\n")
+    );
+    assert_eq!(
+        listed,
+        repo.git(&["for-each-ref", "--format", format, "refs/tags"])
+    );
+}
+
+#[test]
+fn sorts_by_any_atom() {
+    let repo = Repo::init();
+    for (n, (branch, date)) in [
+        ("zeta", "1700000000"),
+        ("alpha", "1700000500"),
+        ("mid", "1600000000"),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        repo.write("a", format!("{n}\n"));
+        repo.git(&["add", "a"]);
+        let status = repo
+            .git_rs(&["commit", "-m", branch])
+            .env("GIT_COMMITTER_DATE", format!("{date} +0000"))
+            .status()
+            .unwrap();
+        assert!(status.success());
+        repo.git(&["branch", branch]);
+    }
+
+    let format = "%(refname:short) %(committerdate:iso)";
+    for sort in [
+        "refname",
+        "-refname",
+        "committerdate",
+        "-committerdate",
+        "subject",
+    ] {
+        let sort = format!("--sort={sort}");
+        let listed = repo.run(&["for-each-ref", &sort, "--format", format, "refs/heads"]);
+        assert_eq!(
+            listed,
+            repo.git(&["for-each-ref", &sort, "--format", format, "refs/heads"]),
+            "{sort}"
+        );
+    }
+    assert_eq!(
+        repo.run(&[
+            "for-each-ref",
+            "--sort=-committerdate",
+            "--format=%(refname:short)",
+            "refs/heads"
+        ]),
+        "alpha\nzeta\nmaster\nmid\n"
+    );
+}
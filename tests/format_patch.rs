@@ -0,0 +1,87 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// A repository with a root commit and two more, the last with a body.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("one");
+    repo.write("a", "a\nb\n");
+    repo.commit_all("add b");
+    repo.write("c", "c\n");
+    repo.commit_all("second change\n\nwith a body");
+    repo
+}
+
+/// `mail` without its last lines, the signature naming the version of whoever wrote it.
+fn without_signature(mail: &str) -> &str {
+    &mail[..mail.rfind("\n-- \n").unwrap()]
+}
+
+#[test]
+fn writes_one_mail_for_a_commit() {
+    let repo = fixture();
+    let out = repo.run(&["format-patch", "HEAD~1", "-o", "out"]);
+    assert_eq!(out, "out/0001-second-change.patch\n");
+    let mail = repo.read("out/0001-second-change.patch");
+    let head = repo.rev_parse("HEAD");
+    assert!(mail.starts_with(&format!("From {head} Mon Sep 17 00:00:00 2001\n")));
+    assert!(
+        mail.contains("\nSubject: [PATCH] second change\n\nwith a body\n---\n"),
+        "{mail}"
+    );
+    assert!(mail.contains(
+        "\ndiff --git a/c b/c\nnew file mode 100644\nindex 0000000..f2ad6c7\n\
+         --- /dev/null\n+++ b/c\n@@ -0,0 +1 @@\n+c\n"
+    ));
+
+    let expected = repo.git(&["format-patch", "--stdout", "HEAD~1"]);
+    assert_eq!(without_signature(&mail), without_signature(&expected));
+}
+
+#[test]
+fn numbers_a_series_and_writes_to_the_output_directory() {
+    let repo = fixture();
+    repo.write("sub/keep", "");
+    let output = repo
+        .git_rs(&["format-patch", "HEAD~2", "-o", "../patches"])
+        .current_dir(repo.join("sub"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "../patches/0001-add-b.patch\n../patches/0002-second-change.patch\n"
+    );
+    let first = repo.read("patches/0001-add-b.patch");
+    assert!(first.contains("\nSubject: [PATCH 1/2] add b\n"), "{first}");
+    let second = repo.read("patches/0002-second-change.patch");
+    assert!(
+        second.contains("\nSubject: [PATCH 2/2] second change\n"),
+        "{second}"
+    );
+
+    // git applies them back onto the base
+    repo.git(&["checkout", "--quiet", "-b", "replay", "HEAD~2"]);
+    let tree = repo.rev_parse("master^{tree}");
+    let mut names: Vec<_> = fs::read_dir(repo.join("patches"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    repo.git(
+        &[
+            &["am", "--quiet"][..],
+            &names.iter().map(String::as_str).collect::<Vec<_>>(),
+        ]
+        .concat(),
+    );
+    assert_eq!(repo.rev_parse("HEAD^{tree}"), tree);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "second change\n\nwith a body\n\n"
+    );
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::Repo;
+use std::{fs, os::unix::fs::PermissionsExt};
+
+/// Replace the loose object `sha` with bytes that don't inflate.
+fn corrupt(repo: &Repo, sha: &str) {
+    let path = repo.join(&format!(".git/objects/{}/{}", &sha[..2], &sha[2..]));
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::write(&path, "garbage").unwrap();
+}
+
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("dir/a", "a\n");
+    repo.write("b", "b\n");
+    repo.commit_all("one");
+    repo
+}
+
+#[test]
+fn clean_repository_reports_dangling_objects_only() {
+    let repo = fixture();
+    let blob = repo
+        .git_with_input(&["hash-object", "-w", "--stdin"], b"loose\n")
+        .trim()
+        .to_string();
+
+    let out = repo.run(&["fsck", "--connectivity-only"]);
+    assert_eq!(out, format!("dangling blob {blob}\n"));
+    assert_eq!(repo.run(&["fsck"]), out);
+}
+
+#[test]
+fn connectivity_only_reports_a_missing_tree_without_reading_blobs() {
+    let repo = fixture();
+    let root = repo.rev_parse("HEAD^{tree}");
+    let tree = repo.rev_parse("HEAD:dir");
+    let blob = repo.rev_parse("HEAD:b");
+    fs::remove_file(repo.join(&format!(".git/objects/{}/{}", &tree[..2], &tree[2..]))).unwrap();
+    // unreadable, but only a full check would notice
+    corrupt(&repo, &blob);
+
+    let output = repo
+        .git_rs(&["fsck", "--connectivity-only"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!("missing tree {tree}\n")),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(&format!("broken link from    tree {root}\n")),
+        "{stdout}"
+    );
+    assert!(!stdout.contains(&blob), "{stdout}");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let stderr = repo.fails(&["fsck"]);
+    assert!(stderr.contains("corrupt"), "{stderr}");
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use common::Repo;
+
+/// A repository in `repo/` with one commit, inside an otherwise empty directory.
+fn fixture() -> Repo {
+    let dir = Repo::empty();
+    dir.git(&["init", "--quiet", "repo"]);
+    dir.write("repo/a", "a\n");
+    dir.git(&["-C", "repo", "add", "a"]);
+    dir.git(&["-C", "repo", "commit", "--quiet", "-m", "one"]);
+    dir.write("elsewhere/file", "");
+    dir
+}
+
+#[test]
+fn cat_file_reads_the_repository_named_by_git_dir() {
+    let dir = fixture();
+    let mut command = dir.git_rs(&["--git-dir", "../repo/.git", "cat-file", "blob", "HEAD:a"]);
+    command.current_dir(dir.join("elsewhere"));
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"a\n");
+
+    let mut command = dir.git_rs(&["cat-file", "blob", "HEAD:a"]);
+    command
+        .current_dir(dir.join("elsewhere"))
+        .env("GIT_DIR", "../repo/.git");
+    assert_eq!(command.output().unwrap().stdout, b"a\n");
+}
+
+#[test]
+fn work_tree_names_the_files_to_compare() {
+    let dir = fixture();
+    dir.write("repo/a", "changed\n");
+    let mut command = dir.git_rs(&[
+        "--git-dir",
+        "repo/.git",
+        "--work-tree",
+        "repo",
+        "status",
+        "--porcelain",
+    ]);
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), " M a\n");
+
+    let mut command = dir.git_rs(&["status", "--porcelain"]);
+    command
+        .env("GIT_DIR", "repo/.git")
+        .env("GIT_WORK_TREE", "repo");
+    assert_eq!(command.output().unwrap().stdout, b" M a\n");
+}
+
+#[test]
+fn missing_git_dir_is_an_error() {
+    let dir = fixture();
+    let stderr = dir.fails(&["--git-dir", "nope", "status"]);
+    assert!(stderr.contains("not a git repository"), "{stderr}");
+}
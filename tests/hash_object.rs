@@ -0,0 +1,80 @@
+mod common;
+
+use common::Repo;
+
+const CRLF: &[u8] = b"one\r\ntwo\r\n";
+
+/// Hash `stdin` with both git-rs and git given `args`, checking they agree, and return the id.
+fn hash(repo: &Repo, args: &[&str], stdin: &[u8]) -> String {
+    let mut full = vec!["hash-object", "--stdin"];
+    full.extend(args);
+    let ours = repo.run_with_input(&full, stdin);
+    assert_eq!(ours, repo.git_with_input(&full, stdin), "{args:?}");
+    ours.trim().to_string()
+}
+
+/// A repository whose `.gitattributes` makes `*.txt` text, `*.bin` binary, and runs `*.up`
+/// through a clean filter that upper-cases it.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write(
+        ".gitattributes",
+        "*.txt text\n*.bin -text\n*.up filter=upper\n",
+    );
+    repo.git(&["config", "filter.upper.clean", "tr a-z A-Z"]);
+    repo
+}
+
+#[test]
+fn path_applies_the_line_ending_conversion_for_that_path() {
+    let repo = fixture();
+    let converted = hash(&repo, &["--path", "file.txt"], CRLF);
+    let raw = hash(&repo, &["--path", "file.bin"], CRLF);
+    assert_ne!(converted, raw);
+    assert_eq!(converted, hash(&repo, &[], b"one\ntwo\n"));
+    // without a path there are no attributes to apply
+    assert_eq!(raw, hash(&repo, &[], CRLF));
+}
+
+#[test]
+fn path_runs_the_clean_filter() {
+    let repo = fixture();
+    let filtered = hash(&repo, &["--path", "shout.up"], b"quiet\n");
+    assert_eq!(filtered, hash(&repo, &[], b"QUIET\n"));
+}
+
+#[test]
+fn autocrlf_converts_files_named_on_the_command_line() {
+    let repo = Repo::init();
+    repo.git(&["config", "core.autocrlf", "true"]);
+    repo.write("file", CRLF);
+    let ours = repo.run(&["hash-object", "file"]);
+    assert_eq!(ours, repo.git(&["hash-object", "file"]));
+    assert_eq!(ours.trim(), hash(&repo, &[], b"one\ntwo\n"));
+}
+
+#[test]
+fn write_stores_the_converted_content() {
+    let repo = fixture();
+    let id = repo.run_with_input(&["hash-object", "-w", "--stdin", "--path", "a.txt"], CRLF);
+    assert_eq!(repo.git(&["cat-file", "blob", id.trim()]), "one\ntwo\n");
+    // and it's the blob git's add makes
+    repo.write("a.txt", CRLF);
+    repo.git(&["add", "a.txt"]);
+    assert_eq!(repo.rev_parse(":a.txt"), id.trim());
+}
+
+#[test]
+fn no_filters_hashes_files_as_they_are() {
+    let repo = fixture();
+    repo.write("file.txt", CRLF);
+    repo.write("shout.up", "quiet\n");
+    for (file, raw) in [("file.txt", CRLF), ("shout.up", b"quiet\n")] {
+        let filtered = repo.run(&["hash-object", file]);
+        assert_eq!(filtered, repo.git(&["hash-object", file]));
+        let unfiltered = repo.run(&["hash-object", "--no-filters", file]);
+        assert_eq!(unfiltered, repo.git(&["hash-object", "--no-filters", file]));
+        assert_ne!(filtered, unfiltered, "{file}");
+        assert_eq!(unfiltered.trim(), hash(&repo, &[], raw));
+    }
+}
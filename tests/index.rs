@@ -0,0 +1,73 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+#[test]
+fn dump_index_checks_the_checksum() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("dir/b", "b\n");
+    repo.run(&["update-index", "--add", "a", "dir/b"]);
+
+    let dump = repo.run(&["dump-index"]);
+    let mut lines = dump.lines();
+    assert_eq!(lines.next(), Some("version 2"));
+    assert_eq!(lines.next(), Some("entries 2"));
+    let checksum = lines.next().unwrap();
+    assert!(checksum.ends_with(" valid true"), "{checksum}");
+    assert!(
+        dump.contains("\na\n") && dump.contains("\ndir/b\n"),
+        "{dump}"
+    );
+
+    // a flipped bit in an entry no longer matches the checksum
+    let path = repo.join(".git/index");
+    let mut index = fs::read(&path).unwrap();
+    index[20] ^= 1;
+    fs::write(&path, index).unwrap();
+    let checksum = repo
+        .run(&["dump-index"])
+        .lines()
+        .nth(2)
+        .unwrap()
+        .to_string();
+    assert!(checksum.ends_with(" valid false"), "{checksum}");
+}
+
+#[test]
+fn git_accepts_an_index_rewritten_with_extensions() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("dir/b", "b\n");
+    repo.write("dir/sub/c", "c\n");
+    repo.git(&["add", "--all"]);
+    repo.git(&["-c", "core.untrackedCache=true", "status"]);
+
+    let has_extension = |signature: &[u8]| {
+        let index = fs::read(repo.join(".git/index")).unwrap();
+        index.windows(4).any(|window| window == signature)
+    };
+    assert!(has_extension(b"UNTR"));
+    repo.run(&["update-index", "--refresh"]);
+    assert!(has_extension(b"UNTR"));
+    // committing records the trees it wrote in the index
+    repo.run(&["commit", "-m", "files"]);
+    assert!(has_extension(b"TREE"));
+    repo.git(&["fsck", "--strict"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    let cached = repo.git(&["ls-files", "--stage"]);
+
+    repo.write("dir/b", "changed\n");
+    repo.run(&["update-index", "dir/b"]);
+    repo.git(&["fsck", "--strict"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), "M  dir/b\n");
+    assert_ne!(repo.git(&["ls-files", "--stage"]), cached);
+    repo.run(&["commit", "-m", "change"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    assert_eq!(
+        repo.git(&["write-tree"]).trim(),
+        repo.rev_parse("HEAD^{tree}")
+    );
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn quiet_prints_nothing_but_creates_the_layout() {
+    let dir = Repo::empty();
+    assert_eq!(dir.run(&["init", "--quiet", "repo"]), "");
+    for path in [
+        "HEAD",
+        "config",
+        "description",
+        "objects",
+        "refs/heads",
+        "refs/tags",
+    ] {
+        assert!(dir.join("repo/.git").join(path).exists(), "{path}");
+    }
+    assert_eq!(dir.read("repo/.git/HEAD"), "ref: refs/heads/master\n");
+    // a repository git can use
+    dir.git(&["-C", "repo", "status", "--porcelain"]);
+}
+
+#[test]
+fn says_where_the_repository_is() {
+    let dir = Repo::empty();
+    assert_eq!(
+        dir.run(&["init", "repo"]),
+        format!(
+            "Initialized empty Git repository in {}/\n",
+            dir.join("repo/.git").display()
+        )
+    );
+}
+
+#[test]
+fn init_again_keeps_what_the_repository_holds() {
+    let repo = Repo::init();
+    repo.git(&["config", "user.name", "Kept Config"]);
+    repo.write("f", "kept\n");
+    let blob = repo.git(&["hash-object", "-w", "f"]);
+    let commit = repo.commit_all("kept commit");
+
+    assert_eq!(
+        repo.run(&["init"]),
+        format!(
+            "Reinitialized existing Git repository in {}/\n",
+            repo.join(".git").display()
+        )
+    );
+    assert_eq!(repo.git(&["cat-file", "-p", blob.trim()]), "kept\n");
+    assert_eq!(repo.rev_parse("HEAD"), commit);
+    assert_eq!(repo.git(&["config", "user.name"]), "Kept Config\n");
+    repo.git(&["fsck", "--strict"]);
+}
+
+#[test]
+fn init_again_restores_missing_directories() {
+    let repo = Repo::init();
+    std::fs::remove_dir_all(repo.join(".git/refs/tags")).unwrap();
+    repo.run(&["init", "--quiet"]);
+    assert!(repo.join(".git/refs/tags").is_dir());
+}
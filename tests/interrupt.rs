@@ -0,0 +1,91 @@
+mod common;
+
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::Repo;
+
+/// Send `signal` to the process `pid`.
+fn kill(signal: &str, pid: u32) {
+    let status = Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Start `diff` with a textconv command that hangs, and wait until it runs: `diff` then has
+/// the blob it converts in a temporary file.
+fn hanging_diff(repo: &Repo) -> std::process::Child {
+    repo.git(&[
+        "config",
+        "diff.slow.textconv",
+        "touch started; sleep 5; cat",
+    ]);
+    repo.write(".gitattributes", "*.txt diff=slow\n");
+    repo.write("a.txt", "a\n");
+    repo.commit_all("one");
+    repo.write("a.txt", "changed\n");
+
+    let child = repo
+        .git_rs(&["diff"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while !repo.join("started").exists() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "textconv didn't start"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+    child
+}
+
+/// The temporary files left in the textconv cache.
+fn leftovers(repo: &Repo) -> Vec<String> {
+    let mut found = Vec::new();
+    for dir in fs::read_dir(repo.join(".git/textconv-cache")).unwrap() {
+        for file in fs::read_dir(dir.unwrap().path()).unwrap() {
+            found.push(file.unwrap().file_name().to_string_lossy().into_owned());
+        }
+    }
+    found
+}
+
+#[test]
+fn sigint_removes_temporary_files_and_exits_130() {
+    let repo = Repo::init();
+    let mut child = hanging_diff(&repo);
+    assert_eq!(leftovers(&repo).len(), 1);
+
+    let interrupted = Instant::now();
+    kill("-INT", child.id());
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), Some(130));
+    assert!(interrupted.elapsed() < Duration::from_secs(3));
+    assert_eq!(leftovers(&repo), Vec::<String>::new());
+
+    // the repository is still usable
+    assert!(!repo.join(".git/index.lock").exists());
+    repo.git(&["fsck", "--no-progress"]);
+    assert_eq!(
+        repo.run(&["status", "--porcelain"]),
+        " M a.txt\n?? started\n"
+    );
+}
+
+#[test]
+fn sigterm_is_handled_the_same_way() {
+    let repo = Repo::init();
+    let mut child = hanging_diff(&repo);
+    kill("-TERM", child.id());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+    assert_eq!(leftovers(&repo), Vec::<String>::new());
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use common::Repo;
+use git_rs::{ConfigScope, GitError, GitRepository, Kind};
+use std::{fs, os::unix::fs::PermissionsExt};
+
+#[test]
+fn reads_head_and_its_tree() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let first = repo.commit_all("one");
+    repo.write("a", "a\nb\n");
+    let second = repo.commit_all("two");
+
+    let git_repo = GitRepository::open(&repo.path).unwrap();
+    assert_eq!(git_repo.resolve_ref("HEAD").unwrap(), second);
+
+    let log = git_repo
+        .log_iter("HEAD")
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let ids: Vec<_> = log.iter().map(|(id, _)| id.as_str()).collect();
+    assert_eq!(ids, [second.as_str(), first.as_str()]);
+    let head = &log[0].1;
+    assert_eq!(head.parents, [first]);
+    assert_eq!(head.message, "two\n");
+    assert_eq!(head.tree, repo.rev_parse("HEAD^{tree}"));
+
+    let (kind, tree) = git_repo.read_object(&head.tree).unwrap();
+    assert_eq!(kind, Kind::Tree);
+    let blob = repo.rev_parse("HEAD:a");
+    let mut expected = b"100644 a\0".to_vec();
+    expected.extend(hex_to_bytes(&blob));
+    assert_eq!(tree, expected);
+    assert_eq!(
+        git_repo.read_object(&blob).unwrap(),
+        (Kind::Blob, b"a\nb\n".to_vec())
+    );
+}
+
+#[test]
+fn writes_objects_and_commits_git_can_read() {
+    let repo = Repo::init();
+    let mut git_repo = GitRepository::open(&repo.path).unwrap();
+    git_repo
+        .config_set(ConfigScope::Local, "user", "name", Some("Lib User"))
+        .unwrap();
+    git_repo
+        .config_set(ConfigScope::Local, "user", "email", Some("lib@example.com"))
+        .unwrap();
+    assert_eq!(repo.git(&["config", "user.name"]), "Lib User\n");
+
+    let blob = git_repo.write_object(Kind::Blob, b"hello\n").unwrap();
+    assert_eq!(
+        blob,
+        repo.git_with_input(&["hash-object", "--stdin"], b"hello\n")
+            .trim()
+    );
+    let mut tree = b"100644 hello.txt\0".to_vec();
+    tree.extend(hex_to_bytes(&blob));
+    let tree = git_repo.write_object(Kind::Tree, &tree).unwrap();
+
+    let root = git_repo.write_commit(&tree, &[], "root\n").unwrap();
+    let child = git_repo
+        .write_commit(&tree, std::slice::from_ref(&root), "child\n")
+        .unwrap();
+    assert_eq!(repo.git(&["cat-file", "-t", &child]), "commit\n");
+    assert_eq!(repo.rev_parse(&format!("{child}^")), root);
+    assert_eq!(
+        repo.git(&["cat-file", "blob", &format!("{child}:hello.txt")]),
+        "hello\n"
+    );
+    assert_eq!(repo.git(&["log", "--format=%s", &child]), "child\nroot\n");
+    // no ref was moved
+    assert!(git_repo.resolve_ref("HEAD").is_err());
+}
+
+#[test]
+fn errors_name_what_went_wrong() {
+    let dir = Repo::empty();
+    let err = GitRepository::open(&dir.path).unwrap_err();
+    assert!(matches!(err, GitError::NotARepository(_)), "{err:?}");
+
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("one");
+    let git_repo = GitRepository::open(&repo.path).unwrap();
+
+    let err = git_repo.resolve_ref("refs/heads/missing").unwrap_err();
+    assert!(matches!(&err, GitError::RefNotFound(name) if name == "refs/heads/missing"));
+
+    let err = git_repo.read_object("not-an-id").unwrap_err();
+    assert!(matches!(err, GitError::InvalidObjectId(_)), "{err:?}");
+
+    let missing = "0123456789012345678901234567890123456789";
+    let err = git_repo.read_object(missing).unwrap_err();
+    assert!(matches!(&err, GitError::ObjectNotFound(id) if id == missing));
+
+    let blob = repo.rev_parse("HEAD:a");
+    let path = repo.join(&format!(".git/objects/{}/{}", &blob[..2], &blob[2..]));
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+    fs::write(&path, "garbage").unwrap();
+    let err = git_repo.read_object(&blob).unwrap_err();
+    assert!(matches!(&err, GitError::InvalidObject { id, .. } if *id == blob));
+}
+
+fn hex_to_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn object_iter_yields_exactly_the_written_objects() {
+    let repo = Repo::init();
+    let git_repo = GitRepository::open(&repo.path).unwrap();
+    assert_eq!(git_repo.object_iter().unwrap().count(), 0);
+
+    let mut written = std::collections::BTreeSet::new();
+    for data in ["one\n", "two\n", "three\n", "one\n"] {
+        let id = repo.run_with_input(&["hash-object", "-w", "--stdin"], data.as_bytes());
+        written.insert(id.trim().to_string());
+    }
+    let found = git_repo
+        .object_iter()
+        .unwrap()
+        .collect::<Result<std::collections::BTreeSet<_>, _>>()
+        .unwrap();
+    assert_eq!(found, written);
+
+    // packed copies of loose objects are only listed once
+    let list: String = written.iter().map(|id| format!("{id}\n")).collect();
+    repo.git_with_input(
+        &["pack-objects", "-q", ".git/objects/pack/pack"],
+        list.as_bytes(),
+    );
+    assert_eq!(
+        repo.git(&["count-objects", "-v"]).lines().nth(2),
+        Some("in-pack: 3")
+    );
+    let ids = git_repo
+        .object_iter()
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(ids.len(), written.len(), "{ids:?}");
+}
+
+#[test]
+fn repositories_open_side_by_side_keep_their_own_hash() {
+    let sha256 = Repo::empty();
+    sha256.git(&["init", "-q", "--object-format=sha256"]);
+    let sha1 = Repo::empty();
+
+    let first = GitRepository::open(&sha256.path).unwrap();
+    let second = GitRepository::init(&sha1.path).unwrap();
+    let blob = first.write_object(Kind::Blob, b"a\n").unwrap();
+    assert_eq!(
+        blob,
+        sha256
+            .git_with_input(&["hash-object", "--stdin"], b"a\n")
+            .trim()
+    );
+    let blob = second.write_object(Kind::Blob, b"a\n").unwrap();
+    assert_eq!(
+        blob,
+        sha1.git_with_input(&["hash-object", "--stdin"], b"a\n")
+            .trim()
+    );
+    sha256.git(&["fsck", "--strict"]);
+    sha1.git(&["fsck", "--strict"]);
+}
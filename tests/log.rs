@@ -0,0 +1,277 @@
+mod common;
+
+use common::Repo;
+
+/// A history touching `a`, `b` and `dir/c` in different commits, with a side branch merged back
+/// in and a rename of `b` to `renamed`.
+fn history() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a1\n");
+    repo.write("b", "b\nwith\nenough\nlines\nto\nbe\nrenamed\n");
+    repo.commit_all("add a and b");
+    repo.write("dir/c", "c1\n");
+    repo.commit_all("add dir/c");
+    repo.git(&["checkout", "-q", "-b", "side"]);
+    repo.write("a", "a2\n");
+    repo.commit_all("change a on side");
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("dir/c", "c2\n");
+    repo.commit_all("change dir/c");
+    repo.git(&["merge", "-q", "--no-ff", "-m", "merge side", "side"]);
+    repo.git(&["mv", "b", "renamed"]);
+    repo.commit_all("rename b");
+    repo.write(
+        "renamed",
+        "b\nwith\nenough\nlines\nto\nbe\nrenamed\nchanged\n",
+    );
+    repo.commit_all("change renamed");
+    repo
+}
+
+fn subjects(repo: &Repo, args: &[&str]) -> String {
+    let mut full = vec!["log", "--format=%s"];
+    full.extend(args);
+    let ours = repo.run(&full);
+    assert_eq!(ours, repo.git(&full), "{args:?}");
+    ours
+}
+
+#[test]
+fn limits_the_log_to_commits_changing_a_path() {
+    let repo = history();
+    assert_eq!(
+        subjects(&repo, &["--", "a"]),
+        "change a on side\nadd a and b\n"
+    );
+    assert_eq!(subjects(&repo, &["--", "dir"]), "change dir/c\nadd dir/c\n");
+    assert_eq!(
+        subjects(&repo, &["--", "a", "dir/c"]),
+        "merge side\nchange dir/c\nchange a on side\nadd dir/c\nadd a and b\n",
+        "the merge differs from each parent in one of the paths"
+    );
+    assert_eq!(subjects(&repo, &["--", "missing"]), "");
+}
+
+#[test]
+fn follow_tracks_a_file_across_its_rename() {
+    let repo = history();
+    assert_eq!(
+        subjects(&repo, &["--", "renamed"]),
+        "change renamed\nrename b\n"
+    );
+    assert_eq!(
+        subjects(&repo, &["--follow", "--", "renamed"]),
+        "change renamed\nrename b\nadd a and b\n"
+    );
+}
+
+/// Commit everything as `author` at the unix time `date`, for both author and committer dates.
+fn commit_at(repo: &Repo, message: &str, author: &str, date: i64) {
+    repo.git(&["add", "--all"]);
+    let date = format!("{date} +0000");
+    let status = repo
+        .git_rs(&["commit", "-m", message])
+        .env("GIT_AUTHOR_NAME", author)
+        .env("GIT_AUTHOR_DATE", &date)
+        .env("GIT_COMMITTER_DATE", &date)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Commits by two authors over several days, a merge, and a child dated before its parent.
+fn dated_history() -> Repo {
+    let repo = Repo::init();
+    let day = 86400;
+    repo.write("a", "1\n");
+    commit_at(&repo, "first: setup", "Ann", 1_700_000_000);
+    repo.git(&["checkout", "-q", "-b", "side"]);
+    repo.write("b", "1\n");
+    commit_at(&repo, "side work", "Bob", 1_700_000_000 + day);
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("a", "2\n");
+    commit_at(&repo, "fix: the bug", "Ann", 1_700_000_000 + 2 * day);
+    repo.git(&["merge", "-q", "--no-ff", "-m", "merge side", "side"]);
+    repo.write("a", "3\n");
+    // clock skew: older than its parent
+    commit_at(&repo, "skewed", "Bob", 1_700_000_000 - day);
+    repo
+}
+
+#[test]
+fn filters_by_date_author_message_and_merges() {
+    let repo = dated_history();
+    let cases: &[&[&str]] = &[
+        &["--since", "2023-11-15 12:00:00 +0000"],
+        &["--until", "2023-11-15 12:00:00 +0000"],
+        &["--since", "@1700000000", "--until", "@1700086400"],
+        &["--author", "^Bob"],
+        &["--author", "Ann", "--author", "Bob"],
+        &["--grep", "^fix:"],
+        &["--grep", "side", "--no-merges"],
+        &["--merges"],
+        &["--no-merges"],
+    ];
+    for args in cases {
+        subjects(&repo, args);
+    }
+    assert_eq!(
+        subjects(&repo, &["--author", "^Bob"]),
+        "skewed\nside work\n"
+    );
+    assert_eq!(subjects(&repo, &["--merges"]), "merge side\n");
+}
+
+#[test]
+fn orders_topologically_and_in_reverse() {
+    let repo = dated_history();
+    let topo = subjects(&repo, &["--topo-order"]);
+    assert!(topo.starts_with("skewed\nmerge side\n"), "{topo}");
+    assert!(topo.ends_with("first: setup\n"), "{topo}");
+    let reversed = subjects(&repo, &["--reverse", "--topo-order"]);
+    assert_eq!(
+        reversed.lines().rev().collect::<Vec<_>>(),
+        topo.lines().collect::<Vec<_>>()
+    );
+    subjects(&repo, &["--reverse"]);
+}
+
+#[test]
+fn lists_only_the_commits_that_changed_a_path() {
+    let repo = Repo::init();
+    repo.write("tracked", "1\n");
+    repo.write("other", "1\n");
+    repo.commit_all("one");
+    repo.write("other", "2\n");
+    repo.commit_all("two");
+    repo.write("tracked", "3\n");
+    repo.commit_all("three");
+    repo.write("other", "4\n");
+    repo.commit_all("four");
+    assert_eq!(subjects(&repo, &["--", "tracked"]), "three\none\n");
+    assert_eq!(
+        subjects(&repo, &["--", "tracked", "other"]),
+        "four\nthree\ntwo\none\n"
+    );
+}
+
+#[test]
+fn first_parent_leaves_out_merged_commits() {
+    let repo = history();
+    let mainline = subjects(&repo, &["--first-parent"]);
+    assert_eq!(
+        mainline,
+        "change renamed\nrename b\nmerge side\nchange dir/c\nadd dir/c\nadd a and b\n"
+    );
+    assert!(subjects(&repo, &[]).contains("change a on side\n"));
+}
+
+#[test]
+fn json_format_prints_one_object_per_commit() {
+    let repo = Repo::init();
+    repo.write("a", "a1\n");
+    let first = repo.commit_all("first");
+    repo.write("a", "a2\n");
+    let second = repo.commit_all(
+        "second \"quoted\"\n\nwith a body\n\nSigned-off-by: A U Thor <author@example.com>",
+    );
+
+    let out = repo.run(&["log", "--format=json"]);
+    let commits: Vec<serde_json::Value> = out
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(commits.len(), 2);
+
+    let (head, root) = (&commits[0], &commits[1]);
+    assert_eq!(head["hash"], second.as_str());
+    assert_eq!(head["parents"], serde_json::json!([first]));
+    assert_eq!(
+        head["message"],
+        "second \"quoted\"\n\nwith a body\n\nSigned-off-by: A U Thor <author@example.com>\n"
+    );
+    assert_eq!(
+        head["trailers"],
+        serde_json::json!([["Signed-off-by", "A U Thor <author@example.com>"]])
+    );
+    assert_eq!(head["author"]["name"], "A U Thor");
+    assert_eq!(head["author"]["email"], "author@example.com");
+    assert_eq!(head["author"]["time"], 1700000000);
+    assert_eq!(head["author"]["tz"], "+0000");
+    assert_eq!(head["committer"]["name"], "C O Mitter");
+    assert_eq!(root["hash"], first.as_str());
+    assert_eq!(root["parents"], serde_json::json!([]));
+    assert_eq!(root["message"], "first\n");
+    assert_eq!(root["trailers"], serde_json::json!([]));
+}
+
+#[test]
+fn date_formats_render_a_fixed_timestamp() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.git(&["add", "a"]);
+    // 22:13:20 UTC, already the next day two hours east
+    let output = repo
+        .git_rs(&["commit", "-m", "one"])
+        .env("GIT_AUTHOR_DATE", "1700000000 +0200")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let date = |args: &[&str]| {
+        let out = repo.run(&[&["log"][..], args].concat());
+        out.lines()
+            .find_map(|line| line.strip_prefix("Date:   "))
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(date(&["--date=short"]), "2023-11-15");
+    assert_eq!(date(&["--date=unix"]), "1700000000");
+    assert_eq!(date(&["--date=iso"]), "2023-11-15 00:13:20 +0200");
+    for format in ["short", "unix", "iso", "relative"] {
+        let option = format!("--date={format}");
+        assert_eq!(
+            repo.run(&["log", &option]),
+            repo.git(&["log", &option]),
+            "{format}"
+        );
+    }
+    let relative = date(&["--date=relative"]);
+    assert!(
+        relative.contains(" year") && relative.ends_with(" ago"),
+        "{relative}"
+    );
+
+    // log.date sets the default, which the option overrides
+    repo.git(&["config", "log.date", "short"]);
+    assert_eq!(date(&[]), "2023-11-15");
+    assert_eq!(date(&["--date=unix"]), "1700000000");
+}
+
+#[test]
+fn patches_and_stats_match_git() {
+    let repo = history();
+    for args in [
+        &["log", "-p"][..],
+        &["log", "--stat"],
+        &["log", "--oneline", "-p", "-3"],
+        &["log", "--oneline", "--stat"],
+        &["log", "--format=%s", "-p", "-M90%", "-2"],
+        &["log", "-1"],
+    ] {
+        assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+    }
+}
+
+#[test]
+fn show_prints_commits_with_their_changes() {
+    let repo = history();
+    for args in [
+        &["show"][..],
+        &["show", "-s", "HEAD~1"],
+        &["show", "--stat", "HEAD~2"],
+        &["show", "--oneline", "HEAD~2", "HEAD~3"],
+    ] {
+        assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+    }
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// A repository ignoring `target/` and `*.log`, with `a` modified, `b` deleted, and untracked,
+/// ignored and nested new files.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write(".gitignore", "target/\n*.log\n");
+    repo.write("a", "a\n");
+    repo.write("b", "b\n");
+    repo.write("d/c", "c\n");
+    repo.commit_all("one");
+    repo.write("a", "modified\n");
+    fs::remove_file(repo.join("b")).unwrap();
+    repo.write("u", "u\n");
+    repo.write("target/x", "x\n");
+    repo.write("d/x.log", "log\n");
+    repo.write("newdir/n", "n\n");
+    repo
+}
+
+fn assert_same(repo: &Repo, args: &[&str]) {
+    assert_eq!(repo.run(args), repo.git(args), "{args:?}");
+}
+
+#[test]
+fn others_and_ignored() {
+    let repo = fixture();
+    assert_eq!(
+        repo.run(&["ls-files", "--others", "--exclude-standard"]),
+        "newdir/n\nu\n"
+    );
+    assert_eq!(
+        repo.run(&["ls-files", "--others", "--ignored", "--exclude-standard"]),
+        "d/x.log\ntarget/x\n"
+    );
+    for args in [
+        &["ls-files"][..],
+        &["ls-files", "--others"],
+        &["ls-files", "--others", "--exclude-standard"],
+        &["ls-files", "--others", "--ignored", "--exclude-standard"],
+        &["ls-files", "-z", "--others", "--exclude-standard"],
+        &["ls-files", "--others", "--exclude-standard", "d"],
+    ] {
+        assert_same(&repo, args);
+    }
+}
+
+#[test]
+fn modified_and_deleted() {
+    let repo = fixture();
+    // a deleted file counts as modified too
+    assert_eq!(repo.run(&["ls-files", "--modified"]), "a\nb\n");
+    assert_eq!(repo.run(&["ls-files", "--deleted"]), "b\n");
+    for args in [
+        &["ls-files", "--modified"][..],
+        &["ls-files", "--deleted"],
+        &["ls-files", "-m", "-d"],
+        &["ls-files", "-z", "--modified"],
+    ] {
+        assert_same(&repo, args);
+    }
+}
+
+#[test]
+fn error_unmatch_checks_that_paths_are_tracked() {
+    let repo = fixture();
+    assert_eq!(
+        repo.run(&["ls-files", "--error-unmatch", "a", "d/c"]),
+        "a\nd/c\n"
+    );
+    let stderr = repo.fails(&["ls-files", "--error-unmatch", "a", "u"]);
+    assert_eq!(
+        stderr,
+        "error: pathspec 'u' did not match any file(s) known to git\n\
+         Did you forget to 'git add'?\n"
+    );
+}
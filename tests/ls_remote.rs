@@ -0,0 +1,74 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+/// A repository to list, with branches, a lightweight and an annotated tag, and packed refs.
+fn fixture() -> Repo {
+    let remote = Repo::init();
+    remote.write("a", "a\n");
+    remote.commit_all("first");
+    remote.git(&["branch", "topic"]);
+    remote.git(&["tag", "light"]);
+    remote.git(&["tag", "-a", "-m", "annotated", "v1"]);
+    remote.git(&["pack-refs", "--all"]);
+    remote.write("a", "b\n");
+    remote.commit_all("second");
+    remote
+}
+
+#[test]
+fn lists_the_refs_of_a_repository_by_path() {
+    let remote = fixture();
+    let repo = Repo::init();
+    let path = remote.path.to_str().unwrap();
+    let listed = repo.run(&["ls-remote", path]);
+    assert_eq!(listed, repo.git(&["ls-remote", path]));
+    // the annotated tag is followed by the commit it tags
+    let head = remote.rev_parse("HEAD~1");
+    assert!(
+        listed.contains(&format!("{head}\trefs/tags/v1^{{}}\n")),
+        "{listed}"
+    );
+}
+
+#[test]
+fn lists_a_configured_remote_and_a_file_url() {
+    let remote = fixture();
+    let repo = Repo::init();
+    let path = remote.path.to_str().unwrap();
+    repo.git(&["remote", "add", "origin", path]);
+    let expected = repo.git(&["ls-remote", path]);
+    assert_eq!(repo.run(&["ls-remote", "origin"]), expected);
+    assert_eq!(
+        repo.run(&["ls-remote", &format!("file://{path}")]),
+        expected
+    );
+}
+
+#[test]
+fn lists_over_either_protocol_and_ssh() {
+    let remote = fixture();
+    let repo = Repo::init();
+    let path = remote.path.to_str().unwrap();
+    let expected = repo.git(&["ls-remote", path]);
+    repo.git(&["config", "protocol.version", "0"]);
+    assert_eq!(repo.run(&["ls-remote", path]), expected);
+    repo.git(&["config", "protocol.version", "2"]);
+
+    // stands in for ssh: runs the remote command here
+    let ssh = repo.join("ssh");
+    fs::write(
+        &ssh,
+        "#!/bin/sh\nwhile [ \"${1#-}\" != \"$1\" ]; do [ \"$1\" = -- ] && { shift; break; }; shift 2; done\nshift\nexec sh -c \"$*\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    let output = repo
+        .git_rs(&["ls-remote", &format!("example.com:{path}")])
+        .env("GIT_SSH_COMMAND", &ssh)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::Repo;
+
+/// A repository where `side` has two commits and `master` one other since they forked.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("base", "base\n");
+    repo.commit_all("base");
+    repo.git(&["checkout", "-q", "-b", "side"]);
+    repo.write("s", "s\n");
+    repo.commit_all("side one");
+    repo.write("t", "t\n");
+    repo.commit_all("side two");
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("m", "m\n");
+    repo.commit_all("master");
+    repo
+}
+
+/// Commit with the message git-rs prepares, accepting it as an editor would.
+fn commit_prepared(repo: &Repo) {
+    let status = repo
+        .git_rs(&["commit"])
+        .env("GIT_EDITOR", "true")
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn parents(repo: &Repo) -> Vec<String> {
+    let parents = repo.git(&["log", "-1", "--format=%P"]);
+    parents.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn no_commit_stops_before_the_merge_commit() {
+    let repo = fixture();
+    let (master, side) = (repo.rev_parse("master"), repo.rev_parse("side"));
+    repo.run(&["merge", "--no-commit", "side"]);
+    assert_eq!(repo.rev_parse("HEAD"), master);
+    assert_eq!(repo.read(".git/MERGE_HEAD"), format!("{side}\n"));
+    assert_eq!(repo.read(".git/MERGE_MSG"), "Merge branch 'side'\n");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "A  s\nA  t\n");
+
+    commit_prepared(&repo);
+    assert_eq!(parents(&repo), [master, side]);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "Merge branch 'side'\n\n"
+    );
+    assert!(!repo.join(".git/MERGE_HEAD").exists());
+    assert!(!repo.join(".git/MERGE_MSG").exists());
+}
+
+#[test]
+fn squash_commits_with_a_single_parent() {
+    let repo = fixture();
+    let master = repo.rev_parse("master");
+    repo.run(&["merge", "--squash", "side"]);
+    assert_eq!(repo.rev_parse("HEAD"), master);
+    assert!(!repo.join(".git/MERGE_HEAD").exists());
+    let message = repo.read(".git/SQUASH_MSG");
+    assert!(
+        message.starts_with("Squashed commit of the following:\n"),
+        "{message}"
+    );
+    let (two, one) = (
+        message.find("    side two\n").unwrap(),
+        message.find("    side one\n").unwrap(),
+    );
+    assert!(two < one, "newest first: {message}");
+
+    commit_prepared(&repo);
+    assert_eq!(parents(&repo), [master]);
+    assert!(repo
+        .git(&["log", "-1", "--format=%B"])
+        .starts_with("Squashed commit of the following:\n"));
+    assert_eq!(
+        repo.git(&["ls-tree", "--name-only", "HEAD"]),
+        "base\nm\ns\nt\n"
+    );
+    assert!(!repo.join(".git/SQUASH_MSG").exists());
+}
+
+#[test]
+fn conflicts_are_staged_until_add_resolves_them() {
+    let repo = fixture();
+    repo.write("base", "master\n");
+    repo.commit_all("master changes base");
+    repo.git(&["checkout", "-q", "side"]);
+    repo.write("base", "side\n");
+    repo.commit_all("side changes base");
+    repo.git(&["checkout", "-q", "master"]);
+    let side = repo.rev_parse("side");
+
+    repo.fails(&["merge", "side"]);
+    let stages = repo.git(&["ls-files", "--stage", "base"]);
+    let stages = stages
+        .lines()
+        .map(|line| line.split_whitespace().nth(2).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(stages, ["1", "2", "3"]);
+    assert_eq!(
+        repo.git(&["status", "--porcelain"]),
+        "UU base\nA  s\nA  t\n"
+    );
+
+    repo.write("base", "resolved\n");
+    repo.run(&["add", "base"]);
+    assert_eq!(
+        repo.git(&["status", "--porcelain"]),
+        "M  base\nA  s\nA  t\n"
+    );
+    commit_prepared(&repo);
+    assert_eq!(parents(&repo)[1], side);
+    assert_eq!(repo.git(&["show", "HEAD:base"]), "resolved\n");
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::Repo;
+
+/// `main` and `side` branch off one commit, changing `a` and `b` respectively.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("b", "b\n");
+    repo.commit_all("one");
+    repo.git(&["branch", "side"]);
+    repo.write("a", "a main\n");
+    repo.commit_all("main");
+    repo.git(&["checkout", "--quiet", "side"]);
+    repo.write("b", "b side\n");
+    repo.commit_all("side");
+    repo.git(&["checkout", "--quiet", "-"]);
+    repo
+}
+
+#[test]
+fn clean_merge_prints_the_tree() {
+    let repo = fixture();
+    let args = ["merge-tree", "--write-tree", "HEAD", "side"];
+    let out = repo.run(&args);
+    assert_eq!(out, repo.git(&args));
+    let tree = out.trim();
+    assert_eq!(
+        repo.git(&["cat-file", "blob", &format!("{tree}:a")]),
+        "a main\n"
+    );
+    assert_eq!(
+        repo.git(&["cat-file", "blob", &format!("{tree}:b")]),
+        "b side\n"
+    );
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+}
+
+#[test]
+fn conflicts_list_the_stages_and_exit_1() {
+    let repo = fixture();
+    repo.git(&["checkout", "--quiet", "side"]);
+    repo.write("a", "a side\n");
+    repo.commit_all("conflict");
+    repo.git(&["checkout", "--quiet", "-"]);
+    let head = repo.rev_parse("HEAD");
+
+    let args = ["merge-tree", "--write-tree", "HEAD", "side"];
+    let output = repo.git_rs(&args).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" 1\ta\n") && stdout.contains(" 2\ta\n") && stdout.contains(" 3\ta\n"));
+    assert!(stdout.ends_with("\nAuto-merging a\nCONFLICT (content): Merge conflict in a\n"));
+    let git = repo.command("git").args(args).output().unwrap();
+    assert_eq!(stdout.as_bytes(), git.stdout);
+
+    // neither the index nor the work tree is touched
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    assert_eq!(repo.read("a"), "a main\n");
+    assert_eq!(repo.rev_parse("HEAD"), head);
+}
+
+#[test]
+fn works_in_a_bare_repository() {
+    let repo = fixture();
+    repo.git(&["clone", "--quiet", "--bare", ".", "bare.git"]);
+    let branch = repo.git(&["symbolic-ref", "--short", "HEAD"]);
+    let mut command = repo.git_rs(&["merge-tree", "--write-tree", branch.trim(), "side"]);
+    command.current_dir(repo.join("bare.git"));
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let expected = repo.git(&["merge-tree", "--write-tree", "HEAD", "side"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::Repo;
+
+/// A history with a merge, a branch behind `master`, and an annotated tag.
+fn history() -> Repo {
+    let repo = Repo::init();
+    for n in 1..=3 {
+        repo.write("f", format!("{n}\n"));
+        repo.commit_all(&format!("c{n}"));
+    }
+    repo.git(&["tag", "-a", "v1", "-m", "tag", "HEAD~1"]);
+    repo.git(&["checkout", "-q", "-b", "side", "HEAD~2"]);
+    repo.write("s", "s\n");
+    repo.commit_all("side");
+    repo.git(&["checkout", "-q", "master"]);
+    repo.git(&["merge", "-q", "--no-ff", "-m", "merge", "side"]);
+    repo.git(&["branch", "-q", "-D", "side"]);
+    repo.git(&["branch", "old", "HEAD~1"]);
+    repo
+}
+
+#[test]
+fn names_every_commit_like_git() {
+    let repo = history();
+    for commit in repo.git(&["rev-list", "--all"]).lines() {
+        let named = repo.run(&["name-rev", commit]);
+        assert_eq!(named, repo.git(&["name-rev", commit]), "{commit}");
+    }
+    let side = repo.rev_parse("HEAD^2");
+    assert_eq!(repo.run(&["name-rev", &side]), format!("{side} master^2\n"));
+}
+
+#[test]
+fn log_decorates_commits_with_their_refs() {
+    let repo = history();
+    let log = repo.run(&["log", "--decorate", "--format=oneline"]);
+    assert_eq!(log, repo.git(&["log", "--decorate", "--format=oneline"]));
+    assert!(log.contains(" (HEAD -> master) merge\n"), "{log}");
+    assert!(log.contains(" (tag: v1) c2\n"), "{log}");
+    assert!(!repo.run(&["log", "--no-decorate"]).contains("HEAD ->"));
+}
+
+#[test]
+fn symbolic_full_name() {
+    let repo = history();
+    let names = repo.run(&["rev-parse", "--symbolic-full-name", "HEAD", "old", "v1"]);
+    assert_eq!(names, "refs/heads/master\nrefs/heads/old\nrefs/tags/v1\n");
+    assert_eq!(
+        names,
+        repo.git(&["rev-parse", "--symbolic-full-name", "HEAD", "old", "v1"])
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with two commits.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    repo.commit_all("first");
+    repo.write("a", "2\n");
+    repo.commit_all("second");
+    repo
+}
+
+#[test]
+fn adds_and_shows_a_note() {
+    let repo = fixture();
+    repo.run(&["notes", "add", "-m", "reviewed", "HEAD~1"]);
+    assert_eq!(repo.run(&["notes", "show", "HEAD~1"]), "reviewed\n");
+    // stored where git looks for it
+    assert_eq!(repo.git(&["notes", "show", "HEAD~1"]), "reviewed\n");
+    repo.git(&["fsck", "--strict"]);
+
+    let err = repo.fails(&["notes", "show", "HEAD"]);
+    assert!(err.contains("no note found"), "{err}");
+}
+
+#[test]
+fn replacing_a_note_needs_force() {
+    let repo = fixture();
+    repo.run(&["notes", "add", "-m", "old", "HEAD"]);
+    let err = repo.fails(&["notes", "add", "-m", "new", "HEAD"]);
+    assert!(
+        err.contains("Use '-f' to overwrite existing notes"),
+        "{err}"
+    );
+    repo.run(&["notes", "add", "-f", "-m", "new", "HEAD"]);
+    assert_eq!(repo.git(&["notes", "show", "HEAD"]), "new\n");
+}
+
+#[test]
+fn log_shows_notes() {
+    let repo = fixture();
+    repo.run(&["notes", "add", "-m", "reviewed", "HEAD~1"]);
+    let log = repo.run(&["log", "--show-notes"]);
+    assert!(
+        log.ends_with("    first\n\nNotes:\n    reviewed\n"),
+        "{log}"
+    );
+    assert_eq!(log, repo.git(&["log", "--show-notes"]));
+    assert!(!repo.run(&["log"]).contains("Notes:"));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::{
+    io::Read,
+    process::{Command, Stdio},
+};
+
+use common::Repo;
+
+/// A repository with a few commits, and a pager that leaves a marker file behind when it runs.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    for n in 0..3 {
+        repo.write("file", format!("{n}\n"));
+        repo.commit_all(&format!("commit {n}"));
+    }
+    repo
+}
+
+fn with_marking_pager(repo: &Repo, args: &[&str]) -> Command {
+    let mut command = repo.git_rs(args);
+    command
+        .env("GIT_PAGER", "touch pager-ran; cat")
+        .env("PAGER", "touch pager-ran; cat");
+    command
+}
+
+#[test]
+fn piped_output_skips_the_pager() {
+    let repo = fixture();
+    for args in [&["log"][..], &["diff", "HEAD~1"]] {
+        let output = with_marking_pager(&repo, args).output().unwrap();
+        assert!(output.status.success(), "{args:?}");
+        assert!(!output.stdout.is_empty(), "{args:?}");
+        assert!(!repo.join("pager-ran").exists(), "{args:?} ran the pager");
+    }
+    assert_eq!(
+        String::from_utf8(
+            with_marking_pager(&repo, &["log", "--format=oneline"])
+                .output()
+                .unwrap()
+                .stdout
+        )
+        .unwrap(),
+        repo.git(&["log", "--format=oneline"])
+    );
+}
+
+#[test]
+fn a_closed_pipe_is_not_an_error() {
+    let repo = fixture();
+    // a diff too big for the pipe's buffer, so that writing it runs into the closed pipe
+    let lines: String = (0..100_000).map(|n| format!("line {n}\n")).collect();
+    repo.write("file", lines);
+    let mut child = repo
+        .git_rs(&["diff"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // read one byte and hang up, like `git-rs diff | head -c 1`
+    child.stdout.take().unwrap().read_exact(&mut [0]).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
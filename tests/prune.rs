@@ -0,0 +1,82 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with two commits, an annotated tag, a staged blob that no commit has, and a
+/// blob nothing reaches; returns that blob's id too.
+fn fixture() -> (Repo, String) {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    repo.commit_all("first");
+    repo.write("a", "2\n");
+    repo.commit_all("second");
+    repo.git(&["tag", "-a", "v1", "-m", "v1"]);
+    repo.write("staged", "staged\n");
+    repo.git(&["add", "staged"]);
+    let dangling = repo.git_with_input(&["hash-object", "-w", "--stdin"], b"dangling\n");
+    (repo, dangling.trim().to_string())
+}
+
+#[test]
+fn prunes_what_nothing_reaches() {
+    let (repo, dangling) = fixture();
+    // only a reflog entry keeps this commit
+    let kept = repo.rev_parse("HEAD~1");
+    repo.git(&["reset", "-q", "--soft", "HEAD~1"]);
+    repo.git(&["commit", "-q", "-m", "again"]);
+
+    let listed = repo.run(&["prune", "-n"]);
+    assert_eq!(listed, repo.git(&["prune", "-n"]));
+    assert_eq!(listed, format!("{dangling} blob\n"));
+    repo.git(&["cat-file", "-e", &dangling]);
+
+    assert_eq!(repo.run(&["prune", "-v"]), listed);
+    assert!(repo
+        .command("git")
+        .args(["cat-file", "-e", &dangling])
+        .status()
+        .unwrap()
+        .code()
+        .is_some_and(|code| code != 0));
+    repo.git(&["cat-file", "-e", &kept]);
+    repo.git(&["fsck", "--strict", "--no-dangling"]);
+    assert_eq!(repo.run(&["prune", "-n"]), "");
+}
+
+#[test]
+fn removes_loose_copies_of_packed_objects() {
+    let (repo, dangling) = fixture();
+    repo.git(&["repack", "-q"]);
+    repo.run(&["prune"]);
+    let counts = repo.git(&["count-objects", "-v"]);
+    assert!(counts.starts_with("count: 0\n"), "{counts}");
+    repo.git(&["fsck", "--strict"]);
+    assert!(!repo.git(&["fsck", "--unreachable"]).contains(&dangling));
+}
+
+#[test]
+fn refuses_to_prune_precious_objects() {
+    let (repo, dangling) = fixture();
+    repo.git(&["config", "core.repositoryformatversion", "1"]);
+    repo.git(&["config", "extensions.preciousObjects", "true"]);
+    let err = repo.fails(&["prune"]);
+    assert!(
+        err.contains("cannot prune in a precious-objects repo"),
+        "{err}"
+    );
+    repo.git(&["cat-file", "-e", &dangling]);
+}
+
+#[test]
+fn a_global_precious_objects_setting_is_ignored() {
+    let (repo, dangling) = fixture();
+    repo.write(".gitconfig", "[extensions]\n\tpreciousObjects = true\n");
+    repo.run(&["prune"]);
+    assert!(repo
+        .command("git")
+        .args(["cat-file", "-e", &dangling])
+        .status()
+        .unwrap()
+        .code()
+        .is_some_and(|code| code != 0));
+}
@@ -0,0 +1,183 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+/// A bare repository to push to, and a clone of it with one commit on `master` that has been
+/// pushed.
+fn fixture() -> (Repo, Repo) {
+    let server = Repo::empty();
+    server.git(&["init", "-q", "--bare"]);
+    let client = Repo::init();
+    client.write("file", "1\n");
+    client.commit_all("first");
+    client.git(&["remote", "add", "origin", server.path.to_str().unwrap()]);
+    client.git(&["push", "-q", "origin", "master"]);
+    client.git(&["branch", "-q", "--set-upstream-to=origin/master"]);
+    (server, client)
+}
+
+/// Run `git-rs push` with `args` in `client`, returning whether it succeeded and what it
+/// reported.
+fn push(client: &Repo, args: &[&str]) -> (bool, String) {
+    let output = client.git_rs(&["push"]).args(args).output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    (output.status.success(), stderr)
+}
+
+#[test]
+fn pushes_the_current_branch() {
+    let (server, client) = fixture();
+    let old = client.rev_parse("HEAD");
+    client.write("file", "2\n");
+    let new = client.commit_all("second");
+    let url = server.path.display();
+    assert_eq!(
+        push(&client, &[]),
+        (
+            true,
+            format!(
+                "To {url}\n   {}..{}  master -> master\n",
+                &old[..7],
+                &new[..7]
+            )
+        )
+    );
+    assert_eq!(server.rev_parse("master"), new);
+    assert_eq!(client.rev_parse("origin/master"), new);
+    server.git(&["fsck", "--strict"]);
+    assert_eq!(
+        push(&client, &[]),
+        (true, "Everything up-to-date\n".to_string())
+    );
+}
+
+#[test]
+fn creates_and_deletes_remote_refs() {
+    let (server, client) = fixture();
+    client.git(&["tag", "-a", "v1", "-m", "v1"]);
+    let url = server.path.display();
+    assert_eq!(
+        push(&client, &["origin", "HEAD:side", "v1"]),
+        (
+            true,
+            format!("To {url}\n * [new branch]      HEAD -> side\n * [new tag]         v1 -> v1\n")
+        )
+    );
+    assert_eq!(server.rev_parse("side"), client.rev_parse("HEAD"));
+    assert_eq!(server.rev_parse("v1"), client.rev_parse("v1"));
+    assert_eq!(client.rev_parse("origin/side"), client.rev_parse("HEAD"));
+
+    assert_eq!(
+        push(&client, &["origin", ":side"]),
+        (true, format!("To {url}\n - [deleted]         side\n"))
+    );
+    assert!(server.git(&["for-each-ref", "refs/heads/side"]).is_empty());
+    assert!(client
+        .git(&["for-each-ref", "refs/remotes/origin/side"])
+        .is_empty());
+
+    let (ok, message) = push(&client, &["origin", ":side", "nope"]);
+    assert!(!ok);
+    assert_eq!(
+        message,
+        format!(
+            "error: unable to delete 'side': remote ref does not exist\n\
+             error: src refspec nope does not match any\n\
+             error: failed to push some refs to '{url}'\n"
+        )
+    );
+}
+
+#[test]
+fn sets_the_upstream_of_what_it_pushes() {
+    let (_server, client) = fixture();
+    client.git(&["checkout", "-q", "-b", "topic"]);
+    let (ok, stderr) = push(&client, &[]);
+    assert!(!ok);
+    assert!(stderr.contains("The current branch topic has no upstream branch."));
+
+    let output = client
+        .git_rs(&["push", "-u", "origin", "topic:other"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "branch 'topic' set up to track 'origin/other'.\n"
+    );
+    assert_eq!(client.git(&["config", "branch.topic.remote"]), "origin\n");
+    assert_eq!(
+        client.git(&["config", "branch.topic.merge"]),
+        "refs/heads/other\n"
+    );
+    assert!(client
+        .run(&["status"])
+        .contains("up to date with 'origin/other'"));
+
+    // tags and deletes set up nothing
+    client.git(&["checkout", "-q", "-b", "more"]);
+    push(&client, &["-u", "origin", "more:refs/tags/more", ":other"]);
+    client.fails(&["config", "branch.more.merge"]);
+}
+
+#[test]
+fn refuses_non_fast_forwards_unless_forced() {
+    let (server, client) = fixture();
+    let pushed = server.rev_parse("master");
+    client.git(&["commit", "-q", "--amend", "-m", "rewritten"]);
+    let (ok, message) = push(&client, &[]);
+    assert!(!ok);
+    assert!(message.contains(" ! [rejected]        master -> master (non-fast-forward)\n"));
+    assert!(message.contains("hint: Updates were rejected because a pushed branch tip is behind"));
+    assert_eq!(server.rev_parse("master"), pushed);
+
+    let new = client.rev_parse("HEAD");
+    let (ok, message) = push(&client, &["-f"]);
+    assert!(ok, "{message}");
+    let forced = format!(
+        " + {}...{} master -> master (forced update)\n",
+        &pushed[..7],
+        &new[..7]
+    );
+    assert!(message.ends_with(&forced), "{message}");
+    assert_eq!(server.rev_parse("master"), new);
+}
+
+#[test]
+fn refuses_to_overwrite_what_it_has_not_seen() {
+    let (server, client) = fixture();
+    let other = Repo::empty();
+    other.git(&["clone", "-q", server.path.to_str().unwrap(), "."]);
+    other.git(&["commit", "-q", "--allow-empty", "-m", "elsewhere"]);
+    other.git(&["push", "-q"]);
+    client.git(&["commit", "-q", "--allow-empty", "-m", "here"]);
+
+    let (ok, message) = push(&client, &[]);
+    assert!(!ok);
+    assert!(message.contains(" ! [rejected]        master -> master (fetch first)\n"));
+    assert_eq!(server.rev_parse("master"), other.rev_parse("HEAD"));
+}
+
+#[test]
+fn reports_what_the_remote_refused() {
+    let (server, client) = fixture();
+    let hook = server.join("hooks/update");
+    fs::create_dir_all(hook.parent().unwrap()).unwrap();
+    fs::write(&hook, "#!/bin/sh\necho \"no pushing to $1\" >&2\nexit 1\n").unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    client.write("file", "2\n");
+    client.commit_all("second");
+
+    // git-rs receive-pack serves the push too
+    let receive_pack = concat!(env!("CARGO_BIN_EXE_git-rs"), " receive-pack");
+    let (ok, message) = push(&client, &["--receive-pack", receive_pack]);
+    assert!(!ok);
+    assert!(
+        message.contains("remote: no pushing to refs/heads/master"),
+        "{message}"
+    );
+    assert!(message.contains(" ! [remote rejected] master -> master (hook declined)\n"));
+    assert_ne!(server.rev_parse("master"), client.rev_parse("HEAD"));
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Repo;
+
+/// An `old` series of three commits on `base`, and a `new` one rebased on `upstream` that keeps
+/// the first, changes the second and replaces the third.
+fn series() -> Repo {
+    let repo = Repo::init();
+    repo.write("base", "base\n");
+    repo.commit_all("base");
+    repo.git(&["branch", "base"]);
+    repo.write("a", "a\n");
+    repo.commit_all("add a");
+    repo.write("b", "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+    repo.commit_all("add b");
+    repo.write("c", "c\n");
+    repo.commit_all("add c");
+    repo.git(&["branch", "old"]);
+
+    repo.git(&["checkout", "--quiet", "-b", "new", "base"]);
+    repo.write("x", "x\n");
+    repo.commit_all("upstream x");
+    repo.git(&["branch", "upstream"]);
+    repo.write("a", "a\n");
+    repo.commit_all("add a");
+    repo.write("b", "1\n2\n3\n4\nfive\n6\n7\n8\n9\n10\n");
+    repo.commit_all("add b");
+    repo.write("d", "d\n");
+    repo.commit_all("add d");
+    repo
+}
+
+#[test]
+fn pairs_unchanged_modified_added_and_removed_commits() {
+    let repo = series();
+    let short = |rev: &str| repo.git(&["rev-parse", "--short", rev]).trim().to_string();
+    let out = repo.run(&["range-diff", "base..old", "upstream..new"]);
+    let headers: Vec<_> = out.lines().filter(|line| !line.starts_with(' ')).collect();
+    assert_eq!(
+        headers,
+        [
+            format!("1:  {} = 1:  {} add a", short("old~2"), short("new~2")),
+            format!("2:  {} ! 2:  {} add b", short("old~1"), short("new~1")),
+            format!("3:  {} < -:  ------- add c", short("old")),
+            format!("-:  ------- > 3:  {} add d", short("new")),
+        ]
+    );
+    // the modified pair shows the difference between the two patches
+    assert!(out.contains("\n    -+5\n    ++five\n"), "{out}");
+}
+
+#[test]
+fn matches_git() {
+    let repo = series();
+    for args in [
+        &["range-diff", "base..old", "upstream..new"][..],
+        &["range-diff", "upstream..new", "base..old"],
+        &["range-diff", "base..old", "base..old"],
+    ] {
+        let mut git_args = args.to_vec();
+        git_args.insert(1, "--no-color");
+        assert_eq!(repo.run(args), repo.git(&git_args), "{args:?}");
+    }
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+/// A committed tree with a plain file, an executable, a symlink and a binary file in a
+/// subdirectory.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("plain.txt", "text\n");
+    repo.write("run.sh", "#!/bin/sh\necho hi\n");
+    fs::set_permissions(repo.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    std::os::unix::fs::symlink("plain.txt", repo.join("link")).unwrap();
+    repo.write("sub/dir/data.bin", [0u8, 1, 2, 255, b'\n', 0]);
+    repo.commit_all("tree");
+    repo
+}
+
+#[test]
+fn read_tree_and_checkout_index_reproduce_the_tree() {
+    let repo = fixture();
+    for path in ["plain.txt", "run.sh", "link", "sub"] {
+        let path = repo.join(path);
+        match fs::symlink_metadata(&path).unwrap().is_dir() {
+            true => fs::remove_dir_all(path).unwrap(),
+            false => fs::remove_file(path).unwrap(),
+        }
+    }
+    fs::remove_file(repo.join(".git/index")).unwrap();
+
+    repo.run(&["read-tree", "HEAD"]);
+    repo.run(&["checkout-index", "--all"]);
+
+    assert_eq!(repo.read("plain.txt"), "text\n");
+    assert_eq!(
+        fs::read(repo.join("sub/dir/data.bin")).unwrap(),
+        [0, 1, 2, 255, b'\n', 0]
+    );
+    let mode = |path: &str| fs::metadata(repo.join(path)).unwrap().permissions().mode();
+    assert_eq!(mode("run.sh") & 0o111, 0o111);
+    assert_eq!(mode("plain.txt") & 0o111, 0);
+    assert_eq!(
+        fs::read_link(repo.join("link")).unwrap().to_str(),
+        Some("plain.txt")
+    );
+    // git sees the same files it committed, and the index holds the same tree
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    assert_eq!(
+        repo.git(&["write-tree"]).trim(),
+        repo.rev_parse("HEAD^{tree}")
+    );
+}
+
+#[test]
+fn checkout_index_writes_below_a_prefix() {
+    let repo = fixture();
+    repo.run(&["checkout-index", "--all", "--prefix", "export/"]);
+    assert_eq!(repo.read("export/plain.txt"), "text\n");
+    assert_eq!(
+        fs::read(repo.join("export/sub/dir/data.bin")).unwrap(),
+        fs::read(repo.join("sub/dir/data.bin")).unwrap()
+    );
+    assert_eq!(
+        fs::read_link(repo.join("export/link")).unwrap().to_str(),
+        Some("plain.txt")
+    );
+}
+
+#[test]
+fn reads_a_tree_into_an_empty_index() {
+    let repo = Repo::init();
+    let blob = |content: &str| {
+        let args = ["hash-object", "-w", "--stdin"];
+        repo.git_with_input(&args, content.as_bytes())
+            .trim()
+            .to_string()
+    };
+    let (one, two) = (blob("one\n"), blob("two\n"));
+    let listing = format!("100644 blob {one}\tone\n100755 blob {two}\ttwo\n");
+    let tree = repo.git_with_input(&["mktree"], listing.as_bytes());
+    assert!(!repo.join(".git/index").exists());
+
+    repo.run(&["read-tree", tree.trim()]);
+    assert_eq!(
+        repo.git(&["ls-files", "--stage"]),
+        format!("100644 {one} 0\tone\n100755 {two} 0\ttwo\n")
+    );
+    // the work tree is left alone
+    assert!(!repo.join("one").exists());
+}
@@ -0,0 +1,329 @@
+mod common;
+
+use common::Repo;
+
+/// A repository where `topic` has two commits on top of `base`, and `master` one other.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("base", "base\n");
+    repo.commit_all("base");
+    repo.git(&["checkout", "-q", "-b", "topic"]);
+    repo.write("topic", "1\n");
+    repo.commit_all("topic 1");
+    repo.write("topic", "2\n");
+    repo.commit_all("topic 2");
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("upstream", "upstream\n");
+    repo.commit_all("upstream");
+    repo.git(&["checkout", "-q", "topic"]);
+    repo
+}
+
+fn log(repo: &Repo) -> String {
+    repo.git(&["log", "--format=%s %P", "--graph"])
+}
+
+#[test]
+fn replays_the_branch_onto_the_upstream() {
+    let repo = fixture();
+    let upstream = repo.rev_parse("master");
+    repo.run(&["rebase", "master"]);
+
+    assert_eq!(
+        repo.git(&["symbolic-ref", "HEAD"]).trim(),
+        "refs/heads/topic",
+        "still on the branch"
+    );
+    assert_eq!(repo.rev_parse("HEAD~2"), upstream);
+    assert_eq!(
+        repo.git(&["log", "--format=%s"]),
+        "topic 2\ntopic 1\nupstream\nbase\n"
+    );
+    assert_eq!(repo.read("topic"), "2\n");
+    assert_eq!(repo.read("upstream"), "upstream\n");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+    // git replays it into the same commits
+    let ours = repo.rev_parse("HEAD");
+    let log_ours = log(&repo);
+    let theirs = fixture();
+    theirs.git(&["rebase", "-q", "master"]);
+    assert_eq!(theirs.rev_parse("HEAD"), ours);
+    assert_eq!(log(&theirs), log_ours);
+}
+
+#[test]
+fn stops_on_a_conflict_and_aborts() {
+    let repo = fixture();
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("topic", "conflicting\n");
+    repo.commit_all("conflict");
+    repo.git(&["checkout", "-q", "topic"]);
+    let before = repo.rev_parse("HEAD");
+
+    repo.fails(&["rebase", "master"]);
+    assert!(repo.join(".git/rebase-apply").is_dir());
+    assert!(repo.read("topic").contains("<<<<<<<"));
+
+    repo.run(&["rebase", "--abort"]);
+    assert!(!repo.join(".git/rebase-apply").exists());
+    assert_eq!(repo.rev_parse("HEAD"), before);
+    assert_eq!(repo.read("topic"), "2\n");
+    assert_eq!(
+        repo.git(&["symbolic-ref", "HEAD"]).trim(),
+        "refs/heads/topic"
+    );
+}
+
+#[test]
+fn continues_after_a_resolved_conflict() {
+    let repo = fixture();
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("topic", "conflicting\n");
+    repo.commit_all("conflict");
+    repo.git(&["checkout", "-q", "topic"]);
+
+    repo.fails(&["rebase", "master"]);
+    repo.write("topic", "1\n");
+    repo.git(&["add", "topic"]);
+    repo.run(&["rebase", "--continue"]);
+    assert!(!repo.join(".git/rebase-apply").exists());
+    assert_eq!(
+        repo.git(&["log", "--format=%s"]),
+        "topic 2\ntopic 1\nconflict\nupstream\nbase\n"
+    );
+    assert_eq!(repo.read("topic"), "2\n");
+}
+
+/// Rebase onto `master` with the todo list `todo`, whose `{1}` and `{2}` stand for the commits
+/// `topic 1` and `topic 2`.
+fn rebase_with_todo(repo: &Repo, todo: &str) {
+    let todo = todo
+        .replace("{1}", &repo.rev_parse("HEAD~1"))
+        .replace("{2}", &repo.rev_parse("HEAD"));
+    repo.write(".git/todo", todo);
+    repo.run(&["rebase", "--todo-file", ".git/todo", "master"]);
+}
+
+#[test]
+fn todo_drop_removes_a_commit() {
+    let repo = fixture();
+    rebase_with_todo(&repo, "pick {1} topic 1\ndrop {2} topic 2\n");
+    assert_eq!(
+        repo.git(&["log", "--format=%s"]),
+        "topic 1\nupstream\nbase\n"
+    );
+    assert_eq!(repo.read("topic"), "1\n");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+}
+
+#[test]
+fn todo_squash_combines_two_commits() {
+    let repo = fixture();
+    rebase_with_todo(&repo, "pick {1} topic 1\nsquash {2} topic 2\n");
+    assert_eq!(
+        repo.git(&["log", "--format=%s"]),
+        "topic 1\nupstream\nbase\n"
+    );
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "topic 1\n\ntopic 2\n\n"
+    );
+    assert_eq!(repo.read("topic"), "2\n");
+    assert_eq!(repo.git(&["diff", "--stat", "HEAD~1"]).lines().count(), 2);
+}
+
+#[test]
+fn todo_fixup_keeps_the_first_message() {
+    let repo = fixture();
+    rebase_with_todo(&repo, "pick {1} topic 1\nfixup {2} topic 2\n");
+    assert_eq!(repo.git(&["log", "-1", "--format=%B"]), "topic 1\n\n");
+    assert_eq!(repo.read("topic"), "2\n");
+}
+
+/// Run `rebase -i` with `args` and `script` as the sequence editor, which gets the todo list
+/// file as `$1`.
+fn rebase_interactive(repo: &Repo, args: &[&str], script: &str) -> std::process::Output {
+    repo.write(".git/sequence-editor", script);
+    let mut command = repo.git_rs(&[&["rebase", "-i"][..], args].concat());
+    command.env("GIT_SEQUENCE_EDITOR", "sh .git/sequence-editor");
+    command.output().unwrap()
+}
+
+#[test]
+fn interactive_squashes_three_commits_and_rewords_another() {
+    let repo = Repo::init();
+    repo.write("base", "base\n");
+    repo.commit_all("base");
+    for n in 1..=4 {
+        repo.write(&format!("f{n}"), format!("{n}\n"));
+        repo.commit_all(&format!("c{n}"));
+    }
+    let script = "cp \"$1\" .git/todo-seen\n\
+                  sed -i -e '2s/^pick/squash/' -e '3s/^pick/fixup/' -e '4s/^pick/reword/' \"$1\"\n";
+
+    let output = rebase_interactive(&repo, &["HEAD~4"], script);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Stopped at "), "{stderr}");
+    let todo = repo.read(".git/todo-seen");
+    let picks: Vec<_> = todo.lines().take_while(|line| !line.is_empty()).collect();
+    assert_eq!(picks.len(), 4, "{todo}");
+    assert!(
+        picks[0].starts_with("pick ") && picks[0].ends_with(" c1"),
+        "{todo}"
+    );
+    assert!(todo.contains("\n# Commands:\n"), "{todo}");
+
+    // reword stops with the message to edit
+    assert!(repo.join(".git/rebase-merge/git-rebase-todo").exists());
+    assert_eq!(repo.read(".git/rebase-merge/message"), "c4\n");
+    repo.write(".git/rebase-merge/message", "c4 reworded\n");
+    repo.run(&["rebase", "--continue"]);
+
+    assert_eq!(
+        repo.git(&["log", "--format=%s|%b"]),
+        "c4 reworded|\nc1|c2\n\nbase|\n"
+    );
+    assert_eq!(
+        repo.git(&["ls-tree", "--name-only", "HEAD~1"]),
+        "base\nf1\nf2\nf3\n"
+    );
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/master\n");
+    assert!(!repo.join(".git/rebase-merge").exists());
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+}
+
+#[test]
+fn interactive_refuses_an_unknown_instruction_before_starting() {
+    let repo = fixture();
+    let head = repo.rev_parse("HEAD");
+    let output = rebase_interactive(&repo, &["master"], "echo 'bogus abc' > \"$1\"\n");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid line 1: bogus abc"), "{stderr}");
+    assert_eq!(repo.rev_parse("HEAD"), head);
+    assert!(!repo.join(".git/rebase-merge").exists());
+}
+
+#[test]
+fn interactive_conflicts_continue_and_abort() {
+    let repo = fixture();
+    let head = repo.rev_parse("HEAD");
+    // swapping the commits makes `topic 2` change a file that isn't there yet
+    let swap = "sed -i -n -e '1h' -e '2{p;x;p}' -e '3,$p' \"$1\"\n";
+    let output = rebase_interactive(&repo, &["master"], swap);
+    assert!(!output.status.success());
+    assert!(repo.join(".git/rebase-merge").exists());
+    repo.run(&["rebase", "--abort"]);
+    assert_eq!(repo.rev_parse("HEAD"), head);
+    assert!(!repo.join(".git/rebase-merge").exists());
+
+    let output = rebase_interactive(&repo, &["master"], swap);
+    assert!(!output.status.success());
+    repo.write("topic", "2\n");
+    repo.git(&["add", "topic"]);
+    // `topic 1` then conflicts with the `topic` that `topic 2` left
+    let stderr = repo.fails(&["rebase", "--continue"]);
+    assert!(stderr.contains("could not apply"), "{stderr}");
+    repo.write("topic", "1 and 2\n");
+    repo.git(&["add", "topic"]);
+    repo.run(&["rebase", "--continue"]);
+    assert_eq!(
+        repo.git(&["log", "--format=%s"]),
+        "topic 1\ntopic 2\nupstream\nbase\n"
+    );
+    assert_eq!(repo.read("topic"), "1 and 2\n");
+    assert!(!repo.join(".git/rebase-merge").exists());
+}
+
+/// Commit with `squash`, an option like `--squash=<commit>`, keeping the message it offers
+/// for editing.
+fn squash(repo: &Repo, squash: &str) {
+    let output = repo
+        .git_rs(&["commit", squash])
+        .env("GIT_EDITOR", "true")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn commit_fixup_and_squash_name_their_target() {
+    let repo = fixture();
+    repo.write("topic", "fixed\n");
+    repo.git(&["add", "topic"]);
+    repo.run(&["commit", "--fixup", "HEAD~1"]);
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "fixup! topic 1\n\n"
+    );
+
+    repo.write("topic", "squashed\n");
+    repo.git(&["add", "topic"]);
+    squash(&repo, "--squash=HEAD");
+    assert_eq!(
+        repo.git(&["log", "-1", "--format=%B"]),
+        "squash! fixup! topic 1\n\n"
+    );
+}
+
+#[test]
+fn autosquash_moves_fixups_after_their_targets() {
+    let repo = fixture();
+    let topic_1 = repo.rev_parse("HEAD~1");
+    repo.write("one-more", "x\n");
+    repo.git(&["add", "one-more"]);
+    repo.run(&["commit", "--fixup", "HEAD~1"]);
+    repo.write("two-more", "y\n");
+    repo.git(&["add", "two-more"]);
+    repo.run(&["commit", "-m", &format!("fixup! {topic_1}")]);
+    repo.write("topic", "3\n");
+    repo.git(&["add", "topic"]);
+    squash(&repo, "--squash=HEAD~2");
+    repo.write("stray", "z\n");
+    repo.commit_all("fixup! no such commit");
+
+    let output = rebase_interactive(
+        &repo,
+        &["--autosquash", "master"],
+        "grep -v '^#' \"$1\" | grep . > .git/todo-seen\n",
+    );
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("warning: no commit found for 'fixup! no such commit'"),
+        "{stderr}"
+    );
+    let actions: Vec<_> = repo
+        .read(".git/todo-seen")
+        .lines()
+        .map(|line| {
+            let mut words = line.split(' ');
+            let action = words.next().unwrap().to_string();
+            action + " " + &words.skip(1).collect::<Vec<_>>().join(" ")
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        [
+            "pick topic 1".to_string(),
+            "fixup fixup! topic 1".to_string(),
+            format!("fixup fixup! {topic_1}"),
+            "pick topic 2".to_string(),
+            "squash squash! topic 2".to_string(),
+            "pick fixup! no such commit".to_string(),
+        ]
+    );
+
+    assert_eq!(
+        repo.git(&["log", "--format=%s", "master.."]),
+        "fixup! no such commit\ntopic 2\ntopic 1\n"
+    );
+    assert_eq!(
+        repo.git(&["ls-tree", "--name-only", "HEAD~2"]),
+        "base\none-more\ntopic\ntwo-more\nupstream\n"
+    );
+    assert_eq!(repo.git(&["show", "HEAD~1:topic"]), "3\n");
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+}
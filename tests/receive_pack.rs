@@ -0,0 +1,158 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+/// The `--receive-pack` command that makes git push to `git-rs receive-pack`.
+const RECEIVE_PACK: &str = concat!(env!("CARGO_BIN_EXE_git-rs"), " receive-pack");
+
+/// A bare repository to push to, and a client with a commit pushed to its `master` and another
+/// on top of it that isn't pushed yet.
+fn fixture() -> (Repo, Repo) {
+    let server = Repo::empty();
+    server.git(&["init", "-q", "--bare"]);
+    let client = Repo::init();
+    client.write("file", "1\n");
+    client.commit_all("first");
+    client.git(&[
+        "remote",
+        "add",
+        "origin",
+        &format!("file://{}", server.path.display()),
+    ]);
+    push(&client, &["master"]);
+    client.write("file", "2\n");
+    client.commit_all("second");
+    (server, client)
+}
+
+/// Run `git push` from `client` to its origin with `args`, returning whether it succeeded.
+fn push(client: &Repo, args: &[&str]) -> bool {
+    client
+        .command("git")
+        .args(["push", "-q", "--receive-pack", RECEIVE_PACK, "origin"])
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .success()
+}
+
+/// Install the shell script `body` as the hook `name` of `server`.
+fn hook(server: &Repo, name: &str, body: &str) {
+    let path = server.join(&format!("hooks/{name}"));
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+fn has_object(repo: &Repo, hash: &str) -> bool {
+    repo.command("git")
+        .args(["cat-file", "-e", hash])
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn accepts_pushed_commits() {
+    let (server, client) = fixture();
+    assert!(push(&client, &["master", "master:refs/heads/copy"]));
+    let head = client.rev_parse("HEAD");
+    assert_eq!(server.rev_parse("master"), head);
+    assert_eq!(server.rev_parse("copy"), head);
+    server.git(&["fsck", "--strict"]);
+
+    assert!(push(&client, &[":copy"]));
+    assert!(server.git(&["for-each-ref", "refs/heads/copy"]).is_empty());
+}
+
+#[test]
+fn deny_non_fast_forwards_refuses_forced_pushes() {
+    let (server, client) = fixture();
+    assert!(push(&client, &["master"]));
+    let second = server.rev_parse("master");
+    server.git(&["config", "receive.denyNonFastForwards", "true"]);
+    client.git(&["reset", "-q", "--hard", "HEAD~1"]);
+    client.write("file", "other\n");
+    client.commit_all("diverged");
+    assert!(!push(&client, &["--force", "master"]));
+    assert_eq!(server.rev_parse("master"), second);
+
+    server.git(&["config", "receive.denyNonFastForwards", "false"]);
+    assert!(push(&client, &["--force", "master"]));
+    assert_eq!(server.rev_parse("master"), client.rev_parse("HEAD"));
+}
+
+#[test]
+fn pre_receive_can_refuse_the_push() {
+    let (server, client) = fixture();
+    let first = server.rev_parse("master");
+    hook(&server, "pre-receive", "cat > pre-receive.in\nexit 1\n");
+    assert!(!push(&client, &["master"]));
+    assert_eq!(server.rev_parse("master"), first);
+    // the objects of a refused push stay in quarantine and are thrown away
+    let second = client.rev_parse("HEAD");
+    assert!(!has_object(&server, &second));
+    assert_eq!(
+        server.read("pre-receive.in"),
+        format!("{first} {second} refs/heads/master\n")
+    );
+}
+
+#[test]
+fn runs_the_update_and_post_receive_hooks() {
+    let (server, client) = fixture();
+    let first = server.rev_parse("master");
+    hook(&server, "update", "echo \"$@\" >> update.args\n");
+    hook(&server, "post-receive", "cat > post-receive.in\n");
+    assert!(push(&client, &["master"]));
+
+    let second = client.rev_parse("HEAD");
+    assert_eq!(
+        server.read("update.args"),
+        format!("refs/heads/master {first} {second}\n")
+    );
+    assert_eq!(
+        server.read("post-receive.in"),
+        format!("{first} {second} refs/heads/master\n")
+    );
+}
+
+#[test]
+fn the_update_hook_refuses_single_refs() {
+    let (server, client) = fixture();
+    hook(&server, "update", "test \"$1\" != refs/heads/master\n");
+    assert!(!push(&client, &["master", "master:refs/heads/copy"]));
+    assert_eq!(server.rev_parse("copy"), client.rev_parse("HEAD"));
+    assert_ne!(server.rev_parse("master"), client.rev_parse("HEAD"));
+}
+
+#[test]
+fn an_atomic_push_that_cannot_lock_a_ref_moves_none() {
+    let (server, client) = fixture();
+    let first = server.rev_parse("master");
+    server.write("refs/heads/copy.lock", "");
+    assert!(!push(
+        &client,
+        &["--atomic", "master", "master:refs/heads/copy"]
+    ));
+    assert_eq!(server.rev_parse("master"), first);
+    assert!(!server.join("refs/heads/master.lock").exists());
+
+    // without --atomic, the ref that could be locked is still updated
+    assert!(!push(&client, &["master", "master:refs/heads/copy"]));
+    assert_eq!(server.rev_parse("master"), client.rev_parse("HEAD"));
+}
+
+#[test]
+fn refuses_malformed_ref_names() {
+    let (server, _client) = fixture();
+    let first = server.rev_parse("master");
+    let pkt = |line: String| format!("{:04x}{line}", line.len() + 4);
+    let null = "0".repeat(40);
+    let request = pkt(format!("{first} {null} refs/heads/a:b\0report-status\n")) + "0000";
+    let out = server.run_with_input(&["receive-pack", "."], request.as_bytes());
+    assert!(out.contains("ng refs/heads/a:b funny refname"), "{out}");
+}
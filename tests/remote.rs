@@ -0,0 +1,56 @@
+mod common;
+
+use common::Repo;
+
+/// A clone of a repository whose `gone` branch was deleted after cloning, with a ref under
+/// another remote's namespace that happens to have the same name.
+fn fixture() -> (Repo, Repo) {
+    let server = Repo::init();
+    server.write("a", "a\n");
+    server.commit_all("commit");
+    server.git(&["branch", "gone"]);
+    server.git(&["branch", "kept"]);
+    let clone = Repo::empty();
+    clone.git(&["clone", "-q", &server.path.display().to_string(), "."]);
+    clone.git(&["update-ref", "refs/remotes/other/gone", "HEAD"]);
+    server.git(&["branch", "-q", "-D", "gone"]);
+    (server, clone)
+}
+
+fn refs(repo: &Repo) -> String {
+    repo.git(&["for-each-ref", "--format=%(refname)"])
+}
+
+#[test]
+fn prune_deletes_refs_gone_from_the_remote() {
+    let (server, clone) = fixture();
+    let pruned = clone.run(&["remote", "prune", "origin"]);
+    assert_eq!(
+        pruned,
+        format!(
+            "Pruning origin\nURL: {}\n * [pruned] origin/gone\n",
+            server.path.display()
+        )
+    );
+    assert_eq!(
+        refs(&clone),
+        "refs/heads/master\nrefs/remotes/origin/HEAD\nrefs/remotes/origin/kept\n\
+         refs/remotes/origin/master\nrefs/remotes/other/gone\n"
+    );
+}
+
+#[test]
+fn prune_dry_run_matches_git_and_deletes_nothing() {
+    let (_server, clone) = fixture();
+    let before = refs(&clone);
+    let pruned = clone.run(&["remote", "prune", "--dry-run", "origin"]);
+    assert!(
+        pruned.ends_with(" * [would prune] origin/gone\n"),
+        "{pruned}"
+    );
+    assert_eq!(
+        pruned,
+        clone.git(&["remote", "prune", "--dry-run", "origin"])
+    );
+    assert_eq!(refs(&clone), before);
+}
@@ -0,0 +1,91 @@
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::Repo;
+
+/// A repository with two commits on `master` and a commit only `refs/heads/gone` reaches.
+/// Returns that commit too.
+fn fixture() -> (Repo, String) {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    repo.commit_all("first");
+    repo.write("a", "2\n");
+    repo.commit_all("second");
+    let tree = repo.rev_parse("HEAD^{tree}");
+    let gone = repo.git(&["commit-tree", "-m", "gone", &tree]);
+    let gone = gone.trim().to_string();
+    repo.git(&["update-ref", "refs/heads/gone", &gone]);
+    (repo, gone)
+}
+
+/// The number `count-objects -v` gives for `key`.
+fn count(repo: &Repo, key: &str) -> u32 {
+    repo.git(&["count-objects", "-v"])
+        .lines()
+        .find_map(|line| line.strip_prefix(&format!("{key}: ")))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+/// The index files of the packs of `repo`.
+fn pack_indexes(repo: &Repo) -> Vec<PathBuf> {
+    fs::read_dir(repo.join(".git/objects/pack"))
+        .into_iter()
+        .flatten()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .collect()
+}
+
+#[test]
+fn packs_loose_objects_and_then_everything() {
+    let (repo, gone) = fixture();
+    let loose = count(&repo, "count");
+    repo.run(&["repack"]);
+    assert_eq!(count(&repo, "in-pack"), loose);
+    assert_eq!(count(&repo, "count"), loose);
+    let [idx] = pack_indexes(&repo).try_into().unwrap();
+    repo.git(&["verify-pack", idx.to_str().unwrap()]);
+    assert_eq!(repo.run(&["repack"]), "Nothing new to pack.\n");
+
+    // -d drops the loose copies, and with -a the packed objects nothing reaches any more
+    repo.run(&["repack", "-d", "-q"]);
+    assert_eq!(count(&repo, "count"), 0);
+    repo.git(&["update-ref", "-d", "refs/heads/gone"]);
+    repo.write("b", "b\n");
+    repo.commit_all("third");
+    repo.run(&["repack", "-a", "-d"]);
+    assert_eq!(count(&repo, "count"), 0);
+    assert_eq!(count(&repo, "in-pack"), loose - 1 + 3);
+    let [idx] = pack_indexes(&repo).try_into().unwrap();
+    repo.git(&["verify-pack", idx.to_str().unwrap()]);
+    repo.git(&["fsck", "--strict"]);
+    assert!(
+        repo.git_rs(&["cat-file", "-t", &gone])
+            .output()
+            .unwrap()
+            .status
+            .code()
+            != Some(0)
+    );
+    assert_eq!(repo.run(&["log", "--format=%s"]), "third\nsecond\nfirst\n");
+}
+
+#[test]
+fn keeps_precious_objects() {
+    let (repo, _) = fixture();
+    repo.git(&["config", "core.repositoryformatversion", "1"]);
+    repo.git(&["config", "extensions.preciousObjects", "true"]);
+    let err = repo.fails(&["repack", "-a", "-d"]);
+    assert!(
+        err.contains("cannot delete packs in a precious-objects repo"),
+        "{err}"
+    );
+    assert!(pack_indexes(&repo).is_empty());
+    // packing without deleting is fine
+    repo.run(&["repack", "-a"]);
+    assert_eq!(pack_indexes(&repo).len(), 1);
+    assert_ne!(count(&repo, "count"), 0);
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// A repository with rerere enabled where merging `side` into `master` conflicts in `f`.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.git(&["config", "rerere.enabled", "true"]);
+    repo.write("f", "a\nb\nc\n");
+    repo.commit_all("base");
+    repo.git(&["checkout", "-q", "-b", "side"]);
+    repo.write("f", "a\nSIDE\nc\n");
+    repo.commit_all("side");
+    repo.git(&["checkout", "-q", "master"]);
+    repo.write("f", "a\nMASTER\nc\n");
+    repo.commit_all("master");
+    repo
+}
+
+/// The conflict ids recorded under `.git/rr-cache`.
+fn recorded(repo: &Repo) -> Vec<String> {
+    let mut ids = fs::read_dir(repo.join(".git/rr-cache"))
+        .map(|dir| {
+            dir.map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new());
+    ids.sort();
+    ids
+}
+
+/// Merge `side`, resolve the conflict and commit, recording the resolution.
+fn resolve_once(repo: &Repo) {
+    repo.fails(&["merge", "side"]);
+    assert_eq!(repo.run(&["rerere", "status"]), "f\n");
+    repo.write("f", "a\nRESOLVED\nc\n");
+    repo.git(&["add", "f"]);
+    repo.run(&["commit", "-m", "merged"]);
+}
+
+#[test]
+fn reuses_a_recorded_resolution() {
+    let repo = fixture();
+    resolve_once(&repo);
+    let ids = recorded(&repo);
+    assert_eq!(ids.len(), 1);
+    let cache = repo.join(&format!(".git/rr-cache/{}", ids[0]));
+    assert!(cache.join("preimage").exists());
+    assert_eq!(
+        fs::read_to_string(cache.join("postimage")).unwrap(),
+        "a\nRESOLVED\nc\n"
+    );
+
+    // the same conflict again
+    repo.git(&["reset", "-q", "--hard", "HEAD~1"]);
+    let err = repo.fails(&["merge", "side"]);
+    assert!(err.contains("Automatic merge failed"), "{err}");
+    assert_eq!(repo.read("f"), "a\nRESOLVED\nc\n");
+}
+
+#[test]
+fn conflict_ids_match_git() {
+    let repo = fixture();
+    repo.fails(&["merge", "side"]);
+    let ours = recorded(&repo);
+    repo.git(&["merge", "--abort"]);
+    fs::remove_dir_all(repo.join(".git/rr-cache")).unwrap();
+    // git conflicts too
+    let merged = repo
+        .command("git")
+        .args(["merge", "side"])
+        .output()
+        .unwrap();
+    assert!(!merged.status.success());
+    assert_eq!(recorded(&repo), ours);
+}
+
+#[test]
+fn forget_drops_the_resolution() {
+    let repo = fixture();
+    resolve_once(&repo);
+    repo.git(&["reset", "-q", "--hard", "HEAD~1"]);
+    repo.fails(&["merge", "side"]);
+    repo.run(&["rerere", "forget", "f"]);
+    let cache = repo.join(&format!(".git/rr-cache/{}", recorded(&repo)[0]));
+    assert!(!cache.join("postimage").exists());
+
+    repo.git(&["reset", "-q", "--hard", "HEAD"]);
+    repo.fails(&["merge", "side"]);
+    assert!(repo.read("f").contains("<<<<<<<"));
+}
+
+#[test]
+fn does_nothing_unless_enabled() {
+    let repo = fixture();
+    repo.git(&["config", "rerere.enabled", "false"]);
+    repo.fails(&["merge", "side"]);
+    assert!(recorded(&repo).is_empty());
+}
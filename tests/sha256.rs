@@ -0,0 +1,101 @@
+mod common;
+
+use common::Repo;
+
+/// A SHA-256 repository made by git-rs, with a file staged.
+fn fixture() -> Repo {
+    let repo = Repo::empty();
+    repo.run(&["init", "--quiet", "--object-format=sha256"]);
+    repo.write("dir/file", "contents\n");
+    repo.write("top", "top\n");
+    repo.git(&["add", "--all"]);
+    repo
+}
+
+#[test]
+fn init_makes_a_repository_git_reads_as_sha256() {
+    let repo = fixture();
+    assert_eq!(repo.git(&["rev-parse", "--show-object-format"]), "sha256\n");
+    assert_eq!(repo.git(&["config", "core.repositoryformatversion"]), "1\n");
+}
+
+#[test]
+fn local_commands_work_with_sha256_ids() {
+    let repo = fixture();
+    let blob = repo.run(&["hash-object", "top"]);
+    assert_eq!(blob.trim().len(), 64);
+    assert_eq!(blob, repo.git(&["hash-object", "top"]));
+
+    let tree = repo.run(&["write-tree"]);
+    assert_eq!(tree, repo.git(&["write-tree"]));
+
+    repo.run(&["commit", "-m", "sha256 commit"]);
+    let commit = repo.rev_parse("HEAD");
+    assert_eq!(commit.len(), 64);
+    assert_eq!(repo.rev_parse("HEAD^{tree}"), tree.trim());
+    assert_eq!(
+        repo.run(&["log", "--format=oneline"]),
+        format!("{commit} sha256 commit\n")
+    );
+    assert_eq!(
+        repo.run(&["cat-file", "commit", &commit]),
+        repo.git(&["cat-file", "commit", &commit])
+    );
+    repo.git(&["fsck", "--strict"]);
+}
+
+#[test]
+fn reads_a_sha256_repository_git_made() {
+    let repo = Repo::empty();
+    repo.git(&["init", "-q", "--object-format=sha256"]);
+    repo.write("a", "a\n");
+    repo.git(&["add", "a"]);
+    repo.git(&["commit", "-q", "-m", "by git"]);
+    let commit = repo.rev_parse("HEAD");
+    assert_eq!(
+        repo.run(&["log", "--format=oneline"]),
+        format!("{commit} by git\n")
+    );
+    assert_eq!(repo.run(&["cat-file", "blob", "HEAD:a"]), "a\n");
+    assert_eq!(repo.run(&["status", "--porcelain"]), "");
+}
+
+#[test]
+fn reads_and_writes_sha256_packs() {
+    let repo = Repo::empty();
+    repo.git(&["init", "-q", "--object-format=sha256"]);
+    for n in 0..3 {
+        repo.write(
+            "a",
+            format!("{}version {n}\n", "a shared line\n".repeat(20)),
+        );
+        repo.git(&["add", "a"]);
+        repo.git(&["commit", "-q", "-m", &format!("commit {n}")]);
+    }
+    repo.git(&["gc", "-q"]);
+    assert!(!repo.join(".git/objects").read_dir().unwrap().any(|entry| {
+        let name = entry.unwrap().file_name();
+        name.len() == 2 && name != "pack"
+    }));
+
+    let head = repo.rev_parse("HEAD");
+    assert_eq!(repo.run(&["rev-parse", "HEAD"]), format!("{head}\n"));
+    assert_eq!(
+        repo.run(&["log", "--format=%s"]),
+        "commit 2\ncommit 1\ncommit 0\n"
+    );
+    assert_eq!(
+        repo.run(&["cat-file", "blob", "HEAD~2:a"]),
+        repo.git(&["cat-file", "blob", "HEAD~2:a"])
+    );
+    assert_eq!(repo.run(&["status", "--porcelain"]), "");
+
+    // a pack git-rs writes is one git reads
+    repo.run(&["repack", "-a", "-d", "-q"]);
+    repo.git(&["fsck", "--strict"]);
+    assert_eq!(
+        repo.git(&["count-objects", "-v"]).lines().nth(2),
+        Some("in-pack: 9")
+    );
+    assert_eq!(repo.run(&["rev-parse", "HEAD"]), format!("{head}\n"));
+}
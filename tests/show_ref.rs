@@ -0,0 +1,33 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn dereference_shows_tag_objects_and_their_commits() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let commit = repo.commit_all("commit");
+    repo.git(&["tag", "-a", "v1", "-m", "annotated"]);
+    repo.git(&["tag", "light"]);
+    let tag = repo.rev_parse("v1");
+    assert_ne!(tag, commit);
+
+    let shown = repo.run(&["show-ref", "--dereference"]);
+    assert_eq!(
+        shown,
+        format!(
+            "{commit} refs/heads/master\n{commit} refs/tags/light\n\
+             {tag} refs/tags/v1\n{commit} refs/tags/v1^{{}}\n"
+        )
+    );
+    assert_eq!(shown, repo.git(&["show-ref", "--dereference"]));
+    assert_eq!(
+        repo.run(&["show-ref", "-d", "--tags"]),
+        repo.git(&["show-ref", "-d", "--tags"])
+    );
+    // without it, only the tag object
+    assert_eq!(
+        repo.run(&["show-ref", "--tags"]),
+        format!("{commit} refs/tags/light\n{tag} refs/tags/v1\n")
+    );
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt};
+
+use common::Repo;
+
+const SIGNATURE: &str =
+    "-----BEGIN PGP SIGNATURE-----\n\nZmFrZSBzaWduYXR1cmU=\n-----END PGP SIGNATURE-----\n";
+
+/// A repository with a staged file, whose `gpg.program` is a script that saves what it's asked
+/// to sign to `signed-payload` and prints a fixed signature, as gpg would.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    let script = format!(
+        "#!/bin/sh\ncat > signed-payload\necho '[GNUPG:] SIG_CREATED D 1 8 00 1700000000 X' >&2\n\
+         cat <<EOF\n{SIGNATURE}EOF\n"
+    );
+    fs::write(repo.join("fake-gpg"), script).unwrap();
+    fs::set_permissions(repo.join("fake-gpg"), fs::Permissions::from_mode(0o755)).unwrap();
+    repo.write(".gitignore", "fake-gpg\nsigned-payload\n");
+    repo.write("file", "contents\n");
+    repo.git(&["add", "file", ".gitignore"]);
+    repo.git(&[
+        "config",
+        "gpg.program",
+        &repo.join("fake-gpg").display().to_string(),
+    ]);
+    repo.git(&["config", "commit.gpgsign", "true"]);
+    repo
+}
+
+#[test]
+fn signed_commits_embed_the_signature() {
+    let repo = fixture();
+    repo.run(&["commit", "-m", "signed"]);
+    let commit = repo.git(&["cat-file", "commit", "HEAD"]);
+    let header = format!("gpgsig {}", SIGNATURE.trim_end().replace('\n', "\n "));
+    assert!(
+        commit.contains(&format!("\n{header}\n\nsigned\n")),
+        "{commit}"
+    );
+
+    // the signer was given the commit without the signature
+    let unsigned = commit.replace(&format!("{header}\n"), "");
+    assert_eq!(repo.read("signed-payload"), unsigned);
+
+    // git signs the same commit the same way
+    let ours = repo.rev_parse("HEAD");
+    repo.git(&["update-ref", "-d", "HEAD"]);
+    repo.git(&["commit", "-q", "-m", "signed"]);
+    assert_eq!(repo.rev_parse("HEAD"), ours);
+}
+
+#[test]
+fn unsigned_without_commit_gpgsign() {
+    let repo = fixture();
+    repo.git(&["config", "commit.gpgsign", "false"]);
+    repo.run(&["commit", "-m", "unsigned"]);
+    assert!(!repo.git(&["cat-file", "commit", "HEAD"]).contains("gpgsig"));
+    assert!(!repo.join("signed-payload").exists());
+}
+
+#[test]
+fn a_failing_signer_stops_the_commit() {
+    let repo = fixture();
+    fs::write(
+        repo.join("fake-gpg"),
+        "#!/bin/sh\necho 'no secret key' >&2\nexit 2\n",
+    )
+    .unwrap();
+    let err = repo.fails(&["commit", "-m", "signed"]);
+    assert!(err.contains("gpg failed to sign the data"), "{err}");
+    assert!(err.contains("no secret key"), "{err}");
+    assert!(repo.git(&["for-each-ref", "refs/heads"]).is_empty());
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use common::Repo;
+
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("b", "b\n");
+    repo.commit_all("one");
+    repo
+}
+
+#[test]
+fn list_apply_and_drop() {
+    let repo = fixture();
+    repo.write("a", "a first\n");
+    repo.run(&["stash", "push"]);
+    assert_eq!(repo.read("a"), "a\n");
+    repo.write("b", "b second\n");
+    repo.run(&["stash", "push", "-m", "second"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+    let list = repo.run(&["stash", "list"]);
+    assert_eq!(list, repo.git(&["stash", "list"]));
+    let lines: Vec<_> = list.lines().collect();
+    assert_eq!(lines.len(), 2, "{list}");
+    assert_eq!(lines[0], "stash@{0}: On master: second");
+    assert!(lines[1].starts_with("stash@{1}: WIP on master: "), "{list}");
+
+    // applying keeps the entry
+    repo.run(&["stash", "apply", "stash@{1}"]);
+    assert_eq!(repo.read("a"), "a first\n");
+    assert_eq!(repo.read("b"), "b\n");
+    assert_eq!(repo.run(&["stash", "list"]), list);
+
+    let second = repo.rev_parse("stash@{0}");
+    let out = repo.run(&["stash", "drop"]);
+    assert_eq!(out, format!("Dropped refs/stash@{{0}} ({second})\n"));
+    let list = repo.run(&["stash", "list"]);
+    assert_eq!(list, lines[1].replace("stash@{1}", "stash@{0}") + "\n");
+    assert_eq!(list, repo.git(&["stash", "list"]));
+}
+
+#[test]
+fn drop_from_the_middle_rewrites_the_reflog() {
+    let repo = fixture();
+    for n in ["1", "2", "3"] {
+        repo.write("a", format!("a{n}\n"));
+        repo.run(&["stash", "push", "-m", n]);
+    }
+    let (newest, oldest) = (repo.rev_parse("stash@{0}"), repo.rev_parse("stash@{2}"));
+
+    repo.run(&["stash", "drop", "stash@{1}"]);
+    assert_eq!(
+        repo.git(&["stash", "list", "--format=%gd %gs"]),
+        "stash@{0} On master: 3\nstash@{1} On master: 1\n"
+    );
+    assert_eq!(repo.rev_parse("refs/stash"), newest);
+    assert_eq!(repo.rev_parse("stash@{1}"), oldest);
+
+    repo.run(&["stash", "drop"]);
+    assert_eq!(repo.rev_parse("refs/stash"), oldest);
+    repo.run(&["stash", "pop"]);
+    assert_eq!(repo.read("a"), "a1\n");
+    assert_eq!(repo.run(&["stash", "list"]), "");
+    let verify = repo
+        .command("git")
+        .args(["rev-parse", "--verify", "-q", "refs/stash"])
+        .output()
+        .unwrap();
+    assert!(!verify.status.success());
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use std::{
+    fs,
+    time::{Duration, SystemTime},
+};
+
+use common::Repo;
+
+/// A repository with tracked and untracked files, in tracked and untracked directories, and
+/// ignored ones.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("tracked/a", "a\n");
+    repo.write("tracked/deep/b", "b\n");
+    repo.write(".gitignore", "*.log\nbuild/\n");
+    repo.commit_all("files");
+    repo.write("tracked/new", "new\n");
+    repo.write("tracked/deep/new.log", "ignored\n");
+    repo.write("untracked/x/y", "y\n");
+    repo.write("build/out", "ignored\n");
+    repo.write("top", "top\n");
+    repo
+}
+
+#[test]
+fn untracked_files_match_git() {
+    let repo = fixture();
+    let status = repo.run(&["status", "--porcelain"]);
+    assert_eq!(status, "?? top\n?? tracked/new\n?? untracked/\n");
+    assert_eq!(status, repo.git(&["status", "--porcelain"]));
+}
+
+/// Set the mtime of the directories of `repo` an hour back, so that listings of them are cached
+/// (directories changed in the second a scan starts aren't).
+fn backdate_directories(repo: &Repo) {
+    let past = SystemTime::now() - Duration::from_secs(3600);
+    for dir in [
+        "",
+        "tracked",
+        "tracked/deep",
+        "untracked",
+        "untracked/x",
+        "build",
+    ] {
+        fs::File::open(repo.join(dir))
+            .unwrap()
+            .set_modified(past)
+            .unwrap();
+    }
+}
+
+#[test]
+fn untracked_cache_follows_changes() {
+    let repo = fixture();
+    backdate_directories(&repo);
+    repo.run(&["status", "--porcelain"]);
+    let cache = fs::read(repo.join(".git/untracked-cache")).unwrap();
+    assert!(String::from_utf8_lossy(&cache).contains("untracked/x/\0"));
+    repo.run(&["status", "--porcelain"]);
+    assert_eq!(fs::read(repo.join(".git/untracked-cache")).unwrap(), cache);
+
+    // a warm run sees files added to and removed from directories it listed before
+    repo.write("tracked/deep/newer", "newer\n");
+    fs::remove_file(repo.join("top")).unwrap();
+    let status = repo.run(&["status", "--porcelain"]);
+    assert_eq!(status, repo.git(&["status", "--porcelain"]));
+    assert!(status.contains("?? tracked/deep/newer\n"), "{status}");
+
+    // and a change of the ignore rules
+    repo.write(".gitignore", "*.log\n");
+    assert_eq!(
+        repo.run(&["status", "--porcelain"]),
+        repo.git(&["status", "--porcelain"])
+    );
+}
+
+#[test]
+fn no_optional_locks_leaves_no_cache() {
+    let repo = fixture();
+    repo.run(&["--no-optional-locks", "status", "--porcelain"]);
+    assert!(!repo.join(".git/untracked-cache").exists());
+}
+
+#[test]
+fn porcelain_lines_for_each_kind_of_change() {
+    let repo = Repo::init();
+    repo.write("modified", "old\n");
+    repo.write(
+        "moved",
+        "a file with enough lines\nto be found\nas a rename\n",
+    );
+    repo.commit_all("files");
+    repo.write("added", "added\n");
+    repo.git(&["add", "added"]);
+    repo.git(&["mv", "moved", "renamed"]);
+    repo.write("modified", "new\n");
+    repo.write("untracked", "untracked\n");
+
+    let status = repo.run(&["status", "--porcelain"]);
+    assert_eq!(
+        status,
+        "A  added\n M modified\nR  moved -> renamed\n?? untracked\n"
+    );
+    assert_eq!(status, repo.git(&["status", "--porcelain"]));
+
+    let status = repo.run(&["status", "--porcelain", "-z"]);
+    assert_eq!(
+        status,
+        "A  added\0 M modified\0R  renamed\0moved\0?? untracked\0"
+    );
+    assert_eq!(status, repo.git(&["status", "--porcelain", "-z"]));
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::Repo;
+
+const TEXT: &str = "\n\n  subject  \n\n\n\nbody\t\n# comment\n  # indented\n\n\nlast";
+
+#[test]
+fn cleans_up_text_like_git() {
+    let repo = Repo::init();
+    for args in [&[][..], &["--strip-comments"], &["--comment-lines"]] {
+        let mut full = vec!["stripspace"];
+        full.extend(args);
+        let ours = repo.run_with_input(&full, TEXT.as_bytes());
+        assert_eq!(
+            ours,
+            repo.git_with_input(&full, TEXT.as_bytes()),
+            "{args:?}"
+        );
+    }
+    assert_eq!(
+        repo.run_with_input(&["stripspace", "-s"], TEXT.as_bytes()),
+        "  subject\n\nbody\n  # indented\n\nlast\n"
+    );
+}
+
+#[test]
+fn empty_text_stays_empty() {
+    let repo = Repo::init();
+    assert_eq!(repo.run_with_input(&["stripspace"], b"\n \n\t\n"), "");
+    assert_eq!(repo.run_with_input(&["stripspace", "-s"], b"# only\n"), "");
+}
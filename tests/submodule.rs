@@ -0,0 +1,55 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with a `.gitmodules` for `lib`, and a gitlink at `lib` to a commit of the
+/// repository made there.
+fn fixture() -> (Repo, String) {
+    let repo = Repo::init();
+    repo.write(
+        ".gitmodules",
+        "[submodule \"lib\"]\n\tpath = lib\n\turl = https://example.com/lib.git\n",
+    );
+    repo.write("lib/file", "lib\n");
+    repo.git(&["-C", "lib", "init", "-q"]);
+    repo.git(&["-C", "lib", "add", "file"]);
+    repo.git(&["-C", "lib", "commit", "-q", "-m", "lib"]);
+    let commit = repo
+        .git(&["-C", "lib", "rev-parse", "HEAD"])
+        .trim()
+        .to_string();
+    let cacheinfo = format!("160000,{commit},lib");
+    repo.git(&["update-index", "--add", "--cacheinfo", &cacheinfo]);
+    repo.git(&["add", ".gitmodules"]);
+    (repo, commit)
+}
+
+#[test]
+fn reports_a_submodule_at_its_recorded_commit() {
+    let (repo, commit) = fixture();
+    assert_eq!(
+        repo.run(&["submodule", "status"]),
+        format!(" {commit} lib (https://example.com/lib.git)\n")
+    );
+}
+
+#[test]
+fn marks_a_submodule_whose_head_moved() {
+    let (repo, _) = fixture();
+    repo.git(&["-C", "lib", "commit", "-q", "--allow-empty", "-m", "more"]);
+    let head = repo.git(&["-C", "lib", "rev-parse", "HEAD"]);
+    assert_eq!(
+        repo.run(&["submodule", "status"]),
+        format!("+{} lib (https://example.com/lib.git)\n", head.trim())
+    );
+}
+
+#[test]
+fn marks_a_submodule_that_isnt_checked_out() {
+    let (repo, commit) = fixture();
+    std::fs::remove_dir_all(repo.join("lib")).unwrap();
+    std::fs::create_dir(repo.join("lib")).unwrap();
+    let status = repo.run(&["submodule", "status"]);
+    assert_eq!(status, format!("-{commit} lib\n"));
+    assert_eq!(status, repo.git(&["submodule", "status"]));
+}
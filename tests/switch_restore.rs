@@ -0,0 +1,86 @@
+mod common;
+
+use common::Repo;
+
+/// `master` with `a` and `b`, and a `topic` branch changing `a` and adding `c`.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("b", "b\n");
+    repo.commit_all("one");
+    repo.run(&["switch", "-c", "topic"]);
+    repo.write("a", "a topic\n");
+    repo.write("c", "c\n");
+    repo.commit_all("topic");
+    repo
+}
+
+#[test]
+fn switch_checks_out_branches() {
+    let repo = fixture();
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/topic\n");
+
+    repo.run(&["switch", "master"]);
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/master\n");
+    assert_eq!(repo.read("a"), "a\n");
+    assert!(!repo.join("c").exists());
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+
+    repo.run(&["switch", "-c", "new", "topic"]);
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/new\n");
+    assert_eq!(repo.rev_parse("new"), repo.rev_parse("topic"));
+    assert_eq!(repo.read("c"), "c\n");
+
+    repo.run(&["switch", "--detach", "master"]);
+    assert_eq!(repo.rev_parse("HEAD"), repo.rev_parse("master"));
+    assert_eq!(
+        repo.read(".git/HEAD"),
+        format!("{}\n", repo.rev_parse("master"))
+    );
+}
+
+#[test]
+fn switch_refuses_to_overwrite_local_changes() {
+    let repo = fixture();
+    repo.write("a", "local\n");
+    let stderr = repo.fails(&["switch", "master"]);
+    assert!(
+        stderr.contains("would be overwritten by checkout:\n\ta\n"),
+        "{stderr}"
+    );
+    assert_eq!(repo.read("a"), "local\n");
+    assert_eq!(repo.git(&["symbolic-ref", "HEAD"]), "refs/heads/topic\n");
+
+    repo.run(&["switch", "--force", "master"]);
+    assert_eq!(repo.read("a"), "a\n");
+}
+
+#[test]
+fn restore_a_single_file() {
+    let repo = fixture();
+    repo.write("a", "changed\n");
+    repo.write("c", "changed\n");
+    repo.run(&["restore", "a"]);
+    assert_eq!(repo.read("a"), "a topic\n");
+    assert_eq!(repo.read("c"), "changed\n");
+
+    // from another commit, leaving the index alone
+    repo.run(&["restore", "--source", "master", "a"]);
+    assert_eq!(repo.read("a"), "a\n");
+    assert_eq!(repo.git(&["status", "--porcelain"]), " M a\n M c\n");
+}
+
+#[test]
+fn restore_staged_unstages_from_head() {
+    let repo = fixture();
+    repo.write("b", "staged\n");
+    repo.git(&["add", "b"]);
+    repo.run(&["restore", "--staged", "b"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), " M b\n");
+    assert_eq!(repo.read("b"), "staged\n");
+
+    repo.git(&["add", "b"]);
+    repo.run(&["restore", "--staged", "--worktree", "b"]);
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    assert_eq!(repo.read("b"), "b\n");
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with one commit, and `script` as `fake-editor`.
+fn repo_with_editor(script: &str) -> Repo {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("first");
+    repo.write("fake-editor", script);
+    repo
+}
+
+/// Run `git-rs tag` with `args` and `editor` as `GIT_EDITOR`.
+fn tag_with_editor(repo: &Repo, args: &[&str], editor: &str) -> std::process::Output {
+    let mut command = repo.git_rs(&["tag"]);
+    command.args(args).env("GIT_EDITOR", editor);
+    command.output().unwrap()
+}
+
+#[test]
+fn annotated_tags_match_git() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("first");
+
+    repo.run(&["tag", "-a", "-m", "release\n\n\nnotes  ", "v1"]);
+    repo.git(&["tag", "-a", "-m", "release\n\n\nnotes  ", "v1-git"]);
+    let ours = repo.git(&["cat-file", "-p", "v1"]);
+    let theirs = repo.git(&["cat-file", "-p", "v1-git"]);
+    assert_eq!(ours, theirs.replace("tag v1-git", "tag v1"));
+    repo.git(&["fsck", "--strict"]);
+
+    // a lightweight tag points at the commit itself
+    repo.run(&["tag", "light", "HEAD"]);
+    assert_eq!(repo.rev_parse("refs/tags/light"), repo.rev_parse("HEAD"));
+    assert_eq!(repo.run(&["tag"]), "light\nv1\nv1-git\n");
+    assert_eq!(repo.run(&["tag", "-l", "v1*"]), "v1\nv1-git\n");
+
+    let error = repo.fails(&["tag", "light"]);
+    assert!(error.contains("tag 'light' already exists"), "{error}");
+    repo.run(&["tag", "-d", "light"]);
+    assert_eq!(repo.run(&["tag"]), "v1\nv1-git\n");
+}
+
+#[test]
+fn editor_writes_the_message() {
+    let repo = repo_with_editor(
+        r#"cp "$1" template
+printf 'edited\n\n\nbody  \n# a comment\n' > "$1"
+"#,
+    );
+    let output = tag_with_editor(&repo, &["-a", "v1"], "sh fake-editor");
+    assert!(output.status.success());
+    assert_eq!(
+        repo.git(&["for-each-ref", "--format=%(contents)", "refs/tags/v1"]),
+        "edited\n\nbody\n\n"
+    );
+    assert_eq!(
+        repo.read("template"),
+        "\n#\n# Write a message for tag:\n#   v1\n# Lines starting with '#' will be ignored.\n"
+    );
+    assert!(!repo.join(".git/TAG_EDITMSG").exists());
+}
+
+#[test]
+fn failing_editor_or_empty_message_aborts_the_tag() {
+    let repo = repo_with_editor("echo ignored > \"$1\"\nexit 1\n");
+    let output = tag_with_editor(&repo, &["-a", "v1"], "sh fake-editor");
+    assert!(!output.status.success());
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("Please supply the message"), "{err}");
+
+    repo.write("fake-editor", r#"printf '# only a comment\n\n' > "$1""#);
+    let output = tag_with_editor(&repo, &["-a", "v1"], "sh fake-editor");
+    assert!(!output.status.success());
+    let err = String::from_utf8_lossy(&output.stderr);
+    assert!(err.contains("no tag message?"), "{err}");
+    assert_eq!(repo.run(&["tag"]), "");
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// A repository whose `*.bin` files are converted with `xxd`, with a binary file committed and
+/// then changed in the work tree.
+fn fixture() -> Repo {
+    let repo = Repo::init();
+    repo.git(&["config", "diff.hex.textconv", "xxd"]);
+    repo.write(".gitattributes", "*.bin diff=hex\n");
+    repo.write("data.bin", b"ab\0cd");
+    repo.commit_all("binary");
+    repo.write("data.bin", b"ab\0ce");
+    repo
+}
+
+#[test]
+fn cat_file_converts_the_blob() {
+    let repo = fixture();
+    let text = repo.run(&["cat-file", "--textconv", "HEAD:data.bin"]);
+    assert_eq!(
+        text,
+        "00000000: 6162 0063 64                             ab.cd\n"
+    );
+    assert_eq!(text, repo.git(&["cat-file", "--textconv", "HEAD:data.bin"]));
+}
+
+#[test]
+fn diff_shows_the_converted_text() {
+    let repo = fixture();
+    let diff = repo.run(&["diff"]);
+    assert!(diff.contains("\ntextconv hex\n"), "{diff}");
+    assert_eq!(
+        diff.replace("textconv hex\n", ""),
+        repo.git(&["diff"]),
+        "the diff of the converted text matches git's"
+    );
+    assert!(repo
+        .run(&["diff", "--no-textconv"])
+        .contains("Binary files a/data.bin and b/data.bin differ"));
+}
+
+#[test]
+fn conversions_are_cached_by_blob() {
+    let repo = fixture();
+    let blob = repo.rev_parse("HEAD:data.bin");
+    repo.run(&["cat-file", "--textconv", "HEAD:data.bin"]);
+
+    let cache = fs::read_dir(repo.join(".git/textconv-cache"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let cached = cache.join(&blob);
+    assert_eq!(
+        fs::read_to_string(&cached).unwrap(),
+        "00000000: 6162 0063 64                             ab.cd\n"
+    );
+    // a cached conversion is used rather than running the converter again
+    fs::write(&cached, "from the cache\n").unwrap();
+    assert_eq!(
+        repo.run(&["cat-file", "--textconv", "HEAD:data.bin"]),
+        "from the cache\n"
+    );
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use common::Repo;
+
+/// The messages of the trace lines in `trace`, without their time and source location.
+fn messages(trace: &str) -> Vec<&str> {
+    trace
+        .lines()
+        .map(|line| {
+            let at = line.find("trace: ").or_else(|| line.find("packet: "));
+            &line[at.unwrap_or_else(|| panic!("not a trace line: {line}"))..]
+        })
+        .collect()
+}
+
+#[test]
+fn commit_traces_the_command_objects_and_ref_update() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.git(&["add", "a"]);
+    let trace = repo.join("trace.txt");
+    let mut command = repo.git_rs(&["commit", "-m", "one"]);
+    let output = command.env("GIT_TRACE", &trace).output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+
+    let (tree, commit) = (repo.rev_parse("HEAD^{tree}"), repo.rev_parse("HEAD"));
+    let size = repo.git(&["cat-file", "-s", &commit]);
+    let trace = fs::read_to_string(trace).unwrap();
+    let messages = messages(&trace);
+    assert_eq!(messages[0], "trace: built-in: git-rs commit -m one");
+    assert!(messages.contains(&format!("trace: object write: {tree} tree 29").as_str()));
+    assert!(messages
+        .contains(&format!("trace: object write: {commit} commit {}", size.trim()).as_str()));
+    assert!(messages.contains(
+        &format!(
+            "trace: ref update: refs/heads/master {} -> {commit}",
+            "0".repeat(40)
+        )
+        .as_str()
+    ));
+    assert!(messages
+        .last()
+        .unwrap()
+        .starts_with("trace: exit: status 0 after "));
+}
+
+#[test]
+fn trace_goes_to_stderr_for_1_and_nowhere_for_0() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("one");
+
+    let output = repo
+        .git_rs(&["log"])
+        .env("GIT_TRACE", "1")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let head = repo.rev_parse("HEAD");
+    assert!(
+        stderr.contains(&format!("trace: object read: {head} commit ")),
+        "{stderr}"
+    );
+    assert!(!String::from_utf8(output.stdout).unwrap().contains("trace:"));
+
+    for value in ["0", "false", ""] {
+        let output = repo
+            .git_rs(&["log"])
+            .env("GIT_TRACE", value)
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "",
+            "GIT_TRACE={value}"
+        );
+    }
+}
+
+#[test]
+fn packet_trace_shows_pkt_lines_like_git() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let head = repo.commit_all("one");
+
+    let mut command = repo.git_rs(&["upload-pack", "."]);
+    command.env("GIT_TRACE_PACKET", "1");
+    let output = stderr_with_input(command, b"0000");
+    let messages = messages(&output);
+    assert!(messages[0].starts_with(&format!("packet:  upload-pack> {head} HEAD\\0")));
+    assert_eq!(
+        messages[1..],
+        [
+            format!("packet:  upload-pack> {head} refs/heads/master").as_str(),
+            "packet:  upload-pack> 0000",
+            "packet:  upload-pack< 0000",
+        ]
+    );
+}
+
+/// The error output of `command` given `stdin`; it must succeed.
+fn stderr_with_input(mut command: Command, stdin: &[u8]) -> String {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    String::from_utf8(output.stderr).unwrap()
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn add_remove_and_chmod() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.write("dir/b", "b\n");
+    // a path that isn't in the index needs --add
+    repo.fails(&["update-index", "a"]);
+    repo.run(&["update-index", "--add", "a", "dir/b"]);
+    let blob_a = repo.git(&["hash-object", "a"]);
+    let blob_b = repo.git(&["hash-object", "dir/b"]);
+    assert_eq!(
+        repo.git(&["ls-files", "--stage"]),
+        format!(
+            "100644 {} 0\ta\n100644 {} 0\tdir/b\n",
+            blob_a.trim(),
+            blob_b.trim()
+        )
+    );
+
+    repo.run(&["update-index", "--chmod=+x", "a"]);
+    assert!(repo
+        .git(&["ls-files", "--stage", "a"])
+        .starts_with("100755 "));
+
+    std::fs::remove_file(repo.join("dir/b")).unwrap();
+    repo.run(&["update-index", "--remove", "dir/b"]);
+    assert_eq!(repo.git(&["ls-files"]), "a\n");
+}
+
+#[test]
+fn cacheinfo_adds_an_entry_without_the_file() {
+    let repo = Repo::init();
+    let blob = repo.run_with_input(&["hash-object", "-w", "--stdin"], b"content\n");
+    let blob = blob.trim();
+    repo.run(&[
+        "update-index",
+        "--add",
+        "--cacheinfo",
+        &format!("100644,{blob},only/in/index"),
+    ]);
+    assert!(!repo.join("only").exists());
+    assert_eq!(
+        repo.git(&["ls-files", "--stage"]),
+        format!("100644 {blob} 0\tonly/in/index\n")
+    );
+    // and it can be committed like any other entry
+    repo.run(&["commit", "-m", "from the index"]);
+    assert_eq!(repo.git(&["show", "HEAD:only/in/index"]), "content\n");
+}
+
+#[test]
+fn refresh_updates_stat_data() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.run(&["update-index", "--add", "a"]);
+    // touching the file makes its stat data stale without changing it
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+    std::fs::File::options()
+        .write(true)
+        .open(repo.join("a"))
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    assert_eq!(repo.git(&["diff-files", "--name-only"]), "a\n");
+    repo.run(&["update-index", "--refresh"]);
+    assert_eq!(repo.git(&["diff-files", "--name-only"]), "");
+}
+
+#[test]
+fn a_held_index_lock_makes_writers_fail_without_changing_the_index() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("one");
+    let index = std::fs::read(repo.join(".git/index")).unwrap();
+
+    repo.write(".git/index.lock", "");
+    repo.write("b", "b\n");
+    let stderr = repo.fails(&["update-index", "--add", "b"]);
+    let lock = repo.join(".git/index.lock");
+    assert!(
+        stderr.contains(&format!(
+            "Unable to create '{}': File exists.",
+            lock.display()
+        )),
+        "{stderr}"
+    );
+    assert_eq!(std::fs::read(repo.join(".git/index")).unwrap(), index);
+    assert!(lock.exists());
+
+    std::fs::remove_file(&lock).unwrap();
+    repo.run(&["update-index", "--add", "b"]);
+    assert_eq!(repo.git(&["ls-files"]), "a\nb\n");
+    assert!(!lock.exists());
+}
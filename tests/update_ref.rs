@@ -0,0 +1,145 @@
+mod common;
+
+use common::Repo;
+
+/// A repository with two commits on `master`, and `x` at the first.
+fn fixture() -> (Repo, String, String) {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    let first = repo.commit_all("one");
+    repo.write("a", "2\n");
+    let second = repo.commit_all("two");
+    repo.git(&["branch", "x", &first]);
+    (repo, first, second)
+}
+
+fn refs(repo: &Repo) -> String {
+    repo.git(&["for-each-ref", "--format=%(refname) %(objectname)"])
+}
+
+#[test]
+fn stdin_updates_several_refs_together() {
+    let (repo, first, second) = fixture();
+    let commands = format!(
+        "update refs/heads/x {second} {first}\n\
+         create refs/heads/y {second}\n\
+         verify refs/heads/master {second}\n"
+    );
+    assert_eq!(
+        repo.run_with_input(
+            &["update-ref", "-m", "batch", "--stdin"],
+            commands.as_bytes()
+        ),
+        ""
+    );
+    assert_eq!(
+        refs(&repo),
+        format!("refs/heads/master {second}\nrefs/heads/x {second}\nrefs/heads/y {second}\n")
+    );
+    // one reflog entry per ref
+    assert_eq!(
+        repo.git(&["reflog", "show", "--format=%gs", "refs/heads/x"]),
+        format!("batch\nbranch: Created from {first}\n")
+    );
+    assert_eq!(
+        repo.git(&["reflog", "show", "--format=%gs", "refs/heads/y"]),
+        "batch\n"
+    );
+
+    let commands = format!("delete refs/heads/y {second}\n");
+    repo.run_with_input(&["update-ref", "--stdin"], commands.as_bytes());
+    assert!(!refs(&repo).contains("refs/heads/y"));
+}
+
+#[test]
+fn a_stale_old_value_moves_no_ref() {
+    let (repo, first, second) = fixture();
+    let before = refs(&repo);
+    // `master` isn't at `first` any more, so the update of `x` before it mustn't happen either
+    let commands = format!(
+        "update refs/heads/x {second} {first}\n\
+         create refs/heads/y {second}\n\
+         verify refs/heads/master {first}\n"
+    );
+    let stderr = repo.fails_with_input(&["update-ref", "--stdin"], commands.as_bytes());
+    assert!(
+        stderr.contains(&format!(
+            "cannot lock ref 'refs/heads/master': is at {second} but expected {first}"
+        )),
+        "{stderr}"
+    );
+    assert_eq!(refs(&repo), before);
+    assert!(!repo.join(".git/refs/heads/x.lock").exists());
+    assert!(!repo.join(".git/refs/heads/y.lock").exists());
+}
+
+#[test]
+fn a_held_lock_moves_no_ref() {
+    let (repo, _, second) = fixture();
+    let before = refs(&repo);
+    repo.write(".git/refs/heads/z.lock", "");
+    let commands = format!("create refs/heads/w {second}\ncreate refs/heads/z {second}\n");
+    let stderr = repo.fails_with_input(&["update-ref", "--stdin"], commands.as_bytes());
+    assert!(
+        stderr.contains("cannot lock ref 'refs/heads/z'"),
+        "{stderr}"
+    );
+    assert_eq!(refs(&repo), before);
+    assert!(!repo.join(".git/refs/heads/w.lock").exists());
+}
+
+#[test]
+fn start_prepare_commit_and_abort() {
+    let (repo, first, second) = fixture();
+    let commands = format!("start\nupdate refs/heads/x {second} {first}\nprepare\ncommit\n");
+    assert_eq!(
+        repo.run_with_input(&["update-ref", "--stdin"], commands.as_bytes()),
+        "start: ok\nprepare: ok\ncommit: ok\n"
+    );
+    assert_eq!(repo.rev_parse("x"), second);
+
+    let before = refs(&repo);
+    let commands = "start\ndelete refs/heads/x\nprepare\nabort\n";
+    assert_eq!(
+        repo.run_with_input(&["update-ref", "--stdin"], commands.as_bytes()),
+        "start: ok\nprepare: ok\nabort: ok\n"
+    );
+    assert_eq!(refs(&repo), before);
+    assert!(!repo.join(".git/refs/heads/x.lock").exists());
+}
+
+#[test]
+fn porcelain_commands_write_reflogs() {
+    let repo = Repo::init();
+    repo.write("a", "1\n");
+    repo.commit_all("one");
+    repo.write("a", "2\n");
+    repo.commit_all("two");
+    repo.run(&["branch", "topic"]);
+    repo.run(&["checkout", "topic"]);
+    assert_eq!(
+        repo.git(&["reflog", "show", "--format=%gs", "refs/heads/master"]),
+        "commit: two\ncommit (initial): one\n"
+    );
+    assert_eq!(
+        repo.git(&["reflog", "show", "--format=%gs", "refs/heads/topic"]),
+        "branch: Created from HEAD\n"
+    );
+    assert_eq!(
+        repo.git(&["reflog", "show", "--format=%gs", "HEAD"]),
+        "checkout: moving from master to topic\ncommit: two\ncommit (initial): one\n"
+    );
+}
+
+#[test]
+fn a_held_packed_refs_lock_keeps_a_packed_ref() {
+    let (repo, ..) = fixture();
+    repo.git(&["pack-refs", "--all"]);
+    let before = refs(&repo);
+    repo.write(".git/packed-refs.lock", "");
+    repo.fails_with_input(&["update-ref", "--stdin"], b"delete refs/heads/x\n");
+    assert_eq!(refs(&repo), before);
+    std::fs::remove_file(repo.join(".git/packed-refs.lock")).unwrap();
+    repo.run_with_input(&["update-ref", "--stdin"], b"delete refs/heads/x\n");
+    assert!(!refs(&repo).contains("refs/heads/x"));
+}
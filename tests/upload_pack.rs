@@ -0,0 +1,165 @@
+mod common;
+
+use common::Repo;
+
+/// The `--upload-pack` command that makes git fetch from `git-rs upload-pack`.
+const UPLOAD_PACK: &str = concat!(env!("CARGO_BIN_EXE_git-rs"), " upload-pack");
+
+/// A repository with a few commits, a branch and an annotated tag to fetch.
+fn server() -> Repo {
+    let repo = Repo::init();
+    for n in 0..3 {
+        repo.write(&format!("file{n}"), format!("{n}\n"));
+        repo.commit_all(&format!("commit {n}"));
+    }
+    repo.git(&["branch", "side", "HEAD~1"]);
+    repo.git(&["tag", "-a", "v1", "-m", "tag"]);
+    repo
+}
+
+fn url(repo: &Repo) -> String {
+    format!("file://{}", repo.path.display())
+}
+
+fn refs(repo: &Repo, pattern: &str) -> String {
+    repo.git(&[
+        "for-each-ref",
+        "--format=%(objectname) %(refname:short)",
+        pattern,
+    ])
+}
+
+#[test]
+fn git_clones_from_it() {
+    let server = server();
+    let client = Repo::empty();
+    client.git(&[
+        "clone",
+        "-q",
+        "--upload-pack",
+        UPLOAD_PACK,
+        &url(&server),
+        ".",
+    ]);
+    client.git(&["fsck", "--strict"]);
+    assert_eq!(client.rev_parse("HEAD"), server.rev_parse("HEAD"));
+    assert_eq!(client.rev_parse("origin/side"), server.rev_parse("side"));
+    assert_eq!(refs(&client, "refs/tags"), refs(&server, "refs/tags"));
+    assert_eq!(client.read("file2"), "2\n");
+}
+
+#[test]
+fn git_fetches_new_commits_from_it() {
+    let server = server();
+    let client = Repo::empty();
+    client.git(&[
+        "clone",
+        "-q",
+        "--upload-pack",
+        UPLOAD_PACK,
+        &url(&server),
+        ".",
+    ]);
+
+    server.write("file3", "3\n");
+    let head = server.commit_all("commit 3");
+    client.git(&["fetch", "-q", "--upload-pack", UPLOAD_PACK, "origin"]);
+    assert_eq!(client.rev_parse("origin/master"), head);
+    client.git(&["fsck", "--strict"]);
+}
+
+#[test]
+fn advertises_its_refs() {
+    let server = server();
+    let client = Repo::empty();
+    let advertised = client.git(&["ls-remote", "--upload-pack", UPLOAD_PACK, &url(&server)]);
+    assert_eq!(advertised, server.git(&["ls-remote", "."]));
+}
+
+#[test]
+fn counts_objects_with_the_bitmaps_git_wrote() {
+    let server = server();
+    server.git(&["repack", "-q", "-a", "-d", "-b"]);
+    // commits after the repack are loose, so counting mixes bitmaps and a walk
+    server.write("file3", "3\n");
+    let head = server.commit_all("commit 3");
+    let trace = server.join(".git/upload-pack.trace");
+    let upload_pack = format!("GIT_TRACE={} {UPLOAD_PACK}", trace.display());
+
+    let client = Repo::empty();
+    client.git(&[
+        "clone",
+        "-q",
+        "--upload-pack",
+        &upload_pack,
+        &url(&server),
+        ".",
+    ]);
+    client.git(&["fsck", "--strict"]);
+    assert_eq!(client.rev_parse("HEAD"), head);
+    let objects = server
+        .git(&["rev-list", "--objects", "--all"])
+        .lines()
+        .count();
+    let traced = std::fs::read_to_string(&trace).unwrap();
+    assert!(
+        traced.contains(&format!("upload-pack: counted {objects} objects in ")),
+        "{traced}"
+    );
+    assert!(traced.contains(" s with bitmaps\n"), "{traced}");
+
+    // a fetch leaves out what the client has, which the bitmaps say too
+    server.write("file4", "4\n");
+    let head = server.commit_all("commit 4");
+    std::fs::remove_file(&trace).unwrap();
+    client.git(&["fetch", "-q", "--upload-pack", &upload_pack, "origin"]);
+    assert_eq!(client.rev_parse("origin/master"), head);
+    client.git(&["fsck", "--strict"]);
+    let traced = std::fs::read_to_string(&trace).unwrap();
+    // the commit, its tree and the new blob
+    assert!(
+        traced.contains("upload-pack: counted 3 objects in "),
+        "{traced}"
+    );
+}
+
+#[test]
+fn counts_objects_without_bitmaps_it_cannot_read() {
+    let server = server();
+    server.git(&["repack", "-q", "-a", "-d", "-b"]);
+    let pack_dir = server.join(".git/objects/pack");
+    let bitmap = std::fs::read_dir(&pack_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "bitmap"))
+        .unwrap();
+    let mut data = std::fs::read(&bitmap).unwrap();
+    // skip the header and the four bitmaps of the object types to the first commit's, whose
+    // first word becomes a run of 2^32 - 1 words of zeros
+    let mut at = 32;
+    let words = |at: usize| u32::from_be_bytes(data[at + 4..at + 8].try_into().unwrap()) as usize;
+    for _ in 0..4 {
+        at += 8 + words(at) * 8 + 4;
+    }
+    at += 6;
+    assert!(words(at) > 0);
+    data[at + 8..at + 16].copy_from_slice(&(0xffff_ffffu64 << 1).to_be_bytes());
+    std::fs::write(&bitmap, data).unwrap();
+
+    let trace = server.join(".git/upload-pack.trace");
+    let upload_pack = format!("GIT_TRACE={} {UPLOAD_PACK}", trace.display());
+    let client = Repo::empty();
+    client.git(&[
+        "clone",
+        "-q",
+        "--upload-pack",
+        &upload_pack,
+        &url(&server),
+        ".",
+    ]);
+    client.git(&["fsck", "--strict"]);
+    assert_eq!(client.rev_parse("HEAD"), server.rev_parse("HEAD"));
+    let traced = std::fs::read_to_string(&trace).unwrap();
+    assert!(traced.contains("upload-pack: counted "), "{traced}");
+    assert!(!traced.contains("with bitmaps"), "{traced}");
+}
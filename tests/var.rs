@@ -0,0 +1,111 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn prints_identities_like_git() {
+    let repo = Repo::init();
+    for var in ["GIT_AUTHOR_IDENT", "GIT_COMMITTER_IDENT", "GIT_PAGER"] {
+        assert_eq!(repo.run(&["var", var]), repo.git(&["var", var]), "{var}");
+    }
+    assert_eq!(
+        repo.run(&["var", "GIT_AUTHOR_IDENT"]),
+        "A U Thor <author@example.com> 1700000000 +0000\n"
+    );
+}
+
+#[test]
+fn takes_the_identity_from_config_without_the_environment() {
+    let repo = Repo::init();
+    repo.git(&["config", "user.name", "Con Fig"]);
+    repo.git(&["config", "user.email", "config@example.com"]);
+    let mut var = repo.git_rs(&["var", "GIT_COMMITTER_IDENT"]);
+    var.env_remove("GIT_COMMITTER_NAME")
+        .env_remove("GIT_COMMITTER_EMAIL");
+    let output = var.output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Con Fig <config@example.com> 1700000000 +0000\n"
+    );
+}
+
+#[test]
+fn the_repository_config_overrides_the_users() {
+    let repo = Repo::init();
+    // HOME is the repository's work tree in the tests
+    repo.run(&["config", "--global", "user.name", "Glo Bal"]);
+    assert_eq!(repo.git(&["config", "--global", "user.name"]), "Glo Bal\n");
+    repo.write(".config/git/config", "[user]\n\temail = xdg@example.com\n");
+    let ident = || {
+        let mut var = repo.git_rs(&["var", "GIT_COMMITTER_IDENT"]);
+        var.env_remove("GIT_COMMITTER_NAME")
+            .env_remove("GIT_COMMITTER_EMAIL");
+        String::from_utf8(var.output().unwrap().stdout).unwrap()
+    };
+    assert_eq!(ident(), "Glo Bal <xdg@example.com> 1700000000 +0000\n");
+
+    repo.git(&["config", "user.name", "Lo Cal"]);
+    assert_eq!(ident(), "Lo Cal <xdg@example.com> 1700000000 +0000\n");
+    assert_eq!(repo.run(&["config", "--global", "user.name"]), "Glo Bal\n");
+}
+
+#[test]
+fn commits_use_the_same_identities() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("commit");
+    let commit = repo.git(&["cat-file", "commit", "HEAD"]);
+    let author = repo.run(&["var", "GIT_AUTHOR_IDENT"]);
+    let committer = repo.run(&["var", "GIT_COMMITTER_IDENT"]);
+    assert!(commit.contains(&format!("\nauthor {author}")), "{commit}");
+    assert!(
+        commit.contains(&format!("\ncommitter {committer}")),
+        "{commit}"
+    );
+}
+
+#[test]
+fn lists_config_and_variables() {
+    let repo = Repo::init();
+    let listed = repo.run(&["var", "-l"]);
+    assert!(listed.contains("core.bare=false\n"), "{listed}");
+    assert!(
+        listed.contains("GIT_AUTHOR_IDENT=A U Thor <author@example.com> 1700000000 +0000\n"),
+        "{listed}"
+    );
+    assert!(listed.contains("GIT_PAGER=cat\n"), "{listed}");
+}
+
+#[test]
+fn editor_follows_gits_precedence() {
+    let repo = Repo::init();
+    let editor = |vars: &[(&str, &str)]| {
+        let mut ours = repo.git_rs(&["var", "GIT_EDITOR"]);
+        let mut git = repo.command("git");
+        git.args(["var", "GIT_EDITOR"]);
+        for (var, value) in vars {
+            ours.env(var, value);
+            git.env(var, value);
+        }
+        let ours = ours.output().unwrap();
+        assert_eq!(ours.stdout, git.output().unwrap().stdout, "{vars:?}");
+        String::from_utf8(ours.stdout).unwrap()
+    };
+    assert_eq!(editor(&[("EDITOR", "ed")]), "ed\n");
+    assert_eq!(
+        editor(&[("EDITOR", "ed"), ("VISUAL", "vis")]),
+        "ed\n",
+        "a dumb terminal"
+    );
+    assert_eq!(
+        editor(&[("TERM", "xterm"), ("EDITOR", "ed"), ("VISUAL", "vis")]),
+        "vis\n"
+    );
+    repo.git(&["config", "core.editor", "code --wait"]);
+    assert_eq!(editor(&[("EDITOR", "ed")]), "code --wait\n");
+    assert_eq!(
+        editor(&[("EDITOR", "ed"), ("GIT_EDITOR", "nano")]),
+        "nano\n"
+    );
+}
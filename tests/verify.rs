@@ -0,0 +1,44 @@
+mod common;
+
+use common::Repo;
+
+/// Write `data` as an object of type `kind`, however malformed, returning its hash.
+fn write_raw(repo: &Repo, kind: &str, data: &str) -> String {
+    let args = ["hash-object", "--literally", "-w", "-t", kind, "--stdin"];
+    repo.git_with_input(&args, data.as_bytes())
+        .trim()
+        .to_string()
+}
+
+#[test]
+fn accepts_a_valid_commit() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let commit = repo.commit_all("valid");
+    repo.run(&["verify-commit", &commit]);
+}
+
+#[test]
+fn rejects_a_commit_without_a_tree() {
+    let repo = Repo::init();
+    let commit = write_raw(
+        &repo,
+        "commit",
+        "author A <a@b> 1700000000 +0000\ncommitter A <a@b> 1700000000 +0000\n\nno tree\n",
+    );
+    let error = repo.fails(&["verify-commit", &commit]);
+    assert!(error.contains("tree"), "{error}");
+}
+
+#[test]
+fn verifies_tags() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    repo.commit_all("tagged");
+    repo.git(&["tag", "-a", "-m", "a tag", "v1"]);
+    let tag = repo.rev_parse("v1");
+    repo.run(&["verify-tag", &tag]);
+
+    let broken = write_raw(&repo, "tag", "type commit\ntag v2\n\nno object\n");
+    repo.fails(&["verify-tag", &broken]);
+}
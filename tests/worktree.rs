@@ -0,0 +1,124 @@
+mod common;
+
+use std::fs;
+
+use common::Repo;
+
+/// A repository in `repo/` with one commit on `master` and a `feature` branch.
+fn fixture() -> Repo {
+    let dir = Repo::empty();
+    dir.git(&["init", "--quiet", "repo"]);
+    dir.write("repo/a", "a\n");
+    dir.git(&["-C", "repo", "add", "a"]);
+    dir.git(&["-C", "repo", "commit", "--quiet", "-m", "one"]);
+    dir.git(&["-C", "repo", "branch", "feature"]);
+    dir
+}
+
+/// Run `git-rs` with `args` in the directory `cwd`; it must succeed.
+fn run_in(dir: &Repo, cwd: &str, args: &[&str]) -> String {
+    let output = dir
+        .git_rs(args)
+        .current_dir(dir.join(cwd))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{args:?}: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn add_links_a_worktree_to_the_shared_repository() {
+    let dir = fixture();
+    run_in(&dir, "repo", &["worktree", "add", "../wt", "feature"]);
+
+    let gitdir = fs::read_to_string(dir.join("wt/.git")).unwrap();
+    let admin = fs::canonicalize(dir.join("repo/.git/worktrees/wt")).unwrap();
+    assert_eq!(gitdir, format!("gitdir: {}\n", admin.display()));
+    assert_eq!(
+        fs::read_to_string(admin.join("HEAD")).unwrap(),
+        "ref: refs/heads/feature\n"
+    );
+    assert_eq!(fs::read_to_string(dir.join("wt/a")).unwrap(), "a\n");
+    assert!(!dir.join("repo/.git/worktrees/wt/objects").exists());
+
+    // commands in the worktree read and write the shared object store and branches
+    let one = dir.git(&["-C", "repo", "rev-parse", "HEAD"]);
+    assert_eq!(run_in(&dir, "wt", &["rev-parse", "HEAD"]), one);
+    dir.write("wt/b", "b\n");
+    dir.git(&["-C", "wt", "add", "b"]);
+    run_in(&dir, "wt", &["commit", "-m", "two"]);
+    let two = dir.git(&["-C", "repo", "rev-parse", "feature"]);
+    assert_ne!(two, one);
+    assert_eq!(
+        run_in(
+            &dir,
+            "repo",
+            &["cat-file", "blob", &format!("{}:b", two.trim())]
+        ),
+        "b\n"
+    );
+    // each worktree has its own HEAD and index
+    assert_eq!(dir.git(&["-C", "repo", "rev-parse", "HEAD"]), one);
+    assert_eq!(run_in(&dir, "repo", &["status", "--porcelain"]), "");
+    assert_eq!(run_in(&dir, "wt", &["status", "--porcelain"]), "");
+    dir.git(&["-C", "repo", "fsck", "--no-progress"]);
+    let list = dir.git(&["-C", "repo", "worktree", "list", "--porcelain"]);
+    assert!(list.contains("branch refs/heads/feature\n"), "{list}");
+}
+
+#[test]
+fn add_refuses_a_branch_checked_out_elsewhere() {
+    let dir = fixture();
+    let mut command = dir.git_rs(&["worktree", "add", "../wt", "master"]);
+    let output = command.current_dir(dir.join("repo")).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'master' is already checked out"));
+    assert!(!dir.join("wt").exists());
+}
+
+#[test]
+fn worktree_config_applies_to_one_worktree_only() {
+    let dir = fixture();
+    run_in(&dir, "repo", &["worktree", "add", "../wt", "feature"]);
+    // without the extension there is no per-worktree config
+    let mut command = dir.git_rs(&["config", "--worktree", "core.sparseCheckout", "true"]);
+    let output = command.current_dir(dir.join("wt")).output().unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("extension worktreeConfig"));
+
+    dir.git(&["-C", "repo", "config", "extensions.worktreeConfig", "true"]);
+    run_in(
+        &dir,
+        "wt",
+        &["config", "--worktree", "core.sparseCheckout", "true"],
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("repo/.git/worktrees/wt/config.worktree")).unwrap(),
+        "[core]\nsparseCheckout=true\n"
+    );
+    assert_eq!(
+        run_in(&dir, "wt", &["config", "core.sparseCheckout"]),
+        "true\n"
+    );
+    assert_eq!(
+        dir.git(&["-C", "wt", "config", "core.sparseCheckout"]),
+        "true\n"
+    );
+    // neither the main checkout nor the shared config sees it
+    let mut command = dir.git_rs(&["config", "core.sparseCheckout"]);
+    let output = command.current_dir(dir.join("repo")).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!dir
+        .git(&["-C", "repo", "config", "--list"])
+        .contains("sparsecheckout"));
+
+    // the worktree's settings win over the shared ones
+    dir.git(&["-C", "repo", "config", "user.name", "Shared"]);
+    run_in(
+        &dir,
+        "wt",
+        &["config", "--worktree", "user.name", "Worktree"],
+    );
+    assert_eq!(run_in(&dir, "wt", &["config", "user.name"]), "Worktree\n");
+    assert_eq!(run_in(&dir, "repo", &["config", "user.name"]), "Shared\n");
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::Repo;
+
+#[test]
+fn tree_is_the_same_for_any_number_of_jobs() {
+    let repo = Repo::init();
+    for dir in 0..8 {
+        for file in 0..40 {
+            repo.write(&format!("d{dir}/f{file}"), format!("{dir} {file}\n"));
+        }
+    }
+    repo.write("top", "top\n");
+
+    let single = repo.run(&["write-tree", "--jobs", "1"]);
+    let parallel = repo.run(&["write-tree", "--jobs", "8"]);
+    assert_eq!(single, parallel);
+
+    // and it's the tree git makes of the same files
+    repo.git(&["add", "--all"]);
+    assert_eq!(single, repo.git(&["write-tree"]));
+}
+
+#[test]
+fn nested_repositories_become_gitlinks() {
+    let repo = Repo::init();
+    repo.write("top", "top\n");
+    repo.write("sub/inner", "inner\n");
+    repo.git(&["-C", "sub", "init", "-q"]);
+    repo.git(&["-C", "sub", "add", "inner"]);
+    repo.git(&["-C", "sub", "commit", "-q", "-m", "inner"]);
+    let head = repo.git(&["-C", "sub", "rev-parse", "HEAD"]);
+
+    let tree = repo.run(&["write-tree"]);
+    assert_eq!(
+        repo.git(&["ls-tree", tree.trim()]),
+        format!(
+            "160000 commit {}\tsub\n100644 blob {}\ttop\n",
+            head.trim(),
+            repo.git(&["hash-object", "top"]).trim()
+        )
+    );
+    // ls-tree shows the commit without reading it, as it's in the nested repository
+    assert!(repo
+        .run(&["ls-tree", tree.trim()])
+        .starts_with(&format!("160000 commit {} sub\n", head.trim())));
+    // git records the nested repository the same way
+    repo.git(&["add", "--all"]);
+    assert_eq!(tree, repo.git(&["write-tree"]));
+}
+
+#[test]
+fn blobs_are_traced_and_checked_against_existing_copies() {
+    let repo = Repo::init();
+    repo.write("a", "a\n");
+    let blob = repo.git(&["hash-object", "a"]).trim().to_string();
+    let output = repo
+        .git_rs(&["write-tree"])
+        .env("GIT_TRACE", "1")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let trace = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        trace.contains(&format!("trace: object write: {blob} blob 2")),
+        "{trace}"
+    );
+
+    // a corrupt copy of the blob is found, unless core.checkCollisions is off
+    let path = repo.join(&format!(".git/objects/{}/{}", &blob[..2], &blob[2..]));
+    std::fs::remove_file(&path).unwrap();
+    std::fs::write(&path, "not an object").unwrap();
+    repo.fails(&["write-tree"]);
+    repo.git(&["config", "core.checkCollisions", "false"]);
+    repo.run(&["write-tree"]);
+}